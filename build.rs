@@ -0,0 +1,12 @@
+fn main() {
+    // The gRPC client is generated from the service definition
+    #[cfg(feature = "grpc")]
+    {
+        // Vendored, so that building doesn't need protoc installed
+        let mut config = tonic_build::Config::new();
+        config.protoc_executable(protoc_bin_vendored::protoc_bin_path().unwrap());
+        tonic_build::configure()
+            .compile_protos_with_config(config, &["proto/legion_prof.proto"], &["proto"])
+            .unwrap();
+    }
+}
@@ -7,9 +7,10 @@ use std::collections::BTreeMap;
 use std::sync::Mutex;
 
 use legion_prof_viewer::data::{
-    DataSource, DataSourceDescription, DataSourceInfo, EntryID, EntryInfo, Field, FieldID,
-    FieldSchema, Item, ItemMeta, ItemUID, SlotMetaTile, SlotMetaTileData, SlotTile, SlotTileData,
-    SummaryTile, SummaryTileData, TileID, TileSet, UtilPoint,
+    Capabilities, DataSource, DataSourceDescription, DataSourceInfo, EntryID, EntryInfo, Field,
+    FieldID, FieldSchema, Item, ItemMeta, ItemUID, PROTOCOL_VERSION, SlotMetaTile,
+    SlotMetaTileData, SlotTile, SlotTileData, SummaryTile, SummaryTileData, SummaryUnits, TileID,
+    TileSet, UtilPoint,
 };
 
 use legion_prof_viewer::deferred_data::DeferredDataSourceWrapper;
@@ -69,6 +70,10 @@ impl RandomDataSource {
             tile_set: TileSet::default(),
             field_schema,
             warning_message: Some("Demo only. The data in this profile is synthetic.".to_string()),
+            refresh_interval: None,
+            version: PROTOCOL_VERSION,
+            capabilities: Capabilities::default(),
+            entry_tile_sets: BTreeMap::new(),
         };
 
         let state = RandomState {
@@ -243,7 +248,10 @@ impl RandomDataSource {
                 kind_slots.push(EntryInfo::Panel {
                     short_name: kind.to_lowercase(),
                     long_name: format!("Node {node} {kind}"),
-                    summary: Some(Box::new(EntryInfo::Summary { color })),
+                    summary: Some(Box::new(EntryInfo::Summary {
+                        color,
+                        units: SummaryUnits::Utilization,
+                    })),
                     slots: proc_slots,
                 });
             }
@@ -353,9 +361,9 @@ impl DataSource for RandomDataSource {
         let (items, item_metas) = self.generate_slot(entry_id);
 
         let mut slot_items = Vec::new();
-        for (row, row_meta) in items.iter().zip(item_metas.into_iter()) {
+        for (row, row_meta) in items.iter().zip(item_metas) {
             let mut slot_row = Vec::new();
-            for (item, item_meta) in row.iter().zip(row_meta.into_iter()) {
+            for (item, item_meta) in row.iter().zip(row_meta) {
                 // When the item straddles a tile boundary, it has to be
                 // sliced to fit
                 if tile_id.0.overlaps(item.interval) {
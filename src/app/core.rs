@@ -1,9 +1,8 @@
+use std::cell::RefCell;
 use std::collections::{BTreeMap, BTreeSet, VecDeque};
 use std::fmt;
-use std::num::NonZeroUsize;
+use std::rc::{Rc, Weak};
 use std::time::Duration;
-#[cfg(not(target_arch = "wasm32"))]
-use std::time::Instant;
 
 use egui::{
     Color32, NumExt, Pos2, Rect, RichText, ScrollArea, Slider, Stroke, TextStyle, TextWrapMode,
@@ -12,22 +11,81 @@ use egui::{
 use egui_extras::{Column, TableBuilder};
 #[cfg(not(target_arch = "wasm32"))]
 use itertools::Itertools;
-use log::warn;
+use log::{debug, trace, warn};
 use percentage::{Percentage, PercentageInteger};
 use regex::{Regex, escape};
 use serde::{Deserialize, Serialize};
+use web_time::Instant;
 
-use crate::app::tile_manager::TileManager;
+#[cfg(not(target_arch = "wasm32"))]
+use crate::app::disk_cache;
+use crate::app::frame_budget::FrameBudget;
+use crate::app::keys::{Action, KeyBindings};
+use crate::app::tile_manager::{CacheExtension, TileManager, TileManagerConfig};
 use crate::data::{
-    DataSourceInfo, EntryID, EntryIndex, EntryInfo, Field, FieldID, FieldSchema, ItemLink,
-    ItemMeta, ItemUID, SlotMetaTileData, SlotTileData, SummaryTileData, TileID, UtilPoint,
+    Capabilities, DataSourceInfo, EntryID, EntryIndex, EntryInfo, Field, FieldID, FieldSchema,
+    Item, ItemLink, ItemMeta, ItemMetaRequest, ItemUID, PROTOCOL_VERSION, SlotMetaTileData,
+    SlotTileData, SummaryTileData, SummaryUnits, TileID, UtilPoint, integrate_points,
 };
+use crate::dedup_data::DedupDeferredDataSource;
 use crate::deferred_data::{
-    CountingDeferredDataSource, DeferredDataSource, LruDeferredDataSource, TileResult,
+    CANCELLED, CachingDeferredDataSource, CountingDeferredDataSource, DeferredDataSource,
+    DeferredProfileIndex, RequestPriority, SharedTileCache, TileCache, TileKind, TileRequest,
+    TileResult, slot_meta_tile_size, slot_tile_size, summary_tile_size,
 };
+#[cfg(not(target_arch = "wasm32"))]
+use crate::export::{ExportItem, ExportOptions, FORMATS, find_format, walk_entries};
+#[cfg(all(feature = "client", not(target_arch = "wasm32")))]
+use crate::http::client::ClientConfig;
+#[cfg(all(feature = "client", target_arch = "wasm32"))]
+use crate::http::client::HTTPClientDataSource;
+use crate::http::schema::ProfileIndex;
+use crate::metrics_data::MetricsDeferredDataSource;
+#[cfg(all(feature = "client", not(target_arch = "wasm32")))]
+use crate::open_data::Locator;
+use crate::retry_data::RetryDeferredDataSource;
+use crate::stats::{ItemStats, busy_ns};
+use crate::throttle_data::ThrottleDeferredDataSource;
+use crate::timeout_data::TimeoutDeferredDataSource;
 use crate::timestamp::{
     Interval, Timestamp, TimestampDisplay, TimestampParseError, TimestampUnits,
 };
+use crate::version;
+
+// Tiles are kept in memory (per profile) so that returning to a previous
+// view doesn't need to fetch them again
+const TILE_CACHE_BYTES: usize = 256 << 20;
+
+// Failed requests from sources that don't retry them on their own (e.g., local
+// files) are retried a few times before giving up, since a busy source would
+// otherwise leave permanent holes in the view
+const RETRY_ATTEMPTS: u32 = 5;
+const RETRY_DELAY: Duration = Duration::from_millis(250);
+
+// Requests that haven't returned by this point are treated as failed, rather
+// than leaving the view waiting forever. This is only a backstop: sources
+// over a network retry and time out on their own (HTTP after 60 seconds per
+// attempt by default), which should happen well before this.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+
+// Requests beyond this many are queued, so that zooming out doesn't send
+// hundreds of requests at once to a small server
+const MAX_OUTSTANDING_REQUESTS: usize = 32;
+
+// Decorators applied to each profile's data source, outermost first
+type ProfileDataSource = CountingDeferredDataSource<
+    CachingDeferredDataSource<
+        DedupDeferredDataSource<
+            RetryDeferredDataSource<
+                TimeoutDeferredDataSource<
+                    ThrottleDeferredDataSource<
+                        MetricsDeferredDataSource<Box<dyn DeferredDataSource>>,
+                    >,
+                >,
+            >,
+        >,
+    >,
+>;
 
 /// Overview:
 ///   ProfApp -> Context, Window *
@@ -63,7 +121,14 @@ use crate::timestamp::{
 struct Summary {
     entry_id: EntryID,
     color: Color32,
+    units: SummaryUnits,
     tiles: BTreeMap<TileID, Option<TileResult<SummaryTileData>>>,
+    // Tiles that were replaced, drawn until their replacements load
+    previous: BTreeMap<TileID, TileResult<SummaryTileData>>,
+    // Epoch each tile was fetched in, to tell when live data made it stale
+    epochs: TileEpochs,
+    // Requested ahead of time; the responses only warm the cache
+    prefetched: BTreeSet<TileID>,
 }
 
 #[derive(Debug, Clone)]
@@ -83,8 +148,42 @@ struct Slot {
     tiles: BTreeMap<TileID, Option<TileResult<SlotTileData>>>,
     tile_metas: BTreeMap<TileID, Option<TileResult<SlotMetaTileData>>>,
     tile_metas_full: BTreeMap<TileID, Option<TileResult<SlotMetaTileData>>>,
+    // Tiles that were replaced, drawn until their replacements load
+    previous: BTreeMap<TileID, TileResult<SlotTileData>>,
+    // Epoch each tile was fetched in, to tell when live data made it stale
+    epochs: TileEpochs,
+    // Requested ahead of time; the responses only warm the cache
+    prefetched: BTreeSet<TileID>,
+    // Item geometry, laid out again only when the view changes
+    layouts: BTreeMap<TileID, TileLayout>,
+}
+
+// Screen space rects of the items of a tile that are in view, and what they
+// were laid out for
+#[derive(Debug, Clone)]
+struct TileLayout {
+    view_interval: Interval,
+    rect: Rect,
+    viewport: Rect,
+    rows: u64,
+    merge_px: f32,
+    items: Vec<RowLayout>,
+}
+
+#[derive(Debug, Clone)]
+struct RowLayout {
+    row: usize,
+    rect: Rect,
+    // Index of the first item, number of items, and rect. Items that share a
+    // column (a pixel wide, unless detail is reduced) are merged, so there is
+    // never more than one rect per column
+    items: Vec<(usize, usize, Rect)>,
 }
 
+// Keyed by the kind of tile and whether it is full, since an entry may hold
+// several kinds of tiles with the same ID
+type TileEpochs = BTreeMap<(TileKind, bool, TileID), u64>;
+
 #[derive(Debug, Clone)]
 struct Panel<S: Entry> {
     entry_id: EntryID,
@@ -115,6 +214,81 @@ struct ItemDetail {
     loc: ItemLocator,
 }
 
+#[derive(Debug, Clone)]
+struct SelectedItem {
+    // Union of the (possibly sliced) item intervals seen in slot tiles
+    interval: Interval,
+
+    // Populated lazily via a batched request
+    meta: Option<ItemMeta>,
+}
+
+#[derive(Debug, Clone)]
+struct PinnedTooltip {
+    meta: ItemMeta,
+
+    // Screen position where the tooltip was pinned (used as the initial
+    // position of the window, after which the user may drag it around)
+    pos: Pos2,
+}
+
+// Accumulates quick statistics about a slot for the current view interval,
+// computed from whatever slot tiles are already loaded
+#[derive(Debug, Clone, Default)]
+struct SlotStats {
+    // Item intervals, clipped to the view interval
+    busy: Vec<Interval>,
+
+    // Items may be sliced across multiple tiles, so track the full extent of
+    // each item here
+    items: BTreeMap<ItemUID, Interval>,
+
+    // Number of tiles we didn't have data for
+    missing_tiles: usize,
+}
+
+// Per-title aggregate statistics for the items in the view interval,
+// computed from the full meta tiles
+#[derive(Debug, Clone, Default)]
+struct SelectionStats {
+    items: ItemStats,
+
+    // Number of tiles we're still waiting on
+    missing_tiles: usize,
+}
+
+// Items of the visible slots, gathered from the full meta tiles (the same
+// ones statistics and search use) for the Export... dialog
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Debug, Clone, Default)]
+struct ExportedItems {
+    interval: Interval,
+    // Only these items, when exporting the selection
+    only: Option<BTreeSet<ItemUID>>,
+    slots: BTreeMap<EntryID, Vec<ExportItem>>,
+
+    // Number of tiles we're still waiting on
+    missing_tiles: usize,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+struct ExportState {
+    // The info as the source gave it (i.e., without the Title field that
+    // the viewer adds), for the exporters
+    info: DataSourceInfo,
+
+    open: bool,
+    format: &'static str,
+    options: ExportOptions,
+    path: String,
+    selection_only: bool,
+
+    // Like statistics, an export waits here until the metadata for the view
+    // interval is loaded
+    pending: bool,
+    status: Option<String>,
+}
+
 #[derive(Debug, Clone)]
 struct SearchCacheItem {
     item_uid: ItemUID,
@@ -152,6 +326,13 @@ struct SearchState {
     entry_tree: BTreeMap<u64, BTreeMap<u64, BTreeSet<u64>>>,
 }
 
+// Requests that failed since the user last retried them
+#[derive(Debug, Clone, Default)]
+struct FetchErrors {
+    count: u64,
+    last: Option<String>,
+}
+
 struct Config {
     field_schema: FieldSchema,
 
@@ -166,21 +347,87 @@ struct Config {
     // This is just for the local profile
     interval: Interval,
     warning_message: Option<String>,
-
-    data_source: CountingDeferredDataSource<LruDeferredDataSource<Box<dyn DeferredDataSource>>>,
+    fetch_errors: FetchErrors,
+    // Tiles that didn't cover the view, when the tile manager verifies them
+    coverage_errors: FetchErrors,
+
+    // Dynamic sources can ask for their info to be fetched again
+    // periodically, see Window::refresh_info
+    refresh_interval: Option<Duration>,
+    last_refresh: Instant,
+    refresh_pending: bool,
+    // Given with --follow: how often to refresh the info whether or not the
+    // source asks to, with the view sliding along with the end of the profile
+    follow: Option<Duration>,
+
+    capabilities: Capabilities,
+    // PROTOCOL_VERSION of the source, or zero if it predates versioning
+    version: u32,
+
+    data_source: ProfileDataSource,
+
+    // Tiles saved on exit are only restored for a profile with the same info
+    #[cfg(not(target_arch = "wasm32"))]
+    info_hash: u64,
 
     search_state: SearchState,
 
     // When the user clicks on an item, we put it here
     items_selected: BTreeMap<ItemUID, ItemDetail>,
 
+    // When the user middle-clicks on an item, we pin its tooltip here
+    pinned_tooltips: BTreeMap<ItemUID, PinnedTooltip>,
+
+    // When the user shift-drags a rectangle over items, we put them here
+    selection: BTreeMap<ItemUID, SelectedItem>,
+    highlight_selection: bool,
+
+    // Metadata for selected items that we still need to fetch. These are
+    // issued as a single batch once per frame
+    items_meta_requests: Vec<ItemMetaRequest>,
+
+    // Tiles that we still need to fetch. Like item metadata, these are
+    // issued in batches once per frame
+    tile_requests: BTreeMap<(TileKind, RequestPriority), Vec<TileRequest>>,
+
+    // When the user requests a statistics export, we wait here until the
+    // metadata for the view interval is loaded
+    export_stats_pending: bool,
+    export_stats_status: Option<String>,
+
+    #[cfg(not(target_arch = "wasm32"))]
+    export: ExportState,
+
     // When the user clicks "Zoom to Item" or a search result, we put it here
     scroll_to_item: Option<ItemLocator>,
     // Sometimes, we cannot find the correct row to scroll to. In this case we
     // populate the following field to track the re-scroll when the item is found
     scroll_to_item_retry: Option<ItemLocator>,
 
+    // The entry given at startup (e.g., with --entry), to scroll to the top of
+    scroll_to_entry: Option<EntryID>,
+
     tile_manager: TileManager,
+    tile_config: TileManagerConfig,
+
+    // Optional second profile to draw faintly behind the summaries
+    baseline: Option<Baseline>,
+    baseline_url: String,
+    baseline_error: Option<String>,
+}
+
+struct Baseline {
+    data_source: CountingDeferredDataSource<Box<dyn DeferredDataSource>>,
+    locator: String,
+
+    // Populated once the baseline's info arrives
+    tile_manager: Option<TileManager>,
+
+    // Shift applied to baseline timestamps to align its start with the
+    // start of the current profile
+    offset_ns: i64,
+
+    tiles: BTreeMap<EntryID, BTreeMap<TileID, Option<TileResult<SummaryTileData>>>>,
 }
 
 struct Window {
@@ -286,44 +533,150 @@ struct Context {
     #[serde(skip)]
     drag_origin: Option<Pos2>,
 
+    // Shift-dragging selects items instead of zooming. When the drag
+    // finishes, the (screen space) rectangle is stashed here so that slots
+    // can pick up the items under it on the next frame.
+    #[serde(skip)]
+    drag_select: bool,
+    #[serde(skip)]
+    select_rect: Option<Rect>,
+
     // Hack: We need to track the screenspace rect where slot/summary
     // data gets drawn. This gets used rendering the cursor, but we
     // only know it when we render slots. So stash it here.
     #[serde(skip)]
     slot_rect: Option<Rect>,
 
+    // Pointer position used to draw the crosshair. This is captured before
+    // slots are rendered so that each row can report the item executing
+    // under the crosshair.
+    #[serde(skip)]
+    crosshair_pos: Option<Pos2>,
+
     item_link_mode: ItemLinkNavigationMode,
 
+    #[serde(default)]
+    tile_config: TileManagerConfig,
+
+    // Rendering detail is reduced while frames take too long
+    #[serde(default)]
+    frame_budget: FrameBudget,
+
     toggle_dark_mode: bool,
 
+    crosshair: bool,
+
     debug: bool,
 
     #[serde(skip)]
     show_controls: bool,
 
+    #[serde(skip)]
+    show_about: bool,
+
+    // From the user's configuration, if any
+    #[serde(skip)]
+    keys: KeyBindings,
+
     #[serde(skip)]
     view_interval_history: IntervalState,
     #[serde(skip)]
     interval_select_state: IntervalSelectState,
 }
 
-#[derive(Default, Deserialize, Serialize)]
-#[serde(default)] // deserialize missing fields as default value
-struct ProfApp {
+impl Context {
+    // Settings that every OS window shares, as opposed to what each one shows
+    fn share_settings(&mut self, other: &Context) {
+        self.item_link_mode = other.item_link_mode;
+        self.tile_config = other.tile_config;
+        self.toggle_dark_mode = other.toggle_dark_mode;
+        self.crosshair = other.crosshair;
+        self.debug = other.debug;
+        self.keys.clone_from(&other.keys);
+    }
+}
+
+// Everything shown in one OS window: its profiles, and how they are viewed
+#[derive(Default)]
+struct Viewer {
     // Data sources waiting to be turned into windows.
-    #[serde(skip)]
     pending_data_sources: VecDeque<Box<dyn DeferredDataSource>>,
 
-    #[serde(skip)]
     windows: Vec<Window>,
 
+    // Data sources that failed to load: (locator, error)
+    load_errors: Vec<(String, String)>,
+
+    // Shown in place of the profiles until one is picked, if started from an
+    // index of profiles
+    chooser: Option<ProfileChooser>,
+
+    #[cfg(not(target_arch = "wasm32"))]
+    open: OpenState,
+
+    // Asked for with File > New Window, for the app to open
+    #[cfg(not(target_arch = "wasm32"))]
+    new_window: bool,
+
+    // Applied to each profile as it loads, until all have
+    start: StartView,
+
+    // Applied to every profile, including those opened later
+    follow: Option<Duration>,
+
     cx: Context,
 
     #[cfg(not(target_arch = "wasm32"))]
-    #[serde(skip)]
     last_update: Option<Instant>,
 }
 
+// Windows showing the same source share a tile cache, so that each tile is
+// only fetched and kept once, even across OS windows
+#[derive(Default)]
+struct TileCaches {
+    caches: BTreeMap<Vec<String>, Weak<RefCell<TileCache>>>,
+
+    // Budget of each tile cache, in bytes
+    budget: usize,
+}
+
+impl TileCaches {
+    fn get(&mut self, locator: Vec<String>) -> SharedTileCache {
+        // Sources without a locator can't be told apart, so never share
+        if locator.is_empty() {
+            return TileCache::shared(self.budget);
+        }
+        self.caches.retain(|_, cache| cache.strong_count() > 0);
+        if let Some(cache) = self.caches.get(&locator).and_then(Weak::upgrade) {
+            return cache;
+        }
+        let cache = TileCache::shared(self.budget);
+        self.caches.insert(locator, Rc::downgrade(&cache));
+        cache
+    }
+}
+
+// What is saved between runs
+#[derive(Default, Deserialize, Serialize)]
+#[serde(default)] // deserialize missing fields as default value
+struct SavedState {
+    cx: Context,
+}
+
+struct ProfApp {
+    // The main OS window, whose settings are saved
+    viewer: Viewer,
+
+    // Further OS windows, opened with File > New Window, each with its own
+    // profiles but the same settings as the main one
+    #[cfg(not(target_arch = "wasm32"))]
+    viewers: Vec<(u64, Viewer)>,
+    #[cfg(not(target_arch = "wasm32"))]
+    next_viewer: u64,
+
+    tile_caches: TileCaches,
+}
+
 trait Entry {
     fn new(info: &EntryInfo, entry_id: EntryID) -> Self;
 
@@ -337,10 +690,26 @@ trait Entry {
 
     fn expand_slot(&mut self, entry_id: &EntryID, level: u64);
 
+    // Brings the entry up to date with a refreshed info, which may add new
+    // entries at the end. Loaded tiles are dropped if they may be stale.
+    fn update_info(&mut self, info: &EntryInfo, clear_tiles: bool);
+
+    // Drops any tiles that failed to load, so that they are requested again
+    fn clear_errors(&mut self);
+
     fn inflate_meta(&mut self, config: &mut Config, cx: &mut Context);
 
     fn search(&mut self, config: &mut Config);
 
+    fn collect_stats(&self, config: &Config, stats: &mut SelectionStats);
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn collect_export(&self, config: &Config, items: &mut ExportedItems);
+
+    fn hover_stats(&self, _cx: &Context) -> Option<String> {
+        None
+    }
+
     fn label(&mut self, ui: &mut egui::Ui, rect: Rect, cx: &Context) {
         let response = ui.allocate_rect(
             rect,
@@ -375,7 +744,14 @@ trait Entry {
             // This will take effect next frame because we can't redraw this widget now
             self.toggle_expanded();
         } else if response.hovered() {
-            response.on_hover_text(self.hover_text());
+            let stats = self.hover_stats(cx);
+            response.on_hover_ui(|ui| {
+                ui.label(self.hover_text());
+                if let Some(stats) = stats {
+                    ui.separator();
+                    ui.label(stats);
+                }
+            });
         }
     }
 
@@ -388,6 +764,18 @@ trait Entry {
         cx: &mut Context,
     );
 
+    // Called instead of content for rows just outside the viewport, so that
+    // their tiles are already loaded by the time they scroll into view
+    fn prefetch(
+        &mut self,
+        _ui: &mut egui::Ui,
+        _rect: Rect,
+        _viewport: Rect,
+        _config: &mut Config,
+        _cx: &mut Context,
+    ) {
+    }
+
     fn height(&self, prefix: Option<&EntryID>, config: &Config, cx: &Context) -> f32;
 
     fn is_expandable(&self) -> bool;
@@ -396,28 +784,79 @@ trait Entry {
 }
 
 impl Summary {
-    fn inflate(&mut self, config: &mut Config, cx: &mut Context) {
+    fn inflate(&mut self, config: &mut Config, cx: &mut Context, priority: RequestPriority) {
         const PART: bool = false;
-        let tile_ids = config.request_tiles(cx.view_interval, PART);
-        Config::invalidate_cache(&tile_ids, &mut self.tiles);
-        for tile_id in tile_ids {
-            self.tiles.entry(tile_id).or_insert_with(|| {
-                config
-                    .data_source
-                    .fetch_summary_tile(&self.entry_id, tile_id, PART);
+        let (tile_ids, prefetch_ids) =
+            config.request_tiles_with_prefetch(&self.entry_id, cx.view_interval, PART);
+        let mut dropped = config.hold_tiles(
+            TileKind::Summary,
+            &self.entry_id,
+            PART,
+            &tile_ids,
+            &prefetch_ids,
+            &mut self.tiles,
+        );
+        dropped.extend(config.take_stale_tiles(
+            TileKind::Summary,
+            &self.entry_id,
+            PART,
+            &mut self.epochs,
+            &mut self.tiles,
+        ));
+        TileManager::refine_cache(&tile_ids, &self.tiles, dropped, &mut self.previous);
+        for tile_id in &tile_ids {
+            self.tiles.entry(*tile_id).or_insert_with(|| {
+                config.request_tile(TileKind::Summary, &self.entry_id, *tile_id, PART, priority);
                 None
             });
         }
+        config.prefetch_tiles(
+            TileKind::Summary,
+            &self.entry_id,
+            &tile_ids,
+            &prefetch_ids,
+            &mut self.prefetched,
+        );
+
+        if let Some(Baseline {
+            data_source,
+            tile_manager: Some(tile_manager),
+            offset_ns,
+            tiles,
+            ..
+        }) = &mut config.baseline
+        {
+            let view_interval = cx.view_interval.translate(-*offset_ns);
+            let tile_ids = tile_manager.request_tiles(view_interval, PART);
+            let tiles = tiles.entry(self.entry_id.clone()).or_default();
+            Config::invalidate_cache(&tile_ids, tiles);
+            for tile_id in tile_ids {
+                tiles.entry(tile_id).or_insert_with(|| {
+                    // The baseline is drawn underneath, so it can wait
+                    data_source.fetch_summary_tile(
+                        &self.entry_id,
+                        tile_id,
+                        PART,
+                        RequestPriority::Prefetch,
+                    );
+                    None
+                });
+            }
+        }
     }
 }
 
 impl Entry for Summary {
     fn new(info: &EntryInfo, entry_id: EntryID) -> Self {
-        if let EntryInfo::Summary { color } = info {
+        if let EntryInfo::Summary { color, units } = info {
             Self {
                 entry_id,
                 color: *color,
+                units: *units,
                 tiles: BTreeMap::new(),
+                previous: BTreeMap::new(),
+                epochs: BTreeMap::new(),
+                prefetched: BTreeSet::new(),
             }
         } else {
             unreachable!()
@@ -431,7 +870,10 @@ impl Entry for Summary {
         "avg"
     }
     fn hover_text(&self) -> &str {
-        "Utilization Plot of Average Usage Over Time"
+        match self.units {
+            SummaryUnits::Utilization => "Utilization Plot of Average Usage Over Time",
+            SummaryUnits::Watts => "Power Plot of Average Power Draw Over Time",
+        }
     }
 
     fn find_slot(&self, _entry_id: &EntryID, _level: u64) -> Option<&Slot> {
@@ -452,6 +894,18 @@ impl Entry for Summary {
         unreachable!()
     }
 
+    fn update_info(&mut self, _info: &EntryInfo, clear_tiles: bool) {
+        if clear_tiles {
+            self.tiles.clear();
+            self.previous.clear();
+            self.epochs.clear();
+        }
+    }
+
+    fn clear_errors(&mut self) {
+        self.tiles.retain(|_, tile| !matches!(tile, Some(Err(_))));
+    }
+
     fn inflate_meta(&mut self, _config: &mut Config, _cx: &mut Context) {
         unreachable!()
     }
@@ -460,6 +914,43 @@ impl Entry for Summary {
         unreachable!()
     }
 
+    fn collect_stats(&self, _config: &Config, _stats: &mut SelectionStats) {
+        unreachable!()
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn collect_export(&self, _config: &Config, _items: &mut ExportedItems) {
+        unreachable!()
+    }
+
+    fn hover_stats(&self, cx: &Context) -> Option<String> {
+        // Tiles are contiguous, so stitch them together to integrate across
+        // tile boundaries
+        let mut points = Vec::new();
+        for tile in self.tiles.values() {
+            let Some(Ok(tile)) = tile else {
+                return None;
+            };
+            points.extend_from_slice(&tile.utilization);
+        }
+        if points.is_empty() {
+            return None;
+        }
+
+        let total = integrate_points(&points, cx.view_interval);
+        let seconds = cx.view_interval.duration_ns().max(1) as f64 * 1e-9;
+        Some(match self.units {
+            SummaryUnits::Utilization => {
+                format!("Average Utilization: {:.1}%", total / seconds * 100.0)
+            }
+            SummaryUnits::Watts => format!(
+                "Energy: {:.3} J\nAverage Power: {:.3} W",
+                total,
+                total / seconds
+            ),
+        })
+    }
+
     fn content(
         &mut self,
         ui: &mut egui::Ui,
@@ -473,7 +964,7 @@ impl Entry for Summary {
         const TOOLTIP_RADIUS: f32 = 4.0;
         let hover_pos = ui.rect_hover_pos(rect); // where is the mouse hovering?
 
-        self.inflate(config, cx);
+        self.inflate(config, cx, RequestPriority::Visible);
 
         let style = ui.style();
         let visuals = style.noninteractive();
@@ -482,16 +973,53 @@ impl Entry for Summary {
 
         let stroke = Stroke::new(visuals.bg_stroke.width, self.color);
 
+        // Baseline points (if any), shifted to align with this profile. We
+        // ignore errors here since the baseline may not have this entry
+        let baseline: Vec<_> = config
+            .baseline
+            .as_ref()
+            .and_then(|baseline| {
+                let tiles = baseline.tiles.get(&self.entry_id)?;
+                Some(
+                    tiles
+                        .values()
+                        .flatten()
+                        .flatten()
+                        .flat_map(|tile| &tile.utilization)
+                        .map(|util| UtilPoint {
+                            time: Timestamp(util.time.0 + baseline.offset_ns),
+                            util: util.util,
+                        })
+                        .collect(),
+                )
+            })
+            .unwrap_or_default();
+
+        // Utilization is already normalized, but for other units we scale
+        // the plot to the peak value of the loaded data
+        let scale = match self.units {
+            SummaryUnits::Utilization => 1.0,
+            SummaryUnits::Watts => self
+                .tiles
+                .values()
+                .flatten()
+                .flatten()
+                .flat_map(|tile| &tile.utilization)
+                .chain(&baseline)
+                .map(|util| util.util)
+                .fold(f32::EPSILON, f32::max),
+        };
+
         // Conversions to and from screen space coordinates
         let util_to_screen = |util: &UtilPoint| {
             let time = cx.view_interval.unlerp(util.time);
-            rect.lerp_inside(Vec2::new(time, 1.0 - util.util))
+            rect.lerp_inside(Vec2::new(time, 1.0 - util.util / scale))
         };
         let screen_to_util = |screen: Pos2| UtilPoint {
             time: cx
                 .view_interval
                 .lerp((screen.x - rect.left()) / rect.width()),
-            util: 1.0 - (screen.y - rect.top()) / rect.height(),
+            util: (1.0 - (screen.y - rect.top()) / rect.height()) * scale,
         };
 
         // Linear interpolation along the line from p1 to p2
@@ -500,55 +1028,85 @@ impl Entry for Summary {
             Rect::from_min_max(p1, p2).lerp_inside(Vec2::new(ratio, ratio))
         };
 
-        let mut last_util: Option<&UtilPoint> = None;
-        let mut last_point: Option<Pos2> = None;
-        let mut hover_util = None;
-        for tile in self.tiles.values().flatten() {
-            let tile = match tile {
-                Ok(t) => t,
-                Err(e) => {
-                    warn!("{}", e);
+        // Draw the baseline first so that it sits behind the main plot
+        let baseline_stroke = Stroke::new(stroke.width, self.color.gamma_multiply(0.35));
+        for (last, util) in baseline.iter().zip(baseline.iter().skip(1)) {
+            if cx
+                .view_interval
+                .overlaps(Interval::new(last.time, util.time))
+            {
+                let mut last_point = util_to_screen(last);
+                let mut point = util_to_screen(util);
+                if last_point.x < rect.min.x {
+                    last_point = interpolate(last_point, point, rect.min.x);
+                }
+                if point.x > rect.max.x {
+                    point = interpolate(last_point, point, rect.max.x);
+                }
+                ui.painter()
+                    .line_segment([last_point, point], baseline_stroke);
+            }
+        }
+
+        // Tiles that are still loading are filled in from the tiles they
+        // replace, if any
+        let mut points: Vec<&UtilPoint> = Vec::new();
+        for (tile_id, tile) in &self.tiles {
+            match tile {
+                Some(Ok(tile)) => points.extend(&tile.utilization),
+                Some(Err(_)) => {
                     // Paint the entire tile red to indicate the error.
                     ui.painter().rect(rect, 0.0, Color32::RED, Stroke::NONE);
                     return;
                 }
-            };
-
-            for util in &tile.utilization {
-                let mut point = util_to_screen(util);
-                if let Some(mut last) = last_point {
-                    let last_util = last_util.unwrap();
-                    if cx
-                        .view_interval
-                        .overlaps(Interval::new(last_util.time, util.time))
-                    {
-                        // Interpolate when out of view
-                        if last.x < rect.min.x {
-                            last = interpolate(last, point, rect.min.x);
-                        }
-                        if point.x > rect.max.x {
-                            point = interpolate(last, point, rect.max.x);
+                None => {
+                    for (_, old) in TileManager::previous_tiles(*tile_id, &self.previous) {
+                        if let Ok(old) = old {
+                            points.extend(
+                                old.utilization
+                                    .iter()
+                                    .filter(|util| tile_id.0.contains(util.time)),
+                            );
                         }
+                    }
+                }
+            }
+        }
 
-                        ui.painter().line_segment([last, point], stroke);
-
-                        if let Some(hover) = hover_pos {
-                            if last.x <= hover.x && hover.x < point.x {
-                                let interp = interpolate(last, point, hover.x);
-                                ui.painter().circle_stroke(
-                                    interp,
-                                    TOOLTIP_RADIUS,
-                                    visuals.fg_stroke,
-                                );
-                                hover_util = Some(screen_to_util(interp));
-                            }
+        let mut last_util: Option<&UtilPoint> = None;
+        let mut last_point: Option<Pos2> = None;
+        let mut hover_util = None;
+        for util in points {
+            let mut point = util_to_screen(util);
+            if let Some(mut last) = last_point {
+                let last_util = last_util.unwrap();
+                if cx
+                    .view_interval
+                    .overlaps(Interval::new(last_util.time, util.time))
+                {
+                    // Interpolate when out of view
+                    if last.x < rect.min.x {
+                        last = interpolate(last, point, rect.min.x);
+                    }
+                    if point.x > rect.max.x {
+                        point = interpolate(last, point, rect.max.x);
+                    }
+
+                    ui.painter().line_segment([last, point], stroke);
+
+                    if let Some(hover) = hover_pos {
+                        if last.x <= hover.x && hover.x < point.x {
+                            let interp = interpolate(last, point, hover.x);
+                            ui.painter()
+                                .circle_stroke(interp, TOOLTIP_RADIUS, visuals.fg_stroke);
+                            hover_util = Some(screen_to_util(interp));
                         }
                     }
                 }
-
-                last_point = Some(point);
-                last_util = Some(util);
             }
+
+            last_point = Some(point);
+            last_util = Some(util);
         }
 
         if let Some(util) = hover_util {
@@ -557,14 +1115,25 @@ impl Entry for Summary {
                 rect.lerp_inside(Vec2::new(time - 0.05, 0.0)),
                 rect.lerp_inside(Vec2::new(time + 0.05, 1.0)),
             );
-            ui.show_tooltip(
-                "utilization_tooltip",
-                &util_rect,
-                format!("{:.0}% Utilization", util.util * 100.0),
-            );
+            let text = match self.units {
+                SummaryUnits::Utilization => format!("{:.0}% Utilization", util.util * 100.0),
+                SummaryUnits::Watts => format!("{:.2} W", util.util),
+            };
+            ui.show_tooltip("utilization_tooltip", &util_rect, text);
         }
     }
 
+    fn prefetch(
+        &mut self,
+        _ui: &mut egui::Ui,
+        _rect: Rect,
+        _viewport: Rect,
+        config: &mut Config,
+        cx: &mut Context,
+    ) {
+        self.inflate(config, cx, RequestPriority::Prefetch);
+    }
+
     fn height(&self, prefix: Option<&EntryID>, _config: &Config, cx: &Context) -> f32 {
         assert!(prefix.is_none());
         const ROWS: u64 = 4;
@@ -614,95 +1183,304 @@ impl fmt::Display for FieldWithName<'_> {
     }
 }
 
-impl Slot {
-    fn rows(&self) -> u64 {
-        const UNEXPANDED_ROWS: u64 = 2;
-        if self.expanded {
-            self.max_rows.at_least(UNEXPANDED_ROWS)
-        } else {
-            UNEXPANDED_ROWS
+impl SelectionStats {
+    fn new(interval: Interval) -> Self {
+        Self {
+            items: ItemStats::new(interval),
+            missing_tiles: 0,
         }
     }
 
-    fn inflate(&mut self, config: &mut Config, cx: &mut Context) -> Vec<TileID> {
-        const PART: bool = false;
-        let tile_ids = config.request_tiles(cx.view_interval, PART);
-        Config::invalidate_cache(&tile_ids, &mut self.tiles);
-        Config::invalidate_cache(&tile_ids, &mut self.tile_metas);
-        for tile_id in &tile_ids {
-            self.tiles.entry(*tile_id).or_insert_with(|| {
-                config
-                    .data_source
-                    .fetch_slot_tile(&self.entry_id, *tile_id, false);
-                None
-            });
+    fn add_item(&mut self, item: &ItemMeta) {
+        self.items.add_item(item);
+    }
+}
+
+impl SlotStats {
+    fn add_item(&mut self, item_uid: ItemUID, interval: Interval, view_interval: Interval) {
+        if !interval.overlaps(view_interval) {
+            return;
         }
-        tile_ids
+        self.busy.push(interval.intersection(view_interval));
+        self.items
+            .entry(item_uid)
+            .and_modify(|i| *i = i.union(interval))
+            .or_insert(interval);
     }
 
-    fn fetch_meta_tile(
-        &mut self,
-        tile_id: TileID,
-        config: &mut Config,
-        full: bool,
-    ) -> Option<&TileResult<SlotMetaTileData>> {
-        let metas = if full {
-            &mut self.tile_metas_full
-        } else {
-            &mut self.tile_metas
-        };
+    fn busy_ns(&self) -> i64 {
+        busy_ns(self.busy.iter().copied())
+    }
 
-        metas
-            .entry(tile_id)
-            .or_insert_with(|| {
-                config
-                    .data_source
-                    .fetch_slot_meta_tile(&self.entry_id, tile_id, full);
-                None
-            })
-            .as_ref()
+    fn longest(&self) -> Option<(ItemUID, i64)> {
+        self.items
+            .iter()
+            .map(|(item_uid, interval)| (*item_uid, interval.duration_ns()))
+            .max_by_key(|(_, duration)| *duration)
     }
+}
 
-    #[allow(clippy::too_many_arguments)]
-    fn render_tile(
-        &mut self,
-        tile_id: TileID,
-        rows: u64,
-        mut hover_pos: Option<Pos2>,
-        ui: &mut egui::Ui,
-        rect: Rect,
-        viewport: Rect,
-        config: &mut Config,
-        cx: &mut Context,
-    ) -> Option<Pos2> {
-        let tile = self.tiles.get(&tile_id).unwrap();
+// Averages the colors of items drawn together
+fn blend_colors(colors: impl Iterator<Item = Color32>) -> Color32 {
+    let mut sum = [0u32; 4];
+    let mut count = 0;
+    for color in colors {
+        for (s, c) in sum.iter_mut().zip(color.to_array()) {
+            *s += c as u32;
+        }
+        count += 1;
+    }
+    let [r, g, b, a] = sum.map(|s| (s / count.max(1)) as u8);
+    Color32::from_rgba_premultiplied(r, g, b, a)
+}
 
-        if !tile.is_some() {
-            // Tile hasn't finished loading.
-            return hover_pos;
+impl Slot {
+    fn find_item_title(&self, item_uid: ItemUID) -> Option<String> {
+        let metas = self
+            .tile_metas
+            .values()
+            .chain(self.tile_metas_full.values());
+        for tile in metas {
+            let Some(Ok(tile)) = tile else {
+                continue;
+            };
+            for item in tile.items.iter().flatten() {
+                if item.item_uid == item_uid {
+                    return Some(item.title.clone());
+                }
+            }
         }
-        let tile = tile.as_ref().unwrap();
+        None
+    }
 
-        let tile = match tile {
-            Ok(t) => t,
-            Err(e) => {
-                warn!("{}", e);
-                // Paint the entire tile red to indicate the error.
-                ui.painter().rect(rect, 0.0, Color32::RED, Stroke::NONE);
-                return hover_pos;
+    fn item_tooltip(
+        ui: &mut egui::Ui,
+        item_meta: &ItemMeta,
+        field_schema: &FieldSchema,
+        cx: &Context,
+    ) {
+        ui.label(&item_meta.title);
+        if cx.debug {
+            ui.label(format!("Item UID: {}", item_meta.item_uid.0));
+        }
+        for (field_id, field, color) in &item_meta.fields {
+            let name = field_schema.get_name(*field_id).unwrap();
+            let text = format!("{}", FieldWithName(name, field));
+            if let Some(color) = color {
+                ui.label(RichText::new(text).color(*color));
+            } else {
+                ui.label(text);
+            }
+        }
+    }
+
+    fn crosshair_labels(
+        ui: &mut egui::Ui,
+        crosshair_x: f32,
+        crosshair_items: &[(usize, usize, Rect)],
+        tile_meta: &SlotMetaTileData,
+    ) {
+        const LABEL_PADDING: f32 = 4.0;
+
+        let style = ui.style();
+        let font_id = TextStyle::Small.resolve(style);
+        let visuals = style.noninteractive();
+        for (row, item_idx, row_rect) in crosshair_items {
+            let item_meta = &tile_meta.items[*row][*item_idx];
+            let left = crosshair_x + LABEL_PADDING;
+            let width = row_rect.right() - left - LABEL_PADDING;
+            if width <= 0.0 {
+                continue;
+            }
+            let layout = ui.painter().layout(
+                item_meta.title.clone(),
+                font_id.clone(),
+                visuals.text_color(),
+                width,
+            );
+            // Only label rows tall enough to fit the text
+            if layout.size().y > row_rect.height() {
+                continue;
             }
+            let min = Pos2::new(left, row_rect.center().y - layout.size().y * 0.5);
+            let label_rect = Rect::from_min_size(min, layout.size()).expand(1.0);
+            ui.painter()
+                .rect(label_rect, 0.0, visuals.bg_fill, Stroke::NONE);
+            ui.painter().galley(min, layout, visuals.text_color());
+        }
+    }
+
+    fn rows(&self) -> u64 {
+        const UNEXPANDED_ROWS: u64 = 2;
+        if self.expanded {
+            self.max_rows.at_least(UNEXPANDED_ROWS)
+        } else {
+            UNEXPANDED_ROWS
+        }
+    }
+
+    fn inflate(
+        &mut self,
+        config: &mut Config,
+        cx: &mut Context,
+        priority: RequestPriority,
+    ) -> Vec<TileID> {
+        const PART: bool = false;
+        let (tile_ids, prefetch_ids) =
+            config.request_tiles_with_prefetch(&self.entry_id, cx.view_interval, PART);
+        let mut dropped = config.hold_tiles(
+            TileKind::Slot,
+            &self.entry_id,
+            PART,
+            &tile_ids,
+            &prefetch_ids,
+            &mut self.tiles,
+        );
+        dropped.extend(config.take_stale_tiles(
+            TileKind::Slot,
+            &self.entry_id,
+            PART,
+            &mut self.epochs,
+            &mut self.tiles,
+        ));
+        TileManager::refine_cache(&tile_ids, &self.tiles, dropped, &mut self.previous);
+        Config::invalidate_cache(&tile_ids, &mut self.layouts);
+        config.hold_tiles(
+            TileKind::SlotMeta,
+            &self.entry_id,
+            PART,
+            &tile_ids,
+            &prefetch_ids,
+            &mut self.tile_metas,
+        );
+        config.take_stale_tiles(
+            TileKind::SlotMeta,
+            &self.entry_id,
+            PART,
+            &mut self.epochs,
+            &mut self.tile_metas,
+        );
+        for tile_id in &tile_ids {
+            self.tiles.entry(*tile_id).or_insert_with(|| {
+                config.request_tile(TileKind::Slot, &self.entry_id, *tile_id, false, priority);
+                None
+            });
+        }
+        config.prefetch_tiles(
+            TileKind::Slot,
+            &self.entry_id,
+            &tile_ids,
+            &prefetch_ids,
+            &mut self.prefetched,
+        );
+        tile_ids
+    }
+
+    fn fetch_meta_tile(
+        &mut self,
+        tile_id: TileID,
+        config: &mut Config,
+        full: bool,
+        priority: RequestPriority,
+    ) -> Option<&TileResult<SlotMetaTileData>> {
+        if !config.capabilities.slot_meta_tiles {
+            return None;
+        }
+
+        // A full tile that is already loaded has everything the partial one
+        // would, so don't fetch the same data again
+        if !full && matches!(self.tile_metas_full.get(&tile_id), Some(Some(Ok(_)))) {
+            return self.tile_metas_full.get(&tile_id).and_then(Option::as_ref);
+        }
+
+        let metas = if full {
+            &mut self.tile_metas_full
+        } else {
+            &mut self.tile_metas
         };
 
-        if !cx.view_interval.overlaps(tile_id.0) {
-            return hover_pos;
+        metas
+            .entry(tile_id)
+            .or_insert_with(|| {
+                config.request_tile(TileKind::SlotMeta, &self.entry_id, tile_id, full, priority);
+                None
+            })
+            .as_ref()
+    }
+
+    fn item_color(item: &Item, config: &Config) -> Color32 {
+        let highlight = config.items_selected.contains_key(&item.item_uid)
+            || (config.highlight_selection && config.selection.contains_key(&item.item_uid));
+
+        if !config.search_state.query.is_empty() {
+            if config.search_state.result_set.contains(&item.item_uid) || highlight {
+                Color32::RED
+            } else {
+                item.color.gamma_multiply(0.2)
+            }
+        } else if highlight {
+            Color32::RED
+        } else {
+            item.color
         }
+    }
 
+    // Draws a tile that is being replaced, limited to the part of the view
+    // covered by the replacement that is still loading. Its items can't be
+    // interacted with, since they may be merged or split once the
+    // replacement arrives.
+    #[allow(clippy::too_many_arguments)]
+    fn render_preview(
+        tile: &SlotTileData,
+        clip: Interval,
+        rows: u64,
+        ui: &mut egui::Ui,
+        rect: Rect,
+        viewport: Rect,
+        config: &Config,
+        cx: &Context,
+    ) {
+        let clip = clip.intersection(cx.view_interval);
+        let mut mesh = egui::Mesh::default();
+        // The slot may have shrunk since the tile was fetched
+        for (row, row_items) in tile.items.iter().enumerate().take(rows as usize) {
+            let irow = rows - (row as u64) - 1;
+            let row_min = rect.lerp_inside(Vec2::new(0.0, (irow as f32 + 0.05) / rows as f32));
+            let row_max = rect.lerp_inside(Vec2::new(1.0, (irow as f32 + 0.95) / rows as f32));
+            if row_max.y - rect.min.y < viewport.min.y {
+                break;
+            } else if row_min.y - rect.min.y > viewport.max.y {
+                continue;
+            }
+
+            for item in row_items {
+                if !clip.overlaps(item.interval) {
+                    continue;
+                }
+                let interval = item.interval.intersection(clip);
+                let start = cx.view_interval.unlerp(interval.start);
+                let stop = cx.view_interval.unlerp(interval.stop);
+                let min = rect.lerp_inside(Vec2::new(start, (irow as f32 + 0.05) / rows as f32));
+                let max = rect.lerp_inside(Vec2::new(stop, (irow as f32 + 0.95) / rows as f32));
+                mesh.add_colored_rect(Rect::from_min_max(min, max), Self::item_color(item, config));
+            }
+        }
+        ui.painter().add(mesh);
+    }
+
+    // Computes the rect of every item in view, in screen space
+    fn layout_tile(
+        tile: &SlotTileData,
+        rows: u64,
+        rect: Rect,
+        viewport: Rect,
+        cx: &Context,
+    ) -> TileLayout {
         // Figure out roughly how large a pixel is on the screen.
         let pixel_ns = (cx.view_interval.duration_ns() as f32 / rect.width()) as i64;
+        let merge_px = cx.frame_budget.merge_px();
 
-        // Track which item, if any, we're interacting with
-        let mut interact_item = None;
-
+        let mut items = Vec::new();
         for (row, row_items) in tile.items.iter().enumerate() {
             // Need to reverse the rows because we're working in screen space
             let irow = rows - (row as u64) - 1;
@@ -722,11 +1500,8 @@ impl Slot {
                 continue;
             }
 
-            // Check if mouse is hovering over this row
-            let row_rect = Rect::from_min_max(row_min, row_max);
-            let row_hover = hover_pos.is_some_and(|h| row_rect.contains(h));
-
-            // Now handle the items
+            let mut row_rects: Vec<(usize, usize, Rect)> = Vec::new();
+            let mut last_narrow = false;
             for (item_idx, item) in row_items.iter().enumerate() {
                 if !cx.view_interval.overlaps(item.interval) {
                     continue;
@@ -755,39 +1530,159 @@ impl Slot {
                 let stop = cx.view_interval.unlerp(interval.stop).at_most(1.0);
                 let min = rect.lerp_inside(Vec2::new(start, (irow as f32 + 0.05) / rows as f32));
                 let max = rect.lerp_inside(Vec2::new(stop, (irow as f32 + 0.95) / rows as f32));
-
                 let item_rect = Rect::from_min_max(min, max);
+
+                // Merge runs of items no wider than a column when they touch
+                // the same column
+                let narrow = item_rect.width() <= merge_px;
+                let column = |x: f32| x / merge_px;
+                match row_rects.last_mut() {
+                    Some((_, count, run))
+                        if narrow
+                            && last_narrow
+                            && column(item_rect.min.x).floor() < column(run.max.x).ceil() =>
+                    {
+                        *count += 1;
+                        *run = run.union(item_rect);
+                    }
+                    _ => row_rects.push((item_idx, 1, item_rect)),
+                }
+                last_narrow = narrow;
+            }
+            items.push(RowLayout {
+                row,
+                rect: Rect::from_min_max(row_min, row_max),
+                items: row_rects,
+            });
+        }
+
+        TileLayout {
+            view_interval: cx.view_interval,
+            rect,
+            viewport,
+            rows,
+            merge_px,
+            items,
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn render_tile(
+        &mut self,
+        tile_id: TileID,
+        rows: u64,
+        mut hover_pos: Option<Pos2>,
+        ui: &mut egui::Ui,
+        rect: Rect,
+        viewport: Rect,
+        config: &mut Config,
+        cx: &mut Context,
+    ) -> Option<Pos2> {
+        let tile = self.tiles.get(&tile_id).unwrap();
+
+        if !tile.is_some() {
+            // Tile hasn't finished loading.
+            return hover_pos;
+        }
+        let tile = tile.as_ref().unwrap();
+
+        let tile = match tile {
+            Ok(t) => t,
+            Err(_) => {
+                // Paint the entire tile red to indicate the error.
+                ui.painter().rect(rect, 0.0, Color32::RED, Stroke::NONE);
+                return hover_pos;
+            }
+        };
+
+        if !cx.view_interval.overlaps(tile_id.0) {
+            return hover_pos;
+        }
+
+        let stale = self.layouts.get(&tile_id).is_none_or(|layout| {
+            layout.view_interval != cx.view_interval
+                || layout.rect != rect
+                || layout.viewport != viewport
+                || layout.rows != rows
+                || layout.merge_px != cx.frame_budget.merge_px()
+        });
+        if stale {
+            let layout = Self::layout_tile(tile, rows, rect, viewport, cx);
+            self.layouts.insert(tile_id, layout);
+        }
+        let layout = &self.layouts[&tile_id];
+
+        // Track which item, if any, we're interacting with
+        let mut interact_item = None;
+
+        // Time under the crosshair, if it's inside this slot
+        let crosshair_time = cx
+            .crosshair_pos
+            .filter(|p| rect.x_range().contains(p.x))
+            .map(|p| cx.view_interval.lerp((p.x - rect.left()) / rect.width()));
+        let mut crosshair_items = Vec::new();
+
+        // All the items go into one mesh, which is much cheaper to draw than
+        // a shape per item
+        let mut mesh = egui::Mesh::default();
+        for row_layout in &layout.items {
+            let (row, row_rect) = (row_layout.row, row_layout.rect);
+            let row_items = &tile.items[row];
+
+            // Check if mouse is hovering over this row
+            let row_hover = hover_pos.is_some_and(|h| row_rect.contains(h));
+
+            // Check if the crosshair passes through an item in this row
+            if let Some(time) = crosshair_time {
+                if let Some(item_idx) = row_items.iter().position(|i| i.interval.contains(time)) {
+                    crosshair_items.push((row, item_idx, row_rect));
+                }
+            }
+
+            // Now handle the items
+            for &(item_idx, count, item_rect) in &row_layout.items {
+                let items = &row_items[item_idx..item_idx + count];
                 if row_hover && hover_pos.is_some_and(|h| item_rect.contains(h)) {
                     hover_pos = None;
-                    interact_item = Some((row, item_idx, item_rect, tile_id));
+                    interact_item = Some((row, item_idx, count, item_rect, tile_id));
                 }
 
-                let highlight = config.items_selected.contains_key(&item.item_uid);
-
-                let mut color = item.color;
-                if !config.search_state.query.is_empty() {
-                    if config.search_state.result_set.contains(&item.item_uid) || highlight {
-                        color = Color32::RED;
-                    } else {
-                        color = color.gamma_multiply(0.2);
+                if cx.select_rect.is_some_and(|r| r.intersects(item_rect)) {
+                    for item in items {
+                        config.select_item(&self.entry_id, item.item_uid, item.interval);
                     }
-                } else if highlight {
-                    color = Color32::RED;
                 }
 
-                ui.painter().rect(item_rect, 0.0, color, Stroke::NONE);
+                let color = if count == 1 {
+                    Self::item_color(&items[0], config)
+                } else {
+                    blend_colors(items.iter().map(|item| Self::item_color(item, config)))
+                };
+                mesh.add_colored_rect(item_rect, color);
             }
         }
+        ui.painter().add(mesh);
 
-        if let Some((row, item_idx, item_rect, tile_id)) = interact_item {
+        if !crosshair_items.is_empty() && cx.frame_budget.show_labels() {
+            const PART: bool = false;
+            if let Some(Ok(tile_meta)) =
+                self.fetch_meta_tile(tile_id, config, PART, RequestPriority::Visible)
+            {
+                let crosshair_x = cx.crosshair_pos.unwrap().x;
+                Self::crosshair_labels(ui, crosshair_x, &crosshair_items, tile_meta);
+            }
+        }
+
+        if let Some((row, item_idx, count, item_rect, tile_id)) = interact_item {
             // Hack: clone here  to avoid mutability conflict.
             let entry_id = self.entry_id.clone();
             const PART: bool = false;
-            if let Some(tile_meta) = self.fetch_meta_tile(tile_id, config, PART) {
+            if let Some(tile_meta) =
+                self.fetch_meta_tile(tile_id, config, PART, RequestPriority::Visible)
+            {
                 let tile_meta = match tile_meta {
                     Ok(t) => t,
                     Err(e) => {
-                        warn!("{}", e);
                         ui.show_tooltip("task_tooltip", &item_rect, e);
                         return hover_pos;
                     }
@@ -795,20 +1690,15 @@ impl Slot {
 
                 let item_meta = &tile_meta.items[row][item_idx];
                 ui.show_tooltip_ui("task_tooltip", &item_rect, |ui| {
-                    ui.label(&item_meta.title);
-                    if cx.debug {
-                        ui.label(format!("Item UID: {}", item_meta.item_uid.0));
+                    if count > 1 {
+                        ui.label(format!(
+                            "First of {} items drawn in this pixel. Zoom in to see the rest.",
+                            count
+                        ));
+                        ui.separator();
                     }
-                    for (field_id, field, color) in &item_meta.fields {
-                        let name = config.field_schema.get_name(*field_id).unwrap();
-                        let text = format!("{}", FieldWithName(name, field));
-                        if let Some(color) = color {
-                            ui.label(RichText::new(text).color(*color));
-                        } else {
-                            ui.label(text);
-                        }
-                    }
-                    ui.label("(Click to show details.)");
+                    Self::item_tooltip(ui, item_meta, &config.field_schema, cx);
+                    ui.label("(Click to show details. Middle-click to pin.)");
                 });
 
                 // Also mark task as selected if the mouse has been clicked
@@ -834,6 +1724,21 @@ impl Slot {
                             }
                         }
                     }
+
+                    // Middle-click pins (or unpins) the tooltip in place
+                    if i.pointer.button_clicked(egui::PointerButton::Middle) {
+                        match config.pinned_tooltips.entry(item_meta.item_uid) {
+                            std::collections::btree_map::Entry::Vacant(e) => {
+                                e.insert(PinnedTooltip {
+                                    meta: item_meta.clone(),
+                                    pos: i.pointer.interact_pos().unwrap_or(item_rect.center()),
+                                });
+                            }
+                            std::collections::btree_map::Entry::Occupied(e) => {
+                                e.remove_entry();
+                            }
+                        }
+                    }
                 });
             }
         }
@@ -859,6 +1764,10 @@ impl Entry for Slot {
                 tiles: BTreeMap::new(),
                 tile_metas: BTreeMap::new(),
                 tile_metas_full: BTreeMap::new(),
+                previous: BTreeMap::new(),
+                epochs: BTreeMap::new(),
+                prefetched: BTreeSet::new(),
+                layouts: BTreeMap::new(),
             }
         } else {
             unreachable!()
@@ -897,13 +1806,141 @@ impl Entry for Slot {
         self.expanded = true;
     }
 
+    fn update_info(&mut self, info: &EntryInfo, clear_tiles: bool) {
+        let EntryInfo::Slot { max_rows, .. } = info else {
+            unreachable!()
+        };
+        self.max_rows = *max_rows;
+        if clear_tiles {
+            self.tiles.clear();
+            self.previous.clear();
+            self.epochs.clear();
+            self.tile_metas.clear();
+            self.tile_metas_full.clear();
+            self.layouts.clear();
+        }
+    }
+
+    fn clear_errors(&mut self) {
+        self.tiles.retain(|_, tile| !matches!(tile, Some(Err(_))));
+        self.tile_metas
+            .retain(|_, tile| !matches!(tile, Some(Err(_))));
+        self.tile_metas_full
+            .retain(|_, tile| !matches!(tile, Some(Err(_))));
+    }
+
     fn inflate_meta(&mut self, config: &mut Config, cx: &mut Context) {
         const FULL: bool = true;
-        let tile_ids = config.request_tiles(cx.view_interval, FULL);
-        Config::invalidate_cache(&tile_ids, &mut self.tile_metas_full);
+        let tile_ids = config.request_tiles(&self.entry_id, cx.view_interval, FULL);
+        config.hold_tiles(
+            TileKind::SlotMeta,
+            &self.entry_id,
+            FULL,
+            &tile_ids,
+            &[],
+            &mut self.tile_metas_full,
+        );
+        config.take_stale_tiles(
+            TileKind::SlotMeta,
+            &self.entry_id,
+            FULL,
+            &mut self.epochs,
+            &mut self.tile_metas_full,
+        );
         for tile_id in tile_ids {
-            self.fetch_meta_tile(tile_id, config, FULL);
+            // Search results trickle in behind whatever is on screen
+            self.fetch_meta_tile(tile_id, config, FULL, RequestPriority::Background);
+        }
+    }
+
+    fn collect_stats(&self, _config: &Config, stats: &mut SelectionStats) {
+        for tile in self.tile_metas_full.values() {
+            let Some(Ok(tile)) = tile else {
+                stats.missing_tiles += 1;
+                continue;
+            };
+            for item in tile.items.iter().flatten() {
+                stats.add_item(item);
+            }
+        }
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn collect_export(&self, _config: &Config, items: &mut ExportedItems) {
+        // Colors only come with the slot tiles, which are loaded for
+        // whatever is on screen
+        let mut colors = BTreeMap::new();
+        for tile in self.tiles.values() {
+            if let Some(Ok(tile)) = tile {
+                for item in tile.items.iter().flatten() {
+                    colors.insert(item.item_uid, item.color);
+                }
+            }
+        }
+
+        // Items may be sliced across multiple tiles, so only add them once
+        let mut seen = BTreeSet::new();
+        let mut result = Vec::new();
+        for tile in self.tile_metas_full.values() {
+            let Some(Ok(tile)) = tile else {
+                items.missing_tiles += 1;
+                continue;
+            };
+            for (row, row_items) in tile.items.iter().enumerate() {
+                for meta in row_items {
+                    if !meta.original_interval.overlaps(items.interval)
+                        || items
+                            .only
+                            .as_ref()
+                            .is_some_and(|only| !only.contains(&meta.item_uid))
+                        || !seen.insert(meta.item_uid)
+                    {
+                        continue;
+                    }
+                    result.push(ExportItem {
+                        row,
+                        interval: meta.original_interval,
+                        color: colors.get(&meta.item_uid).copied().unwrap_or(Color32::GRAY),
+                        meta: meta.clone(),
+                    });
+                }
+            }
+        }
+        result.sort_by_key(|item| (item.interval.start, item.row));
+        items.slots.insert(self.entry_id.clone(), result);
+    }
+
+    fn hover_stats(&self, cx: &Context) -> Option<String> {
+        if self.tiles.is_empty() {
+            return None;
+        }
+
+        let mut stats = SlotStats::default();
+        for tile in self.tiles.values() {
+            let Some(Ok(tile)) = tile else {
+                stats.missing_tiles += 1;
+                continue;
+            };
+            for row in &tile.items {
+                for item in row {
+                    stats.add_item(item.item_uid, item.interval, cx.view_interval);
+                }
+            }
         }
+
+        let view_ns = cx.view_interval.duration_ns().max(1);
+        let busy = stats.busy_ns() as f64 / view_ns as f64 * 100.0;
+        let mut result = format!("Busy: {:.1}%\nItems: {}", busy, stats.items.len());
+        if let Some((item_uid, duration)) = stats.longest() {
+            let title = self
+                .find_item_title(item_uid)
+                .unwrap_or_else(|| format!("<Item UID: {}>", item_uid.0));
+            result.push_str(&format!("\nLongest: {} ({})", title, Timestamp(duration)));
+        }
+        if stats.missing_tiles > 0 {
+            result.push_str("\n(Some data is still loading.)");
+        }
+        Some(result)
     }
 
     fn search(&mut self, config: &mut Config) {
@@ -943,7 +1980,7 @@ impl Entry for Slot {
         let mut hover_pos = ui.rect_hover_pos(rect); // where is the mouse hovering?
 
         if self.expanded {
-            let tile_ids = self.inflate(config, cx);
+            let tile_ids = self.inflate(config, cx, RequestPriority::Visible);
 
             let style = ui.style();
             let visuals = style.noninteractive();
@@ -951,6 +1988,17 @@ impl Entry for Slot {
                 .rect(rect, 0.0, visuals.bg_fill, visuals.bg_stroke);
 
             let rows = self.rows();
+            for tile_id in &tile_ids {
+                if let Some(None) = self.tiles.get(tile_id) {
+                    for (_, old) in TileManager::previous_tiles(*tile_id, &self.previous) {
+                        if let Ok(old) = old {
+                            Self::render_preview(
+                                old, tile_id.0, rows, ui, rect, viewport, config, cx,
+                            );
+                        }
+                    }
+                }
+            }
             for tile_id in tile_ids {
                 hover_pos =
                     self.render_tile(tile_id, rows, hover_pos, ui, rect, viewport, config, cx);
@@ -958,6 +2006,19 @@ impl Entry for Slot {
         }
     }
 
+    fn prefetch(
+        &mut self,
+        _ui: &mut egui::Ui,
+        _rect: Rect,
+        _viewport: Rect,
+        config: &mut Config,
+        cx: &mut Context,
+    ) {
+        if self.expanded {
+            self.inflate(config, cx, RequestPriority::Prefetch);
+        }
+    }
+
     fn height(&self, _prefix: Option<&EntryID>, _config: &Config, cx: &Context) -> f32 {
         self.rows() as f32 * cx.row_height
     }
@@ -991,11 +2052,15 @@ impl<S: Entry> Panel<S> {
         let max_y = min_y + slot.height(None, config, cx);
         *y = max_y + ROW_PADDING;
 
-        // Cull if out of bounds
+        // Cull if out of bounds, keeping rows within a screen of the viewport
+        // to fetch their tiles
         // Note: need to shift by rect.min to get to viewport space
-        if max_y - rect.min.y < viewport.min.y {
+        let margin = viewport.height();
+        let above = max_y - rect.min.y < viewport.min.y;
+        let below = min_y - rect.min.y > viewport.max.y;
+        if max_y - rect.min.y < viewport.min.y - margin {
             return false;
-        } else if min_y - rect.min.y > viewport.max.y {
+        } else if min_y - rect.min.y > viewport.max.y + margin {
             return true;
         }
 
@@ -1016,6 +2081,11 @@ impl<S: Entry> Panel<S> {
         // Note: viewport.min is NOT necessarily (0, 0)
         let content_viewport = viewport.translate(Vec2::new(0.0, rect.min.y - min_y));
 
+        if above || below {
+            slot.prefetch(ui, content_subrect, content_viewport, config, cx);
+            return false;
+        }
+
         slot.content(ui, content_subrect, content_viewport, config, cx);
         slot.label(ui, label_subrect, cx);
 
@@ -1102,11 +2172,43 @@ impl<S: Entry> Entry for Panel<S> {
     }
 
     fn expand_slot(&mut self, entry_id: &EntryID, level: u64) {
+        self.expanded = true;
+        // The entry may be this panel, rather than a slot inside it
+        if entry_id.level() == level {
+            return;
+        }
         self.slots
             .get_mut(entry_id.slot_index(level).unwrap() as usize)
             .unwrap()
             .expand_slot(entry_id, level + 1);
-        self.expanded = true;
+    }
+
+    fn update_info(&mut self, info: &EntryInfo, clear_tiles: bool) {
+        let EntryInfo::Panel { summary, slots, .. } = info else {
+            unreachable!()
+        };
+        match (&mut self.summary, summary) {
+            (Some(entry), Some(info)) => entry.update_info(info, clear_tiles),
+            (None, Some(info)) => {
+                self.summary = Some(Summary::new(info, self.entry_id.summary()));
+            }
+            _ => (),
+        }
+        for (i, info) in slots.iter().enumerate() {
+            match self.slots.get_mut(i) {
+                Some(entry) => entry.update_info(info, clear_tiles),
+                None => self.slots.push(S::new(info, self.entry_id.child(i as u64))),
+            }
+        }
+    }
+
+    fn clear_errors(&mut self) {
+        if let Some(summary) = &mut self.summary {
+            summary.clear_errors();
+        }
+        for slot in &mut self.slots {
+            slot.clear_errors();
+        }
     }
 
     fn inflate_meta(&mut self, config: &mut Config, cx: &mut Context) {
@@ -1137,6 +2239,32 @@ impl<S: Entry> Entry for Panel<S> {
         }
     }
 
+    fn collect_stats(&self, config: &Config, stats: &mut SelectionStats) {
+        let force = config.search_state.include_collapsed_entries;
+        if self.expanded || force {
+            for slot in &self.slots {
+                // Apply visibility settings
+                if !force && !Self::is_slot_visible(slot, config) {
+                    continue;
+                }
+
+                slot.collect_stats(config, stats);
+            }
+        }
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn collect_export(&self, config: &Config, items: &mut ExportedItems) {
+        // Only what's on screen, even if search includes collapsed entries
+        if self.expanded {
+            for slot in &self.slots {
+                if Self::is_slot_visible(slot, config) {
+                    slot.collect_export(config, items);
+                }
+            }
+        }
+    }
+
     fn content(
         &mut self,
         ui: &mut egui::Ui,
@@ -1164,6 +2292,18 @@ impl<S: Entry> Entry for Panel<S> {
         }
     }
 
+    // Nothing is drawn for rows outside the viewport, so this is the same
+    fn prefetch(
+        &mut self,
+        ui: &mut egui::Ui,
+        rect: Rect,
+        viewport: Rect,
+        config: &mut Config,
+        cx: &mut Context,
+    ) {
+        self.content(ui, rect, viewport, config, cx);
+    }
+
     fn height(&self, prefix: Option<&EntryID>, config: &Config, cx: &Context) -> f32 {
         const UNEXPANDED_ROWS: u64 = 2;
         const ROW_PADDING: f32 = 4.0;
@@ -1402,20 +2542,55 @@ impl SearchState {
     }
 }
 
+#[cfg(not(target_arch = "wasm32"))]
+impl ExportState {
+    fn new(info: DataSourceInfo) -> Self {
+        Self {
+            info,
+            open: false,
+            format: "csv",
+            options: ExportOptions::default(),
+            path: String::new(),
+            selection_only: false,
+            pending: false,
+            status: None,
+        }
+    }
+}
+
 impl Config {
-    fn new(data_source: Box<dyn DeferredDataSource>, info: DataSourceInfo) -> Self {
+    fn new(
+        data_source: Box<dyn DeferredDataSource>,
+        info: DataSourceInfo,
+        tile_cache: SharedTileCache,
+    ) -> Self {
+        #[cfg(not(target_arch = "wasm32"))]
+        let info_hash = disk_cache::info_hash(&info);
+        #[cfg(not(target_arch = "wasm32"))]
+        let export = ExportState::new(info.clone());
         let max_node = info.entry_info.nodes();
         let kinds = info.entry_info.kinds();
         let interval = info.interval;
         let tile_set = info.tile_set;
+        let entry_tile_sets = info.entry_tile_sets;
         let warning_message = info.warning_message;
+        let refresh_interval = info.refresh_interval;
+        let capabilities = info.capabilities;
+        let version = info.version;
+        // A single attempt, for sources that already retried what was worth
+        // retrying (so that a server that is down isn't asked again and again)
+        let retry_attempts = if data_source.retries_failures() {
+            1
+        } else {
+            RETRY_ATTEMPTS
+        };
 
         let mut field_schema = info.field_schema;
         assert!(!field_schema.contains_name("Title"));
         let title_id = field_schema.insert("Title".to_owned(), true);
         let search_state = SearchState::new(title_id);
 
-        Self {
+        let mut result = Self {
             field_schema,
             min_node: 0,
             max_node,
@@ -1423,45 +2598,547 @@ impl Config {
             kind_filter: BTreeSet::new(),
             interval,
             warning_message,
-            data_source: CountingDeferredDataSource::new(LruDeferredDataSource::new(
-                data_source,
-                NonZeroUsize::new(1024).unwrap(),
+            fetch_errors: FetchErrors::default(),
+            coverage_errors: FetchErrors::default(),
+            refresh_interval,
+            last_refresh: Instant::now(),
+            refresh_pending: false,
+            follow: None,
+            capabilities,
+            version,
+            data_source: CountingDeferredDataSource::new(CachingDeferredDataSource::with_cache(
+                DedupDeferredDataSource::new(RetryDeferredDataSource::new(
+                    // The timeout is outside the throttle, so that a request
+                    // that timed out is cancelled but keeps its slot until
+                    // the source lets go of it
+                    TimeoutDeferredDataSource::new(
+                        ThrottleDeferredDataSource::new(
+                            MetricsDeferredDataSource::new(data_source),
+                            MAX_OUTSTANDING_REQUESTS,
+                        ),
+                        REQUEST_TIMEOUT,
+                    ),
+                    retry_attempts,
+                    RETRY_DELAY,
+                )),
+                tile_cache,
             )),
+            #[cfg(not(target_arch = "wasm32"))]
+            info_hash,
             search_state,
             items_selected: BTreeMap::new(),
+            pinned_tooltips: BTreeMap::new(),
+            selection: BTreeMap::new(),
+            highlight_selection: true,
+            items_meta_requests: Vec::new(),
+            tile_requests: BTreeMap::new(),
+            export_stats_pending: false,
+            export_stats_status: None,
+            #[cfg(not(target_arch = "wasm32"))]
+            export,
             scroll_to_item: None,
             scroll_to_item_retry: None,
+            scroll_to_entry: None,
             tile_manager: TileManager::new(tile_set, interval),
+            tile_config: TileManagerConfig::default(),
+            baseline: None,
+            baseline_url: String::new(),
+            baseline_error: None,
+        };
+        result.tile_manager.set_entry_tile_sets(&entry_tile_sets);
+        #[cfg(not(target_arch = "wasm32"))]
+        result.load_tiles();
+        result
+    }
+
+    // Live profiles change under us, so their tiles are never kept on disk.
+    // A cache shared with another window has already been loaded.
+    #[cfg(not(target_arch = "wasm32"))]
+    fn load_tiles(&mut self) {
+        if self.refresh_period().is_some()
+            || !self.data_source.data_source().cache().borrow().is_empty()
+        {
+            return;
+        }
+        let Some(dir) = disk_cache::default_dir() else {
+            return;
+        };
+        let description = self.data_source.fetch_description();
+        if let Some(tiles) = disk_cache::load(&dir, &description, self.info_hash) {
+            self.data_source.data_source_mut().restore(tiles);
         }
     }
 
-    fn request_tiles(&mut self, view_interval: Interval, full: bool) -> Vec<TileID> {
-        self.tile_manager.request_tiles(view_interval, full)
+    #[cfg(not(target_arch = "wasm32"))]
+    fn save_tiles(&self) {
+        if self.refresh_period().is_some() {
+            return;
+        }
+        let Some(dir) = disk_cache::default_dir() else {
+            return;
+        };
+        let description = self.data_source.fetch_description();
+        let tiles = self.data_source.data_source().snapshot();
+        if tiles.is_empty() {
+            return;
+        }
+        if let Err(err) = disk_cache::save(&dir, &description, self.info_hash, tiles) {
+            warn!("failed to save tile cache: {}", err);
+        }
+    }
+
+    fn refresh_period(&self) -> Option<Duration> {
+        self.follow.or(self.refresh_interval)
+    }
+
+    // Applies a refreshed info. Returns true if the interval changed in a way
+    // that makes every tile loaded so far suspect.
+    fn update_info(&mut self, info: &DataSourceInfo, following: bool) -> bool {
+        self.max_node = info.entry_info.nodes();
+        self.kinds = info.entry_info.kinds();
+        self.warning_message = info.warning_message.clone();
+        self.refresh_interval = info.refresh_interval;
+        self.capabilities = info.capabilities;
+        self.version = info.version;
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            self.info_hash = disk_cache::info_hash(info);
+            self.export.info = info.clone();
+        }
+
+        // Title takes the first free ID, so it moves if the source added
+        // any fields
+        let mut field_schema = info.field_schema.clone();
+        let title_id = field_schema.insert("Title".to_owned(), true);
+        if field_schema != self.field_schema {
+            self.field_schema = field_schema;
+            self.search_state.title_field = title_id;
+            self.search_state.search_field = title_id;
+            self.search_state.clear();
+        }
+
+        // A live profile only grows at the end, so when that is in view,
+        // refresh just the tiles there. Items that complete late mostly land
+        // in the last tile, so it is refreshed even if the profile didn't
+        // grow.
+        let live = self.capabilities.live_updates && self.tile_manager.is_dynamic();
+        if live && following && info.interval.start == self.interval.start {
+            let edge = Interval::new(
+                Timestamp(self.interval.stop.0 - 1),
+                Timestamp(info.interval.stop.0.max(self.interval.stop.0) + 1),
+            );
+            self.interval = info.interval;
+            self.tile_manager.set_interval(info.interval);
+            self.tile_manager.mark_stale(edge);
+            self.data_source.data_source_mut().invalidate(edge);
+            return false;
+        }
+
+        if info.interval == self.interval {
+            return false;
+        }
+        self.interval = info.interval;
+        self.tile_manager =
+            TileManager::with_config(info.tile_set.clone(), info.interval, self.tile_config);
+        self.tile_manager.set_entry_tile_sets(&info.entry_tile_sets);
+        self.data_source.data_source_mut().clear();
+        self.search_state.clear();
+        true
+    }
+
+    // Failures are logged once here, rather than every time the tile is drawn
+    fn record_error(&mut self, error: &str) {
+        if error == CANCELLED {
+            return;
+        }
+        warn!("{}", error);
+        self.fetch_errors.count += 1;
+        self.fetch_errors.last = Some(error.to_owned());
+    }
+
+    fn record_coverage_errors(&mut self) {
+        let mut errors = self.tile_manager.take_coverage_errors();
+        if let Some(Baseline {
+            tile_manager: Some(tile_manager),
+            ..
+        }) = &mut self.baseline
+        {
+            errors.extend(tile_manager.take_coverage_errors());
+        }
+        for error in errors {
+            self.coverage_errors.count += 1;
+            self.coverage_errors.last = Some(error);
+        }
+    }
+
+    fn select_item(&mut self, entry_id: &EntryID, item_uid: ItemUID, interval: Interval) {
+        match self.selection.entry(item_uid) {
+            std::collections::btree_map::Entry::Vacant(e) => {
+                self.items_meta_requests.push(ItemMetaRequest {
+                    entry_id: entry_id.clone(),
+                    item_uid,
+                    interval,
+                });
+                e.insert(SelectedItem {
+                    interval,
+                    meta: None,
+                });
+            }
+            std::collections::btree_map::Entry::Occupied(mut e) => {
+                // Items sliced across tiles show up more than once
+                let item = e.get_mut();
+                item.interval = item.interval.union(interval);
+            }
+        }
+    }
+
+    fn load_baseline(&mut self, data_source: Box<dyn DeferredDataSource>) {
+        let locator = data_source.fetch_description().source_locator.join(", ");
+        let mut data_source = CountingDeferredDataSource::new(data_source);
+        data_source.fetch_info();
+        self.baseline = Some(Baseline {
+            data_source,
+            locator,
+            tile_manager: None,
+            offset_ns: 0,
+            tiles: BTreeMap::new(),
+        });
+        self.baseline_error = None;
+    }
+
+    fn update_baseline(&mut self) {
+        let Some(baseline) = &mut self.baseline else {
+            return;
+        };
+
+        match baseline.data_source.get_infos().pop() {
+            Some(Ok(info)) => {
+                baseline.offset_ns = self.interval.start.0 - info.interval.start.0;
+                baseline.tile_manager = Some(TileManager::with_config(
+                    info.tile_set,
+                    info.interval,
+                    self.tile_config,
+                ));
+            }
+            Some(Err(e)) => {
+                self.baseline_error = Some(e);
+                self.baseline = None;
+                return;
+            }
+            None => (),
+        }
+
+        for (tile, req) in baseline.data_source.get_summary_tiles() {
+            // If the entry doesn't exist, we already zoomed away and are no
+            // longer interested in this tile.
+            if let Some(tiles) = baseline.tiles.get_mut(&req.entry_id) {
+                tiles
+                    .entry(req.tile_id)
+                    .and_modify(|t| *t = Some(tile.map(|s| s.data)));
+            }
+        }
+    }
+
+    fn request_tiles(
+        &mut self,
+        entry_id: &EntryID,
+        view_interval: Interval,
+        full: bool,
+    ) -> Vec<TileID> {
+        self.tile_manager
+            .request_entry_tiles(entry_id, view_interval, full)
+    }
+
+    fn request_tiles_with_prefetch(
+        &mut self,
+        entry_id: &EntryID,
+        view_interval: Interval,
+        full: bool,
+    ) -> (Vec<TileID>, Vec<TileID>) {
+        self.tile_manager
+            .request_entry_tiles_with_prefetch(entry_id, view_interval, full)
+    }
+
+    // Requests each prefetch tile once, for as long as it stays a candidate.
+    // Candidates that went away without coming into view are cancelled.
+    fn prefetch_tiles(
+        &mut self,
+        kind: TileKind,
+        entry_id: &EntryID,
+        visible_ids: &[TileID],
+        tile_ids: &[TileID],
+        prefetched: &mut BTreeSet<TileID>,
+    ) {
+        let mut stale = Vec::new();
+        prefetched.retain(|tile_id| {
+            let keep = tile_ids.contains(tile_id);
+            if !keep && !visible_ids.contains(tile_id) {
+                stale.push(*tile_id);
+            }
+            keep
+        });
+        for tile_id in stale {
+            self.cancel_tile(TileRequest {
+                entry_id: entry_id.clone(),
+                tile_id,
+                full: false,
+            });
+        }
+        for tile_id in tile_ids {
+            if prefetched.insert(*tile_id) {
+                self.request_tile(kind, entry_id, *tile_id, false, RequestPriority::Prefetch);
+            }
+        }
+    }
+
+    // Requests still waiting to be sent are simply dropped; the rest are
+    // cancelled at the data source, which answers them with CANCELLED
+    fn cancel_tile(&mut self, req: TileRequest) {
+        let mut queued = false;
+        for requests in self.tile_requests.values_mut() {
+            let len = requests.len();
+            requests.retain(|r| *r != req);
+            queued |= requests.len() != len;
+        }
+        if !queued {
+            self.data_source
+                .cancel(&req.entry_id, req.tile_id, req.full);
+        }
+    }
+
+    fn set_tile_config(&mut self, tile_config: TileManagerConfig) {
+        self.tile_config = tile_config;
+        self.tile_manager.set_config(tile_config);
+        if let Some(Baseline {
+            tile_manager: Some(tile_manager),
+            ..
+        }) = &mut self.baseline
+        {
+            tile_manager.set_config(tile_config);
+        }
     }
 
-    fn invalidate_cache<T>(tile_ids: &[TileID], cache: &mut BTreeMap<TileID, T>) {
-        TileManager::invalidate_cache(tile_ids, cache);
+    fn request_tile(
+        &mut self,
+        kind: TileKind,
+        entry_id: &EntryID,
+        tile_id: TileID,
+        full: bool,
+        priority: RequestPriority,
+    ) {
+        self.tile_requests
+            .entry((kind, priority))
+            .or_default()
+            .push(TileRequest {
+                entry_id: entry_id.clone(),
+                tile_id,
+                full,
+            });
+    }
+
+    fn invalidate_cache<T>(tile_ids: &[TileID], cache: &mut BTreeMap<TileID, T>) {
+        TileManager::invalidate_cache(tile_ids, cache);
+    }
+
+    // Stores a response if the entry still wants the tile, and returns
+    // whether it did. A cancelled request leaves the tile to be requested
+    // again, since the tile came back into view before its cancellation was
+    // answered.
+    fn store_tile<T>(
+        tiles: &mut BTreeMap<TileID, Option<TileResult<T>>>,
+        tile_id: TileID,
+        tile: TileResult<T>,
+    ) -> bool {
+        let Some(t) = tiles.get_mut(&tile_id) else {
+            return false;
+        };
+        if matches!(&tile, Err(e) if e == CANCELLED) {
+            if t.is_none() {
+                tiles.remove(&tile_id);
+            }
+            return false;
+        }
+        *t = Some(tile);
+        true
+    }
+
+    // Drops an entry's tiles that are no longer wanted, and marks the rest
+    // as in use so that they aren't evicted. Requests still outstanding for
+    // the dropped tiles are cancelled, unless the tile is still wanted for
+    // something else (e.g., prefetching). Returns the dropped tiles that had
+    // already loaded.
+    fn hold_tiles<T>(
+        &mut self,
+        kind: TileKind,
+        entry_id: &EntryID,
+        full: bool,
+        tile_ids: &[TileID],
+        wanted: &[TileID],
+        cache: &mut BTreeMap<TileID, Option<T>>,
+    ) -> Vec<(TileID, T)> {
+        let req = |tile_id| TileRequest {
+            entry_id: entry_id.clone(),
+            tile_id,
+            full,
+        };
+        let mut stale = Vec::new();
+        let mut dropped = Vec::new();
+        cache.retain(|tile_id, tile| {
+            let keep = tile_ids.contains(tile_id);
+            if !keep {
+                self.tile_manager.remove_tile(kind, req(*tile_id));
+                match tile.take() {
+                    Some(tile) => dropped.push((*tile_id, tile)),
+                    None if !wanted.contains(tile_id) => stale.push(*tile_id),
+                    None => {}
+                }
+            }
+            keep
+        });
+        for tile_id in stale {
+            self.cancel_tile(req(tile_id));
+        }
+        for tile_id in tile_ids {
+            if cache.contains_key(tile_id) {
+                self.tile_manager.touch_tile(kind, req(*tile_id));
+            }
+        }
+        dropped
+    }
+
+    // Drops the tiles that were marked stale since the entry fetched them,
+    // so that they are requested again, and returns the ones that had loaded
+    fn take_stale_tiles<T>(
+        &mut self,
+        kind: TileKind,
+        entry_id: &EntryID,
+        full: bool,
+        epochs: &mut TileEpochs,
+        cache: &mut BTreeMap<TileID, Option<T>>,
+    ) -> Vec<(TileID, T)> {
+        epochs.retain(|(k, f, tile_id), _| (*k, *f) != (kind, full) || cache.contains_key(tile_id));
+        let mut stale = Vec::new();
+        for tile_id in cache.keys() {
+            let epoch = self.tile_manager.tile_epoch(*tile_id);
+            // Tiles seen for the first time were fetched in the current epoch
+            let seen = epochs.entry((kind, full, *tile_id)).or_insert(epoch);
+            if *seen < epoch {
+                *seen = epoch;
+                stale.push(*tile_id);
+            }
+        }
+
+        let mut dropped = Vec::new();
+        for tile_id in stale {
+            self.tile_manager.remove_tile(
+                kind,
+                TileRequest {
+                    entry_id: entry_id.clone(),
+                    tile_id,
+                    full,
+                },
+            );
+            if let Some(Some(tile)) = cache.remove(&tile_id) {
+                dropped.push((tile_id, tile));
+            }
+        }
+        dropped
     }
 
-    fn scroll_to_item(&mut self, item_loc: ItemLocator) {
+    fn scroll_to_item(&mut self, item_loc: ItemLocator, interval: Interval) {
         self.scroll_to_item = Some(item_loc.clone());
         self.scroll_to_item_retry = None;
 
         self.items_selected
             .entry(item_loc.item_uid)
-            .or_insert_with(|| ItemDetail {
-                meta: None,
-                loc: item_loc,
+            .or_insert_with(|| {
+                self.items_meta_requests.push(ItemMetaRequest {
+                    entry_id: item_loc.entry_id.clone(),
+                    item_uid: item_loc.item_uid,
+                    interval,
+                });
+                ItemDetail {
+                    meta: None,
+                    loc: item_loc,
+                }
             });
     }
 }
 
+// Summaries the source can't supply are left out of the view, rather than
+// showing up as plots that never load
+fn remove_unsupported_entries(info: &mut DataSourceInfo) {
+    fn remove_summaries(entry_info: &mut EntryInfo) {
+        if let EntryInfo::Panel { summary, slots, .. } = entry_info {
+            *summary = None;
+            slots.iter_mut().for_each(remove_summaries);
+        }
+    }
+
+    if !info.capabilities.summary_tiles {
+        remove_summaries(&mut info.entry_info);
+    }
+}
+
 impl Window {
-    fn new(data_source: Box<dyn DeferredDataSource>, info: DataSourceInfo, index: u64) -> Self {
+    fn new(
+        data_source: Box<dyn DeferredDataSource>,
+        mut info: DataSourceInfo,
+        index: u64,
+        tile_cache: SharedTileCache,
+    ) -> Self {
+        remove_unsupported_entries(&mut info);
         Self {
             panel: Panel::new(&info.entry_info, EntryID::root()),
             index,
-            config: Config::new(data_source, info),
+            config: Config::new(data_source, info, tile_cache),
+        }
+    }
+
+    // Dynamic sources ask for their info to be fetched again periodically,
+    // so that the view can grow along with the profile
+    fn refresh_info(&mut self, cx: &mut Context) {
+        let config = &mut self.config;
+        if let Some(refresh_interval) = config.refresh_period() {
+            if !config.refresh_pending && config.last_refresh.elapsed() >= refresh_interval {
+                config.data_source.fetch_info();
+                config.refresh_pending = true;
+            }
+        }
+
+        let Some(info) = config.data_source.get_infos().pop() else {
+            return;
+        };
+        config.refresh_pending = false;
+        config.last_refresh = Instant::now();
+        // Keep showing what we have, and try again next time
+        let mut info = match info {
+            Ok(info) => info,
+            Err(e) => {
+                config.record_error(&e);
+                return;
+            }
+        };
+        remove_unsupported_entries(&mut info);
+
+        let old_interval = config.interval;
+        // Keep following the end of the profile if it was in view
+        let following = cx.view_interval.stop >= old_interval.stop;
+        let stale = config.update_info(&info, following);
+        self.panel.update_info(&info.entry_info, stale);
+        if config.interval != old_interval {
+            cx.total_interval = cx.total_interval.union(info.interval);
+            if following && config.follow.is_some() {
+                // Slide along at the same zoom, replacing the last pan in the
+                // history rather than adding to it
+                let shift = cx.total_interval.stop.0 - cx.view_interval.stop.0;
+                let interval = cx.view_interval.translate(shift);
+                ProfApp::update_view_interval(cx, interval, IntervalOrigin::Pan);
+                ProfApp::update_interval_select_state(cx);
+            } else if following {
+                let interval = Interval::new(cx.view_interval.start, cx.total_interval.stop);
+                ProfApp::zoom(cx, interval);
+            }
         }
     }
 
@@ -1477,14 +3154,41 @@ impl Window {
         self.panel.find_summary_mut(entry_id, 0)
     }
 
+    // Drops the tiles that no longer fit in the memory budget. They are
+    // fetched again (usually from the cache) if they come back into view.
+    fn evict_tiles(&mut self) {
+        for (kind, req) in self.config.tile_manager.evict_tiles() {
+            match kind {
+                TileKind::Summary => {
+                    if let Some(entry) = self.find_summary_mut(&req.entry_id) {
+                        entry.tiles.remove(&req.tile_id);
+                    }
+                }
+                TileKind::Slot => {
+                    if let Some(entry) = self.find_slot_mut(&req.entry_id) {
+                        entry.tiles.remove(&req.tile_id);
+                    }
+                }
+                TileKind::SlotMeta => {
+                    if let Some(entry) = self.find_slot_mut(&req.entry_id) {
+                        if req.full {
+                            entry.tile_metas_full.remove(&req.tile_id);
+                        } else {
+                            entry.tile_metas.remove(&req.tile_id);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
     fn expand_slot(&mut self, entry_id: &EntryID) {
         self.panel.expand_slot(entry_id, 0);
     }
 
-    fn inflate_meta(&mut self, entry_id: &EntryID, cx: &mut Context) {
-        // Use the panel version directly to avoid a mutability conflict
-        let slot = self.panel.find_slot_mut(entry_id, 0).unwrap();
-        slot.inflate_meta(&mut self.config, cx);
+    fn scroll_to_entry(&mut self, entry_id: EntryID) {
+        self.expand_slot(&entry_id);
+        self.config.scroll_to_entry = Some(entry_id);
     }
 
     fn find_item_irow(&self, entry_id: &EntryID, item_uid: ItemUID) -> Option<usize> {
@@ -1530,6 +3234,8 @@ impl Window {
                 ui.label(RichText::new(message).color(Color32::RED));
             }
         });
+        self.fetch_errors(ui);
+        self.coverage_errors(ui);
 
         ScrollArea::vertical()
             .auto_shrink([false; 2])
@@ -1582,6 +3288,11 @@ impl Window {
                     }
                 }
 
+                if let Some(entry_id) = self.config.scroll_to_entry.take() {
+                    let prefix_height = self.panel.height(Some(&entry_id), &self.config, cx);
+                    scroll_to(0, prefix_height);
+                }
+
                 // Root panel has no label
                 self.panel.content(ui, rect, viewport, &mut self.config, cx);
             });
@@ -1719,6 +3430,56 @@ impl Window {
         }
     }
 
+    fn fetch_errors(&mut self, ui: &mut egui::Ui) {
+        let FetchErrors {
+            count,
+            last: Some(last),
+        } = &self.config.fetch_errors
+        else {
+            return;
+        };
+        let message = if *count == 1 {
+            format!("A request failed: {last}")
+        } else {
+            format!("{count} requests failed, most recently: {last}")
+        };
+        let mut retry = false;
+        let mut dismiss = false;
+        ui.horizontal(|ui| {
+            ui.label(RichText::new(message).color(Color32::RED));
+            retry = ui.button("Retry").clicked();
+            dismiss = ui.button("Dismiss").clicked();
+        });
+        // Tiles that failed are requested again as they come back into view
+        if retry {
+            self.panel.clear_errors();
+        }
+        if retry || dismiss {
+            self.config.fetch_errors = FetchErrors::default();
+        }
+    }
+
+    fn coverage_errors(&mut self, ui: &mut egui::Ui) {
+        let FetchErrors {
+            count,
+            last: Some(last),
+        } = &self.config.coverage_errors
+        else {
+            return;
+        };
+        let message = if *count == 1 {
+            format!("Tiles don't cover the view: {last}")
+        } else {
+            format!("Tiles didn't cover the view {count} times, most recently: {last}")
+        };
+        ui.horizontal(|ui| {
+            ui.label(RichText::new(message).color(Color32::RED));
+            if ui.button("Dismiss").clicked() {
+                self.config.coverage_errors = FetchErrors::default();
+            }
+        });
+    }
+
     fn controls(&mut self, ui: &mut egui::Ui, cx: &mut Context) {
         const WIDGET_PADDING: f32 = 8.0;
         ui.heading(format!("Profile {}: Controls", self.index));
@@ -1730,6 +3491,515 @@ impl Window {
         self.expand_collapse(ui, cx);
         ui.add_space(WIDGET_PADDING);
         self.select_interval(ui, cx);
+        ui.add_space(WIDGET_PADDING);
+        self.export_stats_controls(ui, cx);
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            ui.add_space(WIDGET_PADDING);
+            self.export_controls(ui, cx);
+        }
+        ui.add_space(WIDGET_PADDING);
+        self.baseline_controls(ui, cx);
+        if cx.debug {
+            ui.add_space(WIDGET_PADDING);
+            self.cache_stats(ui, cx);
+            ui.add_space(WIDGET_PADDING);
+            self.request_metrics(ui, cx);
+            ui.add_space(WIDGET_PADDING);
+            self.transfer_stats(ui, cx);
+        }
+    }
+
+    fn cache_stats(&self, ui: &mut egui::Ui, cx: &mut Context) {
+        ui.subheading("Tile Cache", cx);
+        let cache = self.config.data_source.data_source().cache();
+        let stats = cache.borrow().stats();
+        let lookups = stats.hits + stats.misses;
+        let hit_rate = if lookups > 0 {
+            100.0 * stats.hits as f64 / lookups as f64
+        } else {
+            0.0
+        };
+        ui.label(format!(
+            "Hits: {} / Misses: {} ({hit_rate:.1}% hit rate)",
+            stats.hits, stats.misses
+        ));
+        ui.label(format!(
+            "{} tiles, {:.1} of {} MiB",
+            stats.entries,
+            stats.bytes as f64 / (1 << 20) as f64,
+            cache.borrow().budget() >> 20
+        ));
+        let sharing = Rc::strong_count(cache);
+        if sharing > 1 {
+            ui.label(format!("Shared by {} windows", sharing));
+        }
+        let tile_manager = &self.config.tile_manager;
+        ui.label(format!(
+            "In view: {} tiles, {:.1} of {} MiB",
+            tile_manager.held_tiles(),
+            tile_manager.held_bytes() as f64 / (1 << 20) as f64,
+            self.config.tile_config.memory_budget >> 20
+        ));
+        const PART: bool = false;
+        let tile_size = tile_manager
+            .tile_size(PART)
+            .map_or("-".to_owned(), |size| Timestamp(size).to_string());
+        ui.label(match tile_manager.level(PART) {
+            Some(level) => format!(
+                "LOD {} of {}, tiles of {}",
+                level + 1,
+                tile_manager.levels(),
+                tile_size
+            ),
+            None => format!("Tiles of {}", tile_size),
+        });
+        if let Some(interval) = tile_manager.last_request_interval(PART) {
+            ui.label(format!("Last request: {}", interval));
+        }
+        let tm_stats = tile_manager.stats();
+        ui.label(format!(
+            "Tiles chosen: {} reused / {} recomputed",
+            tm_stats.hits, tm_stats.misses
+        ));
+    }
+
+    fn request_metrics(&self, ui: &mut egui::Ui, cx: &mut Context) {
+        ui.subheading("Requests", cx);
+        // Measured below the retry and throttle layers, so each attempt counts
+        // separately and time spent waiting in the queue is excluded
+        let dedup = self.config.data_source.data_source().data_source();
+        let throttle = dedup.data_source().data_source().data_source();
+        let metrics = throttle.data_source().metrics();
+        let kinds = [
+            ("Info", metrics.info),
+            ("Summary", metrics.summary_tiles),
+            ("Slot", metrics.slot_tiles),
+            ("Slot Meta", metrics.slot_meta_tiles),
+            ("Items Meta", metrics.items_meta),
+        ];
+        egui::Grid::new("request_metrics")
+            .num_columns(5)
+            .striped(true)
+            .show(ui, |ui| {
+                ui.label("Kind");
+                ui.label("Done (Failed)");
+                ui.label("Pending");
+                ui.label("Mean / Max");
+                ui.label("MiB");
+                ui.end_row();
+
+                for (name, m) in kinds {
+                    ui.label(name);
+                    ui.label(format!("{} ({})", m.responses, m.errors));
+                    ui.label(format!("{}", m.in_flight()));
+                    match m.mean_latency() {
+                        Some(mean) => ui.label(format!(
+                            "{:.0} / {:.0} ms",
+                            mean.as_secs_f64() * 1e3,
+                            m.max_latency.as_secs_f64() * 1e3
+                        )),
+                        None => ui.label("-"),
+                    };
+                    ui.label(format!("{:.1}", m.bytes as f64 / (1 << 20) as f64));
+                    ui.end_row();
+                }
+            });
+        ui.label(format!("{} requests queued", throttle.queued()));
+        ui.label(format!(
+            "{} duplicate requests coalesced",
+            dedup.coalesced()
+        ));
+    }
+
+    fn transfer_stats(&self, ui: &mut egui::Ui, cx: &mut Context) {
+        // Only sources that fetch over the network have any
+        let Some(stats) = self.config.data_source.transfer_stats() else {
+            return;
+        };
+        ui.subheading("Network", cx);
+        ui.label(format!(
+            "{} requests ({} failed), {:.1} MiB received",
+            stats.requests,
+            stats.errors,
+            stats.bytes as f64 / (1 << 20) as f64
+        ));
+        if let Some(mean) = stats.mean_latency() {
+            ui.label(format!(
+                "Latency: {:.0} / {:.0} ms (mean / max)",
+                mean.as_secs_f64() * 1e3,
+                stats.max_latency.as_secs_f64() * 1e3
+            ));
+            ui.label(format!(
+                "Decoding: {:.1} ms per request",
+                (stats.decode_time / stats.requests as u32).as_secs_f64() * 1e3
+            ));
+        }
+    }
+
+    fn selection_details(&mut self, ui: &mut egui::Ui) {
+        let selection = &self.config.selection;
+        let mut stats = SelectionStats::new(self.config.interval);
+        let mut missing = 0;
+        for item in selection.values() {
+            match &item.meta {
+                Some(meta) => stats.add_item(meta),
+                None => missing += 1,
+            }
+        }
+        let total_ns: i64 = stats.items.titles.values().map(|t| t.total_ns).sum();
+        let span = selection
+            .values()
+            .map(|item| item.interval)
+            .reduce(Interval::union)
+            .unwrap();
+
+        let mut clear = false;
+        #[cfg(not(target_arch = "wasm32"))]
+        let mut export = false;
+        ui.label(format!("{} items selected", selection.len()));
+        ui.label(format!("Span: {}", span));
+        ui.label(format!("Total Duration: {}", Timestamp(total_ns)));
+        if missing > 0 {
+            ui.horizontal(|ui| {
+                ui.spinner();
+                ui.label(format!("Loading details for {} items", missing));
+            });
+        }
+
+        ui.horizontal(|ui| {
+            if ui
+                .button("Copy")
+                .on_hover_text("Copy the selected items to the clipboard")
+                .clicked()
+            {
+                let mut text = String::from("title\tstart_ns\tstop_ns\tduration_ns\n");
+                for item in selection.values() {
+                    let Some(meta) = &item.meta else {
+                        continue;
+                    };
+                    let interval = meta.original_interval;
+                    text.push_str(&format!(
+                        "{}\t{}\t{}\t{}\n",
+                        meta.title,
+                        interval.start.0,
+                        interval.stop.0,
+                        interval.duration_ns()
+                    ));
+                }
+                ui.ctx().copy_text(text);
+            }
+            if ui
+                .button("Export CSV")
+                .on_hover_text("Export per-title statistics for the selected items")
+                .clicked()
+            {
+                let filename = format!("profile{}_selection_stats.csv", self.index);
+                if let Err(e) = save_file(&filename, &stats.items.to_csv()) {
+                    warn!("{}", e);
+                }
+            }
+            #[cfg(not(target_arch = "wasm32"))]
+            {
+                export = ui
+                    .button("Export...")
+                    .on_hover_text("Export the selected items to a file")
+                    .clicked();
+            }
+            ui.checkbox(&mut self.config.highlight_selection, "Highlight");
+            clear = ui.button("Clear").clicked();
+        });
+
+        ui.separator();
+        TableBuilder::new(ui)
+            .striped(true)
+            .column(Column::remainder().clip(true))
+            .columns(Column::auto(), 3)
+            .header(20.0, |mut header| {
+                for name in ["Title", "Count", "Total", "Max"] {
+                    header.col(|ui| {
+                        ui.strong(name);
+                    });
+                }
+            })
+            .body(|mut body| {
+                for (title, stats) in stats.items.rows() {
+                    body.row(20.0, |mut row| {
+                        row.col(|ui| {
+                            ui.label(title);
+                        });
+                        row.col(|ui| {
+                            ui.label(stats.count.to_string());
+                        });
+                        row.col(|ui| {
+                            ui.label(Timestamp(stats.total_ns).to_string());
+                        });
+                        row.col(|ui| {
+                            ui.label(Timestamp(stats.max_ns).to_string());
+                        });
+                    });
+                }
+            });
+
+        #[cfg(not(target_arch = "wasm32"))]
+        if export {
+            self.open_export(true);
+        }
+        if clear {
+            self.config.selection.clear();
+        }
+    }
+
+    fn baseline_controls(&mut self, ui: &mut egui::Ui, cx: &mut Context) {
+        ui.subheading("Compare to Baseline", cx);
+
+        ui.horizontal(|ui| {
+            ui.label("Location:");
+            ui.text_edit_singleline(&mut self.config.baseline_url)
+                .on_hover_text(
+                    "URL of a profile server or archive, or path to a local profile (an \
+                     archive directory, bundle or trace file)",
+                );
+            if ui.button("Load").clicked() {
+                match open_data_source(&self.config.baseline_url) {
+                    Ok(data_source) => self.config.load_baseline(data_source),
+                    Err(e) => self.config.baseline_error = Some(e),
+                }
+            }
+        });
+
+        if let Some(error) = &self.config.baseline_error {
+            ui.label(RichText::new(error).color(Color32::RED));
+        }
+
+        let mut clear = false;
+        match &self.config.baseline {
+            None => {
+                ui.label("No baseline loaded.");
+            }
+            Some(baseline) => {
+                ui.horizontal_wrapped(|ui| {
+                    if baseline.tile_manager.is_some() {
+                        ui.label(format!("Comparing to {}", baseline.locator));
+                    } else {
+                        ui.spinner();
+                        ui.label(format!("Loading {}", baseline.locator));
+                    }
+                    clear = ui.button("Clear").clicked();
+                });
+            }
+        }
+        if clear {
+            self.config.baseline = None;
+        }
+    }
+
+    fn export_stats_controls(&mut self, ui: &mut egui::Ui, cx: &mut Context) {
+        ui.subheading("Statistics", cx);
+        // Statistics are computed from item details
+        let supported = self.config.capabilities.slot_meta_tiles;
+        ui.horizontal(|ui| {
+            ui.add_enabled_ui(supported && !self.config.export_stats_pending, |ui| {
+                if ui
+                    .button("Export CSV")
+                    .on_hover_text("Export per-title statistics for the current interval")
+                    .clicked()
+                {
+                    self.config.export_stats_pending = true;
+                    self.config.export_stats_status = None;
+                }
+            });
+            if self.config.export_stats_pending {
+                ui.spinner();
+            }
+        });
+        if let Some(status) = &self.config.export_stats_status {
+            ui.label(status);
+        }
+
+        self.export_stats(cx);
+    }
+
+    fn export_stats(&mut self, cx: &mut Context) {
+        if !self.config.export_stats_pending {
+            return;
+        }
+
+        // Expand meta tiles. (Including collapsed entries, if requested).
+        self.panel.inflate_meta(&mut self.config, cx);
+
+        let mut stats = SelectionStats::new(cx.view_interval);
+        self.panel.collect_stats(&self.config, &mut stats);
+        if stats.missing_tiles > 0 {
+            return;
+        }
+
+        let filename = format!(
+            "profile{}_stats_{}_{}.csv",
+            self.index, cx.view_interval.start.0, cx.view_interval.stop.0
+        );
+        self.config.export_stats_pending = false;
+        self.config.export_stats_status = Some(match save_file(&filename, &stats.items.to_csv()) {
+            Ok(path) => format!("Saved {}", path),
+            Err(e) => format!("Export failed: {}", e),
+        });
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn export_controls(&mut self, ui: &mut egui::Ui, cx: &mut Context) {
+        ui.subheading("Export", cx);
+        // Like statistics, exports are made from item details
+        let supported = self.config.capabilities.slot_meta_tiles;
+        ui.add_enabled_ui(supported, |ui| {
+            if ui
+                .button("Export...")
+                .on_hover_text("Export the items in view to a file")
+                .clicked()
+            {
+                self.open_export(false);
+            }
+        });
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn open_export(&mut self, selection_only: bool) {
+        let export = &mut self.config.export;
+        if export.path.is_empty() {
+            let extension = find_format(export.format).map_or("", |format| format.extension);
+            export.path = format!("profile{}.{}", self.index, extension);
+        }
+        export.open = true;
+        export.selection_only = selection_only;
+        export.status = None;
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn export_dialog(&mut self, ctx: &egui::Context, cx: &mut Context) {
+        let mut open = self.config.export.open;
+        egui::Window::new(format!("Profile {}: Export", self.index))
+            .id(egui::Id::new(("export", self.index)))
+            .open(&mut open)
+            .resizable(false)
+            .show(ctx, |ui| self.export_form(ui, cx));
+        self.config.export.open = open;
+        // Closing the dialog gives up on an export that is still loading
+        if !open {
+            self.config.export.pending = false;
+        }
+
+        self.export_items(cx);
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn export_form(&mut self, ui: &mut egui::Ui, cx: &Context) {
+        let has_selection = !self.config.selection.is_empty();
+        let export = &mut self.config.export;
+        if !has_selection {
+            export.selection_only = false;
+        }
+
+        ui.horizontal(|ui| {
+            ui.label("Format:");
+            let previous = export.format;
+            egui::ComboBox::from_id_source(("export_format", self.index))
+                .selected_text(export.format)
+                .show_ui(ui, |ui| {
+                    // Only formats that can be written from the items the
+                    // viewer has loaded
+                    for format in FORMATS
+                        .iter()
+                        .filter(|format| format.export_items.is_some())
+                    {
+                        ui.selectable_value(&mut export.format, format.name, format.name)
+                            .on_hover_text(format.description);
+                    }
+                });
+            // Keep the file name in step with the format
+            if export.format != previous {
+                if let Some(format) = find_format(export.format) {
+                    let path = std::path::Path::new(&export.path).with_extension(format.extension);
+                    export.path = path.display().to_string();
+                }
+            }
+        });
+        ui.horizontal(|ui| {
+            ui.label("Items:");
+            ui.radio_value(&mut export.selection_only, false, "In view");
+            ui.add_enabled_ui(has_selection, |ui| {
+                ui.radio_value(&mut export.selection_only, true, "Selected")
+                    .on_hover_text("Only the selected items that are in view");
+            });
+        });
+        ui.label(format!("Interval: {}", cx.view_interval));
+        ui.horizontal(|ui| {
+            ui.label("File:");
+            ui.text_edit_singleline(&mut export.path);
+        });
+        ui.horizontal(|ui| {
+            let ready = !export.pending && !export.path.is_empty();
+            ui.add_enabled_ui(ready, |ui| {
+                if ui.button("Export").clicked() {
+                    export.pending = true;
+                    export.status = None;
+                }
+            });
+            if export.pending {
+                ui.spinner();
+            }
+        });
+        if let Some(status) = &export.status {
+            ui.label(status);
+        }
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn export_items(&mut self, cx: &mut Context) {
+        if !self.config.export.pending {
+            return;
+        }
+
+        // Expand meta tiles, which come from (and go into) the same cache
+        // as everything else in view
+        self.panel.inflate_meta(&mut self.config, cx);
+
+        let mut items = ExportedItems {
+            interval: cx.view_interval,
+            only: self
+                .config
+                .export
+                .selection_only
+                .then(|| self.config.selection.keys().copied().collect()),
+            ..Default::default()
+        };
+        self.panel.collect_export(&self.config, &mut items);
+        if items.missing_tiles > 0 {
+            return;
+        }
+
+        let export = &mut self.config.export;
+        export.pending = false;
+        // Entries in the same order as in the profile, with their panels
+        let slots = walk_entries(&export.info.entry_info, false)
+            .into_iter()
+            .filter_map(|entry| {
+                let slot_items = items.slots.remove(&entry.entry_id)?;
+                Some((entry, slot_items))
+            })
+            .collect();
+        let result = match find_format(export.format).and_then(|format| format.export_items) {
+            Some(export_items) => {
+                export_items(&export.info, slots, export.path.as_ref(), &export.options)
+                    .map_err(|e| e.to_string())
+            }
+            None => Err(format!("unable to export to {}", export.format)),
+        };
+        export.status = Some(match result {
+            Ok(()) => match std::fs::canonicalize(&export.path) {
+                Ok(path) => format!("Saved {}", path.display()),
+                Err(_) => format!("Saved {}", export.path),
+            },
+            Err(e) => format!("Export failed: {}", e),
+        });
     }
 
     fn search(&mut self, cx: &mut Context) {
@@ -1744,7 +4014,10 @@ impl Window {
         }
 
         // Expand meta tiles. (Including collapsed entries, if requested).
-        self.panel.inflate_meta(&mut self.config, cx);
+        // While frames are slow, make do with the tiles already loaded.
+        if !cx.frame_budget.defer_meta() {
+            self.panel.inflate_meta(&mut self.config, cx);
+        }
 
         // Search whatever data we have. Results are cached by entry/tile.
         self.panel.search(&mut self.config);
@@ -1825,7 +4098,7 @@ impl Window {
 
         self.config.search_state.build_entry_tree();
 
-        let mut scroll_target = None;
+        let mut scroll_target: Option<(ItemLocator, Interval)> = None;
         ScrollArea::vertical()
             // Hack: estimate size of bottom UI.
             .max_height(ui.available_height() - 70.0)
@@ -1853,11 +4126,14 @@ impl Window {
                                                         .interval
                                                         .grow(item.interval.duration_ns() / 20);
                                                     ProfApp::zoom(cx, interval);
-                                                    scroll_target = Some(ItemLocator {
-                                                        entry_id: level2_slot.entry_id.clone(),
-                                                        irow: Some(item.irow),
-                                                        item_uid: item.item_uid,
-                                                    });
+                                                    scroll_target = Some((
+                                                        ItemLocator {
+                                                            entry_id: level2_slot.entry_id.clone(),
+                                                            irow: Some(item.irow),
+                                                            item_uid: item.item_uid,
+                                                        },
+                                                        item.interval,
+                                                    ));
                                                     level2_slot.expanded = true;
                                                     level1_slot.expanded = true;
                                                     level0_slot.expanded = true;
@@ -1871,18 +4147,163 @@ impl Window {
                     });
                 }
             });
-        if let Some(target) = scroll_target {
-            self.config.scroll_to_item(target);
+        if let Some((target, interval)) = scroll_target {
+            self.config.scroll_to_item(target, interval);
+        }
+    }
+
+    fn search_controls(&mut self, ui: &mut egui::Ui, cx: &mut Context) {
+        const WIDGET_PADDING: f32 = 8.0;
+        ui.heading(format!("Profile {}: Search", self.index));
+        ui.add_space(WIDGET_PADDING);
+        if !self.config.capabilities.slot_meta_tiles {
+            ui.label("This data source does not provide item details to search.");
+            return;
+        }
+        self.search_box(ui, cx);
+        ui.add_space(WIDGET_PADDING);
+        self.search_results(ui, cx);
+    }
+}
+
+// Start screen for a URL that hosts several profiles, which lists them until
+// the user picks one to open
+struct ProfileChooser {
+    index: Box<dyn DeferredProfileIndex>,
+    profiles: Option<Result<ProfileIndex, String>>,
+    filter: String,
+}
+
+impl ProfileChooser {
+    fn new(mut index: Box<dyn DeferredProfileIndex>) -> Self {
+        index.fetch_index();
+        Self {
+            index,
+            profiles: None,
+            filter: String::new(),
+        }
+    }
+
+    fn locator(&self) -> String {
+        self.index.fetch_description().source_locator.join(", ")
+    }
+
+    fn poll(&mut self) {
+        if self.profiles.is_none() {
+            self.profiles = self.index.get_index();
         }
     }
 
-    fn search_controls(&mut self, ui: &mut egui::Ui, cx: &mut Context) {
+    fn is_loading(&self) -> bool {
+        self.profiles.is_none()
+    }
+
+    // Returns the data source for the profile the user picked, if any
+    fn show(&mut self, ui: &mut egui::Ui) -> Option<Result<Box<dyn DeferredDataSource>, String>> {
         const WIDGET_PADDING: f32 = 8.0;
-        ui.heading(format!("Profile {}: Search", self.index));
+        ui.heading("Choose a Profile");
+        ui.label(self.locator());
         ui.add_space(WIDGET_PADDING);
-        self.search_box(ui, cx);
+
+        let profiles = match &self.profiles {
+            None => {
+                ui.horizontal(|ui| {
+                    ui.spinner();
+                    ui.label("Loading the list of profiles");
+                });
+                return None;
+            }
+            Some(Err(e)) => {
+                let message = format!("Unable to load the list of profiles: {e}");
+                ui.label(RichText::new(message).color(Color32::RED));
+                if ui.button("Retry").clicked() {
+                    self.profiles = None;
+                    self.index.fetch_index();
+                }
+                return None;
+            }
+            Some(Ok(index)) => &index.profiles,
+        };
+        if profiles.is_empty() {
+            ui.label("No profiles are available.");
+            return None;
+        }
+
+        ui.horizontal(|ui| {
+            ui.label("Filter:");
+            ui.text_edit_singleline(&mut self.filter);
+        });
         ui.add_space(WIDGET_PADDING);
-        self.search_results(ui, cx);
+
+        let filter = self.filter.to_lowercase();
+        let mut chosen = None;
+        ScrollArea::vertical()
+            .auto_shrink([false; 2])
+            .show(ui, |ui| {
+                let matches = profiles.iter().filter(|profile| {
+                    profile.name.to_lowercase().contains(&filter)
+                        || profile.description.to_lowercase().contains(&filter)
+                });
+                for profile in matches {
+                    if ui.link(RichText::new(&profile.name).strong()).clicked() {
+                        chosen = Some(profile.clone());
+                    }
+                    if !profile.description.is_empty() {
+                        ui.label(&profile.description);
+                    }
+                    ui.add_space(WIDGET_PADDING);
+                }
+            });
+        chosen.map(|profile| self.index.open(&profile))
+    }
+}
+
+// The File > Open... dialog, which opens another profile next to the ones
+// already open
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Default)]
+struct OpenState {
+    open: bool,
+    locator: String,
+    error: Option<String>,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl OpenState {
+    // Returns the data source for the profile the user asked for, if any
+    fn dialog(&mut self, ctx: &egui::Context) -> Option<Box<dyn DeferredDataSource>> {
+        let mut open = self.open;
+        let mut result = None;
+        egui::Window::new("Open Profile")
+            .open(&mut open)
+            .collapsible(false)
+            .resizable(false)
+            .show(ctx, |ui| {
+                ui.label("Path or URL:");
+                let response = ui.text_edit_singleline(&mut self.locator).on_hover_text(
+                    "An archive directory, bundle or trace file, or the URL of a profile \
+                     server or archive",
+                );
+                let entered =
+                    response.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter));
+                let ready = !self.locator.trim().is_empty();
+                let clicked = ui.add_enabled(ready, egui::Button::new("Open")).clicked();
+                if ready && (clicked || entered) {
+                    match open_data_source(&self.locator) {
+                        Ok(data_source) => result = Some(data_source),
+                        Err(e) => self.error = Some(e),
+                    }
+                }
+                if let Some(error) = &self.error {
+                    ui.label(RichText::new(error).color(Color32::RED));
+                }
+            });
+        self.open = open && result.is_none();
+        if result.is_some() {
+            self.locator.clear();
+            self.error = None;
+        }
+        result
     }
 }
 
@@ -1896,36 +4317,45 @@ impl ProfApp {
     /// Called once before the first frame.
     pub fn new(
         cc: &eframe::CreationContext<'_>,
-        mut data_sources: Vec<Box<dyn DeferredDataSource>>,
+        data_sources: Vec<Box<dyn DeferredDataSource>>,
+        index: Option<Box<dyn DeferredProfileIndex>>,
+        settings: AppSettings,
     ) -> Self {
         // This is also where you can customized the look at feel of egui using
         // `cc.egui_ctx.set_visuals` and `cc.egui_ctx.set_fonts`.
 
         // Load previous app state (if any).
         // Note that you must enable the `persistence` feature for this to work.
-        let mut result: Self = if let Some(storage) = cc.storage {
+        let saved: SavedState = if let Some(storage) = cc.storage {
             eframe::get_value(storage, eframe::APP_KEY).unwrap_or_default()
         } else {
             Default::default()
         };
 
-        for data_source in &mut data_sources {
-            data_source.fetch_info();
-        }
-        result.pending_data_sources.clear();
-        result.pending_data_sources.extend(data_sources);
-
-        result.windows.clear();
-
-        result.cx.scale_factor = 1.0;
-        result.cx.row_scroll_delta = 0;
+        let mut viewer = Viewer::new(saved.cx, data_sources, index);
+        viewer.start = settings.start;
+        viewer.follow = settings.follow;
+        let mut result = Self {
+            viewer,
+            #[cfg(not(target_arch = "wasm32"))]
+            viewers: Vec::new(),
+            // The main window is the first
+            #[cfg(not(target_arch = "wasm32"))]
+            next_viewer: 2,
+            tile_caches: TileCaches {
+                budget: settings.tile_cache_bytes.unwrap_or(TILE_CACHE_BYTES),
+                ..Default::default()
+            },
+        };
 
-        #[cfg(not(target_arch = "wasm32"))]
-        {
-            result.last_update = Some(Instant::now());
+        // The user's configuration wins over whatever was saved last time
+        let cx = &mut result.viewer.cx;
+        if let Some(dark_mode) = settings.dark_mode {
+            cx.toggle_dark_mode = dark_mode;
         }
+        cx.keys = settings.keys;
 
-        let theme = if result.cx.toggle_dark_mode {
+        let theme = if cx.toggle_dark_mode {
             egui::Visuals::dark()
         } else {
             egui::Visuals::light()
@@ -2038,6 +4468,8 @@ impl ProfApp {
         cx.show_controls = false;
         for window in windows.iter_mut() {
             window.config.items_selected.clear();
+            window.config.pinned_tooltips.clear();
+            window.config.selection.clear();
         }
     }
 
@@ -2047,88 +4479,29 @@ impl ProfApp {
             return;
         }
 
-        enum Actions {
-            ZoomIn,
-            ZoomOut,
-            UndoZoom,
-            RedoZoom,
-            ResetZoom,
-            Pan(PercentageInteger, PanDirection),
-            Scroll(i32),
-            ExpandVertical,
-            ShrinkVertical,
-            ResetVertical,
-            ToggleControls,
-            ResetUI,
-            NoAction,
-        }
-        let action = ctx.input(|i| {
-            if i.modifiers.ctrl {
-                if i.modifiers.alt {
-                    if i.key_pressed(egui::Key::Plus) || i.key_pressed(egui::Key::Equals) {
-                        Actions::ExpandVertical
-                    } else if i.key_pressed(egui::Key::Minus) {
-                        Actions::ShrinkVertical
-                    } else if i.key_pressed(egui::Key::Num0) {
-                        Actions::ResetVertical
-                    } else {
-                        Actions::NoAction
-                    }
-                } else if i.key_pressed(egui::Key::Plus) || i.key_pressed(egui::Key::Equals) {
-                    Actions::ZoomIn
-                } else if i.key_pressed(egui::Key::Minus) {
-                    Actions::ZoomOut
-                } else if i.key_pressed(egui::Key::ArrowLeft) {
-                    Actions::UndoZoom
-                } else if i.key_pressed(egui::Key::ArrowRight) {
-                    Actions::RedoZoom
-                } else if i.key_pressed(egui::Key::Num0) {
-                    Actions::ResetZoom
-                } else {
-                    Actions::NoAction
-                }
-            } else if i.modifiers.shift {
-                if i.key_pressed(egui::Key::ArrowLeft) {
-                    Actions::Pan(Percentage::from(1), PanDirection::Left)
-                } else if i.key_pressed(egui::Key::ArrowRight) {
-                    Actions::Pan(Percentage::from(1), PanDirection::Right)
-                } else if i.key_pressed(egui::Key::ArrowUp) {
-                    Actions::Scroll(1)
-                } else if i.key_pressed(egui::Key::ArrowDown) {
-                    Actions::Scroll(-1)
-                } else {
-                    Actions::NoAction
-                }
-            } else if i.key_pressed(egui::Key::H) {
-                Actions::ToggleControls
-            } else if i.key_pressed(egui::Key::Escape) {
-                Actions::ResetUI
-            } else if i.key_pressed(egui::Key::ArrowLeft) {
-                Actions::Pan(Percentage::from(5), PanDirection::Left)
-            } else if i.key_pressed(egui::Key::ArrowRight) {
-                Actions::Pan(Percentage::from(5), PanDirection::Right)
-            } else if i.key_pressed(egui::Key::ArrowUp) {
-                Actions::Scroll(5)
-            } else if i.key_pressed(egui::Key::ArrowDown) {
-                Actions::Scroll(-5)
-            } else {
-                Actions::NoAction
-            }
-        });
+        let Some(action) = ctx.input(|i| cx.keys.pressed(i)) else {
+            return;
+        };
         match action {
-            Actions::ZoomIn => ProfApp::zoom_in(cx),
-            Actions::ZoomOut => ProfApp::zoom_out(cx),
-            Actions::UndoZoom => ProfApp::undo_pan_zoom(cx),
-            Actions::RedoZoom => ProfApp::redo_pan_zoom(cx),
-            Actions::ResetZoom => ProfApp::zoom(cx, cx.total_interval),
-            Actions::Pan(percent, dir) => ProfApp::pan(cx, percent, dir),
-            Actions::Scroll(rows) => cx.row_scroll_delta = rows,
-            Actions::ExpandVertical => ProfApp::multiply_scale_factor(cx, 2.0),
-            Actions::ShrinkVertical => ProfApp::multiply_scale_factor(cx, 0.5),
-            Actions::ResetVertical => ProfApp::reset_scale_factor(cx),
-            Actions::ToggleControls => cx.show_controls = !cx.show_controls,
-            Actions::ResetUI => ProfApp::reset_ui(cx, windows),
-            Actions::NoAction => {}
+            Action::PanLeft => ProfApp::pan(cx, Percentage::from(5), PanDirection::Left),
+            Action::PanRight => ProfApp::pan(cx, Percentage::from(5), PanDirection::Right),
+            Action::FinePanLeft => ProfApp::pan(cx, Percentage::from(1), PanDirection::Left),
+            Action::FinePanRight => ProfApp::pan(cx, Percentage::from(1), PanDirection::Right),
+            Action::ScrollUp => cx.row_scroll_delta = 5,
+            Action::ScrollDown => cx.row_scroll_delta = -5,
+            Action::FineScrollUp => cx.row_scroll_delta = 1,
+            Action::FineScrollDown => cx.row_scroll_delta = -1,
+            Action::ZoomIn => ProfApp::zoom_in(cx),
+            Action::ZoomOut => ProfApp::zoom_out(cx),
+            Action::UndoZoom => ProfApp::undo_pan_zoom(cx),
+            Action::RedoZoom => ProfApp::redo_pan_zoom(cx),
+            Action::ResetZoom => ProfApp::zoom(cx, cx.total_interval),
+            Action::ExpandVertical => ProfApp::multiply_scale_factor(cx, 2.0),
+            Action::ShrinkVertical => ProfApp::multiply_scale_factor(cx, 0.5),
+            Action::ResetVertical => ProfApp::reset_scale_factor(cx),
+            Action::ToggleControls => cx.show_controls = !cx.show_controls,
+            Action::ToggleCrosshair => cx.crosshair = !cx.crosshair,
+            Action::ResetUi => ProfApp::reset_ui(cx, windows),
         }
     }
 
@@ -2159,6 +4532,7 @@ impl ProfApp {
             // On the beginning of a drag, save our position so we can
             // calculate the delta
             cx.drag_origin = response.interact_pointer_pos();
+            cx.drag_select = ui.input(|i| i.modifiers.shift);
         }
 
         if let Some(origin) = cx.drag_origin {
@@ -2174,22 +4548,35 @@ impl ProfApp {
 
             let interval = Interval::new(start, stop);
 
+            // When selecting, the rectangle covers only the dragged rows
+            let select_rect = Rect::from_two_pos(origin, current).intersect(rect);
+
             if is_active_drag {
                 // Still in drag, draw a rectangle to show the dragged region
-                let drag_rect =
-                    Rect::from_min_max(Pos2::new(min, rect.min.y), Pos2::new(max, rect.max.y));
                 let color = Color32::DARK_GRAY.linear_multiply(0.5);
-                ui.painter().rect(drag_rect, 0.0, color, Stroke::NONE);
+                if cx.drag_select {
+                    let stroke = ui.style().visuals.selection.stroke;
+                    ui.painter().rect(select_rect, 0.0, color, stroke);
+                } else {
+                    let drag_rect =
+                        Rect::from_min_max(Pos2::new(min, rect.min.y), Pos2::new(max, rect.max.y));
+                    ui.painter().rect(drag_rect, 0.0, color, Stroke::NONE);
+                }
 
                 drag_interval = Some(interval);
             } else if response.drag_stopped() {
-                // Only set view interval if the drag was a certain amount
+                // Only act if the drag was a certain amount
                 const MIN_DRAG_DISTANCE: f32 = 4.0;
-                if max - min > MIN_DRAG_DISTANCE {
+                if cx.drag_select {
+                    if select_rect.size().max_elem() > MIN_DRAG_DISTANCE {
+                        cx.select_rect = Some(select_rect);
+                    }
+                } else if max - min > MIN_DRAG_DISTANCE {
                     ProfApp::zoom(cx, interval);
                 }
 
                 cx.drag_origin = None;
+                cx.drag_select = false;
             }
         }
 
@@ -2200,12 +4587,21 @@ impl ProfApp {
             // Draw vertical line through cursor
             const RADIUS: f32 = 12.0;
             let top = Pos2::new(hover.x, ui.min_rect().min.y);
-            let mid_top = Pos2::new(hover.x, (hover.y - RADIUS).at_least(ui.min_rect().min.y));
-            let mid_bottom = Pos2::new(hover.x, (hover.y + RADIUS).at_most(ui.min_rect().max.y));
             let bottom = Pos2::new(hover.x, ui.min_rect().max.y);
-            ui.painter().line_segment([top, mid_top], visuals.fg_stroke);
-            ui.painter()
-                .line_segment([mid_bottom, bottom], visuals.fg_stroke);
+            if cx.crosshair {
+                // Crosshair spans the full height and width of the timeline
+                let left = Pos2::new(rect.min.x, hover.y);
+                let right = Pos2::new(rect.max.x, hover.y);
+                ui.painter().line_segment([top, bottom], visuals.fg_stroke);
+                ui.painter().line_segment([left, right], visuals.fg_stroke);
+            } else {
+                let mid_top = Pos2::new(hover.x, (hover.y - RADIUS).at_least(ui.min_rect().min.y));
+                let mid_bottom =
+                    Pos2::new(hover.x, (hover.y + RADIUS).at_most(ui.min_rect().max.y));
+                ui.painter().line_segment([top, mid_top], visuals.fg_stroke);
+                ui.painter()
+                    .line_segment([mid_bottom, bottom], visuals.fg_stroke);
+            }
 
             // Show timestamp popup
 
@@ -2215,6 +4611,9 @@ impl ProfApp {
 
             let label_text = if let Some(drag) = drag_interval {
                 format!("{drag}")
+            } else if cx.crosshair {
+                // Crosshair shows the exact (nanosecond) timestamp
+                format!("t={} ns", time.0)
             } else {
                 let units: TimestampUnits = cx.view_interval.into();
                 let time_units = TimestampDisplay {
@@ -2262,7 +4661,67 @@ impl ProfApp {
         }
     }
 
-    fn display_controls(ui: &mut egui::Ui, mode: &mut ItemLinkNavigationMode) {
+    // This build's versions, and those of the open profiles' sources, to
+    // diagnose a viewer and server that don't match
+    fn display_about(ui: &mut egui::Ui, windows: &[Window]) {
+        ui.heading("Legion Prof");
+        ui.label(format!("Version {}", version::VERSION));
+        ui.label(format!(
+            "Protocol version {PROTOCOL_VERSION} (reads {})",
+            version::supported_protocols()
+        ));
+        let features = version::features();
+        if !features.is_empty() {
+            ui.label(format!("Features: {}", features.join(", ")));
+        }
+
+        if windows.is_empty() {
+            return;
+        }
+        ui.separator();
+        egui::Grid::new("about_profiles")
+            .num_columns(3)
+            .striped(true)
+            .show(ui, |ui| {
+                ui.strong("Profile");
+                ui.strong("Protocol");
+                ui.strong("Capabilities");
+                ui.end_row();
+                for window in windows {
+                    let config = &window.config;
+                    let locator = config.data_source.fetch_description().source_locator;
+                    ui.label(format!("{}: {}", window.index, locator.join(", ")));
+                    if config.version == 0 {
+                        ui.label("unversioned");
+                    } else {
+                        ui.label(config.version.to_string());
+                    }
+                    let capabilities = config.capabilities;
+                    let names: Vec<_> = [
+                        ("summaries", capabilities.summary_tiles),
+                        ("item details", capabilities.slot_meta_tiles),
+                        ("search", capabilities.search),
+                        ("batches", capabilities.batch_fetch),
+                        ("item lookup", capabilities.items_meta),
+                        ("live updates", capabilities.live_updates),
+                    ]
+                    .into_iter()
+                    .filter(|(_, supported)| *supported)
+                    .map(|(name, _)| name)
+                    .collect();
+                    ui.label(names.join(", "));
+                    ui.end_row();
+                }
+            });
+    }
+
+    fn display_controls(
+        ui: &mut egui::Ui,
+        keys: &KeyBindings,
+        mode: &mut ItemLinkNavigationMode,
+        tile_config: &mut TileManagerConfig,
+        frame_budget: &mut FrameBudget,
+    ) {
         fn show_row_ui(
             body: &mut egui_extras::TableBody<'_>,
             label: &str,
@@ -2288,19 +4747,15 @@ impl ProfApp {
                     });
                 };
                 show_row("Zoom to Interval", "Click and Drag");
-                show_row("Pan 5%", "Left/Right Arrow");
-                show_row("Pan 1%", "Shift + Left/Right Arrow");
-                show_row("Vertical Scroll", "Up/Down Arrow");
-                show_row("Fine Vertical Scroll", "Shift + Up/Down Arrow");
-                show_row("Zoom In", "Ctrl + Plus/Equals");
-                show_row("Zoom Out", "Ctrl + Minus");
-                show_row("Undo Pan/Zoom", "Ctrl + Left Arrow");
-                show_row("Redo Pan/Zoom", "Ctrl + Right Arrow");
-                show_row("Reset Pan/Zoom", "Ctrl + 0");
-                show_row("Expand Vertical Spacing", "Ctrl + Alt + Plus/Equals");
-                show_row("Shrink Vertical Spacing", "Ctrl + Alt + Minus");
-                show_row("Reset Vertical Spacing", "Ctrl + Alt + 0");
-                show_row("Toggle This Window", "H");
+                show_row("Select Items", "Shift + Click and Drag");
+                show_row("Add to Selection", "Ctrl + Shift + Click and Drag");
+                show_row("Pin Item Tooltip", "Middle Click");
+                for action in Action::ALL {
+                    let shortcuts = keys.describe(*action);
+                    show_row_ui(&mut body, action.label(), |ui: &mut _| {
+                        ui.label(&shortcuts);
+                    });
+                }
                 show_row_ui(&mut body, "Item Link Zoom or Pan", |ui: &mut _| {
                     egui::ComboBox::from_id_source("Item Link Zoom or Pan")
                         .selected_text(format!("{:?}", mode))
@@ -2309,6 +4764,52 @@ impl ProfApp {
                             ui.selectable_value(mode, ItemLinkNavigationMode::Pan, "Pan");
                         });
                 });
+                // Only applies to profiles that compute tiles on demand
+                show_row_ui(&mut body, "Refetch Tiles After Zooming", |ui: &mut _| {
+                    ui.add(
+                        egui::DragValue::new(&mut tile_config.zoom_ratio)
+                            .range(1.0..=16.0)
+                            .speed(0.1)
+                            .suffix("x"),
+                    );
+                });
+                show_row_ui(&mut body, "Tiles When Panning", |ui: &mut _| {
+                    let extension = &mut tile_config.extension;
+                    egui::ComboBox::from_id_source("Tiles When Panning")
+                        .selected_text(format!("{:?}", extension))
+                        .show_ui(ui, |ui| {
+                            ui.selectable_value(extension, CacheExtension::Extend, "Extend");
+                            ui.selectable_value(extension, CacheExtension::Replace, "Replace");
+                        });
+                });
+                show_row_ui(&mut body, "Align Tiles to Grid", |ui: &mut _| {
+                    ui.checkbox(&mut tile_config.aligned, "");
+                });
+                show_row_ui(&mut body, "Prefetch Nearby Tiles", |ui: &mut _| {
+                    ui.checkbox(&mut tile_config.prefetch, "");
+                });
+                show_row_ui(&mut body, "Tile Memory Budget", |ui: &mut _| {
+                    let mut mib = tile_config.memory_budget >> 20;
+                    ui.add(
+                        egui::DragValue::new(&mut mib)
+                            .range(16..=16384)
+                            .speed(16)
+                            .suffix(" MiB"),
+                    );
+                    tile_config.memory_budget = mib << 20;
+                });
+                show_row_ui(&mut body, "Verify Tile Coverage", |ui: &mut _| {
+                    ui.checkbox(&mut tile_config.verify, "");
+                });
+                show_row_ui(&mut body, "Reduce Detail When Slow", |ui: &mut _| {
+                    ui.checkbox(&mut frame_budget.enabled, "");
+                    if frame_budget.level() > 0 {
+                        ui.label(format!(
+                            "(reduced, {:.0} ms per frame)",
+                            frame_budget.average().as_secs_f64() * 1e3
+                        ));
+                    }
+                });
             });
     }
 
@@ -2496,61 +4997,234 @@ impl ProfApp {
 impl eframe::App for ProfApp {
     /// Called to save state before shutdown.
     fn save(&mut self, storage: &mut dyn eframe::Storage) {
-        eframe::set_value(storage, eframe::APP_KEY, self);
+        let saved = SavedState {
+            cx: self.viewer.cx.clone(),
+        };
+        eframe::set_value(storage, eframe::APP_KEY, &saved);
+    }
+
+    /// Called once on shutdown, after [`Self::save`].
+    #[cfg(not(target_arch = "wasm32"))]
+    fn on_exit(&mut self, _gl: Option<&eframe::glow::Context>) {
+        let windows = self
+            .viewers
+            .iter()
+            .flat_map(|(_, viewer)| &viewer.windows)
+            .chain(&self.viewer.windows);
+        let mut saved: Vec<&SharedTileCache> = Vec::new();
+        for window in windows {
+            let cache = window.config.data_source.data_source().cache();
+            if saved.iter().any(|other| Rc::ptr_eq(other, cache)) {
+                continue;
+            }
+            saved.push(cache);
+            window.config.save_tiles();
+        }
     }
 
     /// Called each time the UI needs repainting.
-    fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+    fn update(&mut self, ctx: &egui::Context, frame: &mut eframe::Frame) {
+        let cpu_usage = frame.info().cpu_usage;
+        self.viewer.update(ctx, cpu_usage, &mut self.tile_caches);
+
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            let Self {
+                viewer,
+                viewers,
+                next_viewer,
+                tile_caches,
+            } = self;
+
+            // Each further OS window is drawn along with this one, with the
+            // settings passed along so that a change made in any window
+            // shows up in all of them
+            viewers.retain_mut(|(number, other)| {
+                other.cx.share_settings(&viewer.cx);
+                let id = egui::ViewportId::from_hash_of(("viewer", *number));
+                let builder = egui::ViewportBuilder::default()
+                    .with_title(format!("Legion Prof (Window {number})"));
+                let open = ctx.show_viewport_immediate(id, builder, |ctx, _| {
+                    other.update(ctx, cpu_usage, tile_caches);
+                    !ctx.input(|i| i.viewport().close_requested())
+                });
+                viewer.cx.share_settings(&other.cx);
+                open
+            });
+
+            let mut new_window = std::mem::take(&mut viewer.new_window);
+            for (_, other) in viewers.iter_mut() {
+                new_window |= std::mem::take(&mut other.new_window);
+            }
+            if new_window {
+                let mut cx = Context::default();
+                cx.share_settings(&viewer.cx);
+                let mut other = Viewer::new(cx, Vec::new(), None);
+                other.follow = viewer.follow;
+                viewers.push((*next_viewer, other));
+                *next_viewer += 1;
+            }
+        }
+    }
+}
+
+impl Viewer {
+    fn new(
+        mut cx: Context,
+        mut data_sources: Vec<Box<dyn DeferredDataSource>>,
+        index: Option<Box<dyn DeferredProfileIndex>>,
+    ) -> Self {
+        for data_source in &mut data_sources {
+            data_source.fetch_info();
+        }
+        cx.scale_factor = 1.0;
+        cx.row_scroll_delta = 0;
+        let chooser = index.map(ProfileChooser::new);
+        Self {
+            // With nothing to show, start by asking for something
+            #[cfg(not(target_arch = "wasm32"))]
+            open: OpenState {
+                open: data_sources.is_empty() && chooser.is_none(),
+                ..Default::default()
+            },
+            pending_data_sources: data_sources.into(),
+            chooser,
+            cx,
+            #[cfg(not(target_arch = "wasm32"))]
+            last_update: Some(Instant::now()),
+            ..Default::default()
+        }
+    }
+
+    fn update(
+        &mut self,
+        ctx: &egui::Context,
+        cpu_usage: Option<f32>,
+        tile_caches: &mut TileCaches,
+    ) {
         let Self {
             pending_data_sources,
             windows,
+            load_errors,
+            chooser,
             cx,
             #[cfg(not(target_arch = "wasm32"))]
+            open,
+            #[cfg(not(target_arch = "wasm32"))]
+            new_window,
+            start,
+            follow,
+            #[cfg(not(target_arch = "wasm32"))]
             last_update,
-            ..
         } = self;
 
+        // Time spent on the previous frame, not counting time idle between
+        // frames
+        if let Some(cpu_usage) = cpu_usage {
+            cx.frame_budget.record(Duration::from_secs_f32(cpu_usage));
+        }
+
         if let Some(mut source) = pending_data_sources.pop_front() {
             // We made one request, so we know there is always zero or one
             // elements in this list.
-            if let Some(info) = source.get_infos().pop() {
-                let window = Window::new(source, info, windows.len() as u64);
-                if windows.is_empty() {
-                    cx.total_interval = window.config.interval;
-                } else {
-                    cx.total_interval = cx.total_interval.union(window.config.interval);
+            match source.get_infos().pop() {
+                Some(Ok(info)) => {
+                    let locator = source.fetch_description().source_locator;
+                    let tile_cache = tile_caches.get(locator);
+                    let entry_id = start.entry.as_ref().and_then(|path| {
+                        let entry_id = info.entry_info.find_path(path);
+                        if entry_id.is_none() {
+                            warn!("no entry {path:?} in profile {}", windows.len());
+                        }
+                        entry_id
+                    });
+                    let mut window = Window::new(source, info, windows.len() as u64, tile_cache);
+                    window.config.follow = *follow;
+                    if windows.is_empty() {
+                        cx.total_interval = window.config.interval;
+                    } else {
+                        cx.total_interval = cx.total_interval.union(window.config.interval);
+                    }
+                    let interval = start
+                        .interval
+                        .filter(|interval| interval.overlaps(cx.total_interval))
+                        .map_or(cx.total_interval, |interval| {
+                            interval.intersection(cx.total_interval)
+                        });
+                    ProfApp::zoom(cx, interval);
+                    if let Some(entry_id) = entry_id {
+                        window.scroll_to_entry(entry_id);
+                    }
+                    windows.push(window);
                 }
-                ProfApp::zoom(cx, cx.total_interval);
-                windows.push(window);
-            } else {
-                pending_data_sources.push_front(source);
+                Some(Err(e)) => {
+                    let locator = source.fetch_description().source_locator.join(", ");
+                    load_errors.push((locator, e));
+                }
+                None => pending_data_sources.push_front(source),
+            }
+            // Profiles opened later start with the whole profile as usual
+            if pending_data_sources.is_empty() {
+                *start = StartView::default();
             }
         }
 
+        if let Some(chooser) = chooser.as_mut() {
+            chooser.poll();
+        }
+
         for window in windows.iter_mut() {
+            window.refresh_info(cx);
+            window.config.set_tile_config(cx.tile_config);
+            window.config.update_baseline();
+
             for (tile, req) in window.config.data_source.get_summary_tiles() {
+                if let Err(e) = &tile {
+                    window.config.record_error(e);
+                }
+                let size = tile.as_ref().map_or(0, summary_tile_size);
+                let mut held = false;
                 if let Some(entry) = window.find_summary_mut(&req.entry_id) {
                     // If the entry doesn't exist, we already zoomed away and
                     // are no longer interested in this tile.
-                    entry
-                        .tiles
-                        .entry(req.tile_id)
-                        .and_modify(|t| *t = Some(tile.map(|s| s.data)));
+                    held = Config::store_tile(&mut entry.tiles, req.tile_id, tile.map(|s| s.data));
+                }
+                if held && size > 0 {
+                    window
+                        .config
+                        .tile_manager
+                        .insert_tile(TileKind::Summary, req, size);
                 }
             }
 
             for (tile, req) in window.config.data_source.get_slot_tiles() {
+                if let Err(e) = &tile {
+                    window.config.record_error(e);
+                }
+                let size = tile.as_ref().map_or(0, slot_tile_size);
+                let mut held = false;
                 if let Some(entry) = window.find_slot_mut(&req.entry_id) {
                     // If the entry doesn't exist, we already zoomed away and
                     // are no longer interested in this tile.
-                    entry
-                        .tiles
-                        .entry(req.tile_id)
-                        .and_modify(|t| *t = Some(tile.map(|s| s.data)));
+                    held = Config::store_tile(&mut entry.tiles, req.tile_id, tile.map(|s| s.data));
+                    if held {
+                        entry.layouts.remove(&req.tile_id);
+                    }
+                }
+                if held && size > 0 {
+                    window
+                        .config
+                        .tile_manager
+                        .insert_tile(TileKind::Slot, req, size);
                 }
             }
 
             for (tile, req) in window.config.data_source.get_slot_meta_tiles() {
+                if let Err(e) = &tile {
+                    window.config.record_error(e);
+                }
+                let size = tile.as_ref().map_or(0, slot_meta_tile_size);
+                let mut held = false;
                 if let Some(entry) = window.find_slot_mut(&req.entry_id) {
                     // If the entry doesn't exist, we already zoomed away and
                     // are no longer interested in this tile.
@@ -2559,9 +5233,34 @@ impl eframe::App for ProfApp {
                     } else {
                         &mut entry.tile_metas
                     };
-                    metas
-                        .entry(req.tile_id)
-                        .and_modify(|t| *t = Some(tile.map(|s| s.data)));
+                    held = Config::store_tile(metas, req.tile_id, tile.map(|s| s.data));
+                }
+                if held && size > 0 {
+                    window
+                        .config
+                        .tile_manager
+                        .insert_tile(TileKind::SlotMeta, req, size);
+                }
+            }
+            window.evict_tiles();
+            window.config.record_coverage_errors();
+
+            for (result, _reqs) in window.config.data_source.get_items_meta() {
+                match result {
+                    Ok(metas) => {
+                        for meta in metas {
+                            // If the item isn't selected anymore, the user
+                            // already closed it and we can drop the result.
+                            if let Some(item) = window.config.selection.get_mut(&meta.item_uid) {
+                                item.meta.get_or_insert(meta.clone());
+                            }
+                            if let Some(item) = window.config.items_selected.get_mut(&meta.item_uid)
+                            {
+                                item.meta.get_or_insert(meta);
+                            }
+                        }
+                    }
+                    Err(e) => window.config.record_error(&e),
                 }
             }
         }
@@ -2580,10 +5279,26 @@ impl eframe::App for ProfApp {
         egui::TopBottomPanel::top("top_panel").show(ctx, |ui| {
             egui::menu::bar(ui, |ui| {
                 ui.menu_button("File", |ui| {
+                    if ui.button("Open...").clicked() {
+                        open.open = true;
+                        ui.close_menu();
+                    }
+                    // Only where OS windows can be opened, rather than
+                    // embedded in this one
+                    if !ctx.embed_viewports() && ui.button("New Window").clicked() {
+                        *new_window = true;
+                        ui.close_menu();
+                    }
                     if ui.button("Quit").clicked() {
                         ctx.send_viewport_cmd(egui::ViewportCommand::Close);
                     }
                 });
+                ui.menu_button("Help", |ui| {
+                    if ui.button("About").clicked() {
+                        cx.show_about = true;
+                        ui.close_menu();
+                    }
+                });
             });
         });
 
@@ -2643,6 +5358,9 @@ impl eframe::App for ProfApp {
                     if ui.button("Show Controls").clicked() {
                         cx.show_controls = true;
                     }
+                    if ui.button("About").clicked() {
+                        cx.show_about = true;
+                    }
 
                     #[cfg(not(target_arch = "wasm32"))]
                     {
@@ -2664,10 +5382,38 @@ impl eframe::App for ProfApp {
             // Just set this on every frame for now
             cx.row_height = row_height * cx.scale_factor;
 
+            cx.crosshair_pos = if cx.crosshair {
+                ui.input(|i| i.pointer.hover_pos())
+                    .filter(|p| ui.max_rect().contains(*p))
+            } else {
+                None
+            };
+
             let y_scroll_delta = cx.row_height * cx.row_scroll_delta as f32;
             ui.scroll_with_delta(Vec2::new(0.0, y_scroll_delta));
             cx.row_scroll_delta = 0;
 
+            // A new rubber-band selection replaces the old one, unless the
+            // user holds Ctrl/Cmd to add to it
+            if cx.select_rect.is_some() && !ui.input(|i| i.modifiers.command) {
+                for window in windows.iter_mut() {
+                    window.config.selection.clear();
+                }
+            }
+
+            if windows.is_empty() && pending_data_sources.is_empty() {
+                if let Some(chooser) = chooser.as_mut() {
+                    match chooser.show(ui) {
+                        Some(Ok(mut source)) => {
+                            source.fetch_info();
+                            pending_data_sources.push_back(source);
+                        }
+                        Some(Err(e)) => load_errors.push((chooser.locator(), e)),
+                        None => {}
+                    }
+                }
+            }
+
             let mut remaining = windows.len();
             // Only wrap in a frame if more than one profile
             if remaining > 1 {
@@ -2687,13 +5433,29 @@ impl eframe::App for ProfApp {
                 }
             }
 
-            Self::cursor(ui, cx);
+            // The selection has been picked up by the slots by now
+            cx.select_rect = None;
+
+            ProfApp::cursor(ui, cx);
         });
 
+        egui::Window::new("About")
+            .open(&mut cx.show_about)
+            .resizable(false)
+            .show(ctx, |ui| ProfApp::display_about(ui, windows));
+
         egui::Window::new("Controls")
             .open(&mut cx.show_controls)
             .resizable(false)
-            .show(ctx, |ui| Self::display_controls(ui, &mut cx.item_link_mode));
+            .show(ctx, |ui| {
+                ProfApp::display_controls(
+                    ui,
+                    &cx.keys,
+                    &mut cx.item_link_mode,
+                    &mut cx.tile_config,
+                    &mut cx.frame_budget,
+                )
+            });
 
         for window in windows.iter_mut() {
             let mut zoom_target = None;
@@ -2702,9 +5464,9 @@ impl eframe::App for ProfApp {
             let mut items_selected = BTreeMap::new();
             std::mem::swap(&mut items_selected, &mut window.config.items_selected);
             items_selected.retain(|_, item| {
-                // Populate the item meta if it's not already there
+                // Populate the item meta if it's already loaded; otherwise
+                // we wait for the batched request
                 if item.meta.is_none() {
-                    window.inflate_meta(&item.loc.entry_id, cx);
                     if let Some(meta) = window.find_item_meta(&item.loc.entry_id, item.loc.item_uid)
                     {
                         item.meta = Some(meta.clone());
@@ -2722,8 +5484,12 @@ impl eframe::App for ProfApp {
                     .open(&mut enabled)
                     .resizable(true)
                     .show(ctx, |ui| {
-                        let target =
-                            Self::display_item_details(ui, item, &window.config.field_schema, cx);
+                        let target = ProfApp::display_item_details(
+                            ui,
+                            item,
+                            &window.config.field_schema,
+                            cx,
+                        );
                         if target.is_some() {
                             zoom_target = target;
                         }
@@ -2732,6 +5498,37 @@ impl eframe::App for ProfApp {
             });
             std::mem::swap(&mut items_selected, &mut window.config.items_selected);
 
+            window.config.pinned_tooltips.retain(|item_uid, pinned| {
+                let short_title: String = pinned.meta.title.chars().take(50).collect();
+
+                let mut enabled = true;
+                egui::Window::new(short_title)
+                    .id(egui::Id::new(("pinned_tooltip", window.index, item_uid.0)))
+                    .open(&mut enabled)
+                    .default_pos(pinned.pos)
+                    .collapsible(false)
+                    .resizable(false)
+                    .show(ctx, |ui| {
+                        Slot::item_tooltip(ui, &pinned.meta, &window.config.field_schema, cx);
+                    });
+                enabled
+            });
+
+            if !window.config.selection.is_empty() {
+                let mut enabled = true;
+                egui::Window::new(format!("Profile {}: Selection", window.index))
+                    .id(egui::Id::new(("selection", window.index)))
+                    .open(&mut enabled)
+                    .resizable(true)
+                    .show(ctx, |ui| window.selection_details(ui));
+                if !enabled {
+                    window.config.selection.clear();
+                }
+            }
+
+            #[cfg(not(target_arch = "wasm32"))]
+            window.export_dialog(ctx, cx);
+
             if let Some((item_loc, interval)) = zoom_target {
                 let interval = match cx.item_link_mode {
                     // In Zoom mode, put the item in the center of the view
@@ -2745,20 +5542,72 @@ impl eframe::App for ProfApp {
                 };
                 ProfApp::zoom(cx, interval);
                 window.expand_slot(&item_loc.entry_id);
-                window.config.scroll_to_item(item_loc);
+                window.config.scroll_to_item(item_loc, interval);
+            }
+
+            if !window.config.items_meta_requests.is_empty() {
+                let requests = std::mem::take(&mut window.config.items_meta_requests);
+                window.config.data_source.fetch_items_meta(&requests);
+            }
+
+            let tile_requests = std::mem::take(&mut window.config.tile_requests);
+            for ((kind, priority), requests) in tile_requests {
+                debug!(
+                    "profile {}: requesting {} {kind:?} tiles ({priority:?})",
+                    window.index,
+                    requests.len()
+                );
+                for req in &requests {
+                    trace!("profile {}: requesting {kind:?} {req}", window.index);
+                }
+                window
+                    .config
+                    .data_source
+                    .fetch_tiles(kind, &requests, priority);
             }
         }
 
-        Self::keyboard(ctx, cx, windows);
+        ProfApp::keyboard(ctx, cx, windows);
+
+        #[cfg(not(target_arch = "wasm32"))]
+        if let Some(mut source) = open.dialog(ctx) {
+            source.fetch_info();
+            pending_data_sources.push_back(source);
+        }
+
+        load_errors.retain(|(locator, error)| {
+            let mut open = true;
+            egui::Window::new("Unable to Load Profile")
+                .id(egui::Id::new(("load_error", locator.as_str())))
+                .open(&mut open)
+                .collapsible(false)
+                .show(ctx, |ui| {
+                    ui.label(locator.as_str());
+                    ui.label(RichText::new(error.as_str()).color(Color32::RED));
+                });
+            open
+        });
 
         // Keep repainting as long as we have outstanding requests.
         if !pending_data_sources.is_empty()
-            || windows
-                .iter()
-                .any(|w| w.config.data_source.outstanding_requests() > 0)
+            || chooser.as_ref().is_some_and(ProfileChooser::is_loading)
+            || windows.iter().any(|w| {
+                w.config.data_source.outstanding_requests() > 0
+                    || w.config
+                        .baseline
+                        .as_ref()
+                        .is_some_and(|b| b.data_source.outstanding_requests() > 0)
+            })
         {
             ctx.request_repaint_after(Duration::from_millis(50));
         }
+
+        // Dynamic sources need to be refreshed even if nothing else happens
+        for window in windows.iter() {
+            if let Some(refresh_interval) = window.config.refresh_period() {
+                ctx.request_repaint_after(refresh_interval);
+            }
+        }
     }
 }
 
@@ -2829,6 +5678,61 @@ impl UiExtra for egui::Ui {
     }
 }
 
+// Open a data source from a user-provided locator (URL, or on native, a path
+// to an archive directory, bundle, recording or trace from another tool)
+fn open_data_source(locator: &str) -> Result<Box<dyn DeferredDataSource>, String> {
+    #[cfg(all(feature = "client", not(target_arch = "wasm32")))]
+    {
+        let locator: Locator = locator.parse()?;
+        locator.open(&ClientConfig::default())
+    }
+
+    #[cfg(all(feature = "client", target_arch = "wasm32"))]
+    {
+        let url = url::Url::parse(locator).map_err(|e| e.to_string())?;
+        Ok(Box::new(HTTPClientDataSource::new(url)))
+    }
+
+    #[cfg(not(feature = "client"))]
+    Err(format!("unable to open {}", locator))
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn save_file(filename: &str, contents: &str) -> Result<String, String> {
+    std::fs::write(filename, contents).map_err(|e| e.to_string())?;
+    let path = std::fs::canonicalize(filename).map_err(|e| e.to_string())?;
+    Ok(path.display().to_string())
+}
+
+#[cfg(target_arch = "wasm32")]
+fn save_file(filename: &str, contents: &str) -> Result<String, String> {
+    use wasm_bindgen::JsCast;
+
+    let err = |e: wasm_bindgen::JsValue| format!("{:?}", e);
+
+    // Trigger a download by clicking on a temporary link to a blob
+    let parts = js_sys::Array::of1(&wasm_bindgen::JsValue::from_str(contents));
+    let mut options = web_sys::BlobPropertyBag::new();
+    options.type_("text/csv");
+    let blob = web_sys::Blob::new_with_str_sequence_and_options(&parts, &options).map_err(err)?;
+    let url = web_sys::Url::create_object_url_with_blob(&blob).map_err(err)?;
+
+    let document = web_sys::window()
+        .and_then(|w| w.document())
+        .ok_or("no document")?;
+    let link = document
+        .create_element("a")
+        .map_err(err)?
+        .dyn_into::<web_sys::HtmlAnchorElement>()
+        .map_err(|_| "unable to create link")?;
+    link.set_href(&url);
+    link.set_download(filename);
+    link.click();
+    web_sys::Url::revoke_object_url(&url).map_err(err)?;
+
+    Ok(filename.to_string())
+}
+
 #[cfg(not(target_arch = "wasm32"))]
 fn get_locator(data_sources: &[Box<dyn DeferredDataSource>]) -> String {
     let all_locators = data_sources
@@ -2845,8 +5749,55 @@ fn get_locator(data_sources: &[Box<dyn DeferredDataSource>]) -> String {
     }
 }
 
-#[cfg(not(target_arch = "wasm32"))]
+/// Preferences from the user's configuration file, which take precedence over
+/// the state saved from the last run, and where to start viewing
+#[derive(Debug, Clone, Default)]
+pub struct AppSettings {
+    pub dark_mode: Option<bool>,
+    // Budget of each profile's tile cache, in bytes (256 MiB if not given)
+    pub tile_cache_bytes: Option<usize>,
+    pub keys: KeyBindings,
+    pub start: StartView,
+    // Refresh every profile this often, and keep the view on its newest
+    // data while the end is in view (e.g., to watch a running job)
+    pub follow: Option<Duration>,
+}
+
+/// Where to land once the profiles load (e.g., from a link in a bug report),
+/// instead of the whole profile
+#[derive(Debug, Clone, Default)]
+pub struct StartView {
+    pub interval: Option<Interval>,
+    // Short names from the outermost panel down, e.g., "n0/cpu/cpu0". The
+    // entry is expanded and scrolled to in every profile that has it.
+    pub entry: Option<String>,
+}
+
 pub fn start(data_sources: Vec<Box<dyn DeferredDataSource>>) {
+    launch(data_sources, None, AppSettings::default());
+}
+
+// Starts with a list of the profiles in the index, from which the user
+// picks the one to view
+pub fn start_with_index(index: Box<dyn DeferredProfileIndex>) {
+    launch(Vec::new(), Some(index), AppSettings::default());
+}
+
+// Either of the above (with an index, if given), with the user's preferences
+pub fn start_with_settings(
+    data_sources: Vec<Box<dyn DeferredDataSource>>,
+    index: Option<Box<dyn DeferredProfileIndex>>,
+    settings: AppSettings,
+) {
+    launch(data_sources, index, settings);
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn launch(
+    data_sources: Vec<Box<dyn DeferredDataSource>>,
+    index: Option<Box<dyn DeferredProfileIndex>>,
+    settings: AppSettings,
+) {
     env_logger::try_init().unwrap_or(()); // Log to stderr (if you run with `RUST_LOG=debug`).
 
     // IMPORTANT: This will be used as the directory name for the storage
@@ -2856,7 +5807,11 @@ pub fn start(data_sources: Vec<Box<dyn DeferredDataSource>>) {
     let app_name = "Legion Prof";
 
     // This is what will be displayed as the window's actual title.
-    let locator = format!("{} - {}", get_locator(&data_sources), app_name);
+    let locator = match &index {
+        Some(index) => index.fetch_description().source_locator.join(", "),
+        None => get_locator(&data_sources),
+    };
+    let locator = format!("{} - {}", locator, app_name);
 
     let native_options = eframe::NativeOptions {
         viewport: egui::ViewportBuilder::default().with_title(locator),
@@ -2865,13 +5820,17 @@ pub fn start(data_sources: Vec<Box<dyn DeferredDataSource>>) {
     eframe::run_native(
         app_name,
         native_options,
-        Box::new(|cc| Ok(Box::new(ProfApp::new(cc, data_sources)))),
+        Box::new(|cc| Ok(Box::new(ProfApp::new(cc, data_sources, index, settings)))),
     )
     .expect("failed to start eframe");
 }
 
 #[cfg(target_arch = "wasm32")]
-pub fn start(data_sources: Vec<Box<dyn DeferredDataSource>>) {
+fn launch(
+    data_sources: Vec<Box<dyn DeferredDataSource>>,
+    index: Option<Box<dyn DeferredProfileIndex>>,
+    settings: AppSettings,
+) {
     // Redirect `log` message to `console.log` and friends:
     eframe::WebLogger::init(log::LevelFilter::Debug).ok();
 
@@ -2882,7 +5841,7 @@ pub fn start(data_sources: Vec<Box<dyn DeferredDataSource>>) {
             .start(
                 "the_canvas_id",
                 web_options,
-                Box::new(|cc| Ok(Box::new(ProfApp::new(cc, data_sources)))),
+                Box::new(|cc| Ok(Box::new(ProfApp::new(cc, data_sources, index, settings)))),
             )
             .await;
 
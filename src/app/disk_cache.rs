@@ -0,0 +1,145 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use log::info;
+use serde::{Deserialize, Serialize};
+
+use crate::data::{DataSourceDescription, DataSourceInfo, PROTOCOL_VERSION};
+use crate::deferred_data::CacheSnapshot;
+
+// Bump whenever the layout of SavedCache (or anything inside it) changes, so
+// that files written by older builds are ignored instead of misread
+const FORMAT_VERSION: u32 = 1;
+
+// Tiles fetched from one source, saved on exit so that reopening the same
+// profile doesn't fetch them all again
+#[derive(Deserialize, Serialize)]
+struct SavedCache {
+    format_version: u32,
+    protocol_version: u32,
+    source_locator: Vec<String>,
+    // Fingerprint of the info the tiles were fetched under. If the profile
+    // changed since (e.g., it was regenerated), the tiles are thrown away.
+    info_hash: u64,
+    tiles: CacheSnapshot,
+}
+
+// Stable across builds and platforms, unlike std's DefaultHasher
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+pub fn info_hash(info: &DataSourceInfo) -> u64 {
+    let mut bytes = Vec::new();
+    ciborium::into_writer(info, &mut bytes).expect("failed to serialize info");
+    fnv1a(&bytes)
+}
+
+// The platform's cache directory (e.g., ~/.cache on Linux)
+pub fn default_dir() -> Option<PathBuf> {
+    directories::ProjectDirs::from("", "", "Legion Prof").map(|dirs| dirs.cache_dir().join("tiles"))
+}
+
+fn path(dir: &Path, description: &DataSourceDescription) -> PathBuf {
+    let locator = description.source_locator.join("\n");
+    dir.join(format!("{:016x}.cbor.zst", fnv1a(locator.as_bytes())))
+}
+
+pub fn save(
+    dir: &Path,
+    description: &DataSourceDescription,
+    info_hash: u64,
+    tiles: CacheSnapshot,
+) -> io::Result<()> {
+    let saved = SavedCache {
+        format_version: FORMAT_VERSION,
+        protocol_version: PROTOCOL_VERSION,
+        source_locator: description.source_locator.clone(),
+        info_hash,
+        tiles,
+    };
+    let mut bytes = Vec::new();
+    ciborium::into_writer(&saved, &mut bytes).map_err(io::Error::other)?;
+    let bytes = zstd::encode_all(&bytes[..], 3)?;
+
+    // Write to the side and rename, so that a crash part way through can't
+    // leave a truncated file behind
+    fs::create_dir_all(dir)?;
+    let path = path(dir, description);
+    let temp = path.with_extension("tmp");
+    fs::write(&temp, bytes)?;
+    fs::rename(temp, path)
+}
+
+// Returns the saved tiles, if there are any that are still valid for the
+// given info. Files that are out of date are removed.
+pub fn load(
+    dir: &Path,
+    description: &DataSourceDescription,
+    info_hash: u64,
+) -> Option<CacheSnapshot> {
+    let path = path(dir, description);
+    let bytes = fs::read(&path).ok()?;
+    let saved = zstd::decode_all(&bytes[..])
+        .ok()
+        .and_then(|bytes| ciborium::from_reader::<SavedCache, _>(&bytes[..]).ok());
+    match saved {
+        Some(saved)
+            if saved.format_version == FORMAT_VERSION
+                && saved.protocol_version == PROTOCOL_VERSION
+                && saved.source_locator == description.source_locator
+                && saved.info_hash == info_hash =>
+        {
+            info!(
+                "loaded {} cached tiles from {}",
+                saved.tiles.len(),
+                path.display()
+            );
+            Some(saved.tiles)
+        }
+        _ => {
+            info!("removing out of date tile cache {}", path.display());
+            let _ = fs::remove_file(path);
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::data::{DataSource, EntryID, TileID};
+    use crate::deferred_data::{
+        CachingDeferredDataSource, DeferredDataSource, DeferredDataSourceWrapper, RequestPriority,
+    };
+    use crate::random_data::{RandomConfig, RandomDataSource};
+
+    #[test]
+    fn test_save_load() {
+        let source = RandomDataSource::new(RandomConfig::default());
+        let description = source.fetch_description();
+        let info = source.fetch_info();
+        let entry_id = EntryID::root().child(0).child(0).summary();
+        let tile_id = TileID(info.interval);
+
+        let mut cache =
+            CachingDeferredDataSource::new(DeferredDataSourceWrapper::new(source), usize::MAX);
+        cache.fetch_summary_tile(&entry_id, tile_id, false, RequestPriority::Visible);
+        assert_eq!(cache.get_summary_tiles().len(), 1);
+
+        let dir =
+            std::env::temp_dir().join(format!("prof-viewer-disk-cache-{}", std::process::id()));
+        let hash = info_hash(&info);
+        save(&dir, &description, hash, cache.snapshot()).unwrap();
+        assert_eq!(load(&dir, &description, hash).unwrap().len(), 1);
+
+        // A different info invalidates the file
+        assert!(load(&dir, &description, hash + 1).is_none());
+        assert!(load(&dir, &description, hash).is_none());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
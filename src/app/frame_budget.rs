@@ -0,0 +1,144 @@
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+// Deepest level of reduced detail
+const MAX_LEVEL: u8 = 2;
+
+// Frames to wait after changing levels before judging the new one, so that a
+// single slow frame (e.g., a burst of tiles arriving) doesn't cause a change
+const SETTLE_FRAMES: u32 = 10;
+
+// Frames that have to be comfortably within budget before detail comes back,
+// which is longer than it takes to give it up so that the app doesn't flicker
+// between levels
+const RECOVER_FRAMES: u32 = 60;
+
+// Weight of each new frame in the running average
+const SMOOTHING: f64 = 0.2;
+
+// Watches how long frames take, and gives up rendering detail when they take
+// longer than the budget, until they are fast again
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct FrameBudget {
+    pub enabled: bool,
+    pub budget: Duration,
+    #[serde(skip)]
+    level: u8,
+    #[serde(skip)]
+    average_ms: f64,
+    #[serde(skip)]
+    frames_at_level: u32,
+}
+
+impl Default for FrameBudget {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            // 30 frames per second
+            budget: Duration::from_millis(33),
+            level: 0,
+            average_ms: 0.0,
+            frames_at_level: 0,
+        }
+    }
+}
+
+impl FrameBudget {
+    pub fn record(&mut self, frame_time: Duration) {
+        let ms = frame_time.as_secs_f64() * 1e3;
+        self.average_ms = SMOOTHING * ms + (1.0 - SMOOTHING) * self.average_ms;
+        self.frames_at_level += 1;
+
+        let budget_ms = self.budget.as_secs_f64() * 1e3;
+        if !self.enabled {
+            self.level = 0;
+        } else if self.average_ms > budget_ms {
+            if self.level < MAX_LEVEL && self.frames_at_level >= SETTLE_FRAMES {
+                self.level += 1;
+                self.frames_at_level = 0;
+            }
+        } else if self.average_ms > budget_ms * 0.5 {
+            // Close enough to the budget that adding detail back would
+            // likely push it over again
+            self.frames_at_level = self.frames_at_level.min(SETTLE_FRAMES);
+        } else if self.level > 0 && self.frames_at_level >= RECOVER_FRAMES {
+            self.level -= 1;
+            self.frames_at_level = 0;
+        }
+    }
+
+    // Zero at full detail
+    pub fn level(&self) -> u8 {
+        self.level
+    }
+
+    pub fn average(&self) -> Duration {
+        Duration::from_secs_f64(self.average_ms / 1e3)
+    }
+
+    // Labels drawn over items at the crosshair, and the metadata fetched for
+    // them
+    pub fn show_labels(&self) -> bool {
+        self.level == 0
+    }
+
+    // Items closer together than this many pixels are drawn as one
+    pub fn merge_px(&self) -> f32 {
+        (1 << self.level) as f32
+    }
+
+    // Metadata fetched in the background (e.g., to search) waits until
+    // frames are fast again
+    pub fn defer_meta(&self) -> bool {
+        self.level >= MAX_LEVEL
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(budget: &mut FrameBudget, ms: u64, frames: u32) {
+        for _ in 0..frames {
+            budget.record(Duration::from_millis(ms));
+        }
+    }
+
+    #[test]
+    fn degrade_and_recover() {
+        let mut budget = FrameBudget::default();
+        run(&mut budget, 10, 100);
+        assert_eq!(budget.level(), 0);
+
+        // A single slow frame isn't enough
+        run(&mut budget, 100, 1);
+        assert_eq!(budget.level(), 0);
+
+        // Slow frames give up detail one level at a time
+        run(&mut budget, 100, 10);
+        assert_eq!(budget.level(), 1);
+        assert!(!budget.show_labels());
+        assert_eq!(budget.merge_px(), 2.0);
+        run(&mut budget, 100, 100);
+        assert_eq!(budget.level(), MAX_LEVEL);
+        assert!(budget.defer_meta());
+
+        // Frames near the budget hold the level
+        run(&mut budget, 25, 200);
+        assert_eq!(budget.level(), MAX_LEVEL);
+
+        // Fast frames bring it back, slowly
+        run(&mut budget, 5, RECOVER_FRAMES + 10);
+        assert_eq!(budget.level(), 1);
+        run(&mut budget, 5, RECOVER_FRAMES);
+        assert_eq!(budget.level(), 0);
+        assert!(budget.show_labels());
+
+        // Disabled, detail is never reduced
+        budget.enabled = false;
+        run(&mut budget, 100, 100);
+        assert_eq!(budget.level(), 0);
+    }
+}
@@ -0,0 +1,320 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+
+use serde::Deserialize;
+
+// Everything the keyboard can do, in the order the help window lists them
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Action {
+    PanLeft,
+    PanRight,
+    FinePanLeft,
+    FinePanRight,
+    ScrollUp,
+    ScrollDown,
+    FineScrollUp,
+    FineScrollDown,
+    ZoomIn,
+    ZoomOut,
+    UndoZoom,
+    RedoZoom,
+    ResetZoom,
+    ExpandVertical,
+    ShrinkVertical,
+    ResetVertical,
+    ToggleCrosshair,
+    ToggleControls,
+    ResetUi,
+}
+
+impl Action {
+    pub const ALL: &[Action] = &[
+        Action::PanLeft,
+        Action::PanRight,
+        Action::FinePanLeft,
+        Action::FinePanRight,
+        Action::ScrollUp,
+        Action::ScrollDown,
+        Action::FineScrollUp,
+        Action::FineScrollDown,
+        Action::ZoomIn,
+        Action::ZoomOut,
+        Action::UndoZoom,
+        Action::RedoZoom,
+        Action::ResetZoom,
+        Action::ExpandVertical,
+        Action::ShrinkVertical,
+        Action::ResetVertical,
+        Action::ToggleCrosshair,
+        Action::ToggleControls,
+        Action::ResetUi,
+    ];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Action::PanLeft => "Pan Left 5%",
+            Action::PanRight => "Pan Right 5%",
+            Action::FinePanLeft => "Pan Left 1%",
+            Action::FinePanRight => "Pan Right 1%",
+            Action::ScrollUp => "Scroll Up",
+            Action::ScrollDown => "Scroll Down",
+            Action::FineScrollUp => "Fine Scroll Up",
+            Action::FineScrollDown => "Fine Scroll Down",
+            Action::ZoomIn => "Zoom In",
+            Action::ZoomOut => "Zoom Out",
+            Action::UndoZoom => "Undo Pan/Zoom",
+            Action::RedoZoom => "Redo Pan/Zoom",
+            Action::ResetZoom => "Reset Pan/Zoom",
+            Action::ExpandVertical => "Expand Vertical Spacing",
+            Action::ShrinkVertical => "Shrink Vertical Spacing",
+            Action::ResetVertical => "Reset Vertical Spacing",
+            Action::ToggleCrosshair => "Toggle Crosshair",
+            Action::ToggleControls => "Toggle This Window",
+            Action::ResetUi => "Clear Selection",
+        }
+    }
+}
+
+// A key and the modifiers held with it, written like "ctrl+alt+plus"
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Shortcut {
+    pub ctrl: bool,
+    pub alt: bool,
+    pub shift: bool,
+    pub key: egui::Key,
+}
+
+impl Shortcut {
+    const fn new(key: egui::Key) -> Self {
+        Self {
+            ctrl: false,
+            alt: false,
+            shift: false,
+            key,
+        }
+    }
+
+    const fn ctrl(self) -> Self {
+        Self { ctrl: true, ..self }
+    }
+
+    const fn alt(self) -> Self {
+        Self { alt: true, ..self }
+    }
+
+    const fn shift(self) -> Self {
+        Self {
+            shift: true,
+            ..self
+        }
+    }
+
+    fn modifiers(&self) -> usize {
+        [self.ctrl, self.alt, self.shift]
+            .iter()
+            .filter(|held| **held)
+            .count()
+    }
+
+    // Ctrl has to match exactly, but Alt and Shift may be held when not
+    // asked for, so that, e.g., Ctrl + Plus works where Plus needs Shift.
+    // The most specific shortcut wins when several match.
+    fn matches(&self, input: &egui::InputState) -> bool {
+        let held = input.modifiers;
+        input.key_pressed(self.key)
+            && held.ctrl == self.ctrl
+            && (held.alt || !self.alt)
+            && (held.shift || !self.shift)
+    }
+}
+
+impl fmt::Display for Shortcut {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.ctrl {
+            write!(f, "Ctrl + ")?;
+        }
+        if self.alt {
+            write!(f, "Alt + ")?;
+        }
+        if self.shift {
+            write!(f, "Shift + ")?;
+        }
+        write!(f, "{}", self.key.symbol_or_name())
+    }
+}
+
+impl FromStr for Shortcut {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        let mut parts: Vec<_> = s.split('+').map(str::trim).collect();
+        // A trailing "+" is the key itself, as in "ctrl++" or "Ctrl + +"
+        if parts.len() >= 2 && parts[parts.len() - 2..] == ["", ""] {
+            parts.truncate(parts.len() - 2);
+            parts.push("+");
+        }
+        let (key, modifiers) = parts.split_last().ok_or("empty shortcut")?;
+        // Names in any case (e.g., "left" or "arrowleft" for ArrowLeft), or
+        // symbols
+        let key = egui::Key::ALL
+            .iter()
+            .copied()
+            .find(|k| {
+                k.name().eq_ignore_ascii_case(key) || format!("{k:?}").eq_ignore_ascii_case(key)
+            })
+            .or_else(|| egui::Key::from_name(key))
+            .ok_or_else(|| format!("unknown key {key:?} in shortcut {s:?}"))?;
+        let mut shortcut = Shortcut::new(key);
+        for modifier in modifiers {
+            match modifier.to_lowercase().as_str() {
+                "ctrl" | "control" => shortcut.ctrl = true,
+                "alt" | "option" => shortcut.alt = true,
+                "shift" => shortcut.shift = true,
+                _ => return Err(format!("unknown modifier {modifier:?} in shortcut {s:?}")),
+            }
+        }
+        Ok(shortcut)
+    }
+}
+
+impl TryFrom<String> for Shortcut {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, String> {
+        s.parse()
+    }
+}
+
+// The shortcuts for each action. Actions that are rebound lose their default
+// shortcuts, and an empty list unbinds the action entirely.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyBindings {
+    bindings: BTreeMap<Action, Vec<Shortcut>>,
+}
+
+impl Default for KeyBindings {
+    fn default() -> Self {
+        use egui::Key;
+
+        let plus = [Shortcut::new(Key::Plus), Shortcut::new(Key::Equals)];
+        let bindings = [
+            (Action::PanLeft, vec![Shortcut::new(Key::ArrowLeft)]),
+            (Action::PanRight, vec![Shortcut::new(Key::ArrowRight)]),
+            (
+                Action::FinePanLeft,
+                vec![Shortcut::new(Key::ArrowLeft).shift()],
+            ),
+            (
+                Action::FinePanRight,
+                vec![Shortcut::new(Key::ArrowRight).shift()],
+            ),
+            (Action::ScrollUp, vec![Shortcut::new(Key::ArrowUp)]),
+            (Action::ScrollDown, vec![Shortcut::new(Key::ArrowDown)]),
+            (
+                Action::FineScrollUp,
+                vec![Shortcut::new(Key::ArrowUp).shift()],
+            ),
+            (
+                Action::FineScrollDown,
+                vec![Shortcut::new(Key::ArrowDown).shift()],
+            ),
+            (Action::ZoomIn, plus.map(Shortcut::ctrl).to_vec()),
+            (Action::ZoomOut, vec![Shortcut::new(Key::Minus).ctrl()]),
+            (Action::UndoZoom, vec![Shortcut::new(Key::ArrowLeft).ctrl()]),
+            (
+                Action::RedoZoom,
+                vec![Shortcut::new(Key::ArrowRight).ctrl()],
+            ),
+            (Action::ResetZoom, vec![Shortcut::new(Key::Num0).ctrl()]),
+            (
+                Action::ExpandVertical,
+                plus.map(|shortcut| shortcut.ctrl().alt()).to_vec(),
+            ),
+            (
+                Action::ShrinkVertical,
+                vec![Shortcut::new(Key::Minus).ctrl().alt()],
+            ),
+            (
+                Action::ResetVertical,
+                vec![Shortcut::new(Key::Num0).ctrl().alt()],
+            ),
+            (Action::ToggleCrosshair, vec![Shortcut::new(Key::C)]),
+            (Action::ToggleControls, vec![Shortcut::new(Key::H)]),
+            (Action::ResetUi, vec![Shortcut::new(Key::Escape)]),
+        ];
+        Self {
+            bindings: bindings.into_iter().collect(),
+        }
+    }
+}
+
+impl KeyBindings {
+    pub fn bind(&mut self, action: Action, shortcuts: Vec<Shortcut>) {
+        self.bindings.insert(action, shortcuts);
+    }
+
+    pub fn shortcuts(&self, action: Action) -> &[Shortcut] {
+        self.bindings.get(&action).map_or(&[], Vec::as_slice)
+    }
+
+    // The action of the most specific shortcut pressed this frame, if any
+    pub fn pressed(&self, input: &egui::InputState) -> Option<Action> {
+        self.bindings
+            .iter()
+            .flat_map(|(action, shortcuts)| shortcuts.iter().map(move |s| (*action, s)))
+            .filter(|(_, shortcut)| shortcut.matches(input))
+            .max_by_key(|(_, shortcut)| shortcut.modifiers())
+            .map(|(action, _)| action)
+    }
+
+    // For the help window, e.g., "Ctrl + + or Ctrl + ="
+    pub fn describe(&self, action: Action) -> String {
+        let shortcuts = self.shortcuts(action);
+        if shortcuts.is_empty() {
+            return "Unbound".to_owned();
+        }
+        shortcuts
+            .iter()
+            .map(Shortcut::to_string)
+            .collect::<Vec<_>>()
+            .join(" or ")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use egui::Key;
+
+    #[test]
+    fn test_parse_shortcut() {
+        assert_eq!(
+            "ctrl+alt+plus".parse(),
+            Ok(Shortcut::new(Key::Plus).ctrl().alt())
+        );
+        assert_eq!("Ctrl + +".parse(), Ok(Shortcut::new(Key::Plus).ctrl()));
+        assert_eq!("ctrl++".parse(), Ok(Shortcut::new(Key::Plus).ctrl()));
+        assert_eq!("x".parse(), Ok(Shortcut::new(Key::X)));
+        assert_eq!(
+            "shift+arrowleft".parse(),
+            Ok(Shortcut::new(Key::ArrowLeft).shift())
+        );
+        assert_eq!("escape".parse(), Ok(Shortcut::new(Key::Escape)));
+        assert!("hyper+x".parse::<Shortcut>().is_err());
+        assert!("ctrl+nokey".parse::<Shortcut>().is_err());
+    }
+
+    #[test]
+    fn test_bindings() {
+        let mut keys = KeyBindings::default();
+        assert_eq!(keys.shortcuts(Action::ZoomIn).len(), 2);
+        keys.bind(Action::ToggleCrosshair, vec![Shortcut::new(Key::X)]);
+        assert_eq!(keys.describe(Action::ToggleCrosshair), "X");
+        keys.bind(Action::ToggleControls, Vec::new());
+        assert_eq!(keys.describe(Action::ToggleControls), "Unbound");
+    }
+}
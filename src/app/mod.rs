@@ -1,4 +1,9 @@
 mod core;
+#[cfg(not(target_arch = "wasm32"))]
+mod disk_cache;
+mod frame_budget;
+mod keys;
 mod tile_manager;
 
-pub use core::start;
+pub use core::{AppSettings, StartView, start, start_with_index, start_with_settings};
+pub use keys::{Action, KeyBindings, Shortcut};
@@ -1,13 +1,96 @@
 use std::collections::BTreeMap;
 
-use crate::data::{TileID, TileSet};
-use crate::timestamp::Interval;
+use log::{debug, warn};
+use lru::LruCache;
+use serde::{Deserialize, Serialize};
+
+use crate::data::{EntryID, TileID, TileSet};
+use crate::deferred_data::{TileKind, TileRequest};
+use crate::timestamp::{Interval, Timestamp};
+
+// What to do with a dynamic profile's tiles when the view pans partly outside
+// of them
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub enum CacheExtension {
+    // Add tiles of the same size at the edges, keeping the ones already
+    // fetched
+    Extend,
+    // Fetch the new view as a single tile
+    Replace,
+}
+
+// Trade-off between refetching and over-fetching for dynamic profiles. A
+// server on the LAN can afford to refetch on every zoom, while a remote one
+// is better off reusing what it already sent.
+#[derive(Debug, Copy, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct TileManagerConfig {
+    // Tiles are kept until the view is this many times larger or smaller
+    // than they are
+    pub zoom_ratio: f64,
+    pub extension: CacheExtension,
+    // Snap tiles to a power-of-two grid starting at the beginning of the
+    // profile, so that returning to a zoom level asks for the same tiles
+    // again (and hits the cache) instead of ones that are slightly off
+    pub aligned: bool,
+    // Also fetch the tiles just outside the view, so that panning (and the
+    // first zoom step, in static profiles) doesn't have to wait
+    pub prefetch: bool,
+    // Bytes of tiles the window's entries may hold on to before the least
+    // recently drawn ones are dropped
+    pub memory_budget: usize,
+    // Check that the tiles chosen for each request cover it without gaps or
+    // overlaps, to catch sources whose tile sets are off by one
+    pub verify: bool,
+}
+
+impl Default for TileManagerConfig {
+    fn default() -> Self {
+        Self {
+            zoom_ratio: 2.0,
+            extension: CacheExtension::Extend,
+            aligned: true,
+            prefetch: true,
+            memory_budget: 256 << 20,
+            verify: false,
+        }
+    }
+}
+
+// How often request_tiles could answer with the tiles it chose last time
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct TileManagerStats {
+    // Requests for the same interval as the one before
+    pub hits: u64,
+    // Requests where tiles had to be chosen again
+    pub misses: u64,
+}
 
 pub struct TileManager {
     tile_set: TileSet,
     interval: Interval,
+    config: TileManagerConfig,
     last_request_interval: (Option<Interval>, Option<Interval>), // full: false, true
     tile_cache: (Vec<TileID>, Vec<TileID>),                      // full: false, true
+    last_prefetch_interval: (Option<Interval>, Option<Interval>), // full: false, true
+    prefetch_cache: (Vec<TileID>, Vec<TileID>),                  // full: false, true
+    grid_size: (Option<i64>, Option<i64>),                       // full: false, true
+    level: (Option<usize>, Option<usize>),                       // full: false, true
+    stats: TileManagerStats,
+    // Size of each tile held by the app, and the frame it was last used in
+    held_tiles: LruCache<(TileKind, TileRequest), (usize, u64)>,
+    held_bytes: usize,
+    frame: u64,
+    // Bumped each time tiles are marked stale. Tiles remember the last epoch
+    // they were marked in, so that entries can tell whether the copy they
+    // hold predates it.
+    epoch: u64,
+    tile_epochs: BTreeMap<TileID, u64>,
+    // Entries with a tile set of their own choose their tiles separately,
+    // but share the memory budget and stale marks above
+    entries: BTreeMap<EntryID, TileManager>,
+    // Problems found when verifying tiles, since they were last taken
+    coverage_errors: Vec<String>,
 }
 
 fn select<T>(cond: bool, true_value: T, false_value: T) -> T {
@@ -25,6 +108,88 @@ where
     cache.clone()
 }
 
+// Tiles of the power-of-two grid anchored at the start of the profile that
+// cover the request. The grid size is kept while it stays within the zoom
+// ratio of the request, and the tiles already chosen are kept while panning if
+// the config says to extend them.
+fn aligned_tiles(
+    interval: Interval,
+    config: &TileManagerConfig,
+    grid_size: &mut Option<i64>,
+    tile_cache: &[TileID],
+    request_interval: Interval,
+) -> Vec<TileID> {
+    let request_duration = request_interval.duration_ns();
+    let ratio = |size: i64| {
+        let (a, b) = (size.max(request_duration), size.min(request_duration));
+        a as f64 / b as f64
+    };
+    let size = match *grid_size {
+        Some(size) if ratio(size) <= config.zoom_ratio.max(1.0) => size,
+        _ => (request_duration as u64).next_power_of_two() as i64,
+    };
+    let extend = config.extension == CacheExtension::Extend && *grid_size == Some(size);
+    *grid_size = Some(size);
+
+    let origin = interval.start.0;
+    let index = |t: Timestamp| (t.0 - origin).div_euclid(size);
+    let mut first = index(request_interval.start);
+    let mut last = index(Timestamp(request_interval.stop.0 - 1));
+    if let (true, Some(cache_first), Some(cache_last)) =
+        (extend, tile_cache.first(), tile_cache.last())
+    {
+        let (cache_first, cache_last) = (index(cache_first.0.start), index(cache_last.0.start));
+        if cache_first <= last && first <= cache_last {
+            first = first.min(cache_first);
+            last = last.max(cache_last);
+        }
+    }
+    (first..=last)
+        .map(|i| {
+            let start = Timestamp(origin + i * size);
+            TileID(Interval::new(start, Timestamp(start.0 + size)).intersection(interval))
+        })
+        .collect()
+}
+
+// Describes the first gap or overlap in the tiles covering the interval, if
+// there is one
+fn check_coverage(tiles: &[TileID], interval: Interval) -> Option<String> {
+    if interval.duration_ns() <= 0 {
+        return None;
+    }
+    let mut tiles = tiles.to_vec();
+    tiles.sort();
+    let (Some(first), Some(last)) = (tiles.first(), tiles.last()) else {
+        return Some(format!("no tiles cover {}", interval));
+    };
+    if first.0.start > interval.start {
+        return Some(format!(
+            "gap from {} to {} before the first tile",
+            interval.start, first.0.start
+        ));
+    }
+    for pair in tiles.windows(2) {
+        let (prev, next) = (pair[0].0, pair[1].0);
+        if next.start > prev.stop {
+            return Some(format!(
+                "gap from {} to {} between tiles",
+                prev.stop, next.start
+            ));
+        }
+        if next.start < prev.stop {
+            return Some(format!("tiles {} and {} overlap", prev, next));
+        }
+    }
+    if last.0.stop < interval.stop {
+        return Some(format!(
+            "gap from {} to {} after the last tile",
+            last.0.stop, interval.stop
+        ));
+    }
+    None
+}
+
 fn reuse_cache<T: Clone, K>(cache: &[T], last_key: &mut Option<K>, key: K) -> Vec<T> {
     *last_key = Some(key);
     cache.to_owned()
@@ -32,15 +197,121 @@ fn reuse_cache<T: Clone, K>(cache: &[T], last_key: &mut Option<K>, key: K) -> Ve
 
 impl TileManager {
     pub fn new(tile_set: TileSet, interval: Interval) -> Self {
+        Self::with_config(tile_set, interval, TileManagerConfig::default())
+    }
+
+    pub fn with_config(tile_set: TileSet, interval: Interval, config: TileManagerConfig) -> Self {
         Self {
             tile_set,
             interval,
+            config,
             last_request_interval: (None, None),
             tile_cache: (Vec::new(), Vec::new()),
+            last_prefetch_interval: (None, None),
+            prefetch_cache: (Vec::new(), Vec::new()),
+            grid_size: (None, None),
+            level: (None, None),
+            stats: TileManagerStats::default(),
+            held_tiles: LruCache::unbounded(),
+            held_bytes: 0,
+            frame: 0,
+            epoch: 0,
+            tile_epochs: BTreeMap::new(),
+            entries: BTreeMap::new(),
+            coverage_errors: Vec::new(),
         }
     }
 
+    pub fn set_entry_tile_sets(&mut self, tile_sets: &BTreeMap<EntryID, TileSet>) {
+        self.entries = tile_sets
+            .iter()
+            .map(|(entry_id, tile_set)| {
+                let manager =
+                    TileManager::with_config(tile_set.clone(), self.interval, self.config);
+                (entry_id.clone(), manager)
+            })
+            .collect();
+    }
+
+    // Tiles already chosen are kept, but the next request is checked against
+    // the new config
+    pub fn set_config(&mut self, config: TileManagerConfig) {
+        if config != self.config {
+            self.config = config;
+            self.last_request_interval = (None, None);
+            self.last_prefetch_interval = (None, None);
+            self.grid_size = (None, None);
+        }
+        for entry in self.entries.values_mut() {
+            entry.set_config(config);
+        }
+    }
+
+    pub fn is_dynamic(&self) -> bool {
+        self.tile_set.tiles.is_empty()
+    }
+
+    // For profiles that grow while they are open. Tiles already chosen keep
+    // their IDs where they can (i.e., if they are aligned to a grid).
+    pub fn set_interval(&mut self, interval: Interval) {
+        if interval != self.interval {
+            self.interval = interval;
+            self.last_request_interval = (None, None);
+            self.last_prefetch_interval = (None, None);
+        }
+        for entry in self.entries.values_mut() {
+            entry.set_interval(interval);
+        }
+    }
+
+    // Marks the current tiles overlapping the interval as stale, so that
+    // entries fetch them again
+    pub fn mark_stale(&mut self, interval: Interval) {
+        self.epoch += 1;
+        let current: Vec<_> = std::iter::once(&*self)
+            .chain(self.entries.values())
+            .flat_map(|manager| manager.tile_cache.0.iter().chain(&manager.tile_cache.1))
+            .copied()
+            .collect();
+        self.tile_epochs
+            .retain(|tile_id, _| current.contains(tile_id));
+        for tile_id in current {
+            if tile_id.0.overlaps(interval) {
+                self.tile_epochs.insert(tile_id, self.epoch);
+            }
+        }
+    }
+
+    // The last epoch the tile was marked stale in, if any
+    pub fn tile_epoch(&self, tile_id: TileID) -> u64 {
+        self.tile_epochs.get(&tile_id).copied().unwrap_or(0)
+    }
+
     pub fn request_tiles(&mut self, view_interval: Interval, full: bool) -> Vec<TileID> {
+        let misses = self.stats.misses;
+        let tile_ids = self.choose_tiles(view_interval, full);
+        // Tiles are only checked when they were chosen again
+        if self.config.verify && self.stats.misses > misses {
+            let request_interval = view_interval.intersection(self.interval);
+            if let Some(error) = check_coverage(&tile_ids, request_interval) {
+                let error = format!("tiles for {}: {}", request_interval, error);
+                warn!("{}", error);
+                self.coverage_errors.push(error);
+            }
+        }
+        tile_ids
+    }
+
+    // Problems found in the tiles chosen since the last call, when verifying
+    pub fn take_coverage_errors(&mut self) -> Vec<String> {
+        let mut errors = std::mem::take(&mut self.coverage_errors);
+        for entry in self.entries.values_mut() {
+            errors.append(&mut entry.coverage_errors);
+        }
+        errors
+    }
+
+    fn choose_tiles(&mut self, view_interval: Interval, full: bool) -> Vec<TileID> {
         let last_request_interval = select(
             full,
             &mut self.last_request_interval.1,
@@ -50,8 +321,10 @@ impl TileManager {
 
         let request_interval = view_interval.intersection(self.interval);
         if *last_request_interval == Some(request_interval) {
+            self.stats.hits += 1;
             return tile_cache.clone();
         }
+        self.stats.misses += 1;
 
         let request_duration = request_interval.duration_ns();
         if request_duration <= 0 {
@@ -76,6 +349,26 @@ impl TileManager {
         };
 
         // Dynamic profile.
+        if self.tile_set.tiles.is_empty() && self.config.aligned {
+            // Start on the grid the other kind of request is using, so that
+            // full tiles line up with partial ones and can stand in for them
+            let (grid_size, other) = if full {
+                (&mut self.grid_size.1, self.grid_size.0)
+            } else {
+                (&mut self.grid_size.0, self.grid_size.1)
+            };
+            if grid_size.is_none() {
+                *grid_size = other;
+            }
+            let tiles = aligned_tiles(
+                self.interval,
+                &self.config,
+                grid_size,
+                tile_cache,
+                request_interval,
+            );
+            return fill_cache(tile_cache, tiles, last_request_interval, request_interval);
+        }
         if self.tile_set.tiles.is_empty() {
             if let Some(cache_interval) = tile_cache
                 .iter()
@@ -87,11 +380,13 @@ impl TileManager {
                 //  1. There is at least partial overlap with the new request.
                 //  2. We haven't drifted too far from the tile size requested before.
 
-                if ratio(tile_cache) <= 2.0 {
+                if ratio(tile_cache) <= self.config.zoom_ratio.max(1.0) {
                     if cache_interval.0.contains_interval(request_interval) {
                         // Interval completely contained in the existing cache, just return it.
                         return reuse_cache(tile_cache, last_request_interval, request_interval);
-                    } else if cache_interval.0.overlaps(request_interval) {
+                    } else if self.config.extension == CacheExtension::Extend
+                        && cache_interval.0.overlaps(request_interval)
+                    {
                         // Partial overlap, extend the cache to cover. Keep tile
                         // size the same for consistency.
                         let new_before = request_interval.subtract_after(cache_interval.0.start);
@@ -145,18 +440,57 @@ impl TileManager {
         }
 
         // We're in a static profile. Choose an appropriate level to load.
-        let chosen_level = if full {
+        let level = if full {
             // Full request must always fetch highest level of detail.
-            self.tile_set.tiles.last().unwrap()
+            self.tile_set.tiles.len() - 1
         } else {
             // Otherwise estimate the best zoom level, where "best" minimizes the
             // ratio of the tile size to request size.
             self.tile_set
                 .tiles
                 .iter()
-                .min_by(|level1, level2| ratio(level1).partial_cmp(&ratio(level2)).unwrap())
+                .enumerate()
+                .min_by(|(_, level1), (_, level2)| {
+                    ratio(level1).partial_cmp(&ratio(level2)).unwrap()
+                })
+                .map(|(level, _)| level)
                 .unwrap()
         };
+        let last_level = select(full, &mut self.level.1, &mut self.level.0);
+        let same_level = *last_level == Some(level);
+        *last_level = Some(level);
+        let chosen_level = &self.tile_set.tiles[level];
+
+        // Panning within a level, keep the tiles chosen so far (like dynamic
+        // profiles do) and only add the ones that came into view.
+        if let (true, CacheExtension::Extend, Some(cache_interval)) = (
+            same_level,
+            self.config.extension,
+            tile_cache
+                .iter()
+                .copied()
+                .reduce(|a, b| TileID(a.0.union(b.0))),
+        ) {
+            if cache_interval.0.contains_interval(request_interval) {
+                return reuse_cache(tile_cache, last_request_interval, request_interval);
+            } else if cache_interval.0.overlaps(request_interval) {
+                let (before, after): (Vec<_>, Vec<_>) = chosen_level
+                    .iter()
+                    .filter(|tile| {
+                        request_interval.overlaps(tile.0) && !cache_interval.0.overlaps(tile.0)
+                    })
+                    .partition(|tile| tile.0.stop <= cache_interval.0.start);
+                let mut new_tiles = before;
+                new_tiles.extend(tile_cache.iter());
+                new_tiles.extend(after);
+                return fill_cache(
+                    tile_cache,
+                    new_tiles,
+                    last_request_interval,
+                    request_interval,
+                );
+            }
+        }
 
         // Now filter to just tiles overlapping the requested interval.
         fill_cache(
@@ -170,22 +504,287 @@ impl TileManager {
         )
     }
 
+    // Tiles for the entry, from its own tile set if it has one
+    pub fn request_entry_tiles(
+        &mut self,
+        entry_id: &EntryID,
+        view_interval: Interval,
+        full: bool,
+    ) -> Vec<TileID> {
+        match self.entries.get_mut(entry_id) {
+            Some(entry) => entry.request_tiles(view_interval, full),
+            None => self.request_tiles(view_interval, full),
+        }
+    }
+
+    pub fn request_entry_tiles_with_prefetch(
+        &mut self,
+        entry_id: &EntryID,
+        view_interval: Interval,
+        full: bool,
+    ) -> (Vec<TileID>, Vec<TileID>) {
+        match self.entries.get_mut(entry_id) {
+            Some(entry) => entry.request_tiles_with_prefetch(view_interval, full),
+            None => self.request_tiles_with_prefetch(view_interval, full),
+        }
+    }
+
+    // Like request_tiles, but also returns the tiles worth fetching ahead of
+    // time (at low priority): the neighbors on either side of the view, and
+    // for static profiles, the levels one step in and out. None of them are
+    // in the first list.
+    pub fn request_tiles_with_prefetch(
+        &mut self,
+        view_interval: Interval,
+        full: bool,
+    ) -> (Vec<TileID>, Vec<TileID>) {
+        let tile_ids = self.request_tiles(view_interval, full);
+        if !self.config.prefetch {
+            return (tile_ids, Vec::new());
+        }
+
+        let request_interval = view_interval.intersection(self.interval);
+        let last_prefetch_interval = select(
+            full,
+            &mut self.last_prefetch_interval.1,
+            &mut self.last_prefetch_interval.0,
+        );
+        let prefetch_cache = select(full, &mut self.prefetch_cache.1, &mut self.prefetch_cache.0);
+        if *last_prefetch_interval == Some(request_interval) {
+            return (tile_ids, prefetch_cache.clone());
+        }
+
+        let mut prefetch = Vec::new();
+        let mut add = |tile_id: TileID| {
+            if tile_id.0.duration_ns() > 0
+                && !tile_ids.contains(&tile_id)
+                && !prefetch.contains(&tile_id)
+            {
+                prefetch.push(tile_id);
+            }
+        };
+
+        if let (Some(first), Some(last)) = (tile_ids.first(), tile_ids.last()) {
+            if self.tile_set.tiles.is_empty() {
+                // Dynamic profile. Zooming picks tiles to match the new
+                // view, so there is nothing to guess there, but these are
+                // exactly the tiles that panning would add.
+                if self.config.extension == CacheExtension::Extend {
+                    // A grid tile is only ever truncated at the end of the
+                    // profile, so step by the grid size when there is one
+                    let grid_size = select(full, self.grid_size.1, self.grid_size.0);
+                    let tile_size = grid_size.unwrap_or(first.0.duration_ns());
+                    let before = Timestamp(first.0.start.0 - tile_size);
+                    let after = Timestamp(last.0.start.0 + tile_size);
+                    add(TileID(
+                        Interval::new(before, first.0.start).intersection(self.interval),
+                    ));
+                    add(TileID(
+                        Interval::new(after, Timestamp(after.0 + tile_size))
+                            .intersection(self.interval),
+                    ));
+                }
+            } else {
+                // Static profile. Find the level the view was served from.
+                let levels = &self.tile_set.tiles;
+                let index = if full {
+                    levels.len() - 1
+                } else {
+                    levels
+                        .iter()
+                        .position(|level| level.contains(first))
+                        .unwrap()
+                };
+
+                // Up to a view's width on either side at the same level
+                let duration = request_interval.duration_ns();
+                let before = Interval::new(request_interval.start, request_interval.start)
+                    .grow(duration)
+                    .subtract_after(request_interval.start);
+                let after = Interval::new(request_interval.stop, request_interval.stop)
+                    .grow(duration)
+                    .subtract_before(request_interval.stop);
+                for tile in &levels[index] {
+                    if before.overlaps(tile.0) || after.overlaps(tile.0) {
+                        add(*tile);
+                    }
+                }
+
+                // Full requests always come from the last level
+                if !full {
+                    let neighbors = levels[..index]
+                        .last()
+                        .into_iter()
+                        .chain(levels.get(index + 1));
+                    for level in neighbors {
+                        for tile in level {
+                            if request_interval.overlaps(tile.0) {
+                                add(*tile);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        let prefetch = fill_cache(
+            prefetch_cache,
+            prefetch,
+            last_prefetch_interval,
+            request_interval,
+        );
+        (tile_ids, prefetch)
+    }
+
+    // Index of the level the last request chose, in a static profile. Levels
+    // go from coarsest to finest.
+    pub fn level(&self, full: bool) -> Option<usize> {
+        select(full, self.level.1, self.level.0)
+    }
+
+    // Number of levels, or zero in a dynamic profile
+    pub fn levels(&self) -> usize {
+        self.tile_set.tiles.len()
+    }
+
+    // Duration of the tiles the last request chose. Tiles at the edges of the
+    // profile may be shorter.
+    pub fn tile_size(&self, full: bool) -> Option<i64> {
+        let grid_size = select(full, self.grid_size.1, self.grid_size.0);
+        if self.is_dynamic() && self.config.aligned && grid_size.is_some() {
+            return grid_size;
+        }
+        let tile_cache = select(full, &self.tile_cache.1, &self.tile_cache.0);
+        tile_cache.iter().take(2).map(|t| t.0.duration_ns()).max()
+    }
+
+    // The interval the last request was for, clipped to the profile
+    pub fn last_request_interval(&self, full: bool) -> Option<Interval> {
+        select(
+            full,
+            self.last_request_interval.1,
+            self.last_request_interval.0,
+        )
+    }
+
+    pub fn stats(&self) -> TileManagerStats {
+        self.stats
+    }
+
+    // Records a tile the app now holds, so that it counts against the
+    // memory budget
+    pub fn insert_tile(&mut self, kind: TileKind, req: TileRequest, size: usize) {
+        if let Some((old_size, _)) = self.held_tiles.put((kind, req), (size, self.frame)) {
+            self.held_bytes -= old_size;
+        }
+        self.held_bytes += size;
+    }
+
+    // Marks a held tile as used in this frame
+    pub fn touch_tile(&mut self, kind: TileKind, req: TileRequest) {
+        if let Some((_, frame)) = self.held_tiles.get_mut(&(kind, req)) {
+            *frame = self.frame;
+        }
+    }
+
+    pub fn remove_tile(&mut self, kind: TileKind, req: TileRequest) {
+        if let Some((size, _)) = self.held_tiles.pop(&(kind, req)) {
+            self.held_bytes -= size;
+        }
+    }
+
+    // Ends the frame, returning the least recently used tiles that have to
+    // be dropped to get back under budget. Tiles used in this frame are never
+    // evicted, so the budget may be exceeded while they are all on screen.
+    pub fn evict_tiles(&mut self) -> Vec<(TileKind, TileRequest)> {
+        let mut evicted = Vec::new();
+        while self.held_bytes > self.config.memory_budget {
+            match self.held_tiles.peek_lru() {
+                Some((_, (_, frame))) if *frame < self.frame => {}
+                _ => break,
+            }
+            let (key, (size, _)) = self.held_tiles.pop_lru().unwrap();
+            self.held_bytes -= size;
+            evicted.push(key);
+        }
+        if !evicted.is_empty() {
+            debug!(
+                "evicted {} tiles to get under budget, {} bytes still held",
+                evicted.len(),
+                self.held_bytes
+            );
+        }
+        self.frame += 1;
+        evicted
+    }
+
+    pub fn held_tiles(&self) -> usize {
+        self.held_tiles.len()
+    }
+
+    pub fn held_bytes(&self) -> usize {
+        self.held_bytes
+    }
+
     pub fn invalidate_cache<T>(tile_ids: &[TileID], cache: &mut BTreeMap<TileID, T>) {
         cache.retain(|tile_id, _| tile_ids.contains(tile_id));
     }
+
+    // Keeps loaded tiles that were just dropped from the cache in `previous`
+    // for as long as a tile replacing them (i.e., one that overlaps them) is
+    // still loading, so that they can be drawn in its place. Where several
+    // generations overlap, the newest wins.
+    pub fn refine_cache<T>(
+        tile_ids: &[TileID],
+        cache: &BTreeMap<TileID, Option<T>>,
+        dropped: Vec<(TileID, T)>,
+        previous: &mut BTreeMap<TileID, T>,
+    ) {
+        let pending: Vec<_> = tile_ids
+            .iter()
+            .filter(|tile_id| !matches!(cache.get(tile_id), Some(Some(_))))
+            .collect();
+        for (tile_id, tile) in dropped {
+            if pending.iter().any(|p| p.0.overlaps(tile_id.0)) {
+                previous.retain(|old, _| !old.0.overlaps(tile_id.0));
+                previous.insert(tile_id, tile);
+            }
+        }
+        previous.retain(|old, _| pending.iter().any(|p| p.0.overlaps(old.0)));
+    }
+
+    // The tiles in `previous` that stand in for a tile that is still loading
+    pub fn previous_tiles<T>(
+        tile_id: TileID,
+        previous: &BTreeMap<TileID, T>,
+    ) -> impl Iterator<Item = (&TileID, &T)> {
+        previous
+            .iter()
+            .filter(move |(old, _)| old.0.overlaps(tile_id.0))
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
-    use crate::timestamp::Timestamp;
+    use crate::data::EntryID;
+
+    // The tiling these tests were written against, before tiles were
+    // aligned to a grid
+    fn unaligned(tile_set: TileSet, interval: Interval) -> TileManager {
+        let config = TileManagerConfig {
+            aligned: false,
+            ..TileManagerConfig::default()
+        };
+        TileManager::with_config(tile_set, interval, config)
+    }
 
     #[test]
     fn request_dynamic_empty() {
         let int = Interval::new(Timestamp(0), Timestamp(10));
         let req = Interval::new(Timestamp(5), Timestamp(5));
-        let mut tm = TileManager::new(TileSet::default(), int);
+        let mut tm = unaligned(TileSet::default(), int);
         assert!(tm.request_tiles(req, false).is_empty());
         assert!(tm.request_tiles(req, true).is_empty());
     }
@@ -212,7 +811,7 @@ mod tests {
     fn request_dynamic_repeat() {
         let int = Interval::new(Timestamp(0), Timestamp(10));
         let req = Interval::new(Timestamp(0), Timestamp(10));
-        let mut tm = TileManager::new(TileSet::default(), int);
+        let mut tm = unaligned(TileSet::default(), int);
         // Answer should be stable on repeat queries.
         assert_eq!(tm.request_tiles(req, false), vec![TileID(req)]);
         assert_eq!(tm.request_tiles(req, false), vec![TileID(req)]);
@@ -248,6 +847,120 @@ mod tests {
         assert_eq!(&tm.request_tiles(req, false), &part);
         assert_eq!(&tm.request_tiles(req, true), &full);
         assert_eq!(&tm.request_tiles(req, true), &full);
+        assert_eq!(tm.stats(), TileManagerStats { hits: 6, misses: 2 });
+        assert_eq!(tm.last_request_interval(false), Some(req));
+    }
+
+    #[test]
+    fn request_static_pan() {
+        let int = Interval::new(Timestamp(0), Timestamp(100));
+        let half = |i: i64| TileID(Interval::new(Timestamp(i * 50), Timestamp((i + 1) * 50)));
+        let quarter = |i: i64| TileID(Interval::new(Timestamp(i * 25), Timestamp((i + 1) * 25)));
+        let req = |start, stop| Interval::new(Timestamp(start), Timestamp(stop));
+        let ts = TileSet {
+            tiles: vec![
+                vec![TileID(int)],
+                (0..2).map(half).collect(),
+                (0..4).map(quarter).collect(),
+            ],
+        };
+        let mut tm = TileManager::new(ts, int);
+        assert_eq!(
+            tm.request_tiles(req(10, 35), false),
+            vec![quarter(0), quarter(1)]
+        );
+        // Panning adds the tiles that came into view, and keeps the rest.
+        assert_eq!(
+            tm.request_tiles(req(30, 55), false),
+            vec![quarter(0), quarter(1), quarter(2)]
+        );
+        assert_eq!(
+            tm.request_tiles(req(20, 45), false),
+            vec![quarter(0), quarter(1), quarter(2)]
+        );
+        // Zooming out to another level starts over.
+        assert_eq!(tm.request_tiles(req(30, 80), false), vec![half(0), half(1)]);
+        // Unless told to replace the tiles when panning.
+        tm.set_config(TileManagerConfig {
+            extension: CacheExtension::Replace,
+            ..Default::default()
+        });
+        assert_eq!(
+            tm.request_tiles(req(10, 35), false),
+            vec![quarter(0), quarter(1)]
+        );
+        assert_eq!(
+            tm.request_tiles(req(30, 55), false),
+            vec![quarter(1), quarter(2)]
+        );
+    }
+
+    #[test]
+    fn request_entry_tile_set() {
+        let int = Interval::new(Timestamp(0), Timestamp(100));
+        let half = |i: i64| TileID(Interval::new(Timestamp(i * 50), Timestamp((i + 1) * 50)));
+        let quarter = |i: i64| TileID(Interval::new(Timestamp(i * 25), Timestamp((i + 1) * 25)));
+        let req = Interval::new(Timestamp(10), Timestamp(35));
+        let coarse = TileSet {
+            tiles: vec![vec![TileID(int)], (0..2).map(half).collect()],
+        };
+        let fine = TileSet {
+            tiles: vec![vec![TileID(int)], (0..4).map(quarter).collect()],
+        };
+        let dense = EntryID::root().child(0).child(0).child(0);
+        let sparse = EntryID::root().child(0).child(0).child(1);
+        let mut tm = TileManager::new(coarse, int);
+        tm.set_entry_tile_sets(&BTreeMap::from([(dense.clone(), fine)]));
+        assert_eq!(
+            tm.request_entry_tiles(&dense, req, false),
+            vec![quarter(0), quarter(1)]
+        );
+        assert_eq!(tm.request_entry_tiles(&sparse, req, false), vec![half(0)]);
+
+        // Stale marks cover every entry's tiles
+        tm.mark_stale(Interval::new(Timestamp(30), Timestamp(31)));
+        assert_eq!(tm.tile_epoch(quarter(1)), 1);
+        assert_eq!(tm.tile_epoch(half(0)), 1);
+        assert_eq!(tm.tile_epoch(quarter(0)), 0);
+    }
+
+    #[test]
+    fn verify_coverage() {
+        let int = Interval::new(Timestamp(0), Timestamp(100));
+        let tile = |start, stop| TileID(Interval::new(Timestamp(start), Timestamp(stop)));
+        let req = |start, stop| Interval::new(Timestamp(start), Timestamp(stop));
+        let config = TileManagerConfig {
+            verify: true,
+            ..TileManagerConfig::default()
+        };
+
+        // Off by one between the first two tiles
+        let ts = TileSet {
+            tiles: vec![vec![tile(0, 49), tile(50, 100)]],
+        };
+        let mut tm = TileManager::with_config(ts, int, config);
+        tm.request_tiles(req(0, 100), false);
+        let errors = tm.take_coverage_errors();
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].contains("gap"));
+        assert!(tm.take_coverage_errors().is_empty());
+
+        // Only checked when tiles are chosen again
+        tm.request_tiles(req(0, 100), false);
+        assert!(tm.take_coverage_errors().is_empty());
+
+        let ts = TileSet {
+            tiles: vec![vec![tile(0, 51), tile(50, 100)]],
+        };
+        let mut tm = TileManager::with_config(ts, int, config);
+        tm.request_tiles(req(0, 100), false);
+        assert!(tm.take_coverage_errors()[0].contains("overlap"));
+
+        // Dynamic tiles always cover the request
+        let mut tm = TileManager::with_config(TileSet::default(), int, config);
+        tm.request_tiles(req(10, 30), false);
+        tm.request_tiles(req(20, 90), false);
+        assert!(tm.take_coverage_errors().is_empty());
     }
 
     #[test]
@@ -262,7 +975,7 @@ mod tests {
         let req30 = Interval::new(Timestamp(0), Timestamp(30));
         let req20 = Interval::new(Timestamp(0), Timestamp(20));
         let req10 = Interval::new(Timestamp(0), Timestamp(10));
-        let mut tm = TileManager::new(TileSet::default(), int);
+        let mut tm = unaligned(TileSet::default(), int);
         // Zoom level sticks until we reach the threshold.
         assert_eq!(tm.request_tiles(req90, false), vec![TileID(req90)]);
         assert_eq!(tm.request_tiles(req80, false), vec![TileID(req90)]);
@@ -312,7 +1025,7 @@ mod tests {
             TileID(Interval::new(Timestamp(0), Timestamp(70))),
             TileID(Interval::new(Timestamp(70), Timestamp(100))),
         ];
-        let mut tm = TileManager::new(TileSet::default(), int);
+        let mut tm = unaligned(TileSet::default(), int);
         // Zoom level sticks until we reach the threshold.
         assert_eq!(tm.request_tiles(req10, false), ts10);
         assert_eq!(tm.request_tiles(req20, false), ts10x2);
@@ -364,7 +1077,7 @@ mod tests {
             TileID(Interval::new(Timestamp(0), Timestamp(30))),
             TileID(Interval::new(Timestamp(30), Timestamp(100))),
         ];
-        let mut tm = TileManager::new(TileSet::default(), int);
+        let mut tm = unaligned(TileSet::default(), int);
         // Zoom level sticks until we reach the threshold.
         assert_eq!(tm.request_tiles(req10, false), ts10);
         assert_eq!(tm.request_tiles(req20, false), ts10x2);
@@ -419,7 +1132,7 @@ mod tests {
             TileID(Interval::new(Timestamp(15), Timestamp(85))),
             TileID(Interval::new(Timestamp(85), Timestamp(100))),
         ];
-        let mut tm = TileManager::new(TileSet::default(), int);
+        let mut tm = unaligned(TileSet::default(), int);
         // Zoom level sticks until we reach the threshold.
         assert_eq!(tm.request_tiles(req10, false), ts10);
         assert_eq!(tm.request_tiles(req20, false), ts10x3);
@@ -463,7 +1176,7 @@ mod tests {
         ];
         let ts60 = vec![TileID(Interval::new(Timestamp(60), Timestamp(80)))];
         let ts30 = vec![TileID(Interval::new(Timestamp(30), Timestamp(50)))];
-        let mut tm = TileManager::new(TileSet::default(), int);
+        let mut tm = unaligned(TileSet::default(), int);
         // Zoom level sticks while panning, as long as there is some overlap.
         assert_eq!(tm.request_tiles(req00, false), ts20);
         assert_eq!(tm.request_tiles(req10, false), ts20x2);
@@ -472,4 +1185,218 @@ mod tests {
         assert_eq!(tm.request_tiles(req60, false), ts60);
         assert_eq!(tm.request_tiles(req30, false), ts30);
     }
+
+    #[test]
+    fn request_dynamic_config() {
+        let int = Interval::new(Timestamp(0), Timestamp(100));
+        let req00 = Interval::new(Timestamp(0), Timestamp(20));
+        let req10 = Interval::new(Timestamp(10), Timestamp(30));
+        let req05 = Interval::new(Timestamp(10), Timestamp(15));
+        let config = TileManagerConfig {
+            zoom_ratio: 4.0,
+            extension: CacheExtension::Replace,
+            aligned: false,
+            prefetch: false,
+            ..TileManagerConfig::default()
+        };
+        let mut tm = TileManager::with_config(TileSet::default(), int, config);
+        // Panning fetches the new view instead of extending.
+        assert_eq!(tm.request_tiles(req00, false), vec![TileID(req00)]);
+        assert_eq!(tm.request_tiles(req10, false), vec![TileID(req10)]);
+        // Zooming in by up to 4x keeps the tile.
+        assert_eq!(tm.request_tiles(req05, false), vec![TileID(req10)]);
+
+        // A stricter threshold applies to the very next request.
+        tm.set_config(TileManagerConfig {
+            zoom_ratio: 1.5,
+            ..config
+        });
+        assert_eq!(tm.request_tiles(req05, false), vec![TileID(req05)]);
+    }
+
+    #[test]
+    fn request_static_prefetch() {
+        let int = Interval::new(Timestamp(0), Timestamp(100));
+        let half = |i: i64| TileID(Interval::new(Timestamp(i * 50), Timestamp((i + 1) * 50)));
+        let quarter = |i: i64| TileID(Interval::new(Timestamp(i * 25), Timestamp((i + 1) * 25)));
+        let ts = TileSet {
+            tiles: vec![
+                vec![TileID(int)],
+                (0..2).map(half).collect(),
+                (0..4).map(quarter).collect(),
+            ],
+        };
+        let mut tm = TileManager::new(ts, int);
+        // The neighbors on the same level, plus the levels above and below.
+        let req = Interval::new(Timestamp(30), Timestamp(70));
+        let (tiles, prefetch) = tm.request_tiles_with_prefetch(req, false);
+        assert_eq!(tiles, vec![half(0), half(1)]);
+        assert_eq!(prefetch, vec![TileID(int), quarter(1), quarter(2)]);
+        assert_eq!((tm.level(false), tm.levels()), (Some(1), 3));
+        assert_eq!(tm.tile_size(false), Some(50));
+
+        // Full requests stay on the last level.
+        let req = Interval::new(Timestamp(30), Timestamp(45));
+        let (tiles, prefetch) = tm.request_tiles_with_prefetch(req, true);
+        assert_eq!(tiles, vec![quarter(1)]);
+        assert_eq!(prefetch, vec![quarter(0), quarter(2)]);
+        assert_eq!(tm.level(true), Some(2));
+        assert_eq!(tm.tile_size(true), Some(25));
+    }
+
+    #[test]
+    fn request_dynamic_prefetch() {
+        let int = Interval::new(Timestamp(0), Timestamp(100));
+        let req00 = Interval::new(Timestamp(0), Timestamp(20));
+        let req30 = Interval::new(Timestamp(30), Timestamp(50));
+        let tile = |start, stop| TileID(Interval::new(Timestamp(start), Timestamp(stop)));
+        let mut tm = unaligned(TileSet::default(), int);
+        // Nothing to the left of the profile.
+        let (tiles, prefetch) = tm.request_tiles_with_prefetch(req00, false);
+        assert_eq!(tiles, vec![tile(0, 20)]);
+        assert_eq!(prefetch, vec![tile(20, 40)]);
+
+        // The prefetched tiles are the ones panning would add.
+        let req10 = Interval::new(Timestamp(10), Timestamp(30));
+        let (tiles, prefetch) = tm.request_tiles_with_prefetch(req10, false);
+        assert_eq!(tiles, vec![tile(0, 20), tile(20, 40)]);
+        assert_eq!(prefetch, vec![tile(40, 60)]);
+
+        let (tiles, prefetch) = tm.request_tiles_with_prefetch(req30, false);
+        assert_eq!(tiles, vec![tile(0, 20), tile(20, 40), tile(40, 60)]);
+        assert_eq!(prefetch, vec![tile(60, 80)]);
+
+        tm.set_config(TileManagerConfig {
+            aligned: false,
+            prefetch: false,
+            ..TileManagerConfig::default()
+        });
+        assert!(tm.request_tiles_with_prefetch(req30, false).1.is_empty());
+    }
+
+    #[test]
+    fn evict_tiles() {
+        let int = Interval::new(Timestamp(0), Timestamp(100));
+        let config = TileManagerConfig {
+            memory_budget: 100,
+            ..TileManagerConfig::default()
+        };
+        let mut tm = TileManager::with_config(TileSet::default(), int, config);
+        let req = |start, stop| TileRequest {
+            entry_id: EntryID::root().child(0),
+            tile_id: TileID(Interval::new(Timestamp(start), Timestamp(stop))),
+            full: false,
+        };
+
+        tm.insert_tile(TileKind::Slot, req(0, 10), 40);
+        tm.insert_tile(TileKind::Slot, req(10, 20), 40);
+        assert!(tm.evict_tiles().is_empty());
+
+        // Over budget, but everything is still in use in this frame.
+        tm.touch_tile(TileKind::Slot, req(0, 10));
+        tm.touch_tile(TileKind::Slot, req(10, 20));
+        tm.insert_tile(TileKind::Slot, req(20, 30), 40);
+        assert!(tm.evict_tiles().is_empty());
+        assert_eq!(tm.held_bytes(), 120);
+
+        // The least recently used tile goes first.
+        tm.touch_tile(TileKind::Slot, req(0, 10));
+        tm.touch_tile(TileKind::Slot, req(10, 20));
+        assert_eq!(tm.evict_tiles(), vec![(TileKind::Slot, req(20, 30))]);
+        assert_eq!((tm.held_tiles(), tm.held_bytes()), (2, 80));
+
+        tm.remove_tile(TileKind::Slot, req(0, 10));
+        assert_eq!((tm.held_tiles(), tm.held_bytes()), (1, 40));
+    }
+
+    #[test]
+    fn refine_cache() {
+        let tile = |start, stop| TileID(Interval::new(Timestamp(start), Timestamp(stop)));
+        let mut previous = BTreeMap::new();
+
+        // Zooming in replaces one coarse tile with two finer ones.
+        let tile_ids = vec![tile(0, 50), tile(50, 100)];
+        let mut cache = BTreeMap::new();
+        TileManager::refine_cache(&tile_ids, &cache, vec![(tile(0, 100), 'a')], &mut previous);
+        assert_eq!(previous.len(), 1);
+        let stand_ins: Vec<_> = TileManager::previous_tiles(tile(50, 100), &previous).collect();
+        assert_eq!(stand_ins, vec![(&tile(0, 100), &'a')]);
+
+        // The coarse tile stays until both have loaded.
+        cache.insert(tile(0, 50), Some('b'));
+        cache.insert(tile(50, 100), None);
+        TileManager::refine_cache(&tile_ids, &cache, Vec::new(), &mut previous);
+        assert_eq!(previous.len(), 1);
+        cache.insert(tile(50, 100), Some('c'));
+        TileManager::refine_cache(&tile_ids, &cache, Vec::new(), &mut previous);
+        assert!(previous.is_empty());
+
+        // Tiles that don't overlap anything loading are dropped right away.
+        let tile_ids = vec![tile(0, 50)];
+        TileManager::refine_cache(&tile_ids, &cache, vec![(tile(50, 100), 'c')], &mut previous);
+        assert!(previous.is_empty());
+    }
+
+    #[test]
+    fn request_dynamic_aligned() {
+        let int = Interval::new(Timestamp(0), Timestamp(100));
+        let tile = |start, stop| TileID(Interval::new(Timestamp(start), Timestamp(stop)));
+        let req = |start, stop| Interval::new(Timestamp(start), Timestamp(stop));
+        let mut tm = TileManager::new(TileSet::default(), int);
+        // Tiles are the next power of two up from the view.
+        assert_eq!(tm.request_tiles(req(0, 20), false), vec![tile(0, 32)]);
+        assert_eq!(tm.request_tiles(req(10, 30), false), vec![tile(0, 32)]);
+        // Panning extends along the grid.
+        assert_eq!(
+            tm.request_tiles(req(20, 40), false),
+            vec![tile(0, 32), tile(32, 64)]
+        );
+        // Zooming snaps to a finer grid, and the last tile is truncated to
+        // fit the profile.
+        assert_eq!(tm.request_tiles(req(90, 95), false), vec![tile(88, 96)]);
+        assert_eq!(
+            tm.request_tiles(req(94, 100), false),
+            vec![tile(88, 96), tile(96, 100)]
+        );
+        // Coming back to a zoom level gives the same tiles as before.
+        assert_eq!(tm.request_tiles(req(5, 25), false), vec![tile(0, 32)]);
+        assert_eq!(tm.tile_size(false), Some(32));
+        assert_eq!(tm.level(false), None);
+        let (_, prefetch) = tm.request_tiles_with_prefetch(req(5, 25), false);
+        assert_eq!(prefetch, vec![tile(32, 64)]);
+        // Full requests start on the same grid.
+        assert_eq!(
+            tm.request_tiles(req(5, 45), true),
+            vec![tile(0, 32), tile(32, 64)]
+        );
+    }
+
+    #[test]
+    fn mark_stale() {
+        let int = Interval::new(Timestamp(0), Timestamp(100));
+        let tile = |start, stop| TileID(Interval::new(Timestamp(start), Timestamp(stop)));
+        let req = Interval::new(Timestamp(0), Timestamp(60));
+        let mut tm = TileManager::new(TileSet::default(), int);
+        assert_eq!(tm.request_tiles(req, false), vec![tile(0, 64)]);
+        assert_eq!(tm.tile_epoch(tile(0, 64)), 0);
+
+        // Only tiles at the edge are marked.
+        tm.mark_stale(Interval::new(Timestamp(99), Timestamp(100)));
+        assert_eq!(tm.tile_epoch(tile(0, 64)), 0);
+        tm.mark_stale(Interval::new(Timestamp(50), Timestamp(100)));
+        assert_eq!(tm.tile_epoch(tile(0, 64)), 2);
+
+        // Growing the profile keeps grid tiles, but the truncated one at the
+        // end changes.
+        let req = Interval::new(Timestamp(60), Timestamp(100));
+        assert_eq!(
+            tm.request_tiles(req, false),
+            vec![tile(0, 64), tile(64, 100)]
+        );
+        tm.set_interval(Interval::new(Timestamp(0), Timestamp(120)));
+        assert_eq!(
+            tm.request_tiles(req, false),
+            vec![tile(0, 64), tile(64, 120)]
+        );
+    }
 }
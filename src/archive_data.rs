@@ -1,11 +1,17 @@
-use std::fs::{File, create_dir, remove_dir_all};
+use std::fs::{self, File, create_dir, remove_dir_all};
 use std::io;
 use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
 
 use serde::Serialize;
 
-use crate::data::{DataSourceInfo, EntryID, EntryIDSlug, EntryIndex, EntryInfo, TileID, TileSet};
-use crate::deferred_data::{CountingDeferredDataSource, DeferredDataSource};
+use crate::data::{
+    Capabilities, DataSourceInfo, EntryID, EntryIDSlug, EntryIndex, EntryInfo, PROTOCOL_VERSION,
+    TileID, TileSet,
+};
+use crate::deferred_data::{
+    CountingDeferredDataSource, DataSourceInfoResult, DeferredDataSource, RequestPriority,
+};
 use crate::http::schema::TileRequestRef;
 use crate::timestamp::{Interval, Timestamp};
 
@@ -16,6 +22,10 @@ pub struct DataSourceArchiveWriter<T: DeferredDataSource> {
     path: PathBuf,
     force: bool,
     zstd_compression: i32,
+    // A built web viewer (trunk's dist directory) to bundle with the tiles
+    viewer: Option<PathBuf>,
+    // The first file that couldn't be written, from whichever thread
+    write_error: Arc<Mutex<Option<io::Error>>>,
 }
 
 fn create_unique_dir<P: AsRef<Path>>(path: P, force: bool) -> io::Result<PathBuf> {
@@ -45,26 +55,38 @@ fn create_unique_dir<P: AsRef<Path>>(path: P, force: bool) -> io::Result<PathBuf
     Ok(path)
 }
 
-fn write_data<T>(path: PathBuf, data: T, zstd_compression: i32) -> io::Result<()>
+fn write_data<T>(path: &Path, data: T, zstd_compression: i32) -> io::Result<()>
 where
     T: Serialize,
 {
     let mut f = zstd::Encoder::new(File::create(path)?, zstd_compression)?;
-    ciborium::into_writer(&data, &mut f).expect("ciborium encoding failed");
+    ciborium::into_writer(&data, &mut f).map_err(io::Error::other)?;
     f.finish()?;
     Ok(())
 }
 
-fn spawn_write<T>(path: PathBuf, data: T, zstd_compression: i32, scope: &rayon::Scope<'_>)
-where
+fn spawn_write<T>(
+    path: PathBuf,
+    data: T,
+    zstd_compression: i32,
+    write_error: &Arc<Mutex<Option<io::Error>>>,
+    scope: &rayon::Scope<'_>,
+) where
     T: Serialize + Send + Sync + 'static,
 {
+    let write_error = write_error.clone();
     scope.spawn(move |_| {
-        // FIXME (Elliott): is there a better way to handle I/O failure?
-        write_data(path, data, zstd_compression).unwrap();
+        if let Err(e) = write_data(&path, data, zstd_compression) {
+            let e = io::Error::new(e.kind(), format!("unable to write {path:?}: {e}"));
+            write_error.lock().unwrap().get_or_insert(e);
+        }
     });
 }
 
+fn tile_error(kind: &str, e: String) -> io::Error {
+    io::Error::other(format!("unable to fetch {kind} tile: {e}"))
+}
+
 fn walk_entry_list(info: &EntryInfo) -> Vec<EntryID> {
     let mut result = Vec::new();
     fn walk(info: &EntryInfo, entry_id: EntryID, result: &mut Vec<EntryID>) {
@@ -89,6 +111,73 @@ fn walk_entry_list(info: &EntryInfo) -> Vec<EntryID> {
     result
 }
 
+// Opens the archive in the hosted viewer
+const REDIRECT_INDEX: &str = "<html>
+<script>
+window.onload = function() {
+  var prof = location
+  if(location.protocol !== 'https:') {
+    prof = location.replace(`https:${location.href.substring(location.protocol.length)}`);
+  }
+  window.location.replace(\"https://legion.stanford.edu/prof-viewer/?url=\"+prof.href);
+}
+</script>
+</html>
+";
+
+// Opens the archive in the viewer bundled next to it. The viewer resolves
+// the URL against its own page, so this works wherever the directory is served
+const BUNDLE_INDEX: &str = "<!DOCTYPE html>
+<html>
+<head>
+<meta charset=\"utf-8\">
+<meta http-equiv=\"refresh\" content=\"0; url=viewer/index.html?url=../\">
+</head>
+<body>
+<a href=\"viewer/index.html?url=../\">Open the profile</a>
+</body>
+</html>
+";
+
+// Trunk puts its public URL (the base) in front of every asset, which has to
+// be relative for the viewer to load from a subdirectory of the archive
+fn check_viewer(viewer: &Path) -> io::Result<()> {
+    let index = fs::read_to_string(viewer.join("index.html")).map_err(|e| {
+        io::Error::new(
+            e.kind(),
+            format!("unable to read {:?}: {e}", viewer.join("index.html")),
+        )
+    })?;
+    let base = index
+        .split_once("<base href=\"")
+        .and_then(|(_, rest)| rest.split_once('"'))
+        .map(|(base, _)| base)
+        .filter(|base| base.starts_with('/') || base.contains("://"));
+    if let Some(base) = base {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "the viewer in {viewer:?} was built for {base:?}, rebuild it with `trunk build --release --public-url ./`"
+            ),
+        ));
+    }
+    Ok(())
+}
+
+fn copy_dir(from: &Path, to: &Path) -> io::Result<()> {
+    create_dir(to)?;
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+        let target = to.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            copy_dir(&entry.path(), &target)?;
+        } else {
+            fs::copy(entry.path(), target)?;
+        }
+    }
+    Ok(())
+}
+
 impl<T: DeferredDataSource> DataSourceArchiveWriter<T> {
     pub fn new(
         data_source: T,
@@ -107,59 +196,109 @@ impl<T: DeferredDataSource> DataSourceArchiveWriter<T> {
             path: path.as_ref().to_owned(),
             force,
             zstd_compression,
+            viewer: None,
+            write_error: Arc::new(Mutex::new(None)),
         }
     }
 
-    fn check_info(&mut self) -> Option<DataSourceInfo> {
+    // Copies the web viewer built in viewer into the archive, with an
+    // index.html that opens the archive in it, so the directory can be served
+    // as is from anywhere
+    pub fn with_viewer(mut self, viewer: impl AsRef<Path>) -> Self {
+        self.viewer = Some(viewer.as_ref().to_owned());
+        self
+    }
+
+    fn check_info(&mut self) -> Option<DataSourceInfoResult> {
         // We requested this once, so we know we'll get zero or one result
         self.data_source.get_infos().pop()
     }
 
+    fn check_writes(&self) -> io::Result<()> {
+        match self.write_error.lock().unwrap().take() {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
+    }
+
     fn write_info(&mut self, info: DataSourceInfo, scope: &rayon::Scope<'_>) {
         let path = self.path.join("info");
-        spawn_write(path, info, self.zstd_compression, scope);
+        spawn_write(path, info, self.zstd_compression, &self.write_error, scope);
+    }
+
+    fn write_tiles(&mut self, scope: &rayon::Scope<'_>) -> io::Result<()> {
+        self.write_summary_tiles(scope)?;
+        self.write_slot_tiles(scope)?;
+        self.write_slot_meta_tiles(scope)
     }
 
-    fn write_summary_tiles(&mut self, scope: &rayon::Scope<'_>) {
+    fn write_summary_tiles(&mut self, scope: &rayon::Scope<'_>) -> io::Result<()> {
         for (tile, _) in self.data_source.get_summary_tiles() {
-            let tile = tile.expect("writing summary tile failed");
+            let tile = tile.map_err(|e| tile_error("summary", e))?;
             let mut path = self.path.join("summary_tile");
             let req = TileRequestRef {
                 entry_id: &tile.entry_id,
                 tile_id: tile.tile_id,
             };
             path.push(req.to_slug());
-            spawn_write(path, tile, self.zstd_compression, scope);
+            spawn_write(path, tile, self.zstd_compression, &self.write_error, scope);
         }
+        Ok(())
     }
 
-    fn write_slot_tiles(&mut self, scope: &rayon::Scope<'_>) {
+    fn write_slot_tiles(&mut self, scope: &rayon::Scope<'_>) -> io::Result<()> {
         for (tile, _) in self.data_source.get_slot_tiles() {
-            let tile = tile.expect("writing slot tile failed");
+            let tile = tile.map_err(|e| tile_error("slot", e))?;
             let mut path = self.path.join("slot_tile");
             let req = TileRequestRef {
                 entry_id: &tile.entry_id,
                 tile_id: tile.tile_id,
             };
             path.push(req.to_slug());
-            spawn_write(path, tile, self.zstd_compression, scope);
+            spawn_write(path, tile, self.zstd_compression, &self.write_error, scope);
         }
+        Ok(())
     }
 
-    fn write_slot_meta_tiles(&mut self, scope: &rayon::Scope<'_>) {
+    fn write_slot_meta_tiles(&mut self, scope: &rayon::Scope<'_>) -> io::Result<()> {
         for (tile, _) in self.data_source.get_slot_meta_tiles() {
-            let tile = tile.expect("writing slot meta tile failed");
+            let tile = tile.map_err(|e| tile_error("slot meta", e))?;
             let mut path = self.path.join("slot_meta_tile");
             let req = TileRequestRef {
                 entry_id: &tile.entry_id,
                 tile_id: tile.tile_id,
             };
             path.push(req.to_slug());
-            spawn_write(path, tile, self.zstd_compression, scope);
+            spawn_write(path, tile, self.zstd_compression, &self.write_error, scope);
         }
+        Ok(())
+    }
+
+    // Tiles for a dynamic source: the whole profile at the first level, and
+    // each tile split branch_factor ways at the next
+    fn tile_levels(&self, interval: Interval) -> Vec<Vec<TileID>> {
+        let origin = interval.start.0;
+        let duration = interval.duration_ns();
+        (0..self.levels)
+            .map(|level| {
+                let num_tiles = self.branch_factor.pow(level) as i64;
+                (0..num_tiles)
+                    .map(|i| {
+                        let start = Timestamp(origin + duration * i / num_tiles);
+                        let stop = Timestamp(origin + duration * (i + 1) / num_tiles);
+                        TileID(Interval::new(start, stop))
+                    })
+                    .collect()
+            })
+            .collect()
     }
 
     pub fn write(mut self) -> io::Result<()> {
+        // Better to find out about a bad viewer before fetching every tile
+        if let Some(viewer) = &self.viewer {
+            check_viewer(viewer)?;
+        }
+
         self.path = create_unique_dir(&self.path, self.force)?;
         println!("Created output directory {:?}", &self.path);
         create_dir(self.path.join("summary_tile"))?;
@@ -171,7 +310,7 @@ impl<T: DeferredDataSource> DataSourceArchiveWriter<T> {
         while info.is_none() {
             info = self.check_info();
         }
-        let mut info = info.unwrap();
+        let mut info = info.unwrap().map_err(io::Error::other)?;
 
         let entry_ids = walk_entry_list(&info.entry_info);
         for entry_id in &entry_ids {
@@ -187,92 +326,222 @@ impl<T: DeferredDataSource> DataSourceArchiveWriter<T> {
             }
         }
 
-        // For now, this only works on dynamic data sources
-        assert!(info.tile_set.tiles.is_empty());
-
-        let mut tile_set = Vec::new();
-
-        for level in 0..self.levels {
-            let num_tiles = self.branch_factor.pow(level) as i64;
-            let duration = info.interval.duration_ns();
-            let tile_ids: Vec<_> = (0..num_tiles)
-                .map(|i| {
-                    let start = Timestamp(duration * i / num_tiles);
-                    let stop = Timestamp(duration * (i + 1) / num_tiles);
-                    TileID(Interval::new(start, stop))
-                })
-                .collect();
-            tile_set.push(tile_ids);
+        // Static sources already have their tiles, which are copied as they
+        // are. Dynamic ones can make any tile, so they get a fixed number of
+        // levels
+        if info.tile_set.tiles.is_empty() {
+            info.tile_set = TileSet {
+                tiles: self.tile_levels(info.interval),
+            };
+            // Every entry is written with the same tiles
+            info.entry_tile_sets.clear();
         }
-
-        info.tile_set = TileSet {
-            tiles: tile_set.clone(),
+        // The archive is a snapshot, so there is nothing to refresh
+        info.refresh_interval = None;
+        // Whatever the source, the archive is written in this build's format
+        info.version = PROTOCOL_VERSION;
+        let capabilities = info.capabilities;
+        info.capabilities = Capabilities {
+            // Archives are usually served as static files, with no endpoint
+            // to handle batches or item lookups
+            batch_fetch: false,
+            items_meta: false,
+            live_updates: false,
+            ..capabilities
         };
 
         rayon::in_place_scope(|s| {
-            self.write_info(info, s);
+            self.write_info(info.clone(), s);
         });
+        self.check_writes()?;
 
-        for level in 0..self.levels {
-            let tile_ids = &tile_set[level as usize];
-            let full = level == self.levels - 1;
-
-            println!("Writing level {} with {} tiles", level, tile_ids.len());
+        let levels = entry_ids
+            .iter()
+            .map(|entry_id| info.entry_tile_set(entry_id).tiles.len())
+            .max()
+            .unwrap_or(0);
+        for level in 0..levels {
+            let num_tiles = info.tile_set.tiles.get(level).map_or(0, Vec::len);
+            println!("Writing level {} with {} tiles", level, num_tiles);
 
             const MAX_IN_FLIGHT_REQUESTS: u64 = 100;
 
             for entry_id in &entry_ids {
+                // Entries may have their own tiles, with more or fewer levels.
+                // Only the last level has every item
+                let tiles = &info.entry_tile_set(entry_id).tiles;
+                let Some(tile_ids) = tiles.get(level) else {
+                    continue;
+                };
+                let full = level == tiles.len() - 1;
+
                 match entry_id.last_index().unwrap() {
-                    EntryIndex::Summary => {
+                    EntryIndex::Summary if capabilities.summary_tiles => {
                         for tile_id in tile_ids {
-                            self.data_source
-                                .fetch_summary_tile(entry_id, *tile_id, full);
+                            self.data_source.fetch_summary_tile(
+                                entry_id,
+                                *tile_id,
+                                full,
+                                RequestPriority::Background,
+                            );
                         }
                     }
                     EntryIndex::Slot(..) => {
                         for tile_id in tile_ids {
-                            self.data_source.fetch_slot_tile(entry_id, *tile_id, full);
-                            self.data_source
-                                .fetch_slot_meta_tile(entry_id, *tile_id, full);
+                            self.data_source.fetch_slot_tile(
+                                entry_id,
+                                *tile_id,
+                                full,
+                                RequestPriority::Background,
+                            );
+                            if capabilities.slot_meta_tiles {
+                                self.data_source.fetch_slot_meta_tile(
+                                    entry_id,
+                                    *tile_id,
+                                    full,
+                                    RequestPriority::Background,
+                                );
+                            }
                         }
                     }
+                    EntryIndex::Summary => {}
                 }
 
                 // Bound the number of in-flight requests so we don't use too much memory.
                 rayon::in_place_scope(|s| {
                     while self.data_source.outstanding_requests() > MAX_IN_FLIGHT_REQUESTS {
-                        self.write_summary_tiles(s);
-                        self.write_slot_tiles(s);
-                        self.write_slot_meta_tiles(s);
+                        self.write_tiles(s)?;
                     }
-                });
+                    Ok::<_, io::Error>(())
+                })?;
+                self.check_writes()?;
             }
         }
 
         rayon::in_place_scope(|s| {
             while self.data_source.outstanding_requests() > 0 {
-                self.write_summary_tiles(s);
-                self.write_slot_tiles(s);
-                self.write_slot_meta_tiles(s);
+                self.write_tiles(s)?;
             }
-        });
+            Ok::<_, io::Error>(())
+        })?;
+        self.check_writes()?;
 
-        std::fs::write(
-            self.path.join("index.html"),
-            "<html>
-<script>
-window.onload = function() {
-  var prof = location
-  if(location.protocol !== 'https:') {
-    prof = location.replace(`https:${location.href.substring(location.protocol.length)}`);
-  }
-  window.location.replace(\"https://legion.stanford.edu/prof-viewer/?url=\"+prof.href);
-}
-</script>
-</html>
-",
-        )?;
+        match &self.viewer {
+            Some(viewer) => {
+                copy_dir(viewer, &self.path.join("viewer"))?;
+                fs::write(self.path.join("index.html"), BUNDLE_INDEX)?;
+            }
+            None => fs::write(self.path.join("index.html"), REDIRECT_INDEX)?,
+        }
 
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+
+    use super::*;
+
+    use crate::data::{DataSource, ItemMetaRequest, ItemUID};
+    use crate::deferred_data::DeferredDataSourceWrapper;
+    use crate::file_data::FileDataSource;
+    use crate::trace_data::{TraceBuilder, TraceItem};
+
+    #[test]
+    fn test_archive_roundtrip() {
+        let dir = std::env::temp_dir().join(format!("lpv_archive_test_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let mut builder = TraceBuilder::new("test");
+        let thread = builder.thread("node", "cpu", "cpu0");
+        for start in [0, 40, 80] {
+            builder.add_item(
+                thread,
+                TraceItem {
+                    interval: Interval::new(Timestamp(start), Timestamp(start + 10)),
+                    title: format!("item{start}"),
+                    color: None,
+                    fields: Vec::new(),
+                },
+            );
+        }
+        let ds = DeferredDataSourceWrapper::new(builder.build());
+        DataSourceArchiveWriter::new(ds, 3, 2, dir.join("dynamic"), false, 1)
+            .write()
+            .unwrap();
+        let info = FileDataSource::new(dir.join("dynamic")).fetch_info();
+        let counts: Vec<_> = info.tile_set.tiles.iter().map(Vec::len).collect();
+        assert_eq!(counts, [1, 2, 4]);
+
+        // An archive is a static source, so a copy keeps its tiles
+        let ds = DeferredDataSourceWrapper::new(FileDataSource::new(dir.join("dynamic")));
+        DataSourceArchiveWriter::new(ds, 1, 2, dir.join("static"), false, 1)
+            .write()
+            .unwrap();
+        let copy = FileDataSource::new(dir.join("static"));
+        assert_eq!(copy.fetch_info().tile_set, info.tile_set);
+
+        let slot = walk_entry_list(&info.entry_info)
+            .into_iter()
+            .find(|entry_id| matches!(entry_id.last_index(), Some(EntryIndex::Slot(..))))
+            .unwrap();
+        let items: BTreeSet<_> = info.tile_set.tiles[2]
+            .iter()
+            .flat_map(|tile_id| copy.fetch_slot_tile(&slot, *tile_id, true).data.items)
+            .flatten()
+            .map(|item| item.item_uid)
+            .collect();
+        assert_eq!(items.len(), 3);
+
+        // Items are looked up in the tiles, and missing ones are reported
+        let request = |item_uid| ItemMetaRequest {
+            entry_id: slot.clone(),
+            item_uid,
+            interval: info.interval,
+        };
+        let requests: Vec<_> = items.iter().copied().map(request).collect();
+        assert_eq!(copy.try_fetch_items_meta(&requests).unwrap().len(), 3);
+        let missing = ItemUID(items.last().unwrap().0 + 1);
+        let e = copy.try_fetch_items_meta(&[request(missing)]).unwrap_err();
+        assert!(e.starts_with("no item"), "{e}");
+
+        remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_archive_viewer() {
+        let dir = std::env::temp_dir().join(format!("lpv_viewer_test_{}", std::process::id()));
+        let dist = dir.join("dist");
+        std::fs::create_dir_all(dist.join("assets")).unwrap();
+        std::fs::write(dist.join("index.html"), "<base href=\"/\" />").unwrap();
+        std::fs::write(dist.join("assets").join("icon.png"), "png").unwrap();
+
+        let trace = || {
+            let mut builder = TraceBuilder::new("test");
+            builder.thread("node", "cpu", "cpu0");
+            DeferredDataSourceWrapper::new(builder.build())
+        };
+
+        // Built for the root of a domain, so it can't be moved
+        let result = DataSourceArchiveWriter::new(trace(), 1, 2, dir.join("absolute"), false, 1)
+            .with_viewer(&dist)
+            .write();
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::InvalidInput);
+        assert!(!dir.join("absolute").exists());
+
+        std::fs::write(dist.join("index.html"), "<base href=\"./\" />").unwrap();
+        DataSourceArchiveWriter::new(trace(), 1, 2, dir.join("bundle"), false, 1)
+            .with_viewer(&dist)
+            .write()
+            .unwrap();
+        let bundle = dir.join("bundle");
+        assert!(bundle.join("viewer/assets/icon.png").exists());
+        assert!(bundle.join("info").exists());
+        let index = std::fs::read_to_string(bundle.join("index.html")).unwrap();
+        assert!(index.contains("viewer/index.html?url=../"));
+
+        remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,689 @@
+use std::cell::RefCell;
+use std::collections::{BTreeMap, VecDeque};
+use std::future::{Future, poll_fn, ready};
+use std::pin::Pin;
+use std::rc::Rc;
+#[cfg(not(target_arch = "wasm32"))]
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+use std::time::Duration;
+
+use crate::data::{DataSourceDescription, EntryID, ItemMetaRequest, TileID};
+use crate::deferred_data::{
+    CANCELLED, DataSourceInfoResult, DeferredDataSource, ItemsMetaResponse, ItemsMetaResult,
+    RequestPriority, SlotMetaTileResponse, SlotMetaTileResult, SlotTileResponse, SlotTileResult,
+    SummaryTileResponse, SummaryTileResult, TileRequest, TileResult,
+};
+
+pub type BoxFuture<T> = Pin<Box<dyn Future<Output = T>>>;
+
+// The async counterpart of DeferredDataSource: instead of fetching and then
+// polling for responses, each request returns a future for its response.
+// The futures don't borrow the source, so that many can be in flight at once.
+// Dropping a future abandons the request.
+pub trait AsyncDeferredDataSource {
+    fn fetch_description(&self) -> DataSourceDescription;
+    fn fetch_info(&self) -> BoxFuture<DataSourceInfoResult>;
+    fn fetch_summary_tile(
+        &self,
+        entry_id: &EntryID,
+        tile_id: TileID,
+        full: bool,
+        priority: RequestPriority,
+    ) -> BoxFuture<SummaryTileResult>;
+    fn fetch_slot_tile(
+        &self,
+        entry_id: &EntryID,
+        tile_id: TileID,
+        full: bool,
+        priority: RequestPriority,
+    ) -> BoxFuture<SlotTileResult>;
+    fn fetch_slot_meta_tile(
+        &self,
+        entry_id: &EntryID,
+        tile_id: TileID,
+        full: bool,
+        priority: RequestPriority,
+    ) -> BoxFuture<SlotMetaTileResult>;
+    fn fetch_items_meta(&self, requests: &[ItemMetaRequest]) -> BoxFuture<ItemsMetaResult>;
+}
+
+type Pending<K, R> = Vec<(K, BoxFuture<R>)>;
+
+// Polls every pending future once and returns the ones that are done
+fn poll_pending<K, R>(pending: &mut Pending<K, R>) -> Vec<(R, K)> {
+    let mut cx = Context::from_waker(Waker::noop());
+    let mut result = Vec::new();
+    let mut waiting = Vec::new();
+    for (key, mut future) in pending.drain(..) {
+        match future.as_mut().poll(&mut cx) {
+            Poll::Ready(value) => result.push((value, key)),
+            Poll::Pending => waiting.push((key, future)),
+        }
+    }
+    *pending = waiting;
+    result
+}
+
+// Runs an AsyncDeferredDataSource behind the polling interface. Futures are
+// polled each time the corresponding get_* method is called, so they must not
+// rely on being woken up (e.g., futures that need a tokio runtime must have
+// one running on another thread).
+pub struct AsyncDeferredDataSourceWrapper<T: AsyncDeferredDataSource> {
+    data_source: T,
+    infos: Pending<(), DataSourceInfoResult>,
+    summary_tiles: Pending<TileRequest, SummaryTileResult>,
+    slot_tiles: Pending<TileRequest, SlotTileResult>,
+    slot_meta_tiles: Pending<TileRequest, SlotMetaTileResult>,
+    items_meta: Pending<Vec<ItemMetaRequest>, ItemsMetaResult>,
+}
+
+impl<T: AsyncDeferredDataSource> AsyncDeferredDataSourceWrapper<T> {
+    pub fn new(data_source: T) -> Self {
+        Self {
+            data_source,
+            infos: Vec::new(),
+            summary_tiles: Vec::new(),
+            slot_tiles: Vec::new(),
+            slot_meta_tiles: Vec::new(),
+            items_meta: Vec::new(),
+        }
+    }
+
+    fn cancel_pending<R: 'static>(
+        pending: &mut Pending<TileRequest, TileResult<R>>,
+        req: &TileRequest,
+    ) {
+        for (key, future) in pending.iter_mut() {
+            if key == req {
+                // Dropping the future abandons the request, but the caller
+                // still expects a response
+                *future = Box::pin(ready(Err(CANCELLED.to_string())));
+            }
+        }
+    }
+}
+
+impl<T: AsyncDeferredDataSource> DeferredDataSource for AsyncDeferredDataSourceWrapper<T> {
+    fn fetch_description(&self) -> DataSourceDescription {
+        self.data_source.fetch_description()
+    }
+
+    fn fetch_info(&mut self) {
+        self.infos.push(((), self.data_source.fetch_info()));
+    }
+
+    fn get_infos(&mut self) -> Vec<DataSourceInfoResult> {
+        poll_pending(&mut self.infos)
+            .into_iter()
+            .map(|(info, _)| info)
+            .collect()
+    }
+
+    fn fetch_summary_tile(
+        &mut self,
+        entry_id: &EntryID,
+        tile_id: TileID,
+        full: bool,
+        priority: RequestPriority,
+    ) {
+        let req = TileRequest {
+            entry_id: entry_id.clone(),
+            tile_id,
+            full,
+        };
+        let future = self
+            .data_source
+            .fetch_summary_tile(entry_id, tile_id, full, priority);
+        self.summary_tiles.push((req, future));
+    }
+
+    fn get_summary_tiles(&mut self) -> Vec<SummaryTileResponse> {
+        poll_pending(&mut self.summary_tiles)
+    }
+
+    fn fetch_slot_tile(
+        &mut self,
+        entry_id: &EntryID,
+        tile_id: TileID,
+        full: bool,
+        priority: RequestPriority,
+    ) {
+        let req = TileRequest {
+            entry_id: entry_id.clone(),
+            tile_id,
+            full,
+        };
+        let future = self
+            .data_source
+            .fetch_slot_tile(entry_id, tile_id, full, priority);
+        self.slot_tiles.push((req, future));
+    }
+
+    fn get_slot_tiles(&mut self) -> Vec<SlotTileResponse> {
+        poll_pending(&mut self.slot_tiles)
+    }
+
+    fn fetch_slot_meta_tile(
+        &mut self,
+        entry_id: &EntryID,
+        tile_id: TileID,
+        full: bool,
+        priority: RequestPriority,
+    ) {
+        let req = TileRequest {
+            entry_id: entry_id.clone(),
+            tile_id,
+            full,
+        };
+        let future = self
+            .data_source
+            .fetch_slot_meta_tile(entry_id, tile_id, full, priority);
+        self.slot_meta_tiles.push((req, future));
+    }
+
+    fn get_slot_meta_tiles(&mut self) -> Vec<SlotMetaTileResponse> {
+        poll_pending(&mut self.slot_meta_tiles)
+    }
+
+    fn fetch_items_meta(&mut self, requests: &[ItemMetaRequest]) {
+        let future = self.data_source.fetch_items_meta(requests);
+        self.items_meta.push((requests.to_vec(), future));
+    }
+
+    fn get_items_meta(&mut self) -> Vec<ItemsMetaResponse> {
+        poll_pending(&mut self.items_meta)
+    }
+
+    fn cancel(&mut self, entry_id: &EntryID, tile_id: TileID, full: bool) {
+        let req = TileRequest {
+            entry_id: entry_id.clone(),
+            tile_id,
+            full,
+        };
+        Self::cancel_pending(&mut self.summary_tiles, &req);
+        Self::cancel_pending(&mut self.slot_tiles, &req);
+        Self::cancel_pending(&mut self.slot_meta_tiles, &req);
+    }
+}
+
+type Completed<K, R> = BTreeMap<K, VecDeque<R>>;
+
+struct Shared<T: DeferredDataSource> {
+    data_source: T,
+    infos: VecDeque<DataSourceInfoResult>,
+    summary_tiles: Completed<TileRequest, SummaryTileResult>,
+    slot_tiles: Completed<TileRequest, SlotTileResult>,
+    slot_meta_tiles: Completed<TileRequest, SlotMetaTileResult>,
+    items_meta: Completed<Vec<ItemMetaRequest>, ItemsMetaResult>,
+    // Futures waiting for a response, woken together once the timer fires
+    // (or as soon as any response arrives). Non-empty means a wake is
+    // already scheduled.
+    #[cfg(not(target_arch = "wasm32"))]
+    waiting: Arc<Mutex<Vec<Waker>>>,
+    backoff: Duration,
+}
+
+// How long pending futures wait before polling the source again, doubling
+// while nothing arrives
+const MIN_BACKOFF: Duration = Duration::from_millis(1);
+const MAX_BACKOFF: Duration = Duration::from_millis(50);
+
+impl<T: DeferredDataSource> Shared<T> {
+    fn complete<K: Ord, R>(completed: &mut Completed<K, R>, responses: Vec<(R, K)>) -> bool {
+        let progress = !responses.is_empty();
+        for (result, key) in responses {
+            completed.entry(key).or_default().push_back(result);
+        }
+        progress
+    }
+
+    // Returns true if any response arrived
+    fn poll(&mut self) -> bool {
+        let infos = self.data_source.get_infos();
+        let mut progress = !infos.is_empty();
+        self.infos.extend(infos);
+        progress |= Self::complete(
+            &mut self.summary_tiles,
+            self.data_source.get_summary_tiles(),
+        );
+        progress |= Self::complete(&mut self.slot_tiles, self.data_source.get_slot_tiles());
+        progress |= Self::complete(
+            &mut self.slot_meta_tiles,
+            self.data_source.get_slot_meta_tiles(),
+        );
+        progress |= Self::complete(&mut self.items_meta, self.data_source.get_items_meta());
+        progress
+    }
+
+    // Wakes every waiting future, e.g., so that they can pick up responses
+    // that arrived while polling for another
+    fn wake_all(&mut self) {
+        self.backoff = MIN_BACKOFF;
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            let waiting = std::mem::take(&mut *self.waiting.lock().unwrap());
+            waiting.into_iter().for_each(Waker::wake);
+        }
+    }
+
+    // Arranges for the future to be polled again after the current backoff.
+    // The wake is shared by all waiting futures, so the source is polled at
+    // most once per backoff however many requests are outstanding.
+    #[cfg(not(target_arch = "wasm32"))]
+    fn wake_later(&mut self, waker: &Waker) {
+        let mut waiting = self.waiting.lock().unwrap();
+        if !waiting.iter().any(|w| w.will_wake(waker)) {
+            waiting.push(waker.clone());
+        }
+        if waiting.len() == 1 {
+            let waiting = self.waiting.clone();
+            crate::timer::run_after(
+                self.backoff,
+                Box::new(move || {
+                    let waiting = std::mem::take(&mut *waiting.lock().unwrap());
+                    waiting.into_iter().for_each(Waker::wake);
+                }),
+            );
+            self.backoff = (self.backoff * 2).min(MAX_BACKOFF);
+        }
+    }
+
+    // There's no timer thread on the web, so just ask to be polled again
+    #[cfg(target_arch = "wasm32")]
+    fn wake_later(&mut self, waker: &Waker) {
+        waker.wake_by_ref();
+    }
+
+    fn take<K: Ord, R>(completed: &mut Completed<K, R>, key: &K) -> Option<R> {
+        let results = completed.get_mut(key)?;
+        let result = results.pop_front();
+        if results.is_empty() {
+            completed.remove(key);
+        }
+        result
+    }
+}
+
+// Runs a DeferredDataSource behind the async interface. The source has no
+// way to signal that a response is ready, so pending futures poll it each
+// time they are polled and are woken again after a short backoff, which grows
+// while the source stays quiet and resets once a response arrives.
+pub struct DeferredDataSourceAsyncWrapper<T: DeferredDataSource> {
+    shared: Rc<RefCell<Shared<T>>>,
+}
+
+impl<T: DeferredDataSource> DeferredDataSourceAsyncWrapper<T> {
+    pub fn new(data_source: T) -> Self {
+        Self {
+            shared: Rc::new(RefCell::new(Shared {
+                data_source,
+                infos: VecDeque::new(),
+                summary_tiles: BTreeMap::new(),
+                slot_tiles: BTreeMap::new(),
+                slot_meta_tiles: BTreeMap::new(),
+                items_meta: BTreeMap::new(),
+                #[cfg(not(target_arch = "wasm32"))]
+                waiting: Arc::new(Mutex::new(Vec::new())),
+                backoff: MIN_BACKOFF,
+            })),
+        }
+    }
+
+    fn wait<R: 'static>(&self, take: impl Fn(&mut Shared<T>) -> Option<R> + 'static) -> BoxFuture<R>
+    where
+        T: 'static,
+    {
+        let shared = self.shared.clone();
+        Box::pin(poll_fn(move |cx| {
+            let mut shared = shared.borrow_mut();
+            if shared.poll() {
+                shared.wake_all();
+            }
+            match take(&mut shared) {
+                Some(result) => Poll::Ready(result),
+                None => {
+                    shared.wake_later(cx.waker());
+                    Poll::Pending
+                }
+            }
+        }))
+    }
+}
+
+impl<T: DeferredDataSource + 'static> AsyncDeferredDataSource
+    for DeferredDataSourceAsyncWrapper<T>
+{
+    fn fetch_description(&self) -> DataSourceDescription {
+        self.shared.borrow().data_source.fetch_description()
+    }
+
+    fn fetch_info(&self) -> BoxFuture<DataSourceInfoResult> {
+        self.shared.borrow_mut().data_source.fetch_info();
+        self.wait(|shared| shared.infos.pop_front())
+    }
+
+    fn fetch_summary_tile(
+        &self,
+        entry_id: &EntryID,
+        tile_id: TileID,
+        full: bool,
+        priority: RequestPriority,
+    ) -> BoxFuture<SummaryTileResult> {
+        self.shared
+            .borrow_mut()
+            .data_source
+            .fetch_summary_tile(entry_id, tile_id, full, priority);
+        let req = TileRequest {
+            entry_id: entry_id.clone(),
+            tile_id,
+            full,
+        };
+        self.wait(move |shared| Shared::<T>::take(&mut shared.summary_tiles, &req))
+    }
+
+    fn fetch_slot_tile(
+        &self,
+        entry_id: &EntryID,
+        tile_id: TileID,
+        full: bool,
+        priority: RequestPriority,
+    ) -> BoxFuture<SlotTileResult> {
+        self.shared
+            .borrow_mut()
+            .data_source
+            .fetch_slot_tile(entry_id, tile_id, full, priority);
+        let req = TileRequest {
+            entry_id: entry_id.clone(),
+            tile_id,
+            full,
+        };
+        self.wait(move |shared| Shared::<T>::take(&mut shared.slot_tiles, &req))
+    }
+
+    fn fetch_slot_meta_tile(
+        &self,
+        entry_id: &EntryID,
+        tile_id: TileID,
+        full: bool,
+        priority: RequestPriority,
+    ) -> BoxFuture<SlotMetaTileResult> {
+        self.shared
+            .borrow_mut()
+            .data_source
+            .fetch_slot_meta_tile(entry_id, tile_id, full, priority);
+        let req = TileRequest {
+            entry_id: entry_id.clone(),
+            tile_id,
+            full,
+        };
+        self.wait(move |shared| Shared::<T>::take(&mut shared.slot_meta_tiles, &req))
+    }
+
+    fn fetch_items_meta(&self, requests: &[ItemMetaRequest]) -> BoxFuture<ItemsMetaResult> {
+        self.shared
+            .borrow_mut()
+            .data_source
+            .fetch_items_meta(requests);
+        let requests = requests.to_vec();
+        self.wait(move |shared| Shared::<T>::take(&mut shared.items_meta, &requests))
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+mod executor {
+    use std::future::Future;
+    use std::pin::Pin;
+    use std::sync::Arc;
+    use std::task::{Context, Poll, Wake, Waker};
+    use std::thread::{self, Thread};
+
+    struct ThreadWaker(Thread);
+
+    impl Wake for ThreadWaker {
+        fn wake(self: Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+
+    fn thread_waker() -> Waker {
+        Arc::new(ThreadWaker(thread::current())).into()
+    }
+
+    // Runs a future to completion on the current thread
+    pub fn block_on<F: Future>(future: F) -> F::Output {
+        let waker = thread_waker();
+        let mut cx = Context::from_waker(&waker);
+        let mut future = std::pin::pin!(future);
+        loop {
+            if let Poll::Ready(result) = future.as_mut().poll(&mut cx) {
+                return result;
+            }
+            thread::park();
+        }
+    }
+
+    // Runs futures to completion on the current thread, with at most limit of
+    // them started at once, and passes each result to f as it completes
+    pub fn block_on_each<F: Future>(
+        futures: impl IntoIterator<Item = F>,
+        limit: usize,
+        f: impl FnMut(F::Output),
+    ) {
+        block_on_each_with_idle(futures, limit, f, || false)
+    }
+
+    // Like block_on_each, but calls idle between rounds of polling so the
+    // caller can get other work done. idle returns whether it did anything;
+    // if not, the thread sleeps until woken, so whoever hands idle more work
+    // must unpark this thread
+    pub fn block_on_each_with_idle<F: Future>(
+        futures: impl IntoIterator<Item = F>,
+        limit: usize,
+        mut f: impl FnMut(F::Output),
+        mut idle: impl FnMut() -> bool,
+    ) {
+        assert!(limit > 0);
+        let waker = thread_waker();
+        let mut cx = Context::from_waker(&waker);
+        let mut futures = futures.into_iter();
+        let mut running: Vec<Pin<Box<F>>> = Vec::new();
+        loop {
+            running.extend(futures.by_ref().take(limit - running.len()).map(Box::pin));
+            if running.is_empty() {
+                return;
+            }
+            let mut done = false;
+            running.retain_mut(|future| match future.as_mut().poll(&mut cx) {
+                Poll::Ready(result) => {
+                    f(result);
+                    done = true;
+                    false
+                }
+                Poll::Pending => true,
+            });
+            let busy = idle();
+            if !done && !busy {
+                thread::park();
+            }
+        }
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub use executor::{block_on, block_on_each, block_on_each_with_idle};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::data::DataSource;
+    use crate::deferred_data::DeferredDataSourceWrapper;
+    use crate::test_data::TestDataSource;
+    use crate::timestamp::{Interval, Timestamp};
+
+    // Never completes, to check cancellation
+    struct StuckDataSource;
+
+    impl AsyncDeferredDataSource for StuckDataSource {
+        fn fetch_description(&self) -> DataSourceDescription {
+            unimplemented!()
+        }
+        fn fetch_info(&self) -> BoxFuture<DataSourceInfoResult> {
+            Box::pin(std::future::pending())
+        }
+        fn fetch_summary_tile(
+            &self,
+            _: &EntryID,
+            _: TileID,
+            _: bool,
+            _: RequestPriority,
+        ) -> BoxFuture<SummaryTileResult> {
+            Box::pin(std::future::pending())
+        }
+        fn fetch_slot_tile(
+            &self,
+            _: &EntryID,
+            _: TileID,
+            _: bool,
+            _: RequestPriority,
+        ) -> BoxFuture<SlotTileResult> {
+            Box::pin(std::future::pending())
+        }
+        fn fetch_slot_meta_tile(
+            &self,
+            _: &EntryID,
+            _: TileID,
+            _: bool,
+            _: RequestPriority,
+        ) -> BoxFuture<SlotMetaTileResult> {
+            Box::pin(std::future::pending())
+        }
+        fn fetch_items_meta(&self, _: &[ItemMetaRequest]) -> BoxFuture<ItemsMetaResult> {
+            Box::pin(std::future::pending())
+        }
+    }
+
+    // Answers the info request once the delay has passed, counting how often
+    // it's polled in the meantime
+    struct SlowDataSource {
+        ready: std::time::Instant,
+        polls: Rc<std::cell::Cell<usize>>,
+    }
+
+    impl DeferredDataSource for SlowDataSource {
+        fn fetch_description(&self) -> DataSourceDescription {
+            TestDataSource::default().fetch_description()
+        }
+        fn fetch_info(&mut self) {}
+        fn get_infos(&mut self) -> Vec<DataSourceInfoResult> {
+            self.polls.set(self.polls.get() + 1);
+            if std::time::Instant::now() < self.ready {
+                return Vec::new();
+            }
+            vec![Ok(TestDataSource::default().fetch_info())]
+        }
+        fn fetch_summary_tile(&mut self, _: &EntryID, _: TileID, _: bool, _: RequestPriority) {}
+        fn get_summary_tiles(&mut self) -> Vec<SummaryTileResponse> {
+            Vec::new()
+        }
+        fn fetch_slot_tile(&mut self, _: &EntryID, _: TileID, _: bool, _: RequestPriority) {}
+        fn get_slot_tiles(&mut self) -> Vec<SlotTileResponse> {
+            Vec::new()
+        }
+        fn fetch_slot_meta_tile(&mut self, _: &EntryID, _: TileID, _: bool, _: RequestPriority) {}
+        fn get_slot_meta_tiles(&mut self) -> Vec<SlotMetaTileResponse> {
+            Vec::new()
+        }
+        fn fetch_items_meta(&mut self, _: &[ItemMetaRequest]) {}
+        fn get_items_meta(&mut self) -> Vec<ItemsMetaResponse> {
+            Vec::new()
+        }
+        fn cancel(&mut self, _: &EntryID, _: TileID, _: bool) {}
+    }
+
+    #[test]
+    fn test_async_backoff() {
+        let polls = Rc::new(std::cell::Cell::new(0));
+        let ds = DeferredDataSourceAsyncWrapper::new(SlowDataSource {
+            ready: std::time::Instant::now() + Duration::from_millis(200),
+            polls: polls.clone(),
+        });
+        let info = block_on(ds.fetch_info()).unwrap();
+        assert_eq!(info.interval.stop, Timestamp(1000));
+        // Waiting 200ms at up to 50ms a poll, not spinning
+        assert!(polls.get() < 20, "{} polls", polls.get());
+    }
+
+    #[test]
+    fn test_async_round_trip() {
+        let entry_id = EntryID::root().child(0);
+        let tile_ids: Vec<_> = (0..4)
+            .map(|i| TileID(Interval::new(Timestamp(i * 10), Timestamp(i * 10 + 10))))
+            .collect();
+
+        // Polling -> async
+        let ds = DeferredDataSourceAsyncWrapper::new(DeferredDataSourceWrapper::new(
+            TestDataSource::default(),
+        ));
+        assert_eq!(ds.fetch_description().source_locator, vec!["test"]);
+        let info = block_on(ds.fetch_info()).unwrap();
+        assert_eq!(info.interval.stop, Timestamp(1000));
+
+        let futures = tile_ids.iter().map(|tile_id| {
+            ds.fetch_slot_tile(&entry_id, *tile_id, false, RequestPriority::Visible)
+        });
+        let mut tiles = Vec::new();
+        block_on_each(futures, 2, |tile| tiles.push(tile.unwrap().tile_id));
+        tiles.sort();
+        assert_eq!(tiles, tile_ids);
+
+        // Work handed back to idle keeps the loop going
+        let futures = tile_ids.iter().map(|tile_id| {
+            ds.fetch_slot_tile(&entry_id, *tile_id, false, RequestPriority::Visible)
+        });
+        let pending = RefCell::new(Vec::new());
+        let mut tiles = Vec::new();
+        block_on_each_with_idle(
+            futures,
+            2,
+            |tile| pending.borrow_mut().push(tile.unwrap().tile_id),
+            || {
+                let busy = !pending.borrow().is_empty();
+                tiles.append(&mut pending.borrow_mut());
+                busy
+            },
+        );
+        tiles.append(&mut pending.borrow_mut());
+        tiles.sort();
+        assert_eq!(tiles, tile_ids);
+
+        // And back again
+        let mut ds = AsyncDeferredDataSourceWrapper::new(ds);
+        for tile_id in &tile_ids {
+            ds.fetch_slot_tile(&entry_id, *tile_id, true, RequestPriority::Visible);
+        }
+        let mut tiles: Vec<_> = ds
+            .get_slot_tiles()
+            .into_iter()
+            .map(|(_, req)| req.tile_id)
+            .collect();
+        tiles.sort();
+        assert_eq!(tiles, tile_ids);
+    }
+
+    #[test]
+    fn test_async_cancel() {
+        let entry_id = EntryID::root().child(0);
+        let tile_id = TileID(Interval::new(Timestamp(0), Timestamp(10)));
+
+        let mut ds = AsyncDeferredDataSourceWrapper::new(StuckDataSource);
+        ds.fetch_slot_tile(&entry_id, tile_id, false, RequestPriority::Visible);
+        assert!(ds.get_slot_tiles().is_empty());
+
+        ds.cancel(&entry_id, tile_id, false);
+        let (tile, req) = ds.get_slot_tiles().pop().unwrap();
+        assert_eq!(tile.unwrap_err(), CANCELLED);
+        assert_eq!(req.tile_id, tile_id);
+    }
+}
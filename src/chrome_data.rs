@@ -0,0 +1,302 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::path::Path;
+
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::data::{Field, FieldID};
+use crate::timestamp::{Interval, Timestamp};
+use crate::trace_data::{ThreadID, TraceBuilder, TraceDataSource, TraceItem};
+
+// The subset of the Trace Event Format that we use. Durations are given by
+// matching B/E pairs or by complete (X) events; instant, counter, async and
+// flow events are ignored. Times are in microseconds.
+#[derive(Debug, Deserialize)]
+struct Event {
+    #[serde(default)]
+    name: String,
+    #[serde(default)]
+    cat: String,
+    ph: String,
+    #[serde(default)]
+    ts: f64,
+    dur: Option<f64>,
+    #[serde(default)]
+    pid: Value,
+    #[serde(default)]
+    tid: Value,
+    #[serde(default)]
+    args: BTreeMap<String, Value>,
+}
+
+// A trace is either a bare array of events or an object holding them
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum Trace {
+    Array(Vec<Event>),
+    Object {
+        #[serde(rename = "traceEvents")]
+        trace_events: Vec<Event>,
+    },
+}
+
+struct Importer {
+    builder: TraceBuilder,
+    category_field: FieldID,
+    process_names: BTreeMap<String, String>,
+    thread_names: BTreeMap<(String, String), String>,
+    // Open B events on each thread
+    stacks: BTreeMap<(String, String), Vec<Event>>,
+}
+
+impl Importer {
+    fn thread(&mut self, pid: &str, tid: &str) -> ThreadID {
+        let process = self
+            .process_names
+            .get(pid)
+            .cloned()
+            .unwrap_or_else(|| format!("Process {pid}"));
+        let thread = self
+            .thread_names
+            .get(&(pid.to_owned(), tid.to_owned()))
+            .cloned()
+            .unwrap_or_else(|| format!("Thread {tid}"));
+        self.builder.thread(&process, "Threads", &thread)
+    }
+
+    fn add_item(&mut self, event: Event, start: f64, stop: f64, mut args: BTreeMap<String, Value>) {
+        let thread = self.thread(&id(&event.pid), &id(&event.tid));
+        args.extend(event.args);
+        let mut fields = Vec::new();
+        if !event.cat.is_empty() {
+            fields.push((self.category_field, Field::String(event.cat)));
+        }
+        for (name, value) in args {
+            let searchable = value.is_string();
+            fields.push((self.builder.field(name, searchable), to_field(value)));
+        }
+        self.builder.add_item(
+            thread,
+            TraceItem {
+                interval: Interval::new(to_timestamp(start), to_timestamp(stop)),
+                title: event.name,
+                color: None,
+                fields,
+            },
+        );
+    }
+}
+
+// Process and thread IDs may be numbers or strings
+fn id(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        Value::Null => "0".to_owned(),
+        value => value.to_string(),
+    }
+}
+
+fn to_timestamp(us: f64) -> Timestamp {
+    Timestamp((us * 1000.0).round() as i64)
+}
+
+fn to_field(value: Value) -> Field {
+    match value {
+        Value::Null => Field::Empty,
+        Value::String(s) => Field::String(s),
+        Value::Number(n) => {
+            if let Some(i) = n.as_u64() {
+                Field::U64(i)
+            } else if let Some(i) = n.as_i64() {
+                Field::I64(i)
+            } else {
+                Field::String(n.to_string())
+            }
+        }
+        Value::Array(values) => Field::Vec(values.into_iter().map(to_field).collect()),
+        value => Field::String(value.to_string()),
+    }
+}
+
+fn parse_error(error: serde_json::Error) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, error)
+}
+
+// Loads a Chrome trace (as written by chrome://tracing, Perfetto's JSON
+// exporter and many other tools) into memory. Each pid becomes a node and
+// each tid a slot.
+pub fn load_chrome_trace(path: impl AsRef<Path>) -> io::Result<TraceDataSource> {
+    let path = path.as_ref();
+    let reader = BufReader::new(File::open(path)?);
+    let trace = serde_json::from_reader(reader).map_err(parse_error)?;
+    Ok(import(trace, path.to_string_lossy()))
+}
+
+pub fn parse_chrome_trace(
+    source_locator: impl Into<String>,
+    json: &[u8],
+) -> io::Result<TraceDataSource> {
+    let trace = serde_json::from_slice(json).map_err(parse_error)?;
+    Ok(import(trace, source_locator))
+}
+
+fn import(trace: Trace, source_locator: impl Into<String>) -> TraceDataSource {
+    let mut events = match trace {
+        Trace::Array(events) => events,
+        Trace::Object { trace_events } => trace_events,
+    };
+    // B/E pairs are matched in time order, whatever the order in the file
+    events.sort_by(|a, b| a.ts.total_cmp(&b.ts));
+
+    let mut builder = TraceBuilder::new(source_locator);
+    let category_field = builder.field("Category", true);
+    let mut importer = Importer {
+        builder,
+        category_field,
+        process_names: BTreeMap::new(),
+        thread_names: BTreeMap::new(),
+        stacks: BTreeMap::new(),
+    };
+
+    // Names come from metadata events, which may appear anywhere
+    for event in &events {
+        let name = event.args.get("name").and_then(Value::as_str);
+        match (event.ph.as_str(), event.name.as_str(), name) {
+            ("M", "process_name", Some(name)) => {
+                importer
+                    .process_names
+                    .insert(id(&event.pid), name.to_owned());
+            }
+            ("M", "thread_name", Some(name)) => {
+                let key = (id(&event.pid), id(&event.tid));
+                importer.thread_names.insert(key, name.to_owned());
+            }
+            _ => {}
+        }
+    }
+
+    // Register threads in ID order, so that they are listed the same way
+    // regardless of which event comes first
+    let mut threads: Vec<_> = events
+        .iter()
+        .filter(|event| matches!(event.ph.as_str(), "B" | "E" | "X"))
+        .map(|event| (event.pid.clone(), event.tid.clone()))
+        .collect();
+    threads.sort_by(|a, b| sort_key(a).cmp(&sort_key(b)));
+    threads.dedup();
+    for (pid, tid) in threads {
+        importer.thread(&id(&pid), &id(&tid));
+    }
+
+    let mut end = 0.0f64;
+    for event in events {
+        end = end.max(event.ts + event.dur.unwrap_or(0.0));
+        match event.ph.as_str() {
+            "X" => {
+                let (start, stop) = (event.ts, event.ts + event.dur.unwrap_or(0.0));
+                importer.add_item(event, start, stop, BTreeMap::new());
+            }
+            "B" => {
+                let key = (id(&event.pid), id(&event.tid));
+                importer.stacks.entry(key).or_default().push(event);
+            }
+            "E" => {
+                let key = (id(&event.pid), id(&event.tid));
+                let begin = importer.stacks.get_mut(&key).and_then(Vec::pop);
+                // An E without a B has nothing to close
+                if let Some(begin) = begin {
+                    let start = begin.ts;
+                    importer.add_item(begin, start, event.ts, event.args);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    // Anything still open ran until the end of the trace
+    for stack in std::mem::take(&mut importer.stacks).into_values() {
+        for begin in stack {
+            let start = begin.ts;
+            importer.add_item(begin, start, end, BTreeMap::new());
+        }
+    }
+
+    importer.builder.build()
+}
+
+// Numeric IDs sort numerically, and before any string IDs
+fn sort_key((pid, tid): &(Value, Value)) -> impl Ord {
+    let key = |value: &Value| (value.as_i64().is_none(), value.as_i64(), id(value));
+    (key(pid), key(tid))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::data::{DataSource, EntryID, EntryInfo, TileID};
+
+    #[test]
+    fn test_chrome_trace() {
+        let json = br#"{"traceEvents": [
+            {"name": "thread_name", "ph": "M", "pid": 1, "tid": 2, "args": {"name": "worker"}},
+            {"name": "outer", "cat": "app", "ph": "B", "ts": 0, "pid": 1, "tid": 2},
+            {"name": "inner", "ph": "X", "ts": 1.5, "dur": 2, "pid": 1, "tid": 2,
+             "args": {"bytes": 64}},
+            {"ph": "E", "ts": 10, "pid": 1, "tid": 2, "args": {"status": "ok"}},
+            {"name": "other", "ph": "X", "ts": 4, "dur": 1, "pid": 1, "tid": 10},
+            {"name": "main", "ph": "X", "ts": 0, "dur": 5, "pid": 1, "tid": 1}
+        ]}"#;
+        let ds = parse_chrome_trace("test.json", json).unwrap();
+        let info = ds.fetch_info();
+        assert_eq!(
+            info.interval,
+            Interval::new(Timestamp(0), Timestamp(10_000))
+        );
+
+        let process = info.entry_info.get(&EntryID::root().child(0)).unwrap();
+        let EntryInfo::Panel { long_name, .. } = process else {
+            panic!("expected a panel");
+        };
+        assert_eq!(long_name, "Process 1");
+
+        // Threads are in tid order
+        let names: Vec<_> = (0..3)
+            .map(|i| {
+                let entry_id = EntryID::root().child(0).child(0).child(i);
+                let Some(EntryInfo::Slot { short_name, .. }) = info.entry_info.get(&entry_id)
+                else {
+                    panic!("expected a slot");
+                };
+                short_name.clone()
+            })
+            .collect();
+        assert_eq!(names, vec!["Thread 1", "worker", "Thread 10"]);
+
+        let worker = EntryID::root().child(0).child(0).child(1);
+        let meta = ds.fetch_slot_meta_tile(&worker, TileID(info.interval), true);
+        assert_eq!(meta.data.items.len(), 2);
+        let outer = &meta.data.items[0][0];
+        assert_eq!(outer.title, "outer");
+        assert_eq!(
+            outer.original_interval,
+            Interval::new(Timestamp(0), Timestamp(10_000))
+        );
+        // Interval, category and the arguments of the E event
+        assert_eq!(outer.fields.len(), 3);
+        let inner = &meta.data.items[1][0];
+        assert_eq!(
+            inner.original_interval,
+            Interval::new(Timestamp(1500), Timestamp(3500))
+        );
+        let bytes = info.field_schema.get_id("bytes").unwrap();
+        assert!(
+            inner
+                .fields
+                .iter()
+                .any(|(field_id, field, _)| *field_id == bytes && matches!(field, Field::U64(64)))
+        );
+    }
+}
@@ -1,5 +1,6 @@
 use std::collections::BTreeMap;
 use std::fmt;
+use std::time::Duration;
 
 pub use egui::{Color32, Rgba};
 use serde::{Deserialize, Serialize};
@@ -7,6 +8,11 @@ use std::collections::BTreeSet;
 
 use crate::timestamp::{Interval, Timestamp};
 
+// Version of the format in which data sources are serialized (over HTTP and
+// in archives). Bump this whenever a change would stop an older viewer from
+// decoding the data, or vice versa.
+pub const PROTOCOL_VERSION: u32 = 1;
+
 // We encode EntryID as i64 because it allows us to pack Summary into the
 // value -1. Users shouldn't need to know about this and interact through the
 // methods below, or via EntryIndex.
@@ -26,6 +32,62 @@ pub struct DataSourceInfo {
     pub tile_set: TileSet,
     pub field_schema: FieldSchema,
     pub warning_message: Option<String>,
+    // Sources whose data keeps changing (e.g., while monitoring a running
+    // application) can ask for the info to be fetched again this often
+    #[serde(default)]
+    pub refresh_interval: Option<Duration>,
+    // PROTOCOL_VERSION of whoever produced the info, or zero if it predates
+    // versioning
+    #[serde(default)]
+    pub version: u32,
+    #[serde(default)]
+    pub capabilities: Capabilities,
+    // Entries whose density differs a lot from the rest (e.g., a sparse
+    // channel next to busy CPUs) can be tiled on their own. Entries not
+    // listed use tile_set.
+    #[serde(default)]
+    pub entry_tile_sets: BTreeMap<EntryID, TileSet>,
+}
+
+impl DataSourceInfo {
+    pub fn entry_tile_set(&self, entry_id: &EntryID) -> &TileSet {
+        self.entry_tile_sets.get(entry_id).unwrap_or(&self.tile_set)
+    }
+}
+
+// Optional features of a data source, so that callers can avoid requests the
+// source can't answer. Sources that predate this get the defaults, which
+// describe what every source could do at the time. New features should be
+// added here (off by default) rather than by bumping PROTOCOL_VERSION.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Capabilities {
+    // Utilization summaries for panels
+    pub summary_tiles: bool,
+    // Item metadata (used for tooltips, search and selection)
+    pub slot_meta_tiles: bool,
+    // Server-side search, so the viewer doesn't have to fetch every meta tile
+    pub search: bool,
+    // Tiles can be requested many at a time (e.g., via POST /tiles)
+    pub batch_fetch: bool,
+    // Item metadata can be looked up by item (via POST /items_meta), rather
+    // than by fetching the slot meta tile the item is in
+    pub items_meta: bool,
+    // The info may change while the profile is open (see refresh_interval)
+    pub live_updates: bool,
+}
+
+impl Default for Capabilities {
+    fn default() -> Self {
+        Self {
+            summary_tiles: true,
+            slot_meta_tiles: true,
+            search: false,
+            batch_fetch: false,
+            items_meta: false,
+            live_updates: false,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -43,9 +105,21 @@ pub enum EntryInfo {
     },
     Summary {
         color: Color32,
+        #[serde(default)]
+        units: SummaryUnits,
     },
 }
 
+// What the values in a summary's UtilPoints mean
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub enum SummaryUnits {
+    // Fraction of the resource in use, in the range [0, 1]
+    #[default]
+    Utilization,
+    // Instantaneous power draw. Integrating over time gives energy (joules)
+    Watts,
+}
+
 #[derive(Debug, Copy, Clone, PartialEq, PartialOrd, Default, Deserialize, Serialize)]
 pub struct UtilPoint {
     pub time: Timestamp,
@@ -123,6 +197,12 @@ impl FieldSchema {
     pub fn searchable(&self) -> &BTreeSet<FieldID> {
         &self.searchable
     }
+
+    pub fn iter(&self) -> impl Iterator<Item = (FieldID, &str)> {
+        self.field_names
+            .iter()
+            .map(|(field_id, name)| (*field_id, name.as_str()))
+    }
 }
 
 impl Default for FieldSchema {
@@ -142,7 +222,7 @@ pub enum Field {
     Empty,
 }
 
-#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Deserialize, Serialize)]
 pub struct ItemUID(pub u64);
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -207,6 +287,15 @@ pub struct SlotMetaTile {
     pub data: SlotMetaTileData,
 }
 
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Deserialize, Serialize)]
+pub struct ItemMetaRequest {
+    pub entry_id: EntryID,
+    pub item_uid: ItemUID,
+    // Hint for where to look for the item (i.e., the item's original
+    // interval or any interval overlapping it).
+    pub interval: Interval,
+}
+
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct DataSourceDescription {
     pub source_locator: Vec<String>,
@@ -219,6 +308,141 @@ pub trait DataSource {
     fn fetch_slot_tile(&self, entry_id: &EntryID, tile_id: TileID, full: bool) -> SlotTile;
     fn fetch_slot_meta_tile(&self, entry_id: &EntryID, tile_id: TileID, full: bool)
     -> SlotMetaTile;
+
+    // Fetch the metadata for a batch of items at once. Items that cannot be
+    // found are omitted from the result. The default implementation issues
+    // one full meta tile request per entry covering all requested intervals.
+    fn fetch_items_meta(&self, requests: &[ItemMetaRequest]) -> Vec<ItemMeta> {
+        let mut entries: BTreeMap<&EntryID, (Interval, BTreeSet<ItemUID>)> = BTreeMap::new();
+        for req in requests {
+            entries
+                .entry(&req.entry_id)
+                .and_modify(|(interval, item_uids)| {
+                    *interval = interval.union(req.interval);
+                    item_uids.insert(req.item_uid);
+                })
+                .or_insert_with(|| (req.interval, BTreeSet::from([req.item_uid])));
+        }
+
+        let mut result = Vec::new();
+        for (entry_id, (interval, item_uids)) in entries {
+            let tile = self.fetch_slot_meta_tile(entry_id, TileID(interval), true);
+            result.extend(
+                tile.data
+                    .items
+                    .into_iter()
+                    .flatten()
+                    .filter(|item| item_uids.contains(&item.item_uid)),
+            );
+        }
+        result
+    }
+
+    // Fallible versions of the above, for sources that read from somewhere
+    // that can go wrong (e.g., a damaged bundle). Callers that can report an
+    // error should use these; by default they never fail.
+    fn try_fetch_info(&self) -> Result<DataSourceInfo, String> {
+        Ok(self.fetch_info())
+    }
+
+    fn try_fetch_summary_tile(
+        &self,
+        entry_id: &EntryID,
+        tile_id: TileID,
+        full: bool,
+    ) -> Result<SummaryTile, String> {
+        Ok(self.fetch_summary_tile(entry_id, tile_id, full))
+    }
+
+    fn try_fetch_slot_tile(
+        &self,
+        entry_id: &EntryID,
+        tile_id: TileID,
+        full: bool,
+    ) -> Result<SlotTile, String> {
+        Ok(self.fetch_slot_tile(entry_id, tile_id, full))
+    }
+
+    fn try_fetch_slot_meta_tile(
+        &self,
+        entry_id: &EntryID,
+        tile_id: TileID,
+        full: bool,
+    ) -> Result<SlotMetaTile, String> {
+        Ok(self.fetch_slot_meta_tile(entry_id, tile_id, full))
+    }
+
+    fn try_fetch_items_meta(&self, requests: &[ItemMetaRequest]) -> Result<Vec<ItemMeta>, String> {
+        Ok(self.fetch_items_meta(requests))
+    }
+}
+
+impl DataSource for Box<dyn DataSource + Send + Sync> {
+    fn fetch_description(&self) -> DataSourceDescription {
+        self.as_ref().fetch_description()
+    }
+
+    fn fetch_info(&self) -> DataSourceInfo {
+        self.as_ref().fetch_info()
+    }
+
+    fn fetch_summary_tile(&self, entry_id: &EntryID, tile_id: TileID, full: bool) -> SummaryTile {
+        self.as_ref().fetch_summary_tile(entry_id, tile_id, full)
+    }
+
+    fn fetch_slot_tile(&self, entry_id: &EntryID, tile_id: TileID, full: bool) -> SlotTile {
+        self.as_ref().fetch_slot_tile(entry_id, tile_id, full)
+    }
+
+    fn fetch_slot_meta_tile(
+        &self,
+        entry_id: &EntryID,
+        tile_id: TileID,
+        full: bool,
+    ) -> SlotMetaTile {
+        self.as_ref().fetch_slot_meta_tile(entry_id, tile_id, full)
+    }
+
+    fn fetch_items_meta(&self, requests: &[ItemMetaRequest]) -> Vec<ItemMeta> {
+        self.as_ref().fetch_items_meta(requests)
+    }
+
+    fn try_fetch_info(&self) -> Result<DataSourceInfo, String> {
+        self.as_ref().try_fetch_info()
+    }
+
+    fn try_fetch_summary_tile(
+        &self,
+        entry_id: &EntryID,
+        tile_id: TileID,
+        full: bool,
+    ) -> Result<SummaryTile, String> {
+        self.as_ref()
+            .try_fetch_summary_tile(entry_id, tile_id, full)
+    }
+
+    fn try_fetch_slot_tile(
+        &self,
+        entry_id: &EntryID,
+        tile_id: TileID,
+        full: bool,
+    ) -> Result<SlotTile, String> {
+        self.as_ref().try_fetch_slot_tile(entry_id, tile_id, full)
+    }
+
+    fn try_fetch_slot_meta_tile(
+        &self,
+        entry_id: &EntryID,
+        tile_id: TileID,
+        full: bool,
+    ) -> Result<SlotMetaTile, String> {
+        self.as_ref()
+            .try_fetch_slot_meta_tile(entry_id, tile_id, full)
+    }
+
+    fn try_fetch_items_meta(&self, requests: &[ItemMetaRequest]) -> Result<Vec<ItemMeta>, String> {
+        self.as_ref().try_fetch_items_meta(requests)
+    }
 }
 
 impl EntryID {
@@ -307,6 +531,42 @@ impl EntryInfo {
         Some(result)
     }
 
+    pub fn get_mut(&mut self, entry_id: &EntryID) -> Option<&mut EntryInfo> {
+        let mut result = self;
+        for i in 0..entry_id.level() {
+            match (entry_id.index(i)?, result) {
+                (EntryIndex::Summary, EntryInfo::Panel { summary, .. }) => {
+                    return summary.as_deref_mut();
+                }
+                (EntryIndex::Slot(j), EntryInfo::Panel { slots, .. }) => {
+                    result = slots.get_mut(j as usize)?;
+                }
+                _ => panic!("EntryID and EntryInfo do not match"),
+            }
+        }
+        Some(result)
+    }
+
+    // Finds the panel or slot at a path of short names from the outermost
+    // panel down, e.g., "n0/cpu/cpu0" (the same as the export's paths)
+    pub fn find_path(&self, path: &str) -> Option<EntryID> {
+        let mut result = self;
+        let mut entry_id = EntryID::root();
+        for name in path.split('/').filter(|name| !name.is_empty()) {
+            let EntryInfo::Panel { slots, .. } = result else {
+                return None;
+            };
+            let (i, slot) = slots.iter().enumerate().find(|(_, slot)| {
+                matches!(slot,
+                    EntryInfo::Panel { short_name, .. } | EntryInfo::Slot { short_name, .. }
+                        if short_name == name)
+            })?;
+            result = slot;
+            entry_id = entry_id.child(i as u64);
+        }
+        Some(entry_id)
+    }
+
     pub fn nodes(&self) -> u64 {
         if let EntryInfo::Panel { slots, .. } = self {
             slots.len() as u64
@@ -373,6 +633,35 @@ impl From<std::num::ParseIntError> for SlugParseError {
     }
 }
 
+impl SummaryTileData {
+    // Integrate the (piecewise linear) curve over the given interval. The
+    // result is in units of value * seconds, so for a power counter in watts
+    // this is the energy in joules.
+    pub fn integrate(&self, interval: Interval) -> f64 {
+        integrate_points(&self.utilization, interval)
+    }
+}
+
+pub fn integrate_points(points: &[UtilPoint], interval: Interval) -> f64 {
+    let value_at = |a: &UtilPoint, b: &UtilPoint, t: Timestamp| {
+        let ratio = (t.0 - a.time.0) as f64 / (b.time.0 - a.time.0) as f64;
+        a.util as f64 + (b.util as f64 - a.util as f64) * ratio
+    };
+
+    let mut result = 0.0;
+    for (a, b) in points.iter().zip(points.iter().skip(1)) {
+        let segment = Interval::new(a.time, b.time);
+        if segment.duration_ns() <= 0 || !segment.overlaps(interval) {
+            continue;
+        }
+        let clip = segment.intersection(interval);
+        let start = value_at(a, b, clip.start);
+        let stop = value_at(a, b, clip.stop);
+        result += (start + stop) / 2.0 * clip.duration_ns() as f64 * 1e-9;
+    }
+    result
+}
+
 impl TileID {
     pub fn from_slug(s: &str) -> Result<Self, SlugParseError> {
         let elts: Result<Vec<i64>, _> = s.split('_').map(|x| x.parse::<i64>()).collect();
@@ -419,4 +708,103 @@ impl EntryID {
         assert!(result.0[0] >= 0);
         result
     }
+
+    // The raw encoding, for formats other than serde's (e.g., protobuf)
+    #[cfg(all(feature = "grpc", not(target_arch = "wasm32")))]
+    pub(crate) fn indices(&self) -> &[i64] {
+        &self.0
+    }
+
+    #[cfg(all(feature = "grpc", not(target_arch = "wasm32")))]
+    pub(crate) fn from_indices(indices: Vec<i64>) -> Self {
+        Self(indices)
+    }
+}
+
+// Likewise for FieldID and FieldSchema, whose IDs must survive the trip
+#[cfg(all(feature = "grpc", not(target_arch = "wasm32")))]
+impl FieldID {
+    pub(crate) fn index(self) -> usize {
+        self.0
+    }
+
+    pub(crate) fn from_index(index: usize) -> Self {
+        Self(index)
+    }
+}
+
+#[cfg(all(feature = "grpc", not(target_arch = "wasm32")))]
+impl FieldSchema {
+    pub(crate) fn insert_with_id(&mut self, field_id: FieldID, name: String, searchable: bool) {
+        self.field_ids.insert(name.clone(), field_id);
+        self.field_names.insert(field_id, name);
+        if searchable {
+            self.searchable.insert(field_id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn point(time: i64, util: f32) -> UtilPoint {
+        UtilPoint {
+            time: Timestamp(time),
+            util,
+        }
+    }
+
+    #[test]
+    fn test_integrate_constant() {
+        let points = [point(0, 2.0), point(1_000_000_000, 2.0)];
+        let full = Interval::new(Timestamp(0), Timestamp(1_000_000_000));
+        assert!((integrate_points(&points, full) - 2.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_integrate_clipped() {
+        // Ramp from 0 W to 10 W over 1 s, integrate over the second half
+        let points = [point(0, 0.0), point(1_000_000_000, 10.0)];
+        let half = Interval::new(Timestamp(500_000_000), Timestamp(2_000_000_000));
+        assert!((integrate_points(&points, half) - 3.75).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_find_path() {
+        let slot = |name: &str| EntryInfo::Slot {
+            short_name: name.to_owned(),
+            long_name: name.to_owned(),
+            max_rows: 1,
+        };
+        let panel = |name: &str, slots| EntryInfo::Panel {
+            short_name: name.to_owned(),
+            long_name: name.to_owned(),
+            summary: None,
+            slots,
+        };
+        let info = panel(
+            "root",
+            vec![
+                panel("n0", vec![panel("cpu", vec![slot("cpu0"), slot("cpu1")])]),
+                panel("n1", vec![panel("gpu", vec![slot("gpu0")])]),
+            ],
+        );
+        let root = EntryID::root();
+        assert_eq!(
+            info.find_path("n0/cpu/cpu1"),
+            Some(root.child(0).child(0).child(1))
+        );
+        assert_eq!(info.find_path("n1/gpu"), Some(root.child(1).child(0)));
+        assert_eq!(info.find_path(""), Some(root));
+        assert_eq!(info.find_path("n1/cpu"), None);
+        assert_eq!(info.find_path("n0/cpu/cpu0/x"), None);
+    }
+
+    #[test]
+    fn test_integrate_empty() {
+        let interval = Interval::new(Timestamp(0), Timestamp(10));
+        assert_eq!(integrate_points(&[], interval), 0.0);
+        assert_eq!(integrate_points(&[point(5, 1.0)], interval), 0.0);
+    }
 }
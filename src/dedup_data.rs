@@ -0,0 +1,549 @@
+use std::collections::BTreeMap;
+
+use crate::data::{DataSourceDescription, EntryID, ItemMetaRequest, TileID};
+use crate::deferred_data::{
+    CANCELLED, DataSourceInfoResult, DeferredDataSource, ItemsMetaResponse, RequestPriority,
+    SlotMetaTileResponse, SlotTileResponse, SummaryTileResponse, TileKind, TileRequest,
+    TileResponse, TransferStats,
+};
+
+// Coalesces requests for a tile that is already being fetched (e.g., by the
+// summary and slot panels after a resize) into the outstanding request, and
+// answers all of them when its response arrives. A full tile has everything a
+// partial one would, so partial requests also join an outstanding full
+// request for the same tile. Callers still get one response per request.
+//
+// A request that is more urgent than the one it would join is sent again at
+// its own priority, so that it doesn't wait behind prefetches. Whichever
+// response comes back first answers every caller, and the rest are dropped.
+pub struct DedupDeferredDataSource<T: DeferredDataSource> {
+    data_source: T,
+    // Number of callers waiting on each outstanding request
+    waiting: BTreeMap<(TileKind, TileRequest), usize>,
+    // Number of partial requests waiting on each outstanding full request
+    partial: BTreeMap<(TileKind, TileRequest), usize>,
+    // Highest priority each outstanding request was sent at, and how many
+    // times it was sent
+    sent: BTreeMap<(TileKind, TileRequest), (RequestPriority, usize)>,
+    // Responses still to come for requests that were already answered
+    stale: BTreeMap<(TileKind, TileRequest), usize>,
+    coalesced: u64,
+    // Responses for duplicates that were cancelled while the request they
+    // joined is still wanted by someone else
+    summary_tiles: Vec<SummaryTileResponse>,
+    slot_tiles: Vec<SlotTileResponse>,
+    slot_meta_tiles: Vec<SlotMetaTileResponse>,
+}
+
+impl<T: DeferredDataSource> DedupDeferredDataSource<T> {
+    pub fn new(data_source: T) -> Self {
+        Self {
+            data_source,
+            waiting: BTreeMap::new(),
+            partial: BTreeMap::new(),
+            sent: BTreeMap::new(),
+            stale: BTreeMap::new(),
+            coalesced: 0,
+            summary_tiles: Vec::new(),
+            slot_tiles: Vec::new(),
+            slot_meta_tiles: Vec::new(),
+        }
+    }
+
+    pub fn data_source(&self) -> &T {
+        &self.data_source
+    }
+
+    // Requests that were answered by another outstanding request, instead of
+    // being sent to the data source
+    pub fn coalesced(&self) -> u64 {
+        self.coalesced
+    }
+
+    // Whether an outstanding request was sent at the given priority or
+    // higher, so that joining it doesn't delay the caller
+    fn sent_at(&self, key: &(TileKind, TileRequest), priority: RequestPriority) -> bool {
+        self.sent
+            .get(key)
+            .is_some_and(|(sent, _)| *sent >= priority)
+    }
+
+    // Returns true if the request needs to be sent to the data source
+    fn start(&mut self, kind: TileKind, req: TileRequest, priority: RequestPriority) -> bool {
+        if !req.full {
+            let full = (kind, req.as_full());
+            if self.waiting.contains_key(&full) && self.sent_at(&full, priority) {
+                *self.partial.entry(full).or_default() += 1;
+                self.coalesced += 1;
+                return false;
+            }
+        }
+        let key = (kind, req);
+        let joined = self.sent_at(&key, priority);
+        match self.waiting.get_mut(&key) {
+            Some(count) => {
+                *count += 1;
+                if joined {
+                    self.coalesced += 1;
+                    return false;
+                }
+            }
+            None => {
+                self.waiting.insert(key.clone(), 1);
+            }
+        }
+        let (sent, times) = self.sent.entry(key).or_insert((priority, 0));
+        *sent = (*sent).max(priority);
+        *times += 1;
+        true
+    }
+
+    fn fan_out<V: Clone>(
+        &mut self,
+        kind: TileKind,
+        responses: Vec<TileResponse<V>>,
+    ) -> Vec<TileResponse<V>> {
+        let mut result = Vec::new();
+        for (tile, req) in responses {
+            let key = (kind, req.clone());
+            if let Some(stale) = self.stale.get_mut(&key) {
+                *stale -= 1;
+                if *stale == 0 {
+                    self.stale.remove(&key);
+                }
+                continue;
+            }
+            if let Some((_, times)) = self.sent.remove(&key) {
+                if times > 1 {
+                    self.stale.insert(key.clone(), times - 1);
+                }
+            }
+            // Zero if every caller has cancelled, but partial requests that
+            // joined it are still waiting
+            let count = self.waiting.remove(&key).unwrap_or(1);
+            if req.full {
+                let partial = self.partial.remove(&(kind, req.clone())).unwrap_or(0);
+                for _ in 0..partial {
+                    result.push((tile.clone(), req.as_partial()));
+                }
+            }
+            for _ in 1..count {
+                result.push((tile.clone(), req.clone()));
+            }
+            if count > 0 {
+                result.push((tile, req));
+            }
+        }
+        result
+    }
+
+    fn push_cancelled(&mut self, kind: TileKind, req: TileRequest) {
+        let cancelled = CANCELLED.to_owned();
+        match kind {
+            TileKind::Summary => self.summary_tiles.push((Err(cancelled), req)),
+            TileKind::Slot => self.slot_tiles.push((Err(cancelled), req)),
+            TileKind::SlotMeta => self.slot_meta_tiles.push((Err(cancelled), req)),
+        }
+    }
+
+    // Returns true if the request itself should be cancelled, because no one
+    // else is waiting on it
+    fn cancel_tile(&mut self, kind: TileKind, req: TileRequest) -> bool {
+        if !req.full {
+            let full = (kind, req.as_full());
+            if let Some(partial) = self.partial.get_mut(&full) {
+                *partial -= 1;
+                if *partial == 0 {
+                    self.partial.remove(&full);
+                }
+                self.push_cancelled(kind, req);
+                let partial = self.partial.get(&full).copied().unwrap_or(0);
+                return partial == 0 && self.waiting.get(&full) == Some(&0);
+            }
+        }
+
+        let key = (kind, req.clone());
+        let partial = self.partial.get(&key).copied().unwrap_or(0);
+        match self.waiting.get_mut(&key) {
+            Some(count) if *count > 1 || (*count == 1 && partial > 0) => {
+                *count -= 1;
+                self.push_cancelled(kind, req);
+                false
+            }
+            Some(1) => true,
+            _ => false,
+        }
+    }
+
+    fn fetch_tile(
+        &mut self,
+        kind: TileKind,
+        entry_id: &EntryID,
+        tile_id: TileID,
+        full: bool,
+        priority: RequestPriority,
+    ) {
+        let req = TileRequest {
+            entry_id: entry_id.clone(),
+            tile_id,
+            full,
+        };
+        if !self.start(kind, req, priority) {
+            return;
+        }
+        match kind {
+            TileKind::Summary => self
+                .data_source
+                .fetch_summary_tile(entry_id, tile_id, full, priority),
+            TileKind::Slot => self
+                .data_source
+                .fetch_slot_tile(entry_id, tile_id, full, priority),
+            TileKind::SlotMeta => self
+                .data_source
+                .fetch_slot_meta_tile(entry_id, tile_id, full, priority),
+        }
+    }
+}
+
+impl<T: DeferredDataSource> DeferredDataSource for DedupDeferredDataSource<T> {
+    fn fetch_description(&self) -> DataSourceDescription {
+        self.data_source.fetch_description()
+    }
+
+    fn fetch_info(&mut self) {
+        self.data_source.fetch_info()
+    }
+
+    fn get_infos(&mut self) -> Vec<DataSourceInfoResult> {
+        self.data_source.get_infos()
+    }
+
+    fn fetch_summary_tile(
+        &mut self,
+        entry_id: &EntryID,
+        tile_id: TileID,
+        full: bool,
+        priority: RequestPriority,
+    ) {
+        self.fetch_tile(TileKind::Summary, entry_id, tile_id, full, priority)
+    }
+
+    fn get_summary_tiles(&mut self) -> Vec<SummaryTileResponse> {
+        let result = self.data_source.get_summary_tiles();
+        let mut result = self.fan_out(TileKind::Summary, result);
+        result.append(&mut self.summary_tiles);
+        result
+    }
+
+    fn fetch_slot_tile(
+        &mut self,
+        entry_id: &EntryID,
+        tile_id: TileID,
+        full: bool,
+        priority: RequestPriority,
+    ) {
+        self.fetch_tile(TileKind::Slot, entry_id, tile_id, full, priority)
+    }
+
+    fn get_slot_tiles(&mut self) -> Vec<SlotTileResponse> {
+        let result = self.data_source.get_slot_tiles();
+        let mut result = self.fan_out(TileKind::Slot, result);
+        result.append(&mut self.slot_tiles);
+        result
+    }
+
+    fn fetch_slot_meta_tile(
+        &mut self,
+        entry_id: &EntryID,
+        tile_id: TileID,
+        full: bool,
+        priority: RequestPriority,
+    ) {
+        self.fetch_tile(TileKind::SlotMeta, entry_id, tile_id, full, priority)
+    }
+
+    fn get_slot_meta_tiles(&mut self) -> Vec<SlotMetaTileResponse> {
+        let result = self.data_source.get_slot_meta_tiles();
+        let mut result = self.fan_out(TileKind::SlotMeta, result);
+        result.append(&mut self.slot_meta_tiles);
+        result
+    }
+
+    // Item requests are for whatever is selected at the time, so they rarely
+    // repeat and pass straight through
+    fn fetch_items_meta(&mut self, requests: &[ItemMetaRequest]) {
+        self.data_source.fetch_items_meta(requests)
+    }
+
+    fn get_items_meta(&mut self) -> Vec<ItemsMetaResponse> {
+        self.data_source.get_items_meta()
+    }
+
+    fn fetch_tiles(&mut self, kind: TileKind, requests: &[TileRequest], priority: RequestPriority) {
+        // Duplicates within the batch are coalesced too
+        let requests: Vec<_> = requests
+            .iter()
+            .filter(|req| self.start(kind, (*req).clone(), priority))
+            .cloned()
+            .collect();
+        if !requests.is_empty() {
+            self.data_source.fetch_tiles(kind, &requests, priority)
+        }
+    }
+
+    fn cancel(&mut self, entry_id: &EntryID, tile_id: TileID, full: bool) {
+        let req = TileRequest {
+            entry_id: entry_id.clone(),
+            tile_id,
+            full,
+        };
+        // Requests are identical, so there is no telling which caller
+        // cancelled. While others still want the tile, one of them is
+        // answered as cancelled and the request goes on; the last one
+        // cancels the request itself.
+        let mut forward = false;
+        let mut forward_full = false;
+        for kind in [TileKind::Summary, TileKind::Slot, TileKind::SlotMeta] {
+            // A partial request may have joined a full one
+            let joined = !full && self.partial.contains_key(&(kind, req.as_full()));
+            if self.cancel_tile(kind, req.clone()) {
+                if joined {
+                    forward_full = true;
+                } else {
+                    forward = true;
+                }
+            }
+        }
+        if forward {
+            self.data_source.cancel(entry_id, tile_id, full);
+        }
+        if forward_full {
+            self.data_source.cancel(entry_id, tile_id, true);
+        }
+    }
+
+    fn transfer_stats(&self) -> Option<TransferStats> {
+        self.data_source.transfer_stats()
+    }
+
+    fn retries_failures(&self) -> bool {
+        self.data_source.retries_failures()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::data::{SummaryTile, SummaryTileData};
+    use crate::timestamp::{Interval, Timestamp};
+
+    // Answers summary tiles only when asked to, counting the requests it got
+    #[derive(Default)]
+    struct CountingSource {
+        requests: Vec<TileRequest>,
+        priorities: Vec<RequestPriority>,
+        cancelled: Vec<TileRequest>,
+    }
+
+    impl CountingSource {
+        fn respond(&mut self) -> Vec<SummaryTileResponse> {
+            std::mem::take(&mut self.requests)
+                .into_iter()
+                .map(|req| {
+                    let tile = SummaryTile {
+                        entry_id: req.entry_id.clone(),
+                        tile_id: req.tile_id,
+                        data: SummaryTileData {
+                            utilization: Vec::new(),
+                        },
+                    };
+                    (Ok(tile), req)
+                })
+                .collect()
+        }
+    }
+
+    impl DeferredDataSource for CountingSource {
+        fn fetch_description(&self) -> DataSourceDescription {
+            unimplemented!()
+        }
+        fn fetch_info(&mut self) {
+            unimplemented!()
+        }
+        fn get_infos(&mut self) -> Vec<DataSourceInfoResult> {
+            unimplemented!()
+        }
+        fn fetch_summary_tile(
+            &mut self,
+            entry_id: &EntryID,
+            tile_id: TileID,
+            full: bool,
+            priority: RequestPriority,
+        ) {
+            self.requests.push(TileRequest {
+                entry_id: entry_id.clone(),
+                tile_id,
+                full,
+            });
+            self.priorities.push(priority);
+        }
+        fn get_summary_tiles(&mut self) -> Vec<SummaryTileResponse> {
+            Vec::new()
+        }
+        fn fetch_slot_tile(&mut self, _: &EntryID, _: TileID, _: bool, _priority: RequestPriority) {
+            unimplemented!()
+        }
+        fn get_slot_tiles(&mut self) -> Vec<SlotTileResponse> {
+            Vec::new()
+        }
+        fn fetch_slot_meta_tile(
+            &mut self,
+            _: &EntryID,
+            _: TileID,
+            _: bool,
+            _priority: RequestPriority,
+        ) {
+            unimplemented!()
+        }
+        fn get_slot_meta_tiles(&mut self) -> Vec<SlotMetaTileResponse> {
+            Vec::new()
+        }
+        fn fetch_items_meta(&mut self, _: &[ItemMetaRequest]) {
+            unimplemented!()
+        }
+        fn get_items_meta(&mut self) -> Vec<ItemsMetaResponse> {
+            unimplemented!()
+        }
+        fn cancel(&mut self, entry_id: &EntryID, tile_id: TileID, full: bool) {
+            self.cancelled.push(TileRequest {
+                entry_id: entry_id.clone(),
+                tile_id,
+                full,
+            });
+        }
+    }
+
+    #[test]
+    fn test_dedup() {
+        let entry_id = EntryID::root().summary();
+        let tile_id = TileID(Interval::new(Timestamp(0), Timestamp(100)));
+        let req = TileRequest {
+            entry_id: entry_id.clone(),
+            tile_id,
+            full: false,
+        };
+        let mut dedup = DedupDeferredDataSource::new(CountingSource::default());
+
+        // Three requests for the same tile go out as one, and all three are
+        // answered
+        dedup.fetch_summary_tile(&entry_id, tile_id, false, RequestPriority::Visible);
+        dedup.fetch_tiles(
+            TileKind::Summary,
+            &[req.clone(), req.clone()],
+            RequestPriority::Visible,
+        );
+        assert_eq!(dedup.data_source.requests.len(), 1);
+        assert_eq!(dedup.coalesced(), 2);
+        let responses = dedup.data_source.respond();
+        let responses = dedup.fan_out(TileKind::Summary, responses);
+        assert_eq!(responses.len(), 3);
+        assert!(responses.iter().all(|(tile, r)| tile.is_ok() && *r == req));
+
+        // Once answered, the tile is requested again
+        dedup.fetch_summary_tile(&entry_id, tile_id, true, RequestPriority::Visible);
+        dedup.fetch_summary_tile(&entry_id, tile_id, true, RequestPriority::Visible);
+        assert_eq!(dedup.data_source.requests.len(), 1);
+
+        // Cancelling one of two answers it alone, and the last one cancels
+        // the request
+        dedup.cancel(&entry_id, tile_id, true);
+        assert!(dedup.data_source.cancelled.is_empty());
+        let responses = dedup.get_summary_tiles();
+        assert_eq!(responses.len(), 1);
+        assert_eq!(responses[0].0.as_ref().unwrap_err(), CANCELLED);
+        dedup.cancel(&entry_id, tile_id, true);
+        assert_eq!(dedup.data_source.cancelled.len(), 1);
+    }
+
+    #[test]
+    fn test_dedup_partial() {
+        let entry_id = EntryID::root().summary();
+        let tile_id = TileID(Interval::new(Timestamp(0), Timestamp(100)));
+        let mut dedup = DedupDeferredDataSource::new(CountingSource::default());
+
+        // A partial request joins the outstanding full one, and is answered
+        // by it
+        dedup.fetch_summary_tile(&entry_id, tile_id, true, RequestPriority::Visible);
+        dedup.fetch_summary_tile(&entry_id, tile_id, false, RequestPriority::Visible);
+        assert_eq!(dedup.data_source.requests.len(), 1);
+        assert_eq!(dedup.coalesced(), 1);
+        let responses = dedup.data_source.respond();
+        let responses = dedup.fan_out(TileKind::Summary, responses);
+        assert_eq!(responses.len(), 2);
+        assert_eq!(responses.iter().filter(|(_, req)| req.full).count(), 1);
+
+        // Cancelling the full request leaves it going for the partial one
+        dedup.fetch_summary_tile(&entry_id, tile_id, true, RequestPriority::Visible);
+        dedup.fetch_summary_tile(&entry_id, tile_id, false, RequestPriority::Visible);
+        dedup.cancel(&entry_id, tile_id, true);
+        assert!(dedup.data_source.cancelled.is_empty());
+        let responses = dedup.get_summary_tiles();
+        assert_eq!(responses.len(), 1);
+        assert!(responses[0].1.full);
+
+        // Until the partial one is cancelled too
+        dedup.cancel(&entry_id, tile_id, false);
+        assert_eq!(dedup.data_source.cancelled.len(), 1);
+        assert!(dedup.data_source.cancelled[0].full);
+        let responses = dedup.get_summary_tiles();
+        assert_eq!(responses.len(), 1);
+        assert!(!responses[0].1.full);
+
+        // Nothing is left to answer once the request comes back
+        let responses = dedup.data_source.respond();
+        assert!(dedup.fan_out(TileKind::Summary, responses).is_empty());
+        assert!(dedup.waiting.is_empty() && dedup.partial.is_empty());
+        assert!(dedup.sent.is_empty());
+    }
+
+    #[test]
+    fn test_dedup_priority() {
+        let entry_id = EntryID::root().summary();
+        let tile_id = TileID(Interval::new(Timestamp(0), Timestamp(100)));
+        let mut dedup = DedupDeferredDataSource::new(CountingSource::default());
+
+        // A less urgent request joins a more urgent one
+        dedup.fetch_summary_tile(&entry_id, tile_id, false, RequestPriority::Visible);
+        dedup.fetch_summary_tile(&entry_id, tile_id, false, RequestPriority::Prefetch);
+        assert_eq!(dedup.data_source.priorities, vec![RequestPriority::Visible]);
+        let responses = dedup.data_source.respond();
+        assert_eq!(dedup.fan_out(TileKind::Summary, responses).len(), 2);
+        dedup.data_source.priorities.clear();
+
+        // But a more urgent one doesn't wait behind a prefetch, including a
+        // partial request that would otherwise join a full one
+        dedup.fetch_summary_tile(&entry_id, tile_id, true, RequestPriority::Prefetch);
+        dedup.fetch_summary_tile(&entry_id, tile_id, false, RequestPriority::Visible);
+        dedup.fetch_summary_tile(&entry_id, tile_id, true, RequestPriority::Visible);
+        assert_eq!(
+            dedup.data_source.priorities,
+            vec![
+                RequestPriority::Prefetch,
+                RequestPriority::Visible,
+                RequestPriority::Visible
+            ]
+        );
+        assert_eq!(dedup.coalesced(), 1);
+
+        // The first response for each tile answers everyone waiting on it,
+        // and the duplicate is dropped
+        let mut responses = dedup.data_source.respond();
+        let duplicate = responses.remove(0);
+        let responses = dedup.fan_out(TileKind::Summary, responses);
+        assert_eq!(responses.len(), 3);
+        assert_eq!(responses.iter().filter(|(_, req)| req.full).count(), 2);
+        assert!(dedup.fan_out(TileKind::Summary, vec![duplicate]).is_empty());
+        assert!(dedup.waiting.is_empty() && dedup.sent.is_empty() && dedup.stale.is_empty());
+    }
+}
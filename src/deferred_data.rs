@@ -1,19 +1,86 @@
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::fmt;
 use std::num::NonZeroUsize;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Weak};
+use std::time::Duration;
 
+use log::{debug, trace};
 use lru::LruCache;
+use serde::{Deserialize, Serialize};
 
 use crate::data::{
-    DataSource, DataSourceDescription, DataSourceInfo, EntryID, SlotMetaTile, SlotTile,
-    SummaryTile, TileID,
+    DataSource, DataSourceDescription, DataSourceInfo, EntryID, EntryIDSlug, Field, Item, ItemMeta,
+    ItemMetaRequest, SlotMetaTile, SlotTile, SummaryTile, TileID, TileIDSlug, UtilPoint,
 };
+use crate::http::schema::{ProfileIndex, ProfileIndexEntry};
+use crate::timestamp::Interval;
 
-#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Deserialize, Serialize)]
 pub struct TileRequest {
     pub entry_id: EntryID,
     pub tile_id: TileID,
     pub full: bool,
 }
 
+impl TileRequest {
+    // The same tile with all of its data
+    pub fn as_full(&self) -> Self {
+        Self {
+            full: true,
+            ..self.clone()
+        }
+    }
+
+    // The same tile, possibly with only part of its data
+    pub fn as_partial(&self) -> Self {
+        Self {
+            full: false,
+            ..self.clone()
+        }
+    }
+}
+
+// For logs, in the same form as the tile's URL, e.g., "0_1_2/0_1000 (full)"
+impl fmt::Display for TileRequest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}/{}",
+            EntryIDSlug(&self.entry_id),
+            TileIDSlug(self.tile_id)
+        )?;
+        if self.full {
+            write!(f, " (full)")?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Deserialize, Serialize)]
+pub enum TileKind {
+    Summary,
+    Slot,
+    SlotMeta,
+}
+
+// How urgently a tile is needed. Data sources that queue requests should
+// serve higher priorities first, but are free to ignore this.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum RequestPriority {
+    // Not needed for display (e.g., search, export)
+    Background,
+    // Likely to be needed soon (e.g., adjacent to the view)
+    Prefetch,
+    // Needed to draw what is currently on screen
+    #[default]
+    Visible,
+}
+
+pub type DataSourceInfoResult = Result<DataSourceInfo, String>;
+
 pub type TileResult<T> = Result<T, String>;
 pub type TileResponse<T> = (TileResult<T>, TileRequest);
 
@@ -25,24 +92,163 @@ pub type SummaryTileResponse = TileResponse<SummaryTile>;
 pub type SlotTileResponse = TileResponse<SlotTile>;
 pub type SlotMetaTileResponse = TileResponse<SlotMetaTile>;
 
+pub type ItemsMetaResult = Result<Vec<ItemMeta>, String>;
+pub type ItemsMetaResponse = (ItemsMetaResult, Vec<ItemMetaRequest>);
+
+// A listing of profiles, fetched in the background the same way as a data
+// source's info, from which the user picks the profiles to open
+pub trait DeferredProfileIndex {
+    fn fetch_description(&self) -> DataSourceDescription;
+    fn fetch_index(&mut self);
+    fn get_index(&mut self) -> Option<Result<ProfileIndex, String>>;
+    fn open(&self, profile: &ProfileIndexEntry) -> Result<Box<dyn DeferredDataSource>, String>;
+}
+
 pub trait DeferredDataSource {
     fn fetch_description(&self) -> DataSourceDescription;
     fn fetch_info(&mut self);
-    fn get_infos(&mut self) -> Vec<DataSourceInfo>;
-    fn fetch_summary_tile(&mut self, entry_id: &EntryID, tile_id: TileID, full: bool);
+    fn get_infos(&mut self) -> Vec<DataSourceInfoResult>;
+    fn fetch_summary_tile(
+        &mut self,
+        entry_id: &EntryID,
+        tile_id: TileID,
+        full: bool,
+        priority: RequestPriority,
+    );
     fn get_summary_tiles(&mut self) -> Vec<SummaryTileResponse>;
-    fn fetch_slot_tile(&mut self, entry_id: &EntryID, tile_id: TileID, full: bool);
+    fn fetch_slot_tile(
+        &mut self,
+        entry_id: &EntryID,
+        tile_id: TileID,
+        full: bool,
+        priority: RequestPriority,
+    );
     fn get_slot_tiles(&mut self) -> Vec<SlotTileResponse>;
-    fn fetch_slot_meta_tile(&mut self, entry_id: &EntryID, tile_id: TileID, full: bool);
+    fn fetch_slot_meta_tile(
+        &mut self,
+        entry_id: &EntryID,
+        tile_id: TileID,
+        full: bool,
+        priority: RequestPriority,
+    );
     fn get_slot_meta_tiles(&mut self) -> Vec<SlotMetaTileResponse>;
+    fn fetch_items_meta(&mut self, requests: &[ItemMetaRequest]);
+    fn get_items_meta(&mut self) -> Vec<ItemsMetaResponse>;
+    // Request many tiles of one kind at once. Responses still arrive one per
+    // tile through the corresponding get_* method, so sources that can save
+    // round trips by batching should override this.
+    fn fetch_tiles(&mut self, kind: TileKind, requests: &[TileRequest], priority: RequestPriority) {
+        for req in requests {
+            let TileRequest {
+                entry_id,
+                tile_id,
+                full,
+            } = req;
+            match kind {
+                TileKind::Summary => self.fetch_summary_tile(entry_id, *tile_id, *full, priority),
+                TileKind::Slot => self.fetch_slot_tile(entry_id, *tile_id, *full, priority),
+                TileKind::SlotMeta => {
+                    self.fetch_slot_meta_tile(entry_id, *tile_id, *full, priority)
+                }
+            }
+        }
+    }
+    // Abandon any outstanding summary, slot or slot meta tile requests for
+    // the given tile. This is only a hint: every request still gets exactly
+    // one response, which may be an error (CANCELLED) or the tile itself if
+    // it was too late to stop it.
+    fn cancel(&mut self, entry_id: &EntryID, tile_id: TileID, full: bool);
+    // How the source is faring over the network, for sources that use one
+    fn transfer_stats(&self) -> Option<TransferStats> {
+        None
+    }
+    // Whether the source retries failed requests itself (as sources over a
+    // network do, since they know which errors are worth another attempt),
+    // so that the viewer doesn't retry them again on top of that
+    fn retries_failures(&self) -> bool {
+        false
+    }
+}
+
+// Totals for the requests a source has made over the network. Unlike the
+// metrics kept in the viewer, these cover only the transfer itself, from
+// sending the request to decoding the response.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct TransferStats {
+    pub requests: u64,
+    pub errors: u64,
+    // As received, before decompression (but after any Content-Encoding)
+    pub bytes: u64,
+    pub total_latency: Duration,
+    pub max_latency: Duration,
+    // Decompressing and deserializing responses
+    pub decode_time: Duration,
+}
+
+impl TransferStats {
+    pub fn mean_latency(&self) -> Option<Duration> {
+        (self.requests > 0).then(|| self.total_latency / self.requests as u32)
+    }
+
+    pub fn merge(&mut self, other: &TransferStats) {
+        self.requests += other.requests;
+        self.errors += other.errors;
+        self.bytes += other.bytes;
+        self.total_latency += other.total_latency;
+        self.max_latency = self.max_latency.max(other.max_latency);
+        self.decode_time += other.decode_time;
+    }
+}
+
+pub const CANCELLED: &str = "request cancelled";
+
+#[derive(Debug, Clone, Default)]
+pub struct CancelFlag(Arc<AtomicBool>);
+
+impl CancelFlag {
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+// Cancellation flags for requests being processed in the background. The
+// task processing a request holds its flag; once the task is done, the flag
+// is dropped and forgotten here.
+#[derive(Debug, Default)]
+pub struct CancelFlags {
+    flags: BTreeMap<TileRequest, Vec<Weak<AtomicBool>>>,
+}
+
+impl CancelFlags {
+    pub fn start(&mut self, req: TileRequest) -> CancelFlag {
+        self.flags.retain(|_, flags| {
+            flags.retain(|flag| flag.strong_count() > 0);
+            !flags.is_empty()
+        });
+        let flag = CancelFlag::default();
+        self.flags
+            .entry(req)
+            .or_default()
+            .push(Arc::downgrade(&flag.0));
+        flag
+    }
+
+    pub fn cancel(&mut self, req: &TileRequest) {
+        for flag in self.flags.remove(req).into_iter().flatten() {
+            if let Some(flag) = flag.upgrade() {
+                flag.store(true, Ordering::Relaxed);
+            }
+        }
+    }
 }
 
 pub struct DeferredDataSourceWrapper<T: DataSource> {
     data_source: T,
-    infos: Vec<DataSourceInfo>,
+    infos: Vec<DataSourceInfoResult>,
     summary_tiles: Vec<SummaryTileResponse>,
     slot_tiles: Vec<SlotTileResponse>,
     slot_meta_tiles: Vec<SlotMetaTileResponse>,
+    items_meta: Vec<ItemsMetaResponse>,
 }
 
 impl<T: DataSource> DeferredDataSourceWrapper<T> {
@@ -53,6 +259,7 @@ impl<T: DataSource> DeferredDataSourceWrapper<T> {
             summary_tiles: Vec::new(),
             slot_tiles: Vec::new(),
             slot_meta_tiles: Vec::new(),
+            items_meta: Vec::new(),
         }
     }
 }
@@ -63,16 +270,23 @@ impl<T: DataSource> DeferredDataSource for DeferredDataSourceWrapper<T> {
     }
 
     fn fetch_info(&mut self) {
-        self.infos.push(self.data_source.fetch_info());
+        self.infos.push(self.data_source.try_fetch_info());
     }
 
-    fn get_infos(&mut self) -> Vec<DataSourceInfo> {
+    fn get_infos(&mut self) -> Vec<DataSourceInfoResult> {
         std::mem::take(&mut self.infos)
     }
 
-    fn fetch_summary_tile(&mut self, entry_id: &EntryID, tile_id: TileID, full: bool) {
+    fn fetch_summary_tile(
+        &mut self,
+        entry_id: &EntryID,
+        tile_id: TileID,
+        full: bool,
+        _priority: RequestPriority,
+    ) {
         self.summary_tiles.push((
-            Ok(self.data_source.fetch_summary_tile(entry_id, tile_id, full)),
+            self.data_source
+                .try_fetch_summary_tile(entry_id, tile_id, full),
             TileRequest {
                 entry_id: entry_id.clone(),
                 tile_id,
@@ -85,9 +299,16 @@ impl<T: DataSource> DeferredDataSource for DeferredDataSourceWrapper<T> {
         std::mem::take(&mut self.summary_tiles)
     }
 
-    fn fetch_slot_tile(&mut self, entry_id: &EntryID, tile_id: TileID, full: bool) {
+    fn fetch_slot_tile(
+        &mut self,
+        entry_id: &EntryID,
+        tile_id: TileID,
+        full: bool,
+        _priority: RequestPriority,
+    ) {
         self.slot_tiles.push((
-            Ok(self.data_source.fetch_slot_tile(entry_id, tile_id, full)),
+            self.data_source
+                .try_fetch_slot_tile(entry_id, tile_id, full),
             TileRequest {
                 entry_id: entry_id.clone(),
                 tile_id,
@@ -100,11 +321,16 @@ impl<T: DataSource> DeferredDataSource for DeferredDataSourceWrapper<T> {
         std::mem::take(&mut self.slot_tiles)
     }
 
-    fn fetch_slot_meta_tile(&mut self, entry_id: &EntryID, tile_id: TileID, full: bool) {
+    fn fetch_slot_meta_tile(
+        &mut self,
+        entry_id: &EntryID,
+        tile_id: TileID,
+        full: bool,
+        _priority: RequestPriority,
+    ) {
         self.slot_meta_tiles.push((
-            Ok(self
-                .data_source
-                .fetch_slot_meta_tile(entry_id, tile_id, full)),
+            self.data_source
+                .try_fetch_slot_meta_tile(entry_id, tile_id, full),
             TileRequest {
                 entry_id: entry_id.clone(),
                 tile_id,
@@ -116,6 +342,21 @@ impl<T: DataSource> DeferredDataSource for DeferredDataSourceWrapper<T> {
     fn get_slot_meta_tiles(&mut self) -> Vec<SlotMetaTileResponse> {
         std::mem::take(&mut self.slot_meta_tiles)
     }
+
+    fn fetch_items_meta(&mut self, requests: &[ItemMetaRequest]) {
+        self.items_meta.push((
+            self.data_source.try_fetch_items_meta(requests),
+            requests.to_vec(),
+        ));
+    }
+
+    fn get_items_meta(&mut self) -> Vec<ItemsMetaResponse> {
+        std::mem::take(&mut self.items_meta)
+    }
+
+    fn cancel(&mut self, _entry_id: &EntryID, _tile_id: TileID, _full: bool) {
+        // Requests complete immediately, so there is never anything to cancel
+    }
 }
 
 pub struct CountingDeferredDataSource<T: DeferredDataSource> {
@@ -135,6 +376,14 @@ impl<T: DeferredDataSource> CountingDeferredDataSource<T> {
         self.outstanding_requests
     }
 
+    pub fn data_source(&self) -> &T {
+        &self.data_source
+    }
+
+    pub fn data_source_mut(&mut self) -> &mut T {
+        &mut self.data_source
+    }
+
     fn start_request(&mut self) {
         self.outstanding_requests += 1;
     }
@@ -157,14 +406,21 @@ impl<T: DeferredDataSource> DeferredDataSource for CountingDeferredDataSource<T>
         self.data_source.fetch_info()
     }
 
-    fn get_infos(&mut self) -> Vec<DataSourceInfo> {
+    fn get_infos(&mut self) -> Vec<DataSourceInfoResult> {
         let result = self.data_source.get_infos();
         self.finish_request(result)
     }
 
-    fn fetch_summary_tile(&mut self, entry_id: &EntryID, tile_id: TileID, full: bool) {
+    fn fetch_summary_tile(
+        &mut self,
+        entry_id: &EntryID,
+        tile_id: TileID,
+        full: bool,
+        priority: RequestPriority,
+    ) {
         self.start_request();
-        self.data_source.fetch_summary_tile(entry_id, tile_id, full)
+        self.data_source
+            .fetch_summary_tile(entry_id, tile_id, full, priority)
     }
 
     fn get_summary_tiles(&mut self) -> Vec<SummaryTileResponse> {
@@ -172,9 +428,16 @@ impl<T: DeferredDataSource> DeferredDataSource for CountingDeferredDataSource<T>
         self.finish_request(result)
     }
 
-    fn fetch_slot_tile(&mut self, entry_id: &EntryID, tile_id: TileID, full: bool) {
+    fn fetch_slot_tile(
+        &mut self,
+        entry_id: &EntryID,
+        tile_id: TileID,
+        full: bool,
+        priority: RequestPriority,
+    ) {
         self.start_request();
-        self.data_source.fetch_slot_tile(entry_id, tile_id, full)
+        self.data_source
+            .fetch_slot_tile(entry_id, tile_id, full, priority)
     }
 
     fn get_slot_tiles(&mut self) -> Vec<SlotTileResponse> {
@@ -182,16 +445,49 @@ impl<T: DeferredDataSource> DeferredDataSource for CountingDeferredDataSource<T>
         self.finish_request(result)
     }
 
-    fn fetch_slot_meta_tile(&mut self, entry_id: &EntryID, tile_id: TileID, full: bool) {
+    fn fetch_slot_meta_tile(
+        &mut self,
+        entry_id: &EntryID,
+        tile_id: TileID,
+        full: bool,
+        priority: RequestPriority,
+    ) {
         self.start_request();
         self.data_source
-            .fetch_slot_meta_tile(entry_id, tile_id, full)
+            .fetch_slot_meta_tile(entry_id, tile_id, full, priority)
     }
 
     fn get_slot_meta_tiles(&mut self) -> Vec<SlotMetaTileResponse> {
         let result = self.data_source.get_slot_meta_tiles();
         self.finish_request(result)
     }
+
+    fn fetch_items_meta(&mut self, requests: &[ItemMetaRequest]) {
+        self.start_request();
+        self.data_source.fetch_items_meta(requests)
+    }
+
+    fn get_items_meta(&mut self) -> Vec<ItemsMetaResponse> {
+        let result = self.data_source.get_items_meta();
+        self.finish_request(result)
+    }
+
+    fn fetch_tiles(&mut self, kind: TileKind, requests: &[TileRequest], priority: RequestPriority) {
+        self.outstanding_requests += requests.len() as u64;
+        self.data_source.fetch_tiles(kind, requests, priority)
+    }
+
+    fn cancel(&mut self, entry_id: &EntryID, tile_id: TileID, full: bool) {
+        self.data_source.cancel(entry_id, tile_id, full)
+    }
+
+    fn transfer_stats(&self) -> Option<TransferStats> {
+        self.data_source.transfer_stats()
+    }
+
+    fn retries_failures(&self) -> bool {
+        self.data_source.retries_failures()
+    }
 }
 
 pub struct LruDeferredDataSource<T: DeferredDataSource> {
@@ -227,11 +523,17 @@ impl<T: DeferredDataSource> DeferredDataSource for LruDeferredDataSource<T> {
         self.data_source.fetch_info()
     }
 
-    fn get_infos(&mut self) -> Vec<DataSourceInfo> {
+    fn get_infos(&mut self) -> Vec<DataSourceInfoResult> {
         self.data_source.get_infos()
     }
 
-    fn fetch_summary_tile(&mut self, entry_id: &EntryID, tile_id: TileID, full: bool) {
+    fn fetch_summary_tile(
+        &mut self,
+        entry_id: &EntryID,
+        tile_id: TileID,
+        full: bool,
+        priority: RequestPriority,
+    ) {
         let req = TileRequest {
             entry_id: entry_id.clone(),
             tile_id,
@@ -240,7 +542,8 @@ impl<T: DeferredDataSource> DeferredDataSource for LruDeferredDataSource<T> {
         if let Some(tile) = self.summary_cache.get(&req) {
             self.summary_tiles.push(tile.clone());
         } else {
-            self.data_source.fetch_summary_tile(entry_id, tile_id, full);
+            self.data_source
+                .fetch_summary_tile(entry_id, tile_id, full, priority);
         }
     }
 
@@ -253,7 +556,13 @@ impl<T: DeferredDataSource> DeferredDataSource for LruDeferredDataSource<T> {
         std::mem::take(&mut self.summary_tiles)
     }
 
-    fn fetch_slot_tile(&mut self, entry_id: &EntryID, tile_id: TileID, full: bool) {
+    fn fetch_slot_tile(
+        &mut self,
+        entry_id: &EntryID,
+        tile_id: TileID,
+        full: bool,
+        priority: RequestPriority,
+    ) {
         let req = TileRequest {
             entry_id: entry_id.clone(),
             tile_id,
@@ -262,7 +571,8 @@ impl<T: DeferredDataSource> DeferredDataSource for LruDeferredDataSource<T> {
         if let Some(tile) = self.slot_cache.get(&req) {
             self.slot_tiles.push(tile.clone());
         } else {
-            self.data_source.fetch_slot_tile(entry_id, tile_id, full);
+            self.data_source
+                .fetch_slot_tile(entry_id, tile_id, full, priority);
         }
     }
 
@@ -275,7 +585,13 @@ impl<T: DeferredDataSource> DeferredDataSource for LruDeferredDataSource<T> {
         std::mem::take(&mut self.slot_tiles)
     }
 
-    fn fetch_slot_meta_tile(&mut self, entry_id: &EntryID, tile_id: TileID, full: bool) {
+    fn fetch_slot_meta_tile(
+        &mut self,
+        entry_id: &EntryID,
+        tile_id: TileID,
+        full: bool,
+        priority: RequestPriority,
+    ) {
         let req = TileRequest {
             entry_id: entry_id.clone(),
             tile_id,
@@ -285,7 +601,7 @@ impl<T: DeferredDataSource> DeferredDataSource for LruDeferredDataSource<T> {
             self.slot_meta_tiles.push(tile.clone());
         } else {
             self.data_source
-                .fetch_slot_meta_tile(entry_id, tile_id, full);
+                .fetch_slot_meta_tile(entry_id, tile_id, full, priority);
         }
     }
 
@@ -297,6 +613,464 @@ impl<T: DeferredDataSource> DeferredDataSource for LruDeferredDataSource<T> {
         self.slot_meta_tiles.extend(result);
         std::mem::take(&mut self.slot_meta_tiles)
     }
+
+    fn fetch_items_meta(&mut self, requests: &[ItemMetaRequest]) {
+        self.data_source.fetch_items_meta(requests)
+    }
+
+    fn get_items_meta(&mut self) -> Vec<ItemsMetaResponse> {
+        self.data_source.get_items_meta()
+    }
+
+    fn cancel(&mut self, entry_id: &EntryID, tile_id: TileID, full: bool) {
+        self.data_source.cancel(entry_id, tile_id, full)
+    }
+
+    fn transfer_stats(&self) -> Option<TransferStats> {
+        self.data_source.transfer_stats()
+    }
+
+    fn retries_failures(&self) -> bool {
+        self.data_source.retries_failures()
+    }
+}
+
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub entries: usize,
+    pub bytes: usize,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+enum CachedTile {
+    Summary(SummaryTile),
+    Slot(SlotTile),
+    SlotMeta(SlotMetaTile),
+}
+
+// Rough estimates of the memory held by each kind of tile, for budgeting
+// and reporting purposes
+pub(crate) fn summary_tile_size(tile: &SummaryTile) -> usize {
+    size_of::<SummaryTile>() + tile.data.utilization.len() * size_of::<UtilPoint>()
+}
+
+pub(crate) fn slot_tile_size(tile: &SlotTile) -> usize {
+    let rows = &tile.data.items;
+    size_of::<SlotTile>()
+        + rows.len() * size_of::<Vec<Item>>()
+        + rows
+            .iter()
+            .map(|row| row.len() * size_of::<Item>())
+            .sum::<usize>()
+}
+
+pub(crate) fn item_meta_size(item: &ItemMeta) -> usize {
+    fn field_size(field: &Field) -> usize {
+        size_of::<Field>()
+            + match field {
+                Field::String(s) => s.len(),
+                Field::ItemLink(link) => link.title.len(),
+                Field::Vec(fields) => fields.iter().map(field_size).sum(),
+                _ => 0,
+            }
+    }
+
+    size_of::<ItemMeta>()
+        + item.title.len()
+        + item
+            .fields
+            .iter()
+            .map(|(_, field, _)| field_size(field))
+            .sum::<usize>()
+}
+
+pub(crate) fn slot_meta_tile_size(tile: &SlotMetaTile) -> usize {
+    let rows = &tile.data.items;
+    size_of::<SlotMetaTile>()
+        + rows.len() * size_of::<Vec<ItemMeta>>()
+        + rows.iter().flatten().map(item_meta_size).sum::<usize>()
+}
+
+impl CachedTile {
+    fn size(&self) -> usize {
+        match self {
+            CachedTile::Summary(tile) => summary_tile_size(tile),
+            CachedTile::Slot(tile) => slot_tile_size(tile),
+            CachedTile::SlotMeta(tile) => slot_meta_tile_size(tile),
+        }
+    }
+}
+
+// Contents of a tile cache, least recently used first, so that they can be
+// kept across restarts
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct CacheSnapshot {
+    tiles: Vec<(TileKind, TileRequest, CachedTile)>,
+}
+
+impl CacheSnapshot {
+    pub fn len(&self) -> usize {
+        self.tiles.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tiles.is_empty()
+    }
+}
+
+// Successful tile responses, up to a given size in bytes, after which the
+// least recently used tiles are evicted
+pub struct TileCache {
+    budget: usize,
+    cache: LruCache<(TileKind, TileRequest), (CachedTile, usize)>,
+    stats: CacheStats,
+}
+
+// Views of the same source can share one cache, so that tiles are only
+// downloaded and kept in memory once
+pub type SharedTileCache = Rc<RefCell<TileCache>>;
+
+impl TileCache {
+    pub fn new(budget: usize) -> Self {
+        Self {
+            budget,
+            cache: LruCache::unbounded(),
+            stats: CacheStats::default(),
+        }
+    }
+
+    pub fn shared(budget: usize) -> SharedTileCache {
+        Rc::new(RefCell::new(Self::new(budget)))
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        self.stats
+    }
+
+    pub fn budget(&self) -> usize {
+        self.budget
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cache.is_empty()
+    }
+
+    // Drops every cached tile, e.g., because the underlying data changed
+    pub fn clear(&mut self) {
+        self.cache.clear();
+        self.stats.bytes = 0;
+        self.stats.entries = 0;
+    }
+
+    pub fn snapshot(&self) -> CacheSnapshot {
+        CacheSnapshot {
+            tiles: self
+                .cache
+                .iter()
+                .rev()
+                .map(|((kind, req), (tile, _))| (*kind, req.clone(), tile.clone()))
+                .collect(),
+        }
+    }
+
+    // Adds the tiles from a snapshot, as if they had just been fetched in the
+    // same order
+    pub fn restore(&mut self, snapshot: CacheSnapshot) {
+        for (kind, req, tile) in snapshot.tiles {
+            self.insert(kind, req, tile);
+        }
+    }
+
+    // Drops the cached tiles overlapping the interval, e.g., because new data
+    // arrived there
+    pub fn invalidate(&mut self, interval: Interval) {
+        let stale: Vec<_> = self
+            .cache
+            .iter()
+            .filter(|((_, req), _)| req.tile_id.0.overlaps(interval))
+            .map(|(key, _)| key.clone())
+            .collect();
+        debug!(
+            "tile cache: dropping {} tiles stale in {interval}",
+            stale.len()
+        );
+        for key in stale {
+            if let Some((_, size)) = self.cache.pop(&key) {
+                self.stats.bytes -= size;
+            }
+        }
+        self.stats.entries = self.cache.len();
+    }
+
+    fn lookup(&mut self, kind: TileKind, req: &TileRequest) -> Option<CachedTile> {
+        let mut result = self
+            .cache
+            .get(&(kind, req.clone()))
+            .map(|(tile, _)| tile.clone());
+        // A full tile has everything the partial one would, so it can stand
+        // in for it
+        if result.is_none() && !req.full {
+            result = self
+                .cache
+                .get(&(kind, req.as_full()))
+                .map(|(tile, _)| tile.clone());
+        }
+        if result.is_some() {
+            trace!("tile cache: hit {kind:?} {req}");
+            self.stats.hits += 1;
+        } else {
+            trace!("tile cache: miss {kind:?} {req}");
+            self.stats.misses += 1;
+        }
+        result
+    }
+
+    fn insert(&mut self, kind: TileKind, req: TileRequest, tile: CachedTile) {
+        let size = tile.size();
+        if size > self.budget {
+            debug!("tile cache: not caching {kind:?} {req}, {size} bytes is over budget");
+            return;
+        }
+        // Keep at most one of the full and partial tiles, preferring the full
+        if req.full {
+            if let Some((_, partial_size)) = self.cache.pop(&(kind, req.as_partial())) {
+                self.stats.bytes -= partial_size;
+            }
+        } else if self.cache.contains(&(kind, req.as_full())) {
+            return;
+        }
+        if let Some((_, old_size)) = self.cache.put((kind, req), (tile, size)) {
+            self.stats.bytes -= old_size;
+        }
+        self.stats.bytes += size;
+        while self.stats.bytes > self.budget {
+            let ((kind, req), (_, evicted_size)) = self.cache.pop_lru().unwrap();
+            debug!("tile cache: evicting {kind:?} {req} ({evicted_size} bytes)");
+            self.stats.bytes -= evicted_size;
+        }
+        self.stats.entries = self.cache.len();
+    }
+}
+
+// Answers tile requests from a cache when it can, and caches the responses
+// otherwise
+pub struct CachingDeferredDataSource<T: DeferredDataSource> {
+    data_source: T,
+    cache: SharedTileCache,
+    summary_tiles: Vec<SummaryTileResponse>,
+    slot_tiles: Vec<SlotTileResponse>,
+    slot_meta_tiles: Vec<SlotMetaTileResponse>,
+}
+
+impl<T: DeferredDataSource> CachingDeferredDataSource<T> {
+    pub fn new(data_source: T, budget: usize) -> Self {
+        Self::with_cache(data_source, TileCache::shared(budget))
+    }
+
+    pub fn with_cache(data_source: T, cache: SharedTileCache) -> Self {
+        Self {
+            data_source,
+            cache,
+            summary_tiles: Vec::new(),
+            slot_tiles: Vec::new(),
+            slot_meta_tiles: Vec::new(),
+        }
+    }
+
+    pub fn data_source(&self) -> &T {
+        &self.data_source
+    }
+
+    pub fn cache(&self) -> &SharedTileCache {
+        &self.cache
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        self.cache.borrow().stats()
+    }
+
+    pub fn clear(&mut self) {
+        self.cache.borrow_mut().clear();
+    }
+
+    pub fn snapshot(&self) -> CacheSnapshot {
+        self.cache.borrow().snapshot()
+    }
+
+    pub fn restore(&mut self, snapshot: CacheSnapshot) {
+        self.cache.borrow_mut().restore(snapshot);
+    }
+
+    pub fn invalidate(&mut self, interval: Interval) {
+        self.cache.borrow_mut().invalidate(interval);
+    }
+
+    fn lookup(&mut self, kind: TileKind, req: &TileRequest) -> Option<CachedTile> {
+        self.cache.borrow_mut().lookup(kind, req)
+    }
+
+    fn insert(&mut self, kind: TileKind, req: TileRequest, tile: CachedTile) {
+        self.cache.borrow_mut().insert(kind, req, tile);
+    }
+}
+
+impl<T: DeferredDataSource> DeferredDataSource for CachingDeferredDataSource<T> {
+    fn fetch_description(&self) -> DataSourceDescription {
+        self.data_source.fetch_description()
+    }
+
+    fn fetch_info(&mut self) {
+        self.data_source.fetch_info()
+    }
+
+    fn get_infos(&mut self) -> Vec<DataSourceInfoResult> {
+        self.data_source.get_infos()
+    }
+
+    fn fetch_summary_tile(
+        &mut self,
+        entry_id: &EntryID,
+        tile_id: TileID,
+        full: bool,
+        priority: RequestPriority,
+    ) {
+        let req = TileRequest {
+            entry_id: entry_id.clone(),
+            tile_id,
+            full,
+        };
+        if let Some(CachedTile::Summary(tile)) = self.lookup(TileKind::Summary, &req) {
+            self.summary_tiles.push((Ok(tile), req));
+        } else {
+            self.data_source
+                .fetch_summary_tile(entry_id, tile_id, full, priority);
+        }
+    }
+
+    fn get_summary_tiles(&mut self) -> Vec<SummaryTileResponse> {
+        let result = self.data_source.get_summary_tiles();
+        for (tile, req) in &result {
+            // Errors may be transient, so don't cache them
+            if let Ok(tile) = tile {
+                self.insert(
+                    TileKind::Summary,
+                    req.clone(),
+                    CachedTile::Summary(tile.clone()),
+                );
+            }
+        }
+        self.summary_tiles.extend(result);
+        std::mem::take(&mut self.summary_tiles)
+    }
+
+    fn fetch_slot_tile(
+        &mut self,
+        entry_id: &EntryID,
+        tile_id: TileID,
+        full: bool,
+        priority: RequestPriority,
+    ) {
+        let req = TileRequest {
+            entry_id: entry_id.clone(),
+            tile_id,
+            full,
+        };
+        if let Some(CachedTile::Slot(tile)) = self.lookup(TileKind::Slot, &req) {
+            self.slot_tiles.push((Ok(tile), req));
+        } else {
+            self.data_source
+                .fetch_slot_tile(entry_id, tile_id, full, priority);
+        }
+    }
+
+    fn get_slot_tiles(&mut self) -> Vec<SlotTileResponse> {
+        let result = self.data_source.get_slot_tiles();
+        for (tile, req) in &result {
+            if let Ok(tile) = tile {
+                self.insert(TileKind::Slot, req.clone(), CachedTile::Slot(tile.clone()));
+            }
+        }
+        self.slot_tiles.extend(result);
+        std::mem::take(&mut self.slot_tiles)
+    }
+
+    fn fetch_slot_meta_tile(
+        &mut self,
+        entry_id: &EntryID,
+        tile_id: TileID,
+        full: bool,
+        priority: RequestPriority,
+    ) {
+        let req = TileRequest {
+            entry_id: entry_id.clone(),
+            tile_id,
+            full,
+        };
+        if let Some(CachedTile::SlotMeta(tile)) = self.lookup(TileKind::SlotMeta, &req) {
+            self.slot_meta_tiles.push((Ok(tile), req));
+        } else {
+            self.data_source
+                .fetch_slot_meta_tile(entry_id, tile_id, full, priority);
+        }
+    }
+
+    fn get_slot_meta_tiles(&mut self) -> Vec<SlotMetaTileResponse> {
+        let result = self.data_source.get_slot_meta_tiles();
+        for (tile, req) in &result {
+            if let Ok(tile) = tile {
+                self.insert(
+                    TileKind::SlotMeta,
+                    req.clone(),
+                    CachedTile::SlotMeta(tile.clone()),
+                );
+            }
+        }
+        self.slot_meta_tiles.extend(result);
+        std::mem::take(&mut self.slot_meta_tiles)
+    }
+
+    fn fetch_items_meta(&mut self, requests: &[ItemMetaRequest]) {
+        self.data_source.fetch_items_meta(requests)
+    }
+
+    fn get_items_meta(&mut self) -> Vec<ItemsMetaResponse> {
+        self.data_source.get_items_meta()
+    }
+
+    fn fetch_tiles(&mut self, kind: TileKind, requests: &[TileRequest], priority: RequestPriority) {
+        let mut misses = Vec::new();
+        for req in requests {
+            match (kind, self.lookup(kind, req)) {
+                (TileKind::Summary, Some(CachedTile::Summary(tile))) => {
+                    self.summary_tiles.push((Ok(tile), req.clone()))
+                }
+                (TileKind::Slot, Some(CachedTile::Slot(tile))) => {
+                    self.slot_tiles.push((Ok(tile), req.clone()))
+                }
+                (TileKind::SlotMeta, Some(CachedTile::SlotMeta(tile))) => {
+                    self.slot_meta_tiles.push((Ok(tile), req.clone()))
+                }
+                _ => misses.push(req.clone()),
+            }
+        }
+        if !misses.is_empty() {
+            self.data_source.fetch_tiles(kind, &misses, priority);
+        }
+    }
+
+    fn cancel(&mut self, entry_id: &EntryID, tile_id: TileID, full: bool) {
+        self.data_source.cancel(entry_id, tile_id, full)
+    }
+
+    fn transfer_stats(&self) -> Option<TransferStats> {
+        self.data_source.transfer_stats()
+    }
+
+    fn retries_failures(&self) -> bool {
+        self.data_source.retries_failures()
+    }
 }
 
 impl DeferredDataSource for Box<dyn DeferredDataSource> {
@@ -308,31 +1082,268 @@ impl DeferredDataSource for Box<dyn DeferredDataSource> {
         self.as_mut().fetch_info()
     }
 
-    fn get_infos(&mut self) -> Vec<DataSourceInfo> {
+    fn get_infos(&mut self) -> Vec<DataSourceInfoResult> {
         self.as_mut().get_infos()
     }
 
-    fn fetch_summary_tile(&mut self, entry_id: &EntryID, tile_id: TileID, full: bool) {
-        self.as_mut().fetch_summary_tile(entry_id, tile_id, full)
+    fn fetch_summary_tile(
+        &mut self,
+        entry_id: &EntryID,
+        tile_id: TileID,
+        full: bool,
+        priority: RequestPriority,
+    ) {
+        self.as_mut()
+            .fetch_summary_tile(entry_id, tile_id, full, priority)
     }
 
     fn get_summary_tiles(&mut self) -> Vec<SummaryTileResponse> {
         self.as_mut().get_summary_tiles()
     }
 
-    fn fetch_slot_tile(&mut self, entry_id: &EntryID, tile_id: TileID, full: bool) {
-        self.as_mut().fetch_slot_tile(entry_id, tile_id, full)
+    fn fetch_slot_tile(
+        &mut self,
+        entry_id: &EntryID,
+        tile_id: TileID,
+        full: bool,
+        priority: RequestPriority,
+    ) {
+        self.as_mut()
+            .fetch_slot_tile(entry_id, tile_id, full, priority)
     }
 
     fn get_slot_tiles(&mut self) -> Vec<SlotTileResponse> {
         self.as_mut().get_slot_tiles()
     }
 
-    fn fetch_slot_meta_tile(&mut self, entry_id: &EntryID, tile_id: TileID, full: bool) {
-        self.as_mut().fetch_slot_meta_tile(entry_id, tile_id, full)
+    fn fetch_slot_meta_tile(
+        &mut self,
+        entry_id: &EntryID,
+        tile_id: TileID,
+        full: bool,
+        priority: RequestPriority,
+    ) {
+        self.as_mut()
+            .fetch_slot_meta_tile(entry_id, tile_id, full, priority)
     }
 
     fn get_slot_meta_tiles(&mut self) -> Vec<SlotMetaTileResponse> {
         self.as_mut().get_slot_meta_tiles()
     }
+
+    fn fetch_items_meta(&mut self, requests: &[ItemMetaRequest]) {
+        self.as_mut().fetch_items_meta(requests)
+    }
+
+    fn get_items_meta(&mut self) -> Vec<ItemsMetaResponse> {
+        self.as_mut().get_items_meta()
+    }
+
+    fn fetch_tiles(&mut self, kind: TileKind, requests: &[TileRequest], priority: RequestPriority) {
+        self.as_mut().fetch_tiles(kind, requests, priority)
+    }
+
+    fn cancel(&mut self, entry_id: &EntryID, tile_id: TileID, full: bool) {
+        self.as_mut().cancel(entry_id, tile_id, full)
+    }
+
+    fn transfer_stats(&self) -> Option<TransferStats> {
+        self.as_ref().transfer_stats()
+    }
+
+    fn retries_failures(&self) -> bool {
+        self.as_ref().retries_failures()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::test_data::TestDataSource;
+    use crate::timestamp::{Interval, Timestamp};
+
+    #[test]
+    fn test_tile_request_display() {
+        let req = TileRequest {
+            entry_id: EntryID::root().child(0).child(2),
+            tile_id: TileID(Interval::new(Timestamp(0), Timestamp(1000))),
+            full: false,
+        };
+        assert_eq!(req.to_string(), "0_2/0_1000");
+        assert_eq!(req.as_full().to_string(), "0_2/0_1000 (full)");
+    }
+
+    #[test]
+    fn test_cancel_flags() {
+        let req = TileRequest {
+            entry_id: EntryID::root(),
+            tile_id: TileID(Interval::default()),
+            full: false,
+        };
+
+        let mut flags = CancelFlags::default();
+        let first = flags.start(req.clone());
+        let second = flags.start(req.clone());
+        drop(second);
+        flags.cancel(&req);
+        assert!(first.is_cancelled());
+
+        // Requests started after the cancel are unaffected
+        let third = flags.start(req.clone());
+        assert!(!third.is_cancelled());
+    }
+
+    #[test]
+    fn test_caching_budget() {
+        let tile_size = CachedTile::Summary(TestDataSource::default().fetch_summary_tile(
+            &EntryID::root(),
+            TileID(Interval::default()),
+            false,
+        ))
+        .size();
+
+        // Room for two tiles
+        let mut cache = CachingDeferredDataSource::new(
+            DeferredDataSourceWrapper::new(TestDataSource::default()),
+            2 * tile_size + 1,
+        );
+
+        let entry_id = EntryID::root().summary();
+        let tile = |i: i64| TileID(Interval::new(Timestamp(i), Timestamp(i + 1)));
+        for i in 0..3 {
+            cache.fetch_summary_tile(&entry_id, tile(i), false, RequestPriority::Visible);
+            assert_eq!(cache.get_summary_tiles().len(), 1);
+        }
+        assert_eq!(cache.stats().misses, 3);
+        assert_eq!(cache.stats().entries, 2);
+        assert_eq!(cache.stats().bytes, 2 * tile_size);
+
+        // The first tile was evicted, the last is still cached
+        cache.fetch_summary_tile(&entry_id, tile(2), false, RequestPriority::Visible);
+        assert_eq!(cache.get_summary_tiles().len(), 1);
+        assert_eq!(cache.stats().hits, 1);
+        cache.fetch_summary_tile(&entry_id, tile(0), false, RequestPriority::Visible);
+        assert_eq!(cache.get_summary_tiles().len(), 1);
+        assert_eq!(cache.stats().misses, 4);
+
+        // Full and partial tiles are cached separately
+        cache.fetch_summary_tile(&entry_id, tile(0), true, RequestPriority::Visible);
+        assert_eq!(cache.get_summary_tiles().len(), 1);
+        assert_eq!(cache.stats().misses, 5);
+
+        cache.clear();
+        assert_eq!(cache.stats().entries, 0);
+        assert_eq!(cache.stats().bytes, 0);
+        cache.fetch_summary_tile(&entry_id, tile(0), true, RequestPriority::Visible);
+        assert_eq!(cache.get_summary_tiles().len(), 1);
+        assert_eq!(cache.stats().misses, 6);
+
+        // Only tiles overlapping the interval are invalidated
+        cache.fetch_summary_tile(&entry_id, tile(1), true, RequestPriority::Visible);
+        assert_eq!(cache.get_summary_tiles().len(), 1);
+        cache.invalidate(Interval::new(Timestamp(1), Timestamp(5)));
+        assert_eq!(cache.stats().entries, 1);
+        assert_eq!(cache.stats().bytes, tile_size);
+
+        // Snapshots round trip
+        let snapshot = cache.snapshot();
+        assert_eq!(snapshot.len(), 1);
+        cache.clear();
+        cache.restore(snapshot);
+        cache.fetch_summary_tile(&entry_id, tile(0), true, RequestPriority::Visible);
+        assert_eq!(cache.get_summary_tiles().len(), 1);
+        assert_eq!(cache.stats().entries, 1);
+        assert_eq!(cache.stats().hits, 2);
+    }
+
+    #[test]
+    fn test_caching_full() {
+        let mut cache = CachingDeferredDataSource::new(
+            DeferredDataSourceWrapper::new(TestDataSource::default()),
+            usize::MAX,
+        );
+
+        let entry_id = EntryID::root().summary();
+        let tile_id = TileID(Interval::new(Timestamp(0), Timestamp(1)));
+        cache.fetch_summary_tile(&entry_id, tile_id, false, RequestPriority::Visible);
+        assert_eq!(cache.get_summary_tiles().len(), 1);
+
+        // The full tile replaces the partial one
+        cache.fetch_summary_tile(&entry_id, tile_id, true, RequestPriority::Visible);
+        assert_eq!(cache.get_summary_tiles().len(), 1);
+        assert_eq!(cache.stats().misses, 2);
+        assert_eq!(cache.stats().entries, 1);
+
+        // And answers partial requests too
+        cache.fetch_summary_tile(&entry_id, tile_id, false, RequestPriority::Visible);
+        let tiles = cache.get_summary_tiles();
+        assert_eq!(tiles.len(), 1);
+        assert!(!tiles[0].1.full);
+        assert_eq!(cache.stats().hits, 1);
+    }
+
+    #[test]
+    fn test_caching_shared() {
+        let shared = TileCache::shared(usize::MAX);
+        let mut first = CachingDeferredDataSource::with_cache(
+            DeferredDataSourceWrapper::new(TestDataSource::default()),
+            shared.clone(),
+        );
+        let mut second = CountingDeferredDataSource::new(CachingDeferredDataSource::with_cache(
+            DeferredDataSourceWrapper::new(TestDataSource::default()),
+            shared,
+        ));
+
+        let entry_id = EntryID::root().summary();
+        let tile_id = TileID(Interval::new(Timestamp(0), Timestamp(1)));
+        first.fetch_summary_tile(&entry_id, tile_id, false, RequestPriority::Visible);
+        assert_eq!(first.get_summary_tiles().len(), 1);
+
+        // Tiles fetched through one source are answered from the cache in
+        // the other
+        second.fetch_summary_tile(&entry_id, tile_id, false, RequestPriority::Visible);
+        assert_eq!(second.get_summary_tiles().len(), 1);
+        assert_eq!(second.data_source().stats().hits, 1);
+        assert_eq!(first.stats().entries, 1);
+
+        first.clear();
+        assert_eq!(second.data_source().stats().entries, 0);
+    }
+
+    #[test]
+    fn test_fetch_tiles() {
+        let mut ds = CountingDeferredDataSource::new(CachingDeferredDataSource::new(
+            DeferredDataSourceWrapper::new(TestDataSource::default()),
+            usize::MAX,
+        ));
+
+        let req = |i: i64| TileRequest {
+            entry_id: EntryID::root().summary(),
+            tile_id: TileID(Interval::new(Timestamp(i), Timestamp(i + 1))),
+            full: false,
+        };
+        ds.fetch_tiles(
+            TileKind::Summary,
+            &[req(0), req(1)],
+            RequestPriority::Visible,
+        );
+        assert_eq!(ds.outstanding_requests(), 2);
+        let tiles = ds.get_summary_tiles();
+        assert_eq!(
+            tiles.into_iter().map(|(_, req)| req).collect::<Vec<_>>(),
+            vec![req(0), req(1)]
+        );
+        assert_eq!(ds.outstanding_requests(), 0);
+
+        // Only the new tile goes to the underlying source
+        ds.fetch_tiles(
+            TileKind::Summary,
+            &[req(1), req(2)],
+            RequestPriority::Visible,
+        );
+        assert_eq!(ds.get_summary_tiles().len(), 2);
+        let stats = ds.data_source().stats();
+        assert_eq!((stats.hits, stats.misses), (1, 3));
+    }
 }
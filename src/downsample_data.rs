@@ -0,0 +1,497 @@
+use std::collections::BTreeMap;
+use std::ops::Range;
+
+use crate::data::{
+    DataSourceDescription, EntryID, Item, ItemMeta, ItemMetaRequest, SlotMetaTile, SlotTile, TileID,
+};
+use crate::deferred_data::{
+    DataSourceInfoResult, DeferredDataSource, ItemsMetaResponse, RequestPriority,
+    SlotMetaTileResponse, SlotTileResponse, SummaryTileResponse, TileKind, TileRequest,
+    TileResponse, TransferStats,
+};
+use crate::timestamp::Interval;
+
+const MISMATCHED: &str = "slot meta tile does not match slot tile";
+
+type Key = (EntryID, TileID);
+
+// For each row, the ranges of consecutive items that get merged together
+type Layout = Vec<Vec<Range<usize>>>;
+
+// Callers waiting on a full tile fetch. A fetch with no callers at all is
+// one we issued ourselves to get the layout for a slot meta tile.
+struct Waiters {
+    full: usize,
+    coarse: usize,
+    priority: RequestPriority,
+}
+
+impl Waiters {
+    fn new(priority: RequestPriority) -> Self {
+        Self {
+            full: 0,
+            coarse: 0,
+            priority,
+        }
+    }
+
+    fn add(&mut self, full: bool, priority: RequestPriority) {
+        if full {
+            self.full += 1;
+        } else {
+            self.coarse += 1;
+        }
+        self.priority = self.priority.max(priority);
+    }
+
+    fn serves_full(&self) -> bool {
+        self.full > 0
+    }
+
+    fn serves_coarse(&self) -> bool {
+        self.coarse > 0 || self.full == 0
+    }
+}
+
+// Makes coarse tiles out of full ones, for sources that only know how to
+// produce full detail. Each full tile is fetched once no matter how many
+// requests are waiting on it, and runs of consecutive items narrower than
+// 1/resolution of the tile are merged into a single item. Slot meta tiles
+// are merged with the layout computed from the matching slot tile, so that
+// items line up between the two.
+pub struct DownsampleDeferredDataSource<T: DeferredDataSource> {
+    data_source: T,
+    resolution: i64,
+    slot_tiles: BTreeMap<Key, Waiters>,
+    slot_meta_tiles: BTreeMap<Key, Waiters>,
+    layouts: BTreeMap<Key, Layout>,
+    // Coarse slot meta tiles waiting on the layout from their slot tile
+    waiting_slot_meta_tiles: BTreeMap<Key, (SlotMetaTile, usize)>,
+    ready_slot_meta_tiles: Vec<SlotMetaTileResponse>,
+}
+
+impl<T: DeferredDataSource> DownsampleDeferredDataSource<T> {
+    pub fn new(data_source: T, resolution: u64) -> Self {
+        assert!(resolution > 0);
+        Self {
+            data_source,
+            resolution: resolution as i64,
+            slot_tiles: BTreeMap::new(),
+            slot_meta_tiles: BTreeMap::new(),
+            layouts: BTreeMap::new(),
+            waiting_slot_meta_tiles: BTreeMap::new(),
+            ready_slot_meta_tiles: Vec::new(),
+        }
+    }
+
+    fn pending(&mut self, kind: TileKind) -> &mut BTreeMap<Key, Waiters> {
+        match kind {
+            TileKind::Slot => &mut self.slot_tiles,
+            TileKind::SlotMeta => &mut self.slot_meta_tiles,
+            TileKind::Summary => unreachable!(),
+        }
+    }
+
+    // Records the request and returns the full request to forward, if there
+    // isn't one in flight already
+    fn start_request(
+        &mut self,
+        kind: TileKind,
+        req: &TileRequest,
+        priority: RequestPriority,
+    ) -> Option<TileRequest> {
+        let key = (req.entry_id.clone(), req.tile_id);
+        let pending = self.pending(kind);
+        let new = !pending.contains_key(&key);
+        pending
+            .entry(key)
+            .or_insert_with(|| Waiters::new(priority))
+            .add(req.full, priority);
+        new.then(|| TileRequest {
+            full: true,
+            ..req.clone()
+        })
+    }
+
+    fn compute_layout(&self, tile: &SlotTile) -> Layout {
+        let threshold = (tile.tile_id.0.duration_ns() / self.resolution).max(1);
+        let small = |item: &Item| item.interval.duration_ns() < threshold;
+
+        let mut layout = Vec::new();
+        for row in &tile.data.items {
+            let mut groups = Vec::new();
+            let mut i = 0;
+            while i < row.len() {
+                let mut j = i + 1;
+                if small(&row[i]) {
+                    let mut stop = row[i].interval.stop;
+                    while j < row.len()
+                        && small(&row[j])
+                        && row[j].interval.start.0 - stop.0 < threshold
+                    {
+                        stop = stop.max(row[j].interval.stop);
+                        j += 1;
+                    }
+                }
+                groups.push(i..j);
+                i = j;
+            }
+            layout.push(groups);
+        }
+        layout
+    }
+
+    fn apply_layout<I: Clone>(
+        layout: &Layout,
+        rows: &[Vec<I>],
+        merge: impl Fn(&[I]) -> I,
+    ) -> Option<Vec<Vec<I>>> {
+        if layout.len() != rows.len() {
+            return None;
+        }
+        layout
+            .iter()
+            .zip(rows)
+            .map(|(groups, row)| {
+                if groups.last().map_or(0, |g| g.end) != row.len() {
+                    return None;
+                }
+                Some(
+                    groups
+                        .iter()
+                        .map(|g| {
+                            if g.len() == 1 {
+                                row[g.start].clone()
+                            } else {
+                                merge(&row[g.clone()])
+                            }
+                        })
+                        .collect(),
+                )
+            })
+            .collect()
+    }
+
+    fn downsample_slot_tile(layout: &Layout, tile: &SlotTile) -> SlotTile {
+        let items = Self::apply_layout(layout, &tile.data.items, |items| {
+            let first = &items[0];
+            let stop = items.iter().map(|item| item.interval.stop).max().unwrap();
+            Item {
+                item_uid: first.item_uid,
+                interval: Interval::new(first.interval.start, stop),
+                color: first.color,
+            }
+        })
+        .unwrap();
+        let mut result = tile.clone();
+        result.data.items = items;
+        result
+    }
+
+    fn downsample_slot_meta_tile(
+        layout: &Layout,
+        tile: &SlotMetaTile,
+    ) -> Result<SlotMetaTile, String> {
+        let items = Self::apply_layout(layout, &tile.data.items, |items| ItemMeta {
+            item_uid: items[0].item_uid,
+            original_interval: items
+                .iter()
+                .map(|item| item.original_interval)
+                .reduce(Interval::union)
+                .unwrap(),
+            title: format!("{} merged items", items.len()),
+            fields: Vec::new(),
+        })
+        .ok_or_else(|| MISMATCHED.to_string())?;
+        let mut result = tile.clone();
+        result.data.items = items;
+        Ok(result)
+    }
+
+    fn respond<R: Clone>(
+        responses: &mut Vec<TileResponse<R>>,
+        result: Result<R, String>,
+        req: &TileRequest,
+        full: bool,
+        count: usize,
+    ) {
+        let req = TileRequest {
+            full,
+            ..req.clone()
+        };
+        responses.extend(std::iter::repeat_n((result, req), count));
+    }
+
+    fn release_slot_meta_tile(&mut self, key: &Key) {
+        let Some((tile, count)) = self.waiting_slot_meta_tiles.remove(key) else {
+            return;
+        };
+        let result = match self.layouts.get(key) {
+            Some(layout) => Self::downsample_slot_meta_tile(layout, &tile),
+            None => Err(MISMATCHED.to_string()),
+        };
+        let req = TileRequest {
+            entry_id: key.0.clone(),
+            tile_id: key.1,
+            full: false,
+        };
+        Self::respond(&mut self.ready_slot_meta_tiles, result, &req, false, count);
+    }
+}
+
+impl<T: DeferredDataSource> DeferredDataSource for DownsampleDeferredDataSource<T> {
+    fn fetch_description(&self) -> DataSourceDescription {
+        self.data_source.fetch_description()
+    }
+
+    fn fetch_info(&mut self) {
+        self.data_source.fetch_info()
+    }
+
+    fn get_infos(&mut self) -> Vec<DataSourceInfoResult> {
+        self.data_source.get_infos()
+    }
+
+    fn fetch_summary_tile(
+        &mut self,
+        entry_id: &EntryID,
+        tile_id: TileID,
+        full: bool,
+        priority: RequestPriority,
+    ) {
+        self.data_source
+            .fetch_summary_tile(entry_id, tile_id, full, priority)
+    }
+
+    fn get_summary_tiles(&mut self) -> Vec<SummaryTileResponse> {
+        self.data_source.get_summary_tiles()
+    }
+
+    fn fetch_slot_tile(
+        &mut self,
+        entry_id: &EntryID,
+        tile_id: TileID,
+        full: bool,
+        priority: RequestPriority,
+    ) {
+        let req = TileRequest {
+            entry_id: entry_id.clone(),
+            tile_id,
+            full,
+        };
+        if let Some(req) = self.start_request(TileKind::Slot, &req, priority) {
+            self.data_source
+                .fetch_slot_tile(&req.entry_id, req.tile_id, req.full, priority)
+        }
+    }
+
+    fn get_slot_tiles(&mut self) -> Vec<SlotTileResponse> {
+        let mut responses = Vec::new();
+        for (result, req) in self.data_source.get_slot_tiles() {
+            let key = (req.entry_id.clone(), req.tile_id);
+            let Some(waiters) = self.slot_tiles.remove(&key) else {
+                continue;
+            };
+            match result {
+                Ok(tile) => {
+                    if waiters.coarse > 0 || self.waiting_slot_meta_tiles.contains_key(&key) {
+                        let layout = self.compute_layout(&tile);
+                        if waiters.coarse > 0 {
+                            let coarse = Self::downsample_slot_tile(&layout, &tile);
+                            Self::respond(&mut responses, Ok(coarse), &req, false, waiters.coarse);
+                        }
+                        self.layouts.insert(key.clone(), layout);
+                    }
+                    Self::respond(&mut responses, Ok(tile), &req, true, waiters.full);
+                    self.release_slot_meta_tile(&key);
+                }
+                Err(e) => {
+                    Self::respond(&mut responses, Err(e.clone()), &req, false, waiters.coarse);
+                    Self::respond(&mut responses, Err(e), &req, true, waiters.full);
+                    self.release_slot_meta_tile(&key);
+                }
+            }
+        }
+        responses
+    }
+
+    fn fetch_slot_meta_tile(
+        &mut self,
+        entry_id: &EntryID,
+        tile_id: TileID,
+        full: bool,
+        priority: RequestPriority,
+    ) {
+        let req = TileRequest {
+            entry_id: entry_id.clone(),
+            tile_id,
+            full,
+        };
+        if let Some(req) = self.start_request(TileKind::SlotMeta, &req, priority) {
+            self.data_source
+                .fetch_slot_meta_tile(&req.entry_id, req.tile_id, req.full, priority)
+        }
+    }
+
+    fn get_slot_meta_tiles(&mut self) -> Vec<SlotMetaTileResponse> {
+        let mut responses = std::mem::take(&mut self.ready_slot_meta_tiles);
+        for (result, req) in self.data_source.get_slot_meta_tiles() {
+            let key = (req.entry_id.clone(), req.tile_id);
+            let Some(waiters) = self.slot_meta_tiles.remove(&key) else {
+                continue;
+            };
+            match result {
+                Ok(tile) => {
+                    if waiters.coarse > 0 {
+                        if let Some(layout) = self.layouts.get(&key) {
+                            let coarse = Self::downsample_slot_meta_tile(layout, &tile);
+                            Self::respond(&mut responses, coarse, &req, false, waiters.coarse);
+                        } else {
+                            // Need the slot tile to know how the items were
+                            // merged, so fetch it if nobody else has
+                            self.waiting_slot_meta_tiles
+                                .entry(key.clone())
+                                .and_modify(|(_, count)| *count += waiters.coarse)
+                                .or_insert((tile.clone(), waiters.coarse));
+                            if !self.slot_tiles.contains_key(&key) {
+                                self.slot_tiles
+                                    .insert(key.clone(), Waiters::new(waiters.priority));
+                                self.data_source.fetch_slot_tile(
+                                    &req.entry_id,
+                                    req.tile_id,
+                                    true,
+                                    waiters.priority,
+                                );
+                            }
+                        }
+                    }
+                    Self::respond(&mut responses, Ok(tile), &req, true, waiters.full);
+                }
+                Err(e) => {
+                    Self::respond(&mut responses, Err(e.clone()), &req, false, waiters.coarse);
+                    Self::respond(&mut responses, Err(e), &req, true, waiters.full);
+                }
+            }
+        }
+        responses
+    }
+
+    fn fetch_items_meta(&mut self, requests: &[ItemMetaRequest]) {
+        self.data_source.fetch_items_meta(requests)
+    }
+
+    fn get_items_meta(&mut self) -> Vec<ItemsMetaResponse> {
+        self.data_source.get_items_meta()
+    }
+
+    fn fetch_tiles(&mut self, kind: TileKind, requests: &[TileRequest], priority: RequestPriority) {
+        if kind == TileKind::Summary {
+            return self.data_source.fetch_tiles(kind, requests, priority);
+        }
+        let forward: Vec<_> = requests
+            .iter()
+            .filter_map(|req| self.start_request(kind, req, priority))
+            .collect();
+        if !forward.is_empty() {
+            self.data_source.fetch_tiles(kind, &forward, priority)
+        }
+    }
+
+    fn cancel(&mut self, entry_id: &EntryID, tile_id: TileID, full: bool) {
+        // Summary tiles are forwarded as is
+        if !full {
+            self.data_source.cancel(entry_id, tile_id, false);
+        }
+
+        // Slot and slot meta tiles are shared between full and coarse
+        // requests, so only cancel them if nobody else is waiting
+        let key = (entry_id.clone(), tile_id);
+        let busy = [&self.slot_tiles, &self.slot_meta_tiles]
+            .into_iter()
+            .filter_map(|pending| pending.get(&key))
+            .any(|waiters| {
+                if full {
+                    waiters.serves_coarse()
+                } else {
+                    waiters.serves_full()
+                }
+            });
+        if !busy {
+            self.data_source.cancel(entry_id, tile_id, true);
+        }
+    }
+
+    fn transfer_stats(&self) -> Option<TransferStats> {
+        self.data_source.transfer_stats()
+    }
+
+    fn retries_failures(&self) -> bool {
+        self.data_source.retries_failures()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::deferred_data::DeferredDataSourceWrapper;
+    use crate::test_data::TestDataSource;
+    use crate::timestamp::Timestamp;
+
+    // Ten 1 ns items 2 ns apart, followed by one long item. The decorator
+    // always fetches the full tiles, which it then downsamples itself.
+    fn test_source() -> TestDataSource {
+        let mut items: Vec<_> = (0..10)
+            .map(|i| Interval::new(Timestamp(i * 2), Timestamp(i * 2 + 1)))
+            .collect();
+        items.push(Interval::new(Timestamp(500), Timestamp(900)));
+        TestDataSource {
+            items,
+            full_only: true,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_downsample() {
+        let entry_id = EntryID::root().child(0);
+        let tile_id = TileID(Interval::new(Timestamp(0), Timestamp(1000)));
+        let mut ds =
+            DownsampleDeferredDataSource::new(DeferredDataSourceWrapper::new(test_source()), 100);
+
+        // Meta tile first, so it has to wait for the slot tile's layout
+        ds.fetch_slot_meta_tile(&entry_id, tile_id, false, RequestPriority::Visible);
+        let mut metas = ds.get_slot_meta_tiles();
+        assert!(metas.is_empty());
+        ds.fetch_slot_tile(&entry_id, tile_id, false, RequestPriority::Visible);
+        ds.fetch_slot_tile(&entry_id, tile_id, true, RequestPriority::Visible);
+        let mut tiles = ds.get_slot_tiles();
+        tiles.sort_by_key(|(_, req)| req.full);
+        metas.extend(ds.get_slot_meta_tiles());
+
+        assert_eq!(tiles.len(), 2);
+        let (coarse, req) = &tiles[0];
+        assert!(!req.full);
+        let coarse = coarse.as_ref().unwrap();
+        assert_eq!(coarse.data.items[0].len(), 2);
+        assert_eq!(
+            coarse.data.items[0][0].interval,
+            Interval::new(Timestamp(0), Timestamp(19))
+        );
+        let (full, req) = &tiles[1];
+        assert!(req.full);
+        assert_eq!(full.as_ref().unwrap().data.items[0].len(), 11);
+
+        let (meta, req) = metas.pop().unwrap();
+        assert!(!req.full);
+        let meta = meta.unwrap();
+        assert_eq!(meta.data.items[0].len(), 2);
+        assert_eq!(meta.data.items[0][0].title, "10 merged items");
+        assert_eq!(meta.data.items[0][1].title, "item 10");
+
+        // Now the layout is known, so the meta tile comes back right away
+        ds.fetch_slot_meta_tile(&entry_id, tile_id, false, RequestPriority::Visible);
+        let (meta, _) = ds.get_slot_meta_tiles().pop().unwrap();
+        assert_eq!(meta.unwrap().data.items[0].len(), 2);
+    }
+}
@@ -0,0 +1,215 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use serde_json::{Map, Value, json};
+
+use crate::data::{DataSourceInfo, EntryID, Field, FieldSchema};
+use crate::deferred_data::DeferredDataSource;
+use crate::export::{ExportEntry, ExportItem, Exporter, export};
+
+// Writes events one at a time, so that the whole trace is never in memory
+struct EventWriter<W: Write> {
+    writer: W,
+    first: bool,
+}
+
+impl<W: Write> EventWriter<W> {
+    fn write(&mut self, event: Value) -> io::Result<()> {
+        if !self.first {
+            self.writer.write_all(b",\n")?;
+        }
+        self.first = false;
+        serde_json::to_writer(&mut self.writer, &event)?;
+        Ok(())
+    }
+}
+
+fn to_value(field: &Field) -> Value {
+    match field {
+        Field::I64(value) => json!(value),
+        Field::U64(value) => json!(value),
+        Field::String(value) => json!(value),
+        Field::Interval(value) => json!(value.to_string()),
+        Field::ItemLink(link) => json!(link.title),
+        Field::Vec(fields) => Value::Array(fields.iter().map(to_value).collect()),
+        Field::Empty => Value::Null,
+    }
+}
+
+fn to_us(ns: i64) -> f64 {
+    ns as f64 / 1000.0
+}
+
+pub struct ChromeExporter<W: Write> {
+    events: EventWriter<W>,
+    field_schema: FieldSchema,
+    // Process and thread of each slot
+    tids: BTreeMap<EntryID, (usize, usize)>,
+}
+
+impl<W: Write> ChromeExporter<W> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            events: EventWriter {
+                writer,
+                first: true,
+            },
+            field_schema: FieldSchema::new(),
+            tids: BTreeMap::new(),
+        }
+    }
+}
+
+impl<W: Write> Exporter for ChromeExporter<W> {
+    type Error = io::Error;
+
+    fn begin(&mut self, info: &DataSourceInfo, entries: &[ExportEntry]) -> io::Result<()> {
+        self.field_schema = info.field_schema.clone();
+
+        let events = &mut self.events;
+        events
+            .writer
+            .write_all(b"{\"displayTimeUnit\": \"ns\", \"traceEvents\": [\n")?;
+
+        // Slots directly under the root panel all go in one process
+        let mut pids = BTreeMap::new();
+        for entry in entries {
+            let (key, name) = entry
+                .panels
+                .first()
+                .map_or(("", "Profile"), |(short_name, long_name)| {
+                    (short_name.as_str(), long_name.as_str())
+                });
+            let next_pid = pids.len() + 1;
+            let (pid, threads) = pids.entry(key).or_insert((next_pid, 0));
+            if *threads == 0 {
+                events.write(json!({
+                    "name": "process_name", "ph": "M", "pid": *pid,
+                    "args": {"name": name},
+                }))?;
+                events.write(json!({
+                    "name": "process_sort_index", "ph": "M", "pid": *pid,
+                    "args": {"sort_index": *pid},
+                }))?;
+            }
+            *threads += 1;
+            let tid = *threads;
+            events.write(json!({
+                "name": "thread_name", "ph": "M", "pid": *pid, "tid": tid,
+                "args": {"name": entry.short_name},
+            }))?;
+            events.write(json!({
+                "name": "thread_sort_index", "ph": "M", "pid": *pid, "tid": tid,
+                "args": {"sort_index": tid},
+            }))?;
+            self.tids.insert(entry.entry_id.clone(), (*pid, tid));
+        }
+        Ok(())
+    }
+
+    fn write_slot(&mut self, entry: &ExportEntry, items: Vec<ExportItem>) -> io::Result<()> {
+        let (pid, tid) = self.tids[&entry.entry_id];
+        for item in items {
+            let mut args = Map::new();
+            for (field_id, field, _) in &item.meta.fields {
+                if let Some(name) = self.field_schema.get_name(*field_id) {
+                    args.insert(name.to_owned(), to_value(field));
+                }
+            }
+            self.events.write(json!({
+                "name": item.meta.title,
+                "ph": "X",
+                "ts": to_us(item.interval.start.0),
+                "dur": to_us(item.interval.duration_ns()),
+                "pid": pid,
+                "tid": tid,
+                "args": args,
+            }))?;
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.events.writer.write_all(b"\n]}\n")?;
+        self.events.writer.flush()
+    }
+}
+
+// Writes the items of every slot as complete (X) events in the Chrome trace
+// event format, for chrome://tracing, Perfetto and the like. Each top-level
+// panel (usually a node) becomes a process, named by its long name, and each
+// slot a thread, named by its short name. The fields of each item become the
+// arguments of its event.
+pub fn export_chrome_trace<T: DeferredDataSource + 'static>(
+    data_source: T,
+    path: impl AsRef<Path>,
+) -> io::Result<()> {
+    let writer = BufWriter::new(File::create(path)?);
+    write_chrome_trace(data_source, writer)
+}
+
+pub fn write_chrome_trace<T: DeferredDataSource + 'static>(
+    data_source: T,
+    writer: impl Write,
+) -> io::Result<()> {
+    export(data_source, &mut ChromeExporter::new(writer))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::chrome_data::parse_chrome_trace;
+    use crate::data::{DataSource, EntryID, EntryInfo, TileID};
+    use crate::deferred_data::DeferredDataSourceWrapper;
+    use crate::timestamp::{Interval, Timestamp};
+
+    #[test]
+    fn test_chrome_round_trip() {
+        let json = br#"[
+            {"name": "process_name", "ph": "M", "pid": 7, "args": {"name": "node"}},
+            {"name": "thread_name", "ph": "M", "pid": 7, "tid": 2, "args": {"name": "worker"}},
+            {"name": "outer", "ph": "X", "ts": 0, "dur": 10, "pid": 7, "tid": 2},
+            {"name": "inner", "ph": "X", "ts": 1.5, "dur": 2, "pid": 7, "tid": 2,
+             "args": {"bytes": 64}},
+            {"name": "main", "ph": "X", "ts": 0, "dur": 5, "pid": 7, "tid": 1}
+        ]"#;
+        let ds = parse_chrome_trace("test.json", json).unwrap();
+
+        let mut output = Vec::new();
+        write_chrome_trace(DeferredDataSourceWrapper::new(ds), &mut output).unwrap();
+        let ds = parse_chrome_trace("export.json", &output).unwrap();
+
+        let info = ds.fetch_info();
+        let EntryInfo::Panel { long_name, .. } =
+            info.entry_info.get(&EntryID::root().child(0)).unwrap()
+        else {
+            panic!("expected a panel");
+        };
+        assert_eq!(long_name, "node");
+
+        let worker = EntryID::root().child(0).child(0).child(1);
+        let Some(EntryInfo::Slot { short_name, .. }) = info.entry_info.get(&worker) else {
+            panic!("expected a slot");
+        };
+        assert_eq!(short_name, "worker");
+
+        let meta = ds.fetch_slot_meta_tile(&worker, TileID(info.interval), true);
+        let items: Vec<_> = meta.data.items.iter().flatten().collect();
+        assert_eq!(items.len(), 2);
+        let inner = items.iter().find(|item| item.title == "inner").unwrap();
+        assert_eq!(
+            inner.original_interval,
+            Interval::new(Timestamp(1500), Timestamp(3500))
+        );
+        let bytes = info.field_schema.get_id("bytes").unwrap();
+        assert!(
+            inner
+                .fields
+                .iter()
+                .any(|(field_id, field, _)| *field_id == bytes && matches!(field, Field::U64(64)))
+        );
+    }
+}
@@ -0,0 +1,233 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use crate::data::{DataSourceInfo, FieldID, FieldSchema};
+use crate::deferred_data::DeferredDataSource;
+use crate::export::{ExportEntry, ExportItem, Exporter, export, field_text};
+
+// Columns that every item has. Anything else names a field
+const STANDARD_COLUMNS: [&str; 5] = ["entry", "title", "start", "stop", "duration"];
+
+#[derive(Debug, Clone)]
+pub struct CSVConfig {
+    // Usually b',' or, for TSV, b'\t'
+    pub delimiter: u8,
+    // Standard columns (see STANDARD_COLUMNS) or field names, in the order to
+    // write them. By default, the standard columns followed by every field
+    pub columns: Option<Vec<String>>,
+}
+
+impl Default for CSVConfig {
+    fn default() -> Self {
+        Self {
+            delimiter: b',',
+            columns: None,
+        }
+    }
+}
+
+enum Column {
+    Entry,
+    Title,
+    Start,
+    Stop,
+    Duration,
+    Field(FieldID),
+}
+
+impl Column {
+    fn parse(name: &str, field_schema: &FieldSchema) -> io::Result<Self> {
+        Ok(match name {
+            "entry" => Column::Entry,
+            "title" => Column::Title,
+            "start" => Column::Start,
+            "stop" => Column::Stop,
+            "duration" => Column::Duration,
+            _ => Column::Field(field_schema.get_id(name).ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("unknown column {name:?}"),
+                )
+            })?),
+        })
+    }
+
+    fn value(&self, entry: &ExportEntry, item: &ExportItem) -> String {
+        match self {
+            Column::Entry => entry.path(),
+            Column::Title => item.meta.title.clone(),
+            Column::Start => item.interval.start.0.to_string(),
+            Column::Stop => item.interval.stop.0.to_string(),
+            Column::Duration => item.interval.duration_ns().to_string(),
+            Column::Field(field_id) => item
+                .meta
+                .fields
+                .iter()
+                .find(|(id, _, _)| id == field_id)
+                .map(|(_, field, _)| field_text(field))
+                .unwrap_or_default(),
+        }
+    }
+}
+
+fn write_row(writer: &mut impl Write, delimiter: u8, values: &[String]) -> io::Result<()> {
+    for (i, value) in values.iter().enumerate() {
+        if i > 0 {
+            writer.write_all(&[delimiter])?;
+        }
+        // Quote anything that would otherwise be misread, doubling quotes
+        if value
+            .bytes()
+            .any(|b| b == delimiter || b == b'"' || b == b'\n' || b == b'\r')
+        {
+            write!(writer, "\"{}\"", value.replace('"', "\"\""))?;
+        } else {
+            writer.write_all(value.as_bytes())?;
+        }
+    }
+    writer.write_all(b"\n")
+}
+
+pub struct CSVExporter<W: Write> {
+    writer: W,
+    config: CSVConfig,
+    columns: Vec<Column>,
+}
+
+impl<W: Write> CSVExporter<W> {
+    pub fn new(writer: W, config: CSVConfig) -> Self {
+        Self {
+            writer,
+            config,
+            columns: Vec::new(),
+        }
+    }
+}
+
+impl<W: Write> Exporter for CSVExporter<W> {
+    type Error = io::Error;
+
+    fn begin(&mut self, info: &DataSourceInfo, _entries: &[ExportEntry]) -> io::Result<()> {
+        let names: Vec<String> = match &self.config.columns {
+            Some(columns) => columns.clone(),
+            None => STANDARD_COLUMNS
+                .iter()
+                .map(|name| name.to_string())
+                .chain(info.field_schema.iter().map(|(_, name)| name.to_owned()))
+                .collect(),
+        };
+        self.columns = names
+            .iter()
+            .map(|name| Column::parse(name, &info.field_schema))
+            .collect::<io::Result<_>>()?;
+
+        write_row(&mut self.writer, self.config.delimiter, &names)
+    }
+
+    fn write_slot(&mut self, entry: &ExportEntry, items: Vec<ExportItem>) -> io::Result<()> {
+        for item in &items {
+            let values: Vec<_> = self
+                .columns
+                .iter()
+                .map(|column| column.value(entry, item))
+                .collect();
+            write_row(&mut self.writer, self.config.delimiter, &values)?;
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+// Writes one row per item, for spreadsheets, pandas and the like. Times are
+// in nanoseconds, and entries are named by their path (e.g., "n0/cpu/cpu0").
+pub fn export_csv<T: DeferredDataSource + 'static>(
+    data_source: T,
+    path: impl AsRef<Path>,
+    config: &CSVConfig,
+) -> io::Result<()> {
+    let writer = BufWriter::new(File::create(path)?);
+    write_csv(data_source, writer, config)
+}
+
+pub fn write_csv<T: DeferredDataSource + 'static>(
+    data_source: T,
+    writer: impl Write,
+    config: &CSVConfig,
+) -> io::Result<()> {
+    export(data_source, &mut CSVExporter::new(writer, config.clone()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::data::Field;
+    use crate::deferred_data::DeferredDataSourceWrapper;
+    use crate::timestamp::{Interval, Timestamp};
+    use crate::trace_data::{TraceBuilder, TraceDataSource, TraceItem};
+
+    fn trace() -> TraceDataSource {
+        let mut builder = TraceBuilder::new("test");
+        let size = builder.field("Size", false);
+        let thread = builder.thread("node", "cpu", "worker");
+        builder.add_item(
+            thread,
+            TraceItem {
+                interval: Interval::new(Timestamp(10), Timestamp(30)),
+                title: "copy, \"fast\"".to_owned(),
+                color: None,
+                fields: vec![(size, Field::U64(64))],
+            },
+        );
+        builder.add_item(
+            thread,
+            TraceItem {
+                interval: Interval::new(Timestamp(0), Timestamp(5)),
+                title: "init".to_owned(),
+                color: None,
+                fields: Vec::new(),
+            },
+        );
+        builder.build()
+    }
+
+    #[test]
+    fn test_csv() {
+        let config = CSVConfig {
+            delimiter: b',',
+            columns: Some(
+                ["entry", "title", "start", "duration", "Size"]
+                    .map(String::from)
+                    .to_vec(),
+            ),
+        };
+        let mut output = Vec::new();
+        write_csv(
+            DeferredDataSourceWrapper::new(trace()),
+            &mut output,
+            &config,
+        )
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "entry,title,start,duration,Size\n\
+             node/cpu/worker,init,0,5,\n\
+             node/cpu/worker,\"copy, \"\"fast\"\"\",10,20,64\n"
+        );
+    }
+
+    #[test]
+    fn test_csv_unknown_column() {
+        let config = CSVConfig {
+            delimiter: b'\t',
+            columns: Some(vec!["Missing".to_owned()]),
+        };
+        let error =
+            write_csv(DeferredDataSourceWrapper::new(trace()), Vec::new(), &config).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+    }
+}
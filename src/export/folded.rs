@@ -0,0 +1,189 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use crate::data::{DataSourceInfo, FieldID};
+use crate::deferred_data::DeferredDataSource;
+use crate::export::{ExportEntry, ExportItem, Exporter, export, field_text};
+
+#[derive(Debug, Clone, Default)]
+pub struct FoldedConfig {
+    // Start each stack with the entry's path, so that every slot gets its own
+    // tower. Otherwise items are added up across the whole profile
+    pub per_entry: bool,
+    // Names of fields whose values go above the title, outermost first
+    // (e.g., "Provenance"). Items without a field skip its frame
+    pub stack: Vec<String>,
+}
+
+// Semicolons split frames and the last space splits off the count, so only
+// the former needs replacing
+fn frame(name: &str) -> String {
+    name.replace(';', ":").replace(['\n', '\r'], " ")
+}
+
+pub struct FoldedExporter<W: Write> {
+    writer: W,
+    config: FoldedConfig,
+    stack_fields: Vec<FieldID>,
+    // Sorted, so that the output is the same from one run to the next
+    totals: BTreeMap<String, i64>,
+}
+
+impl<W: Write> FoldedExporter<W> {
+    pub fn new(writer: W, config: FoldedConfig) -> Self {
+        Self {
+            writer,
+            config,
+            stack_fields: Vec::new(),
+            totals: BTreeMap::new(),
+        }
+    }
+}
+
+impl<W: Write> Exporter for FoldedExporter<W> {
+    type Error = io::Error;
+
+    fn begin(&mut self, info: &DataSourceInfo, _entries: &[ExportEntry]) -> io::Result<()> {
+        self.stack_fields = self
+            .config
+            .stack
+            .iter()
+            .map(|name| {
+                info.field_schema.get_id(name).ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("unknown field {name:?}"),
+                    )
+                })
+            })
+            .collect::<io::Result<_>>()?;
+        Ok(())
+    }
+
+    fn write_slot(&mut self, entry: &ExportEntry, items: Vec<ExportItem>) -> io::Result<()> {
+        let mut prefix = Vec::new();
+        if self.config.per_entry {
+            prefix.extend(entry.panels.iter().map(|(short_name, _)| frame(short_name)));
+            prefix.push(frame(&entry.short_name));
+        }
+        for item in &items {
+            let mut frames = prefix.clone();
+            for field_id in &self.stack_fields {
+                let value = item
+                    .meta
+                    .fields
+                    .iter()
+                    .find(|(id, _, _)| id == field_id)
+                    .map(|(_, field, _)| field_text(field))
+                    .unwrap_or_default();
+                if !value.is_empty() {
+                    frames.push(frame(&value));
+                }
+            }
+            frames.push(frame(&item.meta.title));
+            *self.totals.entry(frames.join(";")).or_insert(0) += item.interval.duration_ns();
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        for (stack, total) in &self.totals {
+            if *total > 0 {
+                writeln!(self.writer, "{stack} {total}")?;
+            }
+        }
+        self.writer.flush()
+    }
+}
+
+// Writes the total time of the items with each title, in nanoseconds, as
+// folded stacks ("outer;inner count" per line) for inferno, flamegraph.pl,
+// speedscope and the like.
+pub fn export_folded<T: DeferredDataSource + 'static>(
+    data_source: T,
+    path: impl AsRef<Path>,
+    config: &FoldedConfig,
+) -> io::Result<()> {
+    let writer = BufWriter::new(File::create(path)?);
+    write_folded(data_source, writer, config)
+}
+
+pub fn write_folded<T: DeferredDataSource + 'static>(
+    data_source: T,
+    writer: impl Write,
+    config: &FoldedConfig,
+) -> io::Result<()> {
+    export(
+        data_source,
+        &mut FoldedExporter::new(writer, config.clone()),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::data::Field;
+    use crate::deferred_data::DeferredDataSourceWrapper;
+    use crate::timestamp::{Interval, Timestamp};
+    use crate::trace_data::{TraceBuilder, TraceDataSource, TraceItem};
+
+    fn trace() -> TraceDataSource {
+        let mut builder = TraceBuilder::new("test");
+        let provenance = builder.field("Provenance", false);
+        let cpu = builder.thread("node", "cpu", "cpu0");
+        let gpu = builder.thread("node", "gpu", "gpu0");
+        let items = [
+            (cpu, 0, 10, "init", None),
+            (cpu, 20, 25, "copy", Some("main.py:3")),
+            (gpu, 0, 30, "copy", Some("main.py:3")),
+            (gpu, 40, 42, "copy; fast", None),
+        ];
+        for (thread, start, stop, title, source) in items {
+            builder.add_item(
+                thread,
+                TraceItem {
+                    interval: Interval::new(Timestamp(start), Timestamp(stop)),
+                    title: title.to_owned(),
+                    color: None,
+                    fields: source
+                        .map(|source| (provenance, Field::String(source.to_owned())))
+                        .into_iter()
+                        .collect(),
+                },
+            );
+        }
+        builder.build()
+    }
+
+    fn folded(config: &FoldedConfig) -> String {
+        let mut output = Vec::new();
+        write_folded(DeferredDataSourceWrapper::new(trace()), &mut output, config).unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn test_folded() {
+        assert_eq!(
+            folded(&FoldedConfig::default()),
+            "copy 35\ncopy: fast 2\ninit 10\n"
+        );
+    }
+
+    #[test]
+    fn test_folded_per_entry() {
+        let config = FoldedConfig {
+            per_entry: true,
+            stack: vec!["Provenance".to_owned()],
+        };
+        assert_eq!(
+            folded(&config),
+            "node;cpu;cpu0;init 10\n\
+             node;cpu;cpu0;main.py:3;copy 5\n\
+             node;gpu;gpu0;copy: fast 2\n\
+             node;gpu;gpu0;main.py:3;copy 30\n"
+        );
+    }
+}
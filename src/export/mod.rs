@@ -0,0 +1,958 @@
+use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::File;
+use std::io::{self, BufWriter};
+use std::path::Path;
+
+use egui::Color32;
+#[cfg(feature = "nvtxw")]
+use regex::Regex;
+
+use crate::async_data::{
+    AsyncDeferredDataSource, BoxFuture, DeferredDataSourceAsyncWrapper, block_on,
+    block_on_each_with_idle,
+};
+use crate::data::{
+    DataSourceInfo, EntryID, EntryInfo, Field, ItemMeta, SlotMetaTile, SlotTile, SummaryTile,
+    SummaryUnits, TileID, UtilPoint,
+};
+use crate::deferred_data::{DeferredDataSource, RequestPriority};
+#[cfg(feature = "nvtxw")]
+use crate::nvtxw::{NVTXW, NVTXWOptions};
+use crate::timestamp::Interval;
+
+#[cfg(feature = "chrome")]
+pub mod chrome;
+pub mod csv;
+pub mod folded;
+#[cfg(feature = "otf2")]
+pub mod otf2;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
+
+use csv::CSVConfig;
+use folded::FoldedConfig;
+
+const MAX_IN_FLIGHT_REQUESTS: usize = 100;
+
+// A slot or summary of the profile, along with the panels it sits in
+#[derive(Debug, Clone)]
+pub struct ExportEntry {
+    pub entry_id: EntryID,
+    pub short_name: String,
+    pub long_name: String,
+    // Short and long names of the panels above the entry, outermost first.
+    // The root panel is left out. A summary sits in the panel it summarizes
+    pub panels: Vec<(String, String)>,
+    // What the summary measures, or None for slots
+    pub summary: Option<SummaryUnits>,
+}
+
+impl ExportEntry {
+    // Short names from the outermost panel down, e.g., "n0/cpu/cpu0"
+    pub fn path(&self) -> String {
+        self.panels
+            .iter()
+            .map(|(short_name, _)| short_name.as_str())
+            .chain([self.short_name.as_str()])
+            .collect::<Vec<_>>()
+            .join("/")
+    }
+}
+
+// An item along with its metadata. Items are always whole, even when the
+// source splits them across tiles
+#[derive(Debug, Clone)]
+pub struct ExportItem {
+    pub row: usize,
+    pub interval: Interval,
+    pub color: Color32,
+    pub meta: ItemMeta,
+}
+
+// A tile that failed to load, or didn't match its metadata
+#[derive(Debug, Clone)]
+pub struct TileError {
+    pub entry: String,
+    pub tile_id: TileID,
+    pub message: String,
+}
+
+impl From<TileError> for io::Error {
+    fn from(e: TileError) -> Self {
+        io::Error::other(format!(
+            "failed to export {} over {}: {}",
+            e.entry, e.tile_id.0, e.message
+        ))
+    }
+}
+
+// An output format. The driver (see export_entries) fetches the entries of
+// the profile and hands them over one at a time, in order, so that exporters
+// only have to write them out
+pub trait Exporter {
+    type Error: From<TileError>;
+
+    // Whether to pass summaries to write_summary, as well as slots
+    const SUMMARIES: bool = false;
+
+    // Called before anything else, with every entry that will follow
+    fn begin(
+        &mut self,
+        _info: &DataSourceInfo,
+        _entries: &[ExportEntry],
+    ) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    // Called with the items of each slot, sorted by start time
+    fn write_slot(
+        &mut self,
+        entry: &ExportEntry,
+        items: Vec<ExportItem>,
+    ) -> Result<(), Self::Error>;
+
+    fn write_summary(
+        &mut self,
+        _entry: &ExportEntry,
+        _points: Vec<UtilPoint>,
+    ) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    // Called while waiting on tiles, for exporters with work of their own
+    // in the background. Returns whether it did anything; if not, whoever
+    // gives the exporter more work must unpark this thread
+    fn idle(&mut self) -> Result<bool, Self::Error> {
+        Ok(false)
+    }
+
+    // Called after the last entry, unless the export failed
+    fn finish(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ExportConfig {
+    // Only export this part of the profile, rather than all of it
+    pub interval: Option<Interval>,
+}
+
+// Fields as the viewer shows them
+pub fn field_text(field: &Field) -> String {
+    match field {
+        Field::I64(value) => value.to_string(),
+        Field::U64(value) => value.to_string(),
+        Field::String(value) => value.clone(),
+        Field::Interval(value) => value.to_string(),
+        Field::ItemLink(link) => link.title.clone(),
+        Field::Vec(fields) => fields.iter().map(field_text).collect::<Vec<_>>().join(", "),
+        Field::Empty => String::new(),
+    }
+}
+
+// The part of the profile to export, or None if interval doesn't overlap it
+pub fn clamp_interval(interval: Option<Interval>, profile: Interval) -> Option<Interval> {
+    match interval {
+        Some(interval) => {
+            let clamped = interval.intersection(profile);
+            (interval.start < interval.stop && clamped.duration_ns() > 0).then_some(clamped)
+        }
+        None => Some(profile),
+    }
+}
+
+// Every slot of the profile, in order, and each panel's summary (if
+// summaries) ahead of its slots
+pub fn walk_entries(info: &EntryInfo, summaries: bool) -> Vec<ExportEntry> {
+    fn walk(
+        info: &EntryInfo,
+        entry_id: EntryID,
+        summaries: bool,
+        panels: &mut Vec<(String, String)>,
+        result: &mut Vec<ExportEntry>,
+    ) {
+        match info {
+            EntryInfo::Panel {
+                short_name,
+                long_name,
+                summary,
+                slots,
+            } => {
+                let root = entry_id.level() == 0;
+                if !root {
+                    panels.push((short_name.clone(), long_name.clone()));
+                }
+                if let Some(EntryInfo::Summary { units, .. }) = summary.as_deref() {
+                    if summaries {
+                        let name = match units {
+                            SummaryUnits::Utilization => "Utilization",
+                            SummaryUnits::Watts => "Power",
+                        };
+                        result.push(ExportEntry {
+                            entry_id: entry_id.summary(),
+                            short_name: name.to_owned(),
+                            long_name: format!("{long_name} {name}"),
+                            panels: panels.clone(),
+                            summary: Some(*units),
+                        });
+                    }
+                }
+                for (i, slot) in slots.iter().enumerate() {
+                    walk(slot, entry_id.child(i as u64), summaries, panels, result);
+                }
+                if !root {
+                    panels.pop();
+                }
+            }
+            EntryInfo::Slot {
+                short_name,
+                long_name,
+                ..
+            } => {
+                result.push(ExportEntry {
+                    entry_id,
+                    short_name: short_name.clone(),
+                    long_name: long_name.clone(),
+                    panels: panels.clone(),
+                    summary: None,
+                });
+            }
+            EntryInfo::Summary { .. } => {
+                // Handled by the panel that owns it
+            }
+        }
+    }
+
+    let mut result = Vec::new();
+    walk(
+        info,
+        EntryID::root(),
+        summaries,
+        &mut Vec::new(),
+        &mut result,
+    );
+    result
+}
+
+// Dynamic sources make tiles of any size, but static ones only have the tiles
+// in their tile set, of which only the finest level has every item
+fn tile_ids(info: &DataSourceInfo, entry_id: &EntryID, interval: Interval) -> Vec<TileID> {
+    match info.entry_tile_set(entry_id).tiles.last() {
+        Some(tile_ids) => tile_ids
+            .iter()
+            .copied()
+            .filter(|tile_id| tile_id.0.overlaps(interval))
+            .collect(),
+        None => vec![TileID(interval)],
+    }
+}
+
+fn merge_tiles(tiles: Vec<(SlotTile, SlotMetaTile)>) -> Result<Vec<ExportItem>, (TileID, String)> {
+    // Items that span several tiles show up in each of them
+    let mut seen = BTreeSet::new();
+    let mut result = Vec::new();
+    for (tile, meta_tile) in tiles {
+        let tile_id = tile.tile_id;
+        if tile.data.items.len() != meta_tile.data.items.len() {
+            let message = format!(
+                "tile has {} rows but its metadata has {}",
+                tile.data.items.len(),
+                meta_tile.data.items.len()
+            );
+            return Err((tile_id, message));
+        }
+        for (row, (items, meta_items)) in
+            tile.data.items.iter().zip(meta_tile.data.items).enumerate()
+        {
+            if items.len() != meta_items.len() {
+                let message = format!(
+                    "tile row has {} items but its metadata has {}",
+                    items.len(),
+                    meta_items.len()
+                );
+                return Err((tile_id, message));
+            }
+            for (item, meta) in items.iter().zip(meta_items) {
+                if seen.insert(item.item_uid) {
+                    result.push(ExportItem {
+                        row,
+                        interval: meta.original_interval,
+                        color: item.color,
+                        meta,
+                    });
+                }
+            }
+        }
+    }
+    result.sort_by_key(|item| (item.interval.start, item.row));
+    Ok(result)
+}
+
+// Summary tiles share the points at their edges
+fn merge_summary_tiles(tiles: Vec<SummaryTile>) -> Vec<UtilPoint> {
+    let mut result: Vec<UtilPoint> = Vec::new();
+    for tile in tiles {
+        for point in tile.data.utilization {
+            if result.last().is_none_or(|last| last.time < point.time) {
+                result.push(point);
+            }
+        }
+    }
+    result
+}
+
+enum Fetched {
+    Slot(Vec<ExportItem>),
+    Summary(Vec<UtilPoint>),
+}
+
+type FetchResult = Result<Fetched, (TileID, String)>;
+
+fn fetch_entry<T: DeferredDataSource + 'static>(
+    data_source: &DeferredDataSourceAsyncWrapper<T>,
+    info: &DataSourceInfo,
+    entry: &ExportEntry,
+    interval: Interval,
+) -> BoxFuture<FetchResult> {
+    let entry_id = &entry.entry_id;
+    let tile_ids = tile_ids(info, entry_id, interval);
+    if entry.summary.is_some() {
+        let tiles: Vec<_> = tile_ids
+            .into_iter()
+            .map(|tile_id| {
+                let tile = data_source.fetch_summary_tile(
+                    entry_id,
+                    tile_id,
+                    true,
+                    RequestPriority::Background,
+                );
+                (tile_id, tile)
+            })
+            .collect();
+        return Box::pin(async move {
+            let mut result = Vec::new();
+            for (tile_id, tile) in tiles {
+                result.push(tile.await.map_err(|e| (tile_id, e))?);
+            }
+            Ok(Fetched::Summary(merge_summary_tiles(result)))
+        });
+    }
+
+    let tiles: Vec<_> = tile_ids
+        .into_iter()
+        .map(|tile_id| {
+            let tile =
+                data_source.fetch_slot_tile(entry_id, tile_id, true, RequestPriority::Background);
+            let meta_tile = data_source.fetch_slot_meta_tile(
+                entry_id,
+                tile_id,
+                true,
+                RequestPriority::Background,
+            );
+            (tile_id, tile, meta_tile)
+        })
+        .collect();
+    Box::pin(async move {
+        let mut result = Vec::new();
+        for (tile_id, tile, meta_tile) in tiles {
+            match (tile.await, meta_tile.await) {
+                (Ok(tile), Ok(meta_tile)) => result.push((tile, meta_tile)),
+                (Err(e), _) | (_, Err(e)) => return Err((tile_id, e)),
+            }
+        }
+        merge_tiles(result).map(Fetched::Slot)
+    })
+}
+
+// Fetches the entries over interval and passes them to the exporter, in
+// order. Stops at the first error, though requests already in flight still
+// have to finish
+pub fn export_entries<T: DeferredDataSource + 'static, E: Exporter>(
+    data_source: &DeferredDataSourceAsyncWrapper<T>,
+    info: &DataSourceInfo,
+    entries: &[ExportEntry],
+    interval: Interval,
+    exporter: &mut E,
+) -> Result<(), E::Error> {
+    exporter.begin(info, entries)?;
+
+    let error = RefCell::new(None);
+    let fail = |e: E::Error| {
+        error.borrow_mut().get_or_insert(e);
+    };
+    let failed = || error.borrow().is_some();
+
+    let requests = entries
+        .iter()
+        .enumerate()
+        .take_while(|_| !failed())
+        .map(|(index, entry)| {
+            let fetched = fetch_entry(data_source, info, entry, interval);
+            let request: BoxFuture<(usize, FetchResult)> =
+                Box::pin(async move { (index, fetched.await) });
+            request
+        });
+
+    // Entries finish out of order, so hold on to them until their turn
+    let exporter = RefCell::new(exporter);
+    let mut done = BTreeMap::new();
+    let mut next = 0;
+    block_on_each_with_idle(
+        requests,
+        MAX_IN_FLIGHT_REQUESTS,
+        |(index, fetched)| {
+            if failed() {
+                return;
+            }
+            match fetched {
+                Ok(fetched) => {
+                    done.insert(index, fetched);
+                }
+                Err((tile_id, message)) => {
+                    fail(E::Error::from(TileError {
+                        entry: entries[index].long_name.clone(),
+                        tile_id,
+                        message,
+                    }));
+                    return;
+                }
+            }
+            let mut exporter = exporter.borrow_mut();
+            while let Some(fetched) = done.remove(&next) {
+                let entry = &entries[next];
+                let result = match fetched {
+                    Fetched::Slot(items) => exporter.write_slot(entry, items),
+                    Fetched::Summary(points) => exporter.write_summary(entry, points),
+                };
+                if let Err(e) = result {
+                    fail(e);
+                    return;
+                }
+                next += 1;
+            }
+        },
+        || {
+            if failed() {
+                return false;
+            }
+            exporter.borrow_mut().idle().unwrap_or_else(|e| {
+                fail(e);
+                false
+            })
+        },
+    );
+
+    if let Some(e) = error.into_inner() {
+        return Err(e);
+    }
+    exporter.into_inner().finish()
+}
+
+pub fn export<T: DeferredDataSource + 'static, E: Exporter<Error = io::Error>>(
+    data_source: T,
+    exporter: &mut E,
+) -> io::Result<()> {
+    export_with_config(data_source, exporter, &ExportConfig::default())
+}
+
+pub fn export_with_config<T: DeferredDataSource + 'static, E: Exporter<Error = io::Error>>(
+    data_source: T,
+    exporter: &mut E,
+    config: &ExportConfig,
+) -> io::Result<()> {
+    let data_source = DeferredDataSourceAsyncWrapper::new(data_source);
+    let info = block_on(data_source.fetch_info()).map_err(io::Error::other)?;
+
+    // Titles and fields come from the item metadata
+    if !info.capabilities.slot_meta_tiles {
+        return Err(io::Error::other(
+            "data source does not provide item metadata, which export requires",
+        ));
+    }
+
+    let interval = clamp_interval(config.interval, info.interval).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "export interval does not overlap the profile",
+        )
+    })?;
+
+    let entries = walk_entries(
+        &info.entry_info,
+        E::SUMMARIES && info.capabilities.summary_tiles,
+    );
+    export_entries(&data_source, &info, &entries, interval, exporter)
+}
+
+// Slots along with their items, e.g., the ones a viewer has already loaded
+pub type ExportSlots = Vec<(ExportEntry, Vec<ExportItem>)>;
+
+// Writes items that were already fetched, rather than fetching them from a
+// data source. Items of each slot must be sorted by start time
+pub fn export_items<E: Exporter>(
+    info: &DataSourceInfo,
+    slots: ExportSlots,
+    exporter: &mut E,
+) -> Result<(), E::Error> {
+    let entries: Vec<_> = slots.iter().map(|(entry, _)| entry.clone()).collect();
+    exporter.begin(info, &entries)?;
+    for (entry, items) in slots {
+        exporter.write_slot(&entry, items)?;
+    }
+    exporter.finish()
+}
+
+// Options for the formats that have any
+#[derive(Debug, Clone, Default)]
+pub struct ExportOptions {
+    pub csv: CSVConfig,
+    pub folded: FoldedConfig,
+    #[cfg(feature = "nvtxw")]
+    pub nvtxw: NVTXWOptions,
+}
+
+// A format that profiles can be exported to, by name
+pub struct ExportFormat {
+    pub name: &'static str,
+    pub description: &'static str,
+    // Usual extension of the output, or "" for a directory
+    pub extension: &'static str,
+    pub export: fn(Box<dyn DeferredDataSource>, &Path, &ExportOptions) -> io::Result<()>,
+    // Formats that can be written from items that were already fetched (see
+    // export_items)
+    pub export_items: Option<ExportItemsFn>,
+}
+
+pub type ExportItemsFn = fn(&DataSourceInfo, ExportSlots, &Path, &ExportOptions) -> io::Result<()>;
+
+fn create(path: &Path) -> io::Result<BufWriter<File>> {
+    Ok(BufWriter::new(File::create(path)?))
+}
+
+// Every format built into this binary
+pub const FORMATS: &[ExportFormat] = &[
+    #[cfg(feature = "chrome")]
+    ExportFormat {
+        name: "chrome",
+        description: "Chrome trace events, for chrome://tracing and Perfetto",
+        extension: "json",
+        export: |data_source, path, _| chrome::export_chrome_trace(data_source, path),
+        export_items: Some(|info, slots, path, _| {
+            export_items(info, slots, &mut chrome::ChromeExporter::new(create(path)?))
+        }),
+    },
+    ExportFormat {
+        name: "csv",
+        description: "one row per item, comma separated",
+        extension: "csv",
+        export: |data_source, path, options| csv::export_csv(data_source, path, &options.csv),
+        export_items: Some(|info, slots, path, options| {
+            let exporter = &mut csv::CSVExporter::new(create(path)?, options.csv.clone());
+            export_items(info, slots, exporter)
+        }),
+    },
+    ExportFormat {
+        name: "folded",
+        description: "time per title as folded stacks, for flame graphs",
+        extension: "folded",
+        export: |data_source, path, options| {
+            folded::export_folded(data_source, path, &options.folded)
+        },
+        export_items: Some(|info, slots, path, options| {
+            let exporter = &mut folded::FoldedExporter::new(create(path)?, options.folded.clone());
+            export_items(info, slots, exporter)
+        }),
+    },
+    #[cfg(feature = "nvtxw")]
+    ExportFormat {
+        name: "nvtxw",
+        description: "Nsight Systems report, written through NVTXW",
+        extension: "nsys-rep",
+        export: |data_source, path, options| {
+            let nvtxw = &options.nvtxw;
+            NVTXW::with_config(
+                data_source,
+                nvtxw.backend.clone(),
+                path.as_os_str().to_owned(),
+                nvtxw.force,
+                nvtxw.merge.clone(),
+                nvtxw.zero_time,
+                nvtxw.config.clone(),
+            )
+            .write()
+            .map_err(io::Error::from)
+        },
+        // NVTXW writes from several threads, which only the full export sets up
+        export_items: None,
+    },
+    #[cfg(feature = "otf2")]
+    ExportFormat {
+        name: "otf2",
+        description: "OTF2 archive, for Vampir and the Score-P tools",
+        extension: "",
+        export: |data_source, path, _| otf2::export_otf2(data_source, path),
+        export_items: None,
+    },
+    // Perfetto opens Chrome's JSON directly, so this is only another name for
+    // it, for those who go looking for Perfetto
+    #[cfg(feature = "chrome")]
+    ExportFormat {
+        name: "perfetto",
+        description: "the same as chrome",
+        extension: "json",
+        export: |data_source, path, _| chrome::export_chrome_trace(data_source, path),
+        export_items: Some(|info, slots, path, _| {
+            export_items(info, slots, &mut chrome::ChromeExporter::new(create(path)?))
+        }),
+    },
+    #[cfg(feature = "sqlite")]
+    ExportFormat {
+        name: "sqlite",
+        description: "SQLite database of entries, items and fields",
+        extension: "db",
+        export: |data_source, path, _| sqlite::export_sqlite(data_source, path),
+        export_items: Some(|info, slots, path, _| {
+            let mut connection = sqlite::create_database(path)?;
+            export_items(
+                info,
+                slots,
+                &mut sqlite::SQLiteExporter::new(&mut connection)?,
+            )
+        }),
+    },
+    ExportFormat {
+        name: "tsv",
+        description: "one row per item, tab separated",
+        extension: "tsv",
+        export: |data_source, path, options| {
+            let config = CSVConfig {
+                delimiter: b'\t',
+                ..options.csv.clone()
+            };
+            csv::export_csv(data_source, path, &config)
+        },
+        export_items: Some(|info, slots, path, options| {
+            let config = CSVConfig {
+                delimiter: b'\t',
+                ..options.csv.clone()
+            };
+            export_items(
+                info,
+                slots,
+                &mut csv::CSVExporter::new(create(path)?, config),
+            )
+        }),
+    },
+];
+
+pub fn find_format(name: &str) -> Option<&'static ExportFormat> {
+    FORMATS.iter().find(|format| format.name == name)
+}
+
+// A command line option for some of the formats, e.g., "--columns"
+pub struct ExportOption {
+    pub flag: &'static str,
+    // What the value is called in help text, or None for switches
+    pub value: Option<&'static str>,
+    pub help: &'static str,
+    // Names of the formats it applies to
+    pub formats: &'static [&'static str],
+    // Switches are set with an empty value
+    pub set: fn(&mut ExportOptions, &str) -> Result<(), String>,
+}
+
+impl ExportOption {
+    pub fn applies_to(&self, format: &str) -> bool {
+        self.formats.contains(&format)
+    }
+}
+
+// Comma separated names, e.g., "title,start,Provenance"
+fn parse_list(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .map(str::to_owned)
+        .collect()
+}
+
+#[cfg(feature = "nvtxw")]
+fn parse_regex(value: &str) -> Result<Regex, String> {
+    Regex::new(value).map_err(|e| format!("invalid regex {value:?}: {e}"))
+}
+
+#[cfg(feature = "nvtxw")]
+fn parse_number<N: std::str::FromStr>(value: &str) -> Result<N, String> {
+    value
+        .parse()
+        .map_err(|_| format!("invalid number {value:?}"))
+}
+
+// Options of every format built into this binary. Only those of formats that
+// are built in are listed
+pub const OPTIONS: &[ExportOption] = &[
+    ExportOption {
+        flag: "--columns",
+        value: Some("LIST"),
+        help: "columns: entry, title, start, stop, duration or field names",
+        formats: &["csv", "tsv"],
+        set: |options, value| {
+            options.csv.columns = Some(parse_list(value));
+            Ok(())
+        },
+    },
+    ExportOption {
+        flag: "--per-entry",
+        value: None,
+        help: "start each stack with the entry's path",
+        formats: &["folded"],
+        set: |options, _| {
+            options.folded.per_entry = true;
+            Ok(())
+        },
+    },
+    ExportOption {
+        flag: "--stack",
+        value: Some("LIST"),
+        help: "fields to put above each title, outermost first",
+        formats: &["folded"],
+        set: |options, value| {
+            options.folded.stack = parse_list(value);
+            Ok(())
+        },
+    },
+    #[cfg(feature = "nvtxw")]
+    ExportOption {
+        flag: "--backend",
+        value: Some("PATH"),
+        help: "NVTXW backend library, rather than the default",
+        formats: &["nvtxw"],
+        set: |options, value| {
+            options.nvtxw.backend = Some(value.into());
+            Ok(())
+        },
+    },
+    #[cfg(feature = "nvtxw")]
+    ExportOption {
+        flag: "--force",
+        value: None,
+        help: "replace the report if it exists",
+        formats: &["nvtxw"],
+        set: |options, _| {
+            options.nvtxw.force = true;
+            Ok(())
+        },
+    },
+    #[cfg(feature = "nvtxw")]
+    ExportOption {
+        flag: "--merge-into",
+        value: Some("REPORT"),
+        help: "add the profile to an existing Nsight Systems report",
+        formats: &["nvtxw"],
+        set: |options, value| {
+            options.nvtxw.merge = Some(value.into());
+            Ok(())
+        },
+    },
+    #[cfg(feature = "nvtxw")]
+    ExportOption {
+        flag: "--zero-time",
+        value: Some("NS"),
+        help: "time that the profile starts at in the report",
+        formats: &["nvtxw"],
+        set: |options, value| {
+            options.nvtxw.zero_time = parse_number(value)?;
+            Ok(())
+        },
+    },
+    #[cfg(feature = "nvtxw")]
+    ExportOption {
+        flag: "--include",
+        value: Some("REGEX"),
+        help: "only export entries whose name or path matches",
+        formats: &["nvtxw"],
+        set: |options, value| {
+            options.nvtxw.config.include = Some(parse_regex(value)?);
+            Ok(())
+        },
+    },
+    #[cfg(feature = "nvtxw")]
+    ExportOption {
+        flag: "--exclude",
+        value: Some("REGEX"),
+        help: "skip entries whose name or path matches",
+        formats: &["nvtxw"],
+        set: |options, value| {
+            options.nvtxw.config.exclude = Some(parse_regex(value)?);
+            Ok(())
+        },
+    },
+    #[cfg(feature = "nvtxw")]
+    ExportOption {
+        flag: "--threads",
+        value: Some("N"),
+        help: "threads that turn tiles into events (0 means one per core)",
+        formats: &["nvtxw"],
+        set: |options, value| {
+            options.nvtxw.config.threads = parse_number(value)?;
+            Ok(())
+        },
+    },
+    #[cfg(feature = "nvtxw")]
+    ExportOption {
+        flag: "--quiet",
+        value: None,
+        help: "don't report progress",
+        formats: &["nvtxw"],
+        set: |options, _| {
+            options.nvtxw.config.quiet = true;
+            Ok(())
+        },
+    },
+];
+
+pub fn find_option(flag: &str) -> Option<&'static ExportOption> {
+    OPTIONS.iter().find(|option| option.flag == flag)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::data::{DataSource, ItemUID};
+    use crate::deferred_data::DeferredDataSourceWrapper;
+    use crate::timestamp::Timestamp;
+    use crate::trace_data::{TraceBuilder, TraceItem};
+
+    #[derive(Default)]
+    struct Recorder {
+        entries: Vec<String>,
+        slots: Vec<(String, Vec<String>)>,
+        finished: bool,
+    }
+
+    impl Exporter for Recorder {
+        type Error = io::Error;
+
+        fn begin(&mut self, _info: &DataSourceInfo, entries: &[ExportEntry]) -> io::Result<()> {
+            self.entries = entries.iter().map(ExportEntry::path).collect();
+            Ok(())
+        }
+
+        fn write_slot(&mut self, entry: &ExportEntry, items: Vec<ExportItem>) -> io::Result<()> {
+            let titles = items.into_iter().map(|item| item.meta.title).collect();
+            self.slots.push((entry.path(), titles));
+            Ok(())
+        }
+
+        fn finish(&mut self) -> io::Result<()> {
+            self.finished = true;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_export() {
+        let mut builder = TraceBuilder::new("test");
+        let threads = [
+            builder.thread("node", "cpu", "cpu0"),
+            builder.thread("node", "cpu", "cpu1"),
+        ];
+        for (start, title) in [(20, "late"), (0, "early"), (10, "middle")] {
+            builder.add_item(
+                threads[0],
+                TraceItem {
+                    interval: Interval::new(Timestamp(start), Timestamp(start + 5)),
+                    title: title.to_owned(),
+                    color: None,
+                    fields: Vec::new(),
+                },
+            );
+        }
+        let ds = DeferredDataSourceWrapper::new(builder.build());
+
+        let mut recorder = Recorder::default();
+        let config = ExportConfig {
+            interval: Some(Interval::new(Timestamp(8), Timestamp(100))),
+        };
+        export_with_config(ds, &mut recorder, &config).unwrap();
+        assert_eq!(recorder.entries, ["node/cpu/cpu0", "node/cpu/cpu1"]);
+        assert_eq!(
+            recorder.slots,
+            [
+                (
+                    "node/cpu/cpu0".to_owned(),
+                    vec!["middle".to_owned(), "late".to_owned()]
+                ),
+                ("node/cpu/cpu1".to_owned(), Vec::new()),
+            ]
+        );
+        assert!(recorder.finished);
+    }
+
+    #[test]
+    fn test_export_items() {
+        let mut builder = TraceBuilder::new("test");
+        builder.thread("node", "cpu", "cpu0");
+        let info = builder.build().fetch_info();
+        let entry = walk_entries(&info.entry_info, false).remove(0);
+        let interval = Interval::new(Timestamp(0), Timestamp(5));
+        let item = ExportItem {
+            row: 0,
+            interval,
+            color: Color32::RED,
+            meta: ItemMeta {
+                item_uid: ItemUID(7),
+                original_interval: interval,
+                title: "copy".to_owned(),
+                fields: Vec::new(),
+            },
+        };
+
+        let mut recorder = Recorder::default();
+        export_items(&info, vec![(entry, vec![item])], &mut recorder).unwrap();
+        assert_eq!(recorder.entries, ["node/cpu/cpu0"]);
+        assert_eq!(
+            recorder.slots,
+            [("node/cpu/cpu0".to_owned(), vec!["copy".to_owned()])]
+        );
+        assert!(recorder.finished);
+    }
+
+    #[test]
+    fn test_clamp_interval() {
+        let profile = Interval::new(Timestamp(0), Timestamp(100));
+        let interval = |start, stop| Interval::new(Timestamp(start), Timestamp(stop));
+        assert_eq!(clamp_interval(None, profile), Some(profile));
+        assert_eq!(
+            clamp_interval(Some(interval(50, 200)), profile),
+            Some(interval(50, 100))
+        );
+        assert_eq!(clamp_interval(Some(interval(200, 300)), profile), None);
+    }
+
+    #[test]
+    fn test_options() {
+        let mut options = ExportOptions::default();
+        let columns = find_option("--columns").unwrap();
+        assert!(columns.applies_to("tsv") && !columns.applies_to("folded"));
+        (columns.set)(&mut options, "title, start,,Size").unwrap();
+        assert_eq!(
+            options.csv.columns,
+            Some(["title", "start", "Size"].map(String::from).to_vec())
+        );
+        assert!(find_option("--format").is_none());
+        // Every option belongs to some format that's built in
+        for option in OPTIONS {
+            assert!(
+                option
+                    .formats
+                    .iter()
+                    .any(|name| find_format(name).is_some())
+            );
+        }
+    }
+}
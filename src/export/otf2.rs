@@ -0,0 +1,573 @@
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+use std::ffi::{CStr, CString, OsString, c_char, c_void};
+use std::io;
+use std::path::Path;
+use std::ptr;
+
+use libloading::Library;
+
+use crate::data::DataSourceInfo;
+use crate::deferred_data::DeferredDataSource;
+use crate::export::{ExportEntry, ExportItem, Exporter, export};
+use crate::timestamp::{Interval, Timestamp};
+
+// Constants from the OTF2 headers
+type ErrorCode = i32;
+const SUCCESS: ErrorCode = 0;
+const FILEMODE_WRITE: u8 = 0;
+const SUBSTRATE_POSIX: u8 = 1;
+const COMPRESSION_NONE: u8 = 1;
+const CHUNK_SIZE_EVENTS: u64 = 1024 * 1024;
+const CHUNK_SIZE_DEFINITIONS: u64 = 4 * 1024 * 1024;
+const FLUSH: u8 = 1;
+const UNDEFINED_REF: u32 = u32::MAX;
+const UNDEFINED_TIMESTAMP: u64 = u64::MAX;
+const REGION_ROLE_FUNCTION: u8 = 1;
+const PARADIGM_USER: u8 = 1;
+const REGION_FLAG_NONE: u32 = 0;
+const LOCATION_GROUP_TYPE_PROCESS: u8 = 1;
+const LOCATION_TYPE_CPU_THREAD: u8 = 1;
+
+// Loads each function from the library by name
+macro_rules! api {
+    ($($name:ident: fn($($arg:ty),*) -> $ret:ty;)*) => {
+        #[allow(non_snake_case)]
+        struct Api {
+            $($name: unsafe extern "C" fn($($arg),*) -> $ret,)*
+            _library: Library,
+        }
+
+        impl Api {
+            #[allow(non_snake_case)]
+            fn load(library: Library) -> io::Result<Self> {
+                $(
+                    let $name = unsafe {
+                        *library
+                            .get(concat!(stringify!($name), "\0").as_bytes())
+                            .map_err(io::Error::other)?
+                    };
+                )*
+                Ok(Self { $($name,)* _library: library })
+            }
+        }
+    };
+}
+
+// Signatures are those of OTF2 3, which added arguments to the clock
+// properties and location group definitions
+api! {
+    OTF2_Archive_Open: fn(*const c_char, *const c_char, u8, u64, u64, u8, u8) -> *mut c_void;
+    OTF2_Archive_Close: fn(*mut c_void) -> ErrorCode;
+    OTF2_Archive_GetVersion: fn(*mut c_void, *mut u8, *mut u8, *mut u8) -> ErrorCode;
+    OTF2_Archive_SetFlushCallbacks:
+        fn(*mut c_void, *const FlushCallbacks, *mut c_void) -> ErrorCode;
+    OTF2_Archive_SetSerialCollectiveCallbacks: fn(*mut c_void) -> ErrorCode;
+    OTF2_Archive_SetCreator: fn(*mut c_void, *const c_char) -> ErrorCode;
+    OTF2_Archive_OpenEvtFiles: fn(*mut c_void) -> ErrorCode;
+    OTF2_Archive_CloseEvtFiles: fn(*mut c_void) -> ErrorCode;
+    OTF2_Archive_GetEvtWriter: fn(*mut c_void, u64) -> *mut c_void;
+    OTF2_Archive_CloseEvtWriter: fn(*mut c_void, *mut c_void) -> ErrorCode;
+    OTF2_Archive_OpenDefFiles: fn(*mut c_void) -> ErrorCode;
+    OTF2_Archive_CloseDefFiles: fn(*mut c_void) -> ErrorCode;
+    OTF2_Archive_GetDefWriter: fn(*mut c_void, u64) -> *mut c_void;
+    OTF2_Archive_CloseDefWriter: fn(*mut c_void, *mut c_void) -> ErrorCode;
+    OTF2_Archive_GetGlobalDefWriter: fn(*mut c_void) -> *mut c_void;
+    OTF2_Archive_CloseGlobalDefWriter: fn(*mut c_void, *mut c_void) -> ErrorCode;
+    OTF2_EvtWriter_Enter: fn(*mut c_void, *mut c_void, u64, u32) -> ErrorCode;
+    OTF2_EvtWriter_Leave: fn(*mut c_void, *mut c_void, u64, u32) -> ErrorCode;
+    OTF2_GlobalDefWriter_WriteClockProperties: fn(*mut c_void, u64, u64, u64, u64) -> ErrorCode;
+    OTF2_GlobalDefWriter_WriteString: fn(*mut c_void, u32, *const c_char) -> ErrorCode;
+    OTF2_GlobalDefWriter_WriteRegion:
+        fn(*mut c_void, u32, u32, u32, u32, u8, u8, u32, u32, u32, u32) -> ErrorCode;
+    OTF2_GlobalDefWriter_WriteSystemTreeNode: fn(*mut c_void, u32, u32, u32, u32) -> ErrorCode;
+    OTF2_GlobalDefWriter_WriteLocationGroup:
+        fn(*mut c_void, u32, u32, u8, u32, u32) -> ErrorCode;
+    OTF2_GlobalDefWriter_WriteLocation: fn(*mut c_void, u64, u32, u8, u64, u32) -> ErrorCode;
+    OTF2_Error_GetName: fn(ErrorCode) -> *const c_char;
+}
+
+// Calls an OTF2 function, turning its error code into an io::Error
+macro_rules! call {
+    ($api:expr, $name:ident($($arg:expr),* $(,)?)) => {
+        $api.check(unsafe { ($api.$name)($($arg),*) }, stringify!($name))
+    };
+}
+
+impl Api {
+    fn check(&self, code: ErrorCode, call: &str) -> io::Result<()> {
+        if code == SUCCESS {
+            return Ok(());
+        }
+        let name = unsafe { (self.OTF2_Error_GetName)(code) };
+        let name = if name.is_null() {
+            code.to_string()
+        } else {
+            unsafe { CStr::from_ptr(name) }
+                .to_string_lossy()
+                .into_owned()
+        };
+        Err(io::Error::other(format!("{call} failed: {name}")))
+    }
+
+    fn check_handle(&self, handle: *mut c_void, call: &str) -> io::Result<*mut c_void> {
+        if handle.is_null() {
+            return Err(io::Error::other(format!("{call} failed")));
+        }
+        Ok(handle)
+    }
+}
+
+#[repr(C)]
+struct FlushCallbacks {
+    pre_flush: Option<unsafe extern "C" fn(*mut c_void, u8, u64, *mut c_void, bool) -> u8>,
+    post_flush: Option<unsafe extern "C" fn(*mut c_void, u8, u64) -> u64>,
+}
+
+extern "C" fn pre_flush(_: *mut c_void, _: u8, _: u64, _: *mut c_void, _: bool) -> u8 {
+    FLUSH
+}
+
+// OTF2 keeps a pointer to these. Without a post flush callback, it doesn't
+// record flushes in the trace
+static FLUSH_CALLBACKS: FlushCallbacks = FlushCallbacks {
+    pre_flush: Some(pre_flush),
+    post_flush: None,
+};
+
+// Closes the archive even when the export fails part way
+struct Archive<'a> {
+    api: &'a Api,
+    handle: *mut c_void,
+}
+
+impl Archive<'_> {
+    fn close(self) -> io::Result<()> {
+        let (api, handle) = (self.api, self.handle);
+        std::mem::forget(self);
+        call!(api, OTF2_Archive_Close(handle))
+    }
+}
+
+impl Drop for Archive<'_> {
+    fn drop(&mut self) {
+        let _ = call!(self.api, OTF2_Archive_Close(self.handle));
+    }
+}
+
+// Titles and names are arbitrary text, so drop any NULs rather than fail
+fn c_string(s: &str) -> CString {
+    CString::new(s.replace('\0', "")).expect("NULs were removed")
+}
+
+#[derive(Default)]
+struct Strings {
+    ids: BTreeMap<String, u32>,
+    strings: Vec<CString>,
+}
+
+impl Strings {
+    fn id(&mut self, s: &str) -> u32 {
+        if let Some(id) = self.ids.get(s) {
+            return *id;
+        }
+        let id = self.strings.len() as u32;
+        self.ids.insert(s.to_owned(), id);
+        self.strings.push(c_string(s));
+        id
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Event {
+    // Time and index of the item
+    Enter(Timestamp, usize),
+    Leave(Timestamp, usize),
+}
+
+#[derive(Default)]
+struct Lane {
+    // Stops and indices of the items open on the lane, innermost last
+    open: Vec<(Timestamp, usize)>,
+    events: Vec<Event>,
+}
+
+impl Lane {
+    fn leave_until(&mut self, time: Option<Timestamp>) {
+        while let Some(&(stop, index)) = self.open.last() {
+            if time.is_some_and(|time| stop > time) {
+                break;
+            }
+            self.open.pop();
+            self.events.push(Event::Leave(stop, index));
+        }
+    }
+}
+
+// Enters and leaves have to nest on each location, but items on different
+// rows of a slot can overlap without nesting. Each item goes on the first
+// lane where it nests, and each lane becomes a location
+fn nest(intervals: &[Interval]) -> Vec<Vec<Event>> {
+    let mut order: Vec<_> = (0..intervals.len()).collect();
+    order.sort_by_key(|&index| (intervals[index].start, Reverse(intervals[index].stop)));
+
+    let mut lanes: Vec<Lane> = Vec::new();
+    for index in order {
+        let interval = intervals[index];
+        for lane in &mut lanes {
+            lane.leave_until(Some(interval.start));
+        }
+        let fits = lanes.iter().position(|lane| {
+            lane.open
+                .last()
+                .is_none_or(|(stop, _)| *stop >= interval.stop)
+        });
+        let lane = match fits {
+            Some(lane) => &mut lanes[lane],
+            None => {
+                lanes.push(Lane::default());
+                lanes.last_mut().unwrap()
+            }
+        };
+        lane.open.push((interval.stop, index));
+        lane.events.push(Event::Enter(interval.start, index));
+    }
+    lanes
+        .into_iter()
+        .map(|mut lane| {
+            lane.leave_until(None);
+            lane.events
+        })
+        .collect()
+}
+
+struct Location {
+    name: u32,
+    group: u32,
+    events: u64,
+}
+
+struct OTF2Exporter<'a> {
+    api: &'a Api,
+    handle: *mut c_void,
+    // OTF2 times are unsigned, so start at the beginning of the profile
+    origin: Timestamp,
+    length: u64,
+    strings: Strings,
+    // Region or group of each name
+    regions: BTreeMap<u32, u32>,
+    groups: BTreeMap<u32, u32>,
+    locations: Vec<Location>,
+}
+
+impl OTF2Exporter<'_> {
+    fn time(&self, timestamp: Timestamp) -> u64 {
+        (timestamp.0 - self.origin.0).max(0) as u64
+    }
+}
+
+impl Exporter for OTF2Exporter<'_> {
+    type Error = io::Error;
+
+    fn begin(&mut self, info: &DataSourceInfo, _entries: &[ExportEntry]) -> io::Result<()> {
+        self.origin = info.interval.start;
+        self.length = info.interval.duration_ns().max(0) as u64;
+        call!(self.api, OTF2_Archive_OpenEvtFiles(self.handle))
+    }
+
+    fn write_slot(&mut self, entry: &ExportEntry, items: Vec<ExportItem>) -> io::Result<()> {
+        let (api, handle) = (self.api, self.handle);
+
+        // Slots directly under the root panel all go in one group
+        let group_name = entry
+            .panels
+            .first()
+            .map_or("Profile", |(_, long_name)| long_name.as_str());
+        let next_group = self.groups.len() as u32;
+        let group = *self
+            .groups
+            .entry(self.strings.id(group_name))
+            .or_insert(next_group);
+
+        let item_regions: Vec<u32> = items
+            .iter()
+            .map(|item| {
+                let next_region = self.regions.len() as u32;
+                *self
+                    .regions
+                    .entry(self.strings.id(&item.meta.title))
+                    .or_insert(next_region)
+            })
+            .collect();
+        let intervals: Vec<_> = items.iter().map(|item| item.interval).collect();
+        let mut lanes = nest(&intervals);
+        if lanes.is_empty() {
+            lanes.push(Vec::new());
+        }
+
+        for (lane, events) in lanes.into_iter().enumerate() {
+            let name = if lane == 0 {
+                entry.long_name.clone()
+            } else {
+                format!("{} ({})", entry.long_name, lane + 1)
+            };
+            let location = self.locations.len() as u64;
+            self.locations.push(Location {
+                name: self.strings.id(&name),
+                group,
+                events: events.len() as u64,
+            });
+
+            let writer = api.check_handle(
+                unsafe { (api.OTF2_Archive_GetEvtWriter)(handle, location) },
+                "OTF2_Archive_GetEvtWriter",
+            )?;
+            for event in events {
+                match event {
+                    Event::Enter(timestamp, index) => call!(
+                        api,
+                        OTF2_EvtWriter_Enter(
+                            writer,
+                            ptr::null_mut(),
+                            self.time(timestamp),
+                            item_regions[index],
+                        )
+                    )?,
+                    Event::Leave(timestamp, index) => call!(
+                        api,
+                        OTF2_EvtWriter_Leave(
+                            writer,
+                            ptr::null_mut(),
+                            self.time(timestamp),
+                            item_regions[index],
+                        )
+                    )?,
+                }
+            }
+            call!(api, OTF2_Archive_CloseEvtWriter(handle, writer))?;
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        let (api, handle) = (self.api, self.handle);
+        call!(api, OTF2_Archive_CloseEvtFiles(handle))?;
+
+        // Readers expect a (here, empty) local definition file for each location
+        call!(api, OTF2_Archive_OpenDefFiles(handle))?;
+        for location in 0..self.locations.len() as u64 {
+            let writer = api.check_handle(
+                unsafe { (api.OTF2_Archive_GetDefWriter)(handle, location) },
+                "OTF2_Archive_GetDefWriter",
+            )?;
+            call!(api, OTF2_Archive_CloseDefWriter(handle, writer))?;
+        }
+        call!(api, OTF2_Archive_CloseDefFiles(handle))?;
+
+        let root_name = self.strings.id("Profile");
+        let root_class = self.strings.id("machine");
+
+        let writer = api.check_handle(
+            unsafe { (api.OTF2_Archive_GetGlobalDefWriter)(handle) },
+            "OTF2_Archive_GetGlobalDefWriter",
+        )?;
+        call!(
+            api,
+            OTF2_GlobalDefWriter_WriteClockProperties(
+                writer,
+                1_000_000_000,
+                0,
+                self.length,
+                UNDEFINED_TIMESTAMP,
+            )
+        )?;
+        for (id, string) in self.strings.strings.iter().enumerate() {
+            call!(
+                api,
+                OTF2_GlobalDefWriter_WriteString(writer, id as u32, string.as_ptr())
+            )?;
+        }
+        call!(
+            api,
+            OTF2_GlobalDefWriter_WriteSystemTreeNode(
+                writer,
+                0,
+                root_name,
+                root_class,
+                UNDEFINED_REF
+            )
+        )?;
+        for (name, group) in &self.groups {
+            call!(
+                api,
+                OTF2_GlobalDefWriter_WriteLocationGroup(
+                    writer,
+                    *group,
+                    *name,
+                    LOCATION_GROUP_TYPE_PROCESS,
+                    0,
+                    UNDEFINED_REF,
+                )
+            )?;
+        }
+        for (name, region) in &self.regions {
+            call!(
+                api,
+                OTF2_GlobalDefWriter_WriteRegion(
+                    writer,
+                    *region,
+                    *name,
+                    *name,
+                    UNDEFINED_REF,
+                    REGION_ROLE_FUNCTION,
+                    PARADIGM_USER,
+                    REGION_FLAG_NONE,
+                    UNDEFINED_REF,
+                    0,
+                    0,
+                )
+            )?;
+        }
+        for (id, location) in self.locations.iter().enumerate() {
+            call!(
+                api,
+                OTF2_GlobalDefWriter_WriteLocation(
+                    writer,
+                    id as u64,
+                    location.name,
+                    LOCATION_TYPE_CPU_THREAD,
+                    location.events,
+                    location.group,
+                )
+            )?;
+        }
+        call!(api, OTF2_Archive_CloseGlobalDefWriter(handle, writer))
+    }
+}
+
+// Writes the profile as an OTF2 archive (path/traces.otf2, along with its
+// definition and event files) for Vampir and the Score-P tools. Each
+// top-level panel (usually a node) becomes a location group and each slot a
+// location, on which items enter and leave a region named by their title.
+// Items that overlap without nesting go on extra locations for the slot.
+//
+// libotf2 (version 3 or later) is loaded at run time, by default from the
+// library path, or else from LEGION_PROF_OTF2_LIBRARY.
+pub fn export_otf2<T: DeferredDataSource + 'static>(
+    data_source: T,
+    path: impl AsRef<Path>,
+) -> io::Result<()> {
+    let path = path.as_ref().to_str().ok_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidInput, "OTF2 path is not valid UTF-8")
+    })?;
+
+    let library = std::env::var_os("LEGION_PROF_OTF2_LIBRARY")
+        .unwrap_or_else(|| libloading::library_filename("otf2"));
+    let library = unsafe { Library::new(&library) }.map_err(|e| {
+        let library = OsString::from(&library);
+        io::Error::other(format!("unable to load {}: {e}", library.to_string_lossy()))
+    })?;
+    let api = Api::load(library)?;
+
+    let archive = Archive {
+        api: &api,
+        handle: api.check_handle(
+            unsafe {
+                (api.OTF2_Archive_Open)(
+                    c_string(path).as_ptr(),
+                    c"traces".as_ptr(),
+                    FILEMODE_WRITE,
+                    CHUNK_SIZE_EVENTS,
+                    CHUNK_SIZE_DEFINITIONS,
+                    SUBSTRATE_POSIX,
+                    COMPRESSION_NONE,
+                )
+            },
+            "OTF2_Archive_Open",
+        )?,
+    };
+    let handle = archive.handle;
+
+    let (mut major, mut minor, mut bugfix) = (0, 0, 0);
+    call!(
+        api,
+        OTF2_Archive_GetVersion(handle, &mut major, &mut minor, &mut bugfix)
+    )?;
+    if major < 3 {
+        return Err(io::Error::other(format!(
+            "OTF2 {major}.{minor}.{bugfix} is too old, version 3 or later is required"
+        )));
+    }
+
+    call!(
+        api,
+        OTF2_Archive_SetFlushCallbacks(handle, &FLUSH_CALLBACKS, ptr::null_mut())
+    )?;
+    call!(api, OTF2_Archive_SetSerialCollectiveCallbacks(handle))?;
+    call!(
+        api,
+        OTF2_Archive_SetCreator(handle, c"Legion Prof Viewer".as_ptr())
+    )?;
+
+    let mut exporter = OTF2Exporter {
+        api: &api,
+        handle,
+        origin: Timestamp(0),
+        length: 0,
+        strings: Strings::default(),
+        regions: BTreeMap::new(),
+        groups: BTreeMap::new(),
+        locations: Vec::new(),
+    };
+    export(data_source, &mut exporter)?;
+
+    archive.close()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn interval(start: i64, stop: i64) -> Interval {
+        Interval::new(Timestamp(start), Timestamp(stop))
+    }
+
+    #[test]
+    fn test_nest() {
+        // The second item overlaps the first without nesting in it, so it
+        // goes on its own lane. The third nests in the first
+        let lanes = nest(&[interval(0, 10), interval(5, 20), interval(6, 8)]);
+        assert_eq!(
+            lanes,
+            vec![
+                vec![
+                    Event::Enter(Timestamp(0), 0),
+                    Event::Enter(Timestamp(6), 2),
+                    Event::Leave(Timestamp(8), 2),
+                    Event::Leave(Timestamp(10), 0),
+                ],
+                vec![
+                    Event::Enter(Timestamp(5), 1),
+                    Event::Leave(Timestamp(20), 1),
+                ],
+            ]
+        );
+    }
+
+    #[test]
+    fn test_nest_same_start() {
+        // Longer items go outside shorter ones that start at the same time
+        let lanes = nest(&[interval(0, 5), interval(0, 10), interval(10, 12)]);
+        assert_eq!(
+            lanes,
+            vec![vec![
+                Event::Enter(Timestamp(0), 1),
+                Event::Enter(Timestamp(0), 0),
+                Event::Leave(Timestamp(5), 0),
+                Event::Leave(Timestamp(10), 1),
+                Event::Enter(Timestamp(10), 2),
+                Event::Leave(Timestamp(12), 2),
+            ]]
+        );
+    }
+}
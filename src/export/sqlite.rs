@@ -0,0 +1,237 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use rusqlite::types::Value;
+use rusqlite::{Connection, Transaction, params};
+
+use crate::data::{DataSourceInfo, EntryID, Field, FieldID};
+use crate::deferred_data::DeferredDataSource;
+use crate::export::{ExportEntry, ExportItem, Exporter, export, field_text};
+
+// Fields are looked up by item and searched by value, and items by entry and
+// time or by title
+const SCHEMA: &str = "
+CREATE TABLE entries (
+    id INTEGER PRIMARY KEY,
+    path TEXT NOT NULL,
+    short_name TEXT NOT NULL,
+    long_name TEXT NOT NULL
+);
+CREATE TABLE fields (
+    id INTEGER PRIMARY KEY,
+    name TEXT NOT NULL
+);
+CREATE TABLE items (
+    id INTEGER PRIMARY KEY,
+    entry_id INTEGER NOT NULL REFERENCES entries(id),
+    row INTEGER NOT NULL,
+    title TEXT NOT NULL,
+    start INTEGER NOT NULL,
+    stop INTEGER NOT NULL,
+    duration INTEGER NOT NULL
+);
+CREATE TABLE item_fields (
+    item_id INTEGER NOT NULL REFERENCES items(id),
+    field_id INTEGER NOT NULL REFERENCES fields(id),
+    value
+);
+CREATE INDEX items_by_entry ON items(entry_id, start);
+CREATE INDEX items_by_title ON items(title);
+CREATE INDEX item_fields_by_item ON item_fields(item_id);
+CREATE INDEX item_fields_by_value ON item_fields(field_id, value);
+";
+
+// Integers stay integers where SQLite can hold them
+fn to_value(field: &Field) -> Value {
+    match field {
+        Field::I64(value) => Value::Integer(*value),
+        Field::U64(value) => i64::try_from(*value)
+            .map(Value::Integer)
+            .unwrap_or_else(|_| Value::Text(value.to_string())),
+        Field::Empty => Value::Null,
+        _ => Value::Text(field_text(field)),
+    }
+}
+
+pub struct SQLiteExporter<'a> {
+    // Everything goes in one transaction, which is much faster. It's
+    // committed when the export finishes
+    transaction: Option<Transaction<'a>>,
+    field_ids: BTreeMap<FieldID, i64>,
+    entry_ids: BTreeMap<EntryID, i64>,
+}
+
+impl<'a> SQLiteExporter<'a> {
+    pub fn new(connection: &'a mut Connection) -> io::Result<Self> {
+        Ok(Self {
+            transaction: Some(connection.transaction().map_err(io::Error::other)?),
+            field_ids: BTreeMap::new(),
+            entry_ids: BTreeMap::new(),
+        })
+    }
+
+    fn transaction(&self) -> &Transaction<'a> {
+        self.transaction
+            .as_ref()
+            .expect("transaction is open until the export finishes")
+    }
+}
+
+impl Exporter for SQLiteExporter<'_> {
+    type Error = io::Error;
+
+    fn begin(&mut self, info: &DataSourceInfo, entries: &[ExportEntry]) -> io::Result<()> {
+        let transaction = self.transaction();
+        transaction
+            .execute_batch(SCHEMA)
+            .map_err(io::Error::other)?;
+
+        let mut insert_field = transaction
+            .prepare("INSERT INTO fields (name) VALUES (?1)")
+            .map_err(io::Error::other)?;
+        let mut field_ids = BTreeMap::new();
+        for (field_id, name) in info.field_schema.iter() {
+            let id = insert_field.insert([name]).map_err(io::Error::other)?;
+            field_ids.insert(field_id, id);
+        }
+
+        let mut insert_entry = transaction
+            .prepare("INSERT INTO entries (path, short_name, long_name) VALUES (?1, ?2, ?3)")
+            .map_err(io::Error::other)?;
+        let mut entry_ids = BTreeMap::new();
+        for entry in entries {
+            let id = insert_entry
+                .insert(params![entry.path(), entry.short_name, entry.long_name])
+                .map_err(io::Error::other)?;
+            entry_ids.insert(entry.entry_id.clone(), id);
+        }
+
+        drop((insert_field, insert_entry));
+        self.field_ids = field_ids;
+        self.entry_ids = entry_ids;
+        Ok(())
+    }
+
+    fn write_slot(&mut self, entry: &ExportEntry, items: Vec<ExportItem>) -> io::Result<()> {
+        let transaction = self.transaction();
+        let mut insert_item = transaction
+            .prepare_cached(
+                "INSERT INTO items (entry_id, row, title, start, stop, duration)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            )
+            .map_err(io::Error::other)?;
+        let mut insert_item_field = transaction
+            .prepare_cached(
+                "INSERT INTO item_fields (item_id, field_id, value) VALUES (?1, ?2, ?3)",
+            )
+            .map_err(io::Error::other)?;
+
+        let entry_id = self.entry_ids[&entry.entry_id];
+        for item in &items {
+            let item_id = insert_item
+                .insert(params![
+                    entry_id,
+                    item.row,
+                    item.meta.title,
+                    item.interval.start.0,
+                    item.interval.stop.0,
+                    item.interval.duration_ns(),
+                ])
+                .map_err(io::Error::other)?;
+            for (field_id, field, _) in &item.meta.fields {
+                let Some(field_id) = self.field_ids.get(field_id) else {
+                    continue;
+                };
+                insert_item_field
+                    .execute(params![item_id, field_id, to_value(field)])
+                    .map_err(io::Error::other)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        let transaction = self
+            .transaction
+            .take()
+            .expect("transaction is open until the export finishes");
+        transaction.commit().map_err(io::Error::other)
+    }
+}
+
+// An empty database at path, replacing any that was there
+pub fn create_database(path: impl AsRef<Path>) -> io::Result<Connection> {
+    let path = path.as_ref();
+    if path.exists() {
+        fs::remove_file(path)?;
+    }
+    Connection::open(path).map_err(io::Error::other)
+}
+
+// Writes the profile to a new SQLite database, with a row in items for each
+// item and a row in item_fields for each of its fields. Times are in
+// nanoseconds. An existing database at path is replaced.
+pub fn export_sqlite<T: DeferredDataSource + 'static>(
+    data_source: T,
+    path: impl AsRef<Path>,
+) -> io::Result<()> {
+    let mut connection = create_database(path)?;
+    write_sqlite(data_source, &mut connection)
+}
+
+pub fn write_sqlite<T: DeferredDataSource + 'static>(
+    data_source: T,
+    connection: &mut Connection,
+) -> io::Result<()> {
+    export(data_source, &mut SQLiteExporter::new(connection)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::deferred_data::DeferredDataSourceWrapper;
+    use crate::timestamp::{Interval, Timestamp};
+    use crate::trace_data::{TraceBuilder, TraceItem};
+
+    #[test]
+    fn test_sqlite() {
+        let mut builder = TraceBuilder::new("test");
+        let size = builder.field("Size", false);
+        let thread = builder.thread("node", "cpu", "worker");
+        for (i, title) in ["init", "copy"].into_iter().enumerate() {
+            builder.add_item(
+                thread,
+                TraceItem {
+                    interval: Interval::new(Timestamp(i as i64 * 10), Timestamp(i as i64 * 10 + 5)),
+                    title: title.to_owned(),
+                    color: None,
+                    fields: vec![(size, Field::U64(64 << i))],
+                },
+            );
+        }
+        let ds = DeferredDataSourceWrapper::new(builder.build());
+
+        let mut connection = Connection::open_in_memory().unwrap();
+        write_sqlite(ds, &mut connection).unwrap();
+
+        let (path, title, duration, size): (String, String, i64, i64) = connection
+            .query_row(
+                "SELECT entries.path, items.title, items.duration, item_fields.value
+                 FROM items
+                 JOIN entries ON entries.id = items.entry_id
+                 JOIN item_fields ON item_fields.item_id = items.id
+                 JOIN fields ON fields.id = item_fields.field_id
+                 WHERE fields.name = 'Size' AND items.start = 10",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)),
+            )
+            .unwrap();
+        assert_eq!(path, "node/cpu/worker");
+        assert_eq!(title, "copy");
+        assert_eq!(duration, 5);
+        assert_eq!(size, 128);
+    }
+}
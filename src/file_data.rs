@@ -1,69 +1,177 @@
+use std::collections::{BTreeMap, BTreeSet};
 use std::fs::File;
 use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
 
 use serde::Deserialize;
 
 use crate::data::{
-    DataSource, DataSourceDescription, DataSourceInfo, EntryID, SlotMetaTile, SlotTile,
-    SummaryTile, TileID,
+    DataSource, DataSourceDescription, DataSourceInfo, EntryID, ItemMeta, ItemMetaRequest, ItemUID,
+    SlotMetaTile, SlotTile, SummaryTile, TileID,
 };
 use crate::http::schema::TileRequestRef;
 
 pub struct FileDataSource {
     pub basedir: PathBuf,
+    // Read on first use, since the tile set is needed to look up items
+    info: OnceLock<DataSourceInfo>,
 }
 
 impl FileDataSource {
     pub fn new(basedir: impl AsRef<Path>) -> Self {
         Self {
             basedir: basedir.as_ref().to_owned(),
+            info: OnceLock::new(),
         }
     }
 
-    fn read_file<T>(&self, path: impl AsRef<Path>) -> T
+    fn read_file<T>(&self, path: impl AsRef<Path>) -> Result<T, String>
     where
         T: for<'a> Deserialize<'a>,
     {
-        let f = File::open(path).expect("opening file failed");
-        let f = zstd::Decoder::new(f).expect("zstd decompression failed");
-        ciborium::from_reader(f).expect("cbor decoding failed")
+        let path = path.as_ref();
+        let f = File::open(path).map_err(|e| format!("unable to open {}: {e}", path.display()))?;
+        let f = zstd::Decoder::new(f)
+            .map_err(|e| format!("unable to decompress {}: {e}", path.display()))?;
+        ciborium::from_reader(f).map_err(|e| format!("unable to decode {}: {e}", path.display()))
+    }
+
+    fn info(&self) -> Result<&DataSourceInfo, String> {
+        if let Some(info) = self.info.get() {
+            return Ok(info);
+        }
+        let info = self.read_file(self.basedir.join("info"))?;
+        Ok(self.info.get_or_init(|| info))
     }
 }
 
+// Like ZipDataSource, the deferred wrappers use the try_fetch_* methods so
+// that a missing or damaged file is reported rather than panicking
 impl DataSource for FileDataSource {
     fn fetch_description(&self) -> DataSourceDescription {
         DataSourceDescription {
             source_locator: vec![String::from(self.basedir.to_string_lossy())],
         }
     }
+
     fn fetch_info(&self) -> DataSourceInfo {
-        let path = self.basedir.join("info");
-        self.read_file::<DataSourceInfo>(&path)
+        self.try_fetch_info().unwrap_or_else(|e| panic!("{e}"))
+    }
+
+    fn fetch_summary_tile(&self, entry_id: &EntryID, tile_id: TileID, full: bool) -> SummaryTile {
+        self.try_fetch_summary_tile(entry_id, tile_id, full)
+            .unwrap_or_else(|e| panic!("{e}"))
+    }
+
+    fn fetch_slot_tile(&self, entry_id: &EntryID, tile_id: TileID, full: bool) -> SlotTile {
+        self.try_fetch_slot_tile(entry_id, tile_id, full)
+            .unwrap_or_else(|e| panic!("{e}"))
+    }
+
+    fn fetch_slot_meta_tile(
+        &self,
+        entry_id: &EntryID,
+        tile_id: TileID,
+        full: bool,
+    ) -> SlotMetaTile {
+        self.try_fetch_slot_meta_tile(entry_id, tile_id, full)
+            .unwrap_or_else(|e| panic!("{e}"))
     }
 
-    fn fetch_summary_tile(&self, entry_id: &EntryID, tile_id: TileID, _full: bool) -> SummaryTile {
+    fn fetch_items_meta(&self, requests: &[ItemMetaRequest]) -> Vec<ItemMeta> {
+        self.try_fetch_items_meta(requests)
+            .unwrap_or_else(|e| panic!("{e}"))
+    }
+
+    fn try_fetch_info(&self) -> Result<DataSourceInfo, String> {
+        self.info().cloned()
+    }
+
+    fn try_fetch_summary_tile(
+        &self,
+        entry_id: &EntryID,
+        tile_id: TileID,
+        _full: bool,
+    ) -> Result<SummaryTile, String> {
         let req = TileRequestRef { entry_id, tile_id };
         let mut path = self.basedir.join("summary_tile");
         path.push(req.to_slug());
         self.read_file::<SummaryTile>(&path)
     }
 
-    fn fetch_slot_tile(&self, entry_id: &EntryID, tile_id: TileID, _full: bool) -> SlotTile {
+    fn try_fetch_slot_tile(
+        &self,
+        entry_id: &EntryID,
+        tile_id: TileID,
+        _full: bool,
+    ) -> Result<SlotTile, String> {
         let req = TileRequestRef { entry_id, tile_id };
         let mut path = self.basedir.join("slot_tile");
         path.push(req.to_slug());
         self.read_file::<SlotTile>(&path)
     }
 
-    fn fetch_slot_meta_tile(
+    fn try_fetch_slot_meta_tile(
         &self,
         entry_id: &EntryID,
         tile_id: TileID,
         _full: bool,
-    ) -> SlotMetaTile {
+    ) -> Result<SlotMetaTile, String> {
         let req = TileRequestRef { entry_id, tile_id };
         let mut path = self.basedir.join("slot_meta_tile");
         path.push(req.to_slug());
         self.read_file::<SlotMetaTile>(&path)
     }
+
+    fn try_fetch_items_meta(&self, requests: &[ItemMetaRequest]) -> Result<Vec<ItemMeta>, String> {
+        fetch_items_meta_from_tile_set(self, self.info()?, requests)
+    }
+}
+
+// Archives only contain the tiles in the tile set, so look up the (full)
+// tiles at the finest level that overlap each request. Every item asked for
+// must be found in them.
+pub(crate) fn fetch_items_meta_from_tile_set(
+    data_source: &impl DataSource,
+    info: &DataSourceInfo,
+    requests: &[ItemMetaRequest],
+) -> Result<Vec<ItemMeta>, String> {
+    let mut tiles: BTreeMap<(&EntryID, TileID), BTreeSet<ItemUID>> = BTreeMap::new();
+    for req in requests {
+        let tile_ids = info
+            .entry_tile_set(&req.entry_id)
+            .tiles
+            .last()
+            .ok_or_else(|| format!("no tiles for entry {:?}", req.entry_id))?;
+        for tile_id in tile_ids {
+            if tile_id.0.overlaps(req.interval) {
+                tiles
+                    .entry((&req.entry_id, *tile_id))
+                    .or_default()
+                    .insert(req.item_uid);
+            }
+        }
+    }
+
+    // Items may span several tiles, so only keep the first copy
+    let mut seen = BTreeSet::new();
+    let mut result = Vec::new();
+    for ((entry_id, tile_id), item_uids) in tiles {
+        let tile = data_source.try_fetch_slot_meta_tile(entry_id, tile_id, true)?;
+        for item in tile.data.items.into_iter().flatten() {
+            if item_uids.contains(&item.item_uid) && seen.insert((entry_id, item.item_uid)) {
+                result.push(item);
+            }
+        }
+    }
+    if let Some(req) = requests
+        .iter()
+        .find(|req| !seen.contains(&(&req.entry_id, req.item_uid)))
+    {
+        return Err(format!(
+            "no item {:?} in entry {:?}",
+            req.item_uid, req.entry_id
+        ));
+    }
+    Ok(result)
 }
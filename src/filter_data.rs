@@ -0,0 +1,686 @@
+use std::collections::{BTreeMap, VecDeque};
+
+use log::warn;
+
+use crate::data::{
+    DataSourceDescription, DataSourceInfo, EntryID, EntryInfo, Field, ItemMeta, ItemMetaRequest,
+    TileID,
+};
+use crate::deferred_data::{
+    DataSourceInfoResult, DeferredDataSource, ItemsMetaResponse, RequestPriority,
+    SlotMetaTileResponse, SlotTileResponse, SummaryTileResponse, TileKind, TileRequest,
+    TileResponse, TransferStats,
+};
+
+const FILTERED: &str = "entry is filtered out";
+const NO_LONGER_SHOWN: &str = "entry is no longer shown";
+
+type Predicate = Box<dyn Fn(&EntryInfo) -> bool>;
+
+// Hides the entries of a source that don't match a predicate. A slot is kept
+// if it or any panel containing it matches, and a panel is kept if anything
+// inside it is kept (though its summary only if nothing inside it was hidden).
+// The kept entries are renumbered so that the tree stays dense, and requests
+// for anything else fail without reaching the source.
+pub struct FilterDeferredDataSource<T: DeferredDataSource> {
+    data_source: T,
+    predicate: Predicate,
+    dst_to_src: BTreeMap<EntryID, EntryID>,
+    src_to_dst: BTreeMap<EntryID, EntryID>,
+    // The requests as they were made, for each request forwarded to the
+    // source, since the mapping may have changed by the time it responds
+    outstanding_tiles: BTreeMap<(TileKind, TileRequest), VecDeque<TileRequest>>,
+    outstanding_items_meta: BTreeMap<Vec<ItemMetaRequest>, VecDeque<Vec<ItemMetaRequest>>>,
+    summary_tiles: Vec<SummaryTileResponse>,
+    slot_tiles: Vec<SlotTileResponse>,
+    slot_meta_tiles: Vec<SlotMetaTileResponse>,
+    items_meta: Vec<ItemsMetaResponse>,
+}
+
+impl<T: DeferredDataSource> FilterDeferredDataSource<T> {
+    pub fn new(data_source: T, predicate: impl Fn(&EntryInfo) -> bool + 'static) -> Self {
+        Self {
+            data_source,
+            predicate: Box::new(predicate),
+            dst_to_src: BTreeMap::new(),
+            src_to_dst: BTreeMap::new(),
+            outstanding_tiles: BTreeMap::new(),
+            outstanding_items_meta: BTreeMap::new(),
+            summary_tiles: Vec::new(),
+            slot_tiles: Vec::new(),
+            slot_meta_tiles: Vec::new(),
+            items_meta: Vec::new(),
+        }
+    }
+
+    // Keep entries whose short or long name contains the pattern
+    pub fn by_name(data_source: T, pattern: &str) -> Self {
+        let pattern = pattern.to_string();
+        Self::new(data_source, move |info| match info {
+            EntryInfo::Panel {
+                short_name,
+                long_name,
+                ..
+            }
+            | EntryInfo::Slot {
+                short_name,
+                long_name,
+                ..
+            } => short_name.contains(&pattern) || long_name.contains(&pattern),
+            EntryInfo::Summary { .. } => false,
+        })
+    }
+
+    fn add_mapping(&mut self, src: EntryID, dst: EntryID) {
+        self.src_to_dst.insert(src.clone(), dst.clone());
+        self.dst_to_src.insert(dst, src);
+    }
+
+    // Returns the kept entry, and whether everything inside it was kept
+    fn prune(
+        &mut self,
+        info: &EntryInfo,
+        src: EntryID,
+        dst: EntryID,
+        ancestor_matched: bool,
+    ) -> Option<(EntryInfo, bool)> {
+        let matched = ancestor_matched || (self.predicate)(info);
+        let EntryInfo::Panel {
+            short_name,
+            long_name,
+            summary,
+            slots,
+        } = info
+        else {
+            if !matched {
+                return None;
+            }
+            self.add_mapping(src, dst);
+            return Some((info.clone(), true));
+        };
+
+        let (kept, complete) = self.prune_slots(slots, &src, &dst, matched);
+        if kept.is_empty() && !matched {
+            return None;
+        }
+        let summary = self.prune_summary(summary, &src, &dst, complete);
+        self.add_mapping(src, dst);
+        let info = EntryInfo::Panel {
+            short_name: short_name.clone(),
+            long_name: long_name.clone(),
+            summary,
+            slots: kept,
+        };
+        Some((info, complete))
+    }
+
+    fn prune_slots(
+        &mut self,
+        slots: &[EntryInfo],
+        src: &EntryID,
+        dst: &EntryID,
+        matched: bool,
+    ) -> (Vec<EntryInfo>, bool) {
+        let mut kept = Vec::new();
+        let mut complete = true;
+        for (i, slot) in slots.iter().enumerate() {
+            let child = dst.child(kept.len() as u64);
+            match self.prune(slot, src.child(i as u64), child, matched) {
+                Some((slot, slot_complete)) => {
+                    kept.push(slot);
+                    complete &= slot_complete;
+                }
+                None => complete = false,
+            }
+        }
+        (kept, complete)
+    }
+
+    // A panel's summary covers everything inside it, including anything
+    // hidden, so it's only kept if nothing was
+    fn prune_summary(
+        &mut self,
+        summary: &Option<Box<EntryInfo>>,
+        src: &EntryID,
+        dst: &EntryID,
+        complete: bool,
+    ) -> Option<Box<EntryInfo>> {
+        if summary.is_none() || !complete {
+            return None;
+        }
+        self.add_mapping(src.summary(), dst.summary());
+        summary.clone()
+    }
+
+    fn filter_info(&mut self, mut info: DataSourceInfo) -> DataSourceInfo {
+        self.dst_to_src.clear();
+        self.src_to_dst.clear();
+
+        // The root is never tested against the predicate, otherwise most
+        // filters would trivially keep everything
+        let EntryInfo::Panel {
+            short_name,
+            long_name,
+            summary,
+            slots,
+        } = &info.entry_info
+        else {
+            unreachable!();
+        };
+        let root = EntryID::root();
+        let (kept, complete) = self.prune_slots(slots, &root, &root, false);
+        let summary = self.prune_summary(summary, &root, &root, complete);
+        self.add_mapping(root.clone(), root);
+        info.entry_info = EntryInfo::Panel {
+            short_name: short_name.clone(),
+            long_name: long_name.clone(),
+            summary,
+            slots: kept,
+        };
+        // Entries keep their tile sets under their new IDs, and hidden
+        // entries don't need them at all
+        info.entry_tile_sets = std::mem::take(&mut info.entry_tile_sets)
+            .into_iter()
+            .filter_map(|(src, tile_set)| Some((self.src_to_dst.get(&src)?.clone(), tile_set)))
+            .collect();
+        info
+    }
+
+    fn map_src_to_dst_field(&self, field: &mut Field) {
+        match field {
+            Field::ItemLink(link) => {
+                if let Some(dst) = self.src_to_dst.get(&link.entry_id) {
+                    link.entry_id = dst.clone();
+                } else {
+                    // The target is hidden, so there's nothing to link to
+                    *field = Field::String(link.title.clone());
+                }
+            }
+            Field::Vec(elts) => {
+                for elt in elts {
+                    self.map_src_to_dst_field(elt);
+                }
+            }
+            _ => (),
+        }
+    }
+
+    fn map_src_to_dst_item_meta(&self, item: &mut ItemMeta) {
+        for (_, field, _) in &mut item.fields {
+            self.map_src_to_dst_field(field);
+        }
+    }
+
+    // Whether a dst entry still refers to the same src entry it did when a
+    // request was made. Entries can disappear or be renumbered when the
+    // source's info is refreshed.
+    fn still_shown(&self, dst: &EntryID, src: &EntryID) -> bool {
+        self.dst_to_src.get(dst) == Some(src)
+    }
+
+    // Answers each of the source's responses as the request that caused it
+    fn map_src_to_dst_responses<R>(
+        &mut self,
+        kind: TileKind,
+        responses: Vec<TileResponse<R>>,
+        mut map_tile: impl FnMut(&Self, &mut R, &EntryID),
+    ) -> Vec<TileResponse<R>> {
+        let mut result = Vec::new();
+        for (tile, src_req) in responses {
+            let key = (kind, src_req);
+            let Some(queue) = self.outstanding_tiles.get_mut(&key) else {
+                warn!("response for a request that was never made: {}", key.1);
+                continue;
+            };
+            let dst_req = queue.pop_front().unwrap();
+            if queue.is_empty() {
+                self.outstanding_tiles.remove(&key);
+            }
+            let tile = if self.still_shown(&dst_req.entry_id, &key.1.entry_id) {
+                tile.map(|mut tile| {
+                    map_tile(self, &mut tile, &dst_req.entry_id);
+                    tile
+                })
+            } else {
+                Err(NO_LONGER_SHOWN.to_string())
+            };
+            result.push((tile, dst_req));
+        }
+        result
+    }
+
+    // Splits requests into those that can be forwarded (mapped to the
+    // source's entries) and those that are rejected
+    fn map_dst_to_src_requests(
+        &mut self,
+        kind: TileKind,
+        requests: &[TileRequest],
+    ) -> (Vec<TileRequest>, Vec<TileRequest>) {
+        let mut forward = Vec::new();
+        let mut reject = Vec::new();
+        for req in requests {
+            match self.dst_to_src.get(&req.entry_id) {
+                Some(src) => {
+                    let src_req = TileRequest {
+                        entry_id: src.clone(),
+                        ..req.clone()
+                    };
+                    self.outstanding_tiles
+                        .entry((kind, src_req.clone()))
+                        .or_default()
+                        .push_back(req.clone());
+                    forward.push(src_req);
+                }
+                None => reject.push(req.clone()),
+            }
+        }
+        (forward, reject)
+    }
+
+    fn reject<R>(responses: &mut Vec<TileResponse<R>>, requests: Vec<TileRequest>) {
+        responses.extend(
+            requests
+                .into_iter()
+                .map(|req| (Err(FILTERED.to_string()), req)),
+        );
+    }
+
+    fn fetch_tile(&mut self, kind: TileKind, req: TileRequest, priority: RequestPriority) {
+        let (forward, reject) = self.map_dst_to_src_requests(kind, &[req]);
+        for req in forward {
+            let TileRequest {
+                entry_id,
+                tile_id,
+                full,
+            } = req;
+            match kind {
+                TileKind::Summary => self
+                    .data_source
+                    .fetch_summary_tile(&entry_id, tile_id, full, priority),
+                TileKind::Slot => self
+                    .data_source
+                    .fetch_slot_tile(&entry_id, tile_id, full, priority),
+                TileKind::SlotMeta => self
+                    .data_source
+                    .fetch_slot_meta_tile(&entry_id, tile_id, full, priority),
+            }
+        }
+        self.reject_tiles(kind, reject);
+    }
+
+    fn reject_tiles(&mut self, kind: TileKind, requests: Vec<TileRequest>) {
+        match kind {
+            TileKind::Summary => Self::reject(&mut self.summary_tiles, requests),
+            TileKind::Slot => Self::reject(&mut self.slot_tiles, requests),
+            TileKind::SlotMeta => Self::reject(&mut self.slot_meta_tiles, requests),
+        }
+    }
+}
+
+impl<T: DeferredDataSource> DeferredDataSource for FilterDeferredDataSource<T> {
+    fn fetch_description(&self) -> DataSourceDescription {
+        self.data_source.fetch_description()
+    }
+
+    fn fetch_info(&mut self) {
+        self.data_source.fetch_info()
+    }
+
+    fn get_infos(&mut self) -> Vec<DataSourceInfoResult> {
+        let infos = self.data_source.get_infos();
+        infos
+            .into_iter()
+            .map(|info| info.map(|info| self.filter_info(info)))
+            .collect()
+    }
+
+    fn fetch_summary_tile(
+        &mut self,
+        entry_id: &EntryID,
+        tile_id: TileID,
+        full: bool,
+        priority: RequestPriority,
+    ) {
+        let req = TileRequest {
+            entry_id: entry_id.clone(),
+            tile_id,
+            full,
+        };
+        self.fetch_tile(TileKind::Summary, req, priority);
+    }
+
+    fn get_summary_tiles(&mut self) -> Vec<SummaryTileResponse> {
+        let responses = self.data_source.get_summary_tiles();
+        let mut result = std::mem::take(&mut self.summary_tiles);
+        result.extend(self.map_src_to_dst_responses(
+            TileKind::Summary,
+            responses,
+            |_, tile, entry_id| {
+                tile.entry_id = entry_id.clone();
+            },
+        ));
+        result
+    }
+
+    fn fetch_slot_tile(
+        &mut self,
+        entry_id: &EntryID,
+        tile_id: TileID,
+        full: bool,
+        priority: RequestPriority,
+    ) {
+        let req = TileRequest {
+            entry_id: entry_id.clone(),
+            tile_id,
+            full,
+        };
+        self.fetch_tile(TileKind::Slot, req, priority);
+    }
+
+    fn get_slot_tiles(&mut self) -> Vec<SlotTileResponse> {
+        let responses = self.data_source.get_slot_tiles();
+        let mut result = std::mem::take(&mut self.slot_tiles);
+        result.extend(self.map_src_to_dst_responses(
+            TileKind::Slot,
+            responses,
+            |_, tile, entry_id| {
+                tile.entry_id = entry_id.clone();
+            },
+        ));
+        result
+    }
+
+    fn fetch_slot_meta_tile(
+        &mut self,
+        entry_id: &EntryID,
+        tile_id: TileID,
+        full: bool,
+        priority: RequestPriority,
+    ) {
+        let req = TileRequest {
+            entry_id: entry_id.clone(),
+            tile_id,
+            full,
+        };
+        self.fetch_tile(TileKind::SlotMeta, req, priority);
+    }
+
+    fn get_slot_meta_tiles(&mut self) -> Vec<SlotMetaTileResponse> {
+        let responses = self.data_source.get_slot_meta_tiles();
+        let mut result = std::mem::take(&mut self.slot_meta_tiles);
+        result.extend(self.map_src_to_dst_responses(
+            TileKind::SlotMeta,
+            responses,
+            |this, tile, entry_id| {
+                tile.entry_id = entry_id.clone();
+                for item in tile.data.items.iter_mut().flatten() {
+                    this.map_src_to_dst_item_meta(item);
+                }
+            },
+        ));
+        result
+    }
+
+    fn fetch_items_meta(&mut self, requests: &[ItemMetaRequest]) {
+        let mut src_requests = Vec::new();
+        for req in requests {
+            let Some(src) = self.dst_to_src.get(&req.entry_id) else {
+                self.items_meta
+                    .push((Err(FILTERED.to_string()), requests.to_vec()));
+                return;
+            };
+            src_requests.push(ItemMetaRequest {
+                entry_id: src.clone(),
+                ..req.clone()
+            });
+        }
+        self.outstanding_items_meta
+            .entry(src_requests.clone())
+            .or_default()
+            .push_back(requests.to_vec());
+        self.data_source.fetch_items_meta(&src_requests)
+    }
+
+    fn get_items_meta(&mut self) -> Vec<ItemsMetaResponse> {
+        let mut result = std::mem::take(&mut self.items_meta);
+        for (metas, src_reqs) in self.data_source.get_items_meta() {
+            let Some(queue) = self.outstanding_items_meta.get_mut(&src_reqs) else {
+                warn!("response for an items meta request that was never made");
+                continue;
+            };
+            let dst_reqs = queue.pop_front().unwrap();
+            if queue.is_empty() {
+                self.outstanding_items_meta.remove(&src_reqs);
+            }
+            let shown = dst_reqs
+                .iter()
+                .zip(&src_reqs)
+                .all(|(dst, src)| self.still_shown(&dst.entry_id, &src.entry_id));
+            let metas = if shown {
+                metas.map(|mut metas| {
+                    for item in &mut metas {
+                        self.map_src_to_dst_item_meta(item);
+                    }
+                    metas
+                })
+            } else {
+                Err(NO_LONGER_SHOWN.to_string())
+            };
+            result.push((metas, dst_reqs));
+        }
+        result
+    }
+
+    fn fetch_tiles(&mut self, kind: TileKind, requests: &[TileRequest], priority: RequestPriority) {
+        let (forward, reject) = self.map_dst_to_src_requests(kind, requests);
+        if !forward.is_empty() {
+            self.data_source.fetch_tiles(kind, &forward, priority);
+        }
+        self.reject_tiles(kind, reject);
+    }
+
+    fn cancel(&mut self, entry_id: &EntryID, tile_id: TileID, full: bool) {
+        if let Some(src) = self.dst_to_src.get(entry_id) {
+            self.data_source.cancel(src, tile_id, full)
+        }
+    }
+
+    fn transfer_stats(&self) -> Option<TransferStats> {
+        self.data_source.transfer_stats()
+    }
+
+    fn retries_failures(&self) -> bool {
+        self.data_source.retries_failures()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::data::TileSet;
+    use crate::deferred_data::DeferredDataSourceWrapper;
+    use crate::test_data::TestDataSource;
+    use crate::timestamp::{Interval, Timestamp};
+
+    fn slot(name: &str) -> EntryInfo {
+        EntryInfo::Slot {
+            short_name: name.to_string(),
+            long_name: name.to_string(),
+            max_rows: 1,
+        }
+    }
+
+    fn panel(name: &str, slots: Vec<EntryInfo>) -> EntryInfo {
+        EntryInfo::Panel {
+            short_name: name.to_string(),
+            long_name: name.to_string(),
+            summary: None,
+            slots,
+        }
+    }
+
+    fn gpu_tiles() -> TileSet {
+        TileSet {
+            tiles: vec![vec![TileID(Interval::new(Timestamp(0), Timestamp(1000)))]],
+        }
+    }
+
+    // Two nodes, where only node 0 has a GPU
+    fn test_source() -> TestDataSource {
+        let source = TestDataSource::default();
+        let info = DataSourceInfo {
+            entry_info: panel(
+                "root",
+                vec![
+                    panel(
+                        "Node 0",
+                        vec![
+                            panel("CPU", vec![slot("CPU 0"), slot("CPU 1")]),
+                            panel("GPU", vec![slot("GPU 0")]),
+                        ],
+                    ),
+                    panel("Node 1", vec![panel("CPU", vec![slot("CPU 0")])]),
+                ],
+            ),
+            // Node 0 / GPU / GPU 0 and Node 0 / CPU / CPU 1
+            entry_tile_sets: BTreeMap::from([
+                (EntryID::root().child(0).child(1).child(0), gpu_tiles()),
+                (
+                    EntryID::root().child(0).child(0).child(1),
+                    TileSet::default(),
+                ),
+            ]),
+            ..source.info
+        };
+        TestDataSource { info, ..source }
+    }
+
+    #[test]
+    fn test_filter() {
+        let mut filter =
+            FilterDeferredDataSource::by_name(DeferredDataSourceWrapper::new(test_source()), "GPU");
+        filter.fetch_info();
+        let info = filter.get_infos().pop().unwrap().unwrap();
+        assert_eq!(info.entry_info.nodes(), 1);
+        assert_eq!(info.entry_info.kinds(), vec!["GPU"]);
+
+        // Node 0 / GPU / GPU 0 is now the first slot of the first kind, and
+        // keeps its tiles
+        let gpu0 = EntryID::root().child(0).child(0).child(0);
+        assert_eq!(
+            info.entry_tile_sets,
+            BTreeMap::from([(gpu0.clone(), gpu_tiles())])
+        );
+        let tile_id = TileID(info.interval);
+        filter.fetch_slot_tile(&gpu0, tile_id, false, RequestPriority::Visible);
+        let tiles = filter.get_slot_tiles();
+        assert_eq!(tiles.len(), 1);
+        assert_eq!(tiles[0].0.as_ref().unwrap().entry_id, gpu0);
+        assert_eq!(tiles[0].1.entry_id, gpu0);
+
+        // Anything else was pruned
+        let pruned = EntryID::root().child(1).child(0).child(0);
+        filter.fetch_slot_tile(&pruned, tile_id, false, RequestPriority::Visible);
+        let tiles = filter.get_slot_tiles();
+        assert_eq!(tiles[0].0.as_ref().unwrap_err(), FILTERED);
+
+        // Requests for entries that went away in the meantime (e.g., after a
+        // refresh) are still answered, with an error
+        filter.fetch_slot_tile(&gpu0, tile_id, false, RequestPriority::Visible);
+        filter.dst_to_src.clear();
+        filter.src_to_dst.clear();
+        let tiles = filter.get_slot_tiles();
+        assert_eq!(tiles.len(), 1);
+        assert_eq!(tiles[0].0.as_ref().unwrap_err(), NO_LONGER_SHOWN);
+        assert_eq!(tiles[0].1.entry_id, gpu0);
+    }
+
+    #[test]
+    fn test_filter_summary() {
+        let summary = || {
+            Some(Box::new(EntryInfo::Summary {
+                color: Default::default(),
+                units: Default::default(),
+            }))
+        };
+        let with_summary = |info: EntryInfo| match info {
+            EntryInfo::Panel {
+                short_name,
+                long_name,
+                slots,
+                ..
+            } => EntryInfo::Panel {
+                short_name,
+                long_name,
+                summary: summary(),
+                slots,
+            },
+            _ => unreachable!(),
+        };
+        let source = test_source();
+        let EntryInfo::Panel { slots, .. } = source.info.entry_info.clone() else {
+            unreachable!();
+        };
+        let slots = slots.into_iter().map(with_summary).collect();
+        let info = DataSourceInfo {
+            entry_info: with_summary(panel("root", slots)),
+            ..source.info.clone()
+        };
+        let source = || TestDataSource {
+            info: info.clone(),
+            ..Default::default()
+        };
+        let has_summary = |info: Option<&EntryInfo>| {
+            matches!(
+                info,
+                Some(EntryInfo::Panel {
+                    summary: Some(_),
+                    ..
+                })
+            )
+        };
+
+        // Nothing is hidden, so every summary is kept
+        let mut filter =
+            FilterDeferredDataSource::by_name(DeferredDataSourceWrapper::new(source()), "");
+        filter.fetch_info();
+        let info = filter.get_infos().pop().unwrap().unwrap();
+        assert!(has_summary(Some(&info.entry_info)));
+        let tile_id = TileID(info.interval);
+        filter.fetch_summary_tile(
+            &EntryID::root().summary(),
+            tile_id,
+            false,
+            RequestPriority::Visible,
+        );
+        assert!(filter.get_summary_tiles()[0].0.is_ok());
+
+        // Node 1 is hidden, and the root's summary would include it. Node 0
+        // keeps its GPU, but not its CPU, so its summary goes too.
+        let mut filter =
+            FilterDeferredDataSource::by_name(DeferredDataSourceWrapper::new(source()), "GPU");
+        filter.fetch_info();
+        let info = filter.get_infos().pop().unwrap().unwrap();
+        assert!(!has_summary(Some(&info.entry_info)));
+        assert!(!has_summary(info.entry_info.get(&EntryID::root().child(0))));
+        filter.fetch_summary_tile(
+            &EntryID::root().summary(),
+            tile_id,
+            false,
+            RequestPriority::Visible,
+        );
+        assert_eq!(
+            filter.get_summary_tiles()[0].0.as_ref().unwrap_err(),
+            FILTERED
+        );
+
+        // Node 0 is kept whole, so only the root loses its summary
+        let mut filter =
+            FilterDeferredDataSource::by_name(DeferredDataSourceWrapper::new(source()), "Node 0");
+        filter.fetch_info();
+        let info = filter.get_infos().pop().unwrap().unwrap();
+        assert!(!has_summary(Some(&info.entry_info)));
+        assert!(has_summary(info.entry_info.get(&EntryID::root().child(0))));
+    }
+}
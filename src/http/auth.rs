@@ -0,0 +1,322 @@
+use std::sync::{Arc, Mutex};
+
+#[cfg(target_arch = "wasm32")]
+use reqwest::RequestBuilder;
+#[cfg(not(target_arch = "wasm32"))]
+use reqwest::blocking::RequestBuilder;
+#[cfg(not(target_arch = "wasm32"))]
+use reqwest::header::COOKIE;
+#[cfg(all(feature = "grpc", not(target_arch = "wasm32")))]
+use reqwest::header::HeaderMap;
+
+use url::Url;
+
+use crate::deferred_data::CancelFlag;
+use crate::http::fetch::{DataSourceResponse, FORBIDDEN, RetryConfig, UNAUTHORIZED, fetch};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Credentials {
+    Bearer(String),
+    Basic {
+        username: String,
+        password: Option<String>,
+    },
+    // Query parameters added to every URL, e.g., the signature of a signed
+    // URL ("Expires=...&Signature=...") or a SAS token
+    Query(String),
+}
+
+impl Credentials {
+    // Parses "user" or "user:password", the same as curl's --user
+    pub fn basic(user: &str) -> Self {
+        match user.split_once(':') {
+            Some((username, password)) => Credentials::Basic {
+                username: username.to_owned(),
+                password: Some(password.to_owned()),
+            },
+            None => Credentials::Basic {
+                username: user.to_owned(),
+                password: None,
+            },
+        }
+    }
+}
+
+// Called with the server's URL when it rejects the current credentials with
+// a 401, or with a 403 (e.g., once a signature expires). Returns the
+// credentials to retry with, or None to give up.
+pub type Reauthenticate = dyn Fn(&Url) -> Option<Credentials> + Send + Sync;
+
+#[derive(Clone, Default)]
+pub struct AuthConfig {
+    pub credentials: Option<Credentials>,
+    // Sent in a Cookie header, e.g., the session cookie of an SSO proxy.
+    // Browsers don't allow this, so on the web use include_credentials.
+    pub cookies: Vec<(String, String)>,
+    // Have the browser send its own cookies for the server (web only)
+    pub include_credentials: bool,
+    pub reauthenticate: Option<Arc<Reauthenticate>>,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl AuthConfig {
+    // Fixed credentials, replacing any read from a file (e.g., a token given
+    // on the command line over a token file in the user's config)
+    pub fn set_credentials(&mut self, credentials: Credentials) {
+        self.credentials = Some(credentials);
+        self.reauthenticate = None;
+    }
+
+    // Takes the credentials from a token (or signature) file, which is read
+    // again whenever the server rejects the request, so that it can be
+    // refreshed (e.g., by an SSO login, or a script that re-signs the URL)
+    // without restarting the viewer
+    pub fn credentials_file(
+        &mut self,
+        path: impl Into<std::path::PathBuf>,
+        credentials: fn(String) -> Credentials,
+    ) -> Result<(), String> {
+        let path = path.into();
+        let read = move || {
+            std::fs::read_to_string(&path)
+                .map(|token| token.trim().to_owned())
+                .map_err(|e| format!("unable to read {}: {e}", path.display()))
+        };
+        let token = read()?;
+        self.credentials = Some(credentials(token.clone()));
+        let last = Mutex::new(token);
+        self.reauthenticate = Some(Arc::new(move |_: &Url| {
+            // A file that has gone missing since leaves the old credentials
+            // rejected, the same as one that hasn't changed
+            let token = read().ok()?;
+            let mut last = last.lock().unwrap();
+            if token == *last {
+                return None;
+            }
+            *last = token.clone();
+            Some(credentials(token))
+        }));
+        Ok(())
+    }
+}
+
+struct AuthState {
+    credentials: Option<Credentials>,
+    // Bumped whenever the credentials are replaced, so that requests that
+    // fail with old credentials don't each ask for new ones
+    generation: u64,
+}
+
+// Applies the configured authentication to each request, and retries a
+// request (once) with new credentials if the server rejects it
+#[derive(Clone)]
+pub struct Authenticator {
+    baseurl: Url,
+    state: Arc<Mutex<AuthState>>,
+    #[cfg(not(target_arch = "wasm32"))]
+    cookies: Option<String>,
+    #[cfg(target_arch = "wasm32")]
+    include_credentials: bool,
+    reauthenticate: Option<Arc<Reauthenticate>>,
+}
+
+impl Authenticator {
+    pub fn new(baseurl: &Url, config: AuthConfig) -> Self {
+        Self {
+            baseurl: baseurl.clone(),
+            state: Arc::new(Mutex::new(AuthState {
+                credentials: config.credentials,
+                generation: 0,
+            })),
+            #[cfg(not(target_arch = "wasm32"))]
+            cookies: (!config.cookies.is_empty()).then(|| {
+                let cookies: Vec<_> = config
+                    .cookies
+                    .iter()
+                    .map(|(name, value)| format!("{name}={value}"))
+                    .collect();
+                cookies.join("; ")
+            }),
+            #[cfg(target_arch = "wasm32")]
+            include_credentials: config.include_credentials,
+            reauthenticate: config.reauthenticate,
+        }
+    }
+
+    pub(crate) fn apply(&self, mut request: RequestBuilder) -> (RequestBuilder, u64) {
+        let state = self.state.lock().unwrap();
+        match &state.credentials {
+            Some(Credentials::Bearer(token)) => request = request.bearer_auth(token),
+            Some(Credentials::Basic { username, password }) => {
+                request = request.basic_auth(username, password.as_ref())
+            }
+            Some(Credentials::Query(query)) => {
+                let pairs: Vec<_> = url::form_urlencoded::parse(query.as_bytes()).collect();
+                request = request.query(&pairs);
+            }
+            None => {}
+        }
+        #[cfg(not(target_arch = "wasm32"))]
+        if let Some(cookies) = &self.cookies {
+            request = request.header(COOKIE, cookies);
+        }
+        #[cfg(target_arch = "wasm32")]
+        if self.include_credentials {
+            request = request.fetch_credentials_include();
+        }
+        (request, state.generation)
+    }
+
+    // The same credentials and cookies as headers, for transports that don't
+    // build their requests with reqwest (gRPC). Query credentials belong in
+    // a URL, which those don't have.
+    #[cfg(all(feature = "grpc", not(target_arch = "wasm32")))]
+    pub(crate) fn headers(&self) -> Result<(HeaderMap, u64), String> {
+        use base64::Engine;
+        use base64::engine::general_purpose::STANDARD as BASE64;
+        use reqwest::header::{AUTHORIZATION, HeaderValue};
+
+        let state = self.state.lock().unwrap();
+        let mut headers = HeaderMap::new();
+        let mut insert = |name, value: String| -> Result<(), String> {
+            let value =
+                HeaderValue::from_str(&value).map_err(|e| format!("invalid credentials: {e}"))?;
+            headers.insert(name, value);
+            Ok(())
+        };
+        match &state.credentials {
+            Some(Credentials::Bearer(token)) => insert(AUTHORIZATION, format!("Bearer {token}"))?,
+            Some(Credentials::Basic { username, password }) => {
+                let password = password.as_deref().unwrap_or("");
+                let encoded = BASE64.encode(format!("{username}:{password}"));
+                insert(AUTHORIZATION, format!("Basic {encoded}"))?;
+            }
+            Some(Credentials::Query(_)) => {
+                return Err("query credentials can only be sent with HTTP requests".to_owned());
+            }
+            None => {}
+        }
+        if let Some(cookies) = &self.cookies {
+            insert(COOKIE, cookies.clone())?;
+        }
+        Ok((headers, state.generation))
+    }
+
+    // Returns true if there are new credentials to retry with. The lock is
+    // held while asking, so that concurrent failures only ask once.
+    pub(crate) fn refresh(&self, generation: u64) -> bool {
+        let mut state = self.state.lock().unwrap();
+        if state.generation != generation {
+            return true;
+        }
+        let Some(credentials) = self.reauthenticate.as_ref().and_then(|f| f(&self.baseurl)) else {
+            return false;
+        };
+        state.credentials = Some(credentials);
+        state.generation += 1;
+        true
+    }
+
+    pub fn fetch(
+        &self,
+        request: RequestBuilder,
+        cancel: CancelFlag,
+        retry: RetryConfig,
+        on_done: impl 'static + Send + FnOnce(Result<DataSourceResponse, String>),
+    ) {
+        let again = self
+            .reauthenticate
+            .as_ref()
+            .and_then(|_| request.try_clone());
+        let (request, generation) = self.apply(request);
+        let auth = self.clone();
+        fetch(request, cancel.clone(), retry, move |response| {
+            let rejected =
+                matches!(&response, Err(e) if e == UNAUTHORIZED || e.starts_with(FORBIDDEN));
+            match again {
+                Some(again) if rejected && auth.refresh(generation) => {
+                    let (again, _) = auth.apply(again);
+                    fetch(again, cancel, retry, on_done);
+                }
+                _ => on_done(response),
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    #[test]
+    fn test_basic() {
+        assert_eq!(
+            Credentials::basic("alice:secret:2"),
+            Credentials::Basic {
+                username: "alice".to_owned(),
+                password: Some("secret:2".to_owned()),
+            }
+        );
+        assert_eq!(
+            Credentials::basic("alice"),
+            Credentials::Basic {
+                username: "alice".to_owned(),
+                password: None,
+            }
+        );
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    #[test]
+    fn test_query() {
+        let config = AuthConfig {
+            credentials: Some(Credentials::Query("Expires=10&Signature=a%2Bb".to_owned())),
+            ..Default::default()
+        };
+        let url = Url::parse("http://localhost/").unwrap();
+        let auth = Authenticator::new(&url, config);
+        let client = reqwest::blocking::Client::new();
+        let request = client.get("http://localhost/info?full=true");
+        let (request, _) = auth.apply(request);
+        let request = request.build().unwrap();
+        assert_eq!(
+            request.url().query(),
+            Some("full=true&Expires=10&Signature=a%2Bb")
+        );
+    }
+
+    #[test]
+    fn test_refresh() {
+        let calls = Arc::new(AtomicU32::new(0));
+        let counter = calls.clone();
+        let config = AuthConfig {
+            credentials: Some(Credentials::Bearer("old".to_owned())),
+            reauthenticate: Some(Arc::new(move |_: &Url| {
+                counter.fetch_add(1, Ordering::Relaxed);
+                Some(Credentials::Bearer("new".to_owned()))
+            })),
+            ..Default::default()
+        };
+        let url = Url::parse("http://localhost/").unwrap();
+        let auth = Authenticator::new(&url, config);
+
+        // Two requests fail with the same credentials, but only the first
+        // asks for new ones
+        assert!(auth.refresh(0));
+        assert!(auth.refresh(0));
+        assert_eq!(calls.load(Ordering::Relaxed), 1);
+        let state = auth.state.lock().unwrap();
+        assert_eq!(
+            state.credentials,
+            Some(Credentials::Bearer("new".to_owned()))
+        );
+        assert_eq!(state.generation, 1);
+        drop(state);
+
+        // Without a callback, there is nothing to retry with
+        let auth = Authenticator::new(&url, AuthConfig::default());
+        assert!(!auth.refresh(0));
+    }
+}
@@ -0,0 +1,169 @@
+use std::sync::{Arc, Mutex};
+
+use bytes::Bytes;
+
+use log::debug;
+use lru::LruCache;
+
+use url::Url;
+
+use crate::http::fetch::DataSourceResponse;
+
+#[derive(Debug, Clone)]
+struct CachedResponse {
+    etag: String,
+    content_type: Option<String>,
+    body: Bytes,
+}
+
+struct CacheState {
+    budget: usize,
+    bytes: usize,
+    entries: LruCache<Url, CachedResponse>,
+}
+
+// Remembers the bodies of responses that came with an ETag, so that the same
+// URL can be revalidated with If-None-Match and a 304 answered from memory.
+// Bodies are kept until the cache reaches a given size in bytes, and then the
+// least recently used are evicted.
+#[derive(Clone)]
+pub struct ResponseCache {
+    state: Arc<Mutex<CacheState>>,
+}
+
+// A request in progress against a URL that may have been cached
+pub struct Revalidation {
+    cache: ResponseCache,
+    url: Url,
+    cached: Option<CachedResponse>,
+}
+
+impl ResponseCache {
+    pub fn new(budget: usize) -> Self {
+        Self {
+            state: Arc::new(Mutex::new(CacheState {
+                budget,
+                bytes: 0,
+                entries: LruCache::unbounded(),
+            })),
+        }
+    }
+
+    // The cached body is held onto for the duration of the request, so that
+    // a 304 can still be answered if it is evicted in the meantime
+    pub fn start(&self, url: &Url) -> Revalidation {
+        let cached = self.state.lock().unwrap().entries.get(url).cloned();
+        Revalidation {
+            cache: self.clone(),
+            url: url.clone(),
+            cached,
+        }
+    }
+
+    fn insert(&self, url: Url, cached: CachedResponse) {
+        let mut state = self.state.lock().unwrap();
+        let size = cached.body.len();
+        if size > state.budget {
+            debug!("response cache: not caching {url}, {size} bytes is over budget");
+            return;
+        }
+        if let Some(old) = state.entries.put(url, cached) {
+            state.bytes -= old.body.len();
+        }
+        state.bytes += size;
+        while state.bytes > state.budget {
+            let (url, evicted) = state.entries.pop_lru().unwrap();
+            debug!("response cache: evicting {url}");
+            state.bytes -= evicted.body.len();
+        }
+    }
+}
+
+impl Revalidation {
+    // Value for the If-None-Match header, if there is anything to revalidate
+    pub fn etag(&self) -> Option<&str> {
+        self.cached.as_ref().map(|cached| cached.etag.as_str())
+    }
+
+    // Fills in the body of a 304 from the cache, and caches anything new
+    pub fn finish(
+        self,
+        response: Result<DataSourceResponse, String>,
+    ) -> Result<DataSourceResponse, String> {
+        let mut response = response?;
+        if response.not_modified {
+            let cached = self
+                .cached
+                .ok_or_else(|| "server returned 304 for an unconditional request".to_owned())?;
+            debug!("response cache: {} not modified", self.url);
+            response.not_modified = false;
+            response.content_type = cached.content_type;
+            response.body = cached.body;
+        } else if let Some(etag) = &response.etag {
+            let cached = CachedResponse {
+                etag: etag.clone(),
+                content_type: response.content_type.clone(),
+                body: response.body.clone(),
+            };
+            self.cache.insert(self.url, cached);
+        }
+        Ok(response)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn response(etag: Option<&str>, body: &'static [u8]) -> DataSourceResponse {
+        DataSourceResponse {
+            body: Bytes::from_static(body),
+            etag: etag.map(str::to_owned),
+            content_type: None,
+            not_modified: false,
+        }
+    }
+
+    fn not_modified() -> DataSourceResponse {
+        DataSourceResponse {
+            body: Bytes::new(),
+            etag: None,
+            content_type: None,
+            not_modified: true,
+        }
+    }
+
+    #[test]
+    fn test_revalidation() {
+        let cache = ResponseCache::new(8);
+        let a = Url::parse("http://localhost/a").unwrap();
+        let b = Url::parse("http://localhost/b").unwrap();
+
+        // Nothing to revalidate until a response with an ETag comes back
+        let r = cache.start(&a);
+        assert_eq!(r.etag(), None);
+        r.finish(Ok(response(None, b"abc"))).unwrap();
+        let r = cache.start(&a);
+        assert_eq!(r.etag(), None);
+        assert!(r.finish(Ok(not_modified())).is_err());
+
+        cache
+            .start(&a)
+            .finish(Ok(response(Some("\"1\""), b"abcd")))
+            .unwrap();
+        let r = cache.start(&a);
+        assert_eq!(r.etag(), Some("\"1\""));
+        let body = r.finish(Ok(not_modified())).unwrap().body;
+        assert_eq!(&body[..], b"abcd");
+
+        // A request in flight keeps its body even if it is evicted
+        let r = cache.start(&a);
+        cache
+            .start(&b)
+            .finish(Ok(response(Some("\"2\""), b"efghij")))
+            .unwrap();
+        assert_eq!(cache.start(&a).etag(), None);
+        let body = r.finish(Ok(not_modified())).unwrap().body;
+        assert_eq!(&body[..], b"abcd");
+    }
+}
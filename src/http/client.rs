@@ -1,95 +1,1018 @@
+use std::borrow::Cow;
+use std::collections::{BTreeMap, BTreeSet};
+use std::marker::PhantomData;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
-use bytes::Buf;
+use bytes::Bytes;
 
-use log::info;
+use log::{info, warn};
 
 #[cfg(not(target_arch = "wasm32"))]
-use reqwest::blocking::{Client, ClientBuilder};
+use reqwest::blocking::{Client, ClientBuilder, RequestBuilder};
 #[cfg(target_arch = "wasm32")]
-use reqwest::{Client, ClientBuilder};
+use reqwest::{Client, ClientBuilder, RequestBuilder};
 
-use serde::Deserialize;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue, IF_NONE_MATCH};
+
+use serde::{Deserialize, Serialize};
+
+use serde_json::value::RawValue;
 
 use url::Url;
 
+use web_time::Instant;
+
 use crate::data::{
-    DataSourceDescription, DataSourceInfo, EntryID, SlotMetaTile, SlotTile, SummaryTile, TileID,
+    DataSourceDescription, DataSourceInfo, EntryID, ItemMeta, ItemMetaRequest, ItemUID,
+    PROTOCOL_VERSION, SlotMetaTile, SlotTile, SummaryTile, TileID,
 };
 use crate::deferred_data::{
-    DeferredDataSource, SlotMetaTileResponse, SlotTileResponse, SummaryTileResponse, TileRequest,
-    TileResponse,
+    CANCELLED, CancelFlag, CancelFlags, DataSourceInfoResult, DeferredDataSource,
+    DeferredProfileIndex, ItemsMetaResponse, RequestPriority, SlotMetaTileResponse,
+    SlotTileResponse, SummaryTileResponse, TileKind, TileRequest, TileResponse, TransferStats,
+};
+use crate::http::auth::{AuthConfig, Authenticator};
+use crate::http::cache::{ResponseCache, Revalidation};
+use crate::http::fetch::{DataSourceResponse, RetryConfig, for_each_yielding};
+use crate::http::queue::{InFlight, RequestQueue};
+use crate::http::schema::{
+    BodyFormat, ProfileIndex, ProfileIndexEntry, TileBatchRequest, TileIdentity, TileRequestRef,
+    VERSION_HEADER, VersionProbe, check_tile, check_version, decode_info, decompress,
+    detect_format,
 };
-use crate::http::fetch::{DataSourceResponse, fetch};
-use crate::http::schema::TileRequestRef;
 use crate::http::url::ensure_directory;
+use crate::timestamp::Interval;
+
+type ResponseContainer<T, E> = Arc<Mutex<Vec<(Result<T, String>, E)>>>;
+
+// Checks a decoded response against the request it answers
+type ResponseCheck<T, E> = fn(&T, &E) -> Result<(), String>;
+
+// Default maximum number of requests to have outstanding against the server
+// at once. Anything beyond this waits in the queue, highest priority first.
+// Browsers only open 6 connections per server, and anything more waits where
+// it can't be reprioritized.
+const MAX_IN_FLIGHT: usize = if cfg!(target_arch = "wasm32") { 6 } else { 8 };
+
+// Maximum number of tiles to request in a single batched request
+const MAX_BATCH_SIZE: usize = 32;
+
+// Size in bytes of the (still encoded) responses kept for revalidation
+const RESPONSE_CACHE_BYTES: usize = 64 << 20;
+
+// Responses may be CBOR or JSON, either of which may be zstd compressed
+fn decode<T>(response: &DataSourceResponse) -> Result<T, String>
+where
+    T: for<'a> Deserialize<'a>,
+{
+    let bytes = decompress(&response.body).map_err(|x| x.to_string())?;
+    match detect_format(response.content_type.as_deref(), &bytes) {
+        BodyFormat::Cbor => ciborium::from_reader(&bytes[..]).map_err(|x| x.to_string()),
+        BodyFormat::Json => serde_json::from_slice(&bytes).map_err(|x| x.to_string()),
+    }
+}
+
+// Length (if given) and size of the header of the CBOR array a batch of tiles
+// is sent as
+fn cbor_array_header(body: &[u8]) -> Result<(Option<usize>, usize), String> {
+    let not_array = || "expected an array of tiles".to_owned();
+    let (&first, rest) = body.split_first().ok_or_else(not_array)?;
+    if first >> 5 != 4 {
+        return Err(not_array());
+    }
+    let size = match first & 0x1f {
+        0..=23 => return Ok((Some((first & 0x1f) as usize), 1)),
+        24 => 1,
+        25 => 2,
+        26 => 4,
+        27 => 8,
+        31 => return Ok((None, 1)),
+        _ => return Err(not_array()),
+    };
+    let bytes = rest.get(..size).ok_or_else(not_array)?;
+    let len = bytes.iter().fold(0, |len, &byte| len << 8 | byte as u64);
+    Ok((Some(len as usize), 1 + size))
+}
+
+enum EncodedTiles {
+    // Where the next tile starts, and how many are left (unknown for an
+    // indefinite-length array, which ends at a break instead)
+    Cbor {
+        body: Bytes,
+        offset: usize,
+        remaining: Option<usize>,
+    },
+    Json(std::vec::IntoIter<Box<RawValue>>),
+    // A tile that couldn't be decoded leaves the rest of the CBOR unreadable
+    Failed(String),
+}
+
+// The tiles of a batch response, each decoded only when it is taken, so that
+// decoding a batch can be spread out (see for_each_yielding)
+struct BatchTiles<T> {
+    tiles: EncodedTiles,
+    _tile: PhantomData<fn() -> T>,
+}
+
+impl<T> BatchTiles<T> {
+    fn decode(response: &DataSourceResponse) -> Result<Self, String> {
+        let bytes = decompress(&response.body).map_err(|x| x.to_string())?;
+        let tiles = match detect_format(response.content_type.as_deref(), &bytes) {
+            BodyFormat::Cbor => {
+                let (remaining, offset) = cbor_array_header(&bytes)?;
+                let body = match bytes {
+                    Cow::Borrowed(_) => response.body.clone(),
+                    Cow::Owned(bytes) => Bytes::from(bytes),
+                };
+                EncodedTiles::Cbor {
+                    body,
+                    offset,
+                    remaining,
+                }
+            }
+            // Only checks the syntax, which is cheap next to decoding
+            BodyFormat::Json => {
+                let tiles: Vec<Box<RawValue>> =
+                    serde_json::from_slice(&bytes).map_err(|x| x.to_string())?;
+                EncodedTiles::Json(tiles.into_iter())
+            }
+        };
+        Ok(Self {
+            tiles,
+            _tile: PhantomData,
+        })
+    }
+
+    // Number of tiles in the batch, if it says
+    fn len(&self) -> Option<usize> {
+        match &self.tiles {
+            EncodedTiles::Cbor { remaining, .. } => *remaining,
+            EncodedTiles::Json(tiles) => Some(tiles.len()),
+            EncodedTiles::Failed(_) => None,
+        }
+    }
+}
+
+impl<T> Iterator for BatchTiles<T>
+where
+    T: for<'a> Deserialize<'a>,
+{
+    type Item = Result<T, String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let result = match &mut self.tiles {
+            EncodedTiles::Cbor {
+                body,
+                offset,
+                remaining,
+            } => {
+                match remaining {
+                    Some(0) => return None,
+                    Some(n) => *n -= 1,
+                    None if body.get(*offset) == Some(&0xff) => return None,
+                    None => {}
+                }
+                let mut reader = &body[*offset..];
+                let result = ciborium::from_reader(&mut reader).map_err(|x| x.to_string());
+                *offset = body.len() - reader.len();
+                result
+            }
+            EncodedTiles::Json(tiles) => {
+                return tiles
+                    .next()
+                    .map(|tile| serde_json::from_str(tile.get()).map_err(|x| x.to_string()));
+            }
+            EncodedTiles::Failed(e) => return Some(Err(e.clone())),
+        };
+        if let Err(e) = &result {
+            self.tiles = EncodedTiles::Failed(e.clone());
+        }
+        Some(result)
+    }
+}
+
+fn decode_server_info(response: &DataSourceResponse) -> Result<DataSourceInfo, String> {
+    let bytes = decompress(&response.body).map_err(|x| x.to_string())?;
+    match detect_format(response.content_type.as_deref(), &bytes) {
+        BodyFormat::Cbor => decode_info("server", &bytes),
+        BodyFormat::Json => {
+            let probe: VersionProbe = serde_json::from_slice(&bytes).map_err(|x| x.to_string())?;
+            check_version("server", probe.version)?;
+            serde_json::from_slice(&bytes).map_err(|x| x.to_string())
+        }
+    }
+}
+
+// Certificates to use in place of (or in addition to) the system's. Ignored
+// on the web, where the browser decides.
+#[derive(Debug, Clone, Default)]
+pub struct TlsConfig {
+    // PEM files of extra CAs to trust, e.g., for a self-signed certificate on
+    // a cluster head node
+    pub ca_certificates: Vec<PathBuf>,
+    // PEM file with the certificate (chain) to present to the server
+    pub client_certificate: Option<PathBuf>,
+    // PEM file with the PKCS#8 key for the client certificate, if it isn't in
+    // the same file
+    pub client_key: Option<PathBuf>,
+    // Skips verifying the server's certificate altogether. Only for testing.
+    pub accept_invalid_certs: bool,
+}
+
+// How long to wait on the server before giving up on a request (which is then
+// retried, if there are retries left). Ignored on the web, where the browser
+// decides.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimeoutConfig {
+    // For establishing a connection (including any TLS handshake)
+    pub connect: Option<Duration>,
+    // For each read from the server, so that a large download that is still
+    // making progress isn't cut off
+    pub read: Option<Duration>,
+}
+
+impl Default for TimeoutConfig {
+    fn default() -> Self {
+        Self {
+            connect: Some(Duration::from_secs(10)),
+            // Servers may take a while to compute a tile the first time
+            read: Some(Duration::from_secs(60)),
+        }
+    }
+}
+
+// How connections to the server are kept and reused. Tiles are many small
+// requests to the same host, so on a high-latency link most of the time goes
+// to setting up connections unless they are kept open. Ignored on the web,
+// where the browser decides.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConnectionConfig {
+    // Idle connections to keep open (enough for every request in flight if
+    // not given)
+    pub pool_size: Option<usize>,
+    // How long an idle connection is kept before closing it
+    pub idle_timeout: Option<Duration>,
+    // Interval of TCP keep-alive probes, so that middleboxes don't drop
+    // connections that are idle while the user looks at the profile
+    pub keepalive: Option<Duration>,
+    // Speak HTTP/2 to plain http:// servers without negotiating it first
+    // (https:// servers negotiate it automatically). Requests then share a
+    // single connection instead of one each.
+    pub http2_prior_knowledge: bool,
+}
+
+impl Default for ConnectionConfig {
+    fn default() -> Self {
+        Self {
+            pool_size: None,
+            idle_timeout: Some(Duration::from_secs(90)),
+            keepalive: Some(Duration::from_secs(30)),
+            http2_prior_knowledge: false,
+        }
+    }
+}
+
+// Options for connecting to the server
+#[derive(Clone, Default)]
+pub struct ClientConfig {
+    pub auth: AuthConfig,
+    // Sent with every request (e.g., an API key)
+    pub headers: Vec<(String, String)>,
+    pub user_agent: Option<String>,
+    // Proxy for every request, e.g., "http://proxy:3128" or, with the socks
+    // feature, "socks5://localhost:1080". Without it, the usual environment
+    // variables (HTTPS_PROXY, etc.) apply. Ignored on the web, where the
+    // browser decides.
+    pub proxy: Option<String>,
+    // Hosts to reach without the proxy, in the same form as NO_PROXY (e.g.,
+    // "localhost,.internal,10.0.0.0/8"), which applies if not given
+    pub no_proxy: Option<String>,
+    pub tls: TlsConfig,
+    pub timeouts: TimeoutConfig,
+    // Timeouts, dropped connections and gateway errors (502, 503, 504) are
+    // retried this many times before the request fails
+    pub retry: RetryConfig,
+    // Requests to have in flight at once (MAX_IN_FLIGHT if not given)
+    pub max_in_flight: Option<usize>,
+    pub connections: ConnectionConfig,
+}
+
+// The usual environment variables naming a proxy, which reqwest reads itself
+// when no proxy is given
+pub const PROXY_VARS: [&str; 6] = [
+    "HTTP_PROXY",
+    "http_proxy",
+    "HTTPS_PROXY",
+    "https_proxy",
+    "ALL_PROXY",
+    "all_proxy",
+];
+
+#[cfg(not(target_arch = "wasm32"))]
+fn configure_proxy(
+    mut builder: ClientBuilder,
+    config: &ClientConfig,
+) -> Result<ClientBuilder, String> {
+    use reqwest::{NoProxy, Proxy};
+
+    let var = |names: &[&str]| {
+        names
+            .iter()
+            .find_map(|name| std::env::var(name).ok())
+            .filter(|value| !value.is_empty())
+    };
+    let mut proxies = Vec::new();
+    if let Some(proxy) = &config.proxy {
+        proxies.push(Proxy::all(proxy));
+    } else if config.no_proxy.is_some() {
+        // reqwest only reads the environment when given no proxy at all, so
+        // to bypass other hosts than NO_PROXY's, add its proxies here
+        if let Some(proxy) = var(&PROXY_VARS[0..2]) {
+            proxies.push(Proxy::http(proxy));
+        }
+        if let Some(proxy) = var(&PROXY_VARS[2..4]) {
+            proxies.push(Proxy::https(proxy));
+        }
+        if let Some(proxy) = var(&PROXY_VARS[4..6]) {
+            proxies.push(Proxy::all(proxy));
+        }
+    }
+    for proxy in proxies {
+        let proxy = proxy.map_err(|e| format!("invalid proxy: {e}"))?;
+        let no_proxy = match &config.no_proxy {
+            Some(hosts) => NoProxy::from_string(hosts),
+            None => NoProxy::from_env(),
+        };
+        builder = builder.proxy(proxy.no_proxy(no_proxy));
+    }
+    Ok(builder)
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn configure_tls(mut builder: ClientBuilder, tls: &TlsConfig) -> Result<ClientBuilder, String> {
+    use reqwest::{Certificate, Identity};
+
+    let read = |path: &PathBuf| {
+        std::fs::read(path).map_err(|e| format!("unable to read {}: {e}", path.display()))
+    };
+    for path in &tls.ca_certificates {
+        let certificates = Certificate::from_pem_bundle(&read(path)?)
+            .map_err(|e| format!("invalid certificate in {}: {e}", path.display()))?;
+        for certificate in certificates {
+            builder = builder.add_root_certificate(certificate);
+        }
+    }
+    if let Some(path) = &tls.client_certificate {
+        let certificate = read(path)?;
+        let key = match &tls.client_key {
+            Some(key) => read(key)?,
+            None => certificate.clone(),
+        };
+        let identity = Identity::from_pkcs8_pem(&certificate, &key)
+            .map_err(|e| format!("invalid client certificate in {}: {e}", path.display()))?;
+        builder = builder.identity(identity);
+    }
+    Ok(builder.danger_accept_invalid_certs(tls.accept_invalid_certs))
+}
+
+// Other transports (e.g., gRPC) start from the same builder and adjust it
+pub(crate) fn client_builder(config: &ClientConfig) -> Result<ClientBuilder, String> {
+    let mut headers = HeaderMap::new();
+    for (name, value) in &config.headers {
+        let name = HeaderName::from_bytes(name.as_bytes())
+            .map_err(|e| format!("invalid header name {name:?}: {e}"))?;
+        let value =
+            HeaderValue::from_str(value).map_err(|e| format!("invalid header value: {e}"))?;
+        headers.append(name, value);
+    }
+    let mut builder = ClientBuilder::new().default_headers(headers);
+    if let Some(user_agent) = &config.user_agent {
+        builder = builder.user_agent(user_agent);
+    }
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        builder = configure_proxy(builder, config)?;
+        builder = configure_tls(builder, &config.tls)?;
+        builder = builder
+            .connect_timeout(config.timeouts.connect)
+            .timeout(config.timeouts.read);
+        let connections = &config.connections;
+        let pool_size = connections
+            .pool_size
+            .unwrap_or(config.max_in_flight.unwrap_or(MAX_IN_FLIGHT));
+        builder = builder
+            .pool_max_idle_per_host(pool_size)
+            .pool_idle_timeout(connections.idle_timeout)
+            .tcp_keepalive(connections.keepalive)
+            .tcp_nodelay(true)
+            // Lets the window grow to fill a long, fat link, rather than
+            // stalling large tiles on round trips
+            .http2_adaptive_window(true);
+        if connections.http2_prior_knowledge {
+            builder = builder.http2_prior_knowledge();
+        }
+    }
+    Ok(builder)
+}
+
+// One request, from leaving the queue to having its response decoded, for the
+// source's transfer statistics
+struct Transfer {
+    url: Url,
+    revalidation: Option<Revalidation>,
+    stats: Arc<Mutex<TransferStats>>,
+    start: Instant,
+    queued: Duration,
+}
+
+impl Transfer {
+    // Time spent waiting in the queue doesn't count, but is logged
+    fn start(mut self) -> Self {
+        self.queued = self.start.elapsed();
+        self.start = Instant::now();
+        self
+    }
+
+    // Called wherever the response arrives. On native that is one of the
+    // fetch threads, so decoding never holds up the UI.
+    fn finish<T>(
+        self,
+        response: Result<DataSourceResponse, String>,
+        decode: impl FnOnce(&DataSourceResponse) -> Result<T, String>,
+    ) -> Result<T, String> {
+        let latency = self.start.elapsed();
+        // The body of a 304 comes from the cache, not the network
+        let bytes = match &response {
+            Ok(r) if !r.not_modified => r.body.len(),
+            _ => 0,
+        };
+        let response = match self.revalidation {
+            Some(revalidation) => revalidation.finish(response),
+            None => response,
+        };
+        let decode_start = Instant::now();
+        let result = response.and_then(|r| decode(&r));
+        let decode_time = decode_start.elapsed();
+
+        // Cancelled requests say nothing about the server
+        if matches!(&result, Err(e) if e == CANCELLED) {
+            return result;
+        }
+        let mut stats = self.stats.lock().unwrap();
+        stats.requests += 1;
+        stats.bytes += bytes as u64;
+        stats.total_latency += latency;
+        stats.max_latency = stats.max_latency.max(latency);
+        stats.decode_time += decode_time;
+        match &result {
+            Ok(_) => info!(
+                "fetched {}: {bytes} bytes in {:.0} ms after {:.0} ms queued, decoded in {:.1} ms",
+                self.url,
+                latency.as_secs_f64() * 1e3,
+                self.queued.as_secs_f64() * 1e3,
+                decode_time.as_secs_f64() * 1e3
+            ),
+            Err(e) => {
+                stats.errors += 1;
+                warn!("failed to fetch {}: {e}", self.url);
+            }
+        }
+        result
+    }
+}
 
 pub struct HTTPClientDataSource {
     pub baseurl: Url,
     pub client: Client,
-    infos: Arc<Mutex<Vec<DataSourceInfo>>>,
+    infos: Arc<Mutex<Vec<DataSourceInfoResult>>>,
     summary_tiles: Arc<Mutex<Vec<SummaryTileResponse>>>,
     slot_tiles: Arc<Mutex<Vec<SlotTileResponse>>>,
     slot_meta_tiles: Arc<Mutex<Vec<SlotMetaTileResponse>>>,
+    items_meta: Arc<Mutex<Vec<ItemsMetaResponse>>>,
+    cancel_flags: CancelFlags,
+    queue: RequestQueue,
+    // Whether the server accepts batched tile requests, per its info. Until
+    // the info arrives, tiles are requested one at a time.
+    batch_fetch: Arc<AtomicBool>,
+    // Likewise, whether the server looks up item metadata by item. Otherwise
+    // it is found in the slot meta tiles covering the items.
+    items_meta_endpoint: Arc<AtomicBool>,
+    // The last info received, whose tile sets say which slot meta tiles a
+    // static server has to look items up in
+    info: Arc<Mutex<Option<DataSourceInfo>>>,
+    cache: ResponseCache,
+    auth: Authenticator,
+    retry: RetryConfig,
+    stats: Arc<Mutex<TransferStats>>,
 }
 
 impl HTTPClientDataSource {
     pub fn new(baseurl: Url) -> Self {
-        Self {
-            baseurl: ensure_directory(&baseurl),
-            client: ClientBuilder::new().build().unwrap(),
+        Self::with_config(baseurl, ClientConfig::default()).unwrap()
+    }
+
+    pub fn with_config(baseurl: Url, config: ClientConfig) -> Result<Self, String> {
+        let baseurl = ensure_directory(&baseurl);
+        let max_in_flight = config.max_in_flight.unwrap_or(MAX_IN_FLIGHT);
+        if max_in_flight == 0 {
+            return Err("at least one request must be allowed in flight".to_owned());
+        }
+        let client = client_builder(&config)?
+            .build()
+            .map_err(|e| e.to_string())?;
+        Ok(Self {
+            auth: Authenticator::new(&baseurl, config.auth),
+            baseurl,
+            client,
             infos: Arc::new(Mutex::new(Vec::new())),
             summary_tiles: Arc::new(Mutex::new(Vec::new())),
             slot_tiles: Arc::new(Mutex::new(Vec::new())),
             slot_meta_tiles: Arc::new(Mutex::new(Vec::new())),
-        }
+            items_meta: Arc::new(Mutex::new(Vec::new())),
+            cancel_flags: CancelFlags::default(),
+            queue: RequestQueue::new(max_in_flight),
+            batch_fetch: Arc::new(AtomicBool::new(false)),
+            items_meta_endpoint: Arc::new(AtomicBool::new(false)),
+            info: Arc::new(Mutex::new(None)),
+            cache: ResponseCache::new(RESPONSE_CACHE_BYTES),
+            retry: config.retry,
+            stats: Arc::new(Mutex::new(TransferStats::default())),
+        })
     }
 
-    fn request<T>(&mut self, url: Url, container: Arc<Mutex<Vec<T>>>)
-    where
-        T: 'static + Sync + Send + for<'a> Deserialize<'a>,
-    {
+    fn request_info(&mut self, url: Url) {
         info!("fetch: {}", url);
-        let request = self
+        let (request, transfer) = self.get(url);
+        let container = self.infos.clone();
+        let batch_fetch = self.batch_fetch.clone();
+        let items_meta_endpoint = self.items_meta_endpoint.clone();
+        let last_info = self.info.clone();
+        let auth = self.auth.clone();
+        let retry = self.retry;
+        self.queue.push(RequestPriority::Visible, move |slot| {
+            let transfer = transfer.start();
+            auth.fetch(
+                request,
+                CancelFlag::default(),
+                retry,
+                move |response: Result<DataSourceResponse, String>| {
+                    let _slot = slot;
+                    let result = transfer.finish(response, decode_server_info);
+                    if let Ok(info) = &result {
+                        batch_fetch.store(info.capabilities.batch_fetch, Ordering::Relaxed);
+                        items_meta_endpoint.store(info.capabilities.items_meta, Ordering::Relaxed);
+                        *last_info.lock().unwrap() = Some(info.clone());
+                    }
+                    container.lock().unwrap().push(result);
+                },
+            );
+        });
+    }
+
+    // Anything fetched before is revalidated, so that the server can skip
+    // sending it again if it hasn't changed
+    fn get(&self, url: Url) -> (RequestBuilder, Transfer) {
+        let revalidation = self.cache.start(&url);
+        let mut request = self
             .client
-            .get(url)
+            .get(url.clone())
             .header("Accept", "*/*")
-            .header("Content-Type", "application/octet-stream;");
-        fetch(
-            request,
-            move |response: Result<DataSourceResponse, String>| {
-                let f = response.unwrap().body.reader();
-                let f = zstd::Decoder::new(f).expect("zstd decompression failed");
-                let result = ciborium::from_reader(f).expect("cbor decoding failed");
-                container.lock().unwrap().push(result);
-            },
-        );
+            .header("Content-Type", "application/octet-stream;")
+            .header(VERSION_HEADER, PROTOCOL_VERSION.to_string());
+        if let Some(etag) = revalidation.etag() {
+            request = request.header(IF_NONE_MATCH, etag);
+        }
+        (request, self.transfer(url, Some(revalidation)))
+    }
+
+    fn transfer(&self, url: Url, revalidation: Option<Revalidation>) -> Transfer {
+        Transfer {
+            url,
+            revalidation,
+            stats: self.stats.clone(),
+            start: Instant::now(),
+            queued: Duration::ZERO,
+        }
+    }
+
+    // Failures to even form a request are reported the same way as failed
+    // requests, rather than bringing down the viewer
+    fn endpoint(&self, path: &str) -> Result<Url, String> {
+        self.baseurl
+            .join(path)
+            .map_err(|e| format!("invalid URL for {path}: {e}"))
+    }
+
+    fn tile_url(
+        &self,
+        path: &str,
+        entry_id: &EntryID,
+        tile_id: TileID,
+        full: bool,
+    ) -> Result<Url, String> {
+        let req = TileRequestRef { entry_id, tile_id };
+        let mut url = self.endpoint(&format!("{path}/{}", req.to_slug()))?;
+        url.set_query(Some(&format!("full={}", full)));
+        Ok(url)
     }
 
     fn request_extra<T>(
         &mut self,
-        url: Url,
+        url: Result<Url, String>,
         container: Arc<Mutex<Vec<TileResponse<T>>>>,
         extra: TileRequest,
+        priority: RequestPriority,
     ) where
-        T: 'static + Sync + Send + for<'a> Deserialize<'a>,
+        T: 'static + Sync + Send + for<'a> Deserialize<'a> + TileIdentity,
     {
+        let url = match url {
+            Ok(url) => url,
+            Err(e) => {
+                container.lock().unwrap().push((Err(e), extra));
+                return;
+            }
+        };
         info!("fetch: {}", url);
-        let request = self
+        let (request, transfer) = self.get(url);
+        let cancel = self.cancel_flags.start(extra.clone());
+        let auth = self.auth.clone();
+        let retry = self.retry;
+        self.queue.push(priority, move |slot| {
+            Self::fetch_extra(
+                auth,
+                retry,
+                request,
+                transfer.start(),
+                cancel,
+                container,
+                extra,
+                check_tile,
+                slot,
+            )
+        });
+    }
+
+    fn post_extra<B, T, E>(
+        &mut self,
+        url: Result<Url, String>,
+        body: &B,
+        container: ResponseContainer<T, E>,
+        extra: E,
+    ) where
+        B: Serialize,
+        T: 'static + Sync + Send + for<'a> Deserialize<'a>,
+        E: 'static + Sync + Send,
+    {
+        let url = match url {
+            Ok(url) => url,
+            Err(e) => {
+                container.lock().unwrap().push((Err(e), extra));
+                return;
+            }
+        };
+        let transfer = self.transfer(url.clone(), None);
+        let request = match self.post(url, body) {
+            Ok(request) => request,
+            Err(e) => {
+                container.lock().unwrap().push((Err(e), extra));
+                return;
+            }
+        };
+        let auth = self.auth.clone();
+        let retry = self.retry;
+        self.queue.push(RequestPriority::Visible, move |slot| {
+            Self::fetch_extra(
+                auth,
+                retry,
+                request,
+                transfer.start(),
+                CancelFlag::default(),
+                container,
+                extra,
+                |_, _| Ok(()),
+                slot,
+            )
+        });
+    }
+
+    fn post_batch<T>(
+        &mut self,
+        kind: TileKind,
+        requests: &[TileRequest],
+        container: Arc<Mutex<Vec<TileResponse<T>>>>,
+        priority: RequestPriority,
+    ) where
+        T: 'static + Sync + Send + for<'a> Deserialize<'a> + TileIdentity,
+    {
+        let url = match self.endpoint("tiles") {
+            Ok(url) => url,
+            Err(e) => {
+                let errors = requests.iter().map(|req| (Err(e.clone()), req.clone()));
+                container.lock().unwrap().extend(errors);
+                return;
+            }
+        };
+        // Keep batches small enough that tiles still trickle in as the
+        // batches complete, rather than all at once at the end
+        for chunk in requests.chunks(MAX_BATCH_SIZE) {
+            let batch = TileBatchRequest {
+                kind,
+                requests: chunk.to_vec(),
+            };
+            let transfer = self.transfer(url.clone(), None);
+            let request = match self.post(url.clone(), &batch) {
+                Ok(request) => request,
+                Err(e) => {
+                    let mut container = container.lock().unwrap();
+                    container.extend(batch.requests.into_iter().map(|req| (Err(e.clone()), req)));
+                    continue;
+                }
+            };
+            let container = container.clone();
+            let cancels: Vec<_> = batch
+                .requests
+                .iter()
+                .map(|req| self.cancel_flags.start(req.clone()))
+                .collect();
+            let auth = self.auth.clone();
+            let retry = self.retry;
+            let batch_fetch = self.batch_fetch.clone();
+            self.queue.push(priority, move |slot| {
+                // A batch whose tiles were all cancelled while it waited in
+                // the queue is never sent
+                if cancels.iter().all(CancelFlag::is_cancelled) {
+                    let cancelled = batch
+                        .requests
+                        .into_iter()
+                        .map(|req| (Err(CANCELLED.to_owned()), req));
+                    container.lock().unwrap().extend(cancelled);
+                    return;
+                }
+                Self::fetch_batch(
+                    auth,
+                    retry,
+                    request,
+                    transfer.start(),
+                    container,
+                    batch.requests,
+                    cancels,
+                    batch_fetch,
+                    slot,
+                )
+            });
+        }
+    }
+
+    fn post<B>(&self, url: Url, body: &B) -> Result<RequestBuilder, String>
+    where
+        B: Serialize,
+    {
+        info!("post: {}", url);
+        let encoded = zstd::Encoder::new(Vec::new(), 1)
+            .map_err(|x| x.to_string())
+            .and_then(|mut f| {
+                ciborium::into_writer(body, &mut f).map_err(|x| x.to_string())?;
+                f.finish().map_err(|x| x.to_string())
+            })?;
+        Ok(self
             .client
-            .get(url)
+            .post(url)
             .header("Accept", "*/*")
-            .header("Content-Type", "application/octet-stream;");
-        fetch(
+            .header("Content-Type", "application/octet-stream;")
+            .header(VERSION_HEADER, PROTOCOL_VERSION.to_string())
+            .body(encoded))
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn fetch_batch<T>(
+        auth: Authenticator,
+        retry: RetryConfig,
+        request: RequestBuilder,
+        transfer: Transfer,
+        container: Arc<Mutex<Vec<TileResponse<T>>>>,
+        requests: Vec<TileRequest>,
+        cancels: Vec<CancelFlag>,
+        batch_fetch: Arc<AtomicBool>,
+        slot: InFlight,
+    ) where
+        T: 'static + Sync + Send + for<'a> Deserialize<'a> + TileIdentity,
+    {
+        // The batch as a whole can't be stopped once sent, since some of its
+        // tiles may still be wanted
+        auth.fetch(
             request,
+            CancelFlag::default(),
+            retry,
             move |response: Result<DataSourceResponse, String>| {
-                let result = response
-                    .and_then(|r| zstd::Decoder::new(r.body.reader()).map_err(|x| x.to_string()))
-                    .and_then(|f| ciborium::from_reader(f).map_err(|x| x.to_string()));
+                let _slot = slot;
+                let stats = transfer.stats.clone();
+                let result = transfer
+                    .finish(response, BatchTiles::<T>::decode)
+                    .and_then(|tiles| match tiles.len() {
+                        Some(len) if len != requests.len() => Err(format!(
+                            "expected {} tiles in batch, got {len}",
+                            requests.len()
+                        )),
+                        _ => Ok(tiles),
+                    });
+                let mut tiles = match result {
+                    Ok(tiles) => tiles,
+                    Err(e) => {
+                        // The server advertised batches, but something in
+                        // front of it (e.g., a proxy) doesn't pass them on,
+                        // so go back to fetching tiles one at a time
+                        if e.starts_with("404") || e.starts_with("405") {
+                            warn!("batched tile requests are not supported, falling back: {e}");
+                            batch_fetch.store(false, Ordering::Relaxed);
+                        }
+                        let mut container = container.lock().unwrap();
+                        container.extend(requests.into_iter().map(|req| (Err(e.clone()), req)));
+                        return;
+                    }
+                };
+
+                // Each tile is decoded and answered on its own, so that tiles
+                // trickle in and the web can draw frames in between. Tiles
+                // cancelled in the meantime are answered as such, the same as
+                // if they had been fetched alone.
+                let expected = requests.len();
+                let requests = requests.into_iter().zip(cancels);
+                for_each_yielding(requests, move |(req, cancel)| {
+                    let decode_start = Instant::now();
+                    let tile = tiles.next().unwrap_or_else(|| {
+                        Err(format!("expected {expected} tiles in batch, got fewer"))
+                    });
+                    stats.lock().unwrap().decode_time += decode_start.elapsed();
+                    let result = if cancel.is_cancelled() {
+                        Err(CANCELLED.to_owned())
+                    } else {
+                        tile.and_then(|tile| check_tile(&tile, &req).map(|()| tile))
+                    };
+                    container.lock().unwrap().push((result, req));
+                });
+            },
+        );
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn fetch_extra<T, E>(
+        auth: Authenticator,
+        retry: RetryConfig,
+        request: RequestBuilder,
+        transfer: Transfer,
+        cancel: CancelFlag,
+        container: ResponseContainer<T, E>,
+        extra: E,
+        check: ResponseCheck<T, E>,
+        slot: InFlight,
+    ) where
+        T: 'static + Sync + Send + for<'a> Deserialize<'a>,
+        E: 'static + Sync + Send,
+    {
+        auth.fetch(
+            request,
+            cancel,
+            retry,
+            move |response: Result<DataSourceResponse, String>| {
+                let _slot = slot;
+                let result = transfer
+                    .finish(response, decode)
+                    .and_then(|value| check(&value, &extra).map(|()| value));
                 container.lock().unwrap().push((result, extra));
             },
         );
     }
+
+    // Servers without an items_meta endpoint are asked for the full slot meta
+    // tiles covering the items. Static servers only have the tiles in their
+    // tile sets, so these are the tiles at the finest level that overlap each
+    // item. Servers that make any tile (and those whose info hasn't arrived)
+    // are asked for one tile per entry, covering all the items in that entry.
+    fn fetch_items_meta_from_tiles(&mut self, requests: &[ItemMetaRequest]) {
+        let mut tiles: BTreeMap<(EntryID, TileID), BTreeSet<ItemUID>> = BTreeMap::new();
+        let mut entries: BTreeMap<EntryID, (Interval, BTreeSet<ItemUID>)> = BTreeMap::new();
+        let info = self.info.lock().unwrap();
+        for req in requests {
+            let tile_ids = info
+                .as_ref()
+                .and_then(|info| info.entry_tile_set(&req.entry_id).tiles.last());
+            let Some(tile_ids) = tile_ids else {
+                let (interval, item_uids) = entries
+                    .entry(req.entry_id.clone())
+                    .or_insert_with(|| (req.interval, BTreeSet::new()));
+                *interval = interval.union(req.interval);
+                item_uids.insert(req.item_uid);
+                continue;
+            };
+            for tile_id in tile_ids {
+                if tile_id.0.overlaps(req.interval) {
+                    tiles
+                        .entry((req.entry_id.clone(), *tile_id))
+                        .or_default()
+                        .insert(req.item_uid);
+                }
+            }
+        }
+        drop(info);
+        for (entry_id, (interval, item_uids)) in entries {
+            tiles.insert((entry_id, TileID(interval)), item_uids);
+        }
+
+        let pending = Arc::new(Mutex::new(ItemsMetaFromTiles {
+            remaining: tiles.len(),
+            items: Ok(Vec::new()),
+            seen: BTreeSet::new(),
+            requests: requests.to_vec(),
+        }));
+        if tiles.is_empty() {
+            pending.lock().unwrap().finish(&self.items_meta);
+            return;
+        }
+        for ((entry_id, tile_id), item_uids) in tiles {
+            let url = match self.tile_url("slot_meta_tile", &entry_id, tile_id, true) {
+                Ok(url) => url,
+                Err(e) => {
+                    let mut pending = pending.lock().unwrap();
+                    pending.add_tile(Err(e), &item_uids);
+                    pending.finish(&self.items_meta);
+                    continue;
+                }
+            };
+            info!("fetch: {}", url);
+            let tile_req = TileRequest {
+                entry_id,
+                tile_id,
+                full: true,
+            };
+            let (request, transfer) = self.get(url);
+            let container = self.items_meta.clone();
+            let pending = pending.clone();
+            let auth = self.auth.clone();
+            let retry = self.retry;
+            self.queue.push(RequestPriority::Visible, move |slot| {
+                let transfer = transfer.start();
+                auth.fetch(
+                    request,
+                    CancelFlag::default(),
+                    retry,
+                    move |response: Result<DataSourceResponse, String>| {
+                        let _slot = slot;
+                        let result =
+                            transfer
+                                .finish(response, decode)
+                                .and_then(|tile: SlotMetaTile| {
+                                    check_tile(&tile, &tile_req).map(|()| tile)
+                                });
+                        let mut pending = pending.lock().unwrap();
+                        pending.add_tile(result, &item_uids);
+                        pending.finish(&container);
+                    },
+                );
+            });
+        }
+    }
+}
+
+// The slot meta tiles an items_meta request is answered from, which is sent
+// once the last of them arrives
+struct ItemsMetaFromTiles {
+    remaining: usize,
+    items: Result<Vec<ItemMeta>, String>,
+    // Items may span several tiles, so only keep the first copy
+    seen: BTreeSet<(EntryID, ItemUID)>,
+    requests: Vec<ItemMetaRequest>,
+}
+
+impl ItemsMetaFromTiles {
+    fn add_tile(&mut self, tile: Result<SlotMetaTile, String>, item_uids: &BTreeSet<ItemUID>) {
+        self.remaining -= 1;
+        let Ok(items) = &mut self.items else {
+            return;
+        };
+        match tile {
+            Ok(tile) => {
+                for item in tile.data.items.into_iter().flatten() {
+                    if item_uids.contains(&item.item_uid)
+                        && self.seen.insert((tile.entry_id.clone(), item.item_uid))
+                    {
+                        items.push(item);
+                    }
+                }
+            }
+            Err(e) => self.items = Err(e),
+        }
+    }
+
+    fn finish(&mut self, container: &Mutex<Vec<ItemsMetaResponse>>) {
+        if self.remaining == 0 {
+            let items = std::mem::replace(&mut self.items, Ok(Vec::new()));
+            let requests = std::mem::take(&mut self.requests);
+            container.lock().unwrap().push((items, requests));
+        }
+    }
 }
 
 impl DeferredDataSource for HTTPClientDataSource {
@@ -100,71 +1023,603 @@ impl DeferredDataSource for HTTPClientDataSource {
     }
 
     fn fetch_info(&mut self) {
-        let url = self.baseurl.join("info").expect("invalid baseurl");
-        self.request::<DataSourceInfo>(url, self.infos.clone());
+        match self.endpoint("info") {
+            Ok(url) => self.request_info(url),
+            Err(e) => self.infos.lock().unwrap().push(Err(e)),
+        }
     }
 
-    fn get_infos(&mut self) -> Vec<DataSourceInfo> {
+    fn get_infos(&mut self) -> Vec<DataSourceInfoResult> {
+        self.queue.pump();
         std::mem::take(&mut self.infos.lock().unwrap())
     }
 
-    fn fetch_summary_tile(&mut self, entry_id: &EntryID, tile_id: TileID, full: bool) {
-        let req = TileRequestRef { entry_id, tile_id };
-        let mut url = self
-            .baseurl
-            .join("summary_tile/")
-            .and_then(|u| u.join(&req.to_slug()))
-            .expect("invalid baseurl");
-        url.set_query(Some(&format!("full={}", full)));
+    fn fetch_summary_tile(
+        &mut self,
+        entry_id: &EntryID,
+        tile_id: TileID,
+        full: bool,
+        priority: RequestPriority,
+    ) {
+        let url = self.tile_url("summary_tile", entry_id, tile_id, full);
         let extra = TileRequest {
             entry_id: entry_id.clone(),
             tile_id,
             full,
         };
-        self.request_extra::<SummaryTile>(url, self.summary_tiles.clone(), extra);
+        self.request_extra::<SummaryTile>(url, self.summary_tiles.clone(), extra, priority);
     }
 
     fn get_summary_tiles(&mut self) -> Vec<SummaryTileResponse> {
+        self.queue.pump();
         std::mem::take(&mut self.summary_tiles.lock().unwrap())
     }
 
-    fn fetch_slot_tile(&mut self, entry_id: &EntryID, tile_id: TileID, full: bool) {
-        let req = TileRequestRef { entry_id, tile_id };
-        let mut url = self
-            .baseurl
-            .join("slot_tile/")
-            .and_then(|u| u.join(&req.to_slug()))
-            .expect("invalid baseurl");
-        url.set_query(Some(&format!("full={}", full)));
+    fn fetch_slot_tile(
+        &mut self,
+        entry_id: &EntryID,
+        tile_id: TileID,
+        full: bool,
+        priority: RequestPriority,
+    ) {
+        let url = self.tile_url("slot_tile", entry_id, tile_id, full);
         let extra = TileRequest {
             entry_id: entry_id.clone(),
             tile_id,
             full,
         };
-        self.request_extra::<SlotTile>(url, self.slot_tiles.clone(), extra);
+        self.request_extra::<SlotTile>(url, self.slot_tiles.clone(), extra, priority);
     }
 
     fn get_slot_tiles(&mut self) -> Vec<SlotTileResponse> {
+        self.queue.pump();
         std::mem::take(&mut self.slot_tiles.lock().unwrap())
     }
 
-    fn fetch_slot_meta_tile(&mut self, entry_id: &EntryID, tile_id: TileID, full: bool) {
-        let req = TileRequestRef { entry_id, tile_id };
-        let mut url = self
-            .baseurl
-            .join("slot_meta_tile/")
-            .and_then(|u| u.join(&req.to_slug()))
-            .expect("invalid baseurl");
-        url.set_query(Some(&format!("full={}", full)));
+    fn fetch_slot_meta_tile(
+        &mut self,
+        entry_id: &EntryID,
+        tile_id: TileID,
+        full: bool,
+        priority: RequestPriority,
+    ) {
+        let url = self.tile_url("slot_meta_tile", entry_id, tile_id, full);
         let extra = TileRequest {
             entry_id: entry_id.clone(),
             tile_id,
             full,
         };
-        self.request_extra::<SlotMetaTile>(url, self.slot_meta_tiles.clone(), extra);
+        self.request_extra::<SlotMetaTile>(url, self.slot_meta_tiles.clone(), extra, priority);
     }
 
     fn get_slot_meta_tiles(&mut self) -> Vec<SlotMetaTileResponse> {
+        self.queue.pump();
         std::mem::take(&mut self.slot_meta_tiles.lock().unwrap())
     }
+
+    fn fetch_items_meta(&mut self, requests: &[ItemMetaRequest]) {
+        if !self.items_meta_endpoint.load(Ordering::Relaxed) {
+            self.fetch_items_meta_from_tiles(requests);
+            return;
+        }
+        let url = self.endpoint("items_meta");
+        let extra = requests.to_vec();
+        self.post_extra::<_, Vec<ItemMeta>, _>(url, &extra, self.items_meta.clone(), extra.clone());
+    }
+
+    fn get_items_meta(&mut self) -> Vec<ItemsMetaResponse> {
+        self.queue.pump();
+        std::mem::take(&mut self.items_meta.lock().unwrap())
+    }
+
+    fn fetch_tiles(&mut self, kind: TileKind, requests: &[TileRequest], priority: RequestPriority) {
+        if !self.batch_fetch.load(Ordering::Relaxed) {
+            for req in requests {
+                let TileRequest {
+                    entry_id,
+                    tile_id,
+                    full,
+                } = req;
+                match kind {
+                    TileKind::Summary => {
+                        self.fetch_summary_tile(entry_id, *tile_id, *full, priority)
+                    }
+                    TileKind::Slot => self.fetch_slot_tile(entry_id, *tile_id, *full, priority),
+                    TileKind::SlotMeta => {
+                        self.fetch_slot_meta_tile(entry_id, *tile_id, *full, priority)
+                    }
+                }
+            }
+            return;
+        }
+        match kind {
+            TileKind::Summary => {
+                self.post_batch::<SummaryTile>(kind, requests, self.summary_tiles.clone(), priority)
+            }
+            TileKind::Slot => {
+                self.post_batch::<SlotTile>(kind, requests, self.slot_tiles.clone(), priority)
+            }
+            TileKind::SlotMeta => self.post_batch::<SlotMetaTile>(
+                kind,
+                requests,
+                self.slot_meta_tiles.clone(),
+                priority,
+            ),
+        }
+    }
+
+    fn cancel(&mut self, entry_id: &EntryID, tile_id: TileID, full: bool) {
+        self.cancel_flags.cancel(&TileRequest {
+            entry_id: entry_id.clone(),
+            tile_id,
+            full,
+        });
+    }
+
+    fn transfer_stats(&self) -> Option<TransferStats> {
+        Some(*self.stats.lock().unwrap())
+    }
+
+    // Transient errors only (see fetch), which are the only ones worth it
+    fn retries_failures(&self) -> bool {
+        true
+    }
+}
+
+// The index of the profiles hosted under a URL, each of which is opened as an
+// HTTPClientDataSource with the same configuration
+pub struct HTTPProfileIndex {
+    pub baseurl: Url,
+    client: Client,
+    config: ClientConfig,
+    auth: Authenticator,
+    index: Arc<Mutex<Option<Result<ProfileIndex, String>>>>,
+}
+
+impl HTTPProfileIndex {
+    pub fn new(baseurl: Url) -> Self {
+        Self::with_config(baseurl, ClientConfig::default()).unwrap()
+    }
+
+    pub fn with_config(baseurl: Url, config: ClientConfig) -> Result<Self, String> {
+        let baseurl = ensure_directory(&baseurl);
+        let client = client_builder(&config)?
+            .build()
+            .map_err(|e| e.to_string())?;
+        Ok(Self {
+            auth: Authenticator::new(&baseurl, config.auth.clone()),
+            baseurl,
+            client,
+            config,
+            index: Arc::new(Mutex::new(None)),
+        })
+    }
+
+    fn profile_url(&self, profile: &ProfileIndexEntry) -> Result<Url, String> {
+        self.baseurl
+            .join(&profile.path)
+            .map_err(|e| format!("invalid path {:?} for {}: {e}", profile.path, profile.name))
+    }
+}
+
+impl DeferredProfileIndex for HTTPProfileIndex {
+    fn fetch_description(&self) -> DataSourceDescription {
+        DataSourceDescription {
+            source_locator: vec![self.baseurl.to_string()],
+        }
+    }
+
+    fn fetch_index(&mut self) {
+        let url = match self.baseurl.join("index") {
+            Ok(url) => url,
+            Err(e) => {
+                *self.index.lock().unwrap() = Some(Err(e.to_string()));
+                return;
+            }
+        };
+        info!("fetch: {}", url);
+        let request = self
+            .client
+            .get(url)
+            .header("Accept", "*/*")
+            .header(VERSION_HEADER, PROTOCOL_VERSION.to_string());
+        let index = self.index.clone();
+        self.auth.fetch(
+            request,
+            CancelFlag::default(),
+            self.config.retry,
+            move |response: Result<DataSourceResponse, String>| {
+                let result = response.and_then(|r| decode(&r));
+                *index.lock().unwrap() = Some(result);
+            },
+        );
+    }
+
+    fn get_index(&mut self) -> Option<Result<ProfileIndex, String>> {
+        self.index.lock().unwrap().take()
+    }
+
+    fn open(&self, profile: &ProfileIndexEntry) -> Result<Box<dyn DeferredDataSource>, String> {
+        let url = self.profile_url(profile)?;
+        let data_source = HTTPClientDataSource::with_config(url, self.config.clone())?;
+        Ok(Box::new(data_source))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_client_config() {
+        let url = Url::parse("http://localhost/").unwrap();
+        let config = ClientConfig {
+            headers: vec![("X-API-Key".to_owned(), "secret".to_owned())],
+            user_agent: Some("legion-prof".to_owned()),
+            proxy: Some("http://localhost:3128".to_owned()),
+            no_proxy: Some("localhost,.internal".to_owned()),
+            ..Default::default()
+        };
+        assert!(HTTPClientDataSource::with_config(url.clone(), config).is_ok());
+
+        let config = ClientConfig {
+            proxy: Some("http://[::1".to_owned()),
+            ..Default::default()
+        };
+        let err = HTTPClientDataSource::with_config(url.clone(), config)
+            .err()
+            .unwrap();
+        assert!(err.contains("invalid proxy"));
+
+        let config = ClientConfig {
+            headers: vec![("Bad Name".to_owned(), "value".to_owned())],
+            ..Default::default()
+        };
+        let err = HTTPClientDataSource::with_config(url.clone(), config)
+            .err()
+            .unwrap();
+        assert!(err.contains("invalid header name"));
+
+        let config = ClientConfig {
+            max_in_flight: Some(0),
+            ..Default::default()
+        };
+        assert!(HTTPClientDataSource::with_config(url.clone(), config).is_err());
+
+        let config = ClientConfig {
+            connections: ConnectionConfig {
+                pool_size: Some(0),
+                idle_timeout: None,
+                keepalive: None,
+                http2_prior_knowledge: true,
+            },
+            ..Default::default()
+        };
+        assert!(HTTPClientDataSource::with_config(url, config).is_ok());
+    }
+
+    #[test]
+    fn test_transfer_stats() {
+        let url = Url::parse("http://localhost/").unwrap();
+        let ds = HTTPClientDataSource::new(url.clone());
+        let response = |body: &'static [u8], not_modified| {
+            Ok(DataSourceResponse {
+                body: bytes::Bytes::from_static(body),
+                etag: Some("\"1\"".to_owned()),
+                content_type: None,
+                not_modified,
+            })
+        };
+        let ok = |r: &DataSourceResponse| Ok(r.body.len());
+
+        let transfer = ds.transfer(url.clone(), Some(ds.cache.start(&url)));
+        assert_eq!(transfer.finish(response(b"abcd", false), ok), Ok(4));
+        // Served from the cache, so nothing new was downloaded
+        let transfer = ds.transfer(url.clone(), Some(ds.cache.start(&url)));
+        assert_eq!(transfer.finish(response(b"", true), ok), Ok(4));
+        let transfer = ds.transfer(url.clone(), None);
+        assert!(transfer.finish(Err("404".to_owned()), ok).is_err());
+        let transfer = ds.transfer(url.clone(), None);
+        assert!(transfer.finish(Err(CANCELLED.to_owned()), ok).is_err());
+
+        let stats = ds.transfer_stats().unwrap();
+        assert_eq!(stats.requests, 3);
+        assert_eq!(stats.errors, 1);
+        assert_eq!(stats.bytes, 4);
+    }
+
+    #[test]
+    fn test_batch_tiles() {
+        let decode = |body: Vec<u8>| {
+            let response = DataSourceResponse {
+                body: Bytes::from(body),
+                etag: None,
+                content_type: None,
+                not_modified: false,
+            };
+            BatchTiles::<String>::decode(&response).unwrap()
+        };
+        let tiles = vec!["a".to_owned(), "b".repeat(300)];
+
+        let mut cbor = Vec::new();
+        ciborium::into_writer(&tiles, &mut cbor).unwrap();
+        let batch = decode(cbor.clone());
+        assert_eq!(batch.len(), Some(2));
+        assert_eq!(batch.collect::<Result<Vec<_>, _>>(), Ok(tiles.clone()));
+
+        // Indefinite length, which ends at a break
+        let mut indefinite = vec![0x9f];
+        indefinite.extend_from_slice(&cbor[1..]);
+        indefinite.push(0xff);
+        let batch = decode(indefinite);
+        assert_eq!(batch.len(), None);
+        assert_eq!(batch.collect::<Result<Vec<_>, _>>(), Ok(tiles.clone()));
+
+        let batch = decode(serde_json::to_vec(&tiles).unwrap());
+        assert_eq!(batch.len(), Some(2));
+        assert_eq!(batch.collect::<Result<Vec<_>, _>>(), Ok(tiles.clone()));
+
+        // Once a tile can't be decoded, neither can any after it
+        cbor[0] = 0x83;
+        let mut batch = decode(cbor);
+        assert_eq!(batch.nth(1), Some(Ok(tiles[1].clone())));
+        assert!(batch.next().unwrap().is_err());
+        assert!(batch.next().unwrap().is_err());
+
+        let response = DataSourceResponse {
+            body: Bytes::from_static(&[0xa0]),
+            etag: None,
+            content_type: None,
+            not_modified: false,
+        };
+        assert!(BatchTiles::<String>::decode(&response).is_err());
+    }
+
+    // Records the request line of each connection and answers 404
+    #[cfg(not(target_arch = "wasm32"))]
+    fn serve_not_found() -> (Url, std::sync::mpsc::Receiver<String>) {
+        use std::io::{Read, Write};
+        use std::net::TcpListener;
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (sender, receiver) = std::sync::mpsc::channel();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut request = [0; 4096];
+                let n = stream.read(&mut request).unwrap();
+                let request = String::from_utf8_lossy(&request[..n]);
+                let line = request.lines().next().unwrap_or_default().to_owned();
+                let response =
+                    "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n";
+                stream.write_all(response.as_bytes()).unwrap();
+                sender.send(line).unwrap();
+            }
+        });
+        (Url::parse(&format!("http://{addr}/")).unwrap(), receiver)
+    }
+
+    // Serves the files in a directory, like a static web server would
+    #[cfg(not(target_arch = "wasm32"))]
+    fn serve_dir(dir: PathBuf) -> Url {
+        use std::io::{Read, Write};
+        use std::net::TcpListener;
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut request = [0; 4096];
+                let n = stream.read(&mut request).unwrap();
+                let request = String::from_utf8_lossy(&request[..n]);
+                let target = request.split(' ').nth(1).unwrap_or_default();
+                let path = target.split('?').next().unwrap();
+                let response: Vec<u8> = match std::fs::read(dir.join(path.trim_start_matches('/')))
+                {
+                    Ok(body) => format!(
+                        "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                        body.len()
+                    )
+                    .into_bytes()
+                    .into_iter()
+                    .chain(body)
+                    .collect(),
+                    Err(_) => {
+                        b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+                            .to_vec()
+                    }
+                };
+                stream.write_all(&response).unwrap();
+            }
+        });
+        Url::parse(&format!("http://{addr}/")).unwrap()
+    }
+
+    // Requests two slot tiles and waits for both responses
+    #[cfg(not(target_arch = "wasm32"))]
+    fn fetch_slot_tiles(ds: &mut HTTPClientDataSource) -> Vec<SlotTileResponse> {
+        let requests: Vec<_> = (0..2)
+            .map(|i| TileRequest {
+                entry_id: EntryID::root().child(i),
+                tile_id: TileID(crate::timestamp::Interval::new(
+                    crate::timestamp::Timestamp(0),
+                    crate::timestamp::Timestamp(10),
+                )),
+                full: false,
+            })
+            .collect();
+        ds.fetch_tiles(TileKind::Slot, &requests, RequestPriority::Visible);
+
+        let mut responses = Vec::new();
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while responses.len() < requests.len() && Instant::now() < deadline {
+            responses.extend(ds.get_slot_tiles());
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(responses.len(), requests.len());
+        responses
+    }
+
+    #[test]
+    #[cfg(not(target_arch = "wasm32"))]
+    fn test_fetch_tiles_without_batches() {
+        // The server hasn't advertised batches (its info was never fetched),
+        // so each tile is requested on its own
+        let (url, lines) = serve_not_found();
+        let mut ds = HTTPClientDataSource::new(url);
+        let responses = fetch_slot_tiles(&mut ds);
+        assert!(responses.iter().all(|(result, _)| result.is_err()));
+        for _ in 0..2 {
+            let line = lines.recv().unwrap();
+            assert!(line.starts_with("GET /slot_tile/"), "{line}");
+        }
+    }
+
+    #[test]
+    #[cfg(not(target_arch = "wasm32"))]
+    fn test_fetch_tiles_batch_fallback() {
+        // Batches were advertised, but the endpoint isn't there
+        let (url, lines) = serve_not_found();
+        let mut ds = HTTPClientDataSource::new(url);
+        ds.batch_fetch.store(true, Ordering::Relaxed);
+        fetch_slot_tiles(&mut ds);
+        let line = lines.recv().unwrap();
+        assert!(line.starts_with("POST /tiles"), "{line}");
+        assert!(!ds.batch_fetch.load(Ordering::Relaxed));
+
+        fetch_slot_tiles(&mut ds);
+        for _ in 0..2 {
+            let line = lines.recv().unwrap();
+            assert!(line.starts_with("GET /slot_tile/"), "{line}");
+        }
+    }
+
+    #[test]
+    #[cfg(not(target_arch = "wasm32"))]
+    fn test_items_meta_from_archive() {
+        use crate::archive_data::DataSourceArchiveWriter;
+        use crate::data::DataSource;
+        use crate::deferred_data::DeferredDataSourceWrapper;
+        use crate::file_data::FileDataSource;
+        use crate::timestamp::Timestamp;
+        use crate::trace_data::{TraceBuilder, TraceItem};
+
+        let dir = std::env::temp_dir().join(format!("lpv_client_test_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut builder = TraceBuilder::new("test");
+        let thread = builder.thread("node", "cpu", "cpu0");
+        // The middle item spans all the tiles at the finest level
+        for (start, stop) in [(0, 10), (5, 95), (90, 100)] {
+            builder.add_item(
+                thread,
+                TraceItem {
+                    interval: Interval::new(Timestamp(start), Timestamp(stop)),
+                    title: format!("item{start}"),
+                    color: None,
+                    fields: Vec::new(),
+                },
+            );
+        }
+        let ds = DeferredDataSourceWrapper::new(builder.build());
+        DataSourceArchiveWriter::new(ds, 3, 2, dir.join("archive"), false, 1)
+            .write()
+            .unwrap();
+
+        let archive = FileDataSource::new(dir.join("archive"));
+        let info = archive.fetch_info();
+        let entry_id = EntryID::root().child(0).child(0).child(0);
+        let tile_id = info.tile_set.tiles[0][0];
+        let requests: Vec<_> = archive
+            .fetch_slot_tile(&entry_id, tile_id, false)
+            .data
+            .items
+            .into_iter()
+            .flatten()
+            .filter(|item| item.interval.start.0 > 0)
+            .map(|item| ItemMetaRequest {
+                entry_id: entry_id.clone(),
+                item_uid: item.item_uid,
+                interval: item.interval,
+            })
+            .collect();
+        assert_eq!(requests.len(), 2);
+
+        let mut ds = HTTPClientDataSource::new(serve_dir(dir.join("archive")));
+        ds.fetch_info();
+        let deadline = Instant::now() + Duration::from_secs(10);
+        let mut infos = Vec::new();
+        while infos.is_empty() && Instant::now() < deadline {
+            infos.extend(ds.get_infos());
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        assert!(infos.pop().unwrap().is_ok());
+        assert!(!ds.items_meta_endpoint.load(Ordering::Relaxed));
+
+        ds.fetch_items_meta(&requests);
+        let mut responses = Vec::new();
+        while responses.is_empty() && Instant::now() < deadline {
+            responses.extend(ds.get_items_meta());
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(responses.len(), 1);
+        let (items, reqs) = responses.pop().unwrap();
+        assert_eq!(reqs.len(), requests.len());
+        let mut titles: Vec<_> = items.unwrap().into_iter().map(|item| item.title).collect();
+        titles.sort();
+        assert_eq!(titles, ["item5", "item90"]);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_profile_index() {
+        let url = Url::parse("http://localhost/runs").unwrap();
+        let index = HTTPProfileIndex::new(url);
+        let profile = |path: &str| ProfileIndexEntry {
+            name: "run".to_owned(),
+            description: String::new(),
+            path: path.to_owned(),
+        };
+        let url = index.profile_url(&profile("run1/")).unwrap();
+        assert_eq!(url.as_str(), "http://localhost/runs/run1/");
+        let url = index
+            .profile_url(&profile("https://example.net/run2"))
+            .unwrap();
+        assert_eq!(url.as_str(), "https://example.net/run2");
+
+        let json = br#"{"profiles": [{"name": "run1", "path": "run1/"}]}"#;
+        let response = DataSourceResponse {
+            body: json.to_vec().into(),
+            etag: None,
+            content_type: None,
+            not_modified: false,
+        };
+        let decoded: ProfileIndex = decode(&response).unwrap();
+        assert_eq!(decoded.profiles.len(), 1);
+        assert_eq!(decoded.profiles[0].name, "run1");
+        assert!(decoded.profiles[0].description.is_empty());
+    }
+
+    #[test]
+    fn test_decode_json() {
+        let tile_id = TileID(crate::timestamp::Interval::new(
+            crate::timestamp::Timestamp(1),
+            crate::timestamp::Timestamp(2),
+        ));
+        let json = serde_json::to_vec(&tile_id).unwrap();
+        let response = |body: Vec<u8>, content_type: Option<&str>| DataSourceResponse {
+            body: body.into(),
+            etag: None,
+            content_type: content_type.map(str::to_owned),
+            not_modified: false,
+        };
+        let decoded: TileID = decode(&response(json.clone(), Some("application/json"))).unwrap();
+        assert_eq!(decoded, tile_id);
+        // Compressed, and without a Content-Type
+        let framed = zstd::encode_all(&json[..], 1).unwrap();
+        let decoded: TileID = decode(&response(framed, None)).unwrap();
+        assert_eq!(decoded, tile_id);
+    }
 }
@@ -1,21 +1,223 @@
+use std::time::Duration;
+
 use bytes::Bytes;
 
+use log::warn;
+
+use reqwest::StatusCode;
+
+use crate::deferred_data::CancelFlag;
+
 #[cfg(target_arch = "wasm32")]
 use reqwest::RequestBuilder;
 #[cfg(not(target_arch = "wasm32"))]
 use reqwest::blocking::RequestBuilder;
 
+// Error for a 401, which is retried if there are new credentials to try
+pub const UNAUTHORIZED: &str = "401 Unauthorized";
+
+// Prefix of the error for a 403, which is also retried if there are new
+// credentials, since signed URLs answer with it once they expire
+pub const FORBIDDEN: &str = "403 Forbidden";
+
+#[derive(Debug)]
 pub struct DataSourceResponse {
     pub body: Bytes,
+    pub etag: Option<String>,
+    pub content_type: Option<String>,
+    // The server answered 304 Not Modified, so there is no body
+    pub not_modified: bool,
+}
+
+pub struct FetchError {
+    pub message: String,
+    // Whether the request might succeed if sent again (e.g., it timed out,
+    // or the connection was reset)
+    pub transient: bool,
+}
+
+impl FetchError {
+    pub fn permanent(message: impl Into<String>) -> Self {
+        Self {
+            message: message.into(),
+            transient: false,
+        }
+    }
+
+    pub fn transient(message: impl Into<String>) -> Self {
+        Self {
+            message: message.into(),
+            transient: true,
+        }
+    }
+}
+
+// Gateways answer with these when the server behind them is restarting or
+// overloaded, which usually passes
+pub fn is_transient_status(status: StatusCode) -> bool {
+    matches!(
+        status,
+        StatusCode::BAD_GATEWAY | StatusCode::SERVICE_UNAVAILABLE | StatusCode::GATEWAY_TIMEOUT
+    )
+}
+
+// How often to retry requests that fail with a transient error. The delay
+// doubles after each attempt.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryConfig {
+    pub retries: u32,
+    pub backoff: Duration,
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        Self {
+            retries: 3,
+            backoff: Duration::from_millis(250),
+        }
+    }
 }
 
 pub fn fetch(
     request: RequestBuilder,
+    cancel: CancelFlag,
+    retry: RetryConfig,
+    on_done: impl 'static + Send + FnOnce(Result<DataSourceResponse, String>),
+) {
+    attempt(request, cancel, retry, 0, on_done)
+}
+
+fn attempt(
+    request: RequestBuilder,
+    cancel: CancelFlag,
+    retry: RetryConfig,
+    attempts: u32,
     on_done: impl 'static + Send + FnOnce(Result<DataSourceResponse, String>),
 ) {
+    // Requests with a streaming body can't be sent twice
+    let again = (attempts < retry.retries)
+        .then(|| request.try_clone())
+        .flatten();
+    let delay = if attempts == 0 {
+        Duration::ZERO
+    } else {
+        retry.backoff * (1 << (attempts - 1).min(16))
+    };
+    let flag = cancel.clone();
+    let done = move |response: Result<DataSourceResponse, FetchError>| match (response, again) {
+        (Err(e), Some(again)) if e.transient => {
+            warn!("retrying request after error: {}", e.message);
+            attempt(again, cancel, retry, attempts + 1, on_done);
+        }
+        (response, _) => on_done(response.map_err(|e| e.message)),
+    };
+
+    #[cfg(not(target_arch = "wasm32"))]
+    crate::http::fetch_native::fetch(request, flag, delay, Box::new(done));
+
+    #[cfg(target_arch = "wasm32")]
+    crate::http::fetch_web::fetch(request, flag, delay, Box::new(done));
+}
+
+// Calls f on each item in turn, where producing an item may be expensive
+// (e.g., decoding a tile). On the web, where there are no threads to do that
+// on, the browser gets to draw a frame after each item.
+pub fn for_each_yielding<I>(items: I, f: impl 'static + FnMut(I::Item))
+where
+    I: 'static + Iterator,
+{
     #[cfg(not(target_arch = "wasm32"))]
-    crate::http::fetch_native::fetch(request, Box::new(on_done));
+    items.for_each(f);
 
     #[cfg(target_arch = "wasm32")]
-    crate::http::fetch_web::fetch(request, Box::new(on_done));
+    crate::http::fetch_web::spawn_future(async move {
+        let mut f = f;
+        for item in items {
+            f(item);
+            crate::http::fetch_web::yield_now().await;
+        }
+    });
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use super::*;
+
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::sync::mpsc;
+
+    // Answers each connection with the next of the given statuses
+    fn serve(statuses: &'static [&'static str]) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        std::thread::spawn(move || {
+            for (stream, status) in listener.incoming().zip(statuses) {
+                let mut stream = stream.unwrap();
+                let mut request = [0; 4096];
+                let _ = stream.read(&mut request).unwrap();
+                let response = format!(
+                    "HTTP/1.1 {status}\r\nContent-Length: 2\r\nConnection: close\r\n\r\nok"
+                );
+                stream.write_all(response.as_bytes()).unwrap();
+            }
+        });
+        format!("http://{addr}/")
+    }
+
+    fn get(url: &str, retries: u32) -> Result<DataSourceResponse, String> {
+        let client = reqwest::blocking::Client::builder()
+            .timeout(Duration::from_millis(200))
+            .build()
+            .unwrap();
+        let request = client.get(url);
+        let retry = RetryConfig {
+            retries,
+            backoff: Duration::from_millis(1),
+        };
+        let (sender, receiver) = mpsc::channel();
+        fetch(request, CancelFlag::default(), retry, move |response| {
+            sender.send(response).unwrap();
+        });
+        receiver.recv().unwrap()
+    }
+
+    #[test]
+    fn test_retry() {
+        let url = serve(&["503 Service Unavailable", "502 Bad Gateway", "200 OK"]);
+        assert_eq!(&get(&url, 2).unwrap().body[..], b"ok");
+
+        // Out of retries
+        let url = serve(&["503 Service Unavailable", "200 OK"]);
+        assert!(get(&url, 0).unwrap_err().starts_with("503"));
+
+        // Not worth retrying
+        let url = serve(&["404 Not Found", "200 OK"]);
+        assert!(get(&url, 2).unwrap_err().starts_with("404"));
+    }
+
+    #[test]
+    fn test_timeout() {
+        // Sends headers, but the body never arrives
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (sender, receiver) = mpsc::channel();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut request = [0; 4096];
+                let _ = stream.read(&mut request).unwrap();
+                let response = "HTTP/1.1 200 OK\r\nContent-Length: 10\r\n\r\nok";
+                stream.write_all(response.as_bytes()).unwrap();
+                sender.send(()).unwrap();
+                // Hold the connection open
+                std::mem::forget(stream);
+            }
+        });
+
+        let err = get(&format!("http://{addr}/"), 1).unwrap_err();
+        assert_eq!(err, "timed out waiting for the server");
+        // Retried once
+        assert_eq!(receiver.try_iter().count(), 2);
+    }
 }
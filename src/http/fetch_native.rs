@@ -1,18 +1,129 @@
+use std::io::{ErrorKind, Read};
+use std::time::Duration;
+
+use reqwest::StatusCode;
 use reqwest::blocking::RequestBuilder;
+use reqwest::header::{CONTENT_TYPE, ETAG};
+
+use crate::deferred_data::{CANCELLED, CancelFlag};
+use crate::http::fetch::{DataSourceResponse, FetchError, UNAUTHORIZED, is_transient_status};
+use crate::timer::run_after;
+
+// Everything but a malformed request (or a redirect loop) is worth retrying,
+// since the server may simply have been unreachable for a moment
+fn send_error(e: reqwest::Error) -> FetchError {
+    if e.is_builder() || e.is_redirect() {
+        FetchError::permanent(e.to_string())
+    } else {
+        FetchError::transient(e.to_string())
+    }
+}
 
-use crate::http::fetch::DataSourceResponse;
+fn read_error(e: std::io::Error) -> FetchError {
+    // The client's read timeout comes back wrapped in an I/O error
+    let timed_out = e
+        .get_ref()
+        .and_then(|inner| inner.downcast_ref::<reqwest::Error>())
+        .is_some_and(reqwest::Error::is_timeout);
+    if timed_out {
+        return FetchError::transient("timed out waiting for the server");
+    }
+    match e.kind() {
+        ErrorKind::ConnectionReset
+        | ErrorKind::ConnectionAborted
+        | ErrorKind::TimedOut
+        | ErrorKind::UnexpectedEof => FetchError::transient(e.to_string()),
+        _ => FetchError::permanent(e.to_string()),
+    }
+}
+
+// Runs the job on the thread pool once the delay has passed, without holding
+// a pool thread in the meantime
+fn spawn_after(delay: Duration, job: Box<dyn FnOnce() + Send>) {
+    if delay.is_zero() {
+        rayon::spawn(job);
+    } else {
+        run_after(delay, Box::new(move || rayon::spawn(job)));
+    }
+}
 
 pub fn fetch(
     request: RequestBuilder,
-    on_done: Box<dyn FnOnce(Result<DataSourceResponse, String>) + Send>,
+    cancel: CancelFlag,
+    delay: Duration,
+    on_done: Box<dyn FnOnce(Result<DataSourceResponse, FetchError>) + Send>,
 ) {
-    rayon::spawn(move || {
-        let result = request
-            .send()
-            .expect("request failed")
-            .bytes()
-            .expect("unable to get bytes");
-
-        on_done(Ok(DataSourceResponse { body: result }))
-    });
+    // Backing off before a retry
+    spawn_after(
+        delay,
+        Box::new(move || {
+            // Requests can wait in the queue for a while, so check before sending
+            if cancel.is_cancelled() {
+                on_done(Err(FetchError::permanent(CANCELLED)));
+                return;
+            }
+
+            let mut response = match request.send() {
+                Ok(response) => response,
+                Err(e) => {
+                    on_done(Err(send_error(e)));
+                    return;
+                }
+            };
+
+            // Read the body in chunks so that a cancelled download can be
+            // abandoned part way through (dropping the response closes the
+            // connection)
+            let mut body = Vec::new();
+            let mut buffer = vec![0; 1 << 16];
+            loop {
+                if cancel.is_cancelled() {
+                    on_done(Err(FetchError::permanent(CANCELLED)));
+                    return;
+                }
+                let n = match response.read(&mut buffer) {
+                    Ok(n) => n,
+                    Err(e) => {
+                        on_done(Err(read_error(e)));
+                        return;
+                    }
+                };
+                if n == 0 {
+                    break;
+                }
+                body.extend_from_slice(&buffer[..n]);
+            }
+
+            // Errors come back as text (e.g., an incompatible protocol version),
+            // which is far more useful than failing to decode it
+            let status = response.status();
+            if status == StatusCode::UNAUTHORIZED {
+                on_done(Err(FetchError::permanent(UNAUTHORIZED)));
+                return;
+            }
+            let not_modified = status == StatusCode::NOT_MODIFIED;
+            if !status.is_success() && !not_modified {
+                let message = format!("{status}: {}", String::from_utf8_lossy(&body));
+                on_done(Err(FetchError {
+                    message,
+                    transient: is_transient_status(status),
+                }));
+                return;
+            }
+
+            let header = |name| {
+                response
+                    .headers()
+                    .get(name)
+                    .and_then(|value| value.to_str().ok())
+                    .map(str::to_owned)
+            };
+            on_done(Ok(DataSourceResponse {
+                body: body.into(),
+                etag: header(ETAG),
+                content_type: header(CONTENT_TYPE),
+                not_modified,
+            }))
+        }),
+    );
 }
@@ -1,6 +1,12 @@
-use reqwest::RequestBuilder;
+use std::time::Duration;
 
-use crate::http::fetch::DataSourceResponse;
+use reqwest::header::{CONTENT_TYPE, ETAG};
+use reqwest::{RequestBuilder, StatusCode};
+
+use wasm_bindgen::{JsCast, JsValue};
+
+use crate::deferred_data::{CANCELLED, CancelFlag};
+use crate::http::fetch::{DataSourceResponse, FetchError, UNAUTHORIZED, is_transient_status};
 
 /// Spawn an async task.
 ///
@@ -13,21 +19,111 @@ where
     wasm_bindgen_futures::spawn_local(future);
 }
 
+// Looks up setTimeout on the global scope rather than the window, since
+// there is no window in a web worker
+async fn sleep(delay: Duration) -> Result<(), FetchError> {
+    let global = js_sys::global();
+    let set_timeout = js_sys::Reflect::get(&global, &JsValue::from_str("setTimeout"))
+        .ok()
+        .and_then(|f| f.dyn_into::<js_sys::Function>().ok())
+        .ok_or_else(|| FetchError::transient("unable to set timeout: no setTimeout"))?;
+    // Long backoffs would otherwise wrap around to negative (i.e., no) delays
+    let millis = delay.as_millis().min(i32::MAX as u128) as i32;
+    let mut result = Ok(JsValue::UNDEFINED);
+    let promise = js_sys::Promise::new(&mut |resolve, _| {
+        result = set_timeout.call2(&global, &resolve, &JsValue::from(millis));
+    });
+    result.map_err(|e| FetchError::transient(format!("unable to set timeout: {e:?}")))?;
+    let _ = wasm_bindgen_futures::JsFuture::from(promise).await;
+    Ok(())
+}
+
+// Lets the browser draw a frame (or run other tasks) before continuing. If
+// that isn't possible, it just continues.
+pub async fn yield_now() {
+    let _ = sleep(Duration::ZERO).await;
+}
+
 pub fn fetch(
     request: RequestBuilder,
-    on_done: Box<dyn FnOnce(Result<DataSourceResponse, String>) + Send>,
+    cancel: CancelFlag,
+    delay: Duration,
+    on_done: Box<dyn FnOnce(Result<DataSourceResponse, FetchError>) + Send>,
 ) {
     spawn_future(async move {
-        let result = request
-            .send()
-            .await
-            .expect("request failed")
-            .bytes()
-            .await
-            .expect("unable to get bytes");
+        // Backing off before a retry
+        if !delay.is_zero() {
+            if let Err(e) = sleep(delay).await {
+                on_done(Err(e));
+                return;
+            }
+        }
+
+        if cancel.is_cancelled() {
+            on_done(Err(FetchError::permanent(CANCELLED)));
+            return;
+        }
+
+        let result = match request.send().await {
+            Ok(response) => {
+                let status = response.status();
+                let header = |name| {
+                    response
+                        .headers()
+                        .get(name)
+                        .and_then(|value| value.to_str().ok())
+                        .map(str::to_owned)
+                };
+                let headers = (header(ETAG), header(CONTENT_TYPE));
+                response.bytes().await.map(|body| (status, headers, body))
+            }
+            Err(e) => Err(e),
+        };
+        let result = match result {
+            Ok((StatusCode::UNAUTHORIZED, _, _)) => {
+                on_done(Err(FetchError::permanent(UNAUTHORIZED)));
+                return;
+            }
+            Ok((status, _, body)) if !status.is_success() && status != StatusCode::NOT_MODIFIED => {
+                // Errors come back as text (e.g., an incompatible protocol
+                // version), which is far more useful than failing to decode it
+                let message = format!("{status}: {}", String::from_utf8_lossy(&body));
+                on_done(Err(FetchError {
+                    message,
+                    transient: is_transient_status(status),
+                }));
+                return;
+            }
+            Ok((status, (etag, content_type), body)) => DataSourceResponse {
+                body,
+                etag,
+                content_type,
+                not_modified: status == StatusCode::NOT_MODIFIED,
+            },
+            Err(e) if e.is_builder() => {
+                on_done(Err(FetchError::permanent(e.to_string())));
+                return;
+            }
+            // The browser doesn't say why a request failed, so assume the
+            // network rather than the request itself
+            Err(e) => {
+                on_done(Err(FetchError::transient(e.to_string())));
+                return;
+            }
+        };
+
+        // There are no threads to decode on here, and large tiles can take a
+        // while. Yield to the browser first, so that each response is decoded
+        // in a task of its own and frames can be drawn in between.
+        yield_now().await;
 
-        let res = Ok(DataSourceResponse { body: result });
+        // Too late to save the download, but the caller can at least skip
+        // decoding it
+        if cancel.is_cancelled() {
+            on_done(Err(FetchError::permanent(CANCELLED)));
+            return;
+        }
 
-        on_done(res)
+        on_done(Ok(result))
     });
 }
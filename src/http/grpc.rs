@@ -0,0 +1,1126 @@
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use egui::Color32;
+
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+
+use tokio::runtime::Runtime;
+use tokio::sync::oneshot;
+
+use tonic::metadata::MetadataMap;
+use tonic::transport::{Certificate, ClientTlsConfig, Endpoint, Identity};
+use tonic::{Code, Status};
+
+use url::Url;
+
+use crate::data::{
+    Capabilities, DataSourceDescription, DataSourceInfo, EntryID, EntryInfo, Field, FieldID,
+    FieldSchema, Item, ItemLink, ItemMeta, ItemMetaRequest, ItemUID, PROTOCOL_VERSION,
+    SlotMetaTile, SlotMetaTileData, SlotTile, SlotTileData, SummaryTile, SummaryTileData,
+    SummaryUnits, TileID, TileSet, UtilPoint,
+};
+use crate::deferred_data::{
+    CANCELLED, DataSourceInfoResult, DeferredDataSource, ItemsMetaResponse, RequestPriority,
+    SlotMetaTileResponse, SlotTileResponse, SummaryTileResponse, TileRequest,
+};
+use crate::http::auth::Authenticator;
+use crate::http::client::ClientConfig;
+use crate::http::schema::{TileIdentity, VERSION_HEADER, check_tile, check_version};
+use crate::timestamp::{Interval, Timestamp};
+
+// Generated from proto/legion_prof.proto, which describes the service. Public
+// so that servers written in Rust can implement it with the same types.
+pub mod proto {
+    tonic::include_proto!("legion_prof");
+}
+
+use proto::profile_service_client::ProfileServiceClient;
+
+type ResponseContainer<T, E> = Arc<Mutex<Vec<(Result<T, String>, E)>>>;
+
+// Conversions between the messages of the service and the types they mirror.
+// Encoding can't fail, but a message from the server may be missing fields
+// that proto3 can't require.
+
+fn required<T>(value: Option<T>, name: &str) -> Result<T, String> {
+    value.ok_or_else(|| format!("protocol error: the server sent no {name}"))
+}
+
+// Premultiplied RGBA, red in the most significant byte
+fn encode_color(color: Color32) -> u32 {
+    u32::from_be_bytes(color.to_array())
+}
+
+fn decode_color(color: u32) -> Color32 {
+    let [r, g, b, a] = color.to_be_bytes();
+    Color32::from_rgba_premultiplied(r, g, b, a)
+}
+
+impl From<&EntryID> for proto::EntryId {
+    fn from(entry_id: &EntryID) -> Self {
+        Self {
+            index: entry_id.indices().to_vec(),
+        }
+    }
+}
+
+impl From<proto::EntryId> for EntryID {
+    fn from(entry_id: proto::EntryId) -> Self {
+        EntryID::from_indices(entry_id.index)
+    }
+}
+
+fn decode_entry_id(entry_id: Option<proto::EntryId>) -> Result<EntryID, String> {
+    required(entry_id, "entry ID").map(EntryID::from)
+}
+
+impl From<Interval> for proto::Interval {
+    fn from(interval: Interval) -> Self {
+        Self {
+            start: interval.start.0,
+            stop: interval.stop.0,
+        }
+    }
+}
+
+fn decode_interval(interval: Option<proto::Interval>) -> Result<Interval, String> {
+    let interval = required(interval, "interval")?;
+    Ok(Interval::new(
+        Timestamp(interval.start),
+        Timestamp(interval.stop),
+    ))
+}
+
+impl From<&TileRequest> for proto::TileRequest {
+    fn from(req: &TileRequest) -> Self {
+        Self {
+            entry_id: Some((&req.entry_id).into()),
+            tile_id: Some(req.tile_id.0.into()),
+            full: req.full,
+        }
+    }
+}
+
+impl TryFrom<proto::TileRequest> for TileRequest {
+    type Error = String;
+
+    fn try_from(req: proto::TileRequest) -> Result<Self, String> {
+        Ok(Self {
+            entry_id: decode_entry_id(req.entry_id)?,
+            tile_id: TileID(decode_interval(req.tile_id)?),
+            full: req.full,
+        })
+    }
+}
+
+impl From<&ItemMetaRequest> for proto::ItemMetaRequest {
+    fn from(req: &ItemMetaRequest) -> Self {
+        Self {
+            entry_id: Some((&req.entry_id).into()),
+            item_uid: req.item_uid.0,
+            interval: Some(req.interval.into()),
+        }
+    }
+}
+
+impl TryFrom<proto::ItemMetaRequest> for ItemMetaRequest {
+    type Error = String;
+
+    fn try_from(req: proto::ItemMetaRequest) -> Result<Self, String> {
+        Ok(Self {
+            entry_id: decode_entry_id(req.entry_id)?,
+            item_uid: ItemUID(req.item_uid),
+            interval: decode_interval(req.interval)?,
+        })
+    }
+}
+
+impl From<&EntryInfo> for proto::EntryInfo {
+    fn from(info: &EntryInfo) -> Self {
+        use proto::entry_info::Kind;
+        let kind = match info {
+            EntryInfo::Panel {
+                short_name,
+                long_name,
+                summary,
+                slots,
+            } => Kind::Panel(Box::new(proto::Panel {
+                short_name: short_name.clone(),
+                long_name: long_name.clone(),
+                summary: summary.as_deref().map(|summary| Box::new(summary.into())),
+                slots: slots.iter().map(Into::into).collect(),
+            })),
+            EntryInfo::Slot {
+                short_name,
+                long_name,
+                max_rows,
+            } => Kind::Slot(proto::Slot {
+                short_name: short_name.clone(),
+                long_name: long_name.clone(),
+                max_rows: *max_rows,
+            }),
+            EntryInfo::Summary { color, units } => Kind::Summary(proto::Summary {
+                color: encode_color(*color),
+                units: match units {
+                    SummaryUnits::Utilization => proto::SummaryUnits::Utilization,
+                    SummaryUnits::Watts => proto::SummaryUnits::Watts,
+                } as i32,
+            }),
+        };
+        Self { kind: Some(kind) }
+    }
+}
+
+impl TryFrom<proto::EntryInfo> for EntryInfo {
+    type Error = String;
+
+    fn try_from(info: proto::EntryInfo) -> Result<Self, String> {
+        use proto::entry_info::Kind;
+        Ok(match required(info.kind, "entry kind")? {
+            Kind::Panel(panel) => EntryInfo::Panel {
+                short_name: panel.short_name,
+                long_name: panel.long_name,
+                summary: match panel.summary {
+                    Some(summary) => Some(Box::new((*summary).try_into()?)),
+                    None => None,
+                },
+                slots: panel
+                    .slots
+                    .into_iter()
+                    .map(TryInto::try_into)
+                    .collect::<Result<_, _>>()?,
+            },
+            Kind::Slot(slot) => EntryInfo::Slot {
+                short_name: slot.short_name,
+                long_name: slot.long_name,
+                max_rows: slot.max_rows,
+            },
+            Kind::Summary(summary) => EntryInfo::Summary {
+                color: decode_color(summary.color),
+                units: match proto::SummaryUnits::try_from(summary.units) {
+                    Ok(proto::SummaryUnits::Utilization) => SummaryUnits::Utilization,
+                    Ok(proto::SummaryUnits::Watts) => SummaryUnits::Watts,
+                    Err(_) => return Err(format!("unknown summary units {}", summary.units)),
+                },
+            },
+        })
+    }
+}
+
+impl From<&TileSet> for proto::TileSet {
+    fn from(tile_set: &TileSet) -> Self {
+        Self {
+            levels: tile_set
+                .tiles
+                .iter()
+                .map(|level| proto::TileLevel {
+                    tiles: level.iter().map(|tile_id| tile_id.0.into()).collect(),
+                })
+                .collect(),
+        }
+    }
+}
+
+impl From<proto::TileSet> for TileSet {
+    fn from(tile_set: proto::TileSet) -> Self {
+        Self {
+            tiles: tile_set
+                .levels
+                .into_iter()
+                .map(|level| {
+                    level
+                        .tiles
+                        .into_iter()
+                        .map(|tile| {
+                            TileID(Interval::new(Timestamp(tile.start), Timestamp(tile.stop)))
+                        })
+                        .collect()
+                })
+                .collect(),
+        }
+    }
+}
+
+impl From<Capabilities> for proto::Capabilities {
+    fn from(capabilities: Capabilities) -> Self {
+        Self {
+            summary_tiles: capabilities.summary_tiles,
+            slot_meta_tiles: capabilities.slot_meta_tiles,
+            search: capabilities.search,
+            batch_fetch: capabilities.batch_fetch,
+            items_meta: capabilities.items_meta,
+            live_updates: capabilities.live_updates,
+        }
+    }
+}
+
+impl From<proto::Capabilities> for Capabilities {
+    fn from(capabilities: proto::Capabilities) -> Self {
+        Self {
+            summary_tiles: capabilities.summary_tiles,
+            slot_meta_tiles: capabilities.slot_meta_tiles,
+            search: capabilities.search,
+            batch_fetch: capabilities.batch_fetch,
+            items_meta: capabilities.items_meta,
+            live_updates: capabilities.live_updates,
+        }
+    }
+}
+
+impl From<&DataSourceInfo> for proto::DataSourceInfo {
+    fn from(info: &DataSourceInfo) -> Self {
+        let schema = &info.field_schema;
+        Self {
+            entry_info: Some((&info.entry_info).into()),
+            interval: Some(info.interval.into()),
+            tile_set: Some((&info.tile_set).into()),
+            field_schema: schema
+                .iter()
+                .map(|(field_id, name)| proto::FieldSchemaEntry {
+                    field_id: field_id.index() as u64,
+                    name: name.to_owned(),
+                    searchable: schema.searchable().contains(&field_id),
+                })
+                .collect(),
+            warning_message: info.warning_message.clone(),
+            refresh_interval_ns: info
+                .refresh_interval
+                .map(|interval| interval.as_nanos() as u64),
+            version: info.version,
+            capabilities: Some(info.capabilities.into()),
+            entry_tile_sets: info
+                .entry_tile_sets
+                .iter()
+                .map(|(entry_id, tile_set)| proto::EntryTileSet {
+                    entry_id: Some(entry_id.into()),
+                    tile_set: Some(tile_set.into()),
+                })
+                .collect(),
+        }
+    }
+}
+
+impl TryFrom<proto::DataSourceInfo> for DataSourceInfo {
+    type Error = String;
+
+    fn try_from(info: proto::DataSourceInfo) -> Result<Self, String> {
+        check_version("server", info.version)?;
+        let mut field_schema = FieldSchema::new();
+        for field in info.field_schema {
+            let field_id = FieldID::from_index(field.field_id as usize);
+            field_schema.insert_with_id(field_id, field.name, field.searchable);
+        }
+        Ok(Self {
+            entry_info: required(info.entry_info, "entry info")?.try_into()?,
+            interval: decode_interval(info.interval)?,
+            tile_set: required(info.tile_set, "tile set")?.into(),
+            field_schema,
+            warning_message: info.warning_message,
+            refresh_interval: info.refresh_interval_ns.map(Duration::from_nanos),
+            version: info.version,
+            capabilities: info.capabilities.map(Into::into).unwrap_or_default(),
+            entry_tile_sets: info
+                .entry_tile_sets
+                .into_iter()
+                .map(|entry| {
+                    let tile_set = required(entry.tile_set, "tile set")?;
+                    Ok((decode_entry_id(entry.entry_id)?, tile_set.into()))
+                })
+                .collect::<Result<_, String>>()?,
+        })
+    }
+}
+
+impl From<&SummaryTile> for proto::SummaryTile {
+    fn from(tile: &SummaryTile) -> Self {
+        Self {
+            entry_id: Some((&tile.entry_id).into()),
+            tile_id: Some(tile.tile_id.0.into()),
+            utilization: tile
+                .data
+                .utilization
+                .iter()
+                .map(|point| proto::UtilPoint {
+                    time: point.time.0,
+                    util: point.util,
+                })
+                .collect(),
+        }
+    }
+}
+
+impl TryFrom<proto::SummaryTile> for SummaryTile {
+    type Error = String;
+
+    fn try_from(tile: proto::SummaryTile) -> Result<Self, String> {
+        Ok(Self {
+            entry_id: decode_entry_id(tile.entry_id)?,
+            tile_id: TileID(decode_interval(tile.tile_id)?),
+            data: SummaryTileData {
+                utilization: tile
+                    .utilization
+                    .into_iter()
+                    .map(|point| UtilPoint {
+                        time: Timestamp(point.time),
+                        util: point.util,
+                    })
+                    .collect(),
+            },
+        })
+    }
+}
+
+impl From<&SlotTile> for proto::SlotTile {
+    fn from(tile: &SlotTile) -> Self {
+        Self {
+            entry_id: Some((&tile.entry_id).into()),
+            tile_id: Some(tile.tile_id.0.into()),
+            rows: tile
+                .data
+                .items
+                .iter()
+                .map(|row| proto::ItemRow {
+                    items: row
+                        .iter()
+                        .map(|item| proto::Item {
+                            item_uid: item.item_uid.0,
+                            interval: Some(item.interval.into()),
+                            color: encode_color(item.color),
+                        })
+                        .collect(),
+                })
+                .collect(),
+        }
+    }
+}
+
+impl TryFrom<proto::SlotTile> for SlotTile {
+    type Error = String;
+
+    fn try_from(tile: proto::SlotTile) -> Result<Self, String> {
+        let items = tile
+            .rows
+            .into_iter()
+            .map(|row| {
+                row.items
+                    .into_iter()
+                    .map(|item| {
+                        Ok(Item {
+                            item_uid: ItemUID(item.item_uid),
+                            interval: decode_interval(item.interval)?,
+                            color: decode_color(item.color),
+                        })
+                    })
+                    .collect()
+            })
+            .collect::<Result<_, String>>()?;
+        Ok(Self {
+            entry_id: decode_entry_id(tile.entry_id)?,
+            tile_id: TileID(decode_interval(tile.tile_id)?),
+            data: SlotTileData { items },
+        })
+    }
+}
+
+impl From<&Field> for proto::Field {
+    fn from(field: &Field) -> Self {
+        use proto::field::Value;
+        let value = match field {
+            Field::I64(value) => Value::I64(*value),
+            Field::U64(value) => Value::U64(*value),
+            Field::String(value) => Value::String(value.clone()),
+            Field::Interval(interval) => Value::Interval((*interval).into()),
+            Field::ItemLink(link) => Value::ItemLink(proto::ItemLink {
+                item_uid: link.item_uid.0,
+                title: link.title.clone(),
+                interval: Some(link.interval.into()),
+                entry_id: Some((&link.entry_id).into()),
+            }),
+            Field::Vec(fields) => Value::Vec(proto::FieldList {
+                fields: fields.iter().map(Into::into).collect(),
+            }),
+            Field::Empty => Value::Empty(proto::Empty {}),
+        };
+        Self { value: Some(value) }
+    }
+}
+
+impl TryFrom<proto::Field> for Field {
+    type Error = String;
+
+    fn try_from(field: proto::Field) -> Result<Self, String> {
+        use proto::field::Value;
+        Ok(match required(field.value, "field value")? {
+            Value::I64(value) => Field::I64(value),
+            Value::U64(value) => Field::U64(value),
+            Value::String(value) => Field::String(value),
+            Value::Interval(interval) => Field::Interval(decode_interval(Some(interval))?),
+            Value::ItemLink(link) => Field::ItemLink(ItemLink {
+                item_uid: ItemUID(link.item_uid),
+                title: link.title,
+                interval: decode_interval(link.interval)?,
+                entry_id: decode_entry_id(link.entry_id)?,
+            }),
+            Value::Vec(fields) => Field::Vec(
+                fields
+                    .fields
+                    .into_iter()
+                    .map(TryInto::try_into)
+                    .collect::<Result<_, _>>()?,
+            ),
+            Value::Empty(_) => Field::Empty,
+        })
+    }
+}
+
+impl From<&ItemMeta> for proto::ItemMeta {
+    fn from(item: &ItemMeta) -> Self {
+        Self {
+            item_uid: item.item_uid.0,
+            original_interval: Some(item.original_interval.into()),
+            title: item.title.clone(),
+            fields: item
+                .fields
+                .iter()
+                .map(|(field_id, field, color)| proto::ItemField {
+                    field_id: field_id.index() as u64,
+                    value: Some(field.into()),
+                    color: color.map(encode_color),
+                })
+                .collect(),
+        }
+    }
+}
+
+impl TryFrom<proto::ItemMeta> for ItemMeta {
+    type Error = String;
+
+    fn try_from(item: proto::ItemMeta) -> Result<Self, String> {
+        Ok(Self {
+            item_uid: ItemUID(item.item_uid),
+            original_interval: decode_interval(item.original_interval)?,
+            title: item.title,
+            fields: item
+                .fields
+                .into_iter()
+                .map(|field| {
+                    Ok((
+                        FieldID::from_index(field.field_id as usize),
+                        required(field.value, "field value")?.try_into()?,
+                        field.color.map(decode_color),
+                    ))
+                })
+                .collect::<Result<_, String>>()?,
+        })
+    }
+}
+
+fn decode_item_metas(items: Vec<proto::ItemMeta>) -> Result<Vec<ItemMeta>, String> {
+    items.into_iter().map(TryInto::try_into).collect()
+}
+
+impl From<&SlotMetaTile> for proto::SlotMetaTile {
+    fn from(tile: &SlotMetaTile) -> Self {
+        Self {
+            entry_id: Some((&tile.entry_id).into()),
+            tile_id: Some(tile.tile_id.0.into()),
+            rows: tile
+                .data
+                .items
+                .iter()
+                .map(|row| proto::ItemMetaRow {
+                    items: row.iter().map(Into::into).collect(),
+                })
+                .collect(),
+        }
+    }
+}
+
+impl TryFrom<proto::SlotMetaTile> for SlotMetaTile {
+    type Error = String;
+
+    fn try_from(tile: proto::SlotMetaTile) -> Result<Self, String> {
+        let items = tile
+            .rows
+            .into_iter()
+            .map(|row| decode_item_metas(row.items))
+            .collect::<Result<_, _>>()?;
+        Ok(Self {
+            entry_id: decode_entry_id(tile.entry_id)?,
+            tile_id: TileID(decode_interval(tile.tile_id)?),
+            data: SlotMetaTileData { items },
+        })
+    }
+}
+
+// Failed calls carry their status in the trailers (or, for calls that fail
+// before sending anything, in the headers), which tonic reads either way
+fn status_message(status: Status) -> String {
+    format!("gRPC status {:?}: {}", status.code(), status.message())
+}
+
+type Client = ProfileServiceClient<tonic::transport::Channel>;
+
+struct Channel {
+    client: Client,
+    auth: Authenticator,
+    // Sent with every call: the configured headers and the protocol version
+    headers: HeaderMap,
+}
+
+impl Channel {
+    fn request<T>(&self, message: T) -> Result<(tonic::Request<T>, u64), String> {
+        let (auth, generation) = self.auth.headers()?;
+        let mut headers = self.headers.clone();
+        headers.extend(auth);
+        let mut request = tonic::Request::new(message);
+        *request.metadata_mut() = MetadataMap::from_headers(headers);
+        Ok((request, generation))
+    }
+
+    // Makes the call, and makes it once more with new credentials if the
+    // server rejects the current ones (the same as HTTP sources)
+    async fn call<T, R, F>(
+        &self,
+        message: T,
+        method: impl Fn(Client, tonic::Request<T>) -> F,
+    ) -> Result<R, String>
+    where
+        T: Clone,
+        F: Future<Output = Result<tonic::Response<R>, Status>>,
+    {
+        let (request, generation) = self.request(message.clone())?;
+        let response = match method(self.client.clone(), request).await {
+            Err(status)
+                if matches!(
+                    status.code(),
+                    Code::Unauthenticated | Code::PermissionDenied
+                ) && self.auth.refresh(generation) =>
+            {
+                let (request, _) = self.request(message)?;
+                method(self.client.clone(), request).await
+            }
+            response => response,
+        };
+        response
+            .map(tonic::Response::into_inner)
+            .map_err(status_message)
+    }
+}
+
+fn tls_config(config: &ClientConfig) -> Result<ClientTlsConfig, String> {
+    let tls = &config.tls;
+    if tls.accept_invalid_certs {
+        return Err("gRPC sources always verify the server's certificate".to_owned());
+    }
+    let read = |path: &std::path::PathBuf| {
+        std::fs::read(path).map_err(|e| format!("unable to read {}: {e}", path.display()))
+    };
+    let mut tls_config = ClientTlsConfig::new().with_native_roots();
+    for path in &tls.ca_certificates {
+        tls_config = tls_config.ca_certificate(Certificate::from_pem(read(path)?));
+    }
+    if let Some(path) = &tls.client_certificate {
+        let certificate = read(path)?;
+        let key = match &tls.client_key {
+            Some(key) => read(key)?,
+            None => certificate.clone(),
+        };
+        tls_config = tls_config.identity(Identity::from_pem(certificate, key));
+    }
+    Ok(tls_config)
+}
+
+fn endpoint(baseurl: &Url, config: &ClientConfig) -> Result<Endpoint, String> {
+    // tonic has no proxy support, and a source that quietly went around
+    // the proxy it was given would be worse than one that refuses
+    if config.proxy.is_some() {
+        return Err("gRPC sources can't be reached through a proxy".to_owned());
+    }
+    let mut endpoint = Endpoint::from_shared(baseurl.to_string())
+        .map_err(|e| format!("invalid URL {baseurl}: {e}"))?
+        .tcp_nodelay(true)
+        .tcp_keepalive(config.connections.keepalive)
+        .http2_adaptive_window(true);
+    if let Some(timeout) = config.timeouts.connect {
+        endpoint = endpoint.connect_timeout(timeout);
+    }
+    // Calls return a single message, so the read timeout bounds the call
+    if let Some(timeout) = config.timeouts.read {
+        endpoint = endpoint.timeout(timeout);
+    }
+    if let Some(user_agent) = &config.user_agent {
+        endpoint = endpoint
+            .user_agent(user_agent.as_str())
+            .map_err(|e| format!("invalid user agent: {e}"))?;
+    }
+    if baseurl.scheme() == "https" {
+        endpoint = endpoint
+            .tls_config(tls_config(config)?)
+            .map_err(|e| format!("invalid TLS configuration: {e}"))?;
+    }
+    Ok(endpoint)
+}
+
+fn headers(config: &ClientConfig) -> Result<HeaderMap, String> {
+    let version = (VERSION_HEADER.to_owned(), PROTOCOL_VERSION.to_string());
+    let mut headers = HeaderMap::new();
+    // Parsed rather than taken as is, since metadata keys must be lowercase
+    for (name, value) in config.headers.iter().chain([&version]) {
+        let name = HeaderName::from_bytes(name.as_bytes())
+            .map_err(|e| format!("invalid header name {name:?}: {e}"))?;
+        let value =
+            HeaderValue::from_str(value).map_err(|e| format!("invalid header value: {e}"))?;
+        headers.append(name, value);
+    }
+    Ok(headers)
+}
+
+// Fetches a profile from a gRPC server implementing ProfileService (see
+// proto/legion_prof.proto). Calls are made from a runtime on a thread of its
+// own, and share a single HTTP/2 connection.
+pub struct GrpcDataSource {
+    baseurl: Url,
+    channel: Arc<Channel>,
+    runtime: Runtime,
+    infos: ResponseContainer<DataSourceInfo, ()>,
+    summary_tiles: Arc<Mutex<Vec<SummaryTileResponse>>>,
+    slot_tiles: Arc<Mutex<Vec<SlotTileResponse>>>,
+    slot_meta_tiles: Arc<Mutex<Vec<SlotMetaTileResponse>>>,
+    items_meta: Arc<Mutex<Vec<ItemsMetaResponse>>>,
+    // Stops the outstanding calls for each tile when it is cancelled
+    cancels: BTreeMap<TileRequest, Vec<oneshot::Sender<()>>>,
+}
+
+impl GrpcDataSource {
+    pub fn new(baseurl: Url) -> Result<Self, String> {
+        Self::with_config(baseurl, ClientConfig::default())
+    }
+
+    pub fn with_config(baseurl: Url, config: ClientConfig) -> Result<Self, String> {
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(1)
+            .thread_name("grpc")
+            .enable_all()
+            .build()
+            .map_err(|e| format!("unable to start the gRPC runtime: {e}"))?;
+        let endpoint = endpoint(&baseurl, &config)?;
+        // Connects on the first call, so that an unreachable server is
+        // reported (and retried) like any other failed call
+        let client = {
+            let _guard = runtime.enter();
+            ProfileServiceClient::new(endpoint.connect_lazy())
+        };
+        let headers = headers(&config)?;
+        let auth = Authenticator::new(&baseurl, config.auth);
+        // Checked now, since they could never be sent
+        auth.headers()?;
+        Ok(Self {
+            channel: Arc::new(Channel {
+                client,
+                auth,
+                headers,
+            }),
+            baseurl,
+            runtime,
+            infos: Arc::new(Mutex::new(Vec::new())),
+            summary_tiles: Arc::new(Mutex::new(Vec::new())),
+            slot_tiles: Arc::new(Mutex::new(Vec::new())),
+            slot_meta_tiles: Arc::new(Mutex::new(Vec::new())),
+            items_meta: Arc::new(Mutex::new(Vec::new())),
+            cancels: BTreeMap::new(),
+        })
+    }
+
+    // Runs the call, unless cancel fires first. A cancel whose sender is
+    // dropped never fires.
+    fn spawn<T, E>(
+        &self,
+        container: ResponseContainer<T, E>,
+        extra: E,
+        cancel: oneshot::Receiver<()>,
+        call: impl 'static + Send + Future<Output = Result<T, String>>,
+    ) where
+        T: 'static + Send,
+        E: 'static + Send,
+    {
+        self.runtime.spawn(async move {
+            let result = tokio::select! {
+                result = call => result,
+                Ok(()) = cancel => Err(CANCELLED.to_owned()),
+            };
+            container.lock().unwrap().push((result, extra));
+        });
+    }
+
+    fn start(&mut self, req: TileRequest) -> oneshot::Receiver<()> {
+        self.cancels.retain(|_, senders| {
+            senders.retain(|sender| !sender.is_closed());
+            !senders.is_empty()
+        });
+        let (sender, receiver) = oneshot::channel();
+        self.cancels.entry(req).or_default().push(sender);
+        receiver
+    }
+
+    fn fetch_tile<T, P, F>(
+        &mut self,
+        container: ResponseContainer<T, TileRequest>,
+        req: TileRequest,
+        method: fn(Client, tonic::Request<proto::TileRequest>) -> F,
+    ) where
+        T: 'static + Send + TileIdentity + TryFrom<P, Error = String>,
+        P: 'static + Send,
+        F: 'static + Send + Future<Output = Result<tonic::Response<P>, Status>>,
+    {
+        let cancel = self.start(req.clone());
+        let channel = self.channel.clone();
+        let body = req.clone();
+        self.spawn(container, req, cancel, async move {
+            let tile: T = channel.call((&body).into(), method).await?.try_into()?;
+            check_tile(&tile, &body)?;
+            Ok(tile)
+        });
+    }
+}
+
+impl DeferredDataSource for GrpcDataSource {
+    fn fetch_description(&self) -> DataSourceDescription {
+        DataSourceDescription {
+            source_locator: vec![self.baseurl.to_string()],
+        }
+    }
+
+    fn fetch_info(&mut self) {
+        let channel = self.channel.clone();
+        let (_, never) = oneshot::channel();
+        self.spawn(self.infos.clone(), (), never, async move {
+            let info = channel
+                .call(proto::InfoRequest {}, |mut client, request| async move {
+                    client.fetch_info(request).await
+                })
+                .await?;
+            info.try_into()
+        });
+    }
+
+    fn get_infos(&mut self) -> Vec<DataSourceInfoResult> {
+        let infos = std::mem::take(&mut *self.infos.lock().unwrap());
+        infos.into_iter().map(|(info, ())| info).collect()
+    }
+
+    fn fetch_summary_tile(
+        &mut self,
+        entry_id: &EntryID,
+        tile_id: TileID,
+        full: bool,
+        _priority: RequestPriority,
+    ) {
+        let req = TileRequest {
+            entry_id: entry_id.clone(),
+            tile_id,
+            full,
+        };
+        let container = self.summary_tiles.clone();
+        self.fetch_tile::<SummaryTile, _, _>(container, req, |mut client, request| async move {
+            client.fetch_summary_tile(request).await
+        });
+    }
+
+    fn get_summary_tiles(&mut self) -> Vec<SummaryTileResponse> {
+        std::mem::take(&mut self.summary_tiles.lock().unwrap())
+    }
+
+    fn fetch_slot_tile(
+        &mut self,
+        entry_id: &EntryID,
+        tile_id: TileID,
+        full: bool,
+        _priority: RequestPriority,
+    ) {
+        let req = TileRequest {
+            entry_id: entry_id.clone(),
+            tile_id,
+            full,
+        };
+        let container = self.slot_tiles.clone();
+        self.fetch_tile::<SlotTile, _, _>(container, req, |mut client, request| async move {
+            client.fetch_slot_tile(request).await
+        });
+    }
+
+    fn get_slot_tiles(&mut self) -> Vec<SlotTileResponse> {
+        std::mem::take(&mut self.slot_tiles.lock().unwrap())
+    }
+
+    fn fetch_slot_meta_tile(
+        &mut self,
+        entry_id: &EntryID,
+        tile_id: TileID,
+        full: bool,
+        _priority: RequestPriority,
+    ) {
+        let req = TileRequest {
+            entry_id: entry_id.clone(),
+            tile_id,
+            full,
+        };
+        let container = self.slot_meta_tiles.clone();
+        self.fetch_tile::<SlotMetaTile, _, _>(container, req, |mut client, request| async move {
+            client.fetch_slot_meta_tile(request).await
+        });
+    }
+
+    fn get_slot_meta_tiles(&mut self) -> Vec<SlotMetaTileResponse> {
+        std::mem::take(&mut self.slot_meta_tiles.lock().unwrap())
+    }
+
+    fn fetch_items_meta(&mut self, requests: &[ItemMetaRequest]) {
+        let body = proto::ItemsMetaRequest {
+            requests: requests.iter().map(Into::into).collect(),
+        };
+        let channel = self.channel.clone();
+        let (_, never) = oneshot::channel();
+        self.spawn(
+            self.items_meta.clone(),
+            requests.to_vec(),
+            never,
+            async move {
+                let reply = channel
+                    .call(body, |mut client, request| async move {
+                        client.fetch_items_meta(request).await
+                    })
+                    .await?;
+                decode_item_metas(reply.items)
+            },
+        );
+    }
+
+    fn get_items_meta(&mut self) -> Vec<ItemsMetaResponse> {
+        std::mem::take(&mut self.items_meta.lock().unwrap())
+    }
+
+    fn cancel(&mut self, entry_id: &EntryID, tile_id: TileID, full: bool) {
+        // Calls in flight are abandoned (tonic resets their streams) and
+        // answered with CANCELLED, unless they finished first
+        let req = TileRequest {
+            entry_id: entry_id.clone(),
+            tile_id,
+            full,
+        };
+        for sender in self.cancels.remove(&req).into_iter().flatten() {
+            let _ = sender.send(());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::thread::sleep;
+
+    use tonic::transport::Server;
+    use tonic::transport::server::TcpIncoming;
+    use tonic::{Request, Response};
+
+    use crate::data::DataSource;
+    use crate::http::auth::{AuthConfig, Credentials};
+    use crate::random_data::{RandomConfig, RandomDataSource};
+
+    use proto::profile_service_server::{ProfileService, ProfileServiceServer};
+
+    // Serves a data source, for those who know the token
+    struct TestService(RandomDataSource);
+
+    // Status is large, but the service's methods have to return it anyway
+    #[allow(clippy::result_large_err)]
+    fn check<T>(request: &Request<T>) -> Result<(), Status> {
+        let authorization = request.metadata().get("authorization");
+        if authorization.is_none_or(|value| value != "Bearer secret") {
+            return Err(Status::unauthenticated("bad token"));
+        }
+        Ok(())
+    }
+
+    #[allow(clippy::result_large_err)]
+    fn tile_request(request: Request<proto::TileRequest>) -> Result<TileRequest, Status> {
+        check(&request)?;
+        let req: TileRequest = request
+            .into_inner()
+            .try_into()
+            .map_err(Status::invalid_argument)?;
+        // Only processors have tiles
+        if req.entry_id.level() != 3 {
+            return Err(Status::not_found("no such tile"));
+        }
+        Ok(req)
+    }
+
+    #[tonic::async_trait]
+    impl ProfileService for TestService {
+        async fn fetch_info(
+            &self,
+            request: Request<proto::InfoRequest>,
+        ) -> Result<Response<proto::DataSourceInfo>, Status> {
+            check(&request)?;
+            Ok(Response::new((&self.0.fetch_info()).into()))
+        }
+
+        async fn fetch_summary_tile(
+            &self,
+            request: Request<proto::TileRequest>,
+        ) -> Result<Response<proto::SummaryTile>, Status> {
+            let req = tile_request(request)?;
+            let tile = self
+                .0
+                .fetch_summary_tile(&req.entry_id, req.tile_id, req.full);
+            Ok(Response::new((&tile).into()))
+        }
+
+        async fn fetch_slot_tile(
+            &self,
+            request: Request<proto::TileRequest>,
+        ) -> Result<Response<proto::SlotTile>, Status> {
+            let req = tile_request(request)?;
+            let tile = self.0.fetch_slot_tile(&req.entry_id, req.tile_id, req.full);
+            Ok(Response::new((&tile).into()))
+        }
+
+        async fn fetch_slot_meta_tile(
+            &self,
+            request: Request<proto::TileRequest>,
+        ) -> Result<Response<proto::SlotMetaTile>, Status> {
+            let req = tile_request(request)?;
+            let tile = self
+                .0
+                .fetch_slot_meta_tile(&req.entry_id, req.tile_id, req.full);
+            Ok(Response::new((&tile).into()))
+        }
+
+        async fn fetch_items_meta(
+            &self,
+            request: Request<proto::ItemsMetaRequest>,
+        ) -> Result<Response<proto::ItemsMetaReply>, Status> {
+            check(&request)?;
+            let requests = request
+                .into_inner()
+                .requests
+                .into_iter()
+                .map(TryInto::try_into)
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(Status::invalid_argument)?;
+            let items = self.0.fetch_items_meta(&requests);
+            Ok(Response::new(proto::ItemsMetaReply {
+                items: items.iter().map(Into::into).collect(),
+            }))
+        }
+    }
+
+    fn wait<T>(mut get: impl FnMut() -> Vec<T>) -> T {
+        for _ in 0..500 {
+            if let Some(result) = get().pop() {
+                return result;
+            }
+            sleep(Duration::from_millis(10));
+        }
+        panic!("no response from the server");
+    }
+
+    #[test]
+    fn test_grpc() {
+        let config = RandomConfig {
+            nodes: 1,
+            procs: 1,
+            max_rows: 2,
+            items_per_row: 10,
+            ..Default::default()
+        };
+        let runtime = Runtime::new().unwrap();
+        let listener = runtime
+            .block_on(tokio::net::TcpListener::bind("127.0.0.1:0"))
+            .unwrap();
+        let url = Url::parse(&format!("http://{}/", listener.local_addr().unwrap())).unwrap();
+        let incoming = TcpIncoming::from_listener(listener, true, None).unwrap();
+        let service = ProfileServiceServer::new(TestService(RandomDataSource::new(config)));
+        runtime.spawn(
+            Server::builder()
+                .add_service(service)
+                .serve_with_incoming(incoming),
+        );
+
+        // Without the token, the server's status comes through
+        let mut ds = GrpcDataSource::new(url.clone()).unwrap();
+        ds.fetch_info();
+        let e = wait(|| ds.get_infos()).unwrap_err();
+        assert!(e.contains("Unauthenticated: bad token"), "{e}");
+
+        let client_config = ClientConfig {
+            auth: AuthConfig {
+                credentials: Some(Credentials::Bearer("secret".to_owned())),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let mut ds = GrpcDataSource::with_config(url, client_config).unwrap();
+        ds.fetch_info();
+        let info = wait(|| ds.get_infos()).unwrap();
+        let expected = RandomDataSource::new(config).fetch_info();
+        assert_eq!(info.interval, expected.interval);
+        assert_eq!(info.version, PROTOCOL_VERSION);
+        assert_eq!(info.field_schema, expected.field_schema);
+
+        // Tiles and items arrive as the source made them
+        let slot = EntryID::root().child(0).child(0).child(0);
+        let tile_id = TileID(info.interval);
+        ds.fetch_slot_meta_tile(&slot, tile_id, true, RequestPriority::Visible);
+        let (tile, req) = wait(|| ds.get_slot_meta_tiles());
+        let tile = tile.unwrap();
+        assert_eq!(req.entry_id, slot);
+        let source = RandomDataSource::new(config).fetch_slot_meta_tile(&slot, tile_id, true);
+        assert_eq!(tile.data.items.len(), source.data.items.len());
+        let item = &tile.data.items[0][0];
+        assert_eq!(item.title, source.data.items[0][0].title);
+        assert_eq!(item.fields.len(), source.data.items[0][0].fields.len());
+
+        ds.fetch_items_meta(&[ItemMetaRequest {
+            entry_id: slot.clone(),
+            item_uid: item.item_uid,
+            interval: item.original_interval,
+        }]);
+        let (items, _) = wait(|| ds.get_items_meta());
+        assert_eq!(items.unwrap()[0].item_uid, item.item_uid);
+
+        // Errors a server sends in its trailers are reported as sent
+        let panel = EntryID::root().child(0).child(0);
+        ds.fetch_slot_tile(&panel, tile_id, false, RequestPriority::Visible);
+        let (tile, _) = wait(|| ds.get_slot_tiles());
+        let e = tile.unwrap_err();
+        assert!(e.contains("NotFound: no such tile"), "{e}");
+    }
+
+    #[test]
+    fn test_config() {
+        let url = Url::parse("http://localhost:50051/").unwrap();
+        let config = ClientConfig {
+            proxy: Some("http://proxy:3128".to_owned()),
+            ..Default::default()
+        };
+        let e = GrpcDataSource::with_config(url.clone(), config)
+            .err()
+            .unwrap();
+        assert!(e.contains("proxy"), "{e}");
+        let config = ClientConfig {
+            auth: AuthConfig {
+                credentials: Some(Credentials::Query("Signature=x".to_owned())),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let e = GrpcDataSource::with_config(url, config).err().unwrap();
+        assert!(e.contains("query credentials"), "{e}");
+    }
+}
@@ -1,5 +1,9 @@
 pub mod schema;
 
+#[cfg(feature = "client")]
+pub mod auth;
+#[cfg(feature = "client")]
+pub mod cache;
 #[cfg(feature = "client")]
 pub mod client;
 
@@ -12,5 +16,11 @@ pub mod fetch;
 pub mod fetch_native;
 #[cfg(all(feature = "client", target_arch = "wasm32"))]
 pub mod fetch_web;
+#[cfg(all(feature = "grpc", not(target_arch = "wasm32")))]
+pub mod grpc;
+#[cfg(feature = "client")]
+pub mod queue;
 #[cfg(feature = "client")]
 pub mod url;
+#[cfg(all(feature = "websocket", not(target_arch = "wasm32")))]
+pub mod websocket;
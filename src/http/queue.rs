@@ -0,0 +1,129 @@
+use std::cmp::{Ordering, Reverse};
+use std::collections::BinaryHeap;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering as AtomicOrdering};
+
+use crate::deferred_data::RequestPriority;
+
+// Held by a request while it is in flight. Dropping it (normally when the
+// response callback finishes) frees the slot for the next queued request.
+pub struct InFlight(Arc<AtomicUsize>);
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, AtomicOrdering::SeqCst);
+    }
+}
+
+type Start = Box<dyn FnOnce(InFlight)>;
+
+struct Queued {
+    priority: RequestPriority,
+    seq: Reverse<u64>,
+    start: Start,
+}
+
+impl Queued {
+    fn key(&self) -> (RequestPriority, Reverse<u64>) {
+        (self.priority, self.seq)
+    }
+}
+
+impl PartialEq for Queued {
+    fn eq(&self, other: &Self) -> bool {
+        self.key() == other.key()
+    }
+}
+
+impl Eq for Queued {}
+
+impl PartialOrd for Queued {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Queued {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.key().cmp(&other.key())
+    }
+}
+
+// Limits the number of requests in flight and starts the highest priority
+// ones first. Requests of equal priority start in the order they were pushed.
+pub struct RequestQueue {
+    queue: BinaryHeap<Queued>,
+    next_seq: u64,
+    in_flight: Arc<AtomicUsize>,
+    max_in_flight: usize,
+}
+
+impl RequestQueue {
+    pub fn new(max_in_flight: usize) -> Self {
+        assert!(max_in_flight > 0);
+        Self {
+            queue: BinaryHeap::new(),
+            next_seq: 0,
+            in_flight: Arc::new(AtomicUsize::new(0)),
+            max_in_flight,
+        }
+    }
+
+    pub fn push(&mut self, priority: RequestPriority, start: impl FnOnce(InFlight) + 'static) {
+        self.queue.push(Queued {
+            priority,
+            seq: Reverse(self.next_seq),
+            start: Box::new(start),
+        });
+        self.next_seq += 1;
+        self.pump();
+    }
+
+    pub fn pump(&mut self) {
+        while self.in_flight.load(AtomicOrdering::SeqCst) < self.max_in_flight {
+            let Some(queued) = self.queue.pop() else {
+                break;
+            };
+            self.in_flight.fetch_add(1, AtomicOrdering::SeqCst);
+            (queued.start)(InFlight(self.in_flight.clone()));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    #[test]
+    fn test_request_queue() {
+        let started = Rc::new(RefCell::new(Vec::new()));
+        let slots = Rc::new(RefCell::new(Vec::new()));
+        let mut queue = RequestQueue::new(1);
+
+        let push = |queue: &mut RequestQueue, priority, name: &'static str| {
+            let started = started.clone();
+            let slots = slots.clone();
+            queue.push(priority, move |slot| {
+                started.borrow_mut().push(name);
+                slots.borrow_mut().push(slot);
+            });
+        };
+
+        // The first request starts immediately and occupies the only slot
+        push(&mut queue, RequestPriority::Background, "a");
+        push(&mut queue, RequestPriority::Background, "b");
+        push(&mut queue, RequestPriority::Prefetch, "c");
+        push(&mut queue, RequestPriority::Visible, "d");
+        push(&mut queue, RequestPriority::Visible, "e");
+        assert_eq!(*started.borrow(), vec!["a"]);
+
+        for _ in 0..4 {
+            slots.borrow_mut().clear();
+            queue.pump();
+        }
+        assert_eq!(*started.borrow(), vec!["a", "d", "e", "c", "b"]);
+    }
+}
@@ -1,6 +1,153 @@
-use serde::Deserialize;
+use std::borrow::Cow;
+use std::io;
 
-use crate::data::{EntryID, EntryIDSlug, SlugParseError, TileID, TileIDSlug};
+use serde::{Deserialize, Serialize};
+
+use crate::data::{
+    DataSourceInfo, EntryID, EntryIDSlug, PROTOCOL_VERSION, SlotMetaTile, SlotTile, SlugParseError,
+    SummaryTile, TileID, TileIDSlug,
+};
+use crate::deferred_data::{self, TileKind};
+use crate::version::supported_protocols;
+
+// Viewers send their PROTOCOL_VERSION in this header, so that the server can
+// reject them with a readable error rather than a response they can't decode
+pub const VERSION_HEADER: &str = "X-Legion-Prof-Version";
+
+// Oldest version this build can still talk to. Older versions are rejected,
+// and anything in between goes through the shims in decode_info. Peers that
+// predate versioning (version 0) are always accepted, with the baseline
+// Capabilities.
+pub const MIN_PROTOCOL_VERSION: u32 = 1;
+
+// Just enough of DataSourceInfo to find out how to decode the rest of it.
+// Unknown fields are skipped, so this works for any version.
+#[derive(Deserialize)]
+pub(crate) struct VersionProbe {
+    #[serde(default)]
+    pub version: u32,
+}
+
+// Checks that a peer speaking the given version is compatible with this
+// build. The peer is named in the error (e.g., "server" or "viewer").
+pub fn check_version(peer: &str, version: u32) -> Result<(), String> {
+    if version == 0 || (MIN_PROTOCOL_VERSION..=PROTOCOL_VERSION).contains(&version) {
+        return Ok(());
+    }
+    let supported = supported_protocols();
+    Err(format!(
+        "the {peer} uses protocol version {version}, but this build supports {supported}; \
+         upgrade whichever is older"
+    ))
+}
+
+// Decodes a CBOR-encoded DataSourceInfo, checking its version first so that
+// an incompatible peer results in an error naming both versions instead of an
+// opaque decoding failure
+pub fn decode_info(peer: &str, bytes: &[u8]) -> Result<DataSourceInfo, String> {
+    let probe: VersionProbe = ciborium::from_reader(bytes).map_err(|e| e.to_string())?;
+    check_version(peer, probe.version)?;
+    // Once older versions are supported, their encodings would be upgraded
+    // to the current DataSourceInfo here, based on probe.version
+    ciborium::from_reader(bytes).map_err(|e| e.to_string())
+}
+
+// Every zstd frame starts with these bytes, which can't begin a CBOR value
+// that we would send
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+
+// Bodies are CBOR, normally in a zstd frame. Peers may also send it bare
+// (e.g., to make it easier to inspect while debugging), and any
+// Content-Encoding has already been removed by the HTTP stack, so the framing
+// is detected rather than assumed.
+pub fn decompress(body: &[u8]) -> io::Result<Cow<'_, [u8]>> {
+    if body.starts_with(&ZSTD_MAGIC) {
+        zstd::decode_all(body).map(Cow::Owned)
+    } else {
+        Ok(Cow::Borrowed(body))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BodyFormat {
+    Cbor,
+    Json,
+}
+
+// Servers normally send CBOR, but simple ones (e.g., a script written while
+// debugging) may send JSON instead. The Content-Type decides if it names
+// either; otherwise JSON is recognized by its first character, since '{' and
+// '[' can't start the CBOR we send.
+pub fn detect_format(content_type: Option<&str>, body: &[u8]) -> BodyFormat {
+    let mime = content_type
+        .and_then(|t| t.split(';').next())
+        .map(|t| t.trim().to_ascii_lowercase());
+    match mime.as_deref() {
+        Some("application/json") => BodyFormat::Json,
+        Some("application/cbor") => BodyFormat::Cbor,
+        _ => match body.iter().find(|b| !b.is_ascii_whitespace()) {
+            Some(b'{' | b'[') => BodyFormat::Json,
+            _ => BodyFormat::Cbor,
+        },
+    }
+}
+
+// Listing of the profiles hosted under one URL, served at its index endpoint
+// so that a single server (or static directory) can host a whole set of runs
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct ProfileIndex {
+    pub profiles: Vec<ProfileIndexEntry>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ProfileIndexEntry {
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    // Where the profile is served, relative to the index (e.g., "run1/") or
+    // as an absolute URL
+    pub path: String,
+}
+
+// Tiles name the entry and tile they hold, which lets responses be checked
+// against the request they answer
+pub trait TileIdentity {
+    fn entry_id(&self) -> &EntryID;
+    fn tile_id(&self) -> TileID;
+}
+
+macro_rules! impl_tile_identity {
+    ($($tile:ty),*) => {
+        $(impl TileIdentity for $tile {
+            fn entry_id(&self) -> &EntryID {
+                &self.entry_id
+            }
+            fn tile_id(&self) -> TileID {
+                self.tile_id
+            }
+        })*
+    };
+}
+
+impl_tile_identity!(SummaryTile, SlotTile, SlotMetaTile);
+
+// A server that answers with some other tile than the one requested is
+// broken, and its tile would otherwise be cached under the wrong key
+pub fn check_tile<T: TileIdentity>(
+    tile: &T,
+    request: &deferred_data::TileRequest,
+) -> Result<(), String> {
+    if *tile.entry_id() == request.entry_id && tile.tile_id() == request.tile_id {
+        return Ok(());
+    }
+    Err(format!(
+        "protocol error: requested tile {}/{} but the server sent {}/{}",
+        EntryIDSlug(&request.entry_id),
+        TileIDSlug(request.tile_id),
+        EntryIDSlug(tile.entry_id()),
+        TileIDSlug(tile.tile_id()),
+    ))
+}
 
 #[derive(Debug, Clone, Deserialize)]
 pub struct TileRequestPath {
@@ -25,6 +172,14 @@ pub struct TileQuery {
     pub full: bool,
 }
 
+// Body of a batched tile request. The response is the list of tiles, in the
+// same order as the requests.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct TileBatchRequest {
+    pub kind: TileKind,
+    pub requests: Vec<deferred_data::TileRequest>,
+}
+
 impl TileRequestPath {
     pub fn parse(&self) -> Result<TileRequest, SlugParseError> {
         Ok(TileRequest {
@@ -43,3 +198,110 @@ impl TileRequestRef<'_> {
         )
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::data::{Capabilities, DataSource};
+    use crate::random_data::{RandomConfig, RandomDataSource};
+    use crate::timestamp::{Interval, Timestamp};
+
+    #[test]
+    fn test_decode_info_version() {
+        #[derive(Serialize)]
+        struct FutureInfo {
+            version: u32,
+            something_new: String,
+        }
+
+        let mut bytes = Vec::new();
+        let info = FutureInfo {
+            version: PROTOCOL_VERSION + 1,
+            something_new: "x".to_owned(),
+        };
+        ciborium::into_writer(&info, &mut bytes).unwrap();
+        let err = decode_info("server", &bytes).unwrap_err();
+        assert!(err.contains(&format!("version {}", PROTOCOL_VERSION + 1)));
+        assert!(err.contains(&format!("version {PROTOCOL_VERSION}")));
+
+        // Data from before versioning has neither a version nor capabilities,
+        // and is accepted with the baseline ones
+        let info = RandomDataSource::new(RandomConfig::default()).fetch_info();
+        let mut value = ciborium::Value::serialized(&info).unwrap();
+        let ciborium::Value::Map(fields) = &mut value else {
+            unreachable!()
+        };
+        fields.retain(|(key, _)| !matches!(key.as_text(), Some("version" | "capabilities")));
+        let mut bytes = Vec::new();
+        ciborium::into_writer(&value, &mut bytes).unwrap();
+        let decoded = decode_info("server", &bytes).unwrap();
+        assert_eq!(decoded.version, 0);
+        assert_eq!(decoded.capabilities, Capabilities::default());
+        assert_eq!(decoded.interval, info.interval);
+
+        // The current version round trips
+        let mut bytes = Vec::new();
+        ciborium::into_writer(&info, &mut bytes).unwrap();
+        let decoded = decode_info("server", &bytes).unwrap();
+        assert_eq!(decoded.version, PROTOCOL_VERSION);
+        assert_eq!(decoded.interval, info.interval);
+    }
+
+    #[test]
+    fn test_decompress() {
+        let mut bytes = Vec::new();
+        ciborium::into_writer(&vec![1u32, 2, 3], &mut bytes).unwrap();
+        assert_eq!(decompress(&bytes).unwrap(), &bytes[..]);
+
+        let framed = zstd::encode_all(&bytes[..], 1).unwrap();
+        assert_eq!(decompress(&framed).unwrap(), &bytes[..]);
+
+        // A truncated frame is an error, not garbage
+        assert!(decompress(&framed[..framed.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn test_check_tile() {
+        use crate::data::SlotTileData;
+
+        let entry_id = EntryID::root().child(0);
+        let tile_id = TileID(Interval::new(Timestamp(0), Timestamp(10)));
+        let request = deferred_data::TileRequest {
+            entry_id: entry_id.clone(),
+            tile_id,
+            full: false,
+        };
+        let tile = |entry_id: EntryID, tile_id| SlotTile {
+            entry_id,
+            tile_id,
+            data: SlotTileData { items: Vec::new() },
+        };
+        assert!(check_tile(&tile(entry_id.clone(), tile_id), &request).is_ok());
+
+        let other = TileID(Interval::new(Timestamp(10), Timestamp(20)));
+        let err = check_tile(&tile(entry_id, other), &request).unwrap_err();
+        assert!(err.starts_with("protocol error"));
+        assert!(check_tile(&tile(EntryID::root().child(1), tile_id), &request).is_err());
+    }
+
+    #[test]
+    fn test_detect_format() {
+        let mut cbor = Vec::new();
+        ciborium::into_writer(&vec![1u32, 2, 3], &mut cbor).unwrap();
+        assert_eq!(detect_format(None, &cbor), BodyFormat::Cbor);
+        assert_eq!(detect_format(None, b" [1, 2, 3]"), BodyFormat::Json);
+        assert_eq!(
+            detect_format(Some("application/octet-stream"), b"{}"),
+            BodyFormat::Json
+        );
+        assert_eq!(
+            detect_format(Some("Application/JSON; charset=utf-8"), b"1"),
+            BodyFormat::Json
+        );
+        assert_eq!(
+            detect_format(Some("application/cbor"), b"{"),
+            BodyFormat::Cbor
+        );
+    }
+}
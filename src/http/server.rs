@@ -2,17 +2,23 @@ use std::sync::Arc;
 
 use actix_cors::Cors;
 use actix_web::{
-    App, HttpServer, Responder, Result, error, get, http, middleware,
+    App, HttpRequest, HttpServer, Responder, Result, error, get, http, middleware, post,
     web::{self, Data},
 };
 
-use serde::Serialize;
+use bytes::Bytes;
 
-use crate::data::DataSource;
-use crate::http::schema::{TileQuery, TileRequestPath};
+use serde::{Deserialize, Serialize};
+
+use crate::data::{DataSource, ItemMetaRequest, PROTOCOL_VERSION};
+use crate::deferred_data::TileKind;
+use crate::http::schema::{
+    TileBatchRequest, TileQuery, TileRequestPath, VERSION_HEADER, check_version, decompress,
+};
 
 struct AppState {
     data_source: Box<dyn DataSource + Send + Sync + 'static>,
+    compress: bool,
 }
 
 pub struct DataSourceHTTPServer {
@@ -21,65 +27,160 @@ pub struct DataSourceHTTPServer {
     state: AppState,
 }
 
-fn encode<T>(data: T) -> Result<Vec<u8>>
+impl AppState {
+    fn encode<T>(&self, data: T) -> Result<Vec<u8>>
+    where
+        T: Serialize,
+    {
+        if !self.compress {
+            let mut f = Vec::new();
+            ciborium::into_writer(&data, &mut f).expect("ciborium encoding failed");
+            return Ok(f);
+        }
+        let mut f = zstd::Encoder::new(Vec::new(), 1)?;
+        ciborium::into_writer(&data, &mut f).expect("ciborium encoding failed");
+        let f = f.finish()?;
+        Ok(f)
+    }
+}
+
+fn decode<T>(body: &[u8]) -> Result<T>
 where
-    T: Serialize,
+    T: for<'a> Deserialize<'a>,
 {
-    let mut f = zstd::Encoder::new(Vec::new(), 1)?;
-    ciborium::into_writer(&data, &mut f).expect("ciborium encoding failed");
-    let f = f.finish()?;
-    Ok(f)
+    let bytes =
+        decompress(body).map_err(|e| error::ErrorBadRequest(format!("bad request: {}", e)))?;
+    ciborium::from_reader(&bytes[..])
+        .map_err(|e| error::ErrorBadRequest(format!("bad request: {}", e)))
+}
+
+// Viewers that predate versioning don't send the header, and are left to
+// fail (or not) on their own
+fn check_viewer_version(req: &HttpRequest) -> Result<()> {
+    let Some(value) = req.headers().get(VERSION_HEADER) else {
+        return Ok(());
+    };
+    let version = value
+        .to_str()
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .ok_or_else(|| error::ErrorBadRequest("bad request: invalid protocol version"))?;
+    check_version("viewer", version).map_err(error::ErrorBadRequest)
 }
 
 #[get("/info")]
-async fn fetch_info(state: web::Data<AppState>) -> Result<impl Responder> {
-    let result = state.data_source.fetch_info();
-    encode(result)
+async fn fetch_info(req: HttpRequest, state: web::Data<AppState>) -> Result<impl Responder> {
+    check_viewer_version(&req)?;
+    let mut result = state
+        .data_source
+        .try_fetch_info()
+        .map_err(error::ErrorInternalServerError)?;
+    // Whatever the source, the info is about to be encoded in this build's
+    // format, and this server handles batches and item lookups
+    result.version = PROTOCOL_VERSION;
+    result.capabilities.batch_fetch = true;
+    result.capabilities.items_meta = true;
+    state.encode(result)
 }
 
 #[get("/summary_tile/{entry_id}/{tile_id}")]
 async fn fetch_summary_tile(
+    req: HttpRequest,
     path: web::Path<TileRequestPath>,
     query: web::Query<TileQuery>,
     state: web::Data<AppState>,
 ) -> Result<impl Responder> {
+    check_viewer_version(&req)?;
     let path = path
         .parse()
         .map_err(|e| error::ErrorBadRequest(format!("bad request: {}", e)))?;
     let result = state
         .data_source
-        .fetch_summary_tile(&path.entry_id, path.tile_id, query.full);
-    encode(result)
+        .try_fetch_summary_tile(&path.entry_id, path.tile_id, query.full)
+        .map_err(error::ErrorInternalServerError)?;
+    state.encode(result)
 }
 
 #[get("/slot_tile/{entry_id}/{tile_id}")]
 async fn fetch_slot_tile(
+    req: HttpRequest,
     path: web::Path<TileRequestPath>,
     query: web::Query<TileQuery>,
     state: web::Data<AppState>,
 ) -> Result<impl Responder> {
+    check_viewer_version(&req)?;
     let path = path
         .parse()
         .map_err(|e| error::ErrorBadRequest(format!("bad request: {}", e)))?;
     let result = state
         .data_source
-        .fetch_slot_tile(&path.entry_id, path.tile_id, query.full);
-    encode(result)
+        .try_fetch_slot_tile(&path.entry_id, path.tile_id, query.full)
+        .map_err(error::ErrorInternalServerError)?;
+    state.encode(result)
 }
 
 #[get("/slot_meta_tile/{entry_id}/{tile_id}")]
 async fn fetch_slot_meta_tile(
+    req: HttpRequest,
     path: web::Path<TileRequestPath>,
     query: web::Query<TileQuery>,
     state: web::Data<AppState>,
 ) -> Result<impl Responder> {
+    check_viewer_version(&req)?;
     let path = path
         .parse()
         .map_err(|e| error::ErrorBadRequest(format!("bad request: {}", e)))?;
     let result = state
         .data_source
-        .fetch_slot_meta_tile(&path.entry_id, path.tile_id, query.full);
-    encode(result)
+        .try_fetch_slot_meta_tile(&path.entry_id, path.tile_id, query.full)
+        .map_err(error::ErrorInternalServerError)?;
+    state.encode(result)
+}
+
+#[post("/items_meta")]
+async fn fetch_items_meta(
+    req: HttpRequest,
+    body: Bytes,
+    state: web::Data<AppState>,
+) -> Result<impl Responder> {
+    check_viewer_version(&req)?;
+    let requests: Vec<ItemMetaRequest> = decode(&body)?;
+    let result = state
+        .data_source
+        .try_fetch_items_meta(&requests)
+        .map_err(error::ErrorInternalServerError)?;
+    state.encode(result)
+}
+
+#[post("/tiles")]
+async fn fetch_tiles(
+    req: HttpRequest,
+    body: Bytes,
+    state: web::Data<AppState>,
+) -> Result<impl Responder> {
+    check_viewer_version(&req)?;
+    let batch: TileBatchRequest = decode(&body)?;
+    let ds = &state.data_source;
+    let reqs = batch.requests.iter();
+    // The client has no way to tell which tile of a batch failed, so the
+    // whole batch does
+    match batch.kind {
+        TileKind::Summary => state.encode(
+            reqs.map(|r| ds.try_fetch_summary_tile(&r.entry_id, r.tile_id, r.full))
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(error::ErrorInternalServerError)?,
+        ),
+        TileKind::Slot => state.encode(
+            reqs.map(|r| ds.try_fetch_slot_tile(&r.entry_id, r.tile_id, r.full))
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(error::ErrorInternalServerError)?,
+        ),
+        TileKind::SlotMeta => state.encode(
+            reqs.map(|r| ds.try_fetch_slot_meta_tile(&r.entry_id, r.tile_id, r.full))
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(error::ErrorInternalServerError)?,
+        ),
+    }
 }
 
 impl DataSourceHTTPServer {
@@ -91,12 +192,24 @@ impl DataSourceHTTPServer {
         Self {
             host,
             port,
-            state: AppState { data_source },
+            state: AppState {
+                data_source,
+                compress: true,
+            },
         }
     }
 
+    // Sends bare CBOR instead of zstd frames, so that responses can be
+    // inspected with ordinary tools. Responses are then compressed according
+    // to the viewer's Accept-Encoding instead.
+    pub fn uncompressed(mut self) -> Self {
+        self.state.compress = false;
+        self
+    }
+
     #[actix_web::main]
     pub async fn run(self) -> std::io::Result<()> {
+        let compress = self.state.compress;
         let state = Data::from(Arc::new(self.state));
         HttpServer::new(move || {
             let cors = Cors::default()
@@ -105,8 +218,15 @@ impl DataSourceHTTPServer {
                 .allowed_methods(vec!["GET", "POST"])
                 .allowed_headers(vec![http::header::AUTHORIZATION, http::header::ACCEPT])
                 .allowed_header(http::header::CONTENT_TYPE)
+                .allowed_header(VERSION_HEADER)
                 .max_age(3600);
             App::new()
+                // Payloads are already compressed unless the server was
+                // told otherwise
+                .wrap(middleware::Condition::new(
+                    !compress,
+                    middleware::Compress::default(),
+                ))
                 .wrap(middleware::Logger::default())
                 .wrap(cors)
                 .app_data(state.clone())
@@ -114,6 +234,8 @@ impl DataSourceHTTPServer {
                 .service(fetch_summary_tile)
                 .service(fetch_slot_tile)
                 .service(fetch_slot_meta_tile)
+                .service(fetch_items_meta)
+                .service(fetch_tiles)
         })
         .bind((self.host.as_str(), self.port))?
         .run()
@@ -0,0 +1,737 @@
+use std::hash::{BuildHasher, RandomState};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{IpAddr, TcpStream, ToSocketAddrs};
+use std::path::PathBuf;
+
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+
+use sha1::{Digest, Sha1};
+
+use url::Url;
+
+use crate::http::auth::Authenticator;
+use crate::http::client::{ClientConfig, PROXY_VARS, TimeoutConfig, TlsConfig};
+use crate::http::schema::decompress;
+use crate::live_data::{LiveDataSource, LiveMessage};
+
+// Appended to the client's key to form the key the server must answer with
+const ACCEPT_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+// Larger messages are assumed to be garbage rather than allocated
+const MAX_MESSAGE_BYTES: u64 = 1 << 30;
+
+mod opcode {
+    pub const CONTINUATION: u8 = 0x0;
+    pub const TEXT: u8 = 0x1;
+    pub const BINARY: u8 = 0x2;
+    pub const CLOSE: u8 = 0x8;
+    pub const PING: u8 = 0x9;
+    pub const PONG: u8 = 0xa;
+}
+
+trait Stream: Read + Write + Send {}
+impl<T: Read + Write + Send> Stream for T {}
+
+fn invalid_data(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into())
+}
+
+fn random_bytes<const N: usize>() -> [u8; N] {
+    let mut bytes = [0; N];
+    for chunk in bytes.chunks_mut(8) {
+        let random = RandomState::new().hash_one(0u64).to_le_bytes();
+        chunk.copy_from_slice(&random[..chunk.len()]);
+    }
+    bytes
+}
+
+fn is_timeout(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+    )
+}
+
+// Whether the host is in a NO_PROXY style list: domains (which also match
+// their subdomains), IP addresses, CIDR blocks, or * for every host
+fn bypass_proxy(host: &str, no_proxy: &str) -> bool {
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    let ip = host.parse::<IpAddr>().ok();
+    no_proxy
+        .split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .any(|entry| {
+            if entry == "*" {
+                return true;
+            }
+            if let Some((network, bits)) = entry.split_once('/') {
+                let (Some(ip), Ok(network), Ok(bits)) =
+                    (ip, network.parse::<IpAddr>(), bits.parse::<u32>())
+                else {
+                    return false;
+                };
+                return match (ip, network) {
+                    (IpAddr::V4(ip), IpAddr::V4(network)) if bits <= 32 => {
+                        let mask = u32::MAX.checked_shl(32 - bits).unwrap_or(0);
+                        u32::from(ip) & mask == u32::from(network) & mask
+                    }
+                    (IpAddr::V6(ip), IpAddr::V6(network)) if bits <= 128 => {
+                        let mask = u128::MAX.checked_shl(128 - bits).unwrap_or(0);
+                        u128::from(ip) & mask == u128::from(network) & mask
+                    }
+                    _ => false,
+                };
+            }
+            if let Ok(entry) = entry.parse::<IpAddr>() {
+                return ip == Some(entry);
+            }
+            let domain = entry.trim_start_matches('.');
+            host.eq_ignore_ascii_case(domain)
+                || host
+                    .to_ascii_lowercase()
+                    .ends_with(&format!(".{}", domain.to_ascii_lowercase()))
+        })
+}
+
+// The proxy to reach the URL through, the same as for HTTP sources: the
+// configured one, or else the usual environment variables, unless the host
+// is exempt
+fn proxy_for(url: &Url, config: &ClientConfig) -> io::Result<Option<Url>> {
+    let var = |names: &[&str]| {
+        names
+            .iter()
+            .find_map(|name| std::env::var(name).ok())
+            .filter(|value| !value.is_empty())
+    };
+    let proxy = config.proxy.clone().or_else(|| {
+        let scheme = if url.scheme() == "wss" {
+            &PROXY_VARS[2..4]
+        } else {
+            &PROXY_VARS[0..2]
+        };
+        var(scheme).or_else(|| var(&PROXY_VARS[4..6]))
+    });
+    let Some(proxy) = proxy else {
+        return Ok(None);
+    };
+    let no_proxy = config
+        .no_proxy
+        .clone()
+        .or_else(|| var(&["NO_PROXY", "no_proxy"]))
+        .unwrap_or_default();
+    if bypass_proxy(url.host_str().unwrap_or_default(), &no_proxy) {
+        return Ok(None);
+    }
+    // A bare host:port means an HTTP proxy, as it does for reqwest
+    let proxy = if proxy.contains("://") {
+        proxy
+    } else {
+        format!("http://{proxy}")
+    };
+    let proxy = Url::parse(&proxy).map_err(|e| invalid_data(format!("invalid proxy: {e}")))?;
+    if proxy.scheme() != "http" {
+        return Err(invalid_data(format!(
+            "unsupported proxy {proxy} (live streams can only use http:// proxies)"
+        )));
+    }
+    Ok(Some(proxy))
+}
+
+fn connect_tcp(host: &str, port: u16, timeouts: &TimeoutConfig) -> io::Result<TcpStream> {
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    let tcp = match timeouts.connect {
+        Some(timeout) => {
+            let mut last_error =
+                io::Error::new(io::ErrorKind::NotFound, format!("no addresses for {host}"));
+            let mut connected = None;
+            for addr in (host, port).to_socket_addrs()? {
+                match TcpStream::connect_timeout(&addr, timeout) {
+                    Ok(tcp) => {
+                        connected = Some(tcp);
+                        break;
+                    }
+                    Err(e) => last_error = e,
+                }
+            }
+            connected.ok_or(last_error)?
+        }
+        None => TcpStream::connect((host, port))?,
+    };
+    // Also covers the handshakes, so that a server that accepts the
+    // connection and then says nothing doesn't hang the viewer
+    tcp.set_read_timeout(timeouts.read)?;
+    tcp.set_write_timeout(timeouts.read)?;
+    tcp.set_nodelay(true)?;
+    Ok(tcp)
+}
+
+// Asks an HTTP proxy to open a tunnel to the host
+fn connect_proxy(
+    proxy: &Url,
+    host: &str,
+    port: u16,
+    timeouts: &TimeoutConfig,
+) -> io::Result<TcpStream> {
+    let proxy_host = proxy
+        .host_str()
+        .ok_or_else(|| invalid_data(format!("no host in proxy {proxy}")))?;
+    let proxy_port = proxy.port_or_known_default().unwrap_or(80);
+    let mut tcp = connect_tcp(proxy_host, proxy_port, timeouts)?;
+    let mut request = format!("CONNECT {host}:{port} HTTP/1.1\r\nHost: {host}:{port}\r\n");
+    if !proxy.username().is_empty() {
+        let username = percent_decode(proxy.username());
+        let password = percent_decode(proxy.password().unwrap_or_default());
+        let credentials = BASE64.encode(format!("{username}:{password}"));
+        request.push_str(&format!("Proxy-Authorization: Basic {credentials}\r\n"));
+    }
+    request.push_str("\r\n");
+    tcp.write_all(request.as_bytes())?;
+
+    // Read the response a byte at a time, so that nothing from the server
+    // behind it is consumed
+    let mut response = Vec::new();
+    let mut byte = [0];
+    while !response.ends_with(b"\r\n\r\n") {
+        if tcp.read(&mut byte)? == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        response.push(byte[0]);
+        if response.len() > 64 << 10 {
+            return Err(invalid_data("proxy response too large"));
+        }
+    }
+    let response = String::from_utf8_lossy(&response);
+    let status = response.lines().next().unwrap_or_default();
+    if status.split_whitespace().nth(1) != Some("200") {
+        return Err(invalid_data(format!(
+            "proxy refused the connection: {}",
+            status.trim()
+        )));
+    }
+    Ok(tcp)
+}
+
+// Proxy credentials come percent-encoded in the proxy URL
+fn percent_decode(s: &str) -> String {
+    let mut bytes = Vec::new();
+    let mut rest = s.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        let hex = tail.get(..2).and_then(|hex| std::str::from_utf8(hex).ok());
+        match hex.and_then(|hex| u8::from_str_radix(hex, 16).ok()) {
+            Some(decoded) if byte == b'%' => {
+                bytes.push(decoded);
+                rest = &tail[2..];
+            }
+            _ => {
+                bytes.push(byte);
+                rest = tail;
+            }
+        }
+    }
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+fn tls_connector(tls: &TlsConfig) -> io::Result<native_tls::TlsConnector> {
+    let read = |path: &PathBuf| {
+        std::fs::read(path).map_err(|e| {
+            io::Error::new(e.kind(), format!("unable to read {}: {e}", path.display()))
+        })
+    };
+    let mut builder = native_tls::TlsConnector::builder();
+    for path in &tls.ca_certificates {
+        // The file may be a bundle of several certificates
+        let pem = String::from_utf8_lossy(&read(path)?).into_owned();
+        const END: &str = "-----END CERTIFICATE-----";
+        for block in pem.split_inclusive(END).filter(|block| block.contains(END)) {
+            let certificate =
+                native_tls::Certificate::from_pem(block.trim().as_bytes()).map_err(|e| {
+                    invalid_data(format!("invalid certificate in {}: {e}", path.display()))
+                })?;
+            builder.add_root_certificate(certificate);
+        }
+    }
+    if let Some(path) = &tls.client_certificate {
+        let certificate = read(path)?;
+        let key = match &tls.client_key {
+            Some(key) => read(key)?,
+            None => certificate.clone(),
+        };
+        let identity = native_tls::Identity::from_pkcs8(&certificate, &key).map_err(|e| {
+            invalid_data(format!(
+                "invalid client certificate in {}: {e}",
+                path.display()
+            ))
+        })?;
+        builder.identity(identity);
+    }
+    builder.danger_accept_invalid_certs(tls.accept_invalid_certs);
+    builder.build().map_err(io::Error::other)
+}
+
+// The URL and headers of the handshake: the same credentials, cookies and
+// extra headers as any other request to the server
+fn handshake_request(url: &Url, config: &ClientConfig) -> io::Result<(Url, Vec<String>)> {
+    // reqwest only builds requests for http:// and https:// URLs
+    let mut http_url = url.clone();
+    let scheme = if url.scheme() == "wss" {
+        "https"
+    } else {
+        "http"
+    };
+    let _ = http_url.set_scheme(scheme);
+    let auth = Authenticator::new(&http_url, config.auth.clone());
+    let (request, _) = auth.apply(reqwest::blocking::Client::new().get(http_url));
+    let request = request.build().map_err(io::Error::other)?;
+
+    let mut url = request.url().clone();
+    let _ = url.set_scheme(if scheme == "https" { "wss" } else { "ws" });
+    let mut headers = Vec::new();
+    for (name, value) in request.headers() {
+        let value = value.to_str().map_err(io::Error::other)?;
+        headers.push(format!("{name}: {value}"));
+    }
+    for (name, value) in &config.headers {
+        headers.push(format!("{name}: {value}"));
+    }
+    if let Some(user_agent) = &config.user_agent {
+        headers.push(format!("User-Agent: {user_agent}"));
+    }
+    Ok((url, headers))
+}
+
+fn accept_key(key: &str) -> String {
+    let mut hasher = Sha1::new();
+    hasher.update(key.as_bytes());
+    hasher.update(ACCEPT_GUID.as_bytes());
+    BASE64.encode(hasher.finalize())
+}
+
+// Just enough of the client side of the WebSocket protocol (RFC 6455) to
+// receive messages from a server: frames are reassembled into messages, and
+// pings and closes are answered.
+pub struct WebSocket {
+    stream: BufReader<Box<dyn Stream>>,
+}
+
+impl WebSocket {
+    // Connects with the same proxy, TLS, timeout and authentication settings
+    // as HTTP sources
+    pub fn connect(url: &Url, config: &ClientConfig) -> io::Result<Self> {
+        let host = url
+            .host_str()
+            .ok_or_else(|| invalid_data(format!("no host in {url}")))?;
+        let port = url
+            .port_or_known_default()
+            .ok_or_else(|| invalid_data(format!("no port in {url}")))?;
+        if !matches!(url.scheme(), "ws" | "wss") {
+            return Err(invalid_data(format!("unsupported scheme {}", url.scheme())));
+        }
+        let tcp = match proxy_for(url, config)? {
+            Some(proxy) => connect_proxy(&proxy, host, port, &config.timeouts)?,
+            None => connect_tcp(host, port, &config.timeouts)?,
+        };
+        let stream: Box<dyn Stream> = if url.scheme() == "wss" {
+            let connector = tls_connector(&config.tls)?;
+            let host = host.trim_start_matches('[').trim_end_matches(']');
+            Box::new(connector.connect(host, tcp).map_err(io::Error::other)?)
+        } else {
+            Box::new(tcp)
+        };
+        let mut socket = Self {
+            stream: BufReader::new(stream),
+        };
+        let (url, headers) = handshake_request(url, config)?;
+        socket.handshake(&url, &headers)?;
+        Ok(socket)
+    }
+
+    fn handshake(&mut self, url: &Url, headers: &[String]) -> io::Result<()> {
+        let key = BASE64.encode(random_bytes::<16>());
+        let mut path = url.path().to_owned();
+        if let Some(query) = url.query() {
+            path = format!("{path}?{query}");
+        }
+        let mut host = url.host_str().unwrap_or_default().to_owned();
+        if let Some(port) = url.port() {
+            host = format!("{host}:{port}");
+        }
+        let mut request = format!(
+            "GET {path} HTTP/1.1\r\n\
+             Host: {host}\r\n\
+             Upgrade: websocket\r\n\
+             Connection: Upgrade\r\n\
+             Sec-WebSocket-Key: {key}\r\n\
+             Sec-WebSocket-Version: 13\r\n"
+        );
+        for header in headers {
+            request.push_str(header);
+            request.push_str("\r\n");
+        }
+        request.push_str("\r\n");
+        self.stream.get_mut().write_all(request.as_bytes())?;
+
+        let mut status = String::new();
+        self.stream.read_line(&mut status)?;
+        if status.split_whitespace().nth(1) != Some("101") {
+            return Err(invalid_data(format!(
+                "server refused the WebSocket: {}",
+                status.trim()
+            )));
+        }
+        let mut accept = None;
+        loop {
+            let mut line = String::new();
+            if self.stream.read_line(&mut line)? == 0 {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+            let line = line.trim();
+            if line.is_empty() {
+                break;
+            }
+            if let Some((name, value)) = line.split_once(':') {
+                if name.trim().eq_ignore_ascii_case("sec-websocket-accept") {
+                    accept = Some(value.trim().to_owned());
+                }
+            }
+        }
+        if accept != Some(accept_key(&key)) {
+            return Err(invalid_data("server sent the wrong WebSocket accept key"));
+        }
+        Ok(())
+    }
+
+    fn read_frame(&mut self) -> io::Result<(bool, u8, Vec<u8>)> {
+        let mut header = [0; 2];
+        self.stream.read_exact(&mut header)?;
+        let fin = header[0] & 0x80 != 0;
+        let opcode = header[0] & 0x0f;
+        let masked = header[1] & 0x80 != 0;
+        let len = match header[1] & 0x7f {
+            126 => {
+                let mut len = [0; 2];
+                self.stream.read_exact(&mut len)?;
+                u16::from_be_bytes(len) as u64
+            }
+            127 => {
+                let mut len = [0; 8];
+                self.stream.read_exact(&mut len)?;
+                u64::from_be_bytes(len)
+            }
+            len => len as u64,
+        };
+        if len > MAX_MESSAGE_BYTES {
+            return Err(invalid_data(format!(
+                "WebSocket frame too large ({len} bytes)"
+            )));
+        }
+        // Servers aren't supposed to mask, but it costs nothing to allow
+        let mut mask = [0; 4];
+        if masked {
+            self.stream.read_exact(&mut mask)?;
+        }
+        let mut payload = vec![0; len as usize];
+        self.stream.read_exact(&mut payload)?;
+        if masked {
+            for (i, byte) in payload.iter_mut().enumerate() {
+                *byte ^= mask[i % 4];
+            }
+        }
+        Ok((fin, opcode, payload))
+    }
+
+    // Clients must mask every frame they send
+    fn write_frame(&mut self, opcode: u8, payload: &[u8]) -> io::Result<()> {
+        let mut frame = vec![0x80 | opcode];
+        let len = payload.len();
+        if len < 126 {
+            frame.push(0x80 | len as u8);
+        } else if len <= u16::MAX as usize {
+            frame.push(0x80 | 126);
+            frame.extend_from_slice(&(len as u16).to_be_bytes());
+        } else {
+            frame.push(0x80 | 127);
+            frame.extend_from_slice(&(len as u64).to_be_bytes());
+        }
+        let mask = random_bytes::<4>();
+        frame.extend_from_slice(&mask);
+        frame.extend(payload.iter().enumerate().map(|(i, b)| b ^ mask[i % 4]));
+        self.stream.get_mut().write_all(&frame)
+    }
+
+    // Returns the next text or binary message, or None once the server has
+    // closed the connection
+    pub fn read_message(&mut self) -> io::Result<Option<Vec<u8>>> {
+        let mut message: Option<Vec<u8>> = None;
+        let mut pinged = false;
+        loop {
+            // A stream that is quiet for a whole read timeout is pinged once
+            // before giving up on it, so that a profile that simply isn't
+            // changing stays open. Only whole frames are waited on this way;
+            // a timeout partway through one is an error.
+            match self.stream.fill_buf() {
+                Ok(_) => pinged = false,
+                Err(e) if is_timeout(&e) && !pinged => {
+                    self.write_frame(opcode::PING, b"")?;
+                    pinged = true;
+                    continue;
+                }
+                Err(e) if is_timeout(&e) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::TimedOut,
+                        "timed out waiting for the server",
+                    ));
+                }
+                Err(e) => return Err(e),
+            }
+            let (fin, opcode, payload) = self.read_frame()?;
+            match opcode {
+                opcode::PING => self.write_frame(opcode::PONG, &payload)?,
+                opcode::PONG => {}
+                opcode::CLOSE => {
+                    // Echo the status code, if any, to complete the close
+                    self.write_frame(opcode::CLOSE, &payload[..payload.len().min(2)])?;
+                    return Ok(None);
+                }
+                opcode::TEXT | opcode::BINARY if message.is_none() => message = Some(payload),
+                opcode::CONTINUATION if message.is_some() => {
+                    let data = message.as_mut().unwrap();
+                    data.extend_from_slice(&payload);
+                    if data.len() as u64 > MAX_MESSAGE_BYTES {
+                        return Err(invalid_data("WebSocket message too large"));
+                    }
+                }
+                opcode => {
+                    return Err(invalid_data(format!(
+                        "unexpected WebSocket opcode {opcode}"
+                    )));
+                }
+            }
+            // Control frames may arrive between the fragments of a message
+            if fin && opcode < opcode::CLOSE {
+                return Ok(message);
+            }
+        }
+    }
+}
+
+fn read_live_message(socket: &mut WebSocket) -> io::Result<Option<LiveMessage>> {
+    let Some(message) = socket.read_message()? else {
+        return Ok(None);
+    };
+    let bytes = decompress(&message)?;
+    ciborium::from_reader(&bytes[..])
+        .map(Some)
+        .map_err(|e| invalid_data(e.to_string()))
+}
+
+// Opens a profile streamed from a ws:// or wss:// URL. Each message is a
+// LiveMessage (in CBOR, optionally zstd compressed), starting with the info.
+// Returns once the info has arrived; the rest is received in the background.
+pub fn connect_live(url: &Url, config: &ClientConfig) -> io::Result<LiveDataSource> {
+    let mut socket = WebSocket::connect(url, config)?;
+    let Some(LiveMessage::Info(info)) = read_live_message(&mut socket)? else {
+        return Err(invalid_data(
+            "live stream did not start with the profile info",
+        ));
+    };
+    let data_source = LiveDataSource::new(url.as_str(), *info);
+    let stream = data_source.clone();
+    std::thread::spawn(move || {
+        loop {
+            match read_live_message(&mut socket) {
+                Ok(Some(message)) => stream.push(message),
+                Ok(None) => {
+                    stream.close("the server ended the live stream");
+                    break;
+                }
+                Err(e) => {
+                    stream.close(format!("live stream failed: {e}"));
+                    break;
+                }
+            }
+        }
+    });
+    Ok(data_source)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::net::TcpListener;
+
+    use crate::data::DataSource;
+    use crate::http::auth::Credentials;
+    use crate::random_data::{RandomConfig, RandomDataSource};
+
+    // Server frames are unmasked
+    fn frame(fin: bool, opcode: u8, payload: &[u8]) -> Vec<u8> {
+        let mut frame = vec![if fin { 0x80 | opcode } else { opcode }];
+        if payload.len() < 126 {
+            frame.push(payload.len() as u8);
+        } else {
+            frame.push(126);
+            frame.extend_from_slice(&(payload.len() as u16).to_be_bytes());
+        }
+        frame.extend_from_slice(payload);
+        frame
+    }
+
+    #[test]
+    fn test_accept_key() {
+        // The example from RFC 6455
+        assert_eq!(
+            accept_key("dGhlIHNhbXBsZSBub25jZQ=="),
+            "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+        );
+    }
+
+    #[test]
+    fn test_bypass_proxy() {
+        let no_proxy = "localhost, .internal,10.0.0.0/8,::1";
+        assert!(bypass_proxy("localhost", no_proxy));
+        assert!(bypass_proxy("head.internal", no_proxy));
+        assert!(bypass_proxy("internal", no_proxy));
+        assert!(!bypass_proxy("external", no_proxy));
+        assert!(bypass_proxy("10.1.2.3", no_proxy));
+        assert!(!bypass_proxy("11.1.2.3", no_proxy));
+        assert!(bypass_proxy("[::1]", no_proxy));
+        assert!(bypass_proxy("anything", "*"));
+        assert!(!bypass_proxy("anything", ""));
+    }
+
+    #[test]
+    fn test_proxy_for() {
+        let config = ClientConfig {
+            proxy: Some("proxy:3128".to_owned()),
+            no_proxy: Some(".internal".to_owned()),
+            ..Default::default()
+        };
+        let url = Url::parse("wss://example.com/live").unwrap();
+        let proxy = proxy_for(&url, &config).unwrap().unwrap();
+        assert_eq!(proxy.as_str(), "http://proxy:3128/");
+        let url = Url::parse("ws://head.internal/live").unwrap();
+        assert!(proxy_for(&url, &config).unwrap().is_none());
+
+        let config = ClientConfig {
+            proxy: Some("socks5://localhost:1080".to_owned()),
+            no_proxy: Some(String::new()),
+            ..Default::default()
+        };
+        assert!(proxy_for(&url, &config).is_err());
+    }
+
+    #[test]
+    fn test_handshake_request() {
+        let mut config = ClientConfig {
+            headers: vec![("X-API-Key".to_owned(), "secret".to_owned())],
+            user_agent: Some("legion-prof".to_owned()),
+            ..Default::default()
+        };
+        config
+            .auth
+            .set_credentials(Credentials::Bearer("token".to_owned()));
+        let url = Url::parse("wss://example.com/live?run=1").unwrap();
+        let (handshake_url, headers) = handshake_request(&url, &config).unwrap();
+        assert_eq!(handshake_url, url);
+        assert!(headers.contains(&"authorization: Bearer token".to_owned()));
+        assert!(headers.contains(&"X-API-Key: secret".to_owned()));
+        assert!(headers.contains(&"User-Agent: legion-prof".to_owned()));
+
+        // Signed URLs keep their signature
+        config
+            .auth
+            .set_credentials(Credentials::Query("sig=abc".to_owned()));
+        let (handshake_url, _) = handshake_request(&url, &config).unwrap();
+        assert_eq!(
+            handshake_url.as_str(),
+            "wss://example.com/live?run=1&sig=abc"
+        );
+
+        assert_eq!(percent_decode("p%40ss+word%"), "p@ss+word%");
+    }
+
+    #[test]
+    fn test_live_stream() {
+        let info = RandomDataSource::new(RandomConfig::default()).fetch_info();
+        let mut info_bytes = Vec::new();
+        ciborium::into_writer(&LiveMessage::Info(Box::new(info.clone())), &mut info_bytes).unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut key = String::new();
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if let Some(value) = line.strip_prefix("Sec-WebSocket-Key:") {
+                    key = value.trim().to_owned();
+                }
+                if line.trim().is_empty() {
+                    break;
+                }
+            }
+            let mut stream = stream;
+            write!(
+                stream,
+                "HTTP/1.1 101 Switching Protocols\r\n\
+                 Upgrade: websocket\r\n\
+                 Connection: Upgrade\r\n\
+                 Sec-WebSocket-Accept: {}\r\n\r\n",
+                accept_key(&key)
+            )
+            .unwrap();
+
+            // The info in two fragments, with a ping in between
+            let (first, second) = info_bytes.split_at(info_bytes.len() / 2);
+            stream
+                .write_all(&frame(false, opcode::BINARY, first))
+                .unwrap();
+            stream.write_all(&frame(true, opcode::PING, b"hi")).unwrap();
+            stream
+                .write_all(&frame(true, opcode::CONTINUATION, second))
+                .unwrap();
+
+            // The client answers the ping, masked
+            let mut pong = [0; 8];
+            reader.read_exact(&mut pong).unwrap();
+            assert_eq!(pong[0], 0x80 | opcode::PONG);
+            assert_eq!(pong[1], 0x80 | 2);
+            let payload: Vec<_> = pong[6..]
+                .iter()
+                .zip(&pong[2..6])
+                .map(|(b, m)| b ^ m)
+                .collect();
+            assert_eq!(payload, b"hi");
+
+            stream
+                .write_all(&frame(true, opcode::CLOSE, &1000u16.to_be_bytes()))
+                .unwrap();
+            let mut close = [0; 8];
+            reader.read_exact(&mut close).unwrap();
+            assert_eq!(close[0], 0x80 | opcode::CLOSE);
+        });
+
+        let url = Url::parse(&format!("ws://127.0.0.1:{port}/live")).unwrap();
+        let ds = connect_live(&url, &ClientConfig::default()).unwrap();
+        server.join().unwrap();
+
+        // Wait for the reader to notice the close
+        let result = (0..1000)
+            .map(|_| {
+                std::thread::sleep(std::time::Duration::from_millis(10));
+                ds.fetch_info()
+            })
+            .find(|result| result.warning_message.is_some())
+            .expect("stream was never closed");
+        assert_eq!(result.interval, info.interval);
+        assert_eq!(
+            result.warning_message.as_deref(),
+            Some("the server ended the live stream")
+        );
+    }
+}
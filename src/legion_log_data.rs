@@ -0,0 +1,686 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, BufReader, Read};
+use std::path::Path;
+
+use flate2::read::MultiGzDecoder;
+
+use crate::data::{Field, FieldID};
+use crate::timestamp::{Interval, Timestamp};
+use crate::trace_data::{ThreadID, TraceBuilder, TraceDataSource, TraceItem};
+
+const FILE_TYPE: &str = "FileType: BinaryLegionProf v: ";
+
+fn invalid(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into())
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Value {
+    Int(u64),
+    String(String),
+    // Arrays (points) and anything too wide to be a number
+    Bytes(Vec<u8>),
+}
+
+#[derive(Debug, Clone)]
+struct FieldFormat {
+    name: String,
+    kind: String,
+    // -1 for strings and arrays, whose size is only known as they are read
+    size: i64,
+}
+
+#[derive(Debug, Clone)]
+struct RecordFormat {
+    name: String,
+    fields: Vec<FieldFormat>,
+}
+
+#[derive(Debug, Clone)]
+struct Record<'a> {
+    name: &'a str,
+    values: Vec<(&'a str, Value)>,
+}
+
+impl Record<'_> {
+    fn get(&self, name: &str) -> Option<&Value> {
+        self.values
+            .iter()
+            .find(|(field, _)| *field == name)
+            .map(|(_, value)| value)
+    }
+
+    fn int(&self, name: &str) -> Option<u64> {
+        match self.get(name)? {
+            Value::Int(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    fn string(&self, name: &str) -> Option<&str> {
+        match self.get(name)? {
+            Value::String(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    fn time(&self, name: &str) -> Option<Timestamp> {
+        self.int(name).map(|t| Timestamp(t as i64))
+    }
+}
+
+// The preamble is one line per kind of record, e.g.
+//
+//     TaskKind {id:4, task_id:TaskID:4, name:string:-1, overwrite:bool:1}
+//
+// naming each field with its C++ type and size in bytes. Fields are looked
+// up by name, so that logs from any Legion version can be read as long as
+// the records used below keep their names.
+fn parse_record_format(line: &str) -> Option<(u32, RecordFormat)> {
+    let (name, rest) = line.split_once(" {")?;
+    let rest = rest.strip_suffix('}')?;
+    let mut parts = rest.split(", ");
+    let id = parts.next()?.strip_prefix("id:")?.parse().ok()?;
+    let fields = parts
+        .map(|part| {
+            // Types may contain spaces ("unsigned long long") but not colons
+            let (name, rest) = part.split_once(':')?;
+            let (kind, size) = rest.rsplit_once(':')?;
+            Some(FieldFormat {
+                name: name.to_owned(),
+                kind: kind.to_owned(),
+                size: size.parse().ok()?,
+            })
+        })
+        .collect::<Option<_>>()?;
+    Some((
+        id,
+        RecordFormat {
+            name: name.to_owned(),
+            fields,
+        },
+    ))
+}
+
+fn read_line<R: Read>(reader: &mut R) -> io::Result<String> {
+    let mut line = Vec::new();
+    let mut byte = [0];
+    loop {
+        reader.read_exact(&mut byte)?;
+        if byte[0] == b'\n' {
+            break;
+        }
+        line.push(byte[0]);
+    }
+    String::from_utf8(line).map_err(|_| invalid("preamble is not valid UTF-8"))
+}
+
+fn read_preamble<R: Read>(reader: &mut R) -> io::Result<BTreeMap<u32, RecordFormat>> {
+    let file_type = read_line(reader)?;
+    if !file_type.starts_with(FILE_TYPE) {
+        return Err(invalid(
+            "not a binary Legion Prof log (ASCII logs are no longer supported)",
+        ));
+    }
+    // An empty line ends the preamble
+    let mut formats = BTreeMap::new();
+    loop {
+        let line = read_line(reader)?;
+        if line.is_empty() {
+            return Ok(formats);
+        }
+        let (id, format) = parse_record_format(&line)
+            .ok_or_else(|| invalid(format!("unable to parse record format {line:?}")))?;
+        formats.insert(id, format);
+    }
+}
+
+// Reads into buf, or returns false at the end of the log
+fn read_or_eof<R: Read>(reader: &mut R, buf: &mut [u8]) -> io::Result<bool> {
+    let mut n = 0;
+    while n < buf.len() {
+        match reader.read(&mut buf[n..])? {
+            0 if n == 0 => return Ok(false),
+            0 => return Err(io::ErrorKind::UnexpectedEof.into()),
+            k => n += k,
+        }
+    }
+    Ok(true)
+}
+
+fn read_value<R: Read>(reader: &mut R, field: &FieldFormat, max_dim: u64) -> io::Result<Value> {
+    let size = match (field.kind.as_str(), field.size) {
+        ("string", _) => {
+            let mut s = Vec::new();
+            let mut byte = [0];
+            loop {
+                reader.read_exact(&mut byte)?;
+                if byte[0] == 0 {
+                    break;
+                }
+                s.push(byte[0]);
+            }
+            return Ok(Value::String(String::from_utf8_lossy(&s).into_owned()));
+        }
+        // One coordinate per dimension
+        ("array", -1) => max_dim as usize * 8,
+        (_, size) if size >= 0 => size as usize,
+        (kind, size) => {
+            return Err(invalid(format!(
+                "field {} has unknown type {kind} of size {size}",
+                field.name
+            )));
+        }
+    };
+    let mut bytes = vec![0; size];
+    reader.read_exact(&mut bytes)?;
+    if size > 8 || field.kind == "array" {
+        return Ok(Value::Bytes(bytes));
+    }
+    let mut value = [0; 8];
+    value[..size].copy_from_slice(&bytes);
+    Ok(Value::Int(u64::from_le_bytes(value)))
+}
+
+// Calls f on each record of a log, in the order they were written
+fn read_records<R: Read>(
+    reader: &mut R,
+    mut f: impl FnMut(Record<'_>) -> io::Result<()>,
+) -> io::Result<()> {
+    let formats = read_preamble(reader)?;
+    // Points are written with as many coordinates as Legion was built for
+    let mut max_dim = 0;
+    let mut id = [0; 4];
+    while read_or_eof(reader, &mut id)? {
+        let id = u32::from_le_bytes(id);
+        let format = formats
+            .get(&id)
+            .ok_or_else(|| invalid(format!("record has unknown ID {id}")))?;
+        let values = format
+            .fields
+            .iter()
+            .map(|field| Ok((field.name.as_str(), read_value(reader, field, max_dim)?)))
+            .collect::<io::Result<Vec<_>>>()?;
+        let record = Record {
+            name: &format.name,
+            values,
+        };
+        if record.name == "MaxDimDesc" {
+            max_dim = record.int("max_dim").unwrap_or(0);
+        }
+        f(record)?;
+    }
+    Ok(())
+}
+
+// Realm packs the node into bits 40..56 of processor and memory IDs
+fn node_of(id: u64) -> u64 {
+    (id >> 40) & 0xffff
+}
+
+fn index_in_node(id: u64) -> u64 {
+    id & 0xfff
+}
+
+// Realm's Processor::Kind
+fn proc_kind_name(kind: u64) -> &'static str {
+    match kind {
+        1 => "GPU",
+        2 => "CPU",
+        3 => "Utility",
+        4 => "I/O",
+        5 => "Proc Group",
+        6 => "Proc Set",
+        7 => "OpenMP",
+        8 => "Python",
+        _ => "Unknown",
+    }
+}
+
+// CPUs first, then GPUs and utility processors, then the rest
+fn proc_kind_order(kind: u64) -> u64 {
+    match kind {
+        2 => 0,
+        1 => 1,
+        3 => 2,
+        k => k,
+    }
+}
+
+// Realm's Memory::Kind
+fn mem_kind_name(kind: u64) -> &'static str {
+    match kind {
+        1 => "Global",
+        2 => "System",
+        3 => "Registered",
+        4 => "Socket",
+        5 => "Zero-Copy",
+        6 => "Framebuffer",
+        7 => "Disk",
+        8 => "HDF5",
+        9 => "File",
+        10 => "L3 Cache",
+        11 => "L2 Cache",
+        12 => "L1 Cache",
+        13 => "GPU Managed",
+        14 => "GPU Dynamic",
+        _ => "Unknown",
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Timeline {
+    Proc(u64),
+    Mem(u64),
+}
+
+#[derive(Debug, Clone)]
+struct Span {
+    timeline: Timeline,
+    interval: Interval,
+    title: String,
+    fields: Vec<(FieldID, Field)>,
+}
+
+struct Fields {
+    operation: FieldID,
+    variant: FieldID,
+    provenance: FieldID,
+    size: FieldID,
+}
+
+// Everything read from the logs of a run, one log per node
+#[derive(Default)]
+struct Profile {
+    procs: BTreeMap<u64, u64>,
+    mems: BTreeMap<u64, u64>,
+    task_names: BTreeMap<u64, String>,
+    variant_names: BTreeMap<(u64, u64), String>,
+    meta_names: BTreeMap<u64, String>,
+    op_names: BTreeMap<u64, String>,
+    // Kind and provenance of each operation
+    operations: BTreeMap<u64, (u64, String)>,
+    tasks: Vec<(u64, u64, u64, u64, Interval)>,
+    metas: Vec<(u64, u64, u64, Interval)>,
+    prof_tasks: Vec<(u64, u64, Interval)>,
+    instances: Vec<(u64, u64, u64, Interval)>,
+}
+
+impl Profile {
+    fn add(&mut self, record: Record<'_>) -> io::Result<()> {
+        let missing = || invalid(format!("{} record is missing fields", record.name));
+        let interval = |start: &str, stop: &str| -> io::Result<Interval> {
+            let start = record.time(start).ok_or_else(missing)?;
+            let stop = record.time(stop).ok_or_else(missing)?;
+            Ok(Interval::new(start, stop.max(start)))
+        };
+        let int = |name: &str| record.int(name).ok_or_else(missing);
+        let string = |name: &str| record.string(name).map(str::to_owned).ok_or_else(missing);
+        match record.name {
+            "ProcDesc" => {
+                self.procs.insert(int("proc_id")?, int("kind")?);
+            }
+            "MemDesc" => {
+                self.mems.insert(int("mem_id")?, int("kind")?);
+            }
+            "TaskKind" => {
+                // Later names only replace earlier ones when asked to
+                let task_id = int("task_id")?;
+                let overwrite = record.int("overwrite").unwrap_or(0) != 0;
+                if overwrite || !self.task_names.contains_key(&task_id) {
+                    self.task_names.insert(task_id, string("name")?);
+                }
+            }
+            "TaskVariant" => {
+                let key = (int("task_id")?, int("variant_id")?);
+                self.variant_names.insert(key, string("name")?);
+            }
+            "MetaDesc" => {
+                self.meta_names.insert(int("kind")?, string("name")?);
+            }
+            "OpDesc" => {
+                self.op_names.insert(int("kind")?, string("name")?);
+            }
+            "OperationInstance" => {
+                let provenance = record.string("provenance").unwrap_or("").to_owned();
+                self.operations
+                    .insert(int("op_id")?, (int("kind")?, provenance));
+            }
+            // GPU tasks are shown for as long as they hold the processor,
+            // like any other task
+            "TaskInfo" | "GPUTaskInfo" => {
+                self.tasks.push((
+                    int("proc_id")?,
+                    int("op_id")?,
+                    int("task_id")?,
+                    int("variant_id")?,
+                    interval("start", "stop")?,
+                ));
+            }
+            "MetaInfo" | "MessageInfo" => {
+                self.metas.push((
+                    int("proc_id")?,
+                    int("op_id")?,
+                    int("lg_id")?,
+                    interval("start", "stop")?,
+                ));
+            }
+            "ProfTaskInfo" => {
+                self.prof_tasks
+                    .push((int("proc_id")?, int("op_id")?, interval("start", "stop")?));
+            }
+            "InstTimelineInfo" => {
+                self.instances.push((
+                    int("mem_id")?,
+                    int("op_id")?,
+                    int("size")?,
+                    interval("create", "destroy")?,
+                ));
+            }
+            _ => (),
+        }
+        Ok(())
+    }
+
+    fn spans(&self, fields: &Fields) -> Vec<Span> {
+        let operation = |op_id: u64| (fields.operation, Field::U64(op_id));
+        let mut spans = Vec::new();
+        for &(proc_id, op_id, task_id, variant_id, interval) in &self.tasks {
+            let title = match self.task_names.get(&task_id) {
+                Some(name) => name.clone(),
+                None => format!("Task {task_id}"),
+            };
+            let mut item_fields = vec![operation(op_id)];
+            if let Some(variant) = self.variant_names.get(&(task_id, variant_id)) {
+                item_fields.push((fields.variant, Field::String(variant.clone())));
+            }
+            if let Some((_, provenance)) = self.operations.get(&op_id) {
+                if !provenance.is_empty() {
+                    item_fields.push((fields.provenance, Field::String(provenance.clone())));
+                }
+            }
+            spans.push(Span {
+                timeline: Timeline::Proc(proc_id),
+                interval,
+                title,
+                fields: item_fields,
+            });
+        }
+        for &(proc_id, op_id, lg_id, interval) in &self.metas {
+            let title = match self.meta_names.get(&lg_id) {
+                Some(name) => name.clone(),
+                None => format!("Meta {lg_id}"),
+            };
+            spans.push(Span {
+                timeline: Timeline::Proc(proc_id),
+                interval,
+                title,
+                fields: vec![operation(op_id)],
+            });
+        }
+        for &(proc_id, op_id, interval) in &self.prof_tasks {
+            spans.push(Span {
+                timeline: Timeline::Proc(proc_id),
+                interval,
+                title: "ProfTask".to_owned(),
+                fields: vec![operation(op_id)],
+            });
+        }
+        for &(mem_id, op_id, size, interval) in &self.instances {
+            let kind = self.operations.get(&op_id).map(|(kind, _)| kind);
+            let title = match kind.and_then(|kind| self.op_names.get(kind)) {
+                Some(name) => format!("Instance of {name}"),
+                None => "Instance".to_owned(),
+            };
+            spans.push(Span {
+                timeline: Timeline::Mem(mem_id),
+                interval,
+                title,
+                fields: vec![operation(op_id), (fields.size, Field::U64(size))],
+            });
+        }
+        spans
+    }
+
+    // Nodes, then processors by kind, then memories, each in order
+    fn threads(&self, builder: &mut TraceBuilder) -> BTreeMap<Timeline, ThreadID> {
+        let mut timelines: Vec<_> = self
+            .procs
+            .iter()
+            .map(|(&proc_id, &kind)| {
+                let key = (node_of(proc_id), 0, proc_kind_order(kind), proc_id);
+                let kind = proc_kind_name(kind);
+                let name = format!("{kind} {}", index_in_node(proc_id));
+                (key, Timeline::Proc(proc_id), kind.to_owned(), name)
+            })
+            .chain(self.mems.iter().map(|(&mem_id, &kind)| {
+                let key = (node_of(mem_id), 1, kind, mem_id);
+                let name = format!("{} {}", mem_kind_name(kind), index_in_node(mem_id));
+                (key, Timeline::Mem(mem_id), "Memory".to_owned(), name)
+            }))
+            .collect();
+        timelines.sort_by_key(|(key, ..)| *key);
+        timelines
+            .into_iter()
+            .map(|((node, ..), timeline, kind, name)| {
+                let thread = builder.thread(&format!("Node {node}"), &kind, &name);
+                (timeline, thread)
+            })
+            .collect()
+    }
+}
+
+// Loads Legion Prof's binary logs (prof_*.gz, one per node) into memory.
+// Each node gets a panel, with a kind per processor kind plus one for
+// memories. Tasks, meta-tasks and profiling tasks are shown on processors
+// and instances on memories; copies, fills and the other channels are left
+// to legion_prof.
+pub fn load_legion_prof_logs<P: AsRef<Path>>(paths: &[P]) -> io::Result<TraceDataSource> {
+    let mut profile = Profile::default();
+    for path in paths {
+        let path = path.as_ref();
+        let f = File::open(path)?;
+        let mut reader = BufReader::new(MultiGzDecoder::new(BufReader::new(f)));
+        read_records(&mut reader, |record| profile.add(record))
+            .map_err(|e| invalid(format!("{}: {e}", path.display())))?;
+    }
+    let source_locator = match paths {
+        [path] => path.as_ref().to_string_lossy().into_owned(),
+        _ => format!("{} Legion Prof logs", paths.len()),
+    };
+    Ok(build(source_locator, &profile))
+}
+
+fn build(source_locator: String, profile: &Profile) -> TraceDataSource {
+    let mut builder = TraceBuilder::new(source_locator);
+    let fields = Fields {
+        operation: builder.field("Operation", true),
+        variant: builder.field("Variant", true),
+        provenance: builder.field("Provenance", true),
+        size: builder.field("Size (bytes)", false),
+    };
+    let threads = profile.threads(&mut builder);
+    for span in profile.spans(&fields) {
+        // Items on processors or memories that were never described can't
+        // be placed
+        let Some(thread) = threads.get(&span.timeline) else {
+            continue;
+        };
+        builder.add_item(
+            *thread,
+            TraceItem {
+                interval: span.interval,
+                title: span.title,
+                color: None,
+                fields: span.fields,
+            },
+        );
+    }
+    builder.build()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::Write;
+
+    use flate2::Compression;
+    use flate2::write::GzEncoder;
+
+    use crate::data::{DataSource, EntryID, EntryInfo, TileID};
+
+    const PREAMBLE: &str = "\
+FileType: BinaryLegionProf v: 1.0
+MaxDimDesc {id:0, max_dim:maxdim_t:4}
+ProcDesc {id:1, proc_id:ProcID:8, kind:ProcKind:4}
+MemDesc {id:2, mem_id:MemID:8, kind:MemKind:4, capacity:unsigned long long:8}
+TaskKind {id:3, task_id:TaskID:4, name:string:-1, overwrite:bool:1}
+TaskVariant {id:4, task_id:TaskID:4, variant_id:VariantID:4, name:string:-1}
+MetaDesc {id:5, kind:unsigned:4, message:bool:1, ordered_vc:bool:1, name:string:-1}
+IndexSpacePointDesc {id:6, unique_id:IDType:8, dim:unsigned:4, rem:array:-1}
+TaskInfo {id:7, op_id:UniqueID:8, task_id:TaskID:4, variant_id:VariantID:4, proc_id:ProcID:8, create:timestamp_t:8, ready:timestamp_t:8, start:timestamp_t:8, stop:timestamp_t:8}
+MetaInfo {id:8, op_id:UniqueID:8, lg_id:unsigned:4, proc_id:ProcID:8, create:timestamp_t:8, ready:timestamp_t:8, start:timestamp_t:8, stop:timestamp_t:8}
+InstTimelineInfo {id:9, inst_uid:unsigned long long:8, inst_id:InstID:8, mem_id:MemID:8, size:unsigned long long:8, op_id:UniqueID:8, create:timestamp_t:8, ready:timestamp_t:8, destroy:timestamp_t:8}
+
+";
+
+    const NODE1_CPU: u64 = (1 << 40) | 3;
+    const NODE0_GPU: u64 = 1;
+    const NODE0_SYSMEM: u64 = (2 << 60) | 0x10;
+
+    // A small log, in the layout written by Legion's profiling serializer
+    fn fixture() -> Vec<u8> {
+        let mut log = PREAMBLE.as_bytes().to_vec();
+        let mut record = |id: u32, fields: &[&[u8]]| {
+            log.extend(id.to_le_bytes());
+            for field in fields {
+                log.extend(*field);
+            }
+        };
+        record(0, &[&3u32.to_le_bytes()]);
+        record(1, &[&NODE1_CPU.to_le_bytes(), &2u32.to_le_bytes()]);
+        record(1, &[&NODE0_GPU.to_le_bytes(), &1u32.to_le_bytes()]);
+        record(
+            2,
+            &[
+                &NODE0_SYSMEM.to_le_bytes(),
+                &2u32.to_le_bytes(),
+                &1024u64.to_le_bytes(),
+            ],
+        );
+        record(3, &[&7u32.to_le_bytes(), b"main_task\0", &[0]]);
+        record(4, &[&7u32.to_le_bytes(), &1u32.to_le_bytes(), b"cpu\0"]);
+        record(5, &[&2u32.to_le_bytes(), &[0], &[0], b"Mapper Call\0"]);
+        // Points are sized by the MaxDimDesc that came before
+        record(6, &[&1u64.to_le_bytes(), &1u32.to_le_bytes(), &[0; 3 * 8]]);
+        let times = |t: [u64; 4]| t.map(u64::to_le_bytes);
+        let t = times([0, 0, 100, 300]);
+        record(
+            7,
+            &[
+                &42u64.to_le_bytes(),
+                &7u32.to_le_bytes(),
+                &1u32.to_le_bytes(),
+                &NODE1_CPU.to_le_bytes(),
+                &t[0],
+                &t[1],
+                &t[2],
+                &t[3],
+            ],
+        );
+        let t = times([0, 0, 150, 250]);
+        record(
+            8,
+            &[
+                &42u64.to_le_bytes(),
+                &2u32.to_le_bytes(),
+                &NODE0_GPU.to_le_bytes(),
+                &t[0],
+                &t[1],
+                &t[2],
+                &t[3],
+            ],
+        );
+        let t = times([50, 60, 400, 0]);
+        record(
+            9,
+            &[
+                &1u64.to_le_bytes(),
+                &1u64.to_le_bytes(),
+                &NODE0_SYSMEM.to_le_bytes(),
+                &512u64.to_le_bytes(),
+                &42u64.to_le_bytes(),
+                &t[0],
+                &t[1],
+                &t[2],
+            ],
+        );
+        log
+    }
+
+    fn titles(ds: &TraceDataSource, entry_id: &EntryID) -> Vec<(String, Interval)> {
+        let info = ds.fetch_info();
+        let meta = ds.fetch_slot_meta_tile(entry_id, TileID(info.interval), true);
+        meta.data
+            .items
+            .iter()
+            .flatten()
+            .map(|item| (item.title.clone(), item.original_interval))
+            .collect()
+    }
+
+    fn interval(start: i64, stop: i64) -> Interval {
+        Interval::new(Timestamp(start), Timestamp(stop))
+    }
+
+    #[test]
+    fn test_legion_prof_log() {
+        let path = std::env::temp_dir().join(format!("lpv_prof_{}_0.gz", std::process::id()));
+        let mut encoder = GzEncoder::new(File::create(&path).unwrap(), Compression::fast());
+        encoder.write_all(&fixture()).unwrap();
+        encoder.finish().unwrap();
+        let ds = load_legion_prof_logs(&[&path]).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let info = ds.fetch_info();
+        let node = |n: u64| match info.entry_info.get(&EntryID::root().child(n)) {
+            Some(EntryInfo::Panel { long_name, .. }) => long_name.clone(),
+            _ => panic!("expected a panel"),
+        };
+        assert_eq!(node(0), "Node 0");
+        assert_eq!(node(1), "Node 1");
+
+        // Node 0 has a GPU and a memory, node 1 a CPU
+        let gpu = EntryID::root().child(0).child(0).child(0);
+        let mem = EntryID::root().child(0).child(1).child(0);
+        let cpu = EntryID::root().child(1).child(0).child(0);
+        let Some(EntryInfo::Slot { short_name, .. }) = info.entry_info.get(&cpu) else {
+            panic!("expected a slot");
+        };
+        assert_eq!(short_name, "CPU 3");
+        assert_eq!(
+            titles(&ds, &cpu),
+            vec![("main_task".to_owned(), interval(100, 300))]
+        );
+        assert_eq!(
+            titles(&ds, &gpu),
+            vec![("Mapper Call".to_owned(), interval(150, 250))]
+        );
+        assert_eq!(
+            titles(&ds, &mem),
+            vec![("Instance".to_owned(), interval(50, 400))]
+        );
+    }
+
+    #[test]
+    fn test_not_a_log() {
+        let mut reader = "Prof Proc Desc 1 2\n".as_bytes();
+        let e = read_records(&mut reader, |_| Ok(())).unwrap_err();
+        assert!(
+            e.to_string().contains("not a binary Legion Prof log"),
+            "{e}"
+        );
+    }
+}
@@ -3,14 +3,54 @@
 pub mod app;
 #[cfg(not(target_arch = "wasm32"))]
 pub mod archive_data;
+pub mod async_data;
+#[cfg(all(feature = "chrome", not(target_arch = "wasm32")))]
+pub mod chrome_data;
 pub mod data;
+pub mod dedup_data;
 pub mod deferred_data;
+pub mod downsample_data;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod export;
 #[cfg(not(target_arch = "wasm32"))]
 pub mod file_data;
+pub mod filter_data;
 pub mod http;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod legion_log_data;
+pub mod live_data;
 pub mod merge_data;
+pub mod metrics_data;
+#[cfg(all(feature = "nsys", not(target_arch = "wasm32")))]
+pub mod nsys_data;
 #[cfg(feature = "nvtxw")]
 pub mod nvtxw;
+#[cfg(all(feature = "client", not(target_arch = "wasm32")))]
+pub mod open_data;
 #[cfg(not(target_arch = "wasm32"))]
 pub mod parallel_data;
+#[cfg(all(feature = "parquet", not(target_arch = "wasm32")))]
+pub mod parquet_data;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod perf_data;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod perfetto_data;
+pub mod random_data;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod replay_data;
+pub mod retry_data;
+pub mod stats;
+#[cfg(test)]
+mod test_data;
+pub mod throttle_data;
+pub mod timeout_data;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod timer;
 pub mod timestamp;
+pub mod trace_data;
+pub mod transform_data;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod user_config;
+pub mod version;
+#[cfg(all(feature = "bundle", not(target_arch = "wasm32")))]
+pub mod zip_data;
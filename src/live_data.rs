@@ -0,0 +1,289 @@
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::data::{
+    DataSource, DataSourceDescription, DataSourceInfo, EntryID, EntryInfo, Item, ItemMeta,
+    SlotMetaTile, SlotMetaTileData, SlotTile, SlotTileData, SummaryTile, SummaryTileData, TileID,
+    UtilPoint,
+};
+use crate::timestamp::Interval;
+use crate::trace_data::{slot_item_metas, slot_items, summary_points};
+
+// How often the viewer picks up newly arrived items. Tiles are cut from
+// memory, so this can be much more frequent than polling a server.
+const REFRESH_INTERVAL: Duration = Duration::from_millis(200);
+
+// Messages pushed by a server streaming a profile as it is being recorded
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub enum LiveMessage {
+    // The layout of the profile (entries, fields, etc.). Sent first, and
+    // again whenever it changes.
+    Info(Box<DataSourceInfo>),
+    // Items that were added to a slot, each in the row it should be drawn in
+    Items {
+        entry_id: EntryID,
+        items: Vec<LiveItem>,
+    },
+    // Points that were added to a summary
+    Utilization {
+        entry_id: EntryID,
+        points: Vec<UtilPoint>,
+    },
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct LiveItem {
+    pub row: u64,
+    pub item: Item,
+    pub meta: ItemMeta,
+}
+
+#[derive(Default)]
+struct Slot {
+    items: Vec<Vec<Item>>,
+    item_metas: Vec<Vec<ItemMeta>>,
+}
+
+struct LiveState {
+    source_locator: String,
+    info: DataSourceInfo,
+    // Covers everything received so far, which may run past the info
+    interval: Interval,
+    slots: BTreeMap<EntryID, Slot>,
+    summaries: BTreeMap<EntryID, Vec<UtilPoint>>,
+    // Why the stream ended, if it has
+    closed: Option<String>,
+}
+
+// Keeps every message received from a live stream in memory. The info grows
+// to cover new items as they arrive, which prompts the viewer to fetch its
+// tiles again.
+#[derive(Clone)]
+pub struct LiveDataSource {
+    state: Arc<Mutex<LiveState>>,
+}
+
+// Keeps a row sorted by start time, given that items usually arrive in order
+fn insert_sorted<T>(row: &mut Vec<T>, value: T, start: impl Fn(&T) -> i64) {
+    let index = row.partition_point(|x| start(x) <= start(&value));
+    row.insert(index, value);
+}
+
+impl LiveDataSource {
+    pub fn new(source_locator: impl Into<String>, info: DataSourceInfo) -> Self {
+        Self {
+            state: Arc::new(Mutex::new(LiveState {
+                source_locator: source_locator.into(),
+                interval: info.interval,
+                info,
+                slots: BTreeMap::new(),
+                summaries: BTreeMap::new(),
+                closed: None,
+            })),
+        }
+    }
+
+    pub fn push(&self, message: LiveMessage) {
+        let mut state = self.state.lock().unwrap();
+        match message {
+            LiveMessage::Info(info) => {
+                state.interval = state.interval.union(info.interval);
+                state.info = *info;
+            }
+            LiveMessage::Items { entry_id, items } => {
+                let mut interval = state.interval;
+                let slot = state.slots.entry(entry_id).or_default();
+                for LiveItem { row, item, meta } in items {
+                    let row = row as usize;
+                    if slot.items.len() <= row {
+                        slot.items.resize_with(row + 1, Vec::new);
+                        slot.item_metas.resize_with(row + 1, Vec::new);
+                    }
+                    interval = interval.union(meta.original_interval);
+                    insert_sorted(&mut slot.items[row], item, |x| x.interval.start.0);
+                    insert_sorted(&mut slot.item_metas[row], meta, |x| {
+                        x.original_interval.start.0
+                    });
+                }
+                state.interval = interval;
+            }
+            LiveMessage::Utilization { entry_id, points } => {
+                let utilization = state.summaries.entry(entry_id).or_default();
+                for point in points {
+                    insert_sorted(utilization, point, |x| x.time.0);
+                }
+            }
+        }
+    }
+
+    // Records why the stream ended. Everything received so far remains.
+    pub fn close(&self, reason: impl Into<String>) {
+        self.state.lock().unwrap().closed = Some(reason.into());
+    }
+}
+
+impl DataSource for LiveDataSource {
+    fn fetch_description(&self) -> DataSourceDescription {
+        let state = self.state.lock().unwrap();
+        DataSourceDescription {
+            source_locator: vec![state.source_locator.clone()],
+        }
+    }
+
+    fn fetch_info(&self) -> DataSourceInfo {
+        let state = self.state.lock().unwrap();
+        let mut info = state.info.clone();
+        info.interval = state.interval;
+        // Slots may have grown rows since the server last sent its info
+        for (entry_id, slot) in &state.slots {
+            if let Some(EntryInfo::Slot { max_rows, .. }) = info.entry_info.get_mut(entry_id) {
+                *max_rows = (*max_rows).max(slot.items.len() as u64);
+            }
+        }
+        match &state.closed {
+            Some(reason) => info.warning_message = Some(reason.clone()),
+            None => {
+                info.capabilities.live_updates = true;
+                info.refresh_interval = Some(REFRESH_INTERVAL);
+            }
+        }
+        info
+    }
+
+    fn fetch_summary_tile(&self, entry_id: &EntryID, tile_id: TileID, _full: bool) -> SummaryTile {
+        let state = self.state.lock().unwrap();
+        let utilization = state
+            .summaries
+            .get(entry_id)
+            .map(|utilization| summary_points(utilization, tile_id))
+            .unwrap_or_default();
+        SummaryTile {
+            entry_id: entry_id.clone(),
+            tile_id,
+            data: SummaryTileData { utilization },
+        }
+    }
+
+    fn fetch_slot_tile(&self, entry_id: &EntryID, tile_id: TileID, _full: bool) -> SlotTile {
+        let state = self.state.lock().unwrap();
+        let items = state
+            .slots
+            .get(entry_id)
+            .map(|slot| slot_items(&slot.items, tile_id))
+            .unwrap_or_default();
+        SlotTile {
+            entry_id: entry_id.clone(),
+            tile_id,
+            data: SlotTileData { items },
+        }
+    }
+
+    fn fetch_slot_meta_tile(
+        &self,
+        entry_id: &EntryID,
+        tile_id: TileID,
+        _full: bool,
+    ) -> SlotMetaTile {
+        let state = self.state.lock().unwrap();
+        let items = state
+            .slots
+            .get(entry_id)
+            .map(|slot| slot_item_metas(&slot.item_metas, tile_id))
+            .unwrap_or_default();
+        SlotMetaTile {
+            entry_id: entry_id.clone(),
+            tile_id,
+            data: SlotMetaTileData { items },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use egui::Color32;
+
+    use crate::data::{Capabilities, FieldSchema, ItemUID, PROTOCOL_VERSION, TileSet};
+    use crate::timestamp::Timestamp;
+
+    fn live_item(row: u64, uid: u64, start: i64, stop: i64) -> LiveItem {
+        let interval = Interval::new(Timestamp(start), Timestamp(stop));
+        LiveItem {
+            row,
+            item: Item {
+                item_uid: ItemUID(uid),
+                interval,
+                color: Color32::RED,
+            },
+            meta: ItemMeta {
+                item_uid: ItemUID(uid),
+                original_interval: interval,
+                title: format!("item {uid}"),
+                fields: Vec::new(),
+            },
+        }
+    }
+
+    #[test]
+    fn test_live_items() {
+        let slot = |max_rows| EntryInfo::Slot {
+            short_name: "s".to_owned(),
+            long_name: "slot".to_owned(),
+            max_rows,
+        };
+        let panel = |slots| EntryInfo::Panel {
+            short_name: "p".to_owned(),
+            long_name: "panel".to_owned(),
+            summary: None,
+            slots,
+        };
+        let info = DataSourceInfo {
+            entry_info: panel(vec![panel(vec![panel(vec![slot(1)])])]),
+            interval: Interval::new(Timestamp(0), Timestamp(10)),
+            tile_set: TileSet::default(),
+            field_schema: FieldSchema::new(),
+            warning_message: None,
+            refresh_interval: None,
+            version: PROTOCOL_VERSION,
+            capabilities: Capabilities::default(),
+            entry_tile_sets: BTreeMap::new(),
+        };
+        let ds = LiveDataSource::new("ws://localhost/", info);
+        let info = ds.fetch_info();
+        assert!(info.capabilities.live_updates);
+        assert_eq!(info.refresh_interval, Some(REFRESH_INTERVAL));
+
+        let entry_id = EntryID::root().child(0).child(0).child(0);
+        ds.push(LiveMessage::Items {
+            entry_id: entry_id.clone(),
+            items: vec![live_item(0, 2, 20, 30), live_item(1, 3, 25, 28)],
+        });
+        // A late arrival still ends up in order
+        ds.push(LiveMessage::Items {
+            entry_id: entry_id.clone(),
+            items: vec![live_item(0, 1, 5, 15)],
+        });
+
+        let info = ds.fetch_info();
+        assert_eq!(info.interval, Interval::new(Timestamp(0), Timestamp(30)));
+        let Some(EntryInfo::Slot { max_rows, .. }) = info.entry_info.get(&entry_id) else {
+            panic!("expected a slot");
+        };
+        assert_eq!(*max_rows, 2);
+
+        let tile_id = TileID(Interval::new(Timestamp(10), Timestamp(22)));
+        let tile = ds.fetch_slot_tile(&entry_id, tile_id, false);
+        let uids: Vec<_> = tile.data.items[0].iter().map(|x| x.item_uid.0).collect();
+        assert_eq!(uids, vec![1, 2]);
+        assert!(tile.data.items[1].is_empty());
+
+        ds.close("stream ended");
+        let info = ds.fetch_info();
+        assert_eq!(info.refresh_interval, None);
+        assert_eq!(info.warning_message.as_deref(), Some("stream ended"));
+    }
+}
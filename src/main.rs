@@ -1,23 +1,922 @@
 #![warn(clippy::all, rust_2018_idioms)]
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")] // hide console window on Windows in release
 
+#[cfg(not(target_arch = "wasm32"))]
+use std::fmt::Display;
+#[cfg(not(target_arch = "wasm32"))]
+use std::path::PathBuf;
+#[cfg(not(target_arch = "wasm32"))]
+use std::sync::LazyLock;
+use std::time::Duration;
+
+#[cfg(not(target_arch = "wasm32"))]
+use clap::error::ErrorKind;
+#[cfg(not(target_arch = "wasm32"))]
+use clap::{ArgAction, Args, CommandFactory, Parser, Subcommand, ValueEnum};
+
+use legion_prof_viewer::app::AppSettings;
+#[cfg(not(target_arch = "wasm32"))]
+use legion_prof_viewer::app::StartView;
 use legion_prof_viewer::deferred_data::DeferredDataSource;
+#[cfg(not(target_arch = "wasm32"))]
+use legion_prof_viewer::export::{ExportOption, ExportOptions};
+use legion_prof_viewer::http::auth::Credentials;
+#[cfg(target_arch = "wasm32")]
 use legion_prof_viewer::http::client::HTTPClientDataSource;
+#[cfg(not(target_arch = "wasm32"))]
+use legion_prof_viewer::http::client::PROXY_VARS;
+use legion_prof_viewer::http::client::{ClientConfig, HTTPProfileIndex};
+use legion_prof_viewer::merge_data::MergeDeferredDataSource;
+#[cfg(not(target_arch = "wasm32"))]
+use legion_prof_viewer::open_data::Locator;
+#[cfg(not(target_arch = "wasm32"))]
+use legion_prof_viewer::random_data::RandomConfig;
+use legion_prof_viewer::timestamp::{Interval, Timestamp};
+#[cfg(not(target_arch = "wasm32"))]
+use legion_prof_viewer::user_config::{UserConfig, seconds};
 
 use url::Url;
 
-fn http_ds(url: Url) -> Box<dyn DeferredDataSource> {
-    Box::new(HTTPClientDataSource::new(url))
+#[cfg(target_arch = "wasm32")]
+fn http_ds(url: Url, config: &ClientConfig) -> Result<Box<dyn DeferredDataSource>, String> {
+    let ds = HTTPClientDataSource::with_config(url, config.clone())
+        .map_err(|e| format!("unable to configure HTTP client: {e}"))?;
+    Ok(Box::new(ds))
+}
+
+// A server hosting several profiles, listed at its index endpoint
+#[cfg(target_arch = "wasm32")]
+fn index_start(url: Url, config: &ClientConfig, settings: AppSettings) -> Result<(), String> {
+    let index = HTTPProfileIndex::with_config(url, config.clone())
+        .map_err(|e| format!("unable to configure HTTP client: {e}"))?;
+    legion_prof_viewer::app::start_with_settings(Vec::new(), Some(Box::new(index)), settings);
+    Ok(())
+}
+
+// Show the sources as a single profile, with the nodes of each source side by
+// side, rather than as separate profiles one above the other
+fn merge_ds(ds: Vec<Box<dyn DeferredDataSource>>) -> Vec<Box<dyn DeferredDataSource>> {
+    if ds.len() < 2 {
+        return ds;
+    }
+    vec![Box::new(MergeDeferredDataSource::new(ds))]
 }
 
 #[cfg(not(target_arch = "wasm32"))]
-fn main() {
-    let ds: Vec<_> = std::env::args()
-        .skip(1)
-        .map(|arg| http_ds(Url::parse(&arg).expect("unable to parse URL")))
-        .collect();
+fn demo_ds(config: RandomConfig) -> Box<dyn DeferredDataSource> {
+    use legion_prof_viewer::parallel_data::ParallelDeferredDataSource;
+    use legion_prof_viewer::random_data::RandomDataSource;
+
+    Box::new(ParallelDeferredDataSource::new(RandomDataSource::new(
+        config,
+    )))
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn remote_profile(arg: &str) -> Result<Locator, String> {
+    let profile: Locator = arg.parse()?;
+    if !profile.is_remote() {
+        return Err(format!(
+            "{arg} isn't a URL (use view to open local profiles)"
+        ));
+    }
+    Ok(profile)
+}
+
+// Errors that aren't the command line's fault (e.g., a profile that can't be
+// read) are reported without a backtrace, like any other command line tool
+// would
+#[cfg(not(target_arch = "wasm32"))]
+fn fail(message: impl Display) -> ! {
+    eprintln!("error: {message}");
+    std::process::exit(1);
+}
+
+// Logs go to stderr (or the log file) at the level given by the flags, which
+// only applies to the viewer's own logs. RUST_LOG still works on top of that,
+// e.g., to see the HTTP client's.
+#[cfg(not(target_arch = "wasm32"))]
+fn init_logging(cli: &Cli) {
+    use env_logger::{Target, WriteStyle};
+    use log::LevelFilter;
+
+    let verbose = match cli.verbose {
+        0 if cli.log_file.is_some() => 1,
+        verbose => verbose,
+    };
+    let level = match verbose {
+        _ if cli.quiet => LevelFilter::Error,
+        0 => LevelFilter::Warn,
+        1 => LevelFilter::Info,
+        2 => LevelFilter::Debug,
+        _ => LevelFilter::Trace,
+    };
+    let mut builder = env_logger::Builder::new();
+    builder
+        .filter_level(level.min(LevelFilter::Warn))
+        .filter_module("legion_prof_viewer", level);
+    if let Ok(filters) = std::env::var("RUST_LOG") {
+        builder.parse_filters(&filters);
+    }
+    if let Some(path) = &cli.log_file {
+        let file = std::fs::File::create(path)
+            .unwrap_or_else(|e| fail(format!("unable to create {}: {e}", path.display())));
+        builder
+            .target(Target::Pipe(Box::new(file)))
+            .write_style(WriteStyle::Never);
+    }
+    builder.init();
+    log::info!(
+        "{} {}: {}",
+        env!("CARGO_PKG_NAME"),
+        env!("CARGO_PKG_VERSION"),
+        std::env::args().collect::<Vec<_>>().join(" ")
+    );
+}
+
+// Bad arguments that only show up once they're put together, reported the
+// same way clap reports the rest, with the usage of the subcommand
+#[cfg(not(target_arch = "wasm32"))]
+fn usage_error(command: &str, kind: ErrorKind, message: impl Display) -> ! {
+    let mut cli = Cli::command();
+    cli.build();
+    let command = cli
+        .find_subcommand_mut(command)
+        .expect("usage_error requires a subcommand");
+    command.error(kind, message).exit()
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn cookie_arg(cookie: &str) -> Result<(String, String), String> {
+    let (name, value) = cookie
+        .split_once('=')
+        .ok_or_else(|| format!("invalid cookie {cookie:?}, expected NAME=VALUE"))?;
+    Ok((name.trim().to_owned(), value.trim().to_owned()))
+}
+
+// Headers are given the same way as to curl, e.g., "X-API-Key: secret"
+#[cfg(not(target_arch = "wasm32"))]
+fn header_arg(header: &str) -> Result<(String, String), String> {
+    let (name, value) = header
+        .split_once(':')
+        .ok_or_else(|| format!("invalid header {header:?}, expected NAME: VALUE"))?;
+    Ok((name.trim().to_owned(), value.trim().to_owned()))
+}
+
+// An interval given as START..STOP, where each has a unit, e.g., 1.5s..2s
+fn interval_arg(interval: &str) -> Result<Interval, String> {
+    let (start, stop) = interval
+        .split_once("..")
+        .ok_or_else(|| format!("invalid interval {interval:?}, expected START..STOP"))?;
+    let time = |time: &str| {
+        Timestamp::parse(time)
+            .map_err(|_| format!("invalid time {time:?}, expected a number and unit, e.g., 10ms"))
+    };
+    let (start, stop) = (time(start)?, time(stop)?);
+    if start >= stop {
+        return Err(format!("interval {interval:?} ends before it starts"));
+    }
+    Ok(Interval::new(start, stop))
+}
+
+// The protocol and features too, to tell mismatched viewers and servers apart
+#[cfg(not(target_arch = "wasm32"))]
+static LONG_VERSION: LazyLock<String> = LazyLock::new(legion_prof_viewer::version::describe);
+
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Parser)]
+#[command(
+    version,
+    long_version = LONG_VERSION.as_str(),
+    about = "Viewer for Legion Prof profiles",
+    args_conflicts_with_subcommands = true
+)]
+struct Cli {
+    /// Read settings from PATH instead of the default config.toml
+    #[arg(long, global = true, value_name = "PATH")]
+    config: Option<PathBuf>,
+    /// Log more: -v for each request, -vv for cache decisions, -vvv for every
+    /// tile
+    #[arg(short, long, global = true, action = ArgAction::Count)]
+    verbose: u8,
+    /// Only log errors
+    #[arg(short, long, global = true, conflicts_with = "verbose")]
+    quiet: bool,
+    /// Write the log to PATH instead of stderr (at -v unless given otherwise),
+    /// e.g., to attach to a bug report
+    #[arg(long, global = true, value_name = "PATH")]
+    log_file: Option<PathBuf>,
+    #[command(subcommand)]
+    command: Option<Command>,
+    // Without a subcommand, the profiles are viewed
+    #[command(flatten)]
+    view: ViewerArgs,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Subcommand)]
+enum Command {
+    /// View profiles (the default, without a subcommand)
+    View(ViewerArgs),
+    /// View profiles served by a server, or choose one from its index
+    Attach(AttachArgs),
+    /// Serve a local profile to other viewers over HTTP
+    Serve(ServeArgs),
+    /// Write a profile to a file in another format
+    Export(ExportArgs),
+    /// Write every tile of a profile, to serve with any static web server
+    Archive(ArchiveArgs),
+    /// Print how busy each entry is and the biggest items, without the viewer
+    Stats(StatsArgs),
+}
+
+// How to reach servers, for any profile given by URL
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Args)]
+#[command(next_help_heading = "Connection options")]
+struct ClientArgs {
+    /// Bearer token to send (or set LEGION_PROF_TOKEN)
+    #[arg(long, group = "credentials")]
+    token: Option<String>,
+    /// Read the bearer token from PATH, again whenever it is rejected (or set
+    /// LEGION_PROF_TOKEN_FILE)
+    #[arg(long, value_name = "PATH", group = "credentials")]
+    token_file: Option<PathBuf>,
+    /// Query parameters of a signed URL, e.g., 'Expires=...&Signature=...'
+    #[arg(long, value_name = "QUERY", group = "credentials")]
+    signature: Option<String>,
+    /// Read the signature from PATH, again whenever it is rejected
+    #[arg(long, value_name = "PATH", group = "credentials")]
+    signature_file: Option<PathBuf>,
+    /// User name and password, as USER:PASSWORD (or set LEGION_PROF_USER)
+    #[arg(long, value_name = "USER[:PASSWORD]", group = "credentials")]
+    user: Option<String>,
+    /// Cookie to send, as NAME=VALUE (or set LEGION_PROF_COOKIE)
+    #[arg(long, value_name = "NAME=VALUE", value_parser = cookie_arg)]
+    cookie: Vec<(String, String)>,
+    /// Header to send with every request, as 'NAME: VALUE'
+    #[arg(long, value_name = "NAME: VALUE", value_parser = header_arg)]
+    header: Vec<(String, String)>,
+    /// User agent to send instead of the default
+    #[arg(long)]
+    user_agent: Option<String>,
+    /// Proxy for every request (or set HTTPS_PROXY, etc.)
+    #[arg(long, value_name = "URL")]
+    proxy: Option<String>,
+    /// Hosts to reach without the proxy, e.g., 'localhost,.internal' (or set
+    /// NO_PROXY)
+    #[arg(long, value_name = "HOSTS")]
+    no_proxy: Option<String>,
+    /// Extra CA certificates to trust, in PEM
+    #[arg(long, value_name = "PATH")]
+    cacert: Vec<PathBuf>,
+    /// Client certificate to present, in PEM
+    #[arg(long, value_name = "PATH")]
+    cert: Option<PathBuf>,
+    /// PKCS#8 key of the client certificate, if not in the same file
+    #[arg(long, value_name = "PATH")]
+    key: Option<PathBuf>,
+    /// Skip verifying the server's certificate
+    #[arg(long)]
+    insecure: bool,
+    /// Seconds to wait for a connection (default 10, 0 waits forever)
+    #[arg(long, value_name = "SECONDS")]
+    connect_timeout: Option<u64>,
+    /// Seconds to wait on the server (default 60, 0 waits forever)
+    #[arg(long, value_name = "SECONDS")]
+    timeout: Option<u64>,
+    /// Requests to send to a server at once (default 8)
+    #[arg(long, value_name = "N")]
+    max_requests: Option<usize>,
+    /// Idle connections to keep open
+    #[arg(long, value_name = "N")]
+    pool_size: Option<usize>,
+    /// Seconds to keep idle connections open
+    #[arg(long, value_name = "SECONDS")]
+    idle_timeout: Option<u64>,
+    /// Speak HTTP/2 to http:// servers without negotiating first
+    #[arg(long)]
+    http2: bool,
+    /// Times to retry a failed request (default 3)
+    #[arg(long, value_name = "N")]
+    retries: Option<u32>,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl ClientArgs {
+    // Flags win over the environment, which wins over the user's config
+    fn config(self, user: &UserConfig) -> ClientConfig {
+        let mut config = ClientConfig::default();
+        user.http.apply(&mut config).unwrap_or_else(|e| fail(e));
+        // Credentials can also come from the environment, to keep them out
+        // of the process list
+        if let Ok(token) = std::env::var("LEGION_PROF_TOKEN") {
+            config.auth.set_credentials(Credentials::Bearer(token));
+        }
+        if let Some(path) = std::env::var_os("LEGION_PROF_TOKEN_FILE") {
+            let result = config.auth.credentials_file(path, Credentials::Bearer);
+            result.unwrap_or_else(|e| fail(format!("LEGION_PROF_TOKEN_FILE: {e}")));
+        }
+        if let Ok(user) = std::env::var("LEGION_PROF_USER") {
+            config.auth.set_credentials(Credentials::basic(&user));
+        }
+        if let Ok(cookies) = std::env::var("LEGION_PROF_COOKIE") {
+            config.auth.cookies = cookies
+                .split(';')
+                .map(cookie_arg)
+                .collect::<Result<_, _>>()
+                .unwrap_or_else(|e| fail(format!("LEGION_PROF_COOKIE: {e}")));
+        }
+        // reqwest only reads the proxy variables without a proxy of our own,
+        // so one from the config mustn't hide them
+        if PROXY_VARS.iter().any(|var| std::env::var_os(var).is_some()) {
+            config.proxy = None;
+        }
+        if let Some(hosts) = ["NO_PROXY", "no_proxy"]
+            .iter()
+            .find_map(|var| std::env::var(var).ok())
+        {
+            config.no_proxy = Some(hosts);
+        }
+
+        if let Some(token) = self.token {
+            config.auth.set_credentials(Credentials::Bearer(token));
+        }
+        if let Some(path) = self.token_file {
+            let result = config.auth.credentials_file(path, Credentials::Bearer);
+            result.unwrap_or_else(|e| fail(e));
+        }
+        if let Some(query) = self.signature {
+            config.auth.set_credentials(Credentials::Query(query));
+        }
+        if let Some(path) = self.signature_file {
+            let result = config.auth.credentials_file(path, Credentials::Query);
+            result.unwrap_or_else(|e| fail(e));
+        }
+        if let Some(user) = self.user {
+            config.auth.set_credentials(Credentials::basic(&user));
+        }
+        config.auth.cookies.extend(self.cookie);
+        config.headers.extend(self.header);
+        if self.user_agent.is_some() {
+            config.user_agent = self.user_agent;
+        }
+        if self.proxy.is_some() {
+            config.proxy = self.proxy;
+        }
+        if self.no_proxy.is_some() {
+            config.no_proxy = self.no_proxy;
+        }
+        config.tls.ca_certificates.extend(self.cacert);
+        if self.cert.is_some() {
+            config.tls.client_certificate = self.cert;
+        }
+        if self.key.is_some() {
+            config.tls.client_key = self.key;
+        }
+        config.tls.accept_invalid_certs |= self.insecure;
+        if let Some(connect) = self.connect_timeout {
+            config.timeouts.connect = seconds(connect);
+        }
+        if let Some(read) = self.timeout {
+            config.timeouts.read = seconds(read);
+        }
+        if self.max_requests.is_some() {
+            config.max_in_flight = self.max_requests;
+        }
+        if self.pool_size.is_some() {
+            config.connections.pool_size = self.pool_size;
+        }
+        if let Some(idle) = self.idle_timeout {
+            config.connections.idle_timeout = seconds(idle);
+        }
+        config.connections.http2_prior_knowledge |= self.http2;
+        if let Some(retries) = self.retries {
+            config.retry.retries = retries;
+        }
+        config
+    }
+}
+
+// What to do with the profiles once they are open, for every subcommand that
+// opens them
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Args)]
+struct SourceArgs {
+    /// Show the profiles as one, with the nodes of each side by side
+    #[arg(long)]
+    merge: bool,
+    /// Only keep entries whose names match PATTERN
+    #[arg(long, value_name = "PATTERN")]
+    filter: Option<String>,
+    /// Record every request to FILE, to replay later (FILE.0, FILE.1, ...
+    /// with several profiles)
+    #[arg(long, value_name = "FILE")]
+    record: Option<String>,
+    #[command(flatten)]
+    client: ClientArgs,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl SourceArgs {
+    fn open(
+        self,
+        profiles: Vec<Locator>,
+        demo: Option<RandomConfig>,
+        user: &UserConfig,
+    ) -> Vec<Box<dyn DeferredDataSource>> {
+        use legion_prof_viewer::filter_data::FilterDeferredDataSource;
+        use legion_prof_viewer::replay_data::RecordingDeferredDataSource;
+
+        let config = self.client.config(user);
+        let count = profiles.len() + demo.iter().count();
+        let ds: Vec<_> = demo
+            .map(demo_ds)
+            .into_iter()
+            .chain(
+                profiles
+                    .into_iter()
+                    .map(|profile| profile.open(&config).unwrap_or_else(|e| fail(e))),
+            )
+            .map(|ds| {
+                let Some(filter) = &self.filter else {
+                    return ds;
+                };
+                Box::new(FilterDeferredDataSource::by_name(ds, filter))
+                    as Box<dyn DeferredDataSource>
+            })
+            .enumerate()
+            .map(|(i, ds)| {
+                let Some(record) = &self.record else {
+                    return ds;
+                };
+                // Each data source needs its own recording
+                let path = if count > 1 {
+                    format!("{record}.{i}")
+                } else {
+                    record.clone()
+                };
+                let ds = RecordingDeferredDataSource::new(ds, &path)
+                    .unwrap_or_else(|e| fail(format!("unable to create recording {path}: {e}")));
+                Box::new(ds) as Box<dyn DeferredDataSource>
+            })
+            .collect();
+        if self.merge { merge_ds(ds) } else { ds }
+    }
+}
+
+// A synthetic profile, to try out the viewer without one
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Args)]
+#[command(next_help_heading = "Demo options")]
+struct DemoArgs {
+    /// Add a synthetic profile
+    #[arg(long)]
+    demo: bool,
+    /// Seed of the synthetic profile (implies --demo)
+    #[arg(long, value_name = "N")]
+    demo_seed: Option<u64>,
+    /// Most rows of each processor of the synthetic profile (implies --demo)
+    #[arg(long, value_name = "N")]
+    demo_rows: Option<u64>,
+    /// Most items of each row of the synthetic profile (implies --demo)
+    #[arg(long, value_name = "N")]
+    demo_items: Option<u64>,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl DemoArgs {
+    fn config(&self) -> Option<RandomConfig> {
+        let given = self.demo
+            || self.demo_seed.is_some()
+            || self.demo_rows.is_some()
+            || self.demo_items.is_some();
+        if !given {
+            return None;
+        }
+        let mut config = RandomConfig::default();
+        config.seed = self.demo_seed.unwrap_or(config.seed);
+        config.max_rows = self.demo_rows.unwrap_or(config.max_rows);
+        config.items_per_row = self.demo_items.unwrap_or(config.items_per_row);
+        Some(config)
+    }
+}
+
+// Profiles to open, for every subcommand that takes any kind of profile
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Args)]
+struct ViewArgs {
+    /// Archive directories, bundles or trace files (.zip, .json, .perf,
+    /// ...), recordings (.replay), or http(s):// or file:// URLs
+    #[arg(value_name = "PROFILE")]
+    profiles: Vec<Locator>,
+    #[command(flatten)]
+    source: SourceArgs,
+    #[command(flatten)]
+    demo: DemoArgs,
+}
 
-    legion_prof_viewer::app::start(ds);
+#[cfg(not(target_arch = "wasm32"))]
+impl ViewArgs {
+    fn open(self, user: &UserConfig) -> Vec<Box<dyn DeferredDataSource>> {
+        let demo = self.demo.config();
+        self.source.open(self.profiles, demo, user)
+    }
+}
+
+// Where to land once the profiles load (e.g., from a link in a bug report),
+// and whether to follow them as they grow, for the subcommands that show them
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Args)]
+struct StartArgs {
+    /// Start zoomed to START..STOP, e.g., 1.5s..2s
+    #[arg(long, value_name = "START..STOP", value_parser = interval_arg)]
+    interval: Option<Interval>,
+    /// Start with the entry at PATH expanded and scrolled to, e.g., n0/cpu/cpu0
+    #[arg(long, value_name = "PATH")]
+    entry: Option<String>,
+    /// Refresh the profiles every SECONDS (2 if not given) and keep the view
+    /// on the newest data, e.g., to watch a running job
+    #[arg(
+        long,
+        value_name = "SECONDS",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "2",
+        value_parser = clap::value_parser!(u64).range(1..)
+    )]
+    follow: Option<u64>,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl StartArgs {
+    fn settings(self, user: &UserConfig) -> AppSettings {
+        AppSettings {
+            start: StartView {
+                interval: self.interval,
+                entry: self.entry,
+            },
+            follow: self.follow.map(Duration::from_secs),
+            ..user.app_settings()
+        }
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Args)]
+struct ViewerArgs {
+    #[command(flatten)]
+    view: ViewArgs,
+    #[command(flatten)]
+    start: StartArgs,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Args)]
+struct AttachArgs {
+    /// Server (or static directory) listing its profiles at its index
+    /// endpoint, to choose from
+    #[arg(
+        long,
+        value_name = "URL",
+        conflicts_with_all = ["urls", "merge", "filter", "record"]
+    )]
+    index: Option<Url>,
+    /// Servers to view the profiles of (http, ws or grpc)
+    #[arg(value_name = "URL", value_parser = remote_profile, required_unless_present = "index")]
+    urls: Vec<Locator>,
+    #[command(flatten)]
+    source: SourceArgs,
+    #[command(flatten)]
+    start: StartArgs,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn attach(args: AttachArgs, user: &UserConfig) {
+    use legion_prof_viewer::app::start_with_settings;
+
+    let settings = args.start.settings(user);
+    if let Some(index) = args.index {
+        let config = args.source.client.config(user);
+        let index = HTTPProfileIndex::with_config(index, config)
+            .unwrap_or_else(|e| fail(format!("unable to configure HTTP client: {e}")));
+        return start_with_settings(Vec::new(), Some(Box::new(index)), settings);
+    }
+    let ds = args.source.open(args.urls, None, user);
+    start_with_settings(ds, None, settings);
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Args)]
+struct ServeArgs {
+    /// Profile directory or file to serve
+    #[arg(value_name = "PROFILE")]
+    profile: String,
+    /// Host name or address to listen on
+    #[arg(long, default_value = "127.0.0.1")]
+    host: String,
+    /// Port to listen on
+    #[arg(long, default_value_t = 8080)]
+    port: u16,
+    /// Send plain CBOR, compressed only as negotiated with the client
+    #[arg(long)]
+    uncompressed: bool,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn serve(args: ServeArgs) {
+    #[cfg(feature = "server")]
+    {
+        use legion_prof_viewer::http::server::DataSourceHTTPServer;
+
+        use legion_prof_viewer::open_data::open_local;
+
+        let data_source = open_local(&args.profile).unwrap_or_else(|e| fail(e));
+        println!(
+            "Serving {} at http://{}:{}/",
+            args.profile, args.host, args.port
+        );
+        let mut server = DataSourceHTTPServer::new(args.host, args.port, data_source);
+        if args.uncompressed {
+            server = server.uncompressed();
+        }
+        if let Err(e) = server.run() {
+            fail(format!("server failed: {e}"));
+        }
+    }
+    #[cfg(not(feature = "server"))]
+    usage_error(
+        "serve",
+        ErrorKind::InvalidSubcommand,
+        format!(
+            "serve requires the server feature to serve {}",
+            args.profile
+        ),
+    );
+}
+
+// Options of the export formats, which are listed by the export module
+// rather than declared here
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Default)]
+struct FormatOptions {
+    options: ExportOptions,
+    // Format options that were given, to check that they fit the format
+    given: Vec<&'static ExportOption>,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn option_id(option: &ExportOption) -> &'static str {
+    option.flag.trim_start_matches("--")
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl clap::FromArgMatches for FormatOptions {
+    fn from_arg_matches(matches: &clap::ArgMatches) -> Result<Self, clap::Error> {
+        let mut options = Self::default();
+        options.update_from_arg_matches(matches)?;
+        Ok(options)
+    }
+
+    fn update_from_arg_matches(&mut self, matches: &clap::ArgMatches) -> Result<(), clap::Error> {
+        use legion_prof_viewer::export::OPTIONS;
+
+        for option in OPTIONS {
+            let id = option_id(option);
+            // Switches are set with an empty value
+            let value = match option.value {
+                Some(_) => match matches.get_one::<String>(id) {
+                    Some(value) => value.as_str(),
+                    None => continue,
+                },
+                None if matches.get_flag(id) => "",
+                None => continue,
+            };
+            (option.set)(&mut self.options, value).map_err(|e| {
+                clap::Error::raw(
+                    ErrorKind::ValueValidation,
+                    format!("{}: {e}\n", option.flag),
+                )
+            })?;
+            self.given.push(option);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl clap::Args for FormatOptions {
+    fn augment_args(mut cmd: clap::Command) -> clap::Command {
+        use clap::{Arg, ArgAction};
+        use legion_prof_viewer::export::OPTIONS;
+
+        cmd = cmd.next_help_heading("Format options");
+        for option in OPTIONS {
+            let arg = Arg::new(option_id(option))
+                .long(option_id(option))
+                .help(format!("{} ({})", option.help, option.formats.join(", ")));
+            cmd = cmd.arg(match option.value {
+                Some(value) => arg.value_name(value),
+                None => arg.action(ArgAction::SetTrue),
+            });
+        }
+        cmd
+    }
+
+    fn augment_args_for_update(cmd: clap::Command) -> clap::Command {
+        Self::augment_args(cmd)
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn format_names() -> clap::builder::PossibleValuesParser {
+    use clap::builder::PossibleValue;
+    use legion_prof_viewer::export::FORMATS;
+
+    FORMATS
+        .iter()
+        .map(|format| PossibleValue::new(format.name).help(format.description))
+        .collect::<Vec<_>>()
+        .into()
+}
+
+// Several profiles can be exported as one with --merge
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Args)]
+struct ExportArgs {
+    /// Format to write
+    #[arg(short, long, value_parser = format_names())]
+    format: String,
+    /// File (or directory, for some formats) to write
+    #[arg(short, long, value_name = "PATH")]
+    output: String,
+    #[command(flatten)]
+    view: ViewArgs,
+    #[command(flatten)]
+    options: FormatOptions,
+}
+
+// Writes the profile to a file in another format, instead of viewing it
+#[cfg(not(target_arch = "wasm32"))]
+fn export(args: ExportArgs, user: &UserConfig) {
+    use legion_prof_viewer::export::find_format;
+
+    let format = find_format(&args.format).expect("format was checked by the parser");
+    if let Some(option) = args
+        .options
+        .given
+        .iter()
+        .find(|option| !option.applies_to(format.name))
+    {
+        usage_error(
+            "export",
+            ErrorKind::ArgumentConflict,
+            format!(
+                "{} doesn't apply to {} (only to {})",
+                option.flag,
+                format.name,
+                option.formats.join(", ")
+            ),
+        );
+    }
+    let Ok([ds]) = <[_; 1]>::try_from(args.view.open(user)) else {
+        usage_error(
+            "export",
+            ErrorKind::WrongNumberOfValues,
+            "export requires exactly one profile (use --merge to combine several)",
+        );
+    };
+    let output = args.output;
+    if let Err(e) = (format.export)(ds, output.as_ref(), &args.options.options) {
+        fail(format!("export to {output} failed: {e}"));
+    }
+    println!("Exported {output}");
+}
+
+// Several profiles can be archived as one with --merge
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Args)]
+struct ArchiveArgs {
+    /// Directory to write
+    #[arg(short, long, value_name = "DIR")]
+    output: String,
+    /// Levels of tiles to make, for sources that make tiles on demand
+    #[arg(long, value_name = "N", default_value_t = 5,
+          value_parser = clap::value_parser!(u32).range(1..))]
+    levels: u32,
+    /// Tiles each tile is split into at the next level
+    #[arg(long, value_name = "N", default_value_t = 4,
+          value_parser = clap::value_parser!(u64).range(2..))]
+    branch_factor: u64,
+    /// Compression level
+    #[arg(long, value_name = "N", default_value_t = 1)]
+    zstd_level: i32,
+    /// Replace DIR, rather than picking a new name
+    #[arg(long)]
+    force: bool,
+    /// Bundle the web viewer built in DIST (by `trunk build --release
+    /// --public-url ./`), to open the archive without the hosted viewer
+    #[arg(long, value_name = "DIST")]
+    viewer: Option<PathBuf>,
+    #[command(flatten)]
+    view: ViewArgs,
+}
+
+// Writes the profile as a directory of tiles, instead of viewing it
+#[cfg(not(target_arch = "wasm32"))]
+fn archive(args: ArchiveArgs, user: &UserConfig) {
+    use legion_prof_viewer::archive_data::DataSourceArchiveWriter;
+
+    let Ok([ds]) = <[_; 1]>::try_from(args.view.open(user)) else {
+        usage_error(
+            "archive",
+            ErrorKind::WrongNumberOfValues,
+            "archive requires exactly one profile (use --merge to combine several)",
+        );
+    };
+    let output = args.output;
+    let mut writer = DataSourceArchiveWriter::new(
+        ds,
+        args.levels,
+        args.branch_factor,
+        &output,
+        args.force,
+        args.zstd_level,
+    );
+    if let Some(viewer) = args.viewer {
+        writer = writer.with_viewer(viewer);
+    }
+    if let Err(e) = writer.write() {
+        fail(format!("archive to {output} failed: {e}"));
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Copy, Clone, ValueEnum)]
+enum StatsFormat {
+    Table,
+    Json,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Args)]
+struct StatsArgs {
+    /// Only count this part of the profile, e.g., 1.5s..2s
+    #[arg(long, value_name = "START..STOP", value_parser = interval_arg)]
+    interval: Option<Interval>,
+    /// Titles and items to list
+    #[arg(long, value_name = "N", default_value_t = 10)]
+    top: usize,
+    /// Output format
+    #[arg(long, value_enum, default_value_t = StatsFormat::Table)]
+    format: StatsFormat,
+    #[command(flatten)]
+    view: ViewArgs,
+}
+
+// Prints a summary of the profile, instead of viewing it
+#[cfg(not(target_arch = "wasm32"))]
+fn stats(args: StatsArgs, user: &UserConfig) {
+    use legion_prof_viewer::stats::ProfileStats;
+
+    let Ok([ds]) = <[_; 1]>::try_from(args.view.open(user)) else {
+        usage_error(
+            "stats",
+            ErrorKind::WrongNumberOfValues,
+            "stats requires exactly one profile (use --merge to combine several)",
+        );
+    };
+    let stats = ProfileStats::collect(ds, args.interval, args.top)
+        .unwrap_or_else(|e| fail(format!("unable to compute stats: {e}")));
+    match args.format {
+        StatsFormat::Table => print!("{}", stats.to_table()),
+        StatsFormat::Json => {
+            let json = serde_json::to_string_pretty(&stats).expect("stats are serializable");
+            println!("{json}");
+        }
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn main() {
+    let cli = Cli::parse();
+    init_logging(&cli);
+    let user = match &cli.config {
+        Some(path) => UserConfig::load(path),
+        None => UserConfig::load_default(),
+    };
+    let user = user.unwrap_or_else(|e| fail(e));
+    match cli.command.unwrap_or(Command::View(cli.view)) {
+        Command::View(args) => {
+            let settings = args.start.settings(&user);
+            legion_prof_viewer::app::start_with_settings(args.view.open(&user), None, settings);
+        }
+        Command::Attach(args) => attach(args, &user),
+        Command::Serve(args) => serve(args),
+        Command::Export(args) => export(args, &user),
+        Command::Archive(args) => archive(args, &user),
+        Command::Stats(args) => stats(args, &user),
+    }
 }
 
 #[cfg(target_arch = "wasm32")]
@@ -26,11 +925,59 @@ fn main() {
     let href: String = loc.href().expect("unable to get window URL");
     let browser_url = Url::parse(&href).expect("unable to parse location URL");
 
+    // Cookies can't be set by the page, but the browser can be asked to send
+    // its own (e.g., from logging in to an SSO proxy)
+    let mut config = ClientConfig::default();
+    for (key, value) in browser_url.query_pairs() {
+        match &*key {
+            "token" => config.auth.credentials = Some(Credentials::Bearer(value.into_owned())),
+            "user" => config.auth.credentials = Some(Credentials::basic(&value)),
+            "signature" => config.auth.credentials = Some(Credentials::Query(value.into_owned())),
+            "credentials" => config.auth.include_credentials = value == "include",
+            "max_requests" => config.max_in_flight = value.parse().ok(),
+            _ => {}
+        }
+    }
+
+    // Where to land and whether to follow, e.g.,
+    // ?interval=1.5s..2s&entry=n0/cpu/cpu0
+    let mut settings = AppSettings::default();
+    for (key, value) in browser_url.query_pairs() {
+        match &*key {
+            "interval" => {
+                settings.start.interval =
+                    Some(interval_arg(&value).unwrap_or_else(|e| panic!("{e}")))
+            }
+            "entry" => settings.start.entry = Some(value.into_owned()),
+            // ?follow, or ?follow=SECONDS
+            "follow" => {
+                let seconds = value.parse().ok().filter(|&seconds| seconds > 0);
+                settings.follow = Some(Duration::from_secs(seconds.unwrap_or(2)));
+            }
+            _ => {}
+        }
+    }
+
+    // Relative URLs are relative to the viewer's page, e.g., ?url=../ for an
+    // archive bundled with its own viewer
+    if let Some((_, index)) = browser_url.query_pairs().find(|(key, _)| key == "index") {
+        let index = browser_url.join(&index).expect("unable to parse index URL");
+        return index_start(index, &config, settings).unwrap_or_else(|e| panic!("{e}"));
+    }
+
     let ds: Vec<_> = browser_url
         .query_pairs()
         .filter(|(key, _)| key.starts_with("url"))
-        .map(|(_, value)| http_ds(Url::parse(&value).expect("unable to parse query URL")))
+        .map(|(_, value)| {
+            http_ds(
+                browser_url.join(&value).expect("unable to parse query URL"),
+                &config,
+            )
+            .unwrap_or_else(|e| panic!("{e}"))
+        })
         .collect();
+    let merge = browser_url.query_pairs().any(|(key, _)| key == "merge");
+    let ds = if merge { merge_ds(ds) } else { ds };
 
-    legion_prof_viewer::app::start(ds);
+    legion_prof_viewer::app::start_with_settings(ds, None, settings);
 }
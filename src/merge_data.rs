@@ -1,18 +1,31 @@
 use std::collections::VecDeque;
 
 use crate::data::{
-    DataSourceDescription, DataSourceInfo, EntryID, EntryIndex, EntryInfo, Field, ItemLink,
-    ItemUID, SlotMetaTile, SlotTile, SummaryTile, TileID,
+    Capabilities, DataSourceDescription, DataSourceInfo, EntryID, EntryIndex, EntryInfo, Field,
+    ItemLink, ItemMeta, ItemMetaRequest, ItemUID, PROTOCOL_VERSION, SlotMetaTile, SlotTile,
+    SummaryTile, TileID,
 };
 use crate::deferred_data::{
-    DeferredDataSource, SlotMetaTileResponse, SlotTileResponse, SummaryTileResponse,
+    DataSourceInfoResult, DeferredDataSource, ItemsMetaResponse, ItemsMetaResult, RequestPriority,
+    SlotMetaTileResponse, SlotTileResponse, SummaryTileResponse, TileKind, TileRequest,
+    TransferStats,
 };
 use crate::timestamp::Interval;
 
+// A batched item metadata request may span multiple sources. We keep it
+// here until every source has responded so that callers see exactly one
+// response per request.
+struct PendingItemsMeta {
+    requests: Vec<ItemMetaRequest>,
+    waiting: Vec<(usize, Vec<ItemMetaRequest>)>,
+    result: ItemsMetaResult,
+}
+
 pub struct MergeDeferredDataSource {
     data_sources: Vec<Box<dyn DeferredDataSource>>,
-    infos: Vec<VecDeque<DataSourceInfo>>,
+    infos: Vec<VecDeque<DataSourceInfoResult>>,
     mapping: Vec<u64>,
+    pending_items_meta: Vec<PendingItemsMeta>,
 }
 
 impl MergeDeferredDataSource {
@@ -23,6 +36,7 @@ impl MergeDeferredDataSource {
             data_sources,
             infos,
             mapping: Vec::new(),
+            pending_items_meta: Vec::new(),
         }
     }
 
@@ -82,7 +96,7 @@ impl MergeDeferredDataSource {
         mapping
     }
 
-    fn merge_infos(source_infos: Vec<DataSourceInfo>) -> DataSourceInfo {
+    fn merge_infos(source_infos: Vec<DataSourceInfo>, mapping: &[u64]) -> DataSourceInfo {
         assert!(!source_infos.is_empty());
 
         // Some fields can't be meaningfully merged, so just assert they're
@@ -110,6 +124,39 @@ impl MergeDeferredDataSource {
             .map(|info| info.entry_info.clone())
             .reduce(Self::merge_entry)
             .unwrap();
+        let refresh_interval = source_infos
+            .iter()
+            .filter_map(|info| info.refresh_interval)
+            .min();
+        // Features are only available if every source has them, but one live
+        // source is enough for the merged profile to change
+        let capabilities = source_infos
+            .iter()
+            .map(|info| info.capabilities)
+            .reduce(|a, b| Capabilities {
+                summary_tiles: a.summary_tiles && b.summary_tiles,
+                slot_meta_tiles: a.slot_meta_tiles && b.slot_meta_tiles,
+                search: a.search && b.search,
+                batch_fetch: a.batch_fetch && b.batch_fetch,
+                items_meta: a.items_meta && b.items_meta,
+                live_updates: a.live_updates || b.live_updates,
+            })
+            .unwrap();
+        // Each source's entries move to where their first level starts in the
+        // merged profile. Entries that aren't under a slot (e.g., the root)
+        // can't be moved, and use the shared tile set instead.
+        let entry_tile_sets = source_infos
+            .iter()
+            .zip(mapping)
+            .flat_map(|(info, &offset)| {
+                info.entry_tile_sets
+                    .iter()
+                    .filter(|(entry_id, _)| matches!(entry_id.index(0), Some(EntryIndex::Slot(_))))
+                    .map(move |(entry_id, tile_set)| {
+                        (entry_id.shift_level0(offset as i64), tile_set.clone())
+                    })
+            })
+            .collect();
 
         DataSourceInfo {
             entry_info,
@@ -117,6 +164,10 @@ impl MergeDeferredDataSource {
             tile_set,
             field_schema,
             warning_message,
+            refresh_interval,
+            version: PROTOCOL_VERSION,
+            capabilities,
+            entry_tile_sets,
         }
     }
 
@@ -137,6 +188,10 @@ impl MergeDeferredDataSource {
         ItemUID(item_uid.0 * (self.mapping.len() as u64) + (idx as u64))
     }
 
+    fn map_dst_to_src_item_uid(&self, item_uid: ItemUID) -> ItemUID {
+        ItemUID(item_uid.0 / (self.mapping.len() as u64))
+    }
+
     fn map_src_to_dst_summary(&self, idx: usize, tile: SummaryTile) -> SummaryTile {
         SummaryTile {
             entry_id: self.map_src_to_dst_entry(idx, &tile.entry_id),
@@ -174,13 +229,17 @@ impl MergeDeferredDataSource {
         }
     }
 
+    fn map_src_to_dst_item_meta(&self, idx: usize, item: &mut ItemMeta) {
+        item.item_uid = self.map_src_to_dst_item_uid(idx, item.item_uid);
+        for (_, field, _) in &mut item.fields {
+            self.map_src_to_dst_field(idx, field);
+        }
+    }
+
     fn map_src_to_dst_slot_meta(&self, idx: usize, mut tile: SlotMetaTile) -> SlotMetaTile {
         for items in &mut tile.data.items {
             for item in items {
-                item.item_uid = self.map_src_to_dst_item_uid(idx, item.item_uid);
-                for (_, field, _) in &mut item.fields {
-                    self.map_src_to_dst_field(idx, field);
-                }
+                self.map_src_to_dst_item_meta(idx, item);
             }
         }
 
@@ -208,30 +267,41 @@ impl DeferredDataSource for MergeDeferredDataSource {
         }
     }
 
-    fn get_infos(&mut self) -> Vec<DataSourceInfo> {
+    fn get_infos(&mut self) -> Vec<DataSourceInfoResult> {
         for (data_source, infos) in self.data_sources.iter_mut().zip(self.infos.iter_mut()) {
             infos.extend(data_source.get_infos());
         }
 
-        let max_available = self.infos.iter().map(|infos| infos.len()).max().unwrap();
+        // Sources may respond at different times, so we can only merge as
+        // many infos as every source has returned so far
+        let available = self.infos.iter().map(|infos| infos.len()).min().unwrap();
 
         let mut result = Vec::new();
-        for _ in 0..max_available {
-            let source_infos: Vec<_> = self
+        for _ in 0..available {
+            let source_infos: Result<Vec<_>, _> = self
                 .infos
                 .iter_mut()
                 .map(|infos| infos.pop_front().unwrap())
                 .collect();
-            self.mapping = Self::compute_mapping(&source_infos);
-            result.push(Self::merge_infos(source_infos));
+            // If any source failed, there's nothing to merge
+            result.push(source_infos.map(|source_infos| {
+                self.mapping = Self::compute_mapping(&source_infos);
+                Self::merge_infos(source_infos, &self.mapping)
+            }));
         }
         result
     }
 
-    fn fetch_summary_tile(&mut self, entry_id: &EntryID, tile_id: TileID, full: bool) {
+    fn fetch_summary_tile(
+        &mut self,
+        entry_id: &EntryID,
+        tile_id: TileID,
+        full: bool,
+        priority: RequestPriority,
+    ) {
         let (idx, src_entry) = self.map_dst_to_src_entry(entry_id);
 
-        self.data_sources[idx].fetch_summary_tile(&src_entry, tile_id, full);
+        self.data_sources[idx].fetch_summary_tile(&src_entry, tile_id, full, priority);
     }
 
     fn get_summary_tiles(&mut self) -> Vec<SummaryTileResponse> {
@@ -252,10 +322,16 @@ impl DeferredDataSource for MergeDeferredDataSource {
             .collect()
     }
 
-    fn fetch_slot_tile(&mut self, entry_id: &EntryID, tile_id: TileID, full: bool) {
+    fn fetch_slot_tile(
+        &mut self,
+        entry_id: &EntryID,
+        tile_id: TileID,
+        full: bool,
+        priority: RequestPriority,
+    ) {
         let (idx, src_entry) = self.map_dst_to_src_entry(entry_id);
 
-        self.data_sources[idx].fetch_slot_tile(&src_entry, tile_id, full);
+        self.data_sources[idx].fetch_slot_tile(&src_entry, tile_id, full, priority);
     }
 
     fn get_slot_tiles(&mut self) -> Vec<SlotTileResponse> {
@@ -276,10 +352,16 @@ impl DeferredDataSource for MergeDeferredDataSource {
             .collect()
     }
 
-    fn fetch_slot_meta_tile(&mut self, entry_id: &EntryID, tile_id: TileID, full: bool) {
+    fn fetch_slot_meta_tile(
+        &mut self,
+        entry_id: &EntryID,
+        tile_id: TileID,
+        full: bool,
+        priority: RequestPriority,
+    ) {
         let (idx, src_entry) = self.map_dst_to_src_entry(entry_id);
 
-        self.data_sources[idx].fetch_slot_meta_tile(&src_entry, tile_id, full);
+        self.data_sources[idx].fetch_slot_meta_tile(&src_entry, tile_id, full, priority);
     }
 
     fn get_slot_meta_tiles(&mut self) -> Vec<SlotMetaTileResponse> {
@@ -299,13 +381,133 @@ impl DeferredDataSource for MergeDeferredDataSource {
             .map(|(idx, (tile, req))| (tile.map(|t| self.map_src_to_dst_slot_meta(idx, t)), req))
             .collect()
     }
+
+    fn fetch_items_meta(&mut self, requests: &[ItemMetaRequest]) {
+        let mut src_requests = vec![Vec::new(); self.data_sources.len()];
+        for req in requests {
+            let (idx, src_entry) = self.map_dst_to_src_entry(&req.entry_id);
+            src_requests[idx].push(ItemMetaRequest {
+                entry_id: src_entry,
+                item_uid: self.map_dst_to_src_item_uid(req.item_uid),
+                interval: req.interval,
+            });
+        }
+
+        let mut waiting = Vec::new();
+        for (idx, src_reqs) in src_requests.into_iter().enumerate() {
+            if !src_reqs.is_empty() {
+                self.data_sources[idx].fetch_items_meta(&src_reqs);
+                waiting.push((idx, src_reqs));
+            }
+        }
+
+        self.pending_items_meta.push(PendingItemsMeta {
+            requests: requests.to_vec(),
+            waiting,
+            result: Ok(Vec::new()),
+        });
+    }
+
+    fn fetch_tiles(&mut self, kind: TileKind, requests: &[TileRequest], priority: RequestPriority) {
+        let mut src_requests = vec![Vec::new(); self.data_sources.len()];
+        for req in requests {
+            let (idx, src_entry) = self.map_dst_to_src_entry(&req.entry_id);
+            src_requests[idx].push(TileRequest {
+                entry_id: src_entry,
+                tile_id: req.tile_id,
+                full: req.full,
+            });
+        }
+
+        for (idx, src_reqs) in src_requests.into_iter().enumerate() {
+            if !src_reqs.is_empty() {
+                self.data_sources[idx].fetch_tiles(kind, &src_reqs, priority);
+            }
+        }
+    }
+
+    fn get_items_meta(&mut self) -> Vec<ItemsMetaResponse> {
+        let mut responses = Vec::new();
+        for (idx, data_source) in self.data_sources.iter_mut().enumerate() {
+            responses.extend(
+                data_source
+                    .get_items_meta()
+                    .into_iter()
+                    .map(|response| (idx, response)),
+            );
+        }
+
+        for (idx, (result, src_reqs)) in responses {
+            let result = result.map(|mut items| {
+                for item in &mut items {
+                    self.map_src_to_dst_item_meta(idx, item);
+                }
+                items
+            });
+
+            let key = (idx, src_reqs);
+            let pending = self
+                .pending_items_meta
+                .iter_mut()
+                .find(|pending| pending.waiting.contains(&key))
+                .expect("received response for unknown items meta request");
+            pending.waiting.retain(|w| *w != key);
+            pending.result = match (
+                std::mem::replace(&mut pending.result, Ok(Vec::new())),
+                result,
+            ) {
+                (Ok(mut all), Ok(items)) => {
+                    all.extend(items);
+                    Ok(all)
+                }
+                (Err(e), _) | (_, Err(e)) => Err(e),
+            };
+        }
+
+        let (ready, pending) = std::mem::take(&mut self.pending_items_meta)
+            .into_iter()
+            .partition(|pending| pending.waiting.is_empty());
+        self.pending_items_meta = pending;
+        ready
+            .into_iter()
+            .map(|pending: PendingItemsMeta| (pending.result, pending.requests))
+            .collect()
+    }
+
+    fn cancel(&mut self, entry_id: &EntryID, tile_id: TileID, full: bool) {
+        let (idx, src_entry) = self.map_dst_to_src_entry(entry_id);
+
+        self.data_sources[idx].cancel(&src_entry, tile_id, full);
+    }
+
+    fn transfer_stats(&self) -> Option<TransferStats> {
+        // Totals over whichever of the sources use the network
+        let mut result: Option<TransferStats> = None;
+        for stats in self
+            .data_sources
+            .iter()
+            .filter_map(|ds| ds.transfer_stats())
+        {
+            result
+                .get_or_insert_with(TransferStats::default)
+                .merge(&stats);
+        }
+        result
+    }
+
+    fn retries_failures(&self) -> bool {
+        // Otherwise the sources that don't would go without
+        self.data_sources.iter().all(|ds| ds.retries_failures())
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
-    use crate::data::{FieldSchema, TileSet};
+    use std::collections::BTreeMap;
+
+    use crate::data::{FieldSchema, TileID, TileSet};
     use crate::timestamp::Timestamp;
 
     #[test]
@@ -369,6 +571,9 @@ mod tests {
 
     #[test]
     fn test_merge_info() {
+        let slot3_tiles = TileSet {
+            tiles: vec![vec![TileID(Interval::new(Timestamp(0), Timestamp(2000)))]],
+        };
         let first = DataSourceInfo {
             entry_info: EntryInfo::Panel {
                 short_name: "F".to_string(),
@@ -391,6 +596,10 @@ mod tests {
             tile_set: TileSet { tiles: Vec::new() },
             field_schema: FieldSchema::new(),
             warning_message: None,
+            refresh_interval: None,
+            version: PROTOCOL_VERSION,
+            capabilities: Capabilities::default(),
+            entry_tile_sets: BTreeMap::new(),
         };
         let second = DataSourceInfo {
             entry_info: EntryInfo::Panel {
@@ -407,6 +616,10 @@ mod tests {
             tile_set: TileSet { tiles: Vec::new() },
             field_schema: FieldSchema::new(),
             warning_message: None,
+            refresh_interval: None,
+            version: PROTOCOL_VERSION,
+            capabilities: Capabilities::default(),
+            entry_tile_sets: BTreeMap::from([(EntryID::root().child(0), slot3_tiles.clone())]),
         };
 
         let infos = vec![first, second];
@@ -414,10 +627,15 @@ mod tests {
         let mapping = MergeDeferredDataSource::compute_mapping(&infos);
         assert_eq!(mapping, vec![0, 2]);
 
-        let merge = MergeDeferredDataSource::merge_infos(infos);
+        let merge = MergeDeferredDataSource::merge_infos(infos, &mapping);
 
         assert_eq!(merge.interval, Interval::new(Timestamp(0), Timestamp(2000)));
         assert!(merge.tile_set.tiles.is_empty());
+        // Slot 3 is the merged profile's third slot, and keeps its tiles
+        assert_eq!(
+            merge.entry_tile_sets,
+            BTreeMap::from([(EntryID::root().child(2), slot3_tiles)])
+        );
 
         let EntryInfo::Panel {
             short_name,
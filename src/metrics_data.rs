@@ -0,0 +1,334 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::time::Duration;
+
+use web_time::Instant;
+
+use crate::data::{DataSourceDescription, EntryID, ItemMetaRequest, TileID};
+use crate::deferred_data::{
+    CANCELLED, DataSourceInfoResult, DeferredDataSource, ItemsMetaResponse, RequestPriority,
+    SlotMetaTileResponse, SlotTileResponse, SummaryTileResponse, TileKind, TileRequest,
+    TransferStats, item_meta_size, slot_meta_tile_size, slot_tile_size, summary_tile_size,
+};
+
+// Statistics for one kind of request. Latencies are measured from when the
+// request is issued to when its response is collected, so they include any
+// time the response spent waiting to be polled.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct RequestMetrics {
+    pub requests: u64,
+    pub responses: u64,
+    pub errors: u64,
+    pub cancelled: u64,
+    // Estimated in-memory size of successful responses
+    pub bytes: u64,
+    pub total_latency: Duration,
+    pub max_latency: Duration,
+}
+
+impl RequestMetrics {
+    pub fn in_flight(&self) -> u64 {
+        self.requests.saturating_sub(self.responses)
+    }
+
+    pub fn mean_latency(&self) -> Option<Duration> {
+        let timed = self.responses - self.cancelled;
+        (timed > 0).then(|| self.total_latency / timed as u32)
+    }
+
+    fn record(&mut self, latency: Option<Duration>, result: Result<usize, &str>) {
+        self.responses += 1;
+        match result {
+            Ok(bytes) => self.bytes += bytes as u64,
+            // Cancelled requests say nothing about the data source, so they
+            // don't count towards latency either
+            Err(CANCELLED) => {
+                self.cancelled += 1;
+                return;
+            }
+            Err(_) => self.errors += 1,
+        }
+        if let Some(latency) = latency {
+            self.total_latency += latency;
+            self.max_latency = self.max_latency.max(latency);
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct Metrics {
+    pub info: RequestMetrics,
+    pub summary_tiles: RequestMetrics,
+    pub slot_tiles: RequestMetrics,
+    pub slot_meta_tiles: RequestMetrics,
+    pub items_meta: RequestMetrics,
+}
+
+// Start times of outstanding requests, matched up with responses oldest first
+struct Outstanding<R: Ord> {
+    requests: BTreeMap<R, VecDeque<Instant>>,
+}
+
+impl<R: Ord> Outstanding<R> {
+    fn new() -> Self {
+        Self {
+            requests: BTreeMap::new(),
+        }
+    }
+
+    fn start(&mut self, req: R, metrics: &mut RequestMetrics) {
+        metrics.requests += 1;
+        self.requests
+            .entry(req)
+            .or_default()
+            .push_back(Instant::now());
+    }
+
+    fn finish<V>(
+        &mut self,
+        responses: &[(Result<V, String>, R)],
+        size: impl Fn(&V) -> usize,
+        metrics: &mut RequestMetrics,
+    ) {
+        let now = Instant::now();
+        for (value, req) in responses {
+            let mut start = None;
+            if let Some(starts) = self.requests.get_mut(req) {
+                start = starts.pop_front();
+                if starts.is_empty() {
+                    self.requests.remove(req);
+                }
+            }
+            metrics.record(
+                start.map(|start| now - start),
+                value.as_ref().map(&size).map_err(String::as_str),
+            );
+        }
+    }
+}
+
+// Records the latency, size and outcome of every request passing through it,
+// so that slow responses from the data source can be told apart from slow
+// rendering in the viewer
+pub struct MetricsDeferredDataSource<T: DeferredDataSource> {
+    data_source: T,
+    metrics: Metrics,
+    infos: VecDeque<Instant>,
+    summary_tiles: Outstanding<TileRequest>,
+    slot_tiles: Outstanding<TileRequest>,
+    slot_meta_tiles: Outstanding<TileRequest>,
+    items_meta: Outstanding<Vec<ItemMetaRequest>>,
+}
+
+impl<T: DeferredDataSource> MetricsDeferredDataSource<T> {
+    pub fn new(data_source: T) -> Self {
+        Self {
+            data_source,
+            metrics: Metrics::default(),
+            infos: VecDeque::new(),
+            summary_tiles: Outstanding::new(),
+            slot_tiles: Outstanding::new(),
+            slot_meta_tiles: Outstanding::new(),
+            items_meta: Outstanding::new(),
+        }
+    }
+
+    pub fn data_source(&self) -> &T {
+        &self.data_source
+    }
+
+    pub fn metrics(&self) -> Metrics {
+        self.metrics
+    }
+
+    // Zeroes the counters, but keeps track of requests still in flight
+    pub fn reset(&mut self) {
+        let mut metrics = Metrics::default();
+        metrics.info.requests = self.metrics.info.in_flight();
+        metrics.summary_tiles.requests = self.metrics.summary_tiles.in_flight();
+        metrics.slot_tiles.requests = self.metrics.slot_tiles.in_flight();
+        metrics.slot_meta_tiles.requests = self.metrics.slot_meta_tiles.in_flight();
+        metrics.items_meta.requests = self.metrics.items_meta.in_flight();
+        self.metrics = metrics;
+    }
+}
+
+impl<T: DeferredDataSource> DeferredDataSource for MetricsDeferredDataSource<T> {
+    fn fetch_description(&self) -> DataSourceDescription {
+        self.data_source.fetch_description()
+    }
+
+    fn fetch_info(&mut self) {
+        self.metrics.info.requests += 1;
+        self.infos.push_back(Instant::now());
+        self.data_source.fetch_info()
+    }
+
+    fn get_infos(&mut self) -> Vec<DataSourceInfoResult> {
+        let result = self.data_source.get_infos();
+        let now = Instant::now();
+        for info in &result {
+            let start = self.infos.pop_front();
+            self.metrics.info.record(
+                start.map(|start| now - start),
+                info.as_ref().map(|_| 0).map_err(String::as_str),
+            );
+        }
+        result
+    }
+
+    fn fetch_summary_tile(
+        &mut self,
+        entry_id: &EntryID,
+        tile_id: TileID,
+        full: bool,
+        priority: RequestPriority,
+    ) {
+        let req = TileRequest {
+            entry_id: entry_id.clone(),
+            tile_id,
+            full,
+        };
+        self.summary_tiles
+            .start(req, &mut self.metrics.summary_tiles);
+        self.data_source
+            .fetch_summary_tile(entry_id, tile_id, full, priority)
+    }
+
+    fn get_summary_tiles(&mut self) -> Vec<SummaryTileResponse> {
+        let result = self.data_source.get_summary_tiles();
+        self.summary_tiles
+            .finish(&result, summary_tile_size, &mut self.metrics.summary_tiles);
+        result
+    }
+
+    fn fetch_slot_tile(
+        &mut self,
+        entry_id: &EntryID,
+        tile_id: TileID,
+        full: bool,
+        priority: RequestPriority,
+    ) {
+        let req = TileRequest {
+            entry_id: entry_id.clone(),
+            tile_id,
+            full,
+        };
+        self.slot_tiles.start(req, &mut self.metrics.slot_tiles);
+        self.data_source
+            .fetch_slot_tile(entry_id, tile_id, full, priority)
+    }
+
+    fn get_slot_tiles(&mut self) -> Vec<SlotTileResponse> {
+        let result = self.data_source.get_slot_tiles();
+        self.slot_tiles
+            .finish(&result, slot_tile_size, &mut self.metrics.slot_tiles);
+        result
+    }
+
+    fn fetch_slot_meta_tile(
+        &mut self,
+        entry_id: &EntryID,
+        tile_id: TileID,
+        full: bool,
+        priority: RequestPriority,
+    ) {
+        let req = TileRequest {
+            entry_id: entry_id.clone(),
+            tile_id,
+            full,
+        };
+        self.slot_meta_tiles
+            .start(req, &mut self.metrics.slot_meta_tiles);
+        self.data_source
+            .fetch_slot_meta_tile(entry_id, tile_id, full, priority)
+    }
+
+    fn get_slot_meta_tiles(&mut self) -> Vec<SlotMetaTileResponse> {
+        let result = self.data_source.get_slot_meta_tiles();
+        self.slot_meta_tiles.finish(
+            &result,
+            slot_meta_tile_size,
+            &mut self.metrics.slot_meta_tiles,
+        );
+        result
+    }
+
+    fn fetch_items_meta(&mut self, requests: &[ItemMetaRequest]) {
+        self.items_meta
+            .start(requests.to_vec(), &mut self.metrics.items_meta);
+        self.data_source.fetch_items_meta(requests)
+    }
+
+    fn get_items_meta(&mut self) -> Vec<ItemsMetaResponse> {
+        let result = self.data_source.get_items_meta();
+        self.items_meta.finish(
+            &result,
+            |items| items.iter().map(item_meta_size).sum(),
+            &mut self.metrics.items_meta,
+        );
+        result
+    }
+
+    fn fetch_tiles(&mut self, kind: TileKind, requests: &[TileRequest], priority: RequestPriority) {
+        let (outstanding, metrics) = match kind {
+            TileKind::Summary => (&mut self.summary_tiles, &mut self.metrics.summary_tiles),
+            TileKind::Slot => (&mut self.slot_tiles, &mut self.metrics.slot_tiles),
+            TileKind::SlotMeta => (&mut self.slot_meta_tiles, &mut self.metrics.slot_meta_tiles),
+        };
+        for req in requests {
+            outstanding.start(req.clone(), metrics);
+        }
+        self.data_source.fetch_tiles(kind, requests, priority)
+    }
+
+    fn cancel(&mut self, entry_id: &EntryID, tile_id: TileID, full: bool) {
+        // Cancelled requests still get a response, which is counted then
+        self.data_source.cancel(entry_id, tile_id, full)
+    }
+
+    fn transfer_stats(&self) -> Option<TransferStats> {
+        self.data_source.transfer_stats()
+    }
+
+    fn retries_failures(&self) -> bool {
+        self.data_source.retries_failures()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::timestamp::{Interval, Timestamp};
+
+    #[test]
+    fn test_outstanding_metrics() {
+        let req = |i: i64| TileRequest {
+            entry_id: EntryID::root(),
+            tile_id: TileID(Interval::new(Timestamp(i), Timestamp(i + 1))),
+            full: false,
+        };
+
+        let mut metrics = RequestMetrics::default();
+        let mut outstanding = Outstanding::new();
+        outstanding.start(req(0), &mut metrics);
+        outstanding.start(req(1), &mut metrics);
+        outstanding.start(req(2), &mut metrics);
+        assert_eq!(metrics.in_flight(), 3);
+        assert_eq!(metrics.mean_latency(), None);
+
+        std::thread::sleep(Duration::from_millis(10));
+        let responses = vec![
+            (Ok(100), req(0)),
+            (Err("failed".to_owned()), req(1)),
+            (Err(CANCELLED.to_owned()), req(2)),
+        ];
+        outstanding.finish(&responses, |size| *size, &mut metrics);
+        assert_eq!(metrics.in_flight(), 0);
+        assert_eq!(metrics.errors, 1);
+        assert_eq!(metrics.cancelled, 1);
+        assert_eq!(metrics.bytes, 100);
+        assert!(metrics.max_latency >= Duration::from_millis(10));
+        assert_eq!(metrics.mean_latency(), Some(metrics.total_latency / 2));
+    }
+}
@@ -0,0 +1,367 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use rusqlite::{Connection, OpenFlags, Result, Row};
+
+use crate::data::{Field, FieldID};
+use crate::timestamp::{Interval, Timestamp};
+use crate::trace_data::{TraceBuilder, TraceDataSource, TraceItem};
+
+// Tables of the SQLite export of an Nsight Systems report (nsys export
+// --type sqlite). Any of them may be missing, depending on what was traced.
+const KERNEL: &str = "CUPTI_ACTIVITY_KIND_KERNEL";
+const MEMCPY: &str = "CUPTI_ACTIVITY_KIND_MEMCPY";
+const MEMSET: &str = "CUPTI_ACTIVITY_KIND_MEMSET";
+const RUNTIME: &str = "CUPTI_ACTIVITY_KIND_RUNTIME";
+const NVTX: &str = "NVTX_EVENTS";
+const PROCESSES: &str = "PROCESSES";
+const THREAD_NAMES: &str = "ThreadNames";
+
+// NVTX event types for push/pop and start/end ranges. Marks have no
+// duration and are skipped.
+const NVTX_PUSH_POP_RANGE: i64 = 59;
+const NVTX_START_END_RANGE: i64 = 60;
+
+fn copy_kind(kind: i64) -> &'static str {
+    match kind {
+        1 => "HtoD",
+        2 => "DtoH",
+        3 => "HtoA",
+        4 => "AtoH",
+        5 => "AtoA",
+        6 => "AtoD",
+        7 => "DtoA",
+        8 => "DtoD",
+        9 => "HtoH",
+        10 => "PtoP",
+        _ => "Unknown",
+    }
+}
+
+// Global IDs pack the process ID above the thread ID
+fn pid(global_id: i64) -> i64 {
+    (global_id >> 24) & 0xFF_FFFF
+}
+
+fn tid(global_id: i64) -> i64 {
+    global_id & 0xFF_FFFF
+}
+
+struct Importer<'a> {
+    connection: &'a Connection,
+    builder: TraceBuilder,
+    process_names: BTreeMap<i64, String>,
+    thread_names: BTreeMap<i64, String>,
+    correlation_field: FieldID,
+}
+
+impl Importer<'_> {
+    fn has_table(&self, table: &str) -> Result<bool> {
+        let mut statement = self
+            .connection
+            .prepare("SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = ?1")?;
+        statement.exists([table])
+    }
+
+    // Runs the query if the table exists, passing each row to f
+    fn query(
+        &mut self,
+        table: &str,
+        sql: &str,
+        mut f: impl FnMut(&mut Self, &Row<'_>) -> Result<()>,
+    ) -> Result<()> {
+        if !self.has_table(table)? {
+            return Ok(());
+        }
+        let connection = self.connection;
+        let mut statement = connection.prepare(sql)?;
+        let mut rows = statement.query([])?;
+        while let Some(row) = rows.next()? {
+            f(self, row)?;
+        }
+        Ok(())
+    }
+
+    fn process_name(&self, pid: i64) -> String {
+        self.process_names
+            .get(&pid)
+            .cloned()
+            .unwrap_or_else(|| format!("Process {pid}"))
+    }
+
+    fn thread_name(&self, global_tid: i64) -> String {
+        self.thread_names
+            .get(&global_tid)
+            .cloned()
+            .unwrap_or_else(|| format!("Thread {}", tid(global_tid)))
+    }
+
+    fn names(&mut self) -> Result<()> {
+        self.query(
+            PROCESSES,
+            "SELECT pid, name FROM PROCESSES",
+            |importer, row| {
+                let name: Option<String> = row.get(1)?;
+                if let Some(name) = name {
+                    let pid = row.get(0)?;
+                    importer
+                        .process_names
+                        .insert(pid, format!("{name} ({pid})"));
+                }
+                Ok(())
+            },
+        )?;
+        self.query(
+            THREAD_NAMES,
+            "SELECT t.globalTid, s.value FROM ThreadNames t JOIN StringIds s ON t.nameId = s.id",
+            |importer, row| {
+                importer.thread_names.insert(row.get(0)?, row.get(1)?);
+                Ok(())
+            },
+        )
+    }
+
+    // Kernels, copies and sets run on a stream of a device
+    fn gpu_item(
+        &mut self,
+        row: &Row<'_>,
+        title: String,
+        fields: Vec<(FieldID, Field)>,
+    ) -> Result<()> {
+        let start = row.get(0)?;
+        let stop = row.get(1)?;
+        let device: i64 = row.get(2)?;
+        let stream: i64 = row.get(3)?;
+        let process = self.process_name(pid(row.get(4)?));
+        let thread =
+            self.builder
+                .thread(&process, "GPU", &format!("Device {device} Stream {stream}"));
+        self.builder.add_item(
+            thread,
+            TraceItem {
+                interval: Interval::new(Timestamp(start), Timestamp(stop)),
+                title,
+                color: None,
+                fields,
+            },
+        );
+        Ok(())
+    }
+
+    // NVTX ranges and CUDA API calls run on a CPU thread
+    fn thread_item(
+        &mut self,
+        row: &Row<'_>,
+        kind: &str,
+        title: String,
+        fields: Vec<(FieldID, Field)>,
+    ) -> Result<()> {
+        let start = row.get(0)?;
+        let stop = row.get(1)?;
+        let global_tid = row.get(2)?;
+        let process = self.process_name(pid(global_tid));
+        let thread = self
+            .builder
+            .thread(&process, kind, &self.thread_name(global_tid));
+        self.builder.add_item(
+            thread,
+            TraceItem {
+                interval: Interval::new(Timestamp(start), Timestamp(stop)),
+                title,
+                color: None,
+                fields,
+            },
+        );
+        Ok(())
+    }
+
+    fn kernels(&mut self) -> Result<()> {
+        let kernel_field = self.builder.field("Kernel", true);
+        let grid_field = self.builder.field("Grid", false);
+        let block_field = self.builder.field("Block", false);
+        self.query(
+            KERNEL,
+            "SELECT k.start, k.end, k.deviceId, k.streamId, k.globalPid, s.value, d.value,
+                    k.gridX, k.gridY, k.gridZ, k.blockX, k.blockY, k.blockZ, k.correlationId
+             FROM CUPTI_ACTIVITY_KIND_KERNEL k
+             JOIN StringIds s ON k.shortName = s.id
+             LEFT JOIN StringIds d ON k.demangledName = d.id
+             ORDER BY k.start",
+            |importer, row| {
+                let dim = |i: usize| -> Result<String> {
+                    let (x, y, z): (i64, i64, i64) =
+                        (row.get(i)?, row.get(i + 1)?, row.get(i + 2)?);
+                    Ok(format!("({x}, {y}, {z})"))
+                };
+                let mut fields = vec![
+                    (grid_field, Field::String(dim(7)?)),
+                    (block_field, Field::String(dim(10)?)),
+                    (importer.correlation_field, Field::I64(row.get(13)?)),
+                ];
+                let demangled: Option<String> = row.get(6)?;
+                if let Some(demangled) = demangled {
+                    fields.insert(0, (kernel_field, Field::String(demangled)));
+                }
+                importer.gpu_item(row, row.get(5)?, fields)
+            },
+        )
+    }
+
+    fn copies(&mut self) -> Result<()> {
+        let bytes_field = self.builder.field("Bytes", false);
+        self.query(
+            MEMCPY,
+            "SELECT start, end, deviceId, streamId, globalPid, copyKind, bytes, correlationId
+             FROM CUPTI_ACTIVITY_KIND_MEMCPY ORDER BY start",
+            |importer, row| {
+                let title = format!("Memcpy {}", copy_kind(row.get(5)?));
+                let fields = vec![
+                    (bytes_field, Field::I64(row.get(6)?)),
+                    (importer.correlation_field, Field::I64(row.get(7)?)),
+                ];
+                importer.gpu_item(row, title, fields)
+            },
+        )?;
+        self.query(
+            MEMSET,
+            "SELECT start, end, deviceId, streamId, globalPid, bytes, correlationId
+             FROM CUPTI_ACTIVITY_KIND_MEMSET ORDER BY start",
+            |importer, row| {
+                let fields = vec![
+                    (bytes_field, Field::I64(row.get(5)?)),
+                    (importer.correlation_field, Field::I64(row.get(6)?)),
+                ];
+                importer.gpu_item(row, "Memset".to_owned(), fields)
+            },
+        )
+    }
+
+    fn runtime(&mut self) -> Result<()> {
+        self.query(
+            RUNTIME,
+            "SELECT r.start, r.end, r.globalTid, s.value, r.correlationId
+             FROM CUPTI_ACTIVITY_KIND_RUNTIME r JOIN StringIds s ON r.nameId = s.id
+             ORDER BY r.start",
+            |importer, row| {
+                let fields = vec![(importer.correlation_field, Field::I64(row.get(4)?))];
+                importer.thread_item(row, "CUDA API", row.get(3)?, fields)
+            },
+        )
+    }
+
+    fn nvtx(&mut self) -> Result<()> {
+        let sql = format!(
+            "SELECT n.start, n.end, n.globalTid, COALESCE(n.text, s.value, '')
+             FROM NVTX_EVENTS n LEFT JOIN StringIds s ON n.textId = s.id
+             WHERE n.end IS NOT NULL AND n.eventType IN ({NVTX_PUSH_POP_RANGE}, {NVTX_START_END_RANGE})
+             ORDER BY n.start"
+        );
+        self.query(NVTX, &sql, |importer, row| {
+            importer.thread_item(row, "NVTX", row.get(3)?, Vec::new())
+        })
+    }
+}
+
+// Loads the SQLite export of an Nsight Systems report into memory. Each
+// process becomes a node, with GPU kernels and copies in a slot per stream,
+// and NVTX ranges and CUDA API calls in a slot per thread. Correlation IDs
+// tie API calls to the work they launched.
+pub fn load_nsys_report(path: impl AsRef<Path>) -> Result<TraceDataSource> {
+    let path = path.as_ref();
+    let connection = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+    import(&connection, path.to_string_lossy())
+}
+
+fn import(connection: &Connection, source_locator: impl Into<String>) -> Result<TraceDataSource> {
+    let mut builder = TraceBuilder::new(source_locator);
+    let correlation_field = builder.field("Correlation ID", false);
+    let mut importer = Importer {
+        connection,
+        builder,
+        process_names: BTreeMap::new(),
+        thread_names: BTreeMap::new(),
+        correlation_field,
+    };
+    importer.names()?;
+    importer.kernels()?;
+    importer.copies()?;
+    importer.runtime()?;
+    importer.nvtx()?;
+    Ok(importer.builder.build())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::data::{DataSource, EntryID, EntryInfo, TileID};
+
+    #[test]
+    fn test_nsys_report() {
+        let connection = Connection::open_in_memory().unwrap();
+        let global_tid = (42 << 24) | 43;
+        connection
+            .execute_batch(&format!(
+                "CREATE TABLE StringIds (id INTEGER PRIMARY KEY, value TEXT NOT NULL);
+                 INSERT INTO StringIds VALUES (1, 'saxpy'), (2, 'cudaLaunchKernel'), (3, 'main');
+                 CREATE TABLE PROCESSES (globalPid INTEGER, pid INTEGER, name TEXT);
+                 INSERT INTO PROCESSES VALUES ({}, 42, 'app');
+                 CREATE TABLE ThreadNames (nameId INTEGER, priority INTEGER, globalTid INTEGER);
+                 INSERT INTO ThreadNames VALUES (3, 0, {global_tid});
+                 CREATE TABLE CUPTI_ACTIVITY_KIND_KERNEL (
+                     start INTEGER, end INTEGER, deviceId INTEGER, streamId INTEGER,
+                     globalPid INTEGER, shortName INTEGER, demangledName INTEGER,
+                     gridX INTEGER, gridY INTEGER, gridZ INTEGER,
+                     blockX INTEGER, blockY INTEGER, blockZ INTEGER, correlationId INTEGER);
+                 INSERT INTO CUPTI_ACTIVITY_KIND_KERNEL VALUES
+                     (150, 400, 0, 7, {}, 1, NULL, 64, 1, 1, 256, 1, 1, 9);
+                 CREATE TABLE CUPTI_ACTIVITY_KIND_RUNTIME (
+                     start INTEGER, end INTEGER, globalTid INTEGER, nameId INTEGER,
+                     correlationId INTEGER);
+                 INSERT INTO CUPTI_ACTIVITY_KIND_RUNTIME VALUES (100, 120, {global_tid}, 2, 9);
+                 CREATE TABLE NVTX_EVENTS (
+                     start INTEGER, end INTEGER, eventType INTEGER, text TEXT, textId INTEGER,
+                     globalTid INTEGER);
+                 INSERT INTO NVTX_EVENTS VALUES
+                     (0, 500, {NVTX_PUSH_POP_RANGE}, 'step', NULL, {global_tid}),
+                     (50, NULL, 34, 'mark', NULL, {global_tid});",
+                42i64 << 24,
+                42i64 << 24,
+            ))
+            .unwrap();
+
+        let ds = import(&connection, "test.sqlite").unwrap();
+        let info = ds.fetch_info();
+        assert_eq!(info.interval, Interval::new(Timestamp(0), Timestamp(500)));
+        assert_eq!(
+            info.entry_info.kinds(),
+            vec!["gpu".to_owned(), "cuda api".to_owned(), "nvtx".to_owned()]
+        );
+
+        let process = EntryID::root().child(0);
+        let Some(EntryInfo::Panel { long_name, .. }) = info.entry_info.get(&process) else {
+            panic!("expected a panel");
+        };
+        assert_eq!(long_name, "app (42)");
+
+        let stream = process.child(0).child(0);
+        let Some(EntryInfo::Slot { short_name, .. }) = info.entry_info.get(&stream) else {
+            panic!("expected a slot");
+        };
+        assert_eq!(short_name, "Device 0 Stream 7");
+        let meta = ds.fetch_slot_meta_tile(&stream, TileID(info.interval), true);
+        let kernel = &meta.data.items[0][0];
+        assert_eq!(kernel.title, "saxpy");
+        assert_eq!(kernel.fields.len(), 4);
+
+        let nvtx = process.child(2).child(0);
+        let Some(EntryInfo::Slot { short_name, .. }) = info.entry_info.get(&nvtx) else {
+            panic!("expected a slot");
+        };
+        assert_eq!(short_name, "main");
+        let meta = ds.fetch_slot_meta_tile(&nvtx, TileID(info.interval), true);
+        assert_eq!(meta.data.items.len(), 1);
+        assert_eq!(meta.data.items[0].len(), 1);
+        assert_eq!(meta.data.items[0][0].title, "step");
+    }
+}
@@ -1,88 +1,168 @@
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
 use std::ffi::{CString, OsString};
 use std::ffi::{c_char, c_void};
+use std::fmt;
 use std::io;
-use std::iter::zip;
 use std::mem::size_of;
 use std::ptr::{null, null_mut};
+use std::sync::{Mutex, mpsc};
+use std::thread;
+use std::time::{Duration, Instant};
 
 use nvtxw::nvtxw;
+use regex::Regex;
 
-use crate::data::{DataSourceInfo, EntryID, EntryIndex, EntryInfo, SlotMetaTile, SlotTile, TileID};
-use crate::deferred_data::{CountingDeferredDataSource, DeferredDataSource};
+use crate::async_data::{AsyncDeferredDataSource, DeferredDataSourceAsyncWrapper, block_on};
+use crate::data::{DataSourceInfo, EntryID, Field, FieldID, TileID, UtilPoint};
+use crate::deferred_data::DeferredDataSource;
+use crate::export::{
+    ExportEntry, ExportItem, Exporter, TileError, clamp_interval, export_entries, field_text,
+    walk_entries,
+};
+use crate::timestamp::{Interval, Timestamp};
 
 const LEGION_DOMAIN_NAME: &str = "Legion";
 
+// How often to report progress while exporting
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Default)]
+pub struct NVTXWConfig {
+    // Don't print anything while exporting
+    pub quiet: bool,
+    // Only export entries whose name or path (e.g., "Legion/n0/gpu/g0")
+    // matches include, if given, and doesn't match exclude
+    pub include: Option<Regex>,
+    pub exclude: Option<Regex>,
+    // Only export this part of the profile, rather than all of it
+    pub interval: Option<Interval>,
+    // Number of threads that turn tiles into events while others are
+    // fetched (0 means one per core)
+    pub threads: usize,
+}
+
+// Everything that NVTXW::with_config takes besides the source and output,
+// for exporting by format name
+#[derive(Debug, Clone, Default)]
+pub struct NVTXWOptions {
+    pub backend: Option<OsString>,
+    pub force: bool,
+    pub merge: Option<OsString>,
+    pub zero_time: i64,
+    pub config: NVTXWConfig,
+}
+
+impl NVTXWConfig {
+    fn keeps(&self, long_name: &str, hierarchy: &str) -> bool {
+        let matches = |regex: &Regex| regex.is_match(long_name) || regex.is_match(hierarchy);
+        self.include.as_ref().is_none_or(matches) && !self.exclude.as_ref().is_some_and(matches)
+    }
+}
+
+#[derive(Debug)]
+pub enum NVTXWError {
+    // The data source couldn't describe the profile
+    Info(String),
+    // The data source can't be exported
+    Unsupported(&'static str),
+    // The requested interval doesn't overlap the profile
+    Interval {
+        interval: Interval,
+        profile: Interval,
+    },
+    // A tile failed to load, or couldn't be turned into events
+    Tile {
+        entry: String,
+        tile_id: TileID,
+        message: String,
+    },
+    // A call into NVTXW failed
+    Call {
+        call: &'static str,
+        code: nvtxw::ResultCode,
+    },
+}
+
+impl fmt::Display for NVTXWError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NVTXWError::Info(message) => write!(f, "failed to fetch profile info: {message}"),
+            NVTXWError::Unsupported(message) => write!(f, "{message}"),
+            NVTXWError::Interval { interval, profile } => write!(
+                f,
+                "export interval {interval} does not overlap the profile, which is {profile}"
+            ),
+            NVTXWError::Tile {
+                entry,
+                tile_id,
+                message,
+            } => write!(f, "failed to export {entry} over {}: {message}", tile_id.0),
+            NVTXWError::Call { call, code } => write!(f, "NVTXW failed to {call} (error {code})"),
+        }
+    }
+}
+
+impl std::error::Error for NVTXWError {}
+
+impl From<TileError> for NVTXWError {
+    fn from(e: TileError) -> Self {
+        NVTXWError::Tile {
+            entry: e.entry,
+            tile_id: e.tile_id,
+            message: e.message,
+        }
+    }
+}
+
+impl From<NVTXWError> for io::Error {
+    fn from(e: NVTXWError) -> Self {
+        let kind = match e {
+            NVTXWError::Unsupported(..) | NVTXWError::Interval { .. } => {
+                io::ErrorKind::InvalidInput
+            }
+            _ => io::ErrorKind::Other,
+        };
+        io::Error::new(kind, e)
+    }
+}
+
+fn check<T>(result: Result<T, nvtxw::ResultCode>, call: &'static str) -> Result<T, NVTXWError> {
+    result.map_err(|code| NVTXWError::Call { call, code })
+}
+
+// Titles and fields are arbitrary text, so drop any NULs rather than fail
+fn c_string(s: String) -> CString {
+    CString::new(s).unwrap_or_else(|e| {
+        let mut bytes = e.into_vec();
+        bytes.retain(|b| *b != 0);
+        CString::new(bytes).expect("NULs were removed")
+    })
+}
+
 pub struct NVTXW<T: DeferredDataSource> {
-    data_source: CountingDeferredDataSource<T>,
+    data_source: DeferredDataSourceAsyncWrapper<T>,
     backend: Option<OsString>,
     output: OsString,
     force: bool,
     merge: Option<OsString>,
     zero_time: i64,
+    config: NVTXWConfig,
 }
 
-type ResultVec = Vec<(EntryID, String, String)>;
-type UnmatchedTileHold = BTreeMap<EntryID, (Option<SlotTile>, Option<SlotMetaTile>)>;
-
-fn walk_entry_list(info: &EntryInfo) -> ResultVec {
-    let mut result = Vec::new();
-    fn walk(info: &EntryInfo, entry_id: EntryID, result: &mut ResultVec, hierarchy: String) {
-        match info {
-            EntryInfo::Panel {
-                summary,
-                slots,
-                short_name,
-                ..
-            } => {
-                if let Some(summary) = summary {
-                    walk(
-                        summary,
-                        entry_id.summary(),
-                        result,
-                        if entry_id.level() > 0 {
-                            format!("{}/{}", hierarchy, short_name)
-                        } else {
-                            hierarchy.clone()
-                        },
-                    );
-                }
-                for (i, slot) in slots.iter().enumerate() {
-                    walk(
-                        slot,
-                        entry_id.child(i as u64),
-                        result,
-                        if entry_id.level() > 0 {
-                            format!("{}/{}", hierarchy, short_name)
-                        } else {
-                            hierarchy.clone()
-                        },
-                    )
-                }
-            }
-            EntryInfo::Slot {
-                long_name,
-                short_name,
-                ..
-            } => {
-                result.push((
-                    entry_id.clone(),
-                    long_name.clone(),
-                    format!("{}/{}", hierarchy, short_name),
-                ));
-            }
-            EntryInfo::Summary { .. } => {
-                // When implementing counters, fill this in.
-            }
-        }
-    }
-    walk(
-        info,
-        EntryID::root(),
-        &mut result,
-        LEGION_DOMAIN_NAME.to_string(),
+// Where NVTXW puts the entry, e.g., "Legion/n0/gpu/g0". Summaries go in
+// the panel they summarize
+fn hierarchy(entry: &ExportEntry) -> String {
+    let mut names = vec![LEGION_DOMAIN_NAME];
+    names.extend(
+        entry
+            .panels
+            .iter()
+            .map(|(short_name, _)| short_name.as_str()),
     );
-    result
+    if entry.summary.is_none() {
+        names.push(&entry.short_name);
+    }
+    names.join("/")
 }
 
 #[repr(C)]
@@ -94,155 +174,489 @@ struct legion_nvtxw_event {
     color: u32,
 }
 
+#[repr(C)]
+#[derive(Debug)]
+struct legion_nvtxw_counter {
+    time: u64,
+    value: f64,
+}
+
 // See nvToolsExtPayload.h: nvtxPayloadSchemaAttr_t::schemaId
 // See NVTX_PAYLOAD_ENTRY_TYPE_SCHEMA_ID_STATIC_START
 const LEGION_NVTXW_PAYLOAD_SCHEMA_ID: u64 = 0x1c0ffee;
 const LEGION_NVTXW_PAYLOAD_NAME_SCHEMA_ID: u64 = 0x2c0ffee;
+const LEGION_NVTXW_PAYLOAD_COUNTER_SCHEMA_ID: u64 = 0x3c0ffee;
+// Item fields take the IDs from here on, see FieldKind::schema_id
+const LEGION_NVTXW_PAYLOAD_FIELD_SCHEMA_ID: u64 = 0x4c0ffee;
+
+// Item fields are written as extra payloads, one per field, with a schema for
+// each kind of value a field can have. Integers keep their type, and
+// everything else is written as the text the viewer would show
+#[derive(Debug, Clone, Copy)]
+enum FieldKind {
+    I64,
+    U64,
+    Text,
+}
 
-impl<T: DeferredDataSource> NVTXW<T> {
-    pub fn new(
-        data_source: T,
-        backend: Option<OsString>,
-        output: OsString,
-        force: bool,
-        merge: Option<OsString>,
-        zero_time: i64,
-    ) -> Self {
+impl FieldKind {
+    const ALL: [FieldKind; 3] = [FieldKind::I64, FieldKind::U64, FieldKind::Text];
+
+    // field is the index of the field in the FieldSchema
+    fn schema_id(self, field: usize) -> u64 {
+        LEGION_NVTXW_PAYLOAD_FIELD_SCHEMA_ID + (Self::ALL.len() * field + self as usize) as u64
+    }
+}
+
+enum FieldValue {
+    I64(i64),
+    U64(u64),
+    Text(CString),
+}
+
+impl FieldValue {
+    fn new(field: &Field) -> Option<Self> {
+        match field {
+            Field::I64(value) => Some(FieldValue::I64(*value)),
+            Field::U64(value) => Some(FieldValue::U64(*value)),
+            Field::Empty => None,
+            _ => Some(FieldValue::Text(c_string(field_text(field)))),
+        }
+    }
+
+    fn kind(&self) -> FieldKind {
+        match self {
+            FieldValue::I64(_) => FieldKind::I64,
+            FieldValue::U64(_) => FieldKind::U64,
+            FieldValue::Text(_) => FieldKind::Text,
+        }
+    }
+
+    fn payload(&self, schema_id: u64) -> nvtxw::PayloadData {
+        let (size, payload) = match self {
+            FieldValue::I64(value) => (size_of::<i64>(), value as *const i64 as *const c_void),
+            FieldValue::U64(value) => (size_of::<u64>(), value as *const u64 as *const c_void),
+            FieldValue::Text(value) => (usize::MAX, value.as_ptr() as *const c_void),
+        };
+        nvtxw::PayloadData {
+            schemaId: schema_id,
+            size,
+            payload,
+        }
+    }
+}
+
+// What the driver hands over for each entry, to be turned into events
+enum EntryData {
+    Counters(Vec<UtilPoint>),
+    Items(Vec<ExportItem>),
+}
+
+// A legion_nvtxw_event that owns its name and fields, so that it can be sent
+// between threads
+struct Range {
+    time_start: u64,
+    time_stop: u64,
+    name: CString,
+    color: u32,
+    // Schema ID and value of each field
+    fields: Vec<(u64, FieldValue)>,
+}
+
+// Everything to write for one entry
+enum Events {
+    Counters(EntryID, Vec<legion_nvtxw_counter>),
+    Ranges(EntryID, Vec<Range>),
+}
+
+// Exports of large profiles take minutes, so say how far along they are
+struct Progress {
+    quiet: bool,
+    total_entries: usize,
+    entries: usize,
+    events: usize,
+    start: Instant,
+    last_report: Instant,
+}
+
+impl Progress {
+    fn new(total_entries: usize, quiet: bool) -> Self {
+        let now = Instant::now();
         Self {
-            data_source: CountingDeferredDataSource::new(data_source),
-            backend,
-            output,
-            force,
-            merge,
-            zero_time,
+            quiet,
+            total_entries,
+            entries: 0,
+            events: 0,
+            start: now,
+            last_report: now,
         }
     }
 
-    fn check_info(&mut self) -> Option<DataSourceInfo> {
-        // We requested this once, so we know we'll get zero or one result
-        self.data_source.get_infos().pop()
+    fn entry_done(&mut self, events: usize) {
+        self.entries += 1;
+        self.events += events;
+        if self.quiet || self.last_report.elapsed() < PROGRESS_INTERVAL {
+            return;
+        }
+        self.last_report = Instant::now();
+
+        // Assume the remaining entries take as long as the ones so far
+        let elapsed = self.start.elapsed();
+        let remaining = (self.total_entries - self.entries) as u32;
+        let eta = elapsed / self.entries as u32 * remaining;
+        println!(
+            "Exported {}/{} entries, {} events, about {}s left",
+            self.entries,
+            self.total_entries,
+            self.events,
+            eta.as_secs()
+        );
     }
 
-    fn write_matched_tile(
-        interface: &nvtxw::InterfaceHandle,
-        streams: &BTreeMap<EntryID, nvtxw::StreamHandle>,
-        zero_time: i64,
-        tile: &SlotTile,
-        meta_tile: &SlotMetaTile,
-    ) {
-        assert!(tile.data.items.len() == meta_tile.data.items.len());
+    fn finish(&self) {
+        if !self.quiet {
+            println!(
+                "Exported {} entries, {} events in {}s",
+                self.entries,
+                self.events,
+                self.start.elapsed().as_secs()
+            );
+        }
+    }
+}
 
-        for (row, meta_row) in zip(&tile.data.items, &meta_tile.data.items) {
-            assert!(row.len() == meta_row.len());
+fn nvtx_time(time: Timestamp, zero_time: i64) -> Result<u64, String> {
+    zero_time
+        .checked_add(time.0)
+        .and_then(|t| u64::try_from(t).ok())
+        .ok_or_else(|| format!("timestamp {time} is out of range"))
+}
 
-            for (item, meta_item) in zip(row, meta_row) {
-                let time_start = item.interval.start;
-                let time_stop = item.interval.stop;
+fn prepare_events(
+    entry_id: EntryID,
+    data: EntryData,
+    interval: Interval,
+    zero_time: i64,
+    field_indices: &BTreeMap<FieldID, usize>,
+) -> Result<Events, String> {
+    match data {
+        EntryData::Counters(points) => {
+            let counters = points
+                .iter()
+                .map(|point| {
+                    Ok(legion_nvtxw_counter {
+                        time: nvtx_time(point.time, zero_time)?,
+                        value: point.util as f64,
+                    })
+                })
+                .collect::<Result<_, String>>()?;
+            Ok(Events::Counters(entry_id, counters))
+        }
+        EntryData::Items(items) => {
+            let mut ranges = Vec::new();
+            for item in items {
+                // Items are cut off at the edges of the export
+                let time = item.interval.intersection(interval);
                 let color = item.color;
-                // let time_start = meta_item.original_interval.start;
-                // let time_stop = meta_item.original_interval.stop;
-                let title = meta_item.title.clone();
-
-                let c_name = CString::new(title).expect("CString::new failed");
-                let events = [legion_nvtxw_event {
-                    time_start: (time_start.0 as u64)
-                        .checked_add(zero_time.try_into().unwrap())
-                        .expect("time_start overflowed"),
-                    time_stop: (time_stop.0 as u64)
-                        .checked_add(zero_time.try_into().unwrap())
-                        .expect("time_stop overflowed"),
-                    name: c_name.as_ptr(),
+
+                let fields = item
+                    .meta
+                    .fields
+                    .iter()
+                    .filter_map(|(field_id, field, _)| {
+                        let index = *field_indices.get(field_id)?;
+                        let value = FieldValue::new(field)?;
+                        Some((value.kind().schema_id(index), value))
+                    })
+                    .collect();
+
+                ranges.push(Range {
+                    time_start: nvtx_time(time.start, zero_time)?,
+                    time_stop: nvtx_time(time.stop, zero_time)?,
+                    name: c_string(item.meta.title),
                     color: ((color.r() as u32) << 16)
                         | ((color.g() as u32) << 8)
                         | (color.b() as u32)
                         | (0xFF << 24),
-                }];
-
-                let stream = streams[&tile.entry_id];
-
-                let payloads = [
-                    nvtxw::PayloadData {
-                        schemaId: LEGION_NVTXW_PAYLOAD_NAME_SCHEMA_ID,
-                        size: usize::MAX,
-                        payload: c_name.as_ptr() as *const c_void,
-                    },
-                    nvtxw::PayloadData {
-                        schemaId: LEGION_NVTXW_PAYLOAD_SCHEMA_ID,
-                        size: size_of::<legion_nvtxw_event>(),
-                        payload: events.as_ptr() as *const c_void,
-                    },
-                ];
-
-                nvtxw::event_write(interface, stream, &payloads).expect("Failed to write event");
+                    fields,
+                });
             }
+            Ok(Events::Ranges(entry_id, ranges))
         }
     }
+}
 
-    fn process_events(
-        data_source: &mut CountingDeferredDataSource<T>,
-        interface: &nvtxw::InterfaceHandle,
-        streams: &BTreeMap<EntryID, nvtxw::StreamHandle>,
-        zero_time: i64,
-        unmatched_tiles: &mut UnmatchedTileHold,
-        num_requests: u64,
-    ) {
-        while data_source.outstanding_requests() > num_requests {
-            // When implementing counters, uncomment this.
-            // let summary_tiles = data_source.get_summary_tiles();
-            let slot_tiles = data_source.get_slot_tiles();
-            let slot_meta_tiles = data_source.get_slot_meta_tiles();
-
-            for (tile, _) in slot_tiles {
-                let tile = tile.expect("writing slot tile failed");
-                let e = tile.entry_id.clone();
-                unmatched_tiles.entry(e).or_insert((None, None)).0 = Some(tile);
-            }
+// Hands entries to the worker threads, and writes the events that come back
+struct NVTXWExporter<'a> {
+    interface: &'a nvtxw::InterfaceHandle,
+    streams: &'a BTreeMap<EntryID, nvtxw::StreamHandle>,
+    field_schemas: &'a BTreeMap<u64, nvtxw::PayloadSchemaAttr>,
+    registered: BTreeMap<EntryID, BTreeSet<u64>>,
+    // Long names, for errors
+    names: BTreeMap<EntryID, String>,
+    tile_id: TileID,
+    // Dropped when the export finishes, so that the workers stop
+    entries_tx: Option<mpsc::Sender<(EntryID, EntryData)>>,
+    events_rx: mpsc::Receiver<(EntryID, Result<Events, String>)>,
+    progress: Progress,
+}
+
+impl NVTXWExporter<'_> {
+    fn send(&mut self, entry: &ExportEntry, data: EntryData) -> Result<(), NVTXWError> {
+        self.entries_tx
+            .as_ref()
+            .expect("workers run until the export finishes")
+            .send((entry.entry_id.clone(), data))
+            .expect("workers outlive the export");
+        Ok(())
+    }
+
+    fn write(
+        &mut self,
+        entry_id: EntryID,
+        events: Result<Events, String>,
+    ) -> Result<(), NVTXWError> {
+        let events = events.map_err(|message| NVTXWError::Tile {
+            entry: self.names[&entry_id].clone(),
+            tile_id: self.tile_id,
+            message,
+        })?;
+        let count = self.write_events(&events)?;
+        self.progress.entry_done(count);
+        Ok(())
+    }
 
-            for (meta_tile, _) in slot_meta_tiles {
-                let meta_tile = meta_tile.expect("writing slot meta tile failed");
-                let e = meta_tile.entry_id.clone();
-                unmatched_tiles.entry(e).or_insert((None, None)).1 = Some(meta_tile);
+    fn write_events(&mut self, events: &Events) -> Result<usize, NVTXWError> {
+        let interface = self.interface;
+        match events {
+            Events::Counters(entry_id, counters) => {
+                let stream = self.streams[entry_id];
+                for counter in counters {
+                    let payloads = [nvtxw::PayloadData {
+                        schemaId: LEGION_NVTXW_PAYLOAD_COUNTER_SCHEMA_ID,
+                        size: size_of::<legion_nvtxw_counter>(),
+                        payload: counter as *const legion_nvtxw_counter as *const c_void,
+                    }];
+
+                    check(
+                        nvtxw::event_write(interface, stream, &payloads),
+                        "write counter",
+                    )?;
+                }
+                Ok(counters.len())
             }
+            Events::Ranges(entry_id, ranges) => {
+                let stream = self.streams[entry_id];
+                // Field schemas are registered on a stream when it first
+                // needs them, since most streams use only a few
+                let registered = self.registered.entry(entry_id.clone()).or_default();
+                for range in ranges {
+                    for (schema_id, _) in &range.fields {
+                        if registered.insert(*schema_id) {
+                            check(
+                                nvtxw::schema_register(
+                                    interface,
+                                    stream,
+                                    &self.field_schemas[schema_id],
+                                ),
+                                "register field schema",
+                            )?;
+                        }
+                    }
+
+                    let events = [legion_nvtxw_event {
+                        time_start: range.time_start,
+                        time_stop: range.time_stop,
+                        name: range.name.as_ptr(),
+                        color: range.color,
+                    }];
+
+                    let mut payloads = vec![
+                        nvtxw::PayloadData {
+                            schemaId: LEGION_NVTXW_PAYLOAD_NAME_SCHEMA_ID,
+                            size: usize::MAX,
+                            payload: range.name.as_ptr() as *const c_void,
+                        },
+                        nvtxw::PayloadData {
+                            schemaId: LEGION_NVTXW_PAYLOAD_SCHEMA_ID,
+                            size: size_of::<legion_nvtxw_event>(),
+                            payload: events.as_ptr() as *const c_void,
+                        },
+                    ];
+                    payloads.extend(
+                        range
+                            .fields
+                            .iter()
+                            .map(|(schema_id, value)| value.payload(*schema_id)),
+                    );
 
-            unmatched_tiles.retain(|_entry_id, (ut, um)| {
-                if let (Some(tile), Some(meta_tile)) = (ut, um) {
-                    Self::write_matched_tile(interface, streams, zero_time, tile, meta_tile);
-                    return false;
+                    check(
+                        nvtxw::event_write(interface, stream, &payloads),
+                        "write event",
+                    )?;
                 }
-                true
-            });
+                Ok(ranges.len())
+            }
+        }
+    }
+}
+
+impl Exporter for NVTXWExporter<'_> {
+    type Error = NVTXWError;
+
+    // Counters come from the summaries
+    const SUMMARIES: bool = true;
+
+    fn write_slot(
+        &mut self,
+        entry: &ExportEntry,
+        items: Vec<ExportItem>,
+    ) -> Result<(), NVTXWError> {
+        self.send(entry, EntryData::Items(items))
+    }
+
+    fn write_summary(
+        &mut self,
+        entry: &ExportEntry,
+        points: Vec<UtilPoint>,
+    ) -> Result<(), NVTXWError> {
+        self.send(entry, EntryData::Counters(points))
+    }
+
+    fn idle(&mut self) -> Result<bool, NVTXWError> {
+        let mut busy = false;
+        while let Ok((entry_id, events)) = self.events_rx.try_recv() {
+            self.write(entry_id, events)?;
+            busy = true;
+        }
+        Ok(busy)
+    }
+
+    fn finish(&mut self) -> Result<(), NVTXWError> {
+        // Finish whatever the workers still have
+        self.entries_tx = None;
+        while let Ok((entry_id, events)) = self.events_rx.recv() {
+            self.write(entry_id, events)?;
+        }
+        self.progress.finish();
+        Ok(())
+    }
+}
+
+impl<T: DeferredDataSource + 'static> NVTXW<T> {
+    pub fn new(
+        data_source: T,
+        backend: Option<OsString>,
+        output: OsString,
+        force: bool,
+        merge: Option<OsString>,
+        zero_time: i64,
+    ) -> Self {
+        Self::with_config(
+            data_source,
+            backend,
+            output,
+            force,
+            merge,
+            zero_time,
+            NVTXWConfig::default(),
+        )
+    }
+
+    pub fn with_config(
+        data_source: T,
+        backend: Option<OsString>,
+        output: OsString,
+        force: bool,
+        merge: Option<OsString>,
+        zero_time: i64,
+        config: NVTXWConfig,
+    ) -> Self {
+        Self {
+            data_source: DeferredDataSourceAsyncWrapper::new(data_source),
+            backend,
+            output,
+            force,
+            merge,
+            zero_time,
+            config,
         }
     }
 
-    pub fn write(mut self) -> io::Result<()> {
-        self.data_source.fetch_info();
-        let mut info = None;
-        while info.is_none() {
-            info = self.check_info();
+    pub fn write(self) -> Result<(), NVTXWError> {
+        let info = block_on(self.data_source.fetch_info()).map_err(NVTXWError::Info)?;
+
+        // Event names and fields come from the item metadata
+        if !info.capabilities.slot_meta_tiles {
+            return Err(NVTXWError::Unsupported(
+                "data source does not provide item metadata, which NVTXW export requires",
+            ));
         }
-        let info = info.unwrap();
 
-        let entry_ids = walk_entry_list(&info.entry_info);
+        // Counters come from the summaries, if the source has them
+        let mut entries = walk_entries(&info.entry_info, info.capabilities.summary_tiles);
+        entries.retain(|entry| self.config.keeps(&entry.long_name, &hierarchy(entry)));
 
-        let full_range_tile_id = TileID(info.interval);
-        let full = true;
+        let interval =
+            clamp_interval(self.config.interval, info.interval).ok_or(NVTXWError::Interval {
+                interval: self.config.interval.unwrap_or(info.interval),
+                profile: info.interval,
+            })?;
 
-        // For now, this only works on dynamic data sources
-        assert!(info.tile_set.tiles.is_empty());
+        if !self.config.quiet {
+            println!("Exporting {} entries to NVTXW", entries.len());
+        }
 
-        println!("Exporting to NVTXW");
+        let interface = check(nvtxw::initialize_simple(self.backend.clone()), "initialize")?;
 
-        let interface = nvtxw::initialize_simple(self.backend).expect("Failed to initialize NVTXW");
+        let result = check(
+            nvtxw::session_begin_simple(
+                &interface,
+                self.output.clone(),
+                self.force,
+                self.merge.clone(),
+            ),
+            "create session",
+        )
+        .and_then(|session| {
+            let mut streams = BTreeMap::new();
+            let mut result =
+                self.write_session(&interface, session, &info, &entries, interval, &mut streams);
+
+            // Close whatever was opened, even if the export failed part way
+            // through, but report the first error
+            for stream in streams.into_values() {
+                result = result.and(check(
+                    nvtxw::stream_close(&interface, stream),
+                    "close stream",
+                ));
+            }
+            result.and(check(
+                nvtxw::session_end(&interface, session),
+                "end session",
+            ))
+        });
 
-        let session = nvtxw::session_begin_simple(&interface, self.output, self.force, self.merge)
-            .expect("Failed to create session");
+        nvtxw::unload(&interface);
 
-        let c_event_name = CString::new("Legion Event").expect("CString::new failed");
+        result
+    }
 
-        let c_field_name_time_start = CString::new("time_start").expect("CString::new failed");
-        let c_field_name_time_stop = CString::new("time_stop").expect("CString::new failed");
-        let c_field_name_name = CString::new("name").expect("CString::new failed");
-        let c_field_name_color = CString::new("color").expect("CString::new failed");
+    fn write_session(
+        &self,
+        interface: &nvtxw::InterfaceHandle,
+        session: nvtxw::SessionHandle,
+        info: &DataSourceInfo,
+        entries: &[ExportEntry],
+        interval: Interval,
+        streams: &mut BTreeMap<EntryID, nvtxw::StreamHandle>,
+    ) -> Result<(), NVTXWError> {
+        let c_event_name = c"Legion Event";
+
+        let c_field_name_time_start = c"time_start";
+        let c_field_name_time_stop = c"time_stop";
+        let c_field_name_name = c"name";
+        let c_field_name_color = c"color";
 
         // C string fields must be specified as their own payload in addition to a field if their size is dynamic at runtime.
 
@@ -339,75 +753,214 @@ impl<T: DeferredDataSource> NVTXW<T> {
             extension: null_mut(),
         };
 
-        let mut streams: BTreeMap<EntryID, nvtxw::StreamHandle> = BTreeMap::new();
-        for (entry_id, long_name, hierarchy) in &entry_ids {
-            let stream_name = format!("{} {}", LEGION_DOMAIN_NAME, long_name);
-            let domain_name = hierarchy.to_string();
-
-            let stream = nvtxw::stream_open_simple(&interface, session, stream_name, domain_name)
-                .expect("Failed to create stream");
+        let c_counter_name = c"Legion Counter";
+        let c_field_name_time = c"time";
+        let c_field_name_value = c"value";
 
-            nvtxw::schema_register(&interface, stream, &name_schema_attr)
-                .expect("Failed to register name schema");
-
-            nvtxw::schema_register(&interface, stream, &event_schema_attr)
-                .expect("Failed to register event schema");
-
-            streams.insert(entry_id.clone(), stream);
-        }
-
-        let zero_time = self.zero_time;
-
-        const MAX_IN_FLIGHT_REQUESTS: u64 = 100;
+        let counter_schema = [
+            nvtxw::PayloadSchemaEntry {
+                flags: nvtxw::NVTX_PAYLOAD_ENTRY_FLAG_EVENT_TIMESTAMP,
+                type_: nvtxw::NVTX_PAYLOAD_ENTRY_TYPE_UINT64,
+                name: c_field_name_time.as_ptr(),
+                description: null(),
+                arrayOrUnionDetail: 0,
+                offset: 0,
+                semantics: null(),
+                reserved: null(),
+            },
+            nvtxw::PayloadSchemaEntry {
+                flags: nvtxw::NVTX_PAYLOAD_ENTRY_FLAG_COUNTER,
+                type_: nvtxw::NVTX_PAYLOAD_ENTRY_TYPE_DOUBLE,
+                name: c_field_name_value.as_ptr(),
+                description: null(),
+                arrayOrUnionDetail: 0,
+                offset: 0,
+                semantics: null(),
+                reserved: null(),
+            },
+        ];
 
-        let mut unmatched_tiles: UnmatchedTileHold = BTreeMap::new();
+        let counter_schema_attr = nvtxw::PayloadSchemaAttr {
+            fieldMask: nvtxw::NVTX_PAYLOAD_SCHEMA_ATTR_NAME
+                | nvtxw::NVTX_PAYLOAD_SCHEMA_ATTR_TYPE
+                | nvtxw::NVTX_PAYLOAD_SCHEMA_ATTR_FLAGS
+                | nvtxw::NVTX_PAYLOAD_SCHEMA_ATTR_ENTRIES
+                | nvtxw::NVTX_PAYLOAD_SCHEMA_ATTR_NUM_ENTRIES
+                | nvtxw::NVTX_PAYLOAD_SCHEMA_ATTR_STATIC_SIZE
+                | nvtxw::NVTX_PAYLOAD_SCHEMA_ATTR_SCHEMA_ID,
+            name: c_counter_name.as_ptr(),
+            type_: nvtxw::NVTX_PAYLOAD_SCHEMA_TYPE_STATIC,
+            flags: nvtxw::NVTX_PAYLOAD_SCHEMA_FLAG_COUNTER_GROUP,
+            entries: counter_schema.as_ptr(),
+            numEntries: counter_schema.len(),
+            payloadStaticSize: size_of::<legion_nvtxw_counter>(),
+            packAlign: 0,
+            schemaId: LEGION_NVTXW_PAYLOAD_COUNTER_SCHEMA_ID,
+            extension: null_mut(),
+        };
 
-        for (entry_id, _, _) in &entry_ids {
-            match entry_id.last_index().unwrap() {
-                EntryIndex::Summary => {
-                    // When implementing counters, uncomment this.
-                    /*
-                    self.data_source
-                        .fetch_summary_tile(entry_id, full_range_tile_id, full);
-                    */
+        let c_field_names: Vec<_> = info
+            .field_schema
+            .iter()
+            .map(|(_, name)| c_string(name.to_string()))
+            .collect();
+        let field_indices: BTreeMap<_, _> = info
+            .field_schema
+            .iter()
+            .enumerate()
+            .map(|(index, (field_id, _))| (field_id, index))
+            .collect();
+
+        let field_schema_entries: Vec<_> = c_field_names
+            .iter()
+            .enumerate()
+            .flat_map(|(index, c_name)| {
+                FieldKind::ALL.map(|kind| {
+                    let (flags, type_) = match kind {
+                        FieldKind::I64 => (0, nvtxw::NVTX_PAYLOAD_ENTRY_TYPE_INT64),
+                        FieldKind::U64 => (0, nvtxw::NVTX_PAYLOAD_ENTRY_TYPE_UINT64),
+                        FieldKind::Text => (
+                            nvtxw::NVTX_PAYLOAD_ENTRY_FLAG_ARRAY_ZERO_TERMINATED,
+                            nvtxw::NVTX_PAYLOAD_ENTRY_TYPE_CSTRING,
+                        ),
+                    };
+                    let entries = [nvtxw::PayloadSchemaEntry {
+                        flags,
+                        type_,
+                        name: c_name.as_ptr(),
+                        description: null(),
+                        arrayOrUnionDetail: 0,
+                        offset: 0,
+                        semantics: null(),
+                        reserved: null(),
+                    }];
+                    (index, kind, entries)
+                })
+            })
+            .collect();
+
+        let field_schemas: BTreeMap<_, _> = field_schema_entries
+            .iter()
+            .map(|(index, kind, entries)| {
+                let (type_, static_size) = match kind {
+                    FieldKind::I64 => (nvtxw::NVTX_PAYLOAD_SCHEMA_TYPE_STATIC, size_of::<i64>()),
+                    FieldKind::U64 => (nvtxw::NVTX_PAYLOAD_SCHEMA_TYPE_STATIC, size_of::<u64>()),
+                    FieldKind::Text => (nvtxw::NVTX_PAYLOAD_SCHEMA_TYPE_DYNAMIC, 0),
+                };
+                let schema_id = kind.schema_id(*index);
+                let attr = nvtxw::PayloadSchemaAttr {
+                    fieldMask: nvtxw::NVTX_PAYLOAD_SCHEMA_ATTR_NAME
+                        | nvtxw::NVTX_PAYLOAD_SCHEMA_ATTR_TYPE
+                        | nvtxw::NVTX_PAYLOAD_SCHEMA_ATTR_ENTRIES
+                        | nvtxw::NVTX_PAYLOAD_SCHEMA_ATTR_NUM_ENTRIES
+                        | nvtxw::NVTX_PAYLOAD_SCHEMA_ATTR_STATIC_SIZE
+                        | nvtxw::NVTX_PAYLOAD_SCHEMA_ATTR_SCHEMA_ID,
+                    name: c_field_names[*index].as_ptr(),
+                    type_,
+                    flags: nvtxw::NVTX_PAYLOAD_SCHEMA_FLAG_NONE,
+                    entries: entries.as_ptr(),
+                    numEntries: entries.len(),
+                    payloadStaticSize: static_size,
+                    packAlign: 0,
+                    schemaId: schema_id,
+                    extension: null_mut(),
+                };
+                (schema_id, attr)
+            })
+            .collect();
+
+        for entry in entries {
+            let stream_name = format!("{} {}", LEGION_DOMAIN_NAME, entry.long_name);
+            let domain_name = hierarchy(entry);
+
+            let stream = check(
+                nvtxw::stream_open_simple(interface, session, stream_name, domain_name),
+                "create stream",
+            )?;
+            // So that it gets closed if anything below fails
+            streams.insert(entry.entry_id.clone(), stream);
+
+            match entry.summary {
+                Some(_) => {
+                    check(
+                        nvtxw::schema_register(interface, stream, &counter_schema_attr),
+                        "register counter schema",
+                    )?;
                 }
-                EntryIndex::Slot(..) => {
-                    self.data_source
-                        .fetch_slot_tile(entry_id, full_range_tile_id, full);
-                    self.data_source
-                        .fetch_slot_meta_tile(entry_id, full_range_tile_id, full);
+                None => {
+                    check(
+                        nvtxw::schema_register(interface, stream, &name_schema_attr),
+                        "register name schema",
+                    )?;
+
+                    check(
+                        nvtxw::schema_register(interface, stream, &event_schema_attr),
+                        "register event schema",
+                    )?;
                 }
             }
-
-            Self::process_events(
-                &mut self.data_source,
-                &interface,
-                &streams,
-                zero_time,
-                &mut unmatched_tiles,
-                MAX_IN_FLIGHT_REQUESTS,
-            );
         }
+        let streams = &*streams;
 
-        Self::process_events(
-            &mut self.data_source,
-            &interface,
-            &streams,
-            zero_time,
-            &mut unmatched_tiles,
-            0,
-        );
-
-        assert!(unmatched_tiles.is_empty());
-
-        for (_entry_id, stream) in streams {
-            nvtxw::stream_close(&interface, stream).expect("Failed to close stream");
-        }
-
-        nvtxw::session_end(&interface, session).expect("Failed to end session");
+        let zero_time = self.zero_time;
 
-        nvtxw::unload(&interface);
+        let threads = match self.config.threads {
+            0 => thread::available_parallelism().map_or(1, |n| n.get()),
+            n => n,
+        };
 
-        Ok(())
+        // Entries go out to the worker threads to be turned into events,
+        // which come back here to be written, since NVTXW handles can't be
+        // shared. Meanwhile, the driver keeps polling the outstanding requests
+        let (entries_tx, entries_rx) = mpsc::channel::<(EntryID, EntryData)>();
+        let entries_rx = Mutex::new(entries_rx);
+        let (events_tx, events_rx) = mpsc::channel::<(EntryID, Result<Events, String>)>();
+        let main_thread = thread::current();
+
+        thread::scope(|scope| {
+            for _ in 0..threads {
+                let entries_rx = &entries_rx;
+                let field_indices = &field_indices;
+                let events_tx = events_tx.clone();
+                let main_thread = main_thread.clone();
+                scope.spawn(move || {
+                    loop {
+                        // Don't hold the lock while preparing the events
+                        let entry = entries_rx.lock().unwrap().recv();
+                        let Ok((entry_id, data)) = entry else {
+                            break;
+                        };
+                        let events = prepare_events(
+                            entry_id.clone(),
+                            data,
+                            interval,
+                            zero_time,
+                            field_indices,
+                        );
+                        if events_tx.send((entry_id, events)).is_err() {
+                            break;
+                        }
+                        main_thread.unpark();
+                    }
+                });
+            }
+            drop(events_tx);
+
+            let mut exporter = NVTXWExporter {
+                interface,
+                streams,
+                field_schemas: &field_schemas,
+                registered: BTreeMap::new(),
+                names: entries
+                    .iter()
+                    .map(|entry| (entry.entry_id.clone(), entry.long_name.clone()))
+                    .collect(),
+                tile_id: TileID(interval),
+                entries_tx: Some(entries_tx),
+                events_rx,
+                progress: Progress::new(entries.len(), self.config.quiet),
+            };
+            export_entries(&self.data_source, info, entries, interval, &mut exporter)
+        })
     }
 }
@@ -0,0 +1,376 @@
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use url::Url;
+
+use crate::data::DataSource;
+use crate::deferred_data::DeferredDataSource;
+use crate::file_data::FileDataSource;
+use crate::http::client::{ClientConfig, HTTPClientDataSource};
+use crate::legion_log_data::load_legion_prof_logs;
+use crate::parallel_data::ParallelDeferredDataSource;
+use crate::replay_data::ReplayDataSource;
+
+// Kinds of profile file, as told by their extension or, failing that, their
+// first few bytes
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum FileKind {
+    Bundle,
+    Parquet,
+    Nsys,
+    Chrome,
+    Perf,
+    Perfetto,
+    LegionProf,
+}
+
+impl FileKind {
+    fn from_extension(path: &Path) -> Option<Self> {
+        let extension = path.extension()?.to_str()?;
+        match extension {
+            "zip" => Some(FileKind::Bundle),
+            "parquet" => Some(FileKind::Parquet),
+            "sqlite" => Some(FileKind::Nsys),
+            "json" => Some(FileKind::Chrome),
+            "perf" | "folded" => Some(FileKind::Perf),
+            "pftrace" | "perfetto-trace" => Some(FileKind::Perfetto),
+            "gz" => Some(FileKind::LegionProf),
+            _ => None,
+        }
+    }
+
+    // Only formats with a recognizable header can be found this way; perf
+    // samples and Perfetto traces need their extension
+    fn from_contents(path: &Path) -> Option<Self> {
+        let mut header = [0; 16];
+        let mut f = File::open(path).ok()?;
+        let n = f.read(&mut header).ok()?;
+        let header = &header[..n];
+        if header.starts_with(b"PK\x03\x04") {
+            return Some(FileKind::Bundle);
+        }
+        if header.starts_with(b"PAR1") {
+            return Some(FileKind::Parquet);
+        }
+        if header.starts_with(b"SQLite format 3\0") {
+            return Some(FileKind::Nsys);
+        }
+        if header.starts_with(b"\x1f\x8b") {
+            return Some(FileKind::LegionProf);
+        }
+        match header.iter().find(|b| !b.is_ascii_whitespace()) {
+            Some(b'{' | b'[') => Some(FileKind::Chrome),
+            _ => None,
+        }
+    }
+
+    fn open(self, path: &Path) -> Result<Box<dyn DataSource + Send + Sync>, String> {
+        let name = path.display();
+        match self {
+            #[cfg(feature = "bundle")]
+            FileKind::Bundle => {
+                use crate::zip_data::ZipDataSource;
+                let data_source = ZipDataSource::new(path)
+                    .map_err(|e| format!("unable to open bundle {name}: {e}"))?;
+                Ok(Box::new(data_source))
+            }
+            #[cfg(feature = "parquet")]
+            FileKind::Parquet => {
+                use crate::parquet_data::ParquetDataSource;
+                let data_source = ParquetDataSource::new(path)
+                    .map_err(|e| format!("unable to open parquet file {name}: {e}"))?;
+                Ok(Box::new(data_source))
+            }
+            #[cfg(feature = "nsys")]
+            FileKind::Nsys => {
+                use crate::nsys_data::load_nsys_report;
+                let data_source = load_nsys_report(path)
+                    .map_err(|e| format!("unable to load Nsight Systems report {name}: {e}"))?;
+                Ok(Box::new(data_source))
+            }
+            #[cfg(feature = "chrome")]
+            FileKind::Chrome => {
+                use crate::chrome_data::load_chrome_trace;
+                let data_source = load_chrome_trace(path)
+                    .map_err(|e| format!("unable to load Chrome trace {name}: {e}"))?;
+                Ok(Box::new(data_source))
+            }
+            FileKind::Perf => {
+                use crate::perf_data::load_perf_samples;
+                let data_source = load_perf_samples(path)
+                    .map_err(|e| format!("unable to load perf samples {name}: {e}"))?;
+                Ok(Box::new(data_source))
+            }
+            FileKind::Perfetto => {
+                use crate::perfetto_data::load_perfetto_trace;
+                let data_source = load_perfetto_trace(path)
+                    .map_err(|e| format!("unable to load Perfetto trace {name}: {e}"))?;
+                Ok(Box::new(data_source))
+            }
+            FileKind::LegionProf => {
+                let data_source = load_legion_prof_logs(&[path])
+                    .map_err(|e| format!("unable to load Legion Prof log {name}: {e}"))?;
+                Ok(Box::new(data_source))
+            }
+            #[allow(unreachable_patterns)]
+            kind => Err(format!(
+                "{name} looks like {}, which requires the {} feature",
+                kind.description(),
+                kind.feature()
+            )),
+        }
+    }
+
+    fn description(self) -> &'static str {
+        match self {
+            FileKind::Bundle => "a bundle",
+            FileKind::Parquet => "a parquet file",
+            FileKind::Nsys => "an Nsight Systems report",
+            FileKind::Chrome => "a Chrome trace",
+            FileKind::Perf => "perf samples",
+            FileKind::Perfetto => "a Perfetto trace",
+            FileKind::LegionProf => "a Legion Prof log",
+        }
+    }
+
+    fn feature(self) -> &'static str {
+        match self {
+            FileKind::Bundle => "bundle",
+            FileKind::Parquet => "parquet",
+            FileKind::Nsys => "nsys",
+            FileKind::Chrome => "chrome",
+            FileKind::Perf | FileKind::Perfetto | FileKind::LegionProf => {
+                unreachable!("always built in")
+            }
+        }
+    }
+}
+
+// The raw logs of a Legion Prof run, one per node, in a directory
+fn legion_prof_logs(dir: &Path) -> Vec<PathBuf> {
+    let mut logs: Vec<_> = std::fs::read_dir(dir)
+        .into_iter()
+        .flatten()
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| {
+            path.file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| name.starts_with("prof_") && name.ends_with(".gz"))
+        })
+        .collect();
+    logs.sort();
+    logs
+}
+
+/// Opens a profile on the local file system: an archive directory (as
+/// written by `archive_data`), a directory of raw Legion Prof logs, a
+/// bundle, or a single log or trace from another tool.
+pub fn open_local(path: impl AsRef<Path>) -> Result<Box<dyn DataSource + Send + Sync>, String> {
+    let path = path.as_ref();
+    let name = path.display();
+    if path.is_dir() {
+        if path.join("info").is_file() {
+            return Ok(Box::new(FileDataSource::new(path)));
+        }
+        let logs = legion_prof_logs(path);
+        if !logs.is_empty() {
+            let data_source = load_legion_prof_logs(&logs)
+                .map_err(|e| format!("unable to load Legion Prof logs in {name}: {e}"))?;
+            return Ok(Box::new(data_source));
+        }
+        return Err(format!(
+            "{name} isn't a profile archive (it has no info file or prof_*.gz logs)"
+        ));
+    }
+    if !path.exists() {
+        return Err(format!("no such file or directory: {name}"));
+    }
+    let kind = FileKind::from_extension(path)
+        .or_else(|| FileKind::from_contents(path))
+        .ok_or_else(|| format!("unable to tell what kind of profile {name} is"))?;
+    kind.open(path)
+}
+
+/// A profile named the way it would be typed into the viewer: a local
+/// profile (see `open_local`), a recording (`.replay`), or a `file://` or
+/// `http(s)://` URL, plus `ws(s)://` and `grpc(s)://` URLs with the
+/// `websocket` and `grpc` features. Parsing checks that local profiles exist,
+/// so that a typo is caught before anything is opened.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Locator {
+    Local(PathBuf),
+    Replay(PathBuf),
+    Http(Url),
+    #[cfg(feature = "websocket")]
+    Live(Url),
+    // grpc:// and grpcs:// name the server's plain HTTP/2 or TLS endpoint
+    #[cfg(feature = "grpc")]
+    Grpc(Url),
+}
+
+impl Locator {
+    pub fn is_remote(&self) -> bool {
+        !matches!(self, Locator::Local(_) | Locator::Replay(_))
+    }
+
+    pub fn open(self, config: &ClientConfig) -> Result<Box<dyn DeferredDataSource>, String> {
+        match self {
+            Locator::Local(path) => {
+                Ok(Box::new(ParallelDeferredDataSource::new(open_local(path)?)))
+            }
+            Locator::Replay(path) => {
+                let ds = ReplayDataSource::new(&path)
+                    .map_err(|e| format!("unable to open recording {}: {e}", path.display()))?;
+                Ok(Box::new(ds))
+            }
+            Locator::Http(url) => {
+                let ds = HTTPClientDataSource::with_config(url, config.clone())
+                    .map_err(|e| format!("unable to configure HTTP client: {e}"))?;
+                Ok(Box::new(ds))
+            }
+            #[cfg(feature = "websocket")]
+            Locator::Live(url) => {
+                use crate::http::websocket::connect_live;
+                let ds = connect_live(&url, config)
+                    .map_err(|e| format!("unable to connect to {url}: {e}"))?;
+                Ok(Box::new(ParallelDeferredDataSource::new(ds)))
+            }
+            #[cfg(feature = "grpc")]
+            Locator::Grpc(url) => {
+                use crate::http::grpc::GrpcDataSource;
+                use crate::retry_data::RetryDeferredDataSource;
+                let url = url.as_str().replacen("grpc", "http", 1);
+                let url = Url::parse(&url).map_err(|e| format!("invalid URL {url:?}: {e}"))?;
+                let ds = GrpcDataSource::with_config(url, config.clone())
+                    .map_err(|e| format!("unable to configure gRPC client: {e}"))?;
+                // Unlike HTTP sources, calls aren't retried by the client
+                let retry = config.retry;
+                Ok(Box::new(RetryDeferredDataSource::new(
+                    ds,
+                    retry.retries + 1,
+                    retry.backoff,
+                )))
+            }
+        }
+    }
+}
+
+impl FromStr for Locator {
+    type Err = String;
+
+    fn from_str(locator: &str) -> Result<Self, String> {
+        let locator = locator.trim();
+        // Paths go first, since Windows paths parse as URLs (C:\ has the
+        // scheme c)
+        let path = Path::new(locator);
+        if path.exists() {
+            if path
+                .extension()
+                .is_some_and(|extension| extension == "replay")
+            {
+                return Ok(Locator::Replay(path.to_owned()));
+            }
+            return Ok(Locator::Local(path.to_owned()));
+        }
+
+        let url = match Url::parse(locator) {
+            Ok(url) => url,
+            Err(url::ParseError::RelativeUrlWithoutBase) => {
+                return Err(format!("no such file or directory: {locator}"));
+            }
+            Err(e) => return Err(format!("invalid URL {locator:?}: {e}")),
+        };
+        match url.scheme() {
+            "file" => {
+                let path = url
+                    .to_file_path()
+                    .map_err(|_| format!("invalid file URL {locator:?}"))?;
+                path.to_str()
+                    .ok_or_else(|| format!("invalid file URL {locator:?}"))?
+                    .parse()
+            }
+            "http" | "https" => Ok(Locator::Http(url)),
+            #[cfg(feature = "websocket")]
+            "ws" | "wss" => Ok(Locator::Live(url)),
+            #[cfg(feature = "grpc")]
+            "grpc" | "grpcs" => Ok(Locator::Grpc(url)),
+            #[cfg(not(feature = "websocket"))]
+            "ws" | "wss" => Err(format!("{locator} requires the websocket feature")),
+            #[cfg(not(feature = "grpc"))]
+            "grpc" | "grpcs" => Err(format!("{locator} requires the grpc feature")),
+            scheme => Err(format!("unsupported URL scheme {scheme:?} in {locator}")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::fs::{create_dir_all, remove_dir_all, write};
+
+    use crate::archive_data::DataSourceArchiveWriter;
+    use crate::deferred_data::DeferredDataSourceWrapper;
+    use crate::trace_data::TraceBuilder;
+
+    #[test]
+    fn test_open_local() {
+        let dir = std::env::temp_dir().join(format!("lpv_open_test_{}", std::process::id()));
+        create_dir_all(dir.join("empty")).unwrap();
+
+        let mut builder = TraceBuilder::new("test");
+        builder.thread("node", "cpu", "cpu0");
+        let ds = DeferredDataSourceWrapper::new(builder.build());
+        DataSourceArchiveWriter::new(ds, 1, 2, dir.join("archive"), false, 1)
+            .write()
+            .unwrap();
+        assert!(open_local(dir.join("archive")).is_ok());
+        let url = Url::from_file_path(dir.join("archive")).unwrap();
+        let locator: Locator = url.as_str().parse().unwrap();
+        assert_eq!(locator, Locator::Local(dir.join("archive")));
+        assert!(locator.open(&ClientConfig::default()).is_ok());
+
+        // Directories need to be archives or hold logs
+        let e = open_local(dir.join("empty")).err().unwrap();
+        assert!(e.contains("isn't a profile archive"), "{e}");
+        let e = open_local(dir.join("missing")).err().unwrap();
+        assert!(e.contains("no such file"), "{e}");
+        write(dir.join("empty").join("prof_0.gz"), b"\x1f\x8b").unwrap();
+        let e = open_local(dir.join("empty").join("prof_0.gz"))
+            .err()
+            .unwrap();
+        assert!(e.contains("unable to load Legion Prof log"), "{e}");
+        let e = open_local(dir.join("empty")).err().unwrap();
+        assert!(e.contains("unable to load Legion Prof logs"), "{e}");
+
+        // Files are recognized by their contents when the extension doesn't
+        // give them away
+        write(dir.join("unknown.dat"), "not a profile").unwrap();
+        let e = open_local(dir.join("unknown.dat")).err().unwrap();
+        assert!(e.contains("unable to tell"), "{e}");
+        assert_eq!(FileKind::from_contents(&dir.join("unknown.dat")), None);
+        write(dir.join("trace.dat"), "  [{\"ph\": \"X\"}]").unwrap();
+        assert_eq!(
+            FileKind::from_contents(&dir.join("trace.dat")),
+            Some(FileKind::Chrome)
+        );
+        write(dir.join("bundle.dat"), b"PK\x03\x04rest").unwrap();
+        assert_eq!(
+            FileKind::from_contents(&dir.join("bundle.dat")),
+            Some(FileKind::Bundle)
+        );
+
+        assert!(matches!(
+            "https://example.com/".parse(),
+            Ok(Locator::Http(_))
+        ));
+        let e = "ftp://example.com/".parse::<Locator>().unwrap_err();
+        assert!(e.contains("unsupported URL scheme"), "{e}");
+        let e = "typo".parse::<Locator>().unwrap_err();
+        assert!(e.contains("no such file"), "{e}");
+
+        remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,16 +1,19 @@
 use std::sync::{Arc, Mutex};
 
-use crate::data::{DataSource, DataSourceDescription, DataSourceInfo, EntryID, TileID};
+use crate::data::{DataSource, DataSourceDescription, EntryID, ItemMetaRequest, TileID};
 use crate::deferred_data::{
-    DeferredDataSource, SlotMetaTileResponse, SlotTileResponse, SummaryTileResponse, TileRequest,
+    CANCELLED, CancelFlags, DataSourceInfoResult, DeferredDataSource, ItemsMetaResponse,
+    RequestPriority, SlotMetaTileResponse, SlotTileResponse, SummaryTileResponse, TileRequest,
 };
 
 pub struct ParallelDeferredDataSource<T: DataSource + Send + Sync + 'static> {
     data_source: Arc<T>,
-    infos: Arc<Mutex<Vec<DataSourceInfo>>>,
+    infos: Arc<Mutex<Vec<DataSourceInfoResult>>>,
     summary_tiles: Arc<Mutex<Vec<SummaryTileResponse>>>,
     slot_tiles: Arc<Mutex<Vec<SlotTileResponse>>>,
     slot_meta_tiles: Arc<Mutex<Vec<SlotMetaTileResponse>>>,
+    items_meta: Arc<Mutex<Vec<ItemsMetaResponse>>>,
+    cancel_flags: CancelFlags,
 }
 
 impl<T: DataSource + Send + Sync + 'static> ParallelDeferredDataSource<T> {
@@ -21,6 +24,8 @@ impl<T: DataSource + Send + Sync + 'static> ParallelDeferredDataSource<T> {
             summary_tiles: Arc::new(Mutex::new(Vec::new())),
             slot_tiles: Arc::new(Mutex::new(Vec::new())),
             slot_meta_tiles: Arc::new(Mutex::new(Vec::new())),
+            items_meta: Arc::new(Mutex::new(Vec::new())),
+            cancel_flags: CancelFlags::default(),
         }
     }
 }
@@ -34,27 +39,37 @@ impl<T: DataSource + Send + Sync + 'static> DeferredDataSource for ParallelDefer
         let data_source = self.data_source.clone();
         let infos = self.infos.clone();
         rayon::spawn(move || {
-            let result = data_source.fetch_info();
+            let result = data_source.try_fetch_info();
             infos.lock().unwrap().push(result);
         });
     }
 
-    fn get_infos(&mut self) -> Vec<DataSourceInfo> {
+    fn get_infos(&mut self) -> Vec<DataSourceInfoResult> {
         std::mem::take(&mut self.infos.lock().unwrap())
     }
 
-    fn fetch_summary_tile(&mut self, entry_id: &EntryID, tile_id: TileID, full: bool) {
-        let entry_id = entry_id.clone();
+    fn fetch_summary_tile(
+        &mut self,
+        entry_id: &EntryID,
+        tile_id: TileID,
+        full: bool,
+        _priority: RequestPriority,
+    ) {
+        let req = TileRequest {
+            entry_id: entry_id.clone(),
+            tile_id,
+            full,
+        };
+        let cancel = self.cancel_flags.start(req.clone());
         let data_source = self.data_source.clone();
         let summary_tiles = self.summary_tiles.clone();
         rayon::spawn(move || {
-            let result = data_source.fetch_summary_tile(&entry_id, tile_id, full);
-            let req = TileRequest {
-                entry_id: entry_id.clone(),
-                tile_id,
-                full,
+            let result = if cancel.is_cancelled() {
+                Err(CANCELLED.to_owned())
+            } else {
+                data_source.try_fetch_summary_tile(&req.entry_id, tile_id, full)
             };
-            summary_tiles.lock().unwrap().push((Ok(result), req));
+            summary_tiles.lock().unwrap().push((result, req));
         });
     }
 
@@ -62,18 +77,28 @@ impl<T: DataSource + Send + Sync + 'static> DeferredDataSource for ParallelDefer
         std::mem::take(&mut self.summary_tiles.lock().unwrap())
     }
 
-    fn fetch_slot_tile(&mut self, entry_id: &EntryID, tile_id: TileID, full: bool) {
-        let entry_id = entry_id.clone();
+    fn fetch_slot_tile(
+        &mut self,
+        entry_id: &EntryID,
+        tile_id: TileID,
+        full: bool,
+        _priority: RequestPriority,
+    ) {
+        let req = TileRequest {
+            entry_id: entry_id.clone(),
+            tile_id,
+            full,
+        };
+        let cancel = self.cancel_flags.start(req.clone());
         let data_source = self.data_source.clone();
         let slot_tiles = self.slot_tiles.clone();
         rayon::spawn(move || {
-            let result = data_source.fetch_slot_tile(&entry_id, tile_id, full);
-            let req = TileRequest {
-                entry_id: entry_id.clone(),
-                tile_id,
-                full,
+            let result = if cancel.is_cancelled() {
+                Err(CANCELLED.to_owned())
+            } else {
+                data_source.try_fetch_slot_tile(&req.entry_id, tile_id, full)
             };
-            slot_tiles.lock().unwrap().push((Ok(result), req));
+            slot_tiles.lock().unwrap().push((result, req));
         });
     }
 
@@ -81,22 +106,56 @@ impl<T: DataSource + Send + Sync + 'static> DeferredDataSource for ParallelDefer
         std::mem::take(&mut self.slot_tiles.lock().unwrap())
     }
 
-    fn fetch_slot_meta_tile(&mut self, entry_id: &EntryID, tile_id: TileID, full: bool) {
-        let entry_id = entry_id.clone();
+    fn fetch_slot_meta_tile(
+        &mut self,
+        entry_id: &EntryID,
+        tile_id: TileID,
+        full: bool,
+        _priority: RequestPriority,
+    ) {
+        let req = TileRequest {
+            entry_id: entry_id.clone(),
+            tile_id,
+            full,
+        };
+        let cancel = self.cancel_flags.start(req.clone());
         let data_source = self.data_source.clone();
         let slot_meta_tiles = self.slot_meta_tiles.clone();
         rayon::spawn(move || {
-            let result = data_source.fetch_slot_meta_tile(&entry_id, tile_id, full);
-            let req = TileRequest {
-                entry_id: entry_id.clone(),
-                tile_id,
-                full,
+            let result = if cancel.is_cancelled() {
+                Err(CANCELLED.to_owned())
+            } else {
+                data_source.try_fetch_slot_meta_tile(&req.entry_id, tile_id, full)
             };
-            slot_meta_tiles.lock().unwrap().push((Ok(result), req));
+            slot_meta_tiles.lock().unwrap().push((result, req));
         });
     }
 
     fn get_slot_meta_tiles(&mut self) -> Vec<SlotMetaTileResponse> {
         std::mem::take(&mut self.slot_meta_tiles.lock().unwrap())
     }
+
+    fn fetch_items_meta(&mut self, requests: &[ItemMetaRequest]) {
+        let requests = requests.to_vec();
+        let data_source = self.data_source.clone();
+        let items_meta = self.items_meta.clone();
+        rayon::spawn(move || {
+            let result = data_source.try_fetch_items_meta(&requests);
+            items_meta.lock().unwrap().push((result, requests));
+        });
+    }
+
+    fn get_items_meta(&mut self) -> Vec<ItemsMetaResponse> {
+        std::mem::take(&mut self.items_meta.lock().unwrap())
+    }
+
+    fn cancel(&mut self, entry_id: &EntryID, tile_id: TileID, full: bool) {
+        // Tiles that are already being processed run to completion, but
+        // anything still in the queue is skipped
+        self.cancel_flags.cancel(&TileRequest {
+            entry_id: entry_id.clone(),
+            tile_id,
+            full,
+        });
+    }
 }
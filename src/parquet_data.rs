@@ -0,0 +1,545 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::num::NonZeroUsize;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use arrow_array::cast::AsArray;
+use arrow_array::types::{Int64Type, UInt32Type, UInt64Type};
+use arrow_array::{Array, ArrayRef, BooleanArray, RecordBatch};
+use arrow_cast::cast;
+use arrow_schema::{ArrowError, DataType};
+use egui::Color32;
+use lru::LruCache;
+use parquet::arrow::ProjectionMask;
+use parquet::arrow::arrow_reader::{ArrowPredicateFn, ParquetRecordBatchReaderBuilder, RowFilter};
+use parquet::errors::{ParquetError, Result};
+use parquet::file::metadata::ParquetMetaData;
+use parquet::file::statistics::Statistics;
+
+use crate::data::{
+    Capabilities, DataSource, DataSourceDescription, DataSourceInfo, EntryID, EntryInfo, Field,
+    FieldID, FieldSchema, Item, ItemMeta, ItemUID, PROTOCOL_VERSION, SlotMetaTile,
+    SlotMetaTileData, SlotTile, SlotTileData, SummaryTile, SummaryTileData, TileID, TileSet,
+};
+use crate::timestamp::{Interval, Timestamp};
+
+// Columns of the item table. Each row is one item, placed in the slot named
+// by (node, kind, slot) at the given row. Times are in nanoseconds. The color
+// column (0xRRGGBBAA) is optional; any other column is shown as a field.
+const NODE: &str = "node";
+const KIND: &str = "kind";
+const SLOT: &str = "slot";
+const ROW: &str = "row";
+const ITEM_UID: &str = "item_uid";
+const START: &str = "start";
+const STOP: &str = "stop";
+const TITLE: &str = "title";
+const COLOR: &str = "color";
+
+const REQUIRED: &[&str] = &[NODE, KIND, SLOT, ROW, ITEM_UID, START, STOP, TITLE];
+
+type SlotKey = (i64, String, String);
+
+// row -> [(item, meta)] for each slot that has items in the tile
+type TileItems = BTreeMap<EntryID, Vec<Vec<(Item, ItemMeta)>>>;
+
+// Reads items directly out of a Parquet file. Tiles are constructed on
+// demand: row groups whose statistics show no overlap with the tile are
+// skipped, and the remaining rows are filtered on the interval before any
+// other columns are decoded.
+pub struct ParquetDataSource {
+    path: PathBuf,
+    info: DataSourceInfo,
+    slots: BTreeMap<SlotKey, EntryID>,
+    fields: Vec<(String, FieldID)>,
+
+    // A tile is decoded once for all slots, so cache the result for the
+    // other slots that will ask for it
+    tiles: Mutex<LruCache<TileID, Arc<TileItems>>>,
+}
+
+impl ParquetDataSource {
+    pub fn new(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref().to_owned();
+
+        // Scan the placement columns once to discover the slots and extent
+        let builder = ParquetRecordBatchReaderBuilder::try_new(File::open(&path)?)?;
+        let schema = builder.schema().clone();
+        for name in REQUIRED {
+            if schema.column_with_name(name).is_none() {
+                return Err(ParquetError::General(format!("missing column {}", name)));
+            }
+        }
+        let indices = [NODE, KIND, SLOT, ROW, START, STOP]
+            .iter()
+            .map(|name| schema.index_of(name).unwrap());
+        let mask = ProjectionMask::roots(builder.parquet_schema(), indices);
+        let reader = builder.with_projection(mask).build()?;
+
+        let mut max_rows: BTreeMap<i64, BTreeMap<String, BTreeMap<String, u64>>> = BTreeMap::new();
+        let mut interval: Option<Interval> = None;
+        for batch in reader {
+            let batch = batch?;
+            let node = column_i64(&batch, NODE)?;
+            let kind = column_string(&batch, KIND)?;
+            let slot = column_string(&batch, SLOT)?;
+            let row = column_u64(&batch, ROW)?;
+            let start = column_i64(&batch, START)?;
+            let stop = column_i64(&batch, STOP)?;
+            for i in 0..batch.num_rows() {
+                let rows = max_rows
+                    .entry(node.value(i))
+                    .or_default()
+                    .entry(kind.value(i).to_owned())
+                    .or_default()
+                    .entry(slot.value(i).to_owned())
+                    .or_default();
+                *rows = (*rows).max(row.value(i) + 1);
+
+                let item = Interval::new(Timestamp(start.value(i)), Timestamp(stop.value(i)));
+                interval = Some(interval.map_or(item, |x| x.union(item)));
+            }
+        }
+
+        let mut slots = BTreeMap::new();
+        let mut node_slots = Vec::new();
+        for (node_index, (node, kinds)) in max_rows.into_iter().enumerate() {
+            let node_id = EntryID::root().child(node_index as u64);
+            let mut kind_slots = Vec::new();
+            for (kind_index, (kind, procs)) in kinds.into_iter().enumerate() {
+                let kind_id = node_id.child(kind_index as u64);
+                let mut proc_slots = Vec::new();
+                for (slot_index, (slot, rows)) in procs.into_iter().enumerate() {
+                    proc_slots.push(EntryInfo::Slot {
+                        short_name: slot.clone(),
+                        long_name: format!("Node {node} {kind} {slot}"),
+                        max_rows: rows,
+                    });
+                    slots.insert((node, kind.clone(), slot), kind_id.child(slot_index as u64));
+                }
+                kind_slots.push(EntryInfo::Panel {
+                    short_name: kind.to_lowercase(),
+                    long_name: format!("Node {node} {kind}"),
+                    summary: None,
+                    slots: proc_slots,
+                });
+            }
+            node_slots.push(EntryInfo::Panel {
+                short_name: format!("n{node}"),
+                long_name: format!("Node {node}"),
+                summary: None,
+                slots: kind_slots,
+            });
+        }
+
+        let mut field_schema = FieldSchema::new();
+        let fields = schema
+            .fields()
+            .iter()
+            .filter(|field| {
+                let name = field.name().as_str();
+                !REQUIRED.contains(&name) && name != COLOR
+            })
+            .map(|field| {
+                let searchable = matches!(field.data_type(), DataType::Utf8 | DataType::LargeUtf8);
+                let field_id = field_schema.insert(field.name().clone(), searchable);
+                (field.name().clone(), field_id)
+            })
+            .collect();
+
+        let info = DataSourceInfo {
+            entry_info: EntryInfo::Panel {
+                short_name: "root".to_owned(),
+                long_name: "root".to_owned(),
+                summary: None,
+                slots: node_slots,
+            },
+            interval: interval.unwrap_or_default(),
+            tile_set: TileSet::default(),
+            field_schema,
+            warning_message: None,
+            refresh_interval: None,
+            version: PROTOCOL_VERSION,
+            capabilities: Capabilities::default(),
+            entry_tile_sets: BTreeMap::new(),
+        };
+
+        Ok(Self {
+            path,
+            info,
+            slots,
+            fields,
+            tiles: Mutex::new(LruCache::new(NonZeroUsize::new(64).unwrap())),
+        })
+    }
+
+    fn fetch_tile_items(&self, tile_id: TileID) -> Arc<TileItems> {
+        if let Some(items) = self.tiles.lock().unwrap().get(&tile_id) {
+            return items.clone();
+        }
+
+        // Decode outside the lock so that tiles can be read in parallel
+        let items = Arc::new(
+            self.read_tile_items(tile_id.0)
+                .expect("reading parquet file failed"),
+        );
+        self.tiles.lock().unwrap().put(tile_id, items.clone());
+        items
+    }
+
+    fn read_tile_items(&self, interval: Interval) -> Result<TileItems> {
+        let builder = ParquetRecordBatchReaderBuilder::try_new(File::open(&self.path)?)?;
+        let row_groups = overlapping_row_groups(builder.metadata(), interval);
+
+        let parquet_schema = builder.parquet_schema();
+        let indices = [START, STOP]
+            .iter()
+            .map(|name| builder.schema().index_of(name).unwrap());
+        let mask = ProjectionMask::roots(parquet_schema, indices);
+        let predicate = ArrowPredicateFn::new(mask, move |batch: RecordBatch| {
+            let start = column_i64(&batch, START)?;
+            let stop = column_i64(&batch, STOP)?;
+            Ok(start
+                .iter()
+                .zip(stop.iter())
+                .map(|(start, stop)| Some(start? < interval.stop.0 && stop? > interval.start.0))
+                .collect::<BooleanArray>())
+        });
+        let reader = builder
+            .with_row_groups(row_groups)
+            .with_row_filter(RowFilter::new(vec![Box::new(predicate)]))
+            .build()?;
+
+        let mut result = TileItems::new();
+        for batch in reader {
+            let batch = batch?;
+            let node = column_i64(&batch, NODE)?;
+            let kind = column_string(&batch, KIND)?;
+            let slot = column_string(&batch, SLOT)?;
+            let row = column_u64(&batch, ROW)?;
+            let item_uid = column_u64(&batch, ITEM_UID)?;
+            let start = column_i64(&batch, START)?;
+            let stop = column_i64(&batch, STOP)?;
+            let title = column_string(&batch, TITLE)?;
+            let color = batch
+                .column_by_name(COLOR)
+                .map(|column| cast(column, &DataType::UInt32))
+                .transpose()?;
+            let fields = self
+                .fields
+                .iter()
+                .map(|(name, field_id)| {
+                    let column = FieldColumn::new(batch.column_by_name(name).unwrap())?;
+                    Ok((*field_id, column))
+                })
+                .collect::<Result<Vec<_>>>()?;
+
+            for i in 0..batch.num_rows() {
+                let key = (
+                    node.value(i),
+                    kind.value(i).to_owned(),
+                    slot.value(i).to_owned(),
+                );
+                let entry_id = &self.slots[&key];
+                let slot_info = self.info.entry_info.get(entry_id);
+                let Some(EntryInfo::Slot { max_rows, .. }) = slot_info else {
+                    unreachable!();
+                };
+
+                let original_interval =
+                    Interval::new(Timestamp(start.value(i)), Timestamp(stop.value(i)));
+                let color = match &color {
+                    Some(color) if color.is_valid(i) => {
+                        let [r, g, b, a] =
+                            color.as_primitive::<UInt32Type>().value(i).to_be_bytes();
+                        Color32::from_rgba_unmultiplied(r, g, b, a)
+                    }
+                    _ => title_color(title.value(i)),
+                };
+                let item = Item {
+                    item_uid: ItemUID(item_uid.value(i)),
+                    // When the item straddles a tile boundary, it has to be
+                    // sliced to fit
+                    interval: original_interval.intersection(interval),
+                    color,
+                };
+                let meta = ItemMeta {
+                    item_uid: item.item_uid,
+                    original_interval,
+                    title: title.value(i).to_owned(),
+                    fields: fields
+                        .iter()
+                        .filter_map(|(field_id, column)| Some((*field_id, column.value(i)?, None)))
+                        .collect(),
+                };
+
+                let rows = result
+                    .entry(entry_id.clone())
+                    .or_insert_with(|| vec![Vec::new(); *max_rows as usize]);
+                rows[row.value(i) as usize].push((item, meta));
+            }
+        }
+
+        for rows in result.values_mut() {
+            for row in rows {
+                row.sort_by_key(|(item, _)| item.interval.start);
+            }
+        }
+        Ok(result)
+    }
+
+    fn slot_rows(&self, entry_id: &EntryID, tile_id: TileID) -> Vec<Vec<(Item, ItemMeta)>> {
+        let items = self.fetch_tile_items(tile_id);
+        if let Some(rows) = items.get(entry_id) {
+            return rows.clone();
+        }
+        let Some(EntryInfo::Slot { max_rows, .. }) = self.info.entry_info.get(entry_id) else {
+            panic!("entry is not a slot");
+        };
+        vec![Vec::new(); *max_rows as usize]
+    }
+}
+
+impl DataSource for ParquetDataSource {
+    fn fetch_description(&self) -> DataSourceDescription {
+        DataSourceDescription {
+            source_locator: vec![String::from(self.path.to_string_lossy())],
+        }
+    }
+
+    fn fetch_info(&self) -> DataSourceInfo {
+        self.info.clone()
+    }
+
+    fn fetch_summary_tile(&self, entry_id: &EntryID, tile_id: TileID, _full: bool) -> SummaryTile {
+        // Item tables have no utilization, so there are no summaries to show
+        SummaryTile {
+            entry_id: entry_id.clone(),
+            tile_id,
+            data: SummaryTileData {
+                utilization: Vec::new(),
+            },
+        }
+    }
+
+    fn fetch_slot_tile(&self, entry_id: &EntryID, tile_id: TileID, _full: bool) -> SlotTile {
+        let rows = self.slot_rows(entry_id, tile_id);
+        SlotTile {
+            entry_id: entry_id.clone(),
+            tile_id,
+            data: SlotTileData {
+                items: rows
+                    .into_iter()
+                    .map(|row| row.into_iter().map(|(item, _)| item).collect())
+                    .collect(),
+            },
+        }
+    }
+
+    fn fetch_slot_meta_tile(
+        &self,
+        entry_id: &EntryID,
+        tile_id: TileID,
+        _full: bool,
+    ) -> SlotMetaTile {
+        let rows = self.slot_rows(entry_id, tile_id);
+        SlotMetaTile {
+            entry_id: entry_id.clone(),
+            tile_id,
+            data: SlotMetaTileData {
+                items: rows
+                    .into_iter()
+                    .map(|row| row.into_iter().map(|(_, meta)| meta).collect())
+                    .collect(),
+            },
+        }
+    }
+}
+
+// Row groups whose statistics are missing can't be ruled out, so they are
+// always read
+fn overlapping_row_groups(metadata: &ParquetMetaData, interval: Interval) -> Vec<usize> {
+    let columns = metadata.file_metadata().schema_descr().columns();
+    let start_column = columns.iter().position(|c| c.name() == START);
+    let stop_column = columns.iter().position(|c| c.name() == STOP);
+
+    let statistic = |row_group: usize, column: Option<usize>, min: bool| {
+        let statistics = metadata.row_group(row_group).column(column?).statistics()?;
+        match statistics {
+            Statistics::Int64(s) if min => s.min_opt().copied(),
+            Statistics::Int64(s) => s.max_opt().copied(),
+            Statistics::Int32(s) if min => s.min_opt().map(|x| *x as i64),
+            Statistics::Int32(s) => s.max_opt().map(|x| *x as i64),
+            _ => None,
+        }
+    };
+
+    (0..metadata.num_row_groups())
+        .filter(|&row_group| {
+            let min_start = statistic(row_group, start_column, true);
+            let max_stop = statistic(row_group, stop_column, false);
+            min_start.is_none_or(|start| start < interval.stop.0)
+                && max_stop.is_none_or(|stop| stop > interval.start.0)
+        })
+        .collect()
+}
+
+fn column(batch: &RecordBatch, name: &str, data_type: &DataType) -> Result<ArrayRef, ArrowError> {
+    let column = batch
+        .column_by_name(name)
+        .ok_or_else(|| ArrowError::SchemaError(format!("missing column {}", name)))?;
+    cast(column, data_type)
+}
+
+fn column_i64(batch: &RecordBatch, name: &str) -> Result<arrow_array::Int64Array, ArrowError> {
+    Ok(column(batch, name, &DataType::Int64)?
+        .as_primitive::<Int64Type>()
+        .clone())
+}
+
+fn column_u64(batch: &RecordBatch, name: &str) -> Result<arrow_array::UInt64Array, ArrowError> {
+    Ok(column(batch, name, &DataType::UInt64)?
+        .as_primitive::<UInt64Type>()
+        .clone())
+}
+
+fn column_string(batch: &RecordBatch, name: &str) -> Result<arrow_array::StringArray, ArrowError> {
+    Ok(column(batch, name, &DataType::Utf8)?
+        .as_string::<i32>()
+        .clone())
+}
+
+// Field columns are converted once per batch to the closest Field type
+enum FieldColumn {
+    I64(arrow_array::Int64Array),
+    U64(arrow_array::UInt64Array),
+    String(arrow_array::StringArray),
+}
+
+impl FieldColumn {
+    fn new(column: &ArrayRef) -> Result<Self, ArrowError> {
+        Ok(match column.data_type() {
+            DataType::Int8 | DataType::Int16 | DataType::Int32 | DataType::Int64 => {
+                let column = cast(column, &DataType::Int64)?;
+                FieldColumn::I64(column.as_primitive::<Int64Type>().clone())
+            }
+            DataType::UInt8 | DataType::UInt16 | DataType::UInt32 | DataType::UInt64 => {
+                let column = cast(column, &DataType::UInt64)?;
+                FieldColumn::U64(column.as_primitive::<UInt64Type>().clone())
+            }
+            _ => {
+                let column = cast(column, &DataType::Utf8)?;
+                FieldColumn::String(column.as_string::<i32>().clone())
+            }
+        })
+    }
+
+    fn value(&self, i: usize) -> Option<Field> {
+        match self {
+            FieldColumn::I64(c) => c.is_valid(i).then(|| Field::I64(c.value(i))),
+            FieldColumn::U64(c) => c.is_valid(i).then(|| Field::U64(c.value(i))),
+            FieldColumn::String(c) => c.is_valid(i).then(|| Field::String(c.value(i).to_owned())),
+        }
+    }
+}
+
+// Items with the same title get the same color
+fn title_color(title: &str) -> Color32 {
+    let mut hasher = DefaultHasher::new();
+    title.hash(&mut hasher);
+    let hue = (hasher.finish() % 360) as f32 / 360.0;
+    egui::ecolor::Hsva::new(hue, 0.6, 0.8, 1.0).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use arrow_array::{Int64Array, StringArray, UInt32Array, UInt64Array};
+    use parquet::arrow::ArrowWriter;
+    use parquet::file::properties::WriterProperties;
+
+    #[test]
+    fn test_parquet_tiles() {
+        let path = std::env::temp_dir().join(format!("lpv_parquet_test_{}", std::process::id()));
+
+        let batch = RecordBatch::try_from_iter([
+            (
+                NODE,
+                Arc::new(Int64Array::from(vec![0, 0, 0, 1])) as ArrayRef,
+            ),
+            (
+                KIND,
+                Arc::new(StringArray::from(vec!["CPU"; 4])) as ArrayRef,
+            ),
+            (
+                SLOT,
+                Arc::new(StringArray::from(vec!["c0", "c0", "c1", "c0"])) as ArrayRef,
+            ),
+            (
+                ROW,
+                Arc::new(UInt32Array::from(vec![0, 1, 0, 0])) as ArrayRef,
+            ),
+            (
+                ITEM_UID,
+                Arc::new(UInt64Array::from(vec![1, 2, 3, 4])) as ArrayRef,
+            ),
+            (
+                START,
+                Arc::new(Int64Array::from(vec![0, 50, 200, 900])) as ArrayRef,
+            ),
+            (
+                STOP,
+                Arc::new(Int64Array::from(vec![100, 150, 300, 1000])) as ArrayRef,
+            ),
+            (
+                TITLE,
+                Arc::new(StringArray::from(vec!["a", "b", "c", "d"])) as ArrayRef,
+            ),
+            (
+                "op",
+                Arc::new(Int64Array::from(vec![7, 8, 9, 10])) as ArrayRef,
+            ),
+        ])
+        .unwrap();
+
+        // Small row groups so that some of them get skipped
+        let props = WriterProperties::builder()
+            .set_max_row_group_size(2)
+            .build();
+        let mut writer =
+            ArrowWriter::try_new(File::create(&path).unwrap(), batch.schema(), Some(props))
+                .unwrap();
+        writer.write(&batch).unwrap();
+        writer.close().unwrap();
+
+        let data_source = ParquetDataSource::new(&path).unwrap();
+        let info = data_source.fetch_info();
+        assert_eq!(info.interval, Interval::new(Timestamp(0), Timestamp(1000)));
+        assert_eq!(info.entry_info.nodes(), 2);
+        assert_eq!(info.entry_info.kinds(), vec!["cpu".to_string()]);
+
+        let c0 = EntryID::root().child(0).child(0).child(0);
+        let tile_id = TileID(Interval::new(Timestamp(75), Timestamp(250)));
+        let tile = data_source.fetch_slot_tile(&c0, tile_id, false);
+        assert_eq!(tile.data.items.len(), 2);
+        assert_eq!(
+            tile.data.items[0][0].interval,
+            Interval::new(Timestamp(75), Timestamp(100))
+        );
+        assert_eq!(tile.data.items[1][0].item_uid, ItemUID(2));
+
+        let c1 = EntryID::root().child(0).child(0).child(1);
+        let meta = data_source.fetch_slot_meta_tile(&c1, tile_id, false);
+        assert_eq!(meta.data.items[0][0].title, "c");
+        assert_eq!(meta.data.items[0][0].fields.len(), 1);
+
+        let n1 = EntryID::root().child(1).child(0).child(0);
+        let tile = data_source.fetch_slot_tile(&n1, tile_id, false);
+        assert!(tile.data.items[0].is_empty());
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
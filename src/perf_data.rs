@@ -0,0 +1,375 @@
+use std::collections::BTreeMap;
+use std::io;
+use std::path::Path;
+
+use regex::Regex;
+
+use crate::data::{Field, FieldID};
+use crate::timestamp::{Interval, Timestamp};
+use crate::trace_data::{ThreadID, TraceBuilder, TraceDataSource, TraceItem};
+
+// Folded stacks have no timestamps, so each sample is drawn this long
+const FOLDED_SAMPLE_NS: i64 = 1_000_000;
+
+// A thread is considered idle when it goes this many sampling periods
+// without a sample
+const IDLE_PERIODS: i64 = 2;
+
+#[derive(Debug, Clone)]
+struct Sample {
+    interval: Interval,
+    // Folded stacks combine identical samples
+    count: u64,
+    // Outermost frame first
+    stack: Vec<String>,
+}
+
+struct Frame {
+    name: String,
+    start: Timestamp,
+    samples: u64,
+}
+
+// Turns a thread's samples into items. Consecutive samples that share a
+// frame extend a single item for that frame, so the result reads like a
+// flame graph laid out over time.
+fn add_samples(
+    builder: &mut TraceBuilder,
+    thread: ThreadID,
+    samples_field: FieldID,
+    samples: &[Sample],
+) {
+    let mut open: Vec<Frame> = Vec::new();
+    let mut close = |open: &mut Vec<Frame>, depth: usize, stop: Timestamp| {
+        for frame in open.drain(depth..).rev() {
+            builder.add_item(
+                thread,
+                TraceItem {
+                    interval: Interval::new(frame.start, stop),
+                    title: frame.name,
+                    color: None,
+                    fields: vec![(samples_field, Field::U64(frame.samples))],
+                },
+            );
+        }
+    };
+
+    let mut last: Option<Timestamp> = None;
+    for sample in samples {
+        // Nothing carries over a gap in the samples
+        if let Some(last) = last {
+            if last < sample.interval.start {
+                close(&mut open, 0, last);
+            }
+        }
+        let common = open
+            .iter()
+            .zip(&sample.stack)
+            .take_while(|(frame, name)| frame.name == **name)
+            .count();
+        close(&mut open, common, sample.interval.start);
+        for frame in &mut open {
+            frame.samples += sample.count;
+        }
+        open.extend(sample.stack[common..].iter().map(|name| Frame {
+            name: name.clone(),
+            start: sample.interval.start,
+            samples: sample.count,
+        }));
+        last = Some(sample.interval.stop);
+    }
+    if let Some(last) = last {
+        close(&mut open, 0, last);
+    }
+}
+
+// Each sample stands for the time until the next one, unless the thread
+// went idle in between. The sampling period is estimated from the typical
+// gap between samples.
+fn sample_intervals(samples: &mut [(Timestamp, Vec<String>)]) -> Vec<Sample> {
+    samples.sort_by_key(|(time, _)| *time);
+    let mut gaps: Vec<_> = samples
+        .windows(2)
+        .map(|w| w[1].0.0 - w[0].0.0)
+        .filter(|gap| *gap > 0)
+        .collect();
+    gaps.sort();
+    let period = gaps
+        .get(gaps.len() / 2)
+        .copied()
+        .unwrap_or(FOLDED_SAMPLE_NS);
+
+    (0..samples.len())
+        .map(|i| {
+            let (start, stack) = &samples[i];
+            let stop = match samples.get(i + 1) {
+                Some((next, _)) if next.0 - start.0 <= IDLE_PERIODS * period => *next,
+                _ => Timestamp(start.0 + period),
+            };
+            Sample {
+                interval: Interval::new(*start, stop),
+                count: 1,
+                stack: stack.clone(),
+            }
+        })
+        .collect()
+}
+
+// perf script timestamps are seconds with up to nanosecond precision. They
+// are parsed as integers, since an f64 can't hold all the digits.
+fn parse_seconds(s: &str) -> Option<Timestamp> {
+    let (seconds, fraction) = s.split_once('.').unwrap_or((s, ""));
+    let seconds: i64 = seconds.parse().ok()?;
+    let digits = fraction.len().min(9);
+    let fraction: i64 = if digits > 0 {
+        fraction[..digits].parse().ok()?
+    } else {
+        0
+    };
+    Some(Timestamp(
+        seconds * 1_000_000_000 + fraction * 10i64.pow(9 - digits as u32),
+    ))
+}
+
+// A frame line is "address symbol+offset (dso)"; keep the symbol
+fn parse_frame(line: &str) -> String {
+    let line = line.trim();
+    let line = line.split_once(' ').map_or("", |(_, rest)| rest.trim());
+    let symbol = match line.rfind(" (") {
+        Some(i) if line.ends_with(')') => &line[..i],
+        _ => line,
+    };
+    let symbol = match symbol.rfind("+0x") {
+        Some(i) => &symbol[..i],
+        None => symbol,
+    };
+    if symbol.is_empty() {
+        "[unknown]".to_owned()
+    } else {
+        symbol.to_owned()
+    }
+}
+
+type ThreadKey = (u64, u64);
+
+// Parses the default output of perf script, with call chains (perf record
+// -g). Each sample is a header line followed by one indented line per
+// frame, innermost first, and a blank line.
+type ThreadSamples = BTreeMap<ThreadKey, (String, Vec<(Timestamp, Vec<String>)>)>;
+
+fn parse_perf_script(text: &str) -> io::Result<ThreadSamples> {
+    let header = Regex::new(
+        r"^\s*(?P<comm>.+?)\s+(?P<pid>\d+)(?:/(?P<tid>\d+))?\s+(?:\[\d+\]\s+)?(?P<time>\d+(?:\.\d+)?):",
+    )
+    .unwrap();
+
+    let mut threads = ThreadSamples::new();
+    let mut current: Option<(ThreadKey, Timestamp, Vec<String>)> = None;
+    let finish = |threads: &mut ThreadSamples, current: &mut Option<(_, _, Vec<_>)>| {
+        if let Some((key, time, mut stack)) = current.take() {
+            stack.reverse();
+            threads.get_mut(&key).unwrap().1.push((time, stack));
+        }
+    };
+    for (number, line) in text.lines().enumerate() {
+        if line.trim().is_empty() {
+            finish(&mut threads, &mut current);
+        } else if line.starts_with(char::is_whitespace) && current.is_some() {
+            let (_, _, stack) = current.as_mut().unwrap();
+            stack.push(parse_frame(line));
+        } else {
+            finish(&mut threads, &mut current);
+            let captures = header.captures(line).ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("unable to parse perf script line {}", number + 1),
+                )
+            })?;
+            let pid: u64 = captures["pid"].parse().unwrap();
+            let tid = captures
+                .name("tid")
+                .map_or(pid, |tid| tid.as_str().parse().unwrap());
+            let time = parse_seconds(&captures["time"]).unwrap();
+            let key = (pid, tid);
+            threads
+                .entry(key)
+                .or_insert_with(|| (captures["comm"].to_owned(), Vec::new()));
+            current = Some((key, time, Vec::new()));
+        }
+    }
+    finish(&mut threads, &mut current);
+    Ok(threads)
+}
+
+// Parses folded stacks ("outer;inner count" per line), as written by
+// stackcollapse-perf.pl and similar tools. There is no time, so samples are
+// laid out one after another in the order given.
+fn parse_folded(text: &str) -> io::Result<Vec<Sample>> {
+    let mut samples = Vec::new();
+    let mut time = 0;
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let parsed = line
+            .rsplit_once(' ')
+            .and_then(|(stack, count)| Some((stack, count.parse::<u64>().ok()?)));
+        let Some((stack, count)) = parsed else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("unable to parse folded stack line {}", number + 1),
+            ));
+        };
+        let stop = time + count as i64 * FOLDED_SAMPLE_NS;
+        samples.push(Sample {
+            interval: Interval::new(Timestamp(time), Timestamp(stop)),
+            count,
+            stack: stack.split(';').map(str::to_owned).collect(),
+        });
+        time = stop;
+    }
+    Ok(samples)
+}
+
+// Loads sampled call stacks, either the output of perf script or folded
+// stacks, into memory. perf script output gets a node per process and a slot
+// per thread; folded stacks go in a single slot.
+pub fn load_perf_samples(path: impl AsRef<Path>) -> io::Result<TraceDataSource> {
+    let path = path.as_ref();
+    let text = std::fs::read_to_string(path)?;
+    parse_perf_samples(path.to_string_lossy(), &text)
+}
+
+pub fn parse_perf_samples(
+    source_locator: impl Into<String>,
+    text: &str,
+) -> io::Result<TraceDataSource> {
+    let mut builder = TraceBuilder::new(source_locator);
+    let samples_field = builder.field("Samples", false);
+
+    // Only perf script output has indented lines
+    if text
+        .lines()
+        .any(|line| line.starts_with(char::is_whitespace))
+    {
+        let mut threads = parse_perf_script(text)?;
+        let process_names: BTreeMap<_, _> = threads
+            .iter()
+            .filter(|((pid, tid), _)| pid == tid)
+            .map(|((pid, _), (comm, _))| (*pid, comm.clone()))
+            .collect();
+        for ((pid, tid), (comm, samples)) in threads.iter_mut() {
+            // The main thread has the same ID as its process, and usually
+            // the process's name
+            let process = match process_names.get(pid) {
+                Some(comm) => format!("{comm} ({pid})"),
+                None => format!("Process {pid}"),
+            };
+            let thread = builder.thread(&process, "Threads", &format!("{comm} {tid}"));
+            add_samples(
+                &mut builder,
+                thread,
+                samples_field,
+                &sample_intervals(samples),
+            );
+        }
+    } else {
+        let thread = builder.thread("Samples", "Threads", "all");
+        add_samples(&mut builder, thread, samples_field, &parse_folded(text)?);
+    }
+    Ok(builder.build())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::data::{DataSource, EntryID, EntryInfo, TileID};
+
+    fn titles(ds: &TraceDataSource, entry_id: &EntryID) -> Vec<Vec<(String, Interval)>> {
+        let info = ds.fetch_info();
+        let meta = ds.fetch_slot_meta_tile(entry_id, TileID(info.interval), true);
+        meta.data
+            .items
+            .iter()
+            .map(|row| {
+                row.iter()
+                    .map(|item| (item.title.clone(), item.original_interval))
+                    .collect()
+            })
+            .collect()
+    }
+
+    fn interval(start: i64, stop: i64) -> Interval {
+        Interval::new(Timestamp(start), Timestamp(stop))
+    }
+
+    #[test]
+    fn test_perf_script() {
+        let text = "\
+app 100/100 [000] 1.000000001: 250000 cycles:
+\t    401000 work+0x10 (/usr/bin/app)
+\t    400500 main+0x20 (/usr/bin/app)
+
+app 100/100 [000] 1.000001001: 250000 cycles:
+\t    402000 helper (/usr/bin/app)
+\t    401000 work+0x18 (/usr/bin/app)
+\t    400500 main+0x20 (/usr/bin/app)
+
+worker 100/101 [001] 1.000002001: 250000 cycles:
+\t    403000 std::vector<int, std::allocator<int> >::push_back+0x4 (/usr/bin/app)
+
+app 100/100 [000] 1.000002001: 250000 cycles:
+\t    400500 main+0x20 (/usr/bin/app)
+";
+        let ds = parse_perf_samples("test.perf", text).unwrap();
+        let info = ds.fetch_info();
+        let Some(EntryInfo::Panel { long_name, .. }) =
+            info.entry_info.get(&EntryID::root().child(0))
+        else {
+            panic!("expected a panel");
+        };
+        assert_eq!(long_name, "app (100)");
+
+        let start = 1_000_000_001;
+        let main = EntryID::root().child(0).child(0).child(0);
+        assert_eq!(
+            titles(&ds, &main),
+            vec![
+                vec![("main".to_owned(), interval(start, start + 3000))],
+                vec![("work".to_owned(), interval(start, start + 2000))],
+                vec![("helper".to_owned(), interval(start + 1000, start + 2000))],
+            ]
+        );
+
+        let worker = EntryID::root().child(0).child(0).child(1);
+        let Some(EntryInfo::Slot { short_name, .. }) = info.entry_info.get(&worker) else {
+            panic!("expected a slot");
+        };
+        assert_eq!(short_name, "worker 101");
+        let worker_titles = titles(&ds, &worker);
+        assert_eq!(
+            worker_titles[0][0].0,
+            "std::vector<int, std::allocator<int> >::push_back"
+        );
+    }
+
+    #[test]
+    fn test_folded() {
+        let text = "main;work 2\nmain;idle 1\n";
+        let ds = parse_perf_samples("test.folded", text).unwrap();
+        let ms = FOLDED_SAMPLE_NS;
+        let all = EntryID::root().child(0).child(0).child(0);
+        assert_eq!(
+            titles(&ds, &all),
+            vec![
+                vec![("main".to_owned(), interval(0, 3 * ms))],
+                vec![
+                    ("work".to_owned(), interval(0, 2 * ms)),
+                    ("idle".to_owned(), interval(2 * ms, 3 * ms))
+                ],
+            ]
+        );
+    }
+}
@@ -0,0 +1,663 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::io;
+use std::path::Path;
+
+use crate::data::{Field, FieldID};
+use crate::timestamp::{Interval, Timestamp};
+use crate::trace_data::{TraceBuilder, TraceDataSource, TraceItem};
+
+// Field numbers from Perfetto's protos/perfetto/trace. Only track events
+// (and the descriptors and interned names they refer to) are read; ftrace,
+// process stats and other data sources are skipped.
+mod proto {
+    pub const TRACE_PACKET: u32 = 1;
+
+    pub const PACKET_TIMESTAMP: u32 = 8;
+    pub const PACKET_SEQUENCE_ID: u32 = 10;
+    pub const PACKET_TRACK_EVENT: u32 = 11;
+    pub const PACKET_INTERNED_DATA: u32 = 12;
+    pub const PACKET_SEQUENCE_FLAGS: u32 = 13;
+    pub const PACKET_TRACK_DESCRIPTOR: u32 = 60;
+
+    pub const SEQ_INCREMENTAL_STATE_CLEARED: u64 = 1;
+
+    pub const EVENT_DEBUG_ANNOTATIONS: u32 = 4;
+    pub const EVENT_TYPE: u32 = 9;
+    pub const EVENT_NAME_IID: u32 = 10;
+    pub const EVENT_TRACK_UUID: u32 = 11;
+    pub const EVENT_CATEGORIES: u32 = 22;
+    pub const EVENT_NAME: u32 = 23;
+    pub const EVENT_COUNTER_VALUE: u32 = 30;
+    pub const EVENT_DOUBLE_COUNTER_VALUE: u32 = 44;
+
+    pub const TYPE_SLICE_BEGIN: u64 = 1;
+    pub const TYPE_SLICE_END: u64 = 2;
+    pub const TYPE_INSTANT: u64 = 3;
+    pub const TYPE_COUNTER: u64 = 4;
+
+    pub const ANNOTATION_NAME_IID: u32 = 1;
+    pub const ANNOTATION_BOOL_VALUE: u32 = 2;
+    pub const ANNOTATION_UINT_VALUE: u32 = 3;
+    pub const ANNOTATION_INT_VALUE: u32 = 4;
+    pub const ANNOTATION_DOUBLE_VALUE: u32 = 5;
+    pub const ANNOTATION_STRING_VALUE: u32 = 6;
+    pub const ANNOTATION_NAME: u32 = 10;
+
+    pub const INTERNED_EVENT_NAMES: u32 = 2;
+    pub const INTERNED_ANNOTATION_NAMES: u32 = 3;
+    pub const INTERNED_IID: u32 = 1;
+    pub const INTERNED_NAME: u32 = 2;
+
+    pub const TRACK_UUID: u32 = 1;
+    pub const TRACK_NAME: u32 = 2;
+    pub const TRACK_PROCESS: u32 = 3;
+    pub const TRACK_THREAD: u32 = 4;
+    pub const TRACK_PARENT_UUID: u32 = 5;
+
+    pub const PROCESS_PID: u32 = 1;
+    pub const PROCESS_NAME: u32 = 6;
+
+    pub const THREAD_PID: u32 = 1;
+    pub const THREAD_TID: u32 = 2;
+    pub const THREAD_NAME: u32 = 5;
+}
+
+// Protobuf wire format. We don't depend on a protobuf library for the few
+// messages we need.
+#[derive(Debug, Clone, Copy)]
+enum Value<'a> {
+    Varint(u64),
+    Fixed64(u64),
+    Bytes(&'a [u8]),
+    Fixed32(u32),
+}
+
+impl<'a> Value<'a> {
+    fn as_u64(self) -> u64 {
+        match self {
+            Value::Varint(x) | Value::Fixed64(x) => x,
+            Value::Fixed32(x) => x as u64,
+            Value::Bytes(_) => 0,
+        }
+    }
+
+    fn as_f64(self) -> f64 {
+        match self {
+            Value::Fixed64(x) => f64::from_bits(x),
+            Value::Fixed32(x) => f32::from_bits(x) as f64,
+            Value::Varint(x) => x as i64 as f64,
+            Value::Bytes(_) => 0.0,
+        }
+    }
+
+    fn as_bytes(self) -> &'a [u8] {
+        match self {
+            Value::Bytes(x) => x,
+            _ => &[],
+        }
+    }
+
+    fn as_string(self) -> String {
+        String::from_utf8_lossy(self.as_bytes()).into_owned()
+    }
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("invalid Perfetto trace: {message}"),
+    )
+}
+
+struct Message<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Message<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data }
+    }
+
+    fn varint(&mut self) -> io::Result<u64> {
+        let mut result = 0;
+        for shift in (0..64).step_by(7) {
+            let (&byte, rest) = self
+                .data
+                .split_first()
+                .ok_or_else(|| invalid("truncated varint"))?;
+            self.data = rest;
+            result |= ((byte & 0x7f) as u64) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(result);
+            }
+        }
+        Err(invalid("varint too long"))
+    }
+
+    fn take(&mut self, n: usize) -> io::Result<&'a [u8]> {
+        if self.data.len() < n {
+            return Err(invalid("truncated field"));
+        }
+        let (result, rest) = self.data.split_at(n);
+        self.data = rest;
+        Ok(result)
+    }
+
+    fn field(&mut self) -> io::Result<(u32, Value<'a>)> {
+        let key = self.varint()?;
+        let value = match key & 7 {
+            0 => Value::Varint(self.varint()?),
+            1 => Value::Fixed64(u64::from_le_bytes(self.take(8)?.try_into().unwrap())),
+            2 => {
+                let n = self.varint()? as usize;
+                Value::Bytes(self.take(n)?)
+            }
+            5 => Value::Fixed32(u32::from_le_bytes(self.take(4)?.try_into().unwrap())),
+            _ => return Err(invalid("unsupported wire type")),
+        };
+        Ok(((key >> 3) as u32, value))
+    }
+}
+
+impl<'a> Iterator for Message<'a> {
+    type Item = io::Result<(u32, Value<'a>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.data.is_empty() {
+            return None;
+        }
+        let result = self.field();
+        if result.is_err() {
+            // Don't keep reading after an error
+            self.data = &[];
+        }
+        Some(result)
+    }
+}
+
+#[derive(Debug, Default)]
+struct Track {
+    name: Option<String>,
+    parent: Option<u64>,
+    pid: Option<u64>,
+    tid: Option<u64>,
+    process_name: Option<String>,
+}
+
+struct Slice {
+    name: String,
+    start: Timestamp,
+    fields: Vec<(FieldID, Field)>,
+}
+
+#[derive(Default)]
+struct Sequence {
+    event_names: BTreeMap<u64, String>,
+    annotation_names: BTreeMap<u64, String>,
+}
+
+#[derive(Default)]
+struct Importer {
+    tracks: BTreeMap<u64, Track>,
+    sequences: BTreeMap<u64, Sequence>,
+    slices: Vec<(u64, Interval, Slice)>,
+    samples: Vec<(u64, Timestamp, f64)>,
+    // Open slices on each track
+    stacks: BTreeMap<u64, Vec<Slice>>,
+    fields: Vec<(String, Field)>,
+}
+
+impl Importer {
+    fn packet(&mut self, builder: &mut TraceBuilder, data: &[u8]) -> io::Result<()> {
+        let mut timestamp = 0;
+        let mut sequence_id = 0;
+        let mut event = None;
+        let mut interned = None;
+        let mut cleared = false;
+        for field in Message::new(data) {
+            match field? {
+                (proto::PACKET_TIMESTAMP, value) => timestamp = value.as_u64() as i64,
+                (proto::PACKET_SEQUENCE_ID, value) => sequence_id = value.as_u64(),
+                (proto::PACKET_TRACK_EVENT, value) => event = Some(value.as_bytes()),
+                (proto::PACKET_INTERNED_DATA, value) => interned = Some(value.as_bytes()),
+                (proto::PACKET_SEQUENCE_FLAGS, value) => {
+                    cleared = value.as_u64() & proto::SEQ_INCREMENTAL_STATE_CLEARED != 0;
+                }
+                (proto::PACKET_TRACK_DESCRIPTOR, value) => self.track(value.as_bytes())?,
+                _ => {}
+            }
+        }
+
+        // Interned data applies to the packet it arrives in, so it has to be
+        // read before the event
+        if cleared {
+            self.sequences.remove(&sequence_id);
+        }
+        if let Some(interned) = interned {
+            self.interned(sequence_id, interned)?;
+        }
+        if let Some(event) = event {
+            self.event(builder, sequence_id, Timestamp(timestamp), event)?;
+        }
+        Ok(())
+    }
+
+    fn track(&mut self, data: &[u8]) -> io::Result<()> {
+        let mut uuid = 0;
+        let mut track = Track::default();
+        for field in Message::new(data) {
+            match field? {
+                (proto::TRACK_UUID, value) => uuid = value.as_u64(),
+                (proto::TRACK_NAME, value) => track.name = Some(value.as_string()),
+                (proto::TRACK_PARENT_UUID, value) => track.parent = Some(value.as_u64()),
+                (proto::TRACK_PROCESS, value) => {
+                    for field in Message::new(value.as_bytes()) {
+                        match field? {
+                            (proto::PROCESS_PID, value) => track.pid = Some(value.as_u64()),
+                            (proto::PROCESS_NAME, value) => {
+                                track.process_name = Some(value.as_string())
+                            }
+                            _ => {}
+                        }
+                    }
+                }
+                (proto::TRACK_THREAD, value) => {
+                    for field in Message::new(value.as_bytes()) {
+                        match field? {
+                            (proto::THREAD_PID, value) => track.pid = Some(value.as_u64()),
+                            (proto::THREAD_TID, value) => track.tid = Some(value.as_u64()),
+                            (proto::THREAD_NAME, value) => track.name = Some(value.as_string()),
+                            _ => {}
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        self.tracks.insert(uuid, track);
+        Ok(())
+    }
+
+    fn interned(&mut self, sequence_id: u64, data: &[u8]) -> io::Result<()> {
+        let sequence = self.sequences.entry(sequence_id).or_default();
+        for field in Message::new(data) {
+            let (number, value) = field?;
+            let names = match number {
+                proto::INTERNED_EVENT_NAMES => &mut sequence.event_names,
+                proto::INTERNED_ANNOTATION_NAMES => &mut sequence.annotation_names,
+                _ => continue,
+            };
+            let mut iid = 0;
+            let mut name = String::new();
+            for field in Message::new(value.as_bytes()) {
+                match field? {
+                    (proto::INTERNED_IID, value) => iid = value.as_u64(),
+                    (proto::INTERNED_NAME, value) => name = value.as_string(),
+                    _ => {}
+                }
+            }
+            names.insert(iid, name);
+        }
+        Ok(())
+    }
+
+    fn annotation(&mut self, sequence_id: u64, data: &[u8]) -> io::Result<()> {
+        let mut name = String::new();
+        let mut result = Field::Empty;
+        for field in Message::new(data) {
+            match field? {
+                (proto::ANNOTATION_NAME, value) => name = value.as_string(),
+                (proto::ANNOTATION_NAME_IID, value) => {
+                    let sequence = self.sequences.get(&sequence_id);
+                    let interned = sequence.and_then(|s| s.annotation_names.get(&value.as_u64()));
+                    name = interned.cloned().unwrap_or_default();
+                }
+                (proto::ANNOTATION_BOOL_VALUE, value) => {
+                    result = Field::String((value.as_u64() != 0).to_string())
+                }
+                (proto::ANNOTATION_UINT_VALUE, value) => result = Field::U64(value.as_u64()),
+                (proto::ANNOTATION_INT_VALUE, value) => result = Field::I64(value.as_u64() as i64),
+                (proto::ANNOTATION_DOUBLE_VALUE, value) => {
+                    result = Field::String(value.as_f64().to_string())
+                }
+                (proto::ANNOTATION_STRING_VALUE, value) => {
+                    result = Field::String(value.as_string())
+                }
+                _ => {}
+            }
+        }
+        if !name.is_empty() {
+            self.fields.push((name, result));
+        }
+        Ok(())
+    }
+
+    fn event(
+        &mut self,
+        builder: &mut TraceBuilder,
+        sequence_id: u64,
+        time: Timestamp,
+        data: &[u8],
+    ) -> io::Result<()> {
+        let mut kind = 0;
+        let mut track = 0;
+        let mut name = String::new();
+        let mut categories = Vec::new();
+        let mut value = None;
+        for field in Message::new(data) {
+            match field? {
+                (proto::EVENT_TYPE, v) => kind = v.as_u64(),
+                (proto::EVENT_TRACK_UUID, v) => track = v.as_u64(),
+                (proto::EVENT_NAME, v) => name = v.as_string(),
+                (proto::EVENT_NAME_IID, v) => {
+                    let sequence = self.sequences.get(&sequence_id);
+                    let interned = sequence.and_then(|s| s.event_names.get(&v.as_u64()));
+                    name = interned.cloned().unwrap_or_default();
+                }
+                (proto::EVENT_CATEGORIES, v) => categories.push(v.as_string()),
+                (proto::EVENT_COUNTER_VALUE, v) => value = Some(v.as_u64() as i64 as f64),
+                (proto::EVENT_DOUBLE_COUNTER_VALUE, v) => value = Some(v.as_f64()),
+                (proto::EVENT_DEBUG_ANNOTATIONS, v) => {
+                    self.annotation(sequence_id, v.as_bytes())?
+                }
+                _ => {}
+            }
+        }
+
+        let mut fields = Vec::new();
+        if !categories.is_empty() {
+            let category = builder.field("Category", true);
+            fields.push((category, Field::String(categories.join(","))));
+        }
+        for (name, field) in std::mem::take(&mut self.fields) {
+            let searchable = matches!(field, Field::String(_));
+            fields.push((builder.field(name, searchable), field));
+        }
+
+        match kind {
+            proto::TYPE_SLICE_BEGIN => {
+                let slice = Slice {
+                    name,
+                    start: time,
+                    fields,
+                };
+                self.stacks.entry(track).or_default().push(slice);
+            }
+            proto::TYPE_SLICE_END => {
+                let begin = self.stacks.get_mut(&track).and_then(Vec::pop);
+                // An end without a begin has nothing to close
+                if let Some(mut slice) = begin {
+                    slice.fields.extend(fields);
+                    let interval = Interval::new(slice.start, time);
+                    self.slices.push((track, interval, slice));
+                }
+            }
+            proto::TYPE_INSTANT => {
+                let slice = Slice {
+                    name,
+                    start: time,
+                    fields,
+                };
+                self.slices.push((track, Interval::new(time, time), slice));
+            }
+            proto::TYPE_COUNTER => {
+                if let Some(value) = value {
+                    self.samples.push((track, time, value));
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    // The process that owns the track, looking through its parents
+    fn process(&self, uuid: u64) -> String {
+        let mut uuid = Some(uuid);
+        let mut pid = None;
+        // Bound the walk in case of a cycle
+        for _ in 0..16 {
+            let Some(track) = uuid.and_then(|uuid| self.tracks.get(&uuid)) else {
+                break;
+            };
+            if track.process_name.is_some() && track.tid.is_none() {
+                return track.process_name.clone().unwrap();
+            }
+            pid = pid.or(track.pid);
+            uuid = track.parent;
+        }
+        let name = pid.and_then(|pid| {
+            let process = self.tracks.values().find(|track| {
+                track.pid == Some(pid) && track.tid.is_none() && track.process_name.is_some()
+            })?;
+            process.process_name.clone()
+        });
+        match (name, pid) {
+            (Some(name), _) => name,
+            (None, Some(pid)) => format!("Process {pid}"),
+            (None, None) => "Global".to_owned(),
+        }
+    }
+
+    fn track_name(&self, uuid: u64) -> String {
+        let track = self.tracks.get(&uuid);
+        match track.and_then(|track| track.name.clone()) {
+            Some(name) => name,
+            None => match track.and_then(|track| track.tid) {
+                Some(tid) => format!("Thread {tid}"),
+                None => format!("Track {uuid}"),
+            },
+        }
+    }
+
+    fn finish(mut self, builder: &mut TraceBuilder) {
+        // Anything still open ran until the end of the trace
+        let end = self
+            .slices
+            .iter()
+            .map(|(_, interval, _)| interval.stop)
+            .chain(self.samples.iter().map(|(_, time, _)| *time))
+            .max()
+            .unwrap_or_default();
+        for (track, stack) in std::mem::take(&mut self.stacks) {
+            for slice in stack {
+                let interval = Interval::new(slice.start, end.max(slice.start));
+                self.slices.push((track, interval, slice));
+            }
+        }
+
+        // Register tracks in UUID order, so that the layout doesn't depend
+        // on which event comes first
+        let used: BTreeSet<_> = self.slices.iter().map(|(track, _, _)| *track).collect();
+        for uuid in used {
+            builder.thread(&self.process(uuid), "Threads", &self.track_name(uuid));
+        }
+
+        for (track, interval, slice) in std::mem::take(&mut self.slices) {
+            let thread = builder.thread(&self.process(track), "Threads", &self.track_name(track));
+            builder.add_item(
+                thread,
+                TraceItem {
+                    interval,
+                    title: slice.name,
+                    color: None,
+                    fields: slice.fields,
+                },
+            );
+        }
+        for (track, time, value) in std::mem::take(&mut self.samples) {
+            let counter = builder.counter(&self.process(track), &self.track_name(track));
+            builder.add_sample(counter, time, value);
+        }
+    }
+}
+
+// Loads a Perfetto protobuf trace into memory. Slices on thread tracks are
+// shown as items in a slot per thread, grouped by process. Other tracks get
+// a slot of their own in the process that owns them. Counter tracks become
+// summaries, scaled to their largest value.
+pub fn load_perfetto_trace(path: impl AsRef<Path>) -> io::Result<TraceDataSource> {
+    let path = path.as_ref();
+    let data = std::fs::read(path)?;
+    parse_perfetto_trace(path.to_string_lossy(), &data)
+}
+
+pub fn parse_perfetto_trace(
+    source_locator: impl Into<String>,
+    data: &[u8],
+) -> io::Result<TraceDataSource> {
+    let mut builder = TraceBuilder::new(source_locator);
+    let mut importer = Importer::default();
+    for field in Message::new(data) {
+        if let (proto::TRACE_PACKET, value) = field? {
+            importer.packet(&mut builder, value.as_bytes())?;
+        }
+    }
+    importer.finish(&mut builder);
+    Ok(builder.build())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::data::{DataSource, EntryID, EntryInfo, TileID};
+
+    fn varint(out: &mut Vec<u8>, mut x: u64) {
+        while x >= 0x80 {
+            out.push((x as u8) | 0x80);
+            x >>= 7;
+        }
+        out.push(x as u8);
+    }
+
+    fn uint(out: &mut Vec<u8>, number: u32, x: u64) {
+        varint(out, (number as u64) << 3);
+        varint(out, x);
+    }
+
+    fn bytes(out: &mut Vec<u8>, number: u32, data: &[u8]) {
+        varint(out, ((number as u64) << 3) | 2);
+        varint(out, data.len() as u64);
+        out.extend_from_slice(data);
+    }
+
+    fn packet(trace: &mut Vec<u8>, build: impl FnOnce(&mut Vec<u8>)) {
+        let mut packet = Vec::new();
+        build(&mut packet);
+        bytes(trace, proto::TRACE_PACKET, &packet);
+    }
+
+    #[test]
+    fn test_perfetto_trace() {
+        let mut trace = Vec::new();
+        packet(&mut trace, |p| {
+            let mut process = Vec::new();
+            uint(&mut process, proto::PROCESS_PID, 7);
+            bytes(&mut process, proto::PROCESS_NAME, b"app");
+            let mut track = Vec::new();
+            uint(&mut track, proto::TRACK_UUID, 1);
+            bytes(&mut track, proto::TRACK_PROCESS, &process);
+            bytes(p, proto::PACKET_TRACK_DESCRIPTOR, &track);
+        });
+        packet(&mut trace, |p| {
+            let mut thread = Vec::new();
+            uint(&mut thread, proto::THREAD_PID, 7);
+            uint(&mut thread, proto::THREAD_TID, 8);
+            let mut track = Vec::new();
+            uint(&mut track, proto::TRACK_UUID, 2);
+            uint(&mut track, proto::TRACK_PARENT_UUID, 1);
+            bytes(&mut track, proto::TRACK_THREAD, &thread);
+            bytes(p, proto::PACKET_TRACK_DESCRIPTOR, &track);
+        });
+        packet(&mut trace, |p| {
+            let mut track = Vec::new();
+            uint(&mut track, proto::TRACK_UUID, 3);
+            uint(&mut track, proto::TRACK_PARENT_UUID, 1);
+            bytes(&mut track, proto::TRACK_NAME, b"memory");
+            // The counter descriptor is empty; the track's events say
+            // that it's a counter
+            bytes(&mut track, 8, &[]);
+            bytes(p, proto::PACKET_TRACK_DESCRIPTOR, &track);
+        });
+
+        // An interned name, then a slice that uses it around one with a
+        // plain name
+        packet(&mut trace, |p| {
+            let mut name = Vec::new();
+            uint(&mut name, proto::INTERNED_IID, 1);
+            bytes(&mut name, proto::INTERNED_NAME, b"outer");
+            let mut interned = Vec::new();
+            bytes(&mut interned, proto::INTERNED_EVENT_NAMES, &name);
+            let mut event = Vec::new();
+            uint(&mut event, proto::EVENT_TYPE, proto::TYPE_SLICE_BEGIN);
+            uint(&mut event, proto::EVENT_TRACK_UUID, 2);
+            uint(&mut event, proto::EVENT_NAME_IID, 1);
+            uint(p, proto::PACKET_TIMESTAMP, 100);
+            uint(p, proto::PACKET_SEQUENCE_ID, 1);
+            bytes(p, proto::PACKET_INTERNED_DATA, &interned);
+            bytes(p, proto::PACKET_TRACK_EVENT, &event);
+        });
+        for (time, kind) in [(200, proto::TYPE_SLICE_BEGIN), (300, proto::TYPE_SLICE_END)] {
+            packet(&mut trace, |p| {
+                let mut annotation = Vec::new();
+                bytes(&mut annotation, proto::ANNOTATION_NAME, b"size");
+                uint(&mut annotation, proto::ANNOTATION_UINT_VALUE, 64);
+                let mut event = Vec::new();
+                uint(&mut event, proto::EVENT_TYPE, kind);
+                uint(&mut event, proto::EVENT_TRACK_UUID, 2);
+                bytes(&mut event, proto::EVENT_NAME, b"inner");
+                bytes(&mut event, proto::EVENT_DEBUG_ANNOTATIONS, &annotation);
+                uint(p, proto::PACKET_TIMESTAMP, time);
+                uint(p, proto::PACKET_SEQUENCE_ID, 1);
+                bytes(p, proto::PACKET_TRACK_EVENT, &event);
+            });
+        }
+        packet(&mut trace, |p| {
+            let mut event = Vec::new();
+            uint(&mut event, proto::EVENT_TYPE, proto::TYPE_SLICE_END);
+            uint(&mut event, proto::EVENT_TRACK_UUID, 2);
+            uint(p, proto::PACKET_TIMESTAMP, 400);
+            uint(p, proto::PACKET_SEQUENCE_ID, 1);
+            bytes(p, proto::PACKET_TRACK_EVENT, &event);
+        });
+        for (time, value) in [(100, 5), (250, 10)] {
+            packet(&mut trace, |p| {
+                let mut event = Vec::new();
+                uint(&mut event, proto::EVENT_TYPE, proto::TYPE_COUNTER);
+                uint(&mut event, proto::EVENT_TRACK_UUID, 3);
+                uint(&mut event, proto::EVENT_COUNTER_VALUE, value);
+                uint(p, proto::PACKET_TIMESTAMP, time);
+                bytes(p, proto::PACKET_TRACK_EVENT, &event);
+            });
+        }
+
+        let ds = parse_perfetto_trace("test.pftrace", &trace).unwrap();
+        let info = ds.fetch_info();
+        assert_eq!(info.interval, Interval::new(Timestamp(100), Timestamp(400)));
+        let Some(EntryInfo::Panel { long_name, .. }) =
+            info.entry_info.get(&EntryID::root().child(0))
+        else {
+            panic!("expected a panel");
+        };
+        assert_eq!(long_name, "app");
+
+        let thread = EntryID::root().child(0).child(0).child(0);
+        let meta = ds.fetch_slot_meta_tile(&thread, TileID(info.interval), true);
+        let outer = &meta.data.items[0][0];
+        assert_eq!(outer.title, "outer");
+        assert_eq!(
+            outer.original_interval,
+            Interval::new(Timestamp(100), Timestamp(400))
+        );
+        let inner = &meta.data.items[1][0];
+        assert_eq!(inner.title, "inner");
+        // The interval, plus the annotation from each end
+        assert_eq!(inner.fields.len(), 3);
+
+        // The counter is scaled to its maximum
+        let memory = EntryID::root().child(0).child(1).summary();
+        let summary = ds.fetch_summary_tile(&memory, TileID(info.interval), true);
+        let utils: Vec<_> = summary.data.utilization.iter().map(|p| p.util).collect();
+        assert_eq!(utils, vec![0.5, 0.5, 1.0, 1.0]);
+    }
+}
@@ -0,0 +1,424 @@
+use std::collections::BTreeMap;
+
+use egui::Color32;
+
+use crate::data::{
+    Capabilities, DataSource, DataSourceDescription, DataSourceInfo, EntryID, EntryInfo, Field,
+    FieldID, FieldSchema, Item, ItemMeta, ItemUID, PROTOCOL_VERSION, SlotMetaTile,
+    SlotMetaTileData, SlotTile, SlotTileData, SummaryTile, SummaryTileData, SummaryUnits, TileID,
+    TileSet, UtilPoint,
+};
+use crate::timestamp::{Interval, Timestamp};
+
+const KINDS: &[(&str, Color32)] = &[
+    ("CPU", Color32::BLUE),
+    ("GPU", Color32::GREEN),
+    ("Util", Color32::RED),
+    ("Chan", Color32::YELLOW),
+];
+
+const TITLES: &[(&str, Color32)] = &[
+    ("init", Color32::KHAKI),
+    ("compute", Color32::BLUE),
+    ("copy", Color32::DARK_GREEN),
+    ("reduce", Color32::RED),
+    ("barrier", Color32::DARK_BLUE),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RandomConfig {
+    pub seed: u64,
+    pub nodes: u64,
+    // Processors of each kind on every node
+    pub procs: u64,
+    // Each processor gets between 1 and this many rows
+    pub max_rows: u64,
+    // Upper bound on items per row; roughly three quarters are generated
+    pub items_per_row: u64,
+}
+
+impl Default for RandomConfig {
+    fn default() -> Self {
+        Self {
+            seed: 0,
+            nodes: 16,
+            procs: 4,
+            max_rows: 16,
+            items_per_row: 1000,
+        }
+    }
+}
+
+// SplitMix64: small, fast and good enough for synthetic data. We don't
+// depend on rand outside of the examples.
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64, stream: u64) -> Self {
+        let mut rng = Self(seed ^ stream.wrapping_mul(0xD1B5_4A32_D192_ED03));
+        rng.next_u64();
+        rng
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    // Uniform in [0, 1)
+    fn next_f32(&mut self) -> f32 {
+        (self.next_u64() >> 40) as f32 / (1u64 << 24) as f32
+    }
+
+    // Uniform in [lo, hi)
+    fn range(&mut self, lo: u64, hi: u64) -> u64 {
+        lo + self.next_u64() % (hi - lo)
+    }
+}
+
+// Generates a profile from a seed, with no input files. Every tile is a pure
+// function of the configuration, so the same seed always produces the same
+// profile regardless of the order in which tiles are requested.
+pub struct RandomDataSource {
+    config: RandomConfig,
+    info: DataSourceInfo,
+    interval_field: FieldID,
+    item_uid_field: FieldID,
+    row_field: FieldID,
+}
+
+impl RandomDataSource {
+    pub fn new(config: RandomConfig) -> Self {
+        assert!(config.max_rows > 0);
+
+        let mut field_schema = FieldSchema::new();
+        let interval_field = field_schema.insert("Interval".to_owned(), false);
+        let item_uid_field = field_schema.insert("Item UID".to_owned(), false);
+        let row_field = field_schema.insert("Row".to_owned(), false);
+
+        let mut rng = Rng::new(config.seed, 0);
+        let interval = Interval::new(
+            Timestamp(0),
+            Timestamp(rng.range(1_000_000, 2_000_000) as i64),
+        );
+
+        let mut result = Self {
+            config,
+            info: DataSourceInfo {
+                entry_info: EntryInfo::Panel {
+                    short_name: "root".to_owned(),
+                    long_name: "root".to_owned(),
+                    summary: None,
+                    slots: Vec::new(),
+                },
+                interval,
+                tile_set: TileSet::default(),
+                field_schema,
+                warning_message: Some(
+                    "Demo only. The data in this profile is synthetic.".to_owned(),
+                ),
+                refresh_interval: None,
+                version: PROTOCOL_VERSION,
+                capabilities: Capabilities::default(),
+                entry_tile_sets: BTreeMap::new(),
+            },
+            interval_field,
+            item_uid_field,
+            row_field,
+        };
+        result.info.entry_info = result.entry_info();
+        result
+    }
+
+    // Global index of the processor, used to derive its random stream and
+    // to keep item UIDs unique across the profile
+    fn proc_index(&self, node: u64, kind: u64, proc: u64) -> u64 {
+        (node * KINDS.len() as u64 + kind) * self.config.procs + proc
+    }
+
+    // Streams are disjoint between summaries and slots, and stream 0 is
+    // reserved for the profile as a whole
+    fn summary_rng(&self, node: u64, kind: u64) -> Rng {
+        Rng::new(self.config.seed, 2 * (node * KINDS.len() as u64 + kind) + 1)
+    }
+
+    fn slot_rng(&self, node: u64, kind: u64, proc: u64) -> Rng {
+        Rng::new(self.config.seed, 2 * self.proc_index(node, kind, proc) + 2)
+    }
+
+    fn entry_info(&self) -> EntryInfo {
+        let mut node_slots = Vec::new();
+        for node in 0..self.config.nodes {
+            let mut kind_slots = Vec::new();
+            for (kind, (name, color)) in KINDS.iter().enumerate() {
+                let mut proc_slots = Vec::new();
+                for proc in 0..self.config.procs {
+                    // The row count is always the first value in the stream
+                    let max_rows = self
+                        .slot_rng(node, kind as u64, proc)
+                        .range(1, self.config.max_rows + 1);
+                    proc_slots.push(EntryInfo::Slot {
+                        short_name: format!(
+                            "{}{proc}",
+                            name.chars().next().unwrap().to_lowercase()
+                        ),
+                        long_name: format!("Node {node} {name} {proc}"),
+                        max_rows,
+                    });
+                }
+                kind_slots.push(EntryInfo::Panel {
+                    short_name: name.to_lowercase(),
+                    long_name: format!("Node {node} {name}"),
+                    summary: Some(Box::new(EntryInfo::Summary {
+                        color: *color,
+                        units: SummaryUnits::Utilization,
+                    })),
+                    slots: proc_slots,
+                });
+            }
+            node_slots.push(EntryInfo::Panel {
+                short_name: format!("n{node}"),
+                long_name: format!("Node {node}"),
+                summary: None,
+                slots: kind_slots,
+            });
+        }
+        EntryInfo::Panel {
+            short_name: "root".to_owned(),
+            long_name: "root".to_owned(),
+            summary: None,
+            slots: node_slots,
+        }
+    }
+
+    fn generate_summary(&self, entry_id: &EntryID) -> Vec<UtilPoint> {
+        // Midpoint displacement, with smaller perturbations at finer levels
+        fn generate_point(
+            rng: &mut Rng,
+            first: UtilPoint,
+            last: UtilPoint,
+            level: i32,
+            utilization: &mut Vec<UtilPoint>,
+        ) {
+            let time = Timestamp((first.time.0 + last.time.0) / 2);
+            let util = (first.util + last.util) * 0.5;
+            let diff = (rng.next_f32() - 0.5) / 1.2_f32.powi(LEVELS - level);
+            let point = UtilPoint {
+                time,
+                util: (util + diff).clamp(0.0, 1.0),
+            };
+            if level > 0 {
+                generate_point(rng, first, point, level - 1, utilization);
+            }
+            utilization.push(point);
+            if level > 0 {
+                generate_point(rng, point, last, level - 1, utilization);
+            }
+        }
+        const LEVELS: i32 = 8;
+
+        let node = entry_id.slot_index(0).unwrap();
+        let kind = entry_id.slot_index(1).unwrap();
+        let mut rng = self.summary_rng(node, kind);
+
+        let first = UtilPoint {
+            time: self.info.interval.start,
+            util: rng.next_f32(),
+        };
+        let last = UtilPoint {
+            time: self.info.interval.stop,
+            util: rng.next_f32(),
+        };
+        let mut utilization = vec![first];
+        generate_point(&mut rng, first, last, LEVELS, &mut utilization);
+        utilization.push(last);
+        utilization
+    }
+
+    fn generate_slot(&self, entry_id: &EntryID) -> (Vec<Vec<Item>>, Vec<Vec<ItemMeta>>) {
+        let node = entry_id.slot_index(0).unwrap();
+        let kind = entry_id.slot_index(1).unwrap();
+        let proc = entry_id.slot_index(2).unwrap();
+        let mut rng = self.slot_rng(node, kind, proc);
+        let rows = rng.range(1, self.config.max_rows + 1);
+
+        let n = self.config.items_per_row;
+        let base_uid = self.proc_index(node, kind, proc) * self.config.max_rows * n;
+
+        let mut items = Vec::new();
+        let mut item_metas = Vec::new();
+        for row in 0..rows {
+            let mut row_items = Vec::new();
+            let mut row_item_metas = Vec::new();
+            for i in 0..n {
+                // Leave some gaps so that rows aren't solid
+                if rng.next_f32() < 0.25 {
+                    continue;
+                }
+                let begin = rng.next_f32() * 0.5;
+                let end = begin + 0.1 + rng.next_f32() * (0.9 - begin);
+                let start = self.info.interval.lerp((i as f32 + begin) / n as f32);
+                let stop = self.info.interval.lerp((i as f32 + end) / n as f32);
+                let interval = Interval::new(start, stop);
+
+                let (title, color) = TITLES[rng.range(0, TITLES.len() as u64) as usize];
+                let item_uid = ItemUID(base_uid + row * n + i);
+                row_items.push(Item {
+                    item_uid,
+                    interval,
+                    color,
+                });
+                row_item_metas.push(ItemMeta {
+                    item_uid,
+                    original_interval: interval,
+                    title: title.to_owned(),
+                    fields: vec![
+                        (self.interval_field, Field::Interval(interval), None),
+                        (self.item_uid_field, Field::U64(item_uid.0), None),
+                        (self.row_field, Field::U64(row), None),
+                    ],
+                });
+            }
+            items.push(row_items);
+            item_metas.push(row_item_metas);
+        }
+        (items, item_metas)
+    }
+}
+
+impl DataSource for RandomDataSource {
+    fn fetch_description(&self) -> DataSourceDescription {
+        DataSourceDescription {
+            source_locator: vec![format!("Random Data Source (seed {})", self.config.seed)],
+        }
+    }
+
+    fn fetch_info(&self) -> DataSourceInfo {
+        self.info.clone()
+    }
+
+    fn fetch_summary_tile(&self, entry_id: &EntryID, tile_id: TileID, _full: bool) -> SummaryTile {
+        let utilization = self.generate_summary(entry_id);
+
+        // Keep the points inside the tile, plus one on either side so that
+        // the line reaches the edges
+        let first = utilization
+            .iter()
+            .rposition(|point| point.time <= tile_id.0.start)
+            .unwrap_or(0);
+        let last = utilization
+            .iter()
+            .position(|point| point.time >= tile_id.0.stop)
+            .unwrap_or(utilization.len() - 1);
+
+        SummaryTile {
+            entry_id: entry_id.clone(),
+            tile_id,
+            data: SummaryTileData {
+                utilization: utilization[first..=last].to_vec(),
+            },
+        }
+    }
+
+    fn fetch_slot_tile(&self, entry_id: &EntryID, tile_id: TileID, _full: bool) -> SlotTile {
+        let (items, _) = self.generate_slot(entry_id);
+
+        let items = items
+            .into_iter()
+            .map(|row| {
+                row.into_iter()
+                    .filter(|item| tile_id.0.overlaps(item.interval))
+                    .map(|mut item| {
+                        // Items that straddle the tile boundary are sliced
+                        // to fit
+                        item.interval = item.interval.intersection(tile_id.0);
+                        item
+                    })
+                    .collect()
+            })
+            .collect();
+
+        SlotTile {
+            entry_id: entry_id.clone(),
+            tile_id,
+            data: SlotTileData { items },
+        }
+    }
+
+    fn fetch_slot_meta_tile(
+        &self,
+        entry_id: &EntryID,
+        tile_id: TileID,
+        _full: bool,
+    ) -> SlotMetaTile {
+        let (_, item_metas) = self.generate_slot(entry_id);
+
+        let items = item_metas
+            .into_iter()
+            .map(|row| {
+                row.into_iter()
+                    .filter(|item| tile_id.0.overlaps(item.original_interval))
+                    .collect()
+            })
+            .collect();
+
+        SlotMetaTile {
+            entry_id: entry_id.clone(),
+            tile_id,
+            data: SlotMetaTileData { items },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_random_deterministic() {
+        let config = RandomConfig {
+            nodes: 2,
+            max_rows: 4,
+            items_per_row: 50,
+            ..Default::default()
+        };
+        let ds = RandomDataSource::new(config);
+        let info = ds.fetch_info();
+        let entry_id = EntryID::root().child(1).child(2).child(3);
+        let Some(EntryInfo::Slot { max_rows, .. }) = info.entry_info.get(&entry_id) else {
+            panic!("expected a slot");
+        };
+
+        let tile_id = TileID(info.interval);
+        let tile = ds.fetch_slot_tile(&entry_id, tile_id, true);
+        let meta = ds.fetch_slot_meta_tile(&entry_id, tile_id, true);
+        assert_eq!(tile.data.items.len() as u64, *max_rows);
+        for (row, row_meta) in tile.data.items.iter().zip(&meta.data.items) {
+            assert!(row.len() as u64 <= config.items_per_row);
+            assert_eq!(row.len(), row_meta.len());
+            for (item, item_meta) in row.iter().zip(row_meta) {
+                assert_eq!(item.item_uid, item_meta.item_uid);
+            }
+        }
+
+        // The same seed gives the same profile, and a different one doesn't
+        let again = RandomDataSource::new(config).fetch_slot_tile(&entry_id, tile_id, true);
+        assert_eq!(
+            tile.data.items[0][0].interval,
+            again.data.items[0][0].interval
+        );
+        let other = RandomDataSource::new(RandomConfig { seed: 1, ..config });
+        assert_ne!(other.fetch_info().interval, info.interval);
+
+        // Summary tiles cover the requested interval
+        let summary_id = EntryID::root().child(0).child(0).summary();
+        let half = TileID(Interval::new(info.interval.start, info.interval.lerp(0.5)));
+        let summary = ds.fetch_summary_tile(&summary_id, half, true);
+        let points = &summary.data.utilization;
+        assert!(points.first().unwrap().time <= half.0.start);
+        assert!(points.last().unwrap().time >= half.0.stop);
+    }
+}
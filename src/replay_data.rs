@@ -0,0 +1,394 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Write};
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::data::{DataSourceDescription, DataSourceInfo, EntryID, ItemMetaRequest, TileID};
+use crate::deferred_data::{
+    DataSourceInfoResult, DeferredDataSource, ItemsMetaResponse, RequestPriority,
+    SlotMetaTileResponse, SlotTileResponse, SummaryTileResponse, TileKind, TileRequest,
+    TransferStats,
+};
+
+// One entry in a recording. Responses are recorded as they are returned to
+// the app, so a recording contains exactly what the app saw.
+#[derive(Deserialize)]
+enum Record {
+    Description(DataSourceDescription),
+    Info(DataSourceInfo),
+    SummaryTile(SummaryTileResponse),
+    SlotTile(SlotTileResponse),
+    SlotMetaTile(SlotMetaTileResponse),
+    ItemsMeta(ItemsMetaResponse),
+}
+
+// Same encoding as Record, but borrowed to avoid copying tiles on the way out
+#[derive(Serialize)]
+enum RecordRef<'a> {
+    Description(&'a DataSourceDescription),
+    Info(&'a DataSourceInfo),
+    SummaryTile(&'a SummaryTileResponse),
+    SlotTile(&'a SlotTileResponse),
+    SlotMetaTile(&'a SlotMetaTileResponse),
+    ItemsMeta(&'a ItemsMetaResponse),
+}
+
+// Records every response from the wrapped data source to a file that can be
+// loaded with ReplayDataSource
+pub struct RecordingDeferredDataSource<T: DeferredDataSource> {
+    data_source: T,
+    writer: zstd::stream::AutoFinishEncoder<'static, BufWriter<File>>,
+}
+
+impl<T: DeferredDataSource> RecordingDeferredDataSource<T> {
+    pub fn new(data_source: T, path: impl AsRef<Path>) -> io::Result<Self> {
+        let f = BufWriter::new(File::create(path)?);
+        let writer = zstd::Encoder::new(f, 1)?.auto_finish();
+        let mut result = Self {
+            data_source,
+            writer,
+        };
+        let description = result.data_source.fetch_description();
+        result.record([RecordRef::Description(&description)]);
+        Ok(result)
+    }
+
+    fn record<'a>(&mut self, records: impl IntoIterator<Item = RecordRef<'a>>) {
+        let mut empty = true;
+        for record in records {
+            ciborium::into_writer(&record, &mut self.writer).expect("writing recording failed");
+            empty = false;
+        }
+        // Flush so that the recording is usable even if the app never exits
+        // cleanly (which is often the case when reporting a bug)
+        if !empty {
+            self.writer.flush().expect("writing recording failed");
+        }
+    }
+}
+
+impl<T: DeferredDataSource> DeferredDataSource for RecordingDeferredDataSource<T> {
+    fn fetch_description(&self) -> DataSourceDescription {
+        self.data_source.fetch_description()
+    }
+
+    fn fetch_info(&mut self) {
+        self.data_source.fetch_info()
+    }
+
+    fn get_infos(&mut self) -> Vec<DataSourceInfoResult> {
+        let result = self.data_source.get_infos();
+        // Failures aren't worth replaying
+        self.record(
+            result
+                .iter()
+                .filter_map(|info| info.as_ref().ok())
+                .map(RecordRef::Info),
+        );
+        result
+    }
+
+    fn fetch_summary_tile(
+        &mut self,
+        entry_id: &EntryID,
+        tile_id: TileID,
+        full: bool,
+        priority: RequestPriority,
+    ) {
+        self.data_source
+            .fetch_summary_tile(entry_id, tile_id, full, priority)
+    }
+
+    fn get_summary_tiles(&mut self) -> Vec<SummaryTileResponse> {
+        let result = self.data_source.get_summary_tiles();
+        self.record(result.iter().map(RecordRef::SummaryTile));
+        result
+    }
+
+    fn fetch_slot_tile(
+        &mut self,
+        entry_id: &EntryID,
+        tile_id: TileID,
+        full: bool,
+        priority: RequestPriority,
+    ) {
+        self.data_source
+            .fetch_slot_tile(entry_id, tile_id, full, priority)
+    }
+
+    fn get_slot_tiles(&mut self) -> Vec<SlotTileResponse> {
+        let result = self.data_source.get_slot_tiles();
+        self.record(result.iter().map(RecordRef::SlotTile));
+        result
+    }
+
+    fn fetch_slot_meta_tile(
+        &mut self,
+        entry_id: &EntryID,
+        tile_id: TileID,
+        full: bool,
+        priority: RequestPriority,
+    ) {
+        self.data_source
+            .fetch_slot_meta_tile(entry_id, tile_id, full, priority)
+    }
+
+    fn get_slot_meta_tiles(&mut self) -> Vec<SlotMetaTileResponse> {
+        let result = self.data_source.get_slot_meta_tiles();
+        self.record(result.iter().map(RecordRef::SlotMetaTile));
+        result
+    }
+
+    fn fetch_items_meta(&mut self, requests: &[ItemMetaRequest]) {
+        self.data_source.fetch_items_meta(requests)
+    }
+
+    fn get_items_meta(&mut self) -> Vec<ItemsMetaResponse> {
+        let result = self.data_source.get_items_meta();
+        self.record(result.iter().map(RecordRef::ItemsMeta));
+        result
+    }
+
+    fn fetch_tiles(&mut self, kind: TileKind, requests: &[TileRequest], priority: RequestPriority) {
+        self.data_source.fetch_tiles(kind, requests, priority)
+    }
+
+    fn cancel(&mut self, entry_id: &EntryID, tile_id: TileID, full: bool) {
+        self.data_source.cancel(entry_id, tile_id, full)
+    }
+
+    fn transfer_stats(&self) -> Option<TransferStats> {
+        self.data_source.transfer_stats()
+    }
+
+    fn retries_failures(&self) -> bool {
+        self.data_source.retries_failures()
+    }
+}
+
+// Serves the responses from a recording. Responses are returned immediately
+// and in request order; requests that were never recorded fail.
+pub struct ReplayDataSource {
+    description: DataSourceDescription,
+    info: Option<DataSourceInfo>,
+    summary_cache: BTreeMap<TileRequest, SummaryTileResponse>,
+    slot_cache: BTreeMap<TileRequest, SlotTileResponse>,
+    slot_meta_cache: BTreeMap<TileRequest, SlotMetaTileResponse>,
+    items_meta_cache: BTreeMap<Vec<ItemMetaRequest>, ItemsMetaResponse>,
+    infos: Vec<DataSourceInfoResult>,
+    summary_tiles: Vec<SummaryTileResponse>,
+    slot_tiles: Vec<SlotTileResponse>,
+    slot_meta_tiles: Vec<SlotMetaTileResponse>,
+    items_meta: Vec<ItemsMetaResponse>,
+}
+
+impl ReplayDataSource {
+    pub fn new(path: impl AsRef<Path>) -> io::Result<Self> {
+        let f = File::open(path)?;
+        let mut f = BufReader::new(zstd::Decoder::new(f)?);
+
+        let Ok(Record::Description(description)) = ciborium::from_reader(&mut f) else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "not a recording",
+            ));
+        };
+
+        let mut result = Self {
+            description,
+            info: None,
+            summary_cache: BTreeMap::new(),
+            slot_cache: BTreeMap::new(),
+            slot_meta_cache: BTreeMap::new(),
+            items_meta_cache: BTreeMap::new(),
+            infos: Vec::new(),
+            summary_tiles: Vec::new(),
+            slot_tiles: Vec::new(),
+            slot_meta_tiles: Vec::new(),
+            items_meta: Vec::new(),
+        };
+
+        // A recording that was cut off (e.g., because the app crashed) ends
+        // in a partial record, so stop at the first one that fails to decode
+        while let Ok(record) = ciborium::from_reader::<Record, _>(&mut f) {
+            match record {
+                Record::Description(_) => {}
+                Record::Info(info) => {
+                    result.info.get_or_insert(info);
+                }
+                Record::SummaryTile(tile) => {
+                    result.summary_cache.insert(tile.1.clone(), tile);
+                }
+                Record::SlotTile(tile) => {
+                    result.slot_cache.insert(tile.1.clone(), tile);
+                }
+                Record::SlotMetaTile(tile) => {
+                    result.slot_meta_cache.insert(tile.1.clone(), tile);
+                }
+                Record::ItemsMeta(items) => {
+                    result.items_meta_cache.insert(items.1.clone(), items);
+                }
+            }
+        }
+
+        if result.info.is_none() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "recording does not contain profile info",
+            ));
+        }
+        Ok(result)
+    }
+
+    fn lookup<T: Clone>(
+        cache: &BTreeMap<TileRequest, (Result<T, String>, TileRequest)>,
+        req: TileRequest,
+    ) -> (Result<T, String>, TileRequest) {
+        match cache.get(&req) {
+            Some(tile) => tile.clone(),
+            None => (Err("tile not in recording".to_owned()), req),
+        }
+    }
+}
+
+impl DeferredDataSource for ReplayDataSource {
+    fn fetch_description(&self) -> DataSourceDescription {
+        self.description.clone()
+    }
+
+    fn fetch_info(&mut self) {
+        self.infos.push(
+            self.info
+                .clone()
+                .ok_or_else(|| "recording does not contain an info".to_string()),
+        );
+    }
+
+    fn get_infos(&mut self) -> Vec<DataSourceInfoResult> {
+        std::mem::take(&mut self.infos)
+    }
+
+    fn fetch_summary_tile(
+        &mut self,
+        entry_id: &EntryID,
+        tile_id: TileID,
+        full: bool,
+        _priority: RequestPriority,
+    ) {
+        let req = TileRequest {
+            entry_id: entry_id.clone(),
+            tile_id,
+            full,
+        };
+        self.summary_tiles
+            .push(Self::lookup(&self.summary_cache, req));
+    }
+
+    fn get_summary_tiles(&mut self) -> Vec<SummaryTileResponse> {
+        std::mem::take(&mut self.summary_tiles)
+    }
+
+    fn fetch_slot_tile(
+        &mut self,
+        entry_id: &EntryID,
+        tile_id: TileID,
+        full: bool,
+        _priority: RequestPriority,
+    ) {
+        let req = TileRequest {
+            entry_id: entry_id.clone(),
+            tile_id,
+            full,
+        };
+        self.slot_tiles.push(Self::lookup(&self.slot_cache, req));
+    }
+
+    fn get_slot_tiles(&mut self) -> Vec<SlotTileResponse> {
+        std::mem::take(&mut self.slot_tiles)
+    }
+
+    fn fetch_slot_meta_tile(
+        &mut self,
+        entry_id: &EntryID,
+        tile_id: TileID,
+        full: bool,
+        _priority: RequestPriority,
+    ) {
+        let req = TileRequest {
+            entry_id: entry_id.clone(),
+            tile_id,
+            full,
+        };
+        self.slot_meta_tiles
+            .push(Self::lookup(&self.slot_meta_cache, req));
+    }
+
+    fn get_slot_meta_tiles(&mut self) -> Vec<SlotMetaTileResponse> {
+        std::mem::take(&mut self.slot_meta_tiles)
+    }
+
+    fn fetch_items_meta(&mut self, requests: &[ItemMetaRequest]) {
+        let items = match self.items_meta_cache.get(requests) {
+            Some(items) => items.clone(),
+            None => (Err("items not in recording".to_owned()), requests.to_vec()),
+        };
+        self.items_meta.push(items);
+    }
+
+    fn get_items_meta(&mut self) -> Vec<ItemsMetaResponse> {
+        std::mem::take(&mut self.items_meta)
+    }
+
+    fn cancel(&mut self, _entry_id: &EntryID, _tile_id: TileID, _full: bool) {
+        // Responses are served immediately, so there is never anything to
+        // cancel
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::deferred_data::DeferredDataSourceWrapper;
+    use crate::test_data::TestDataSource;
+    use crate::timestamp::{Interval, Timestamp};
+
+    #[test]
+    fn test_record_replay() {
+        let path = std::env::temp_dir().join(format!("lpv_replay_test_{}", std::process::id()));
+
+        let entry_id = EntryID::root().summary();
+        let tile_id = TileID(Interval::new(Timestamp(100), Timestamp(200)));
+        {
+            let mut recording = RecordingDeferredDataSource::new(
+                DeferredDataSourceWrapper::new(TestDataSource::default()),
+                &path,
+            )
+            .unwrap();
+            recording.fetch_info();
+            assert_eq!(recording.get_infos().len(), 1);
+            recording.fetch_summary_tile(&entry_id, tile_id, true, RequestPriority::Visible);
+            assert_eq!(recording.get_summary_tiles().len(), 1);
+        }
+
+        let mut replay = ReplayDataSource::new(&path).unwrap();
+        assert_eq!(replay.fetch_description().source_locator, vec!["test"]);
+        replay.fetch_info();
+        assert_eq!(
+            replay.get_infos()[0].as_ref().unwrap().interval.stop,
+            Timestamp(1000)
+        );
+
+        replay.fetch_summary_tile(&entry_id, tile_id, true, RequestPriority::Visible);
+        replay.fetch_summary_tile(&entry_id, tile_id, false, RequestPriority::Visible);
+        let tiles = replay.get_summary_tiles();
+        assert_eq!(tiles.len(), 2);
+        let tile = tiles[0].0.as_ref().unwrap();
+        assert_eq!(tile.data.utilization[0].time, Timestamp(100));
+        assert!(tiles[1].0.is_err());
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
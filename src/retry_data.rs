@@ -0,0 +1,473 @@
+use std::collections::BTreeMap;
+use std::hash::{BuildHasher, RandomState};
+use std::time::Duration;
+
+use web_time::Instant;
+
+use crate::data::{DataSourceDescription, EntryID, ItemMetaRequest, TileID};
+use crate::deferred_data::{
+    CANCELLED, DataSourceInfoResult, DeferredDataSource, ItemsMetaResponse, RequestPriority,
+    SlotMetaTileResponse, SlotTileResponse, SummaryTileResponse, TileKind, TileRequest,
+    TransferStats,
+};
+
+// Upper bound on the delay between attempts, regardless of how many times the
+// request has failed
+const MAX_DELAY: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+enum Request {
+    SummaryTile(TileRequest),
+    SlotTile(TileRequest),
+    SlotMetaTile(TileRequest),
+    ItemsMeta(Vec<ItemMetaRequest>),
+}
+
+// Retries failed requests with exponential backoff. Only requests that fail
+// on every attempt are returned to the caller (with the last error).
+pub struct RetryDeferredDataSource<T: DeferredDataSource> {
+    data_source: T,
+    max_attempts: u32,
+    base_delay: Duration,
+    // Failed attempts so far, for requests that have failed at least once
+    attempts: BTreeMap<Request, u32>,
+    // Priorities of outstanding tile requests, so that retries keep them
+    priorities: BTreeMap<Request, RequestPriority>,
+    // Requests waiting for their backoff to expire
+    retries: Vec<(Instant, Request)>,
+    // Requests that were cancelled while waiting to be retried
+    cancelled: Vec<Request>,
+    random: RandomState,
+}
+
+impl<T: DeferredDataSource> RetryDeferredDataSource<T> {
+    pub fn new(data_source: T, max_attempts: u32, base_delay: Duration) -> Self {
+        assert!(max_attempts > 0);
+        Self {
+            data_source,
+            max_attempts,
+            base_delay,
+            attempts: BTreeMap::new(),
+            priorities: BTreeMap::new(),
+            retries: Vec::new(),
+            cancelled: Vec::new(),
+            random: RandomState::new(),
+        }
+    }
+
+    pub fn data_source(&self) -> &T {
+        &self.data_source
+    }
+
+    // Delay doubles on each failure, with jitter so that many tiles that
+    // failed together (e.g., because the server was busy) don't all come
+    // back at once
+    fn delay(&self, request: &Request, attempt: u32) -> Duration {
+        let delay = self
+            .base_delay
+            .saturating_mul(1 << (attempt - 1).min(16))
+            .min(MAX_DELAY);
+        let jitter = (self.random.hash_one((request, attempt)) % 1000) as f64 / 1000.0;
+        delay.mul_f64(0.5 + 0.5 * jitter)
+    }
+
+    // Sends the retries whose backoff has expired. This happens on every call
+    // that fetches or polls, so that retries don't depend on the caller
+    // polling in particular.
+    fn issue_retries(&mut self) {
+        let now = Instant::now();
+        let (ready, waiting) = std::mem::take(&mut self.retries)
+            .into_iter()
+            .partition(|(time, _)| *time <= now);
+        self.retries = waiting;
+        for (_, request) in ready {
+            let priority = self.priorities.get(&request).copied().unwrap_or_default();
+            match request {
+                Request::SummaryTile(req) => {
+                    self.data_source.fetch_summary_tile(
+                        &req.entry_id,
+                        req.tile_id,
+                        req.full,
+                        priority,
+                    );
+                }
+                Request::SlotTile(req) => {
+                    self.data_source.fetch_slot_tile(
+                        &req.entry_id,
+                        req.tile_id,
+                        req.full,
+                        priority,
+                    );
+                }
+                Request::SlotMetaTile(req) => {
+                    self.data_source.fetch_slot_meta_tile(
+                        &req.entry_id,
+                        req.tile_id,
+                        req.full,
+                        priority,
+                    );
+                }
+                Request::ItemsMeta(reqs) => {
+                    self.data_source.fetch_items_meta(&reqs);
+                }
+            }
+        }
+    }
+
+    // Returns the responses that should be passed on to the caller, and
+    // schedules the rest to be retried
+    fn filter_responses<V, R: Clone>(
+        &mut self,
+        responses: Vec<(Result<V, String>, R)>,
+        request: impl Fn(R) -> Request,
+    ) -> Vec<(Result<V, String>, R)> {
+        self.issue_retries();
+
+        let mut result = Vec::new();
+        for (value, req) in responses {
+            let key = request(req.clone());
+            if let Err(e) = value.as_ref().map_err(|e| e.as_str()) {
+                if e == CANCELLED {
+                    self.attempts.remove(&key);
+                    self.priorities.remove(&key);
+                    result.push((value, req));
+                    continue;
+                }
+                let attempt = self.attempts.entry(key.clone()).or_default();
+                *attempt += 1;
+                let attempt = *attempt;
+                if attempt < self.max_attempts {
+                    let delay = self.delay(&key, attempt);
+                    log::info!("request failed (attempt {attempt}), retrying in {delay:?}: {e}");
+                    self.retries.push((Instant::now() + delay, key));
+                    continue;
+                }
+            }
+            self.attempts.remove(&key);
+            self.priorities.remove(&key);
+            result.push((value, req));
+        }
+        result
+    }
+
+    fn take_cancelled<V, R>(
+        &mut self,
+        request: impl Fn(Request) -> Result<R, Request>,
+    ) -> Vec<(Result<V, String>, R)> {
+        let mut result = Vec::new();
+        let mut remaining = Vec::new();
+        for key in std::mem::take(&mut self.cancelled) {
+            match request(key) {
+                Ok(req) => result.push((Err(CANCELLED.to_owned()), req)),
+                Err(key) => remaining.push(key),
+            }
+        }
+        self.cancelled = remaining;
+        result
+    }
+}
+
+impl<T: DeferredDataSource> DeferredDataSource for RetryDeferredDataSource<T> {
+    fn fetch_description(&self) -> DataSourceDescription {
+        self.data_source.fetch_description()
+    }
+
+    fn fetch_info(&mut self) {
+        self.data_source.fetch_info()
+    }
+
+    fn get_infos(&mut self) -> Vec<DataSourceInfoResult> {
+        self.data_source.get_infos()
+    }
+
+    fn fetch_summary_tile(
+        &mut self,
+        entry_id: &EntryID,
+        tile_id: TileID,
+        full: bool,
+        priority: RequestPriority,
+    ) {
+        let req = TileRequest {
+            entry_id: entry_id.clone(),
+            tile_id,
+            full,
+        };
+        self.issue_retries();
+        self.priorities.insert(Request::SummaryTile(req), priority);
+        self.data_source
+            .fetch_summary_tile(entry_id, tile_id, full, priority)
+    }
+
+    fn get_summary_tiles(&mut self) -> Vec<SummaryTileResponse> {
+        let result = self.data_source.get_summary_tiles();
+        let mut result = self.filter_responses(result, Request::SummaryTile);
+        result.extend(self.take_cancelled(|key| match key {
+            Request::SummaryTile(req) => Ok(req),
+            key => Err(key),
+        }));
+        result
+    }
+
+    fn fetch_slot_tile(
+        &mut self,
+        entry_id: &EntryID,
+        tile_id: TileID,
+        full: bool,
+        priority: RequestPriority,
+    ) {
+        let req = TileRequest {
+            entry_id: entry_id.clone(),
+            tile_id,
+            full,
+        };
+        self.issue_retries();
+        self.priorities.insert(Request::SlotTile(req), priority);
+        self.data_source
+            .fetch_slot_tile(entry_id, tile_id, full, priority)
+    }
+
+    fn get_slot_tiles(&mut self) -> Vec<SlotTileResponse> {
+        let result = self.data_source.get_slot_tiles();
+        let mut result = self.filter_responses(result, Request::SlotTile);
+        result.extend(self.take_cancelled(|key| match key {
+            Request::SlotTile(req) => Ok(req),
+            key => Err(key),
+        }));
+        result
+    }
+
+    fn fetch_slot_meta_tile(
+        &mut self,
+        entry_id: &EntryID,
+        tile_id: TileID,
+        full: bool,
+        priority: RequestPriority,
+    ) {
+        let req = TileRequest {
+            entry_id: entry_id.clone(),
+            tile_id,
+            full,
+        };
+        self.issue_retries();
+        self.priorities.insert(Request::SlotMetaTile(req), priority);
+        self.data_source
+            .fetch_slot_meta_tile(entry_id, tile_id, full, priority)
+    }
+
+    fn get_slot_meta_tiles(&mut self) -> Vec<SlotMetaTileResponse> {
+        let result = self.data_source.get_slot_meta_tiles();
+        let mut result = self.filter_responses(result, Request::SlotMetaTile);
+        result.extend(self.take_cancelled(|key| match key {
+            Request::SlotMetaTile(req) => Ok(req),
+            key => Err(key),
+        }));
+        result
+    }
+
+    fn fetch_items_meta(&mut self, requests: &[ItemMetaRequest]) {
+        self.issue_retries();
+        self.data_source.fetch_items_meta(requests)
+    }
+
+    fn get_items_meta(&mut self) -> Vec<ItemsMetaResponse> {
+        let result = self.data_source.get_items_meta();
+        self.filter_responses(result, Request::ItemsMeta)
+    }
+
+    fn fetch_tiles(&mut self, kind: TileKind, requests: &[TileRequest], priority: RequestPriority) {
+        self.issue_retries();
+        for req in requests {
+            let key = match kind {
+                TileKind::Summary => Request::SummaryTile(req.clone()),
+                TileKind::Slot => Request::SlotTile(req.clone()),
+                TileKind::SlotMeta => Request::SlotMetaTile(req.clone()),
+            };
+            self.priorities.insert(key, priority);
+        }
+        self.data_source.fetch_tiles(kind, requests, priority)
+    }
+
+    fn cancel(&mut self, entry_id: &EntryID, tile_id: TileID, full: bool) {
+        let req = TileRequest {
+            entry_id: entry_id.clone(),
+            tile_id,
+            full,
+        };
+
+        // Requests waiting for a retry are answered right away, the rest get
+        // whatever the data source returns for them (without retrying)
+        let (cancelled, waiting): (Vec<_>, _) = std::mem::take(&mut self.retries)
+            .into_iter()
+            .partition(|(_, key)| match key {
+                Request::SummaryTile(r) | Request::SlotTile(r) | Request::SlotMetaTile(r) => {
+                    *r == req
+                }
+                Request::ItemsMeta(_) => false,
+            });
+        self.retries = waiting;
+        for (_, key) in cancelled {
+            self.attempts.remove(&key);
+            self.priorities.remove(&key);
+            self.cancelled.push(key);
+        }
+
+        self.data_source.cancel(entry_id, tile_id, full)
+    }
+
+    fn transfer_stats(&self) -> Option<TransferStats> {
+        self.data_source.transfer_stats()
+    }
+
+    fn retries_failures(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::data::{SummaryTile, SummaryTileData};
+    use crate::timestamp::{Interval, Timestamp};
+
+    // Fails the first N requests, then succeeds
+    struct FlakyDataSource {
+        failures: u32,
+        summary_tiles: Vec<SummaryTileResponse>,
+    }
+
+    impl DeferredDataSource for FlakyDataSource {
+        fn fetch_description(&self) -> DataSourceDescription {
+            unimplemented!()
+        }
+        fn fetch_info(&mut self) {
+            unimplemented!()
+        }
+        fn get_infos(&mut self) -> Vec<DataSourceInfoResult> {
+            unimplemented!()
+        }
+        fn fetch_summary_tile(
+            &mut self,
+            entry_id: &EntryID,
+            tile_id: TileID,
+            full: bool,
+            _priority: RequestPriority,
+        ) {
+            let req = TileRequest {
+                entry_id: entry_id.clone(),
+                tile_id,
+                full,
+            };
+            if self.failures > 0 {
+                self.failures -= 1;
+                self.summary_tiles.push((Err("busy".to_string()), req));
+            } else {
+                let tile = SummaryTile {
+                    entry_id: entry_id.clone(),
+                    tile_id,
+                    data: SummaryTileData {
+                        utilization: Vec::new(),
+                    },
+                };
+                self.summary_tiles.push((Ok(tile), req));
+            }
+        }
+        fn get_summary_tiles(&mut self) -> Vec<SummaryTileResponse> {
+            std::mem::take(&mut self.summary_tiles)
+        }
+        fn fetch_slot_tile(&mut self, _: &EntryID, _: TileID, _: bool, _priority: RequestPriority) {
+            unimplemented!()
+        }
+        fn get_slot_tiles(&mut self) -> Vec<SlotTileResponse> {
+            unimplemented!()
+        }
+        fn fetch_slot_meta_tile(
+            &mut self,
+            _: &EntryID,
+            _: TileID,
+            _: bool,
+            _priority: RequestPriority,
+        ) {
+            unimplemented!()
+        }
+        fn get_slot_meta_tiles(&mut self) -> Vec<SlotMetaTileResponse> {
+            unimplemented!()
+        }
+        fn fetch_items_meta(&mut self, _: &[ItemMetaRequest]) {
+            unimplemented!()
+        }
+        fn get_items_meta(&mut self) -> Vec<ItemsMetaResponse> {
+            unimplemented!()
+        }
+        fn cancel(&mut self, _: &EntryID, _: TileID, _: bool) {}
+    }
+
+    fn poll(data_source: &mut impl DeferredDataSource) -> SummaryTileResponse {
+        loop {
+            if let Some(tile) = data_source.get_summary_tiles().pop() {
+                return tile;
+            }
+            std::thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    #[test]
+    fn test_retry() {
+        let entry_id = EntryID::root().summary();
+        let tile_id = TileID(Interval::new(Timestamp(0), Timestamp(100)));
+
+        // Succeeds on the last attempt
+        let flaky = FlakyDataSource {
+            failures: 2,
+            summary_tiles: Vec::new(),
+        };
+        let mut retry = RetryDeferredDataSource::new(flaky, 3, Duration::from_millis(1));
+        retry.fetch_summary_tile(&entry_id, tile_id, false, RequestPriority::Visible);
+        assert!(poll(&mut retry).0.is_ok());
+
+        // Runs out of attempts
+        let flaky = FlakyDataSource {
+            failures: 3,
+            summary_tiles: Vec::new(),
+        };
+        let mut retry = RetryDeferredDataSource::new(flaky, 3, Duration::from_millis(1));
+        retry.fetch_summary_tile(&entry_id, tile_id, false, RequestPriority::Visible);
+        assert_eq!(poll(&mut retry).0.unwrap_err(), "busy");
+
+        // The layer retries, even if the source doesn't
+        assert!(!retry.data_source().retries_failures());
+        assert!(retry.retries_failures());
+
+        // A single attempt passes failures straight through, as the viewer
+        // does for sources that retry on their own
+        let flaky = FlakyDataSource {
+            failures: 1,
+            summary_tiles: Vec::new(),
+        };
+        let mut retry = RetryDeferredDataSource::new(flaky, 1, Duration::from_millis(1));
+        retry.fetch_summary_tile(&entry_id, tile_id, false, RequestPriority::Visible);
+        assert_eq!(poll(&mut retry).0.unwrap_err(), "busy");
+    }
+
+    #[test]
+    fn test_retry_on_fetch() {
+        let entry_id = EntryID::root().summary();
+        let tile_id = |start| TileID(Interval::new(Timestamp(start), Timestamp(start + 100)));
+        let flaky = FlakyDataSource {
+            failures: 1,
+            summary_tiles: Vec::new(),
+        };
+        let mut retry = RetryDeferredDataSource::new(flaky, 2, Duration::from_millis(1));
+        retry.fetch_summary_tile(&entry_id, tile_id(0), false, RequestPriority::Visible);
+        assert!(retry.get_summary_tiles().is_empty());
+        std::thread::sleep(Duration::from_millis(5));
+
+        // The next request sends the retry along with it, without waiting for
+        // the caller to poll
+        retry.fetch_summary_tile(&entry_id, tile_id(100), false, RequestPriority::Visible);
+        let tiles = &retry.data_source().summary_tiles;
+        assert_eq!(tiles.len(), 2);
+        assert!(tiles.iter().all(|(tile, _)| tile.is_ok()));
+        assert_eq!(tiles[0].1.tile_id, tile_id(0));
+    }
+}
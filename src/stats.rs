@@ -0,0 +1,396 @@
+use std::cmp::Reverse;
+#[cfg(not(target_arch = "wasm32"))]
+use std::collections::BinaryHeap;
+use std::collections::{BTreeMap, BTreeSet};
+#[cfg(not(target_arch = "wasm32"))]
+use std::fmt::Write;
+#[cfg(not(target_arch = "wasm32"))]
+use std::io;
+
+use itertools::Itertools;
+use serde::Serialize;
+
+#[cfg(not(target_arch = "wasm32"))]
+use crate::async_data::{AsyncDeferredDataSource, DeferredDataSourceAsyncWrapper, block_on};
+use crate::data::{ItemMeta, ItemUID};
+#[cfg(not(target_arch = "wasm32"))]
+use crate::deferred_data::DeferredDataSource;
+#[cfg(not(target_arch = "wasm32"))]
+use crate::export::{
+    ExportEntry, ExportItem, Exporter, clamp_interval, export_entries, walk_entries,
+};
+use crate::timestamp::Interval;
+#[cfg(not(target_arch = "wasm32"))]
+use crate::timestamp::Timestamp;
+
+// Time covered by the intervals. Rows may overlap (e.g., nested items), so
+// overlapping intervals are merged first to avoid double counting
+pub fn busy_ns(intervals: impl IntoIterator<Item = Interval>) -> i64 {
+    let mut busy_ns = 0;
+    let mut current: Option<Interval> = None;
+    for interval in intervals.into_iter().sorted_by_key(|i| i.start) {
+        match current {
+            Some(c) if interval.start <= c.stop => current = Some(c.union(interval)),
+            _ => {
+                if let Some(c) = current {
+                    busy_ns += c.duration_ns();
+                }
+                current = Some(interval);
+            }
+        }
+    }
+    if let Some(c) = current {
+        busy_ns += c.duration_ns();
+    }
+    busy_ns
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct TitleStats {
+    pub count: u64,
+    pub total_ns: i64,
+    pub max_ns: i64,
+}
+
+impl TitleStats {
+    pub fn mean_ns(&self) -> i64 {
+        self.total_ns / self.count.max(1) as i64
+    }
+}
+
+// Per-title aggregate statistics for the items in an interval, counting only
+// the part of each item inside it
+#[derive(Debug, Clone, Default)]
+pub struct ItemStats {
+    pub interval: Interval,
+    pub titles: BTreeMap<String, TitleStats>,
+
+    // Items may be sliced across multiple tiles, so only count them once
+    seen: BTreeSet<ItemUID>,
+}
+
+impl ItemStats {
+    pub fn new(interval: Interval) -> Self {
+        Self {
+            interval,
+            ..Default::default()
+        }
+    }
+
+    pub fn add_item(&mut self, item: &ItemMeta) {
+        if !item.original_interval.overlaps(self.interval) || !self.seen.insert(item.item_uid) {
+            return;
+        }
+        let duration = item
+            .original_interval
+            .intersection(self.interval)
+            .duration_ns();
+        let stats = self.titles.entry(item.title.clone()).or_default();
+        stats.count += 1;
+        stats.total_ns += duration;
+        stats.max_ns = stats.max_ns.max(duration);
+    }
+
+    pub fn items(&self) -> u64 {
+        self.titles.values().map(|stats| stats.count).sum()
+    }
+
+    // Titles by total time, most first
+    pub fn rows(&self) -> impl Iterator<Item = (&String, &TitleStats)> {
+        self.titles
+            .iter()
+            .sorted_by_key(|(_, stats)| Reverse(stats.total_ns))
+    }
+
+    pub fn to_csv(&self) -> String {
+        let escape = |s: &str| {
+            if s.contains([',', '"', '\n']) {
+                format!("\"{}\"", s.replace('"', "\"\""))
+            } else {
+                s.to_string()
+            }
+        };
+
+        let mut result = String::from("title,count,total_ns,mean_ns,max_ns\n");
+        for (title, stats) in self.rows() {
+            result.push_str(&format!(
+                "{},{},{},{},{}\n",
+                escape(title),
+                stats.count,
+                stats.total_ns,
+                stats.mean_ns(),
+                stats.max_ns
+            ));
+        }
+        result
+    }
+}
+
+// How busy one slot was over the interval
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Debug, Clone, Serialize)]
+pub struct EntryStats {
+    // Short names from the outermost panel down, e.g., "n0/cpu/cpu0"
+    pub path: String,
+    pub name: String,
+    pub items: u64,
+    pub busy_ns: i64,
+    pub busy_percent: f64,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Debug, Clone, Serialize)]
+pub struct TitleRow {
+    pub title: String,
+    pub count: u64,
+    pub total_ns: i64,
+    pub mean_ns: i64,
+    pub max_ns: i64,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+pub struct LongItem {
+    // First, so that items order by it
+    pub duration_ns: i64,
+    pub title: String,
+    pub entry: String,
+    pub interval: Interval,
+}
+
+// A summary of a whole profile (or part of it), for scripts that don't need
+// the viewer
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Debug, Clone, Serialize)]
+pub struct ProfileStats {
+    pub interval: Interval,
+    pub items: u64,
+    // Every slot, in the order the viewer shows them
+    pub entries: Vec<EntryStats>,
+    // The titles with the most total time, most first
+    pub titles: Vec<TitleRow>,
+    // The longest items, longest first
+    pub longest: Vec<LongItem>,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+struct StatsCollector {
+    top: usize,
+    titles: ItemStats,
+    entries: Vec<EntryStats>,
+    // The shortest of the longest items so far is on top, to be dropped
+    longest: BinaryHeap<Reverse<LongItem>>,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Exporter for StatsCollector {
+    type Error = io::Error;
+
+    fn write_slot(&mut self, entry: &ExportEntry, items: Vec<ExportItem>) -> io::Result<()> {
+        let interval = self.titles.interval;
+        let path = entry.path();
+        let mut busy = Vec::new();
+        for item in &items {
+            let clipped = item.interval.intersection(interval);
+            busy.push(clipped);
+            self.titles.add_item(&item.meta);
+            self.longest.push(Reverse(LongItem {
+                duration_ns: clipped.duration_ns(),
+                title: item.meta.title.clone(),
+                entry: path.clone(),
+                interval: item.interval,
+            }));
+            if self.longest.len() > self.top {
+                self.longest.pop();
+            }
+        }
+        let busy_ns = busy_ns(busy);
+        self.entries.push(EntryStats {
+            path,
+            name: entry.long_name.clone(),
+            items: items.len() as u64,
+            busy_ns,
+            busy_percent: busy_ns as f64 / interval.duration_ns().max(1) as f64 * 100.0,
+        });
+        Ok(())
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl ProfileStats {
+    // Fetches every slot over interval (or the whole profile), keeping the
+    // top titles and longest items
+    pub fn collect<T: DeferredDataSource + 'static>(
+        data_source: T,
+        interval: Option<Interval>,
+        top: usize,
+    ) -> io::Result<Self> {
+        let data_source = DeferredDataSourceAsyncWrapper::new(data_source);
+        let info = block_on(data_source.fetch_info()).map_err(io::Error::other)?;
+
+        // Titles come from the item metadata
+        if !info.capabilities.slot_meta_tiles {
+            return Err(io::Error::other(
+                "data source does not provide item metadata, which stats requires",
+            ));
+        }
+
+        let interval = clamp_interval(interval, info.interval).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "interval does not overlap the profile",
+            )
+        })?;
+
+        let entries = walk_entries(&info.entry_info, false);
+        let mut collector = StatsCollector {
+            top,
+            titles: ItemStats::new(interval),
+            entries: Vec::new(),
+            longest: BinaryHeap::new(),
+        };
+        export_entries(&data_source, &info, &entries, interval, &mut collector)?;
+
+        let titles = collector
+            .titles
+            .rows()
+            .take(top)
+            .map(|(title, stats)| TitleRow {
+                title: title.clone(),
+                count: stats.count,
+                total_ns: stats.total_ns,
+                mean_ns: stats.mean_ns(),
+                max_ns: stats.max_ns,
+            })
+            .collect();
+        let longest = collector
+            .longest
+            .into_sorted_vec()
+            .into_iter()
+            .map(|Reverse(item)| item)
+            .collect();
+        Ok(Self {
+            interval,
+            items: collector.titles.items(),
+            entries: collector.entries,
+            titles,
+            longest,
+        })
+    }
+
+    // Plain text tables, for people rather than scripts
+    pub fn to_table(&self) -> String {
+        let mut result = String::new();
+        let _ = writeln!(result, "Interval: {}", self.interval);
+        let _ = writeln!(result, "Items: {}", self.items);
+
+        let width = |names: &mut dyn Iterator<Item = usize>, header: &str| {
+            names.max().unwrap_or(0).max(header.len())
+        };
+
+        let w = width(&mut self.entries.iter().map(|e| e.path.len()), "Entry");
+        let _ = writeln!(result, "\n{:w$}  {:>7}  {:>10}", "Entry", "Busy", "Items");
+        for entry in &self.entries {
+            let _ = writeln!(
+                result,
+                "{:w$}  {:>6.1}%  {:>10}",
+                entry.path, entry.busy_percent, entry.items
+            );
+        }
+
+        let w = width(&mut self.titles.iter().map(|t| t.title.len()), "Title");
+        let _ = writeln!(
+            result,
+            "\n{:w$}  {:>10}  {:>12}  {:>12}  {:>12}",
+            "Title", "Count", "Total", "Mean", "Max"
+        );
+        for row in &self.titles {
+            let _ = writeln!(
+                result,
+                "{:w$}  {:>10}  {:>12}  {:>12}  {:>12}",
+                row.title,
+                row.count,
+                Timestamp(row.total_ns).to_string(),
+                Timestamp(row.mean_ns).to_string(),
+                Timestamp(row.max_ns).to_string()
+            );
+        }
+
+        let w = width(&mut self.longest.iter().map(|i| i.title.len()), "Longest");
+        let _ = writeln!(result, "\n{:w$}  {:>12}  Entry", "Longest", "Duration");
+        for item in &self.longest {
+            let _ = writeln!(
+                result,
+                "{:w$}  {:>12}  {}",
+                item.title,
+                Timestamp(item.duration_ns).to_string(),
+                item.entry
+            );
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::timestamp::Timestamp;
+
+    fn interval(start: i64, stop: i64) -> Interval {
+        Interval::new(Timestamp(start), Timestamp(stop))
+    }
+
+    #[test]
+    fn test_busy_ns() {
+        assert_eq!(busy_ns([]), 0);
+        // Nested and overlapping intervals count once
+        let intervals = [
+            interval(0, 10),
+            interval(2, 5),
+            interval(8, 12),
+            interval(20, 25),
+        ];
+        assert_eq!(busy_ns(intervals), 17);
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    #[test]
+    fn test_profile_stats() {
+        use crate::deferred_data::DeferredDataSourceWrapper;
+        use crate::trace_data::{TraceBuilder, TraceItem};
+
+        let mut builder = TraceBuilder::new("test");
+        let cpu0 = builder.thread("n0", "cpu", "cpu0");
+        let cpu1 = builder.thread("n0", "cpu", "cpu1");
+        for (thread, title, start, stop) in [
+            (cpu0, "a", 0, 50),
+            (cpu0, "b", 10, 20),
+            (cpu1, "a", 60, 100),
+        ] {
+            builder.add_item(
+                thread,
+                TraceItem {
+                    interval: interval(start, stop),
+                    title: title.to_owned(),
+                    color: None,
+                    fields: Vec::new(),
+                },
+            );
+        }
+        let ds = DeferredDataSourceWrapper::new(builder.build());
+
+        let stats = ProfileStats::collect(ds, Some(interval(0, 100)), 2).unwrap();
+        assert_eq!(stats.items, 3);
+        let busy: Vec<_> = stats.entries.iter().map(|e| e.busy_ns).collect();
+        assert_eq!(busy, [50, 40]);
+        assert_eq!(stats.titles.len(), 2);
+        assert_eq!(stats.titles[0].title, "a");
+        assert_eq!(stats.titles[0].count, 2);
+        assert_eq!(stats.titles[0].total_ns, 90);
+        assert_eq!(stats.longest.len(), 2);
+        assert_eq!(stats.longest[0].duration_ns, 50);
+        assert_eq!(stats.longest[1].duration_ns, 40);
+        assert!(stats.to_table().contains("n0/cpu/cpu0"));
+    }
+}
@@ -0,0 +1,124 @@
+use std::collections::BTreeMap;
+
+use crate::data::{
+    Capabilities, DataSource, DataSourceDescription, DataSourceInfo, EntryID, EntryInfo,
+    FieldSchema, Item, ItemMeta, ItemUID, PROTOCOL_VERSION, SlotMetaTile, SlotMetaTileData,
+    SlotTile, SlotTileData, SummaryTile, SummaryTileData, TileID, TileSet, UtilPoint,
+};
+use crate::timestamp::{Interval, Timestamp};
+
+// A small source for testing the decorators. Every slot tile has the same
+// items (in one row), whatever the entry or tile asked for, so that tests can
+// tell exactly what a decorator did to them.
+pub struct TestDataSource {
+    pub info: DataSourceInfo,
+    pub items: Vec<Interval>,
+    // Panics on requests for anything but full tiles, for decorators that
+    // are expected to ask for nothing else
+    pub full_only: bool,
+}
+
+impl Default for TestDataSource {
+    fn default() -> Self {
+        Self {
+            info: DataSourceInfo {
+                entry_info: EntryInfo::Panel {
+                    short_name: "root".to_string(),
+                    long_name: "root".to_string(),
+                    summary: None,
+                    slots: Vec::new(),
+                },
+                interval: Interval::new(Timestamp(0), Timestamp(1000)),
+                tile_set: TileSet::default(),
+                field_schema: FieldSchema::new(),
+                warning_message: None,
+                refresh_interval: None,
+                version: PROTOCOL_VERSION,
+                capabilities: Capabilities::default(),
+                entry_tile_sets: BTreeMap::new(),
+            },
+            items: Vec::new(),
+            full_only: false,
+        }
+    }
+}
+
+impl TestDataSource {
+    fn check(&self, full: bool) {
+        assert!(
+            full || !self.full_only,
+            "expected a request for a full tile"
+        );
+    }
+}
+
+impl DataSource for TestDataSource {
+    fn fetch_description(&self) -> DataSourceDescription {
+        DataSourceDescription {
+            source_locator: vec!["test".to_string()],
+        }
+    }
+
+    fn fetch_info(&self) -> DataSourceInfo {
+        self.info.clone()
+    }
+
+    // A single point at the start of the tile
+    fn fetch_summary_tile(&self, entry_id: &EntryID, tile_id: TileID, full: bool) -> SummaryTile {
+        self.check(full);
+        SummaryTile {
+            entry_id: entry_id.clone(),
+            tile_id,
+            data: SummaryTileData {
+                utilization: vec![UtilPoint {
+                    time: tile_id.0.start,
+                    util: 0.5,
+                }],
+            },
+        }
+    }
+
+    fn fetch_slot_tile(&self, entry_id: &EntryID, tile_id: TileID, full: bool) -> SlotTile {
+        self.check(full);
+        let items = self
+            .items
+            .iter()
+            .enumerate()
+            .map(|(i, interval)| Item {
+                item_uid: ItemUID(i as u64),
+                interval: *interval,
+                color: Default::default(),
+            })
+            .collect();
+        SlotTile {
+            entry_id: entry_id.clone(),
+            tile_id,
+            data: SlotTileData { items: vec![items] },
+        }
+    }
+
+    fn fetch_slot_meta_tile(
+        &self,
+        entry_id: &EntryID,
+        tile_id: TileID,
+        full: bool,
+    ) -> SlotMetaTile {
+        self.check(full);
+        let items = self
+            .items
+            .iter()
+            .enumerate()
+            .map(|(i, interval)| ItemMeta {
+                item_uid: ItemUID(i as u64),
+                original_interval: *interval,
+                title: format!("item {i}"),
+                fields: Vec::new(),
+            })
+            .collect();
+        SlotMetaTile {
+            entry_id: entry_id.clone(),
+            tile_id,
+            data: SlotMetaTileData { items: vec![items] },
+        }
+    }
+}
@@ -0,0 +1,282 @@
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+
+use crate::data::{DataSourceDescription, EntryID, ItemMetaRequest, TileID};
+use crate::deferred_data::{
+    CANCELLED, DataSourceInfoResult, DeferredDataSource, ItemsMetaResponse, RequestPriority,
+    SlotMetaTileResponse, SlotTileResponse, SummaryTileResponse, TileKind, TileRequest,
+    TransferStats,
+};
+
+enum Request {
+    Tile(TileKind, TileRequest),
+    ItemsMeta(Vec<ItemMetaRequest>),
+}
+
+// Caps the number of requests outstanding in the underlying data source, and
+// queues the rest until earlier requests complete. Queued requests are issued
+// highest priority first, and in order within a priority. Info requests are
+// rare and always pass straight through.
+pub struct ThrottleDeferredDataSource<T: DeferredDataSource> {
+    data_source: T,
+    max_outstanding: usize,
+    outstanding: usize,
+    queue: BTreeMap<(Reverse<RequestPriority>, u64), Request>,
+    next_seq: u64,
+    // Responses for requests cancelled while still in the queue
+    summary_tiles: Vec<SummaryTileResponse>,
+    slot_tiles: Vec<SlotTileResponse>,
+    slot_meta_tiles: Vec<SlotMetaTileResponse>,
+}
+
+impl<T: DeferredDataSource> ThrottleDeferredDataSource<T> {
+    pub fn new(data_source: T, max_outstanding: usize) -> Self {
+        assert!(max_outstanding > 0);
+        Self {
+            data_source,
+            max_outstanding,
+            outstanding: 0,
+            queue: BTreeMap::new(),
+            next_seq: 0,
+            summary_tiles: Vec::new(),
+            slot_tiles: Vec::new(),
+            slot_meta_tiles: Vec::new(),
+        }
+    }
+
+    pub fn data_source(&self) -> &T {
+        &self.data_source
+    }
+
+    pub fn queued(&self) -> usize {
+        self.queue.len()
+    }
+
+    fn enqueue(&mut self, request: Request, priority: RequestPriority) {
+        self.queue
+            .insert((Reverse(priority), self.next_seq), request);
+        self.next_seq += 1;
+    }
+
+    // Issues queued requests while there is room. Tiles of the same kind
+    // and priority are sent together, so that sources that batch requests
+    // still can.
+    fn issue(&mut self) {
+        let mut batch: Vec<TileRequest> = Vec::new();
+        let mut batch_key = None;
+        while self.outstanding < self.max_outstanding {
+            let Some(((Reverse(priority), _), request)) = self.queue.pop_first() else {
+                break;
+            };
+            self.outstanding += 1;
+            match request {
+                Request::Tile(kind, req) => {
+                    if batch_key != Some((kind, priority)) {
+                        if let Some((kind, priority)) = batch_key {
+                            self.data_source.fetch_tiles(kind, &batch, priority);
+                        }
+                        batch.clear();
+                        batch_key = Some((kind, priority));
+                    }
+                    batch.push(req);
+                }
+                Request::ItemsMeta(requests) => self.data_source.fetch_items_meta(&requests),
+            }
+        }
+        if let Some((kind, priority)) = batch_key {
+            self.data_source.fetch_tiles(kind, &batch, priority);
+        }
+    }
+
+    fn finish<V>(&mut self, mut result: Vec<V>, cancelled: Vec<V>) -> Vec<V> {
+        self.outstanding -= result.len();
+        self.issue();
+        result.extend(cancelled);
+        result
+    }
+}
+
+impl<T: DeferredDataSource> DeferredDataSource for ThrottleDeferredDataSource<T> {
+    fn fetch_description(&self) -> DataSourceDescription {
+        self.data_source.fetch_description()
+    }
+
+    fn fetch_info(&mut self) {
+        self.data_source.fetch_info()
+    }
+
+    fn get_infos(&mut self) -> Vec<DataSourceInfoResult> {
+        self.data_source.get_infos()
+    }
+
+    fn fetch_summary_tile(
+        &mut self,
+        entry_id: &EntryID,
+        tile_id: TileID,
+        full: bool,
+        priority: RequestPriority,
+    ) {
+        let req = TileRequest {
+            entry_id: entry_id.clone(),
+            tile_id,
+            full,
+        };
+        self.fetch_tiles(TileKind::Summary, &[req], priority)
+    }
+
+    fn get_summary_tiles(&mut self) -> Vec<SummaryTileResponse> {
+        let result = self.data_source.get_summary_tiles();
+        let cancelled = std::mem::take(&mut self.summary_tiles);
+        self.finish(result, cancelled)
+    }
+
+    fn fetch_slot_tile(
+        &mut self,
+        entry_id: &EntryID,
+        tile_id: TileID,
+        full: bool,
+        priority: RequestPriority,
+    ) {
+        let req = TileRequest {
+            entry_id: entry_id.clone(),
+            tile_id,
+            full,
+        };
+        self.fetch_tiles(TileKind::Slot, &[req], priority)
+    }
+
+    fn get_slot_tiles(&mut self) -> Vec<SlotTileResponse> {
+        let result = self.data_source.get_slot_tiles();
+        let cancelled = std::mem::take(&mut self.slot_tiles);
+        self.finish(result, cancelled)
+    }
+
+    fn fetch_slot_meta_tile(
+        &mut self,
+        entry_id: &EntryID,
+        tile_id: TileID,
+        full: bool,
+        priority: RequestPriority,
+    ) {
+        let req = TileRequest {
+            entry_id: entry_id.clone(),
+            tile_id,
+            full,
+        };
+        self.fetch_tiles(TileKind::SlotMeta, &[req], priority)
+    }
+
+    fn get_slot_meta_tiles(&mut self) -> Vec<SlotMetaTileResponse> {
+        let result = self.data_source.get_slot_meta_tiles();
+        let cancelled = std::mem::take(&mut self.slot_meta_tiles);
+        self.finish(result, cancelled)
+    }
+
+    fn fetch_items_meta(&mut self, requests: &[ItemMetaRequest]) {
+        // Item metadata is only requested when the user asks for it, so it
+        // goes to the front of the queue
+        self.enqueue(
+            Request::ItemsMeta(requests.to_vec()),
+            RequestPriority::Visible,
+        );
+        self.issue()
+    }
+
+    fn get_items_meta(&mut self) -> Vec<ItemsMetaResponse> {
+        let result = self.data_source.get_items_meta();
+        self.finish(result, Vec::new())
+    }
+
+    fn fetch_tiles(&mut self, kind: TileKind, requests: &[TileRequest], priority: RequestPriority) {
+        for req in requests {
+            self.enqueue(Request::Tile(kind, req.clone()), priority);
+        }
+        self.issue()
+    }
+
+    fn cancel(&mut self, entry_id: &EntryID, tile_id: TileID, full: bool) {
+        let target = TileRequest {
+            entry_id: entry_id.clone(),
+            tile_id,
+            full,
+        };
+        self.queue.retain(|_, request| {
+            let Request::Tile(kind, req) = request else {
+                return true;
+            };
+            if *req != target {
+                return true;
+            }
+            let err = CANCELLED.to_owned();
+            match kind {
+                TileKind::Summary => self.summary_tiles.push((Err(err), req.clone())),
+                TileKind::Slot => self.slot_tiles.push((Err(err), req.clone())),
+                TileKind::SlotMeta => self.slot_meta_tiles.push((Err(err), req.clone())),
+            }
+            false
+        });
+        // Requests already issued may still be stopped underneath
+        self.data_source.cancel(entry_id, tile_id, full)
+    }
+
+    fn transfer_stats(&self) -> Option<TransferStats> {
+        self.data_source.transfer_stats()
+    }
+
+    fn retries_failures(&self) -> bool {
+        self.data_source.retries_failures()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::data::DataSource;
+    use crate::deferred_data::DeferredDataSourceWrapper;
+    use crate::random_data::{RandomConfig, RandomDataSource};
+    use crate::timestamp::Interval;
+
+    #[test]
+    fn test_throttle() {
+        let random = RandomDataSource::new(RandomConfig {
+            nodes: 1,
+            ..Default::default()
+        });
+        let interval = random.fetch_info().interval;
+        let mut ds = ThrottleDeferredDataSource::new(DeferredDataSourceWrapper::new(random), 4);
+
+        let entry_id = EntryID::root().child(0).child(0).summary();
+        let req = |i: usize| TileRequest {
+            entry_id: entry_id.clone(),
+            tile_id: TileID(Interval::new(
+                interval.lerp(i as f32 / 10.0),
+                interval.lerp((i + 1) as f32 / 10.0),
+            )),
+            full: true,
+        };
+        let background: Vec<_> = (0..8).map(req).collect();
+        ds.fetch_tiles(TileKind::Summary, &background, RequestPriority::Background);
+        assert_eq!(ds.queued(), 4);
+
+        // Visible tiles jump the queue
+        ds.fetch_summary_tile(&entry_id, req(9).tile_id, true, RequestPriority::Visible);
+        assert_eq!(ds.queued(), 5);
+
+        // Cancelling a queued request answers it right away
+        ds.cancel(&entry_id, req(7).tile_id, true);
+        assert_eq!(ds.queued(), 4);
+
+        let tiles = ds.get_summary_tiles();
+        assert_eq!(tiles.len(), 5);
+        assert!(tiles[..4].iter().all(|(tile, _)| tile.is_ok()));
+        assert_eq!(tiles[4].0.as_ref().unwrap_err(), CANCELLED);
+        assert_eq!(tiles[4].1, req(7));
+        assert_eq!(ds.queued(), 0);
+
+        let tiles = ds.get_summary_tiles();
+        let issued: Vec<_> = tiles.into_iter().map(|(_, req)| req).collect();
+        assert_eq!(issued, vec![req(9), req(4), req(5), req(6)]);
+        assert!(ds.get_summary_tiles().is_empty());
+    }
+}
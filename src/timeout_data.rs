@@ -0,0 +1,285 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::time::Duration;
+
+use web_time::Instant;
+
+use crate::data::{DataSourceDescription, EntryID, ItemMetaRequest, TileID};
+use crate::deferred_data::{
+    DataSourceInfoResult, DeferredDataSource, ItemsMetaResponse, RequestPriority,
+    SlotMetaTileResponse, SlotTileResponse, SummaryTileResponse, TileKind, TileRequest,
+    TransferStats,
+};
+
+// Start times of outstanding requests. The same request may be outstanding
+// more than once, in which case responses are matched up oldest first.
+struct Outstanding<R: Ord> {
+    requests: BTreeMap<R, VecDeque<Instant>>,
+    // Requests that timed out with no other copy outstanding, which the
+    // underlying source can stop working on
+    expired: Vec<R>,
+}
+
+impl<R: Ord + Clone> Outstanding<R> {
+    fn new() -> Self {
+        Self {
+            requests: BTreeMap::new(),
+            expired: Vec::new(),
+        }
+    }
+
+    fn contains(&self, req: &R) -> bool {
+        self.requests.contains_key(req)
+    }
+
+    fn start(&mut self, req: R) {
+        self.requests
+            .entry(req)
+            .or_default()
+            .push_back(Instant::now());
+    }
+
+    fn finish<V>(
+        &mut self,
+        responses: Vec<(Result<V, String>, R)>,
+        timeout: Duration,
+    ) -> Vec<(Result<V, String>, R)> {
+        let mut result = Vec::new();
+        for (value, req) in responses {
+            // If the request already timed out, the caller has seen an error
+            // for it and isn't expecting anything else
+            let Some(starts) = self.requests.get_mut(&req) else {
+                continue;
+            };
+            starts.pop_front();
+            if starts.is_empty() {
+                self.requests.remove(&req);
+            }
+            result.push((value, req));
+        }
+
+        let now = Instant::now();
+        self.requests.retain(|req, starts| {
+            while starts.front().is_some_and(|start| now - *start >= timeout) {
+                starts.pop_front();
+                result.push((
+                    Err(format!("request timed out after {:?}", timeout)),
+                    req.clone(),
+                ));
+            }
+            if starts.is_empty() {
+                self.expired.push(req.clone());
+            }
+            !starts.is_empty()
+        });
+        result
+    }
+}
+
+// Converts requests that take longer than the timeout into error responses,
+// and cancels them in the underlying source. Sources that limit the requests
+// in flight (e.g., ThrottleDeferredDataSource) belong below this one, so that
+// a request keeps its slot until the server has actually given up on it.
+pub struct TimeoutDeferredDataSource<T: DeferredDataSource> {
+    data_source: T,
+    timeout: Duration,
+    summary_tiles: Outstanding<TileRequest>,
+    slot_tiles: Outstanding<TileRequest>,
+    slot_meta_tiles: Outstanding<TileRequest>,
+    items_meta: Outstanding<Vec<ItemMetaRequest>>,
+}
+
+impl<T: DeferredDataSource> TimeoutDeferredDataSource<T> {
+    pub fn new(data_source: T, timeout: Duration) -> Self {
+        Self {
+            data_source,
+            timeout,
+            summary_tiles: Outstanding::new(),
+            slot_tiles: Outstanding::new(),
+            slot_meta_tiles: Outstanding::new(),
+            items_meta: Outstanding::new(),
+        }
+    }
+
+    pub fn data_source(&self) -> &T {
+        &self.data_source
+    }
+
+    // Slot and slot meta tiles share requests, and cancellation applies to
+    // both, so a tile is only cancelled once nothing is waiting on it
+    fn cancel_expired(&mut self) {
+        let expired: Vec<_> = [
+            &mut self.summary_tiles,
+            &mut self.slot_tiles,
+            &mut self.slot_meta_tiles,
+        ]
+        .into_iter()
+        .flat_map(|outstanding| std::mem::take(&mut outstanding.expired))
+        .collect();
+        for req in expired {
+            let waiting = [&self.summary_tiles, &self.slot_tiles, &self.slot_meta_tiles]
+                .into_iter()
+                .any(|outstanding| outstanding.contains(&req));
+            if !waiting {
+                self.data_source
+                    .cancel(&req.entry_id, req.tile_id, req.full);
+            }
+        }
+        // There is no cancelling item requests
+        self.items_meta.expired.clear();
+    }
+}
+
+impl<T: DeferredDataSource> DeferredDataSource for TimeoutDeferredDataSource<T> {
+    fn fetch_description(&self) -> DataSourceDescription {
+        self.data_source.fetch_description()
+    }
+
+    fn fetch_info(&mut self) {
+        self.data_source.fetch_info()
+    }
+
+    fn get_infos(&mut self) -> Vec<DataSourceInfoResult> {
+        self.data_source.get_infos()
+    }
+
+    fn fetch_summary_tile(
+        &mut self,
+        entry_id: &EntryID,
+        tile_id: TileID,
+        full: bool,
+        priority: RequestPriority,
+    ) {
+        self.summary_tiles.start(TileRequest {
+            entry_id: entry_id.clone(),
+            tile_id,
+            full,
+        });
+        self.data_source
+            .fetch_summary_tile(entry_id, tile_id, full, priority)
+    }
+
+    fn get_summary_tiles(&mut self) -> Vec<SummaryTileResponse> {
+        let result = self.data_source.get_summary_tiles();
+        let result = self.summary_tiles.finish(result, self.timeout);
+        self.cancel_expired();
+        result
+    }
+
+    fn fetch_slot_tile(
+        &mut self,
+        entry_id: &EntryID,
+        tile_id: TileID,
+        full: bool,
+        priority: RequestPriority,
+    ) {
+        self.slot_tiles.start(TileRequest {
+            entry_id: entry_id.clone(),
+            tile_id,
+            full,
+        });
+        self.data_source
+            .fetch_slot_tile(entry_id, tile_id, full, priority)
+    }
+
+    fn get_slot_tiles(&mut self) -> Vec<SlotTileResponse> {
+        let result = self.data_source.get_slot_tiles();
+        let result = self.slot_tiles.finish(result, self.timeout);
+        self.cancel_expired();
+        result
+    }
+
+    fn fetch_slot_meta_tile(
+        &mut self,
+        entry_id: &EntryID,
+        tile_id: TileID,
+        full: bool,
+        priority: RequestPriority,
+    ) {
+        self.slot_meta_tiles.start(TileRequest {
+            entry_id: entry_id.clone(),
+            tile_id,
+            full,
+        });
+        self.data_source
+            .fetch_slot_meta_tile(entry_id, tile_id, full, priority)
+    }
+
+    fn get_slot_meta_tiles(&mut self) -> Vec<SlotMetaTileResponse> {
+        let result = self.data_source.get_slot_meta_tiles();
+        let result = self.slot_meta_tiles.finish(result, self.timeout);
+        self.cancel_expired();
+        result
+    }
+
+    fn fetch_items_meta(&mut self, requests: &[ItemMetaRequest]) {
+        self.items_meta.start(requests.to_vec());
+        self.data_source.fetch_items_meta(requests)
+    }
+
+    fn get_items_meta(&mut self) -> Vec<ItemsMetaResponse> {
+        let result = self.data_source.get_items_meta();
+        self.items_meta.finish(result, self.timeout)
+    }
+
+    fn fetch_tiles(&mut self, kind: TileKind, requests: &[TileRequest], priority: RequestPriority) {
+        let outstanding = match kind {
+            TileKind::Summary => &mut self.summary_tiles,
+            TileKind::Slot => &mut self.slot_tiles,
+            TileKind::SlotMeta => &mut self.slot_meta_tiles,
+        };
+        for req in requests {
+            outstanding.start(req.clone());
+        }
+        self.data_source.fetch_tiles(kind, requests, priority)
+    }
+
+    fn cancel(&mut self, entry_id: &EntryID, tile_id: TileID, full: bool) {
+        self.data_source.cancel(entry_id, tile_id, full)
+    }
+
+    fn transfer_stats(&self) -> Option<TransferStats> {
+        self.data_source.transfer_stats()
+    }
+
+    fn retries_failures(&self) -> bool {
+        self.data_source.retries_failures()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::timestamp::{Interval, Timestamp};
+
+    #[test]
+    fn test_outstanding_timeout() {
+        let timeout = Duration::from_millis(10);
+        let req = |i: i64| TileRequest {
+            entry_id: EntryID::root(),
+            tile_id: TileID(Interval::new(Timestamp(i), Timestamp(i + 1))),
+            full: false,
+        };
+
+        let mut outstanding = Outstanding::new();
+        outstanding.start(req(0));
+        outstanding.start(req(1));
+
+        // Nothing has expired yet
+        let result = outstanding.finish(vec![(Ok(()), req(0))], timeout);
+        assert_eq!(result.len(), 1);
+        assert!(result[0].0.is_ok());
+
+        std::thread::sleep(timeout);
+        let result = outstanding.finish::<()>(Vec::new(), timeout);
+        assert_eq!(result.len(), 1);
+        assert!(result[0].0.is_err());
+        assert_eq!(result[0].1, req(1));
+
+        assert_eq!(outstanding.expired, vec![req(1)]);
+
+        // The late response is dropped
+        let result = outstanding.finish(vec![(Ok(()), req(1))], timeout);
+        assert!(result.is_empty());
+    }
+}
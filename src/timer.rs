@@ -0,0 +1,67 @@
+use std::collections::BTreeMap;
+use std::sync::OnceLock;
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::time::{Duration, Instant};
+
+type Job = Box<dyn FnOnce() + Send>;
+
+// Runs the job once the delay has passed, on a single timer thread shared by
+// everything that needs to wait (e.g., retries backing off), rather than each
+// holding a thread of its own. Jobs should be quick: anything more should be
+// handed off (e.g., with rayon::spawn).
+pub fn run_after(delay: Duration, job: Job) {
+    static TIMER: OnceLock<Sender<(Instant, Job)>> = OnceLock::new();
+    let timer = TIMER.get_or_init(|| {
+        let (sender, receiver) = mpsc::channel::<(Instant, Job)>();
+        std::thread::spawn(move || {
+            let mut jobs: BTreeMap<(Instant, u64), Job> = BTreeMap::new();
+            let mut seq = 0;
+            loop {
+                let next = jobs.first_key_value().map(|((time, _), _)| *time);
+                let received = match next {
+                    Some(time) => {
+                        receiver.recv_timeout(time.saturating_duration_since(Instant::now()))
+                    }
+                    None => receiver.recv().map_err(|_| RecvTimeoutError::Disconnected),
+                };
+                match received {
+                    Ok((time, job)) => {
+                        jobs.insert((time, seq), job);
+                        seq += 1;
+                    }
+                    Err(RecvTimeoutError::Timeout) => {}
+                    // The sender is never dropped
+                    Err(RecvTimeoutError::Disconnected) => break,
+                }
+                let now = Instant::now();
+                while let Some(entry) = jobs.first_entry() {
+                    if entry.key().0 > now {
+                        break;
+                    }
+                    entry.remove()();
+                }
+            }
+        });
+        sender
+    });
+    let _ = timer.send((Instant::now() + delay, job));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_run_after() {
+        let (sender, receiver) = mpsc::channel();
+        for (i, delay) in [30, 10, 20].into_iter().enumerate() {
+            let sender = sender.clone();
+            run_after(
+                Duration::from_millis(delay),
+                Box::new(move || sender.send(i).unwrap()),
+            );
+        }
+        let order: Vec<_> = receiver.iter().take(3).collect();
+        assert_eq!(order, vec![1, 2, 0]);
+    }
+}
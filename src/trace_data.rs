@@ -0,0 +1,537 @@
+use std::collections::BTreeMap;
+use std::hash::{DefaultHasher, Hash, Hasher};
+
+use egui::Color32;
+
+use crate::data::{
+    Capabilities, DataSource, DataSourceDescription, DataSourceInfo, EntryID, EntryInfo, Field,
+    FieldID, FieldSchema, Item, ItemMeta, ItemUID, PROTOCOL_VERSION, SlotMetaTile,
+    SlotMetaTileData, SlotTile, SlotTileData, SummaryTile, SummaryTileData, SummaryUnits, TileID,
+    TileSet, UtilPoint,
+};
+use crate::timestamp::{Interval, Timestamp};
+
+// Utilization is precomputed at this resolution over the whole profile
+const SUMMARY_BINS: i64 = 2048;
+
+#[derive(Debug, Clone)]
+pub struct TraceItem {
+    pub interval: Interval,
+    pub title: String,
+    // Items without a color get one derived from their title
+    pub color: Option<Color32>,
+    pub fields: Vec<(FieldID, Field)>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ThreadID(usize, usize, usize);
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct CounterID(usize, usize);
+
+#[derive(Default)]
+struct Thread {
+    name: String,
+    items: Vec<TraceItem>,
+}
+
+#[derive(Default)]
+struct Kind {
+    name: String,
+    threads: Vec<Thread>,
+    // Counters are shown as a summary of their own, in place of the
+    // utilization of the threads
+    samples: Vec<(Timestamp, f64)>,
+}
+
+#[derive(Default)]
+struct Process {
+    name: String,
+    kinds: Vec<Kind>,
+}
+
+// Collects the items of a trace written by some other tool, for the
+// importers of those formats. Importers place items by process, kind of
+// thread and thread; rows are assigned here, so that items that overlap
+// within a thread (such as nested calls) are stacked instead of hidden.
+pub struct TraceBuilder {
+    source_locator: String,
+    processes: Vec<Process>,
+    field_schema: FieldSchema,
+    interval_field: FieldID,
+}
+
+impl TraceBuilder {
+    pub fn new(source_locator: impl Into<String>) -> Self {
+        let mut field_schema = FieldSchema::new();
+        let interval_field = field_schema.insert("Interval".to_owned(), false);
+        Self {
+            source_locator: source_locator.into(),
+            processes: Vec::new(),
+            field_schema,
+            interval_field,
+        }
+    }
+
+    pub fn field(&mut self, name: impl Into<String>, searchable: bool) -> FieldID {
+        self.field_schema.insert(name.into(), searchable)
+    }
+
+    fn kind(&mut self, process: &str, kind: &str) -> (usize, usize) {
+        let p = find(
+            &mut self.processes,
+            process,
+            |x| &x.name,
+            || Process {
+                name: process.to_owned(),
+                ..Default::default()
+            },
+        );
+        let k = find(
+            &mut self.processes[p].kinds,
+            kind,
+            |x| &x.name,
+            || Kind {
+                name: kind.to_owned(),
+                ..Default::default()
+            },
+        );
+        (p, k)
+    }
+
+    // Processes, kinds and threads are shown in the order they are first
+    // named here
+    pub fn thread(&mut self, process: &str, kind: &str, thread: &str) -> ThreadID {
+        let (p, k) = self.kind(process, kind);
+        let threads = &mut self.processes[p].kinds[k].threads;
+        let t = find(
+            threads,
+            thread,
+            |x| &x.name,
+            || Thread {
+                name: thread.to_owned(),
+                ..Default::default()
+            },
+        );
+        ThreadID(p, k, t)
+    }
+
+    pub fn add_item(&mut self, thread: ThreadID, item: TraceItem) {
+        let ThreadID(p, k, t) = thread;
+        self.processes[p].kinds[k].threads[t].items.push(item);
+    }
+
+    pub fn counter(&mut self, process: &str, name: &str) -> CounterID {
+        let (p, k) = self.kind(process, name);
+        CounterID(p, k)
+    }
+
+    // The counter holds each value until the next sample
+    pub fn add_sample(&mut self, counter: CounterID, time: Timestamp, value: f64) {
+        let CounterID(p, k) = counter;
+        self.processes[p].kinds[k].samples.push((time, value));
+    }
+
+    pub fn build(self) -> TraceDataSource {
+        let interval = self
+            .processes
+            .iter()
+            .flat_map(|p| &p.kinds)
+            .flat_map(|k| &k.threads)
+            .flat_map(|t| &t.items)
+            .map(|item| item.interval)
+            .chain(
+                self.processes
+                    .iter()
+                    .flat_map(|p| &p.kinds)
+                    .flat_map(|k| &k.samples)
+                    .map(|(time, _)| Interval::new(*time, *time)),
+            )
+            .reduce(|a, b| a.union(b))
+            .unwrap_or_default();
+
+        let mut next_uid = 0;
+        let mut slots = BTreeMap::new();
+        let mut summaries = BTreeMap::new();
+        let mut process_slots = Vec::new();
+        for (p, process) in self.processes.into_iter().enumerate() {
+            let process_id = EntryID::root().child(p as u64);
+            let mut kind_slots = Vec::new();
+            for (k, kind) in process.kinds.into_iter().enumerate() {
+                let kind_id = process_id.child(k as u64);
+                let mut thread_slots = Vec::new();
+                let mut busy = Vec::new();
+                for (t, thread) in kind.threads.into_iter().enumerate() {
+                    let rows = assign_rows(thread.items);
+                    busy.push(busy_intervals(&rows));
+                    thread_slots.push(EntryInfo::Slot {
+                        short_name: thread.name.clone(),
+                        long_name: format!("{} {} {}", process.name, kind.name, thread.name),
+                        max_rows: rows.len().max(1) as u64,
+                    });
+                    let slot = to_slot(rows, &mut next_uid, self.interval_field);
+                    slots.insert(kind_id.child(t as u64), slot);
+                }
+                let mut long_name = format!("{} {}", process.name, kind.name);
+                let summary = if kind.samples.is_empty() {
+                    utilization(&busy, interval)
+                } else {
+                    let (summary, max) = counter_summary(kind.samples, interval);
+                    long_name = format!("{long_name} (max {max})");
+                    summary
+                };
+                summaries.insert(kind_id.summary(), summary);
+                kind_slots.push(EntryInfo::Panel {
+                    short_name: kind.name.to_lowercase(),
+                    long_name,
+                    summary: Some(Box::new(EntryInfo::Summary {
+                        color: Color32::BLUE,
+                        units: SummaryUnits::Utilization,
+                    })),
+                    slots: thread_slots,
+                });
+            }
+            process_slots.push(EntryInfo::Panel {
+                short_name: process.name.clone(),
+                long_name: process.name,
+                summary: None,
+                slots: kind_slots,
+            });
+        }
+
+        TraceDataSource {
+            source_locator: self.source_locator,
+            info: DataSourceInfo {
+                entry_info: EntryInfo::Panel {
+                    short_name: "root".to_owned(),
+                    long_name: "root".to_owned(),
+                    summary: None,
+                    slots: process_slots,
+                },
+                interval,
+                tile_set: TileSet::default(),
+                field_schema: self.field_schema,
+                warning_message: None,
+                refresh_interval: None,
+                version: PROTOCOL_VERSION,
+                capabilities: Capabilities::default(),
+                entry_tile_sets: BTreeMap::new(),
+            },
+            slots,
+            summaries,
+        }
+    }
+}
+
+fn find<T>(
+    list: &mut Vec<T>,
+    name: &str,
+    get: impl Fn(&T) -> &str,
+    make: impl FnOnce() -> T,
+) -> usize {
+    if let Some(index) = list.iter().position(|x| get(x) == name) {
+        return index;
+    }
+    list.push(make());
+    list.len() - 1
+}
+
+// Places each item in the first row that is free at its start. Longer items
+// go first, so an enclosing call ends up in a lower row than the calls
+// nested within it.
+fn assign_rows(mut items: Vec<TraceItem>) -> Vec<Vec<TraceItem>> {
+    items.sort_by_key(|item| (item.interval.start, std::cmp::Reverse(item.interval.stop)));
+    let mut rows: Vec<Vec<TraceItem>> = Vec::new();
+    for item in items {
+        let free = rows
+            .iter()
+            .position(|row| row.last().unwrap().interval.stop <= item.interval.start);
+        match free {
+            Some(row) => rows[row].push(item),
+            None => rows.push(vec![item]),
+        }
+    }
+    rows
+}
+
+// The union of the items in all rows, sorted and disjoint
+fn busy_intervals(rows: &[Vec<TraceItem>]) -> Vec<Interval> {
+    let mut intervals: Vec<_> = rows.iter().flatten().map(|item| item.interval).collect();
+    intervals.sort_by_key(|interval| interval.start);
+    let mut result: Vec<Interval> = Vec::new();
+    for interval in intervals {
+        match result.last_mut() {
+            Some(last) if interval.start <= last.stop => {
+                last.stop = last.stop.max(interval.stop);
+            }
+            _ => result.push(interval),
+        }
+    }
+    result
+}
+
+// Fraction of the threads busy in each bin
+fn utilization(threads: &[Vec<Interval>], interval: Interval) -> Vec<UtilPoint> {
+    let duration = interval.duration_ns();
+    if threads.is_empty() || duration <= 0 {
+        return Vec::new();
+    }
+    let bins = SUMMARY_BINS.min(duration);
+    let bin_start = |bin: i64| Timestamp(interval.start.0 + duration * bin / bins);
+
+    let mut busy = vec![0i64; bins as usize];
+    for busy_interval in threads.iter().flatten() {
+        let first = (busy_interval.start.0 - interval.start.0) * bins / duration;
+        let last = ((busy_interval.stop.0 - interval.start.0) * bins / duration).min(bins - 1);
+        for bin in first..=last {
+            let bin_interval = Interval::new(bin_start(bin), bin_start(bin + 1));
+            busy[bin as usize] += busy_interval.intersection(bin_interval).duration_ns();
+        }
+    }
+
+    let mut result: Vec<_> = busy
+        .into_iter()
+        .enumerate()
+        .map(|(bin, busy)| {
+            let bin = bin as i64;
+            let capacity = (bin_start(bin + 1).0 - bin_start(bin).0) * threads.len() as i64;
+            UtilPoint {
+                time: bin_start(bin),
+                util: busy as f32 / capacity as f32,
+            }
+        })
+        .collect();
+    // Hold the last bin's value to the end of the profile
+    let last = result.last().unwrap().util;
+    result.push(UtilPoint {
+        time: interval.stop,
+        util: last,
+    });
+    result
+}
+
+// Counter values are scaled to the largest one, since summaries are drawn
+// in the range [0, 1]. Negative values are clamped to zero.
+fn counter_summary(
+    mut samples: Vec<(Timestamp, f64)>,
+    interval: Interval,
+) -> (Vec<UtilPoint>, f64) {
+    samples.sort_by_key(|(time, _)| *time);
+    let max = samples.iter().map(|(_, value)| *value).fold(0.0, f64::max);
+    let scale = if max > 0.0 { 1.0 / max } else { 0.0 };
+
+    let mut result = Vec::new();
+    let mut last = None;
+    for (time, value) in samples {
+        let util = (value * scale).clamp(0.0, 1.0) as f32;
+        // Two points at each change make the line a step
+        if let Some(last) = last {
+            result.push(UtilPoint { time, util: last });
+        }
+        result.push(UtilPoint { time, util });
+        last = Some(util);
+    }
+    if let Some(last) = last {
+        result.push(UtilPoint {
+            time: interval.stop,
+            util: last,
+        });
+    }
+    (result, max)
+}
+
+struct Slot {
+    items: Vec<Vec<Item>>,
+    item_metas: Vec<Vec<ItemMeta>>,
+}
+
+fn to_slot(rows: Vec<Vec<TraceItem>>, next_uid: &mut u64, interval_field: FieldID) -> Slot {
+    let mut items = Vec::new();
+    let mut item_metas = Vec::new();
+    for row in rows {
+        let mut row_items = Vec::new();
+        let mut row_item_metas = Vec::new();
+        for item in row {
+            let item_uid = ItemUID(*next_uid);
+            *next_uid += 1;
+            row_items.push(Item {
+                item_uid,
+                interval: item.interval,
+                color: item.color.unwrap_or_else(|| title_color(&item.title)),
+            });
+            let mut fields = vec![(interval_field, Field::Interval(item.interval), None)];
+            fields.extend(
+                item.fields
+                    .into_iter()
+                    .map(|(field_id, field)| (field_id, field, None)),
+            );
+            row_item_metas.push(ItemMeta {
+                item_uid,
+                original_interval: item.interval,
+                title: item.title,
+                fields,
+            });
+        }
+        items.push(row_items);
+        item_metas.push(row_item_metas);
+    }
+    Slot { items, item_metas }
+}
+
+// Items with the same title get the same color
+fn title_color(title: &str) -> Color32 {
+    let mut hasher = DefaultHasher::new();
+    title.hash(&mut hasher);
+    let hue = (hasher.finish() % 360) as f32 / 360.0;
+    egui::ecolor::Hsva::new(hue, 0.6, 0.8, 1.0).into()
+}
+
+// Items within a row don't overlap, so they are sorted by both start and
+// stop, and the ones in the tile can be found by binary search
+fn in_tile<T>(row: &[T], tile_id: TileID, interval: impl Fn(&T) -> Interval) -> &[T] {
+    let first = row.partition_point(|x| interval(x).stop <= tile_id.0.start);
+    let last = row.partition_point(|x| interval(x).start < tile_id.0.stop);
+    &row[first..last.max(first)]
+}
+
+// Keeps the points inside the tile, plus one on either side so that the line
+// reaches the edges
+pub(crate) fn summary_points(utilization: &[UtilPoint], tile_id: TileID) -> Vec<UtilPoint> {
+    let first = utilization
+        .partition_point(|point| point.time <= tile_id.0.start)
+        .saturating_sub(1);
+    let last = utilization
+        .partition_point(|point| point.time < tile_id.0.stop)
+        .min(utilization.len().saturating_sub(1));
+    utilization.get(first..=last).unwrap_or_default().to_vec()
+}
+
+pub(crate) fn slot_items(rows: &[Vec<Item>], tile_id: TileID) -> Vec<Vec<Item>> {
+    rows.iter()
+        .map(|row| {
+            in_tile(row, tile_id, |item| item.interval)
+                .iter()
+                .map(|item| Item {
+                    // Items that straddle the tile boundary are sliced to fit
+                    interval: item.interval.intersection(tile_id.0),
+                    ..item.clone()
+                })
+                .collect()
+        })
+        .collect()
+}
+
+pub(crate) fn slot_item_metas(rows: &[Vec<ItemMeta>], tile_id: TileID) -> Vec<Vec<ItemMeta>> {
+    rows.iter()
+        .map(|row| in_tile(row, tile_id, |item| item.original_interval).to_vec())
+        .collect()
+}
+
+// A trace held entirely in memory, as assembled by a TraceBuilder
+pub struct TraceDataSource {
+    source_locator: String,
+    info: DataSourceInfo,
+    slots: BTreeMap<EntryID, Slot>,
+    summaries: BTreeMap<EntryID, Vec<UtilPoint>>,
+}
+
+impl DataSource for TraceDataSource {
+    fn fetch_description(&self) -> DataSourceDescription {
+        DataSourceDescription {
+            source_locator: vec![self.source_locator.clone()],
+        }
+    }
+
+    fn fetch_info(&self) -> DataSourceInfo {
+        self.info.clone()
+    }
+
+    fn fetch_summary_tile(&self, entry_id: &EntryID, tile_id: TileID, _full: bool) -> SummaryTile {
+        SummaryTile {
+            entry_id: entry_id.clone(),
+            tile_id,
+            data: SummaryTileData {
+                utilization: summary_points(&self.summaries[entry_id], tile_id),
+            },
+        }
+    }
+
+    fn fetch_slot_tile(&self, entry_id: &EntryID, tile_id: TileID, _full: bool) -> SlotTile {
+        SlotTile {
+            entry_id: entry_id.clone(),
+            tile_id,
+            data: SlotTileData {
+                items: slot_items(&self.slots[entry_id].items, tile_id),
+            },
+        }
+    }
+
+    fn fetch_slot_meta_tile(
+        &self,
+        entry_id: &EntryID,
+        tile_id: TileID,
+        _full: bool,
+    ) -> SlotMetaTile {
+        SlotMetaTile {
+            entry_id: entry_id.clone(),
+            tile_id,
+            data: SlotMetaTileData {
+                items: slot_item_metas(&self.slots[entry_id].item_metas, tile_id),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn item(start: i64, stop: i64, title: &str) -> TraceItem {
+        TraceItem {
+            interval: Interval::new(Timestamp(start), Timestamp(stop)),
+            title: title.to_owned(),
+            color: None,
+            fields: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_trace_rows() {
+        let mut builder = TraceBuilder::new("test");
+        let main = builder.thread("p0", "Threads", "main");
+        let worker = builder.thread("p0", "Threads", "worker");
+        builder.add_item(main, item(10, 20, "inner"));
+        builder.add_item(main, item(0, 100, "outer"));
+        builder.add_item(main, item(30, 40, "inner"));
+        builder.add_item(worker, item(50, 100, "work"));
+        assert_eq!(builder.thread("p0", "Threads", "worker"), worker);
+        let ds = builder.build();
+
+        let info = ds.fetch_info();
+        assert_eq!(info.interval, Interval::new(Timestamp(0), Timestamp(100)));
+        assert_eq!(info.entry_info.nodes(), 1);
+        assert_eq!(info.entry_info.kinds(), vec!["threads".to_owned()]);
+
+        let main_id = EntryID::root().child(0).child(0).child(0);
+        let tile_id = TileID(Interval::new(Timestamp(15), Timestamp(35)));
+        let meta = ds.fetch_slot_meta_tile(&main_id, tile_id, false);
+        let titles: Vec<Vec<_>> = meta
+            .data
+            .items
+            .iter()
+            .map(|row| row.iter().map(|item| item.title.as_str()).collect())
+            .collect();
+        assert_eq!(titles, vec![vec!["outer"], vec!["inner", "inner"]]);
+
+        let tile = ds.fetch_slot_tile(&main_id, tile_id, false);
+        assert_eq!(tile.data.items[0][0].interval, tile_id.0);
+
+        // One of the two threads is busy throughout the first half
+        let summary_id = EntryID::root().child(0).child(0).summary();
+        let summary = ds.fetch_summary_tile(&summary_id, TileID(info.interval), false);
+        let first = summary.data.utilization[0];
+        assert_eq!(first.time, Timestamp(0));
+        assert_eq!(first.util, 0.5);
+        assert_eq!(summary.data.utilization.last().unwrap().util, 1.0);
+    }
+}
@@ -0,0 +1,336 @@
+use std::collections::BTreeMap;
+
+use crate::data::{
+    DataSourceDescription, EntryID, Field, ItemMeta, ItemMetaRequest, SlotMetaTile, SlotTile,
+    SummaryTile, TileID,
+};
+use crate::deferred_data::{
+    DataSourceInfoResult, DeferredDataSource, ItemsMetaResponse, RequestPriority,
+    SlotMetaTileResponse, SlotTileResponse, SummaryTileResponse, TileKind, TileRequest,
+    TileResponse, TransferStats,
+};
+use crate::timestamp::{Interval, Timestamp};
+
+// Maps a source timestamp t to t * scale + offset_ns
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Transform {
+    pub offset_ns: i64,
+    pub scale: f64,
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Self {
+            offset_ns: 0,
+            scale: 1.0,
+        }
+    }
+}
+
+impl Transform {
+    pub fn new(offset_ns: i64, scale: f64) -> Self {
+        assert!(scale > 0.0, "scale must be positive");
+        Self { offset_ns, scale }
+    }
+
+    pub fn apply(self, t: Timestamp) -> Timestamp {
+        Timestamp((t.0 as f64 * self.scale).round() as i64 + self.offset_ns)
+    }
+
+    pub fn invert(self, t: Timestamp) -> Timestamp {
+        Timestamp(((t.0 - self.offset_ns) as f64 / self.scale).round() as i64)
+    }
+
+    pub fn apply_interval(self, i: Interval) -> Interval {
+        Interval::new(self.apply(i.start), self.apply(i.stop))
+    }
+
+    pub fn invert_interval(self, i: Interval) -> Interval {
+        Interval::new(self.invert(i.start), self.invert(i.stop))
+    }
+
+    fn apply_field(self, field: &mut Field) {
+        match field {
+            Field::Interval(interval) => *interval = self.apply_interval(*interval),
+            Field::ItemLink(link) => link.interval = self.apply_interval(link.interval),
+            Field::Vec(elts) => {
+                for elt in elts {
+                    self.apply_field(elt);
+                }
+            }
+            _ => (),
+        }
+    }
+
+    fn apply_item_meta(self, item: &mut ItemMeta) {
+        item.original_interval = self.apply_interval(item.original_interval);
+        for (_, field, _) in &mut item.fields {
+            self.apply_field(field);
+        }
+    }
+}
+
+// Shifts and stretches the time axis of a source, e.g., to line up profiles
+// recorded against different epochs or with clocks running at different rates
+pub struct TransformDeferredDataSource<T: DeferredDataSource> {
+    data_source: T,
+    transform: Transform,
+    // Rounding means that a tile doesn't necessarily map back to exactly the
+    // tile that was requested, so remember what the caller asked for
+    requests: BTreeMap<(TileKind, TileRequest), TileRequest>,
+    items_meta_requests: BTreeMap<Vec<ItemMetaRequest>, Vec<ItemMetaRequest>>,
+}
+
+impl<T: DeferredDataSource> TransformDeferredDataSource<T> {
+    pub fn new(data_source: T, transform: Transform) -> Self {
+        Self {
+            data_source,
+            transform,
+            requests: BTreeMap::new(),
+            items_meta_requests: BTreeMap::new(),
+        }
+    }
+
+    fn start_request(&mut self, kind: TileKind, req: TileRequest) -> TileRequest {
+        let src = TileRequest {
+            tile_id: TileID(self.transform.invert_interval(req.tile_id.0)),
+            ..req.clone()
+        };
+        self.requests.insert((kind, src.clone()), req);
+        src
+    }
+
+    fn finish_request(&mut self, kind: TileKind, src: TileRequest) -> TileRequest {
+        self.requests
+            .remove(&(kind, src.clone()))
+            .unwrap_or_else(|| TileRequest {
+                tile_id: TileID(self.transform.apply_interval(src.tile_id.0)),
+                ..src
+            })
+    }
+
+    fn finish_requests<R>(
+        &mut self,
+        kind: TileKind,
+        responses: Vec<TileResponse<R>>,
+        mut transform: impl FnMut(Transform, &mut R),
+    ) -> Vec<TileResponse<R>> {
+        responses
+            .into_iter()
+            .map(|(tile, src)| {
+                let req = self.finish_request(kind, src);
+                let tile = tile.map(|mut tile| {
+                    transform(self.transform, &mut tile);
+                    tile
+                });
+                (tile, req)
+            })
+            .collect()
+    }
+}
+
+impl<T: DeferredDataSource> DeferredDataSource for TransformDeferredDataSource<T> {
+    fn fetch_description(&self) -> DataSourceDescription {
+        self.data_source.fetch_description()
+    }
+
+    fn fetch_info(&mut self) {
+        self.data_source.fetch_info()
+    }
+
+    fn get_infos(&mut self) -> Vec<DataSourceInfoResult> {
+        let mut infos = self.data_source.get_infos();
+        for info in infos.iter_mut().flatten() {
+            info.interval = self.transform.apply_interval(info.interval);
+            let tile_sets =
+                std::iter::once(&mut info.tile_set).chain(info.entry_tile_sets.values_mut());
+            for tiles in tile_sets.flat_map(|tile_set| &mut tile_set.tiles) {
+                for tile in tiles {
+                    tile.0 = self.transform.apply_interval(tile.0);
+                }
+            }
+        }
+        infos
+    }
+
+    fn fetch_summary_tile(
+        &mut self,
+        entry_id: &EntryID,
+        tile_id: TileID,
+        full: bool,
+        priority: RequestPriority,
+    ) {
+        let req = TileRequest {
+            entry_id: entry_id.clone(),
+            tile_id,
+            full,
+        };
+        let src = self.start_request(TileKind::Summary, req);
+        self.data_source
+            .fetch_summary_tile(entry_id, src.tile_id, full, priority)
+    }
+
+    fn get_summary_tiles(&mut self) -> Vec<SummaryTileResponse> {
+        let result = self.data_source.get_summary_tiles();
+        self.finish_requests(TileKind::Summary, result, |t, tile: &mut SummaryTile| {
+            tile.tile_id = TileID(t.apply_interval(tile.tile_id.0));
+            for point in &mut tile.data.utilization {
+                point.time = t.apply(point.time);
+            }
+        })
+    }
+
+    fn fetch_slot_tile(
+        &mut self,
+        entry_id: &EntryID,
+        tile_id: TileID,
+        full: bool,
+        priority: RequestPriority,
+    ) {
+        let req = TileRequest {
+            entry_id: entry_id.clone(),
+            tile_id,
+            full,
+        };
+        let src = self.start_request(TileKind::Slot, req);
+        self.data_source
+            .fetch_slot_tile(entry_id, src.tile_id, full, priority)
+    }
+
+    fn get_slot_tiles(&mut self) -> Vec<SlotTileResponse> {
+        let result = self.data_source.get_slot_tiles();
+        self.finish_requests(TileKind::Slot, result, |t, tile: &mut SlotTile| {
+            tile.tile_id = TileID(t.apply_interval(tile.tile_id.0));
+            for item in tile.data.items.iter_mut().flatten() {
+                item.interval = t.apply_interval(item.interval);
+            }
+        })
+    }
+
+    fn fetch_slot_meta_tile(
+        &mut self,
+        entry_id: &EntryID,
+        tile_id: TileID,
+        full: bool,
+        priority: RequestPriority,
+    ) {
+        let req = TileRequest {
+            entry_id: entry_id.clone(),
+            tile_id,
+            full,
+        };
+        let src = self.start_request(TileKind::SlotMeta, req);
+        self.data_source
+            .fetch_slot_meta_tile(entry_id, src.tile_id, full, priority)
+    }
+
+    fn get_slot_meta_tiles(&mut self) -> Vec<SlotMetaTileResponse> {
+        let result = self.data_source.get_slot_meta_tiles();
+        self.finish_requests(TileKind::SlotMeta, result, |t, tile: &mut SlotMetaTile| {
+            tile.tile_id = TileID(t.apply_interval(tile.tile_id.0));
+            for item in tile.data.items.iter_mut().flatten() {
+                t.apply_item_meta(item);
+            }
+        })
+    }
+
+    fn fetch_items_meta(&mut self, requests: &[ItemMetaRequest]) {
+        let src: Vec<_> = requests
+            .iter()
+            .map(|req| ItemMetaRequest {
+                interval: self.transform.invert_interval(req.interval),
+                ..req.clone()
+            })
+            .collect();
+        self.items_meta_requests
+            .insert(src.clone(), requests.to_vec());
+        self.data_source.fetch_items_meta(&src)
+    }
+
+    fn get_items_meta(&mut self) -> Vec<ItemsMetaResponse> {
+        let result = self.data_source.get_items_meta();
+        result
+            .into_iter()
+            .map(|(metas, src)| {
+                let reqs = self.items_meta_requests.remove(&src).unwrap_or_else(|| {
+                    src.iter()
+                        .map(|req| ItemMetaRequest {
+                            interval: self.transform.apply_interval(req.interval),
+                            ..req.clone()
+                        })
+                        .collect()
+                });
+                let metas = metas.map(|mut metas| {
+                    for item in &mut metas {
+                        self.transform.apply_item_meta(item);
+                    }
+                    metas
+                });
+                (metas, reqs)
+            })
+            .collect()
+    }
+
+    fn fetch_tiles(&mut self, kind: TileKind, requests: &[TileRequest], priority: RequestPriority) {
+        let src: Vec<_> = requests
+            .iter()
+            .map(|req| self.start_request(kind, req.clone()))
+            .collect();
+        self.data_source.fetch_tiles(kind, &src, priority)
+    }
+
+    fn cancel(&mut self, entry_id: &EntryID, tile_id: TileID, full: bool) {
+        let tile_id = TileID(self.transform.invert_interval(tile_id.0));
+        self.data_source.cancel(entry_id, tile_id, full)
+    }
+
+    fn transfer_stats(&self) -> Option<TransferStats> {
+        self.data_source.transfer_stats()
+    }
+
+    fn retries_failures(&self) -> bool {
+        self.data_source.retries_failures()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::deferred_data::DeferredDataSourceWrapper;
+    use crate::test_data::TestDataSource;
+
+    #[test]
+    fn test_transform() {
+        let t = Transform::new(100, 3.0);
+        assert_eq!(t.apply(Timestamp(10)), Timestamp(130));
+        assert_eq!(t.invert(Timestamp(130)), Timestamp(10));
+
+        // One item covering the whole profile
+        let source = TestDataSource {
+            items: vec![Interval::new(Timestamp(0), Timestamp(1000))],
+            ..Default::default()
+        };
+        let mut ds = TransformDeferredDataSource::new(DeferredDataSourceWrapper::new(source), t);
+        ds.fetch_info();
+        let info = ds.get_infos().pop().unwrap().unwrap();
+        assert_eq!(
+            info.interval,
+            Interval::new(Timestamp(100), Timestamp(3100))
+        );
+
+        // Doesn't land exactly on a source timestamp
+        let entry_id = EntryID::root().child(0);
+        let tile_id = TileID(Interval::new(Timestamp(101), Timestamp(3100)));
+        ds.fetch_slot_tile(&entry_id, tile_id, false, RequestPriority::Visible);
+        let (tile, req) = ds.get_slot_tiles().pop().unwrap();
+        assert_eq!(req.tile_id, tile_id);
+        let tile = tile.unwrap();
+        assert_eq!(tile.data.items[0][0].interval, info.interval);
+
+        ds.fetch_tiles(TileKind::Summary, &[req], RequestPriority::Visible);
+        let (tile, req) = ds.get_summary_tiles().pop().unwrap();
+        assert_eq!(req.tile_id, tile_id);
+        assert_eq!(tile.unwrap().data.utilization[0].time, Timestamp(100));
+    }
+}
@@ -0,0 +1,265 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+#[cfg(feature = "client")]
+use std::time::Duration;
+
+use serde::Deserialize;
+
+use crate::app::{Action, AppSettings, KeyBindings, Shortcut};
+#[cfg(feature = "client")]
+use crate::http::auth::Credentials;
+#[cfg(feature = "client")]
+use crate::http::client::ClientConfig;
+
+// The user's preferences, read from config.toml in the platform's config
+// directory (e.g., ~/.config/legionprof on Linux) or the file given with
+// --config. Everything is optional, and command line flags (and environment
+// variables) win over what is set here.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct UserConfig {
+    pub theme: Option<Theme>,
+    // Budget of each profile's tile cache, in MiB
+    pub tile_cache_mib: Option<usize>,
+    // Shortcuts of each action, e.g., toggle_crosshair = "x" or
+    // zoom_in = ["ctrl+plus", "ctrl+equals"]
+    pub keys: BTreeMap<Action, Shortcuts>,
+    pub http: HttpConfig,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Theme {
+    Light,
+    Dark,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(untagged)]
+pub enum Shortcuts {
+    One(Shortcut),
+    Many(Vec<Shortcut>),
+}
+
+impl Shortcuts {
+    fn to_vec(&self) -> Vec<Shortcut> {
+        match self {
+            Shortcuts::One(shortcut) => vec![*shortcut],
+            Shortcuts::Many(shortcuts) => shortcuts.clone(),
+        }
+    }
+}
+
+// Defaults for the flags of the same names
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HttpConfig {
+    pub token: Option<String>,
+    // Read again whenever the token is rejected, like --token-file
+    pub token_file: Option<PathBuf>,
+    // "user" or "user:password"
+    pub user: Option<String>,
+    pub cookies: BTreeMap<String, String>,
+    pub headers: BTreeMap<String, String>,
+    pub user_agent: Option<String>,
+    pub proxy: Option<String>,
+    pub no_proxy: Option<String>,
+    pub cacert: Vec<PathBuf>,
+    pub cert: Option<PathBuf>,
+    pub key: Option<PathBuf>,
+    pub insecure: bool,
+    // In seconds, where 0 waits forever
+    pub connect_timeout: Option<u64>,
+    pub timeout: Option<u64>,
+    pub idle_timeout: Option<u64>,
+    pub max_requests: Option<usize>,
+    pub pool_size: Option<usize>,
+    pub retries: Option<u32>,
+}
+
+// A timeout in seconds, where 0 means none (as given in the config file or
+// on the command line)
+#[cfg(feature = "client")]
+pub fn seconds(seconds: u64) -> Option<Duration> {
+    (seconds > 0).then(|| Duration::from_secs(seconds))
+}
+
+#[cfg(feature = "client")]
+impl HttpConfig {
+    pub fn apply(&self, config: &mut ClientConfig) -> Result<(), String> {
+        if let Some(token) = &self.token {
+            config
+                .auth
+                .set_credentials(Credentials::Bearer(token.clone()));
+        }
+        if let Some(path) = &self.token_file {
+            config
+                .auth
+                .credentials_file(path.clone(), Credentials::Bearer)?;
+        }
+        if let Some(user) = &self.user {
+            config.auth.set_credentials(Credentials::basic(user));
+        }
+        config
+            .auth
+            .cookies
+            .extend(self.cookies.iter().map(|(k, v)| (k.clone(), v.clone())));
+        config
+            .headers
+            .extend(self.headers.iter().map(|(k, v)| (k.clone(), v.clone())));
+        if self.user_agent.is_some() {
+            config.user_agent.clone_from(&self.user_agent);
+        }
+        if self.proxy.is_some() {
+            config.proxy.clone_from(&self.proxy);
+        }
+        if self.no_proxy.is_some() {
+            config.no_proxy.clone_from(&self.no_proxy);
+        }
+        config
+            .tls
+            .ca_certificates
+            .extend(self.cacert.iter().cloned());
+        if self.cert.is_some() {
+            config.tls.client_certificate.clone_from(&self.cert);
+        }
+        if self.key.is_some() {
+            config.tls.client_key.clone_from(&self.key);
+        }
+        config.tls.accept_invalid_certs |= self.insecure;
+        if let Some(connect) = self.connect_timeout {
+            config.timeouts.connect = seconds(connect);
+        }
+        if let Some(read) = self.timeout {
+            config.timeouts.read = seconds(read);
+        }
+        if let Some(idle) = self.idle_timeout {
+            config.connections.idle_timeout = seconds(idle);
+        }
+        if self.max_requests.is_some() {
+            config.max_in_flight = self.max_requests;
+        }
+        if self.pool_size.is_some() {
+            config.connections.pool_size = self.pool_size;
+        }
+        if let Some(retries) = self.retries {
+            config.retry.retries = retries;
+        }
+        Ok(())
+    }
+}
+
+// Where the config is read from when --config isn't given
+pub fn default_path() -> Option<PathBuf> {
+    directories::ProjectDirs::from("", "", "Legion Prof")
+        .map(|dirs| dirs.config_dir().join("config.toml"))
+}
+
+impl UserConfig {
+    pub fn parse(text: &str) -> Result<Self, String> {
+        toml::from_str(text).map_err(|e| e.to_string())
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, String> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| format!("unable to read {}: {e}", path.display()))?;
+        Self::parse(&text).map_err(|e| format!("invalid config {}: {e}", path.display()))
+    }
+
+    // The config at the default path, or the defaults if there is none
+    pub fn load_default() -> Result<Self, String> {
+        match default_path() {
+            Some(path) if path.exists() => Self::load(path),
+            _ => Ok(Self::default()),
+        }
+    }
+
+    pub fn app_settings(&self) -> AppSettings {
+        let mut keys = KeyBindings::default();
+        for (action, shortcuts) in &self.keys {
+            keys.bind(*action, shortcuts.to_vec());
+        }
+        AppSettings {
+            dark_mode: self.theme.map(|theme| theme == Theme::Dark),
+            tile_cache_bytes: self.tile_cache_mib.map(|mib| mib << 20),
+            keys,
+            ..Default::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let config = UserConfig::parse(
+            r#"
+theme = "dark"
+tile_cache_mib = 512
+
+[keys]
+toggle_crosshair = "x"
+zoom_in = ["ctrl+plus", "ctrl+i"]
+toggle_controls = []
+"#,
+        )
+        .unwrap();
+
+        let settings = config.app_settings();
+        assert_eq!(settings.dark_mode, Some(true));
+        assert_eq!(settings.tile_cache_bytes, Some(512 << 20));
+        assert_eq!(settings.keys.describe(Action::ToggleCrosshair), "X");
+        assert_eq!(settings.keys.shortcuts(Action::ZoomIn).len(), 2);
+        assert_eq!(settings.keys.describe(Action::ToggleControls), "Unbound");
+        // Actions that aren't mentioned keep their shortcuts
+        assert_eq!(
+            settings.keys.shortcuts(Action::ZoomOut),
+            KeyBindings::default().shortcuts(Action::ZoomOut)
+        );
+    }
+
+    #[cfg(feature = "client")]
+    #[test]
+    fn test_parse_http() {
+        let config = UserConfig::parse(
+            r#"
+[http]
+connect_timeout = 0
+timeout = 120
+proxy = "http://proxy:3128"
+no_proxy = "localhost"
+user = "alice:secret"
+headers = { "X-API-Key" = "key" }
+"#,
+        )
+        .unwrap();
+
+        let mut client = ClientConfig::default();
+        config.http.apply(&mut client).unwrap();
+        assert_eq!(client.timeouts.connect, None);
+        assert_eq!(client.timeouts.read, Some(Duration::from_secs(120)));
+        assert_eq!(client.proxy.as_deref(), Some("http://proxy:3128"));
+        assert_eq!(client.no_proxy.as_deref(), Some("localhost"));
+        assert_eq!(
+            client.auth.credentials,
+            Some(Credentials::basic("alice:secret"))
+        );
+        assert_eq!(
+            client.headers,
+            vec![("X-API-Key".to_owned(), "key".to_owned())]
+        );
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(UserConfig::parse("theme = \"blue\"").is_err());
+        assert!(UserConfig::parse("[keys]\nzoom_in = \"ctrl+nokey\"").is_err());
+        assert!(UserConfig::parse("[keys]\nfly = \"f\"").is_err());
+        // Typos are caught rather than ignored
+        assert!(UserConfig::parse("tile_cache = 512").is_err());
+        assert!(UserConfig::parse("").is_ok());
+    }
+}
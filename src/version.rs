@@ -0,0 +1,69 @@
+use crate::data::PROTOCOL_VERSION;
+use crate::http::schema::MIN_PROTOCOL_VERSION;
+
+// What this build is and which peers it can talk to, for --version and the
+// About window, so that a viewer and a server that don't match can be told
+// apart at a glance
+
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+// The protocol versions this build can decode, e.g., "version 1" or
+// "versions 1 to 3"
+pub fn supported_protocols() -> String {
+    if MIN_PROTOCOL_VERSION == PROTOCOL_VERSION {
+        format!("version {PROTOCOL_VERSION}")
+    } else {
+        format!("versions {MIN_PROTOCOL_VERSION} to {PROTOCOL_VERSION}")
+    }
+}
+
+// Optional features this build was compiled with
+pub fn features() -> Vec<&'static str> {
+    [
+        ("client", cfg!(feature = "client")),
+        ("server", cfg!(feature = "server")),
+        ("bundle", cfg!(feature = "bundle")),
+        ("parquet", cfg!(feature = "parquet")),
+        ("chrome", cfg!(feature = "chrome")),
+        ("nsys", cfg!(feature = "nsys")),
+        ("sqlite", cfg!(feature = "sqlite")),
+        ("otf2", cfg!(feature = "otf2")),
+        ("nvtxw", cfg!(feature = "nvtxw")),
+        ("socks", cfg!(feature = "socks")),
+        ("websocket", cfg!(feature = "websocket")),
+        ("grpc", cfg!(feature = "grpc")),
+    ]
+    .into_iter()
+    .filter(|(_, enabled)| *enabled)
+    .map(|(feature, _)| feature)
+    .collect()
+}
+
+// The version followed by the protocol and features, one per line
+pub fn describe() -> String {
+    let features = features();
+    let features = if features.is_empty() {
+        "none".to_owned()
+    } else {
+        features.join(", ")
+    };
+    format!(
+        "{VERSION}\nprotocol: {PROTOCOL_VERSION} (reads {})\nfeatures: {features}",
+        supported_protocols()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_describe() {
+        let description = describe();
+        let lines: Vec<_> = description.lines().collect();
+        assert_eq!(lines[0], VERSION);
+        assert!(lines[1].starts_with(&format!("protocol: {PROTOCOL_VERSION} ")));
+        assert!(lines[1].contains(&supported_protocols()));
+        assert_eq!(lines.len(), 3);
+    }
+}
@@ -0,0 +1,264 @@
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+
+use serde::Deserialize;
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, ZipArchive, ZipWriter};
+
+use crate::data::{
+    DataSource, DataSourceDescription, DataSourceInfo, EntryID, ItemMeta, ItemMetaRequest,
+    SlotMetaTile, SlotTile, SummaryTile, TileID,
+};
+use crate::file_data::fetch_items_meta_from_tile_set;
+use crate::http::schema::TileRequestRef;
+
+// Reads an archive (as written by DataSourceArchiveWriter) out of a single
+// zip file. Members are decoded on demand; nothing is extracted to disk.
+pub struct ZipDataSource {
+    path: PathBuf,
+    archive: Mutex<ZipArchive<File>>,
+
+    // The archive directory may be nested inside the zip file (e.g., if the
+    // user zipped the directory rather than its contents)
+    prefix: String,
+
+    // Read on first use, since the tile set is needed to look up items
+    info: OnceLock<DataSourceInfo>,
+}
+
+impl ZipDataSource {
+    pub fn new(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref().to_owned();
+        let archive = ZipArchive::new(File::open(&path)?)?;
+        let prefix = archive
+            .file_names()
+            .filter_map(|name| name.strip_suffix("info"))
+            .filter(|prefix| prefix.is_empty() || prefix.ends_with('/'))
+            .min_by_key(|prefix| prefix.len())
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "no profile info in bundle"))?
+            .to_owned();
+        Ok(Self {
+            path,
+            archive: Mutex::new(archive),
+            prefix,
+            info: OnceLock::new(),
+        })
+    }
+
+    fn read_member<T>(&self, name: &str) -> Result<T, String>
+    where
+        T: for<'a> Deserialize<'a>,
+    {
+        let name = format!("{}{}", self.prefix, name);
+        let bundle = self.path.display();
+        // Decompress while holding the lock since the member borrows the
+        // archive, but decode outside so that parallel readers don't serialize
+        // on CBOR decoding
+        let mut data = Vec::new();
+        {
+            let mut archive = self.archive.lock().unwrap();
+            let mut member = archive
+                .by_name(&name)
+                .map_err(|e| format!("unable to open {name} in bundle {bundle}: {e}"))?;
+            member
+                .read_to_end(&mut data)
+                .map_err(|e| format!("unable to read {name} in bundle {bundle}: {e}"))?;
+        }
+        let f = zstd::Decoder::new(&data[..])
+            .map_err(|e| format!("unable to decompress {name} in bundle {bundle}: {e}"))?;
+        ciborium::from_reader(f)
+            .map_err(|e| format!("unable to decode {name} in bundle {bundle}: {e}"))
+    }
+
+    fn info(&self) -> Result<&DataSourceInfo, String> {
+        if let Some(info) = self.info.get() {
+            return Ok(info);
+        }
+        let info = self.read_member("info")?;
+        Ok(self.info.get_or_init(|| info))
+    }
+}
+
+// Reading can fail on a damaged bundle, so the deferred wrappers use the
+// try_fetch_* methods, which report the error along with the request. The
+// plain methods are only for callers with no way to report it.
+impl DataSource for ZipDataSource {
+    fn fetch_description(&self) -> DataSourceDescription {
+        DataSourceDescription {
+            source_locator: vec![String::from(self.path.to_string_lossy())],
+        }
+    }
+
+    fn fetch_info(&self) -> DataSourceInfo {
+        self.try_fetch_info().unwrap_or_else(|e| panic!("{e}"))
+    }
+
+    fn fetch_summary_tile(&self, entry_id: &EntryID, tile_id: TileID, full: bool) -> SummaryTile {
+        self.try_fetch_summary_tile(entry_id, tile_id, full)
+            .unwrap_or_else(|e| panic!("{e}"))
+    }
+
+    fn fetch_slot_tile(&self, entry_id: &EntryID, tile_id: TileID, full: bool) -> SlotTile {
+        self.try_fetch_slot_tile(entry_id, tile_id, full)
+            .unwrap_or_else(|e| panic!("{e}"))
+    }
+
+    fn fetch_slot_meta_tile(
+        &self,
+        entry_id: &EntryID,
+        tile_id: TileID,
+        full: bool,
+    ) -> SlotMetaTile {
+        self.try_fetch_slot_meta_tile(entry_id, tile_id, full)
+            .unwrap_or_else(|e| panic!("{e}"))
+    }
+
+    fn fetch_items_meta(&self, requests: &[ItemMetaRequest]) -> Vec<ItemMeta> {
+        self.try_fetch_items_meta(requests)
+            .unwrap_or_else(|e| panic!("{e}"))
+    }
+
+    fn try_fetch_info(&self) -> Result<DataSourceInfo, String> {
+        self.info().cloned()
+    }
+
+    fn try_fetch_summary_tile(
+        &self,
+        entry_id: &EntryID,
+        tile_id: TileID,
+        _full: bool,
+    ) -> Result<SummaryTile, String> {
+        let req = TileRequestRef { entry_id, tile_id };
+        self.read_member::<SummaryTile>(&format!("summary_tile/{}", req.to_slug()))
+    }
+
+    fn try_fetch_slot_tile(
+        &self,
+        entry_id: &EntryID,
+        tile_id: TileID,
+        _full: bool,
+    ) -> Result<SlotTile, String> {
+        let req = TileRequestRef { entry_id, tile_id };
+        self.read_member::<SlotTile>(&format!("slot_tile/{}", req.to_slug()))
+    }
+
+    fn try_fetch_slot_meta_tile(
+        &self,
+        entry_id: &EntryID,
+        tile_id: TileID,
+        _full: bool,
+    ) -> Result<SlotMetaTile, String> {
+        let req = TileRequestRef { entry_id, tile_id };
+        self.read_member::<SlotMetaTile>(&format!("slot_meta_tile/{}", req.to_slug()))
+    }
+
+    fn try_fetch_items_meta(&self, requests: &[ItemMetaRequest]) -> Result<Vec<ItemMeta>, String> {
+        fetch_items_meta_from_tile_set(self, self.info()?, requests)
+    }
+}
+
+// Pack an archive directory into a single zip file that can be opened with
+// ZipDataSource. Members are already zstd-compressed, so they are stored as-is.
+pub fn write_bundle(archive_dir: impl AsRef<Path>, bundle: impl AsRef<Path>) -> io::Result<()> {
+    fn walk(
+        dir: &Path,
+        name: &str,
+        zip: &mut ZipWriter<File>,
+        options: SimpleFileOptions,
+    ) -> io::Result<()> {
+        let mut entries: Vec<_> = std::fs::read_dir(dir)?.collect::<io::Result<_>>()?;
+        entries.sort_by_key(|entry| entry.file_name());
+        for entry in entries {
+            let file_name = entry.file_name();
+            let member = format!("{}{}", name, file_name.to_string_lossy());
+            if entry.file_type()?.is_dir() {
+                zip.add_directory(&member, options)?;
+                walk(&entry.path(), &format!("{}/", member), zip, options)?;
+            } else {
+                zip.start_file(&member, options)?;
+                zip.write_all(&std::fs::read(entry.path())?)?;
+            }
+        }
+        Ok(())
+    }
+
+    let mut zip = ZipWriter::new(File::create(bundle)?);
+    let options = SimpleFileOptions::default()
+        .compression_method(CompressionMethod::Stored)
+        .large_file(true);
+    walk(archive_dir.as_ref(), "", &mut zip, options)?;
+    zip.finish()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::*;
+
+    use crate::data::{Capabilities, EntryInfo, FieldSchema, PROTOCOL_VERSION, TileSet};
+    use crate::timestamp::{Interval, Timestamp};
+
+    fn write_member<T: serde::Serialize>(path: &Path, data: &T) {
+        let mut f = zstd::Encoder::new(File::create(path).unwrap(), 1).unwrap();
+        ciborium::into_writer(data, &mut f).unwrap();
+        f.finish().unwrap();
+    }
+
+    #[test]
+    fn test_bundle_roundtrip() {
+        let dir = std::env::temp_dir().join(format!("lpv_bundle_test_{}", std::process::id()));
+        let archive_dir = dir.join("archive");
+        std::fs::create_dir_all(archive_dir.join("summary_tile")).unwrap();
+
+        let info = DataSourceInfo {
+            entry_info: EntryInfo::Panel {
+                short_name: "root".to_string(),
+                long_name: "root".to_string(),
+                summary: None,
+                slots: Vec::new(),
+            },
+            interval: Interval::new(Timestamp(0), Timestamp(1000)),
+            tile_set: TileSet::default(),
+            field_schema: FieldSchema::new(),
+            warning_message: None,
+            refresh_interval: None,
+            version: PROTOCOL_VERSION,
+            capabilities: Capabilities::default(),
+            entry_tile_sets: BTreeMap::new(),
+        };
+        write_member(&archive_dir.join("info"), &info);
+
+        let bundle = dir.join("profile.lpv.zip");
+        write_bundle(&archive_dir, &bundle).unwrap();
+
+        let data_source = ZipDataSource::new(&bundle).unwrap();
+        assert_eq!(data_source.fetch_info().interval, info.interval);
+
+        // Missing members are reported rather than panicking
+        let tile_id = TileID(Interval::new(Timestamp(0), Timestamp(10)));
+        let e = data_source
+            .try_fetch_summary_tile(&EntryID::root(), tile_id, false)
+            .unwrap_err();
+        assert!(e.contains("unable to open summary_tile/"), "{e}");
+
+        // Bundles zipped by hand are usually deflated
+        let deflated = dir.join("deflated.zip");
+        let mut zip = ZipWriter::new(File::create(&deflated).unwrap());
+        let options = SimpleFileOptions::default().compression_method(CompressionMethod::Deflated);
+        zip.start_file("info", options).unwrap();
+        zip.write_all(&std::fs::read(archive_dir.join("info")).unwrap())
+            .unwrap();
+        zip.finish().unwrap();
+        let data_source = ZipDataSource::new(&deflated).unwrap();
+        assert_eq!(
+            data_source.try_fetch_info().unwrap().interval,
+            info.interval
+        );
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}